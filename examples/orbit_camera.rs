@@ -0,0 +1,38 @@
+use bevy_ecs::system::{Commands, Res, ResMut};
+use cgmath::Point3;
+use try_wgpu::{
+    camera_controller::OrbitCameraBundle,
+    color::Color,
+    render::{
+        material::ColorMaterial,
+        mesh::{primitive::create_unit_cube, GpuMesh, MeshCache},
+    },
+    transform::Transform,
+    util::Store,
+    FlatEngine,
+};
+
+/// Left drag orbits the cube, scrolling zooms and middle drag pans.
+/// `FpsCameraBundle::default()` flies around it instead, click to look and Escape to stop
+fn main() {
+    FlatEngine::new().build().add_startup_system(spawn).run();
+}
+
+fn spawn(
+    mut commands: Commands,
+    device: Res<std::sync::Arc<wgpu::Device>>,
+    mut meshes: ResMut<Store<GpuMesh>>,
+    mut cache: ResMut<MeshCache>,
+) {
+    let cube = cache.get_or_create(&device, &mut meshes, "unit_cube", create_unit_cube);
+    commands
+        .spawn()
+        .insert_bundle(OrbitCameraBundle::new(Point3::new(0.0, 0.0, 0.0), 4.0));
+    commands.spawn().insert_bundle((
+        Transform::default(),
+        cube,
+        ColorMaterial {
+            color: Color::srgb(0.8, 0.3, 0.2),
+        },
+    ));
+}
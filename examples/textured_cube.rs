@@ -0,0 +1,47 @@
+use std::sync::Arc;
+
+use bevy_ecs::system::{Commands, Res, ResMut};
+use try_wgpu::{
+    camera::CameraBundle,
+    color::Color,
+    render::{
+        material::TextureMaterial,
+        mesh::{primitive::create_unit_cube, GpuMesh, MeshCache},
+    },
+    texture::Texture,
+    transform::Transform,
+    util::Store,
+    window::toggle_fullscreen_system,
+    FlatEngine,
+};
+
+/// Alt+Enter toggles fullscreen, the camera keeps the aspect ratio of the window
+fn main() {
+    FlatEngine::new()
+        .with_clear_color(Color::srgb(0.1, 0.2, 0.3))
+        .build()
+        .add_startup_system(spawn_cube)
+        .add_system(toggle_fullscreen_system)
+        .run();
+}
+
+fn spawn_cube(
+    mut commands: Commands,
+    device: Res<Arc<wgpu::Device>>,
+    queue: Res<wgpu::Queue>,
+    mut meshes: ResMut<Store<GpuMesh>>,
+    mut cache: ResMut<MeshCache>,
+) {
+    let tree = include_bytes!("../res/happy-tree.png");
+    let texture = Texture::from_bytes(&device, &queue, tree, true, "happy-tree.png").unwrap();
+    let cube = cache.get_or_create(&device, &mut meshes, "unit_cube", create_unit_cube);
+
+    commands.spawn().insert_bundle(CameraBundle::default());
+    commands.spawn().insert_bundle((
+        Transform::default(),
+        cube,
+        TextureMaterial {
+            texture: Arc::new(texture),
+        },
+    ));
+}
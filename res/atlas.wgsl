@@ -0,0 +1,35 @@
+// -- Vertex -----
+
+struct VertexInput {
+    @location(0)    position: vec3<f32>,
+    @location(1)    tex_coords: vec2<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position)  clip_position: vec4<f32>,
+    @location(0)        tex_coords: vec2<f32>,
+}
+
+@vertex
+fn vs_main(
+    mesh: VertexInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(mesh.position.xy, 0.5 - mesh.position.z * 0.5, 1.0);
+    out.tex_coords = mesh.tex_coords;
+    return out;
+}
+
+// -- Fragment -----
+
+@group(0) @binding(0)
+var t_atlas: texture_2d<f32>;
+@group(0) @binding(1)
+var s_atlas: sampler;
+
+// single channel coverage, drawn white
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let coverage = textureSample(t_atlas, s_atlas, in.tex_coords).r;
+    return vec4<f32>(coverage, coverage, coverage, 1.0);
+}
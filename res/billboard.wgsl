@@ -0,0 +1,57 @@
+
+// -- Vertex -----
+
+struct BillboardCamera {
+    view_proj: mat4x4<f32>,
+    // world space axes of the view, w unused
+    right: vec4<f32>,
+    up: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> camera: BillboardCamera;
+
+struct VertexInput {
+    @location(0)    position: vec3<f32>,
+    @location(1)    tex_coords: vec2<f32>,
+}
+
+struct ParticleInput {
+    @location(5)    center: vec3<f32>,
+    @location(6)    size: f32,
+    @location(7)    color: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position)  clip_position: vec4<f32>,
+    @location(0)        tex_coords: vec2<f32>,
+    @location(1)        color: vec4<f32>,
+}
+
+@vertex
+fn vs_main(
+    mesh: VertexInput,
+    particle: ParticleInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    // the quad is in the xy plane, spanned along the view axes it faces the camera
+    let offset = (camera.right.xyz * mesh.position.x + camera.up.xyz * mesh.position.y) * particle.size;
+    out.clip_position = camera.view_proj * vec4<f32>(particle.center + offset, 1.0);
+    out.tex_coords = mesh.tex_coords;
+    out.color = particle.color;
+    return out;
+}
+
+// -- Fragment -----
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // round, fading towards the edge
+    let distance = length(in.tex_coords - vec2<f32>(0.5, 0.5)) * 2.0;
+    let alpha = in.color.a * clamp(1.0 - distance, 0.0, 1.0);
+    // the corners would still write depth
+    if (alpha <= 0.0) {
+        discard;
+    }
+    return vec4<f32>(in.color.rgb, alpha);
+}
@@ -0,0 +1,26 @@
+
+//!include "common/object.wgsl"
+
+struct ColorUniform {
+    color: vec4<f32>,
+}
+
+@group(0) @binding(2)
+var<uniform> color: ColorUniform;
+
+struct VertexInput {
+    @location(0)    position: vec3<f32>,
+    @location(1)    tex_coords: vec2<f32>,
+}
+
+@vertex
+fn vs_main(
+    mesh: VertexInput,
+) -> @builtin(position) vec4<f32> {
+    return camera.view_proj * model.model * vec4<f32>(mesh.position, 1.0);
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return color.color;
+}
@@ -0,0 +1,8 @@
+// View projection of the camera, CameraUniform in src/camera.rs
+
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
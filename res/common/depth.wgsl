@@ -0,0 +1,40 @@
+// Depth of the main pass for post-processing, bound by DepthBindGroup in src/render/depth.rs.
+// Effects bind their own inputs at group 0.
+
+struct DepthPlanes {
+    // x: znear, y: zfar or 0 when infinite, z: 1 for orthographic projections,
+    // w: 1 when the near plane is at depth 1
+    planes: vec4<f32>,
+}
+
+@group(1) @binding(0)
+var<uniform> depth_planes: DepthPlanes;
+@group(1) @binding(1)
+var t_depth: texture_depth_2d;
+@group(1) @binding(2)
+var s_depth: sampler;
+
+// View distance of a perspective depth in [0, 1]
+fn linearize_depth(raw: f32, znear: f32, zfar: f32) -> f32 {
+    return znear * zfar / (zfar - raw * (zfar - znear));
+}
+
+// View distance at `uv`, zfar where nothing was drawn, or very far without one
+fn sample_linear_depth(uv: vec2<f32>) -> f32 {
+    let raw = textureSample(t_depth, s_depth, uv);
+    let znear = depth_planes.planes.x;
+    let zfar = depth_planes.planes.y;
+    // measured from the near plane
+    var depth = raw;
+    if (depth_planes.planes.w > 0.5) {
+        depth = 1.0 - raw;
+    }
+    if (depth_planes.planes.z > 0.5) {
+        return mix(znear, zfar, depth);
+    }
+    if (zfar <= 0.0) {
+        // reversed without a far plane, raw = znear / distance
+        return znear / max(raw, 1e-7);
+    }
+    return linearize_depth(depth, znear, zfar);
+}
@@ -0,0 +1,19 @@
+// A single triangle covering the screen, drawn with 3 vertices and no vertex buffer:
+//
+// @vertex
+// fn vs_main(@builtin(vertex_index) index: u32) -> FullscreenOutput {
+//     return fullscreen_triangle(index);
+// }
+
+struct FullscreenOutput {
+    @builtin(position)  clip_position: vec4<f32>,
+    @location(0)        uv: vec2<f32>,
+}
+
+fn fullscreen_triangle(index: u32) -> FullscreenOutput {
+    var out: FullscreenOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
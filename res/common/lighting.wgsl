@@ -0,0 +1,70 @@
+// Lights of the frame, LightsUniform in src/light.rs, bound by GpuLights at group 1.
+// Lit materials bind their own set at group 0.
+
+struct PackedPointLight {
+    // xyz: world position, w: range
+    position_range: vec4<f32>,
+    // rgb: color, a: intensity
+    color_intensity: vec4<f32>,
+}
+
+struct PackedDirectionalLight {
+    // xyz: direction the light travels along
+    direction: vec4<f32>,
+    // rgb: color, a: intensity, zero without a directional light
+    color_intensity: vec4<f32>,
+}
+
+struct LightsUniform {
+    count: u32,
+    // xyz: eye of the camera
+    eye: vec4<f32>,
+    dir_light: PackedDirectionalLight,
+    // MAX_POINT_LIGHTS, `count` of them are set
+    point_lights: array<PackedPointLight, 8>,
+}
+
+@group(1) @binding(0)
+var<uniform> lights: LightsUniform;
+
+// Lambert diffuse and Blinn-Phong specular of one light, `to_light` normalized
+fn blinn_phong(to_light: vec3<f32>, to_eye: vec3<f32>, normal: vec3<f32>, albedo: vec3<f32>, radiance: vec3<f32>) -> vec3<f32> {
+    let diffuse = max(dot(normal, to_light), 0.0) * albedo;
+    let half_dir = normalize(to_light + to_eye);
+    let specular = pow(max(dot(normal, half_dir), 0.0), 32.0) * 0.5;
+    return (diffuse + vec3<f32>(specular, specular, specular)) * radiance;
+}
+
+// Color of a surface at `world_pos` lit by every light, with a small ambient term
+fn calc_lighting(world_pos: vec3<f32>, normal: vec3<f32>, albedo: vec3<f32>) -> vec3<f32> {
+    let n = normalize(normal);
+    let to_eye = normalize(lights.eye.xyz - world_pos);
+    var color = albedo * 0.03;
+
+    let dir_light = lights.dir_light;
+    color = color + blinn_phong(
+        normalize(-dir_light.direction.xyz),
+        to_eye,
+        n,
+        albedo,
+        dir_light.color_intensity.rgb * dir_light.color_intensity.a,
+    );
+
+    for (var i = 0u; i < lights.count; i = i + 1u) {
+        let light = lights.point_lights[i];
+        let offset = light.position_range.xyz - world_pos;
+        let distance = length(offset);
+        let range = light.position_range.w;
+        // smooth window to zero at the range, over the inverse square falloff
+        let window = clamp(1.0 - pow(distance / range, 4.0), 0.0, 1.0);
+        let attenuation = window * window / (1.0 + distance * distance);
+        color = color + blinn_phong(
+            offset / max(distance, 0.0001),
+            to_eye,
+            n,
+            albedo,
+            light.color_intensity.rgb * light.color_intensity.a * attenuation,
+        );
+    }
+    return color;
+}
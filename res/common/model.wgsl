@@ -0,0 +1,25 @@
+// Model matrix of an object, ModelUniform in src/transform.rs, or of an instance,
+// InstanceRaw in src/render/resource/buffer.rs
+
+struct ModelUniform {
+    model: mat4x4<f32>,
+}
+
+@group(0) @binding(1)
+var<uniform> model: ModelUniform;
+
+struct InstanceInput {
+    @location(5)    model_mx_0: vec4<f32>,
+    @location(6)    model_mx_1: vec4<f32>,
+    @location(7)    model_mx_2: vec4<f32>,
+    @location(8)    model_mx_3: vec4<f32>,
+}
+
+fn instance_model(instance: InstanceInput) -> mat4x4<f32> {
+    return mat4x4<f32>(
+        instance.model_mx_0,
+        instance.model_mx_1,
+        instance.model_mx_2,
+        instance.model_mx_3,
+    );
+}
@@ -0,0 +1,4 @@
+// Per-object uniforms shared by the materials in src/render/material.rs
+
+//!include "common/camera.wgsl"
+//!include "common/model.wgsl"
@@ -0,0 +1,19 @@
+// Joint matrices of a skinned mesh, filled from the Skeleton in src/render/skin.rs.
+// Bound at group 0 after the per-object uniforms.
+
+struct JointPalette {
+    // MAX_JOINTS
+    joints: array<mat4x4<f32>, 64>,
+}
+
+@group(0) @binding(2)
+var<uniform> joint_palette: JointPalette;
+
+// Blend of the joints influencing a vertex, the weights sum to one.
+// Points have w = 1, directions like normals w = 0
+fn skin(v: vec4<f32>, joints: vec4<u32>, weights: vec4<f32>) -> vec4<f32> {
+    return (joint_palette.joints[joints.x] * v) * weights.x
+        + (joint_palette.joints[joints.y] * v) * weights.y
+        + (joint_palette.joints[joints.z] * v) * weights.z
+        + (joint_palette.joints[joints.w] * v) * weights.w;
+}
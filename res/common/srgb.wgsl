@@ -0,0 +1,15 @@
+// Exact sRGB transfer functions, for targets that do not encode themselves
+
+fn srgb_to_linear(color: vec3<f32>) -> vec3<f32> {
+    let offset = vec3<f32>(0.055, 0.055, 0.055);
+    let low = color * (1.0 / 12.92);
+    let high = pow((color + offset) * (1.0 / 1.055), vec3<f32>(2.4, 2.4, 2.4));
+    return select(high, low, color <= vec3<f32>(0.04045, 0.04045, 0.04045));
+}
+
+fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+    let offset = vec3<f32>(0.055, 0.055, 0.055);
+    let low = color * 12.92;
+    let high = pow(color, vec3<f32>(1.0 / 2.4, 1.0 / 2.4, 1.0 / 2.4)) * 1.055 - offset;
+    return select(high, low, color <= vec3<f32>(0.0031308, 0.0031308, 0.0031308));
+}
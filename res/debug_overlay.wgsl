@@ -0,0 +1,42 @@
+struct ScreenUniform {
+    // xy: size in pixels
+    size: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> screen: ScreenUniform;
+@group(0) @binding(1)
+var t_atlas: texture_2d<f32>;
+@group(0) @binding(2)
+var s_atlas: sampler;
+
+struct VertexInput {
+    @location(0)    position: vec2<f32>,
+    @location(1)    tex_coords: vec2<f32>,
+    @location(2)    color: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position)  clip_position: vec4<f32>,
+    @location(0)        tex_coords: vec2<f32>,
+    @location(1)        color: vec4<f32>,
+}
+
+@vertex
+fn vs_main(
+    glyph: VertexInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    // pixels, origin at the bottom left
+    let ndc = glyph.position.xy / screen.size.xy * 2.0 - vec2<f32>(1.0, 1.0);
+    out.clip_position = vec4<f32>(ndc, 0.0, 1.0);
+    out.tex_coords = glyph.tex_coords;
+    out.color = glyph.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let coverage = textureSample(t_atlas, s_atlas, in.tex_coords).r;
+    return vec4<f32>(in.color.rgb, in.color.a * coverage);
+}
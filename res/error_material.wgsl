@@ -0,0 +1,16 @@
+//!include "common/object.wgsl"
+
+// Drawn in place of a material whose shader could not be loaded or preprocessed,
+// every material vertex starts with the position
+
+@vertex
+fn vs_main(
+    @location(0) position: vec3<f32>,
+) -> @builtin(position) vec4<f32> {
+    return camera.view_proj * model.model * vec4<f32>(position, 1.0);
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return vec4<f32>(1.0, 0.0, 1.0, 1.0);
+}
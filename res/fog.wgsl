@@ -0,0 +1,26 @@
+//!include "common/depth.wgsl"
+//!include "common/fullscreen.wgsl"
+
+struct Fog {
+    color: vec4<f32>,
+    // x: view distance the fog starts at, y: where it is opaque
+    range: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> fog: Fog;
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) index: u32,
+) -> FullscreenOutput {
+    return fullscreen_triangle(index);
+}
+
+// blended over the frame, alpha is the amount of fog
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let distance = sample_linear_depth(in.uv);
+    let amount = clamp((distance - fog.range.x) / (fog.range.y - fog.range.x), 0.0, 1.0);
+    return vec4<f32>(fog.color.rgb, amount * fog.color.a);
+}
@@ -0,0 +1,40 @@
+//!include "common/object.wgsl"
+//!include "common/lighting.wgsl"
+
+struct ColorUniform {
+    color: vec4<f32>,
+}
+
+@group(0) @binding(2)
+var<uniform> color: ColorUniform;
+
+struct VertexInput {
+    @location(0)    position: vec3<f32>,
+    @location(1)    tex_coords: vec2<f32>,
+    @location(2)    normal: vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position)  clip_position: vec4<f32>,
+    @location(0)        world_position: vec3<f32>,
+    @location(1)        normal: vec3<f32>,
+}
+
+@vertex
+fn vs_main(
+    mesh: VertexInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    let world_position = model.model * vec4<f32>(mesh.position, 1.0);
+    out.clip_position = camera.view_proj * world_position;
+    out.world_position = world_position.xyz;
+    // NOTE: without a normal matrix, only right for uniform scales
+    out.normal = (model.model * vec4<f32>(mesh.normal, 0.0)).xyz;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let lit = calc_lighting(in.world_position, in.normal, color.color.rgb);
+    return vec4<f32>(lit, color.color.a);
+}
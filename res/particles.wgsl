@@ -0,0 +1,28 @@
+
+struct Instance {
+    model: mat4x4<f32>,
+}
+
+struct Params {
+    time: vec4<f32>, // x: delta seconds
+}
+
+@group(0) @binding(0)
+var<storage, read_write> instances: array<Instance>;
+@group(0) @binding(1)
+var<storage, read> velocities: array<vec4<f32>>;
+@group(0) @binding(2)
+var<uniform> params: Params;
+
+@compute @workgroup_size(64)
+fn cs_main(
+    @builtin(global_invocation_id) id: vec3<u32>,
+) {
+    let i = id.x;
+    if (i >= arrayLength(&instances)) {
+        return;
+    }
+
+    let step = velocities[i].xyz * params.time.x;
+    instances[i].model[3] = instances[i].model[3] + vec4<f32>(step, 0.0);
+}
@@ -0,0 +1,84 @@
+struct PostProcessUniform {
+    // x: exposure, y: gamma, z: vignette intensity, w: vignette radius
+    params: vec4<f32>,
+    // x: tonemapping operator, y: 1 if the target takes linear values,
+    // z: 1 if the target keeps values above one
+    flags: vec4<u32>,
+}
+
+@group(0) @binding(0)
+var<uniform> settings: PostProcessUniform;
+@group(0) @binding(1)
+var t_hdr: texture_2d<f32>;
+@group(0) @binding(2)
+var s_hdr: sampler;
+
+struct VertexOutput {
+    @builtin(position)  clip_position: vec4<f32>,
+    @location(0)        uv: vec2<f32>,
+}
+
+// a single triangle covering the screen, no vertex buffer
+@vertex
+fn vs_main(
+    @builtin(vertex_index) index: u32,
+) -> VertexOutput {
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+fn reinhard(color: vec3<f32>) -> vec3<f32> {
+    return color / (color + vec3<f32>(1.0, 1.0, 1.0));
+}
+
+// Narkowicz 2015, fit of the ACES filmic curve
+fn aces(x: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return clamp(
+        (x * (a * x + b)) / (x * (c * x + d) + e),
+        vec3<f32>(0.0, 0.0, 0.0),
+        vec3<f32>(1.0, 1.0, 1.0),
+    );
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let hdr = textureSample(t_hdr, s_hdr, in.uv);
+    var color = hdr.rgb * settings.params.x;
+
+    switch settings.flags.x {
+        case 1u: {
+            color = reinhard(color);
+        }
+        case 2u: {
+            color = aces(color);
+        }
+        default: {
+            color = max(color, vec3<f32>(0.0, 0.0, 0.0));
+            if (settings.flags.z == 0u) {
+                color = min(color, vec3<f32>(1.0, 1.0, 1.0));
+            }
+        }
+    }
+
+    if (settings.params.z > 0.0) {
+        // 0 at the center, 1 at the corners
+        let d = distance(in.uv, vec2<f32>(0.5, 0.5)) * 1.41421356;
+        color = color * (1.0 - settings.params.z * smoothstep(settings.params.w, 1.0, d));
+    }
+
+    // srgb targets encode with ~2.2 themselves and float ones are linear, only the
+    // difference is applied
+    let gamma = settings.params.y;
+    let exponent = select(1.0 / gamma, 2.2 / gamma, settings.flags.y == 1u);
+    color = pow(color, vec3<f32>(exponent, exponent, exponent));
+
+    return vec4<f32>(color, hdr.a);
+}
@@ -0,0 +1,34 @@
+// -- Vertex -----
+
+struct VertexInput {
+    @location(0)    position: vec3<f32>,
+    @location(1)    tex_coords: vec2<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position)  clip_position: vec4<f32>,
+    @location(0)        tex_coords: vec2<f32>,
+}
+
+@vertex
+fn vs_main(
+    mesh: VertexInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    // +z is towards the viewer, map [-1, 1] to depth [1, 0]
+    out.clip_position = vec4<f32>(mesh.position.xy, 0.5 - mesh.position.z * 0.5, 1.0);
+    out.tex_coords = mesh.tex_coords;
+    return out;
+}
+
+// -- Fragment -----
+
+@group(0) @binding(0)
+var t_diffuse: texture_2d<f32>;
+@group(0) @binding(1)
+var s_diffuse: sampler;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
+}
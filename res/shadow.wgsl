@@ -0,0 +1,52 @@
+
+// Depth-only pass, rendered from the directional light
+
+struct LightUniform {
+    view_proj: mat4x4<f32>,
+    direction: vec4<f32>,
+    color: vec4<f32>,
+    shadow_params: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> light: LightUniform;
+
+struct InstanceInput {
+    @location(5)    model_mx_0: vec4<f32>,
+    @location(6)    model_mx_1: vec4<f32>,
+    @location(7)    model_mx_2: vec4<f32>,
+    @location(8)    model_mx_3: vec4<f32>,
+}
+
+@vertex
+fn vs_main(
+    @location(0) position: vec3<f32>,
+) -> @builtin(position) vec4<f32> {
+    return light.view_proj * vec4<f32>(position, 1.0);
+}
+
+@vertex
+fn vs_instanced(
+    @location(0) position: vec3<f32>,
+    instance: InstanceInput,
+) -> @builtin(position) vec4<f32> {
+    let model = mat4x4<f32>(
+        instance.model_mx_0,
+        instance.model_mx_1,
+        instance.model_mx_2,
+        instance.model_mx_3,
+    );
+    return light.view_proj * model * vec4<f32>(position, 1.0);
+}
+
+// Receiver side, bind as a group of the main pass:
+//
+// @group(N) @binding(0) var<uniform> light: LightUniform;
+// @group(N) @binding(1) var shadow_map: texture_depth_2d;
+// @group(N) @binding(2) var shadow_sampler: sampler_comparison;
+//
+// fn shadow_factor(world_position: vec3<f32>) -> f32 {
+//     let p = light.view_proj * vec4<f32>(world_position, 1.0);
+//     let uv = p.xy / p.w * vec2<f32>(0.5, -0.5) + vec2<f32>(0.5, 0.5);
+//     return textureSampleCompare(shadow_map, shadow_sampler, uv, p.z / p.w - light.shadow_params.x);
+// }
@@ -0,0 +1,30 @@
+//!include "common/object.wgsl"
+//!include "common/skinning.wgsl"
+
+struct ColorUniform {
+    color: vec4<f32>,
+}
+
+@group(0) @binding(3)
+var<uniform> color: ColorUniform;
+
+struct VertexInput {
+    @location(0)    position: vec3<f32>,
+    @location(1)    tex_coords: vec2<f32>,
+    @location(2)    normal: vec3<f32>,
+    @location(3)    joints: vec4<u32>,
+    @location(4)    weights: vec4<f32>,
+}
+
+@vertex
+fn vs_main(
+    mesh: VertexInput,
+) -> @builtin(position) vec4<f32> {
+    let position = skin(vec4<f32>(mesh.position, 1.0), mesh.joints, mesh.weights);
+    return camera.view_proj * model.model * position;
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return color.color;
+}
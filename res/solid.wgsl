@@ -0,0 +1,22 @@
+
+// -- Vertex -----
+
+struct VertexInput {
+    @location(0)    position: vec3<f32>,
+    @location(1)    tex_coords: vec2<f32>,
+}
+
+@vertex
+fn vs_main(
+    mesh: VertexInput,
+) -> @builtin(position) vec4<f32> {
+    // +z is towards the viewer, map [-1, 1] to depth [1, 0]
+    return vec4<f32>(mesh.position.xy, 0.5 - mesh.position.z * 0.5, 1.0);
+}
+
+// -- Fragment -----
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return vec4<f32>(1.0, 0.0, 0.0, 1.0);
+}
@@ -0,0 +1,58 @@
+
+// -- Vertex -----
+
+struct Camera {
+    view_proj: mat4x4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+struct VertexInput {
+    @location(0)    position: vec3<f32>,
+    @location(1)    tex_coords: vec2<f32>,
+}
+
+struct SpriteInput {
+    // bottom left corner
+    @location(5)    position: vec3<f32>,
+    @location(6)    size: vec2<f32>,
+    // texture coordinates, min is the top left
+    @location(7)    uv_min: vec2<f32>,
+    @location(8)    uv_max: vec2<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position)  clip_position: vec4<f32>,
+    @location(0)        tex_coords: vec2<f32>,
+}
+
+@vertex
+fn vs_main(
+    mesh: VertexInput,
+    sprite: SpriteInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    // the quad spans (0, 0) to (1, 1)
+    let position = sprite.position + vec3<f32>(mesh.position.xy * sprite.size, 0.0);
+    out.clip_position = camera.view_proj * vec4<f32>(position, 1.0);
+    out.tex_coords = mix(sprite.uv_min, sprite.uv_max, mesh.tex_coords);
+    return out;
+}
+
+// -- Fragment -----
+
+@group(1) @binding(0)
+var t_atlas: texture_2d<f32>;
+@group(1) @binding(1)
+var s_atlas: sampler;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_atlas, s_atlas, in.tex_coords);
+    // transparent texels would still write depth
+    if (color.a <= 0.0) {
+        discard;
+    }
+    return color;
+}
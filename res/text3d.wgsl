@@ -0,0 +1,79 @@
+// -- Vertex -----
+
+struct BillboardCamera {
+    view_proj: mat4x4<f32>,
+    // world space axes of the view, w of right: the nearest depth
+    right: vec4<f32>,
+    up: vec4<f32>,
+}
+
+struct Label {
+    // world position of the baseline center, w: 1 for billboards
+    anchor: vec4<f32>,
+    // world axes the glyphs of fixed labels are spanned along, w unused
+    right: vec4<f32>,
+    up: vec4<f32>,
+    color: vec4<f32>,
+    // x: half the width in pixels, y: world units per pixel, z: 1 when drawn on top
+    layout: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> camera: BillboardCamera;
+
+@group(2) @binding(0)
+var<uniform> label: Label;
+
+struct VertexInput {
+    @location(0)    position: vec2<f32>,
+    @location(1)    tex_coords: vec2<f32>,
+    @location(2)    color: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position)  clip_position: vec4<f32>,
+    @location(0)        tex_coords: vec2<f32>,
+    @location(1)        color: vec4<f32>,
+}
+
+@vertex
+fn vs_main(
+    glyph: VertexInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    // pixels from the baseline center
+    let pixel = vec2<f32>(glyph.position.x - label.layout.x, glyph.position.y);
+    var right = label.right.xyz;
+    var up = label.up.xyz;
+    // spanned along the view axes the glyphs face the camera
+    if (label.anchor.w > 0.5) {
+        right = camera.right.xyz;
+        up = camera.up.xyz;
+    }
+    let world = label.anchor.xyz + (right * pixel.x + up * pixel.y) * label.layout.y;
+    out.clip_position = camera.view_proj * vec4<f32>(world, 1.0);
+    // nearest depth, the geometry drawn after fails the depth test against it
+    if (label.layout.z > 0.5) {
+        out.clip_position.z = camera.right.w * out.clip_position.w;
+    }
+    out.tex_coords = glyph.tex_coords;
+    out.color = glyph.color * label.color;
+    return out;
+}
+
+// -- Fragment -----
+
+@group(1) @binding(0)
+var t_atlas: texture_2d<f32>;
+@group(1) @binding(1)
+var s_atlas: sampler;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let coverage = textureSample(t_atlas, s_atlas, in.tex_coords).r;
+    // the empty parts of the glyph quads would still write depth
+    if (coverage <= 0.0) {
+        discard;
+    }
+    return vec4<f32>(in.color.rgb, in.color.a * coverage);
+}
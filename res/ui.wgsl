@@ -0,0 +1,43 @@
+
+// -- Vertex -----
+
+struct UiQuad {
+    // pixels to clip space
+    transform: mat4x4<f32>,
+    color: vec4<f32>,
+}
+
+@group(1) @binding(0)
+var<uniform> quad: UiQuad;
+
+struct VertexInput {
+    @location(0)    position: vec3<f32>,
+    @location(1)    tex_coords: vec2<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position)  clip_position: vec4<f32>,
+    @location(0)        tex_coords: vec2<f32>,
+}
+
+@vertex
+fn vs_main(
+    mesh: VertexInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = quad.transform * vec4<f32>(mesh.position, 1.0);
+    out.tex_coords = mesh.tex_coords;
+    return out;
+}
+
+// -- Fragment -----
+
+@group(0) @binding(0)
+var t_atlas: texture_2d<f32>;
+@group(0) @binding(1)
+var s_atlas: sampler;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(t_atlas, s_atlas, in.tex_coords) * quad.color;
+}
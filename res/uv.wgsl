@@ -0,0 +1,29 @@
+// -- Vertex -----
+
+struct VertexInput {
+    @location(0)    position: vec3<f32>,
+    @location(1)    tex_coords: vec2<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position)  clip_position: vec4<f32>,
+    @location(0)        tex_coords: vec2<f32>,
+}
+
+@vertex
+fn vs_main(
+    mesh: VertexInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    // +z is towards the viewer, map [-1, 1] to depth [1, 0]
+    out.clip_position = vec4<f32>(mesh.position.xy, 0.5 - mesh.position.z * 0.5, 1.0);
+    out.tex_coords = mesh.tex_coords;
+    return out;
+}
+
+// -- Fragment -----
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(in.tex_coords, 0.0, 1.0);
+}
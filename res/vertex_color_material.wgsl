@@ -0,0 +1,36 @@
+
+//!include "common/object.wgsl"
+
+@group(0) @binding(2)
+var t_diffuse: texture_2d<f32>;
+@group(0) @binding(3)
+var s_diffuse: sampler;
+
+struct VertexInput {
+    @location(0)    position: vec3<f32>,
+    @location(1)    tex_coords: vec2<f32>,
+    @location(2)    color: vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position)  clip_position: vec4<f32>,
+    @location(0)        tex_coords: vec2<f32>,
+    @location(1)        color: vec3<f32>,
+}
+
+@vertex
+fn vs_main(
+    mesh: VertexInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * model.model * vec4<f32>(mesh.position, 1.0);
+    out.tex_coords = mesh.tex_coords;
+    out.color = mesh.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    return vec4<f32>(texel.rgb * in.color, texel.a);
+}
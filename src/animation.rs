@@ -0,0 +1,414 @@
+use std::f32::consts::TAU;
+
+use bevy_app::{CoreStage, Plugin};
+use bevy_asset::{AddAsset, Assets, Handle};
+use bevy_ecs::{
+    prelude::Component,
+    schedule::ParallelSystemDescriptorCoercion,
+    system::{Query, Res},
+};
+use bevy_reflect::TypeUuid;
+use cgmath::{InnerSpace, Quaternion, Rad, Rotation3};
+
+use crate::{time::Time, transform::Transform, FlatSystemLabels};
+
+pub struct FlatAnimationPlugin;
+impl Plugin for FlatAnimationPlugin {
+    fn build(&self, app: &mut bevy_app::App) {
+        app.add_asset::<AnimationClip>()
+            // NOTE: after the systems of the app, which may start or pause players
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                animation_system.label(FlatSystemLabels::TransformPropagate),
+            );
+    }
+}
+
+/// The [`Transform`] field a [`Curve`] writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CurveTarget {
+    TranslationX,
+    TranslationY,
+    TranslationZ,
+    ScaleX,
+    ScaleY,
+    ScaleZ,
+    /// Every axis of the scale
+    ScaleUniform,
+    RotationQuat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    /// The value of the last key until the next one
+    Step,
+    Linear,
+    /// Catmull-Rom through the keys, the tangents come from the neighbouring keys.
+    /// Rotations are slerped like `Linear`
+    CubicSpline,
+}
+
+/// Keys sorted by time, in seconds
+#[derive(Debug, Clone, PartialEq)]
+pub enum Keyframes {
+    Scalar(Vec<(f32, f32)>),
+    Rotation(Vec<(f32, Quaternion<f32>)>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CurveValue {
+    Scalar(f32),
+    Rotation(Quaternion<f32>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Curve {
+    pub target: CurveTarget,
+    pub keyframes: Keyframes,
+    pub interpolation: Interpolation,
+}
+
+impl Curve {
+    /// Keys are sorted by time
+    pub fn scalar(
+        target: CurveTarget,
+        mut keyframes: Vec<(f32, f32)>,
+        interpolation: Interpolation,
+    ) -> Self {
+        debug_assert_ne!(target, CurveTarget::RotationQuat);
+        keyframes.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Self {
+            target,
+            keyframes: Keyframes::Scalar(keyframes),
+            interpolation,
+        }
+    }
+
+    /// Keys are sorted by time
+    pub fn rotation(
+        mut keyframes: Vec<(f32, Quaternion<f32>)>,
+        interpolation: Interpolation,
+    ) -> Self {
+        keyframes.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Self {
+            target: CurveTarget::RotationQuat,
+            keyframes: Keyframes::Rotation(keyframes),
+            interpolation,
+        }
+    }
+
+    /// Time of the last key, zero without keys
+    pub fn duration(&self) -> f32 {
+        let last = match &self.keyframes {
+            Keyframes::Scalar(keys) => keys.last().map(|key| key.0),
+            Keyframes::Rotation(keys) => keys.last().map(|key| key.0),
+        };
+        last.unwrap_or(0.0)
+    }
+
+    /// Clamped to the first and last key, `None` without keys
+    pub fn sample(&self, time: f32) -> Option<CurveValue> {
+        match &self.keyframes {
+            Keyframes::Scalar(keys) => {
+                let (i, j, s) = segment(keys, time)?;
+                let value = match self.interpolation {
+                    Interpolation::Step => keys[i].1,
+                    Interpolation::Linear => keys[i].1 + (keys[j].1 - keys[i].1) * s,
+                    Interpolation::CubicSpline => catmull_rom(keys, i, j, s),
+                };
+                Some(CurveValue::Scalar(value))
+            }
+            Keyframes::Rotation(keys) => {
+                let (i, j, s) = segment(keys, time)?;
+                let value = match self.interpolation {
+                    Interpolation::Step => keys[i].1,
+                    Interpolation::Linear | Interpolation::CubicSpline => {
+                        slerp_shortest(keys[i].1, keys[j].1, s)
+                    }
+                };
+                Some(CurveValue::Rotation(value))
+            }
+        }
+    }
+
+    pub fn apply(&self, time: f32, transform: &mut Transform) {
+        match (self.target, self.sample(time)) {
+            (CurveTarget::TranslationX, Some(CurveValue::Scalar(x))) => transform.translation.x = x,
+            (CurveTarget::TranslationY, Some(CurveValue::Scalar(y))) => transform.translation.y = y,
+            (CurveTarget::TranslationZ, Some(CurveValue::Scalar(z))) => transform.translation.z = z,
+            (CurveTarget::ScaleX, Some(CurveValue::Scalar(x))) => transform.scale.x = x,
+            (CurveTarget::ScaleY, Some(CurveValue::Scalar(y))) => transform.scale.y = y,
+            (CurveTarget::ScaleZ, Some(CurveValue::Scalar(z))) => transform.scale.z = z,
+            (CurveTarget::ScaleUniform, Some(CurveValue::Scalar(scale))) => {
+                transform.scale = [scale; 3].into();
+            }
+            (CurveTarget::RotationQuat, Some(CurveValue::Rotation(rotation))) => {
+                transform.rotation = rotation;
+            }
+            // no keys, or keys of the other kind
+            _ => {}
+        }
+    }
+}
+
+// the keys around `time` and how far `time` is from the first to the second, the same
+// key twice before the first and past the last
+fn segment<T>(keys: &[(f32, T)], time: f32) -> Option<(usize, usize, f32)> {
+    let next = keys
+        .iter()
+        .position(|key| key.0 > time)
+        .unwrap_or(keys.len());
+    match next {
+        _ if keys.is_empty() => None,
+        0 => Some((0, 0, 0.0)),
+        _ if next == keys.len() => Some((next - 1, next - 1, 0.0)),
+        _ => {
+            let (start, end) = (keys[next - 1].0, keys[next].0);
+            Some((next - 1, next, (time - start) / (end - start)))
+        }
+    }
+}
+
+fn catmull_rom(keys: &[(f32, f32)], i: usize, j: usize, s: f32) -> f32 {
+    let (t0, p0) = keys[i];
+    let (t1, p1) = keys[j];
+    if i == j {
+        return p0;
+    }
+    // one-sided at the ends
+    let tangent = |k: usize| {
+        let (before, after) = (keys[k.saturating_sub(1)], keys[(k + 1).min(keys.len() - 1)]);
+        (after.1 - before.1) / (after.0 - before.0)
+    };
+    let dt = t1 - t0;
+    let (m0, m1) = (tangent(i) * dt, tangent(j) * dt);
+    let (s2, s3) = (s * s, s * s * s);
+    (2.0 * s3 - 3.0 * s2 + 1.0) * p0
+        + (s3 - 2.0 * s2 + s) * m0
+        + (-2.0 * s3 + 3.0 * s2) * p1
+        + (s3 - s2) * m1
+}
+
+/// Along the shorter arc, `q` and `-q` are the same rotation
+pub fn slerp_shortest(a: Quaternion<f32>, b: Quaternion<f32>, s: f32) -> Quaternion<f32> {
+    let mut dot = a.dot(b);
+    let b = if dot < 0.0 {
+        dot = -dot;
+        -b
+    } else {
+        b
+    };
+    // nearly the same rotation, the sine below would divide by zero
+    if dot > 0.9995 {
+        return (a * (1.0 - s) + b * s).normalize();
+    }
+    let angle = dot.acos();
+    let sin = angle.sin();
+    (a * ((1.0 - s) * angle).sin() + b * (s * angle).sin()) / sin
+}
+
+/// Keyframe curves over [`Transform`] fields, sampled by an [`AnimationPlayer`]
+#[derive(Debug, Clone, Default, PartialEq, TypeUuid)]
+#[uuid = "E8DFC308-E4B7-469C-B0BC-782443AD7960"]
+pub struct AnimationClip {
+    pub curves: Vec<Curve>,
+}
+
+impl AnimationClip {
+    pub fn new(curves: Vec<Curve>) -> Self {
+        Self { curves }
+    }
+
+    /// A full turn around y every `duration` seconds
+    pub fn rotate_y_loop(duration: f32) -> Self {
+        // NOTE: thirds of a turn, half turns would be ambiguous for the shortest path
+        let keys = (0..=3)
+            .map(|i| {
+                let part = i as f32 / 3.0;
+                (part * duration, Quaternion::from_angle_y(Rad(part * TAU)))
+            })
+            .collect();
+        Self::new(vec![Curve::rotation(keys, Interpolation::Linear)])
+    }
+
+    /// Time of the last key of any curve
+    pub fn duration(&self) -> f32 {
+        self.curves.iter().map(Curve::duration).fold(0.0, f32::max)
+    }
+
+    pub fn apply(&self, time: f32, transform: &mut Transform) {
+        for curve in &self.curves {
+            curve.apply(time, transform);
+        }
+    }
+}
+
+#[derive(Component, Debug, Clone)]
+pub struct AnimationPlayer {
+    pub clip: Handle<AnimationClip>,
+    /// Negative plays backwards
+    pub speed: f32,
+    /// Wraps past the end, otherwise holds the last keys
+    pub looping: bool,
+    pub paused: bool,
+    /// Seconds played, scaled by the speed
+    pub elapsed: f32,
+}
+
+impl AnimationPlayer {
+    /// Looping at normal speed
+    pub fn new(clip: Handle<AnimationClip>) -> Self {
+        Self {
+            clip,
+            speed: 1.0,
+            looping: true,
+            paused: false,
+            elapsed: 0.0,
+        }
+    }
+
+    /// Where in a clip of `duration` the player is
+    pub fn clip_time(&self, duration: f32) -> f32 {
+        if duration <= 0.0 {
+            0.0
+        } else if self.looping {
+            self.elapsed.rem_euclid(duration)
+        } else {
+            self.elapsed.clamp(0.0, duration)
+        }
+    }
+}
+
+/// Advances the players and writes the sampled curves to their transforms. Players of
+/// clips that are still loading wait at the start
+pub fn animation_system(
+    time: Res<Time>,
+    clips: Res<Assets<AnimationClip>>,
+    mut players: Query<(&mut AnimationPlayer, &mut Transform)>,
+) {
+    for (mut player, mut transform) in players.iter_mut() {
+        let clip = match clips.get(&player.clip) {
+            Some(clip) => clip,
+            None => continue,
+        };
+        if !player.paused {
+            player.elapsed += time.delta_seconds() * player.speed;
+        }
+        let duration = clip.duration();
+        clip.apply(player.clip_time(duration), &mut transform);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_asset::Handle;
+    use cgmath::{Deg, InnerSpace, Quaternion, Rotation3, Vector3};
+
+    use crate::transform::Transform;
+
+    use super::{
+        slerp_shortest, AnimationClip, AnimationPlayer, Curve, CurveTarget, CurveValue,
+        Interpolation,
+    };
+
+    fn scalar(curve: &Curve, time: f32) -> f32 {
+        match curve.sample(time) {
+            Some(CurveValue::Scalar(value)) => value,
+            other => panic!("{:?}", other),
+        }
+    }
+
+    fn same_rotation(a: Quaternion<f32>, b: Quaternion<f32>) -> bool {
+        a.dot(b).abs() > 1.0 - 1e-5
+    }
+
+    #[test]
+    fn scalar_curves_sample_between_keys() {
+        let keys = vec![(1.0, 10.0), (0.0, 0.0), (3.0, 0.0)];
+        let linear = Curve::scalar(
+            CurveTarget::TranslationX,
+            keys.clone(),
+            Interpolation::Linear,
+        );
+        assert_eq!(scalar(&linear, 0.0), 0.0);
+        assert!((scalar(&linear, 0.5) - 5.0).abs() < 1e-5);
+        assert!((scalar(&linear, 2.0) - 5.0).abs() < 1e-5);
+        // clamped outside of the keys
+        assert_eq!(scalar(&linear, -1.0), 0.0);
+        assert_eq!(scalar(&linear, 5.0), 0.0);
+
+        let step = Curve::scalar(CurveTarget::TranslationX, keys.clone(), Interpolation::Step);
+        assert_eq!(scalar(&step, 0.99), 0.0);
+        assert_eq!(scalar(&step, 1.0), 10.0);
+
+        let cubic = Curve::scalar(CurveTarget::TranslationX, keys, Interpolation::CubicSpline);
+        // passes through the keys, smooth in between
+        assert!((scalar(&cubic, 1.0) - 10.0).abs() < 1e-5);
+        let mid = scalar(&cubic, 0.5);
+        assert!(mid > 0.0 && mid < 10.0 && (mid - 5.0).abs() > 1e-3);
+    }
+
+    #[test]
+    fn quaternions_take_the_shortest_path() {
+        let a = Quaternion::from_angle_y(Deg(10.0));
+        let b = Quaternion::from_angle_y(Deg(30.0));
+        // the same rotation as b, on the other side of the sphere
+        let mid = slerp_shortest(a, -b, 0.5);
+        assert!(same_rotation(mid, Quaternion::from_angle_y(Deg(20.0))));
+        assert!((mid.magnitude() - 1.0).abs() < 1e-5);
+
+        let curve = Curve::rotation(vec![(0.0, a), (1.0, -b)], Interpolation::Linear);
+        match curve.sample(0.25) {
+            Some(CurveValue::Rotation(rotation)) => {
+                assert!(same_rotation(rotation, Quaternion::from_angle_y(Deg(15.0))))
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn players_loop_or_clamp_past_the_end() {
+        let clip = AnimationClip::rotate_y_loop(3.0);
+        assert_eq!(clip.duration(), 3.0);
+        let mut player = AnimationPlayer::new(Handle::default());
+
+        let rotation_at = |player: &AnimationPlayer| {
+            let mut transform = Transform::default();
+            clip.apply(player.clip_time(clip.duration()), &mut transform);
+            transform.rotation
+        };
+        assert!(same_rotation(
+            rotation_at(&player),
+            Quaternion::from_angle_y(Deg(0.0))
+        ));
+        player.elapsed = 0.75;
+        assert!(same_rotation(
+            rotation_at(&player),
+            Quaternion::from_angle_y(Deg(90.0))
+        ));
+
+        player.elapsed = 3.75;
+        assert!((player.clip_time(3.0) - 0.75).abs() < 1e-5);
+        assert!(same_rotation(
+            rotation_at(&player),
+            Quaternion::from_angle_y(Deg(90.0))
+        ));
+
+        player.looping = false;
+        assert_eq!(player.clip_time(3.0), 3.0);
+        // a full turn
+        assert!(same_rotation(
+            rotation_at(&player),
+            Quaternion::from_angle_y(Deg(0.0))
+        ));
+
+        // other fields are kept
+        let mut transform = Transform::from_translation(Vector3::new(1.0, 2.0, 3.0));
+        clip.apply(1.5, &mut transform);
+        assert_eq!(transform.translation, Vector3::new(1.0, 2.0, 3.0));
+        assert!(same_rotation(
+            transform.rotation,
+            Quaternion::from_angle_y(Deg(180.0))
+        ));
+    }
+}
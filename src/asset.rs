@@ -1,18 +1,363 @@
-use bevy_app::Plugin;
-use bevy_asset::{AddAsset, AssetPlugin, AssetServerSettings};
+use std::{
+    any::Any,
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        mpsc::{self, Sender},
+        Arc, Mutex,
+    },
+    thread,
+};
 
-use crate::{render::resource::shader::ShaderSource, Text, TextLoader};
+use anyhow::anyhow;
+use bevy_app::{App, CoreStage, Plugin};
+use bevy_asset::{AddAsset, Asset, AssetEvent, AssetPlugin, AssetServerSettings, Handle};
+use bevy_ecs::event::{EventReader, EventWriter};
+use bevy_tasks::{IoTaskPool, TaskPool};
+use futures_intrusive::channel::shared::oneshot_channel;
+
+use crate::{
+    audio::{AudioLoader, AudioSource},
+    render::{
+        mesh::{ObjLoadOptions, ObjLoader, ObjMaterial, ObjMesh, ObjModel},
+        resource::shader::ShaderSource,
+    },
+    texture::{Image, ImageLoader},
+    Text, TextLoader,
+};
+
+pub struct FlatAssetPlugin {
+    pub asset_folder: String,
+    /// Reload assets when their files change, see [`AssetReloaded`]
+    pub watch: bool,
+    /// Threads of the [`DecodePool`], 0 decodes on the threads loading the assets
+    pub decode_threads: usize,
+    pub obj_options: ObjLoadOptions,
+}
+
+impl FlatAssetPlugin {
+    pub fn new(asset_folder: impl Into<String>, watch: bool) -> Self {
+        Self {
+            asset_folder: asset_folder.into(),
+            watch,
+            decode_threads: DecodePool::default_threads(),
+            obj_options: ObjLoadOptions::default(),
+        }
+    }
+
+    pub fn with_decode_threads(mut self, decode_threads: usize) -> Self {
+        self.decode_threads = decode_threads;
+        self
+    }
+
+    pub fn with_obj_options(mut self, obj_options: ObjLoadOptions) -> Self {
+        self.obj_options = obj_options;
+        self
+    }
+}
+
+impl Default for FlatAssetPlugin {
+    fn default() -> Self {
+        Self::new("res", false)
+    }
+}
 
-pub struct FlatAssetPlugin;
 impl Plugin for FlatAssetPlugin {
     fn build(&self, app: &mut bevy_app::App) {
+        let watch_for_changes = if self.watch && cfg!(target_arch = "wasm32") {
+            log::warn!("Watching assets for changes is not supported on this platform");
+            false
+        } else {
+            self.watch
+        };
+        let decode_pool = Arc::new(DecodePool::new(self.decode_threads));
+        // NOTE: the asset server loads on the global io pool, set up by bevy_core otherwise
+        IoTaskPool::init(TaskPool::default);
+
         app.insert_resource(AssetServerSettings {
-            asset_folder: "res".to_string(),
-            watch_for_changes: false,
+            asset_folder: self.asset_folder.clone(),
+            watch_for_changes,
         })
         .add_plugin(AssetPlugin)
         .add_asset_loader(TextLoader)
-        .add_asset::<Text>()
-        .add_asset::<ShaderSource>();
+        .add_reloadable_asset::<Text>()
+        .add_reloadable_asset::<ShaderSource>()
+        .add_asset_loader(ImageLoader::new(decode_pool.clone()))
+        .add_reloadable_asset::<Image>()
+        .add_asset_loader(ObjLoader::new(decode_pool.clone(), self.obj_options))
+        .add_reloadable_asset::<ObjModel>()
+        .add_reloadable_asset::<ObjMesh>()
+        .add_reloadable_asset::<ObjMaterial>()
+        .add_asset_loader(AudioLoader)
+        .add_asset::<AudioSource>()
+        .insert_resource(decode_pool);
+    }
+}
+
+type DecodeJob = Box<dyn FnOnce() + Send>;
+
+/// Threads for the CPU heavy part of loading, e.g. decoding images and parsing OBJ files.
+///
+/// Loaders read the bytes and await [`DecodePool::decode`], so a large file does not hold
+/// up the other loads. The finished asset still goes through the asset server, which
+/// inserts it into `Assets` and sends `AssetEvent::Created` on the main thread.
+/// Without threads, on wasm or when created with 0, jobs run on the calling thread.
+pub struct DecodePool {
+    // None runs the jobs inline
+    queue: Option<Mutex<Sender<DecodeJob>>>,
+    threads: usize,
+}
+
+impl Default for DecodePool {
+    fn default() -> Self {
+        Self::new(Self::default_threads())
+    }
+}
+
+impl DecodePool {
+    pub const MAX_THREADS: usize = 4;
+
+    pub fn new(threads: usize) -> Self {
+        let threads = if cfg!(target_arch = "wasm32") {
+            0
+        } else {
+            threads
+        };
+        let queue = (threads > 0).then(|| {
+            let (queue, jobs) = mpsc::channel::<DecodeJob>();
+            let jobs = Arc::new(Mutex::new(jobs));
+            for i in 0..threads {
+                let jobs = jobs.clone();
+                thread::Builder::new()
+                    .name(format!("Asset Decoder {}", i))
+                    .spawn(move || loop {
+                        // NOTE: the lock is released before running the job
+                        let next = jobs.lock().unwrap().recv();
+                        // the pool was dropped
+                        match next {
+                            Ok(job) => job(),
+                            Err(_) => break,
+                        }
+                    })
+                    .expect("Could not spawn an asset decoder thread");
+            }
+            Mutex::new(queue)
+        });
+        Self { queue, threads }
+    }
+
+    /// One per core, up to [`DecodePool::MAX_THREADS`]
+    pub fn default_threads() -> usize {
+        thread::available_parallelism()
+            .map_or(1, |threads| threads.get())
+            .min(Self::MAX_THREADS)
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Runs `job` on one of the threads, a panic is returned as an error
+    pub fn decode<T: Send + 'static>(
+        &self,
+        job: impl FnOnce() -> anyhow::Result<T> + Send + 'static,
+    ) -> impl Future<Output = anyhow::Result<T>> + Send + 'static {
+        let (sender, receiver) = oneshot_channel();
+        let job: DecodeJob = Box::new(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(job)).unwrap_or_else(|payload| {
+                Err(anyhow!("Decoding panicked: {}", panic_message(&*payload)))
+            });
+            // the load was dropped
+            let _ = sender.send(result);
+        });
+        match &self.queue {
+            Some(queue) => {
+                if let Err(mpsc::SendError(job)) = queue.lock().unwrap().send(job) {
+                    job();
+                }
+            }
+            None => job(),
+        }
+
+        async move {
+            receiver
+                .receive()
+                .await
+                .unwrap_or_else(|| Err(anyhow!("The decode job was dropped")))
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown error")
+}
+
+/// Sent the frame after an asset of type `T` was reloaded from disk
+pub struct AssetReloaded<T: Asset> {
+    pub handle: Handle<T>,
+}
+
+pub fn forward_asset_reloaded_system<T: Asset>(
+    mut asset_events: EventReader<AssetEvent<T>>,
+    mut reloaded_events: EventWriter<AssetReloaded<T>>,
+) {
+    for event in asset_events.iter() {
+        if let AssetEvent::Modified { handle } = event {
+            reloaded_events.send(AssetReloaded {
+                handle: handle.clone_weak(),
+            });
+        }
+    }
+}
+
+pub trait AddReloadableAsset {
+    fn add_reloadable_asset<T: Asset>(&mut self) -> &mut Self;
+}
+
+impl AddReloadableAsset for App {
+    fn add_reloadable_asset<T: Asset>(&mut self) -> &mut Self {
+        self.add_asset::<T>()
+            .add_event::<AssetReloaded<T>>()
+            .add_system_to_stage(CoreStage::PreUpdate, forward_asset_reloaded_system::<T>)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Cursor,
+        sync::{Arc, Mutex},
+        thread,
+        time::Duration,
+    };
+
+    use bevy_app::App;
+    use bevy_asset::{AssetServer, Assets, Handle, LoadState};
+
+    use crate::{
+        render::mesh::{ObjMaterial, ObjMesh, ObjModel},
+        texture::Image,
+        Text,
+    };
+
+    use super::{DecodePool, FlatAssetPlugin};
+
+    #[test]
+    fn last_strong_handle_frees_the_asset() {
+        let mut app = App::new();
+        app.add_plugin(FlatAssetPlugin::default());
+
+        let handle = app
+            .world
+            .resource_mut::<Assets<Text>>()
+            .add(Text("text".to_string()));
+        let weak = handle.clone_weak();
+        let second = handle.clone();
+
+        drop(handle);
+        app.update();
+        assert!(app.world.resource::<Assets<Text>>().contains(&weak));
+
+        // weak handles do not keep it alive
+        drop(second);
+        app.update();
+        assert!(!app.world.resource::<Assets<Text>>().contains(&weak));
+    }
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = Cursor::new(Vec::new());
+        image::DynamicImage::ImageRgba8(image::RgbaImage::new(width, height))
+            .write_to(&mut bytes, image::ImageOutputFormat::Png)
+            .unwrap();
+        bytes.into_inner()
+    }
+
+    #[test]
+    fn images_decode_concurrently_in_any_order() {
+        let pool = Arc::new(DecodePool::new(3));
+        assert_eq!(pool.threads(), 3);
+        let main = thread::current().id();
+        let finished = Arc::new(Mutex::new(Vec::new()));
+
+        // the first image takes the longest, so they finish in reverse
+        let decodes: Vec<_> = (0..3u32)
+            .map(|i| {
+                let (bytes, finished) = (png(8 * (i + 1), 4), finished.clone());
+                pool.decode(move || {
+                    thread::sleep(Duration::from_millis(60 * (3 - i as u64)));
+                    assert_ne!(thread::current().id(), main);
+                    let image = Image::decode(&bytes)?;
+                    finished.lock().unwrap().push(i);
+                    Ok(image)
+                })
+            })
+            .collect();
+
+        // awaited in request order, each still gets its own image
+        for (i, decode) in decodes.into_iter().enumerate() {
+            let image = pollster::block_on(decode).unwrap();
+            assert_eq!(image.dim, (8 * (i as u32 + 1), 4));
+            assert_eq!(image.bytes.len(), 4 * 8 * (i + 1) * 4);
+        }
+        assert_eq!(*finished.lock().unwrap(), vec![2, 1, 0]);
+    }
+
+    #[test]
+    fn failed_decodes_are_errors() {
+        for pool in [DecodePool::new(1), DecodePool::new(0)] {
+            let decode = pool.decode(|| Image::decode(b"not an image"));
+            assert!(pollster::block_on(decode).is_err());
+            let decode = pool.decode(|| -> anyhow::Result<()> { panic!("corrupt file") });
+            let error = pollster::block_on(decode).unwrap_err();
+            assert!(error.to_string().contains("corrupt file"));
+        }
+        // inline without threads
+        let main = thread::current().id();
+        let decode = DecodePool::new(0).decode(move || Ok(thread::current().id() == main));
+        assert!(pollster::block_on(decode).unwrap());
+    }
+
+    #[test]
+    fn obj_meshes_and_materials_load_by_label() {
+        let mut app = App::new();
+        app.add_plugin(FlatAssetPlugin::default().with_decode_threads(0));
+        let asset_server = app.world.resource::<AssetServer>().clone();
+
+        let model: Handle<ObjModel> = asset_server.load("ship.obj");
+        let hull: Handle<ObjMesh> = asset_server.load("ship.obj#mesh/Hull");
+        let sail: Handle<ObjMesh> = asset_server.load("ship.obj#mesh/Sail");
+        let second_hull: Handle<ObjMesh> = asset_server.load("ship.obj#mesh/Hull_1");
+        let paint: Handle<ObjMaterial> = asset_server.load("ship.obj#material/Paint_Red");
+
+        // loaded on the io pool, added to the assets in an update after
+        for _ in 0..500 {
+            app.update();
+            if app.world.resource::<Assets<ObjModel>>().contains(&model) {
+                break;
+            }
+            assert_ne!(asset_server.get_load_state(&model), LoadState::Failed);
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let models = app.world.resource::<Assets<ObjModel>>();
+        let model = models.get(&model).expect("ship.obj is loaded");
+        assert_eq!(model.mesh_labels, vec!["Hull", "Sail", "Hull_1"]);
+        assert_eq!(model.material_labels, vec!["Paint_Red", "Canvas"]);
+        assert_eq!(model.mesh("Hull_1"), Some(&second_hull));
+        assert_eq!(model.material("Paint_Red"), Some(&paint));
+        assert!(model.mesh("Hull_2").is_none());
+
+        let meshes = app.world.resource::<Assets<ObjMesh>>();
+        for handle in [&hull, &sail, &second_hull] {
+            assert_eq!(meshes.get(handle).unwrap().mesh.vertex_count(), 3);
+        }
+        assert_eq!(meshes.get(&hull).unwrap().material, Some(paint.clone()));
+        assert_ne!(meshes.get(&sail).unwrap().material, Some(paint.clone()));
+        let materials = app.world.resource::<Assets<ObjMaterial>>();
+        assert_eq!(materials.get(&paint).unwrap().diffuse, [1.0, 0.0, 0.0]);
     }
 }
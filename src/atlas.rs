@@ -0,0 +1,316 @@
+use std::{collections::HashMap, fmt, hash::Hash};
+
+use crate::texture::{PixelFormat, RawImage, Texture};
+
+/// Normalized texture coordinates of an atlas entry
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rect {
+    pub min: (f32, f32),
+    pub max: (f32, f32),
+}
+
+/// Position of an atlas entry in pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PackedRect {
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+}
+
+impl PackedRect {
+    pub fn overlaps(&self, other: &PackedRect) -> bool {
+        self.x < other.x + other.w
+            && other.x < self.x + self.w
+            && self.y < other.y + other.h
+            && other.y < self.y + self.h
+    }
+
+    pub fn normalized(&self, (width, height): (u32, u32)) -> Rect {
+        Rect {
+            min: (self.x as f32 / width as f32, self.y as f32 / height as f32),
+            max: (
+                (self.x + self.w) as f32 / width as f32,
+                (self.y + self.h) as f32 / height as f32,
+            ),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TextureAtlasError {
+    /// Entries that could not be placed in an atlas of `max_size`
+    DoesNotFit {
+        offenders: Vec<String>,
+        max_size: (u32, u32),
+    },
+    PixelFormatMismatch {
+        key: String,
+        expected: PixelFormat,
+    },
+}
+
+impl fmt::Display for TextureAtlasError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TextureAtlasError::DoesNotFit {
+                offenders,
+                max_size: (width, height),
+            } => write!(
+                f,
+                "{} do not fit in a {}x{} atlas",
+                offenders.join(", "),
+                width,
+                height
+            ),
+            TextureAtlasError::PixelFormatMismatch { key, expected } => {
+                write!(f, "{} is not {:?} like the other entries", key, expected)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TextureAtlasError {}
+
+pub struct TexturePacking<K> {
+    pub size: (u32, u32),
+    pub rects: HashMap<K, PackedRect>,
+}
+
+/// Packs images into one texture using shelves sorted by height.
+/// Starts at `initial_size` and doubles up to `max_size` until everything fits.
+pub struct TextureAtlasBuilder<'a, K> {
+    pub initial_size: (u32, u32),
+    pub max_size: (u32, u32),
+    /// Empty pixels between entries and around the border, against bleeding when sampling
+    pub padding: u32,
+    entries: Vec<(K, RawImage<'a>)>,
+}
+
+impl<'a, K> Default for TextureAtlasBuilder<'a, K> {
+    fn default() -> Self {
+        Self {
+            initial_size: (256, 256),
+            max_size: (4096, 4096),
+            padding: 2,
+            entries: Vec::new(),
+        }
+    }
+}
+
+impl<'a, K: Clone + Eq + Hash + fmt::Debug> TextureAtlasBuilder<'a, K> {
+    pub fn new(max_size: (u32, u32), padding: u32) -> Self {
+        Self {
+            max_size,
+            padding,
+            ..Default::default()
+        }
+    }
+
+    pub fn add(&mut self, key: K, image: RawImage<'a>) -> &mut Self {
+        self.entries.push((key, image));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn pack_sizes(
+        sizes: &[(u32, u32)],
+        (width, height): (u32, u32),
+        padding: u32,
+    ) -> Result<Vec<PackedRect>, Vec<usize>> {
+        let mut order: Vec<usize> = (0..sizes.len()).collect();
+        order.sort_by(|a, b| sizes[*b].1.cmp(&sizes[*a].1).then(sizes[*b].0.cmp(&sizes[*a].0)));
+
+        let mut rects = vec![PackedRect { x: 0, y: 0, w: 0, h: 0 }; sizes.len()];
+        let mut offenders = Vec::new();
+        let (mut x, mut y, mut shelf_height) = (padding, padding, 0);
+        for i in order {
+            let (w, h) = sizes[i];
+            if x + w + padding > width && x > padding {
+                y += shelf_height + padding;
+                x = padding;
+                shelf_height = 0;
+            }
+            if x + w + padding > width || y + h + padding > height {
+                offenders.push(i);
+                continue;
+            }
+            rects[i] = PackedRect { x, y, w, h };
+            x += w + padding;
+            shelf_height = shelf_height.max(h);
+        }
+
+        if offenders.is_empty() {
+            Ok(rects)
+        } else {
+            Err(offenders)
+        }
+    }
+
+    /// Pixel positions only, no GPU work
+    pub fn pack(&self) -> Result<TexturePacking<K>, TextureAtlasError> {
+        if let Some((_, first)) = self.entries.first() {
+            let expected = first.pixel_format;
+            if let Some((key, _)) = self
+                .entries
+                .iter()
+                .find(|(_, image)| image.pixel_format != expected)
+            {
+                return Err(TextureAtlasError::PixelFormatMismatch {
+                    key: format!("{:?}", key),
+                    expected,
+                });
+            }
+        }
+
+        let sizes: Vec<(u32, u32)> = self
+            .entries
+            .iter()
+            .map(|(_, image)| (image.dim.0, image.dim.1))
+            .collect();
+        let mut size = (
+            self.initial_size.0.min(self.max_size.0),
+            self.initial_size.1.min(self.max_size.1),
+        );
+        loop {
+            match Self::pack_sizes(&sizes, size, self.padding) {
+                Ok(rects) => {
+                    return Ok(TexturePacking {
+                        size,
+                        rects: self
+                            .entries
+                            .iter()
+                            .map(|(key, _)| key.clone())
+                            .zip(rects)
+                            .collect(),
+                    })
+                }
+                Err(offenders) if size == self.max_size => {
+                    return Err(TextureAtlasError::DoesNotFit {
+                        offenders: offenders
+                            .into_iter()
+                            .map(|i| format!("{:?}", self.entries[i].0))
+                            .collect(),
+                        max_size: self.max_size,
+                    })
+                }
+                Err(_) => {
+                    // grow the shorter side first to stay close to square
+                    if size.0 <= size.1 && size.0 < self.max_size.0 || size.1 == self.max_size.1 {
+                        size.0 = (size.0 * 2).min(self.max_size.0);
+                    } else {
+                        size.1 = (size.1 * 2).min(self.max_size.1);
+                    }
+                }
+            }
+        }
+    }
+
+    /// `max_size` is clamped to what the device supports
+    pub fn build(
+        mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> anyhow::Result<(Texture, HashMap<K, Rect>)> {
+        let max_dimension = device.limits().max_texture_dimension_2d;
+        self.max_size = (
+            self.max_size.0.min(max_dimension),
+            self.max_size.1.min(max_dimension),
+        );
+
+        let packing = self.pack()?;
+        let pixel_format = self
+            .entries
+            .first()
+            .map_or(PixelFormat::RGBA8, |(_, image)| image.pixel_format);
+        let pixel_size = pixel_format.bytes() as usize;
+        let (width, height) = packing.size;
+
+        let mut bytes = vec![0; width as usize * height as usize * pixel_size];
+        for (key, image) in self.entries.iter() {
+            let rect = &packing.rects[key];
+            let row_size = rect.w as usize * pixel_size;
+            for row in 0..rect.h as usize {
+                let dst = ((rect.y as usize + row) * width as usize + rect.x as usize) * pixel_size;
+                let src = row * row_size;
+                bytes[dst..dst + row_size].copy_from_slice(&image.bytes[src..src + row_size]);
+            }
+        }
+
+        let texture = Texture::from_raw_image(
+            device,
+            queue,
+            &RawImage::new(&bytes, packing.size, pixel_format),
+            Some("Texture Atlas"),
+        )?;
+        let rects = packing
+            .rects
+            .into_iter()
+            .map(|(key, rect)| (key, rect.normalized(packing.size)))
+            .collect();
+
+        Ok((texture, rects))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::texture::{PixelFormat, RawImage};
+
+    use super::{TextureAtlasBuilder, TextureAtlasError};
+
+    #[test]
+    fn packs_random_rects_without_overlap() {
+        let mut state = 0x2545F4914F6CDD1Du64;
+        let mut next = |max: u32| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % max as u64) as u32 + 1
+        };
+        let sizes: Vec<(u32, u32)> = (0..100).map(|_| (next(64), next(64))).collect();
+        let bytes = vec![0u8; 64 * 64 * 4];
+
+        let mut builder = TextureAtlasBuilder::new((2048, 2048), 2);
+        for (i, size) in sizes.iter().enumerate() {
+            let len = (size.0 * size.1 * 4) as usize;
+            builder.add(i, RawImage::new(&bytes[..len], *size, PixelFormat::RGBA8));
+        }
+        let packing = builder.pack().unwrap();
+        let (width, height) = packing.size;
+
+        let rects: Vec<_> = (0..100).map(|i| packing.rects[&i]).collect();
+        for (i, rect) in rects.iter().enumerate() {
+            assert_eq!((rect.w, rect.h), sizes[i]);
+            assert!(rect.x >= 2 && rect.y >= 2);
+            assert!(rect.x + rect.w + 2 <= width && rect.y + rect.h + 2 <= height);
+            for other in &rects[i + 1..] {
+                assert!(!rect.overlaps(other));
+            }
+        }
+    }
+
+    #[test]
+    fn oversized_entries_are_listed() {
+        let bytes = vec![0u8; 100 * 100];
+        let mut builder = TextureAtlasBuilder::new((64, 64), 1);
+        builder
+            .add("small", RawImage::new(&bytes[..16], (4, 4), PixelFormat::G8))
+            .add("big", RawImage::new(&bytes, (100, 100), PixelFormat::G8));
+
+        assert_eq!(
+            builder.pack().err(),
+            Some(TextureAtlasError::DoesNotFit {
+                offenders: vec!["\"big\"".to_string()],
+                max_size: (64, 64),
+            })
+        );
+    }
+}
@@ -0,0 +1,336 @@
+use std::{collections::HashMap, io::Cursor, sync::Arc, time::Duration};
+
+use anyhow::bail;
+use bevy_app::{CoreStage, Plugin};
+use bevy_asset::{AssetLoader, Assets, Handle, LoadedAsset};
+use bevy_ecs::system::{NonSend, Res, ResMut};
+use bevy_reflect::TypeUuid;
+use rodio::{OutputStream, OutputStreamHandle, Sink, Source};
+
+pub struct FlatAudioPlugin;
+impl Plugin for FlatAudioPlugin {
+    fn build(&self, app: &mut bevy_app::App) {
+        app.insert_non_send_resource(AudioStream::open())
+            .init_resource::<AudioOutput>()
+            .add_system_to_stage(CoreStage::PostUpdate, audio_output_system);
+    }
+}
+
+/// Decoded interleaved samples
+#[derive(TypeUuid)]
+#[uuid = "3C1B5A0E-7D52-4C8B-9F0A-6E2D81B4C7F3"]
+pub struct AudioSource {
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub samples: Arc<[f32]>,
+}
+
+impl AudioSource {
+    pub fn decode(bytes: &[u8], extension: &str) -> anyhow::Result<Self> {
+        match extension {
+            "wav" => Self::decode_wav(bytes),
+            "ogg" => Self::decode_ogg(bytes),
+            _ => bail!("Unsupported audio format: {extension}"),
+        }
+    }
+
+    fn decode_wav(bytes: &[u8]) -> anyhow::Result<Self> {
+        let mut reader = hound::WavReader::new(Cursor::new(bytes))?;
+        let spec = reader.spec();
+        let samples = match spec.sample_format {
+            hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<Vec<_>, _>>()?,
+            hound::SampleFormat::Int => {
+                let scale = 1.0 / (1u64 << (spec.bits_per_sample - 1)) as f32;
+                reader
+                    .samples::<i32>()
+                    .map(|sample| sample.map(|sample| sample as f32 * scale))
+                    .collect::<Result<Vec<_>, _>>()?
+            }
+        };
+
+        Ok(Self {
+            channels: spec.channels,
+            sample_rate: spec.sample_rate,
+            samples: samples.into(),
+        })
+    }
+
+    fn decode_ogg(bytes: &[u8]) -> anyhow::Result<Self> {
+        let mut reader = lewton::inside_ogg::OggStreamReader::new(Cursor::new(bytes))?;
+        let mut samples = Vec::new();
+        while let Some(packet) = reader.read_dec_packet_itl()? {
+            samples.extend(packet.into_iter().map(|sample| sample as f32 / 32768.0));
+        }
+
+        Ok(Self {
+            channels: reader.ident_hdr.audio_channels as u16,
+            sample_rate: reader.ident_hdr.audio_sample_rate,
+            samples: samples.into(),
+        })
+    }
+
+    /// Samples per channel
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.channels.max(1) as usize
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.frames() as f64 / self.sample_rate as f64)
+    }
+
+    fn source(&self) -> SharedSamples {
+        SharedSamples {
+            samples: self.samples.clone(),
+            position: 0,
+            channels: self.channels,
+            sample_rate: self.sample_rate,
+        }
+    }
+}
+
+pub struct AudioLoader;
+impl AssetLoader for AudioLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut bevy_asset::LoadContext,
+    ) -> bevy_asset::BoxedFuture<'a, anyhow::Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let extension = load_context
+                .path()
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .unwrap_or_default()
+                .to_lowercase();
+            let source = AudioSource::decode(bytes, &extension)?;
+            load_context.set_default_asset(LoadedAsset::new(source));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["wav", "ogg"]
+    }
+}
+
+/// Plays the samples without copying them per playback
+struct SharedSamples {
+    samples: Arc<[f32]>,
+    position: usize,
+    channels: u16,
+    sample_rate: u32,
+}
+
+impl Iterator for SharedSamples {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let sample = self.samples.get(self.position).copied();
+        self.position += 1;
+        sample
+    }
+}
+
+impl Source for SharedSamples {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// The output stream stops when dropped and is not `Send` on every platform,
+/// so it lives on the main thread while [`AudioOutput`] only holds its handle.
+pub struct AudioStream {
+    stream: Option<(OutputStream, OutputStreamHandle)>,
+}
+
+impl AudioStream {
+    pub fn open() -> Self {
+        let stream = match OutputStream::try_default() {
+            Ok(stream) => Some(stream),
+            Err(err) => {
+                log::warn!("No audio output device, audio is disabled: {err}");
+                None
+            }
+        };
+        Self { stream }
+    }
+
+    pub fn handle(&self) -> Option<&OutputStreamHandle> {
+        self.stream.as_ref().map(|(_, handle)| handle)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Playback(u64);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlaybackSettings {
+    pub volume: f32,
+    pub looped: bool,
+}
+
+impl Default for PlaybackSettings {
+    fn default() -> Self {
+        Self {
+            volume: 1.0,
+            looped: false,
+        }
+    }
+}
+
+struct QueuedPlayback {
+    playback: Playback,
+    source: Handle<AudioSource>,
+    settings: PlaybackSettings,
+}
+
+/// Playbacks are queued and started by `audio_output_system` once their source is loaded,
+/// the samples are mixed on the audio thread so nothing here blocks.
+#[derive(Default)]
+pub struct AudioOutput {
+    next_id: u64,
+    queued: Vec<QueuedPlayback>,
+    sinks: HashMap<Playback, Sink>,
+}
+
+impl AudioOutput {
+    pub fn play(&mut self, source: Handle<AudioSource>) -> Playback {
+        self.play_with(source, PlaybackSettings::default())
+    }
+
+    pub fn play_looped(&mut self, source: Handle<AudioSource>) -> Playback {
+        self.play_with(
+            source,
+            PlaybackSettings {
+                looped: true,
+                ..Default::default()
+            },
+        )
+    }
+
+    pub fn play_with(
+        &mut self,
+        source: Handle<AudioSource>,
+        settings: PlaybackSettings,
+    ) -> Playback {
+        let playback = Playback(self.next_id);
+        self.next_id += 1;
+        self.queued.push(QueuedPlayback {
+            playback,
+            source,
+            settings,
+        });
+        playback
+    }
+
+    pub fn set_volume(&mut self, playback: Playback, volume: f32) {
+        if let Some(sink) = self.sinks.get(&playback) {
+            sink.set_volume(volume);
+        } else if let Some(queued) = self.queued.iter_mut().find(|q| q.playback == playback) {
+            queued.settings.volume = volume;
+        }
+    }
+
+    pub fn stop(&mut self, playback: Playback) {
+        if let Some(sink) = self.sinks.remove(&playback) {
+            sink.stop();
+        }
+        self.queued.retain(|queued| queued.playback != playback);
+    }
+
+    /// Queued or still playing
+    pub fn is_playing(&self, playback: Playback) -> bool {
+        self.sinks.contains_key(&playback)
+            || self.queued.iter().any(|queued| queued.playback == playback)
+    }
+}
+
+pub fn audio_output_system(
+    stream: NonSend<AudioStream>,
+    mut output: ResMut<AudioOutput>,
+    sources: Res<Assets<AudioSource>>,
+) {
+    let handle = match stream.handle() {
+        Some(handle) => handle,
+        None => {
+            output.queued.clear();
+            return;
+        }
+    };
+
+    let output = &mut *output;
+    output.sinks.retain(|_, sink| !sink.empty());
+    output.queued.retain(|queued| {
+        let source = match sources.get(&queued.source) {
+            Some(source) => source,
+            None => return true,
+        };
+        match Sink::try_new(handle) {
+            Ok(sink) => {
+                sink.set_volume(queued.settings.volume);
+                if queued.settings.looped {
+                    sink.append(source.source().repeat_infinite());
+                } else {
+                    sink.append(source.source());
+                }
+                output.sinks.insert(queued.playback, sink);
+            }
+            Err(err) => log::error!("Could not start playback: {err}"),
+        }
+        false
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_asset::Handle;
+
+    use super::{AudioOutput, AudioSource};
+
+    /// Mono 16 bit 8kHz, samples 0, 16384, -16384, 32767
+    const WAV: [u8; 52] = [
+        b'R', b'I', b'F', b'F', 44, 0, 0, 0, b'W', b'A', b'V', b'E', // riff
+        b'f', b'm', b't', b' ', 16, 0, 0, 0, // fmt chunk
+        1, 0, 1, 0, 0x40, 0x1F, 0, 0, 0x80, 0x3E, 0, 0, 2, 0, 16, 0, // pcm
+        b'd', b'a', b't', b'a', 8, 0, 0, 0, // data chunk
+        0x00, 0x00, 0x00, 0x40, 0x00, 0xC0, 0xFF, 0x7F,
+    ];
+
+    #[test]
+    fn decode_wav() {
+        let source = AudioSource::decode(&WAV, "wav").unwrap();
+        assert_eq!(source.channels, 1);
+        assert_eq!(source.sample_rate, 8000);
+        assert_eq!(source.frames(), 4);
+        assert_eq!(&source.samples[..3], &[0.0, 0.5, -0.5]);
+        assert!(AudioSource::decode(&WAV, "mp3").is_err());
+    }
+
+    #[test]
+    fn queued_playbacks_keep_their_settings() {
+        let mut output = AudioOutput::default();
+        let music = output.play_looped(Handle::default());
+        let effect = output.play(Handle::default());
+        assert_ne!(music, effect);
+
+        output.set_volume(music, 0.25);
+        assert_eq!(output.queued[0].settings.volume, 0.25);
+        assert!(output.queued[0].settings.looped);
+
+        output.stop(effect);
+        assert!(output.is_playing(music));
+        assert!(!output.is_playing(effect));
+    }
+}
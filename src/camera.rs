@@ -1,8 +1,26 @@
+use bevy_ecs::{
+    bundle::Bundle,
+    entity::Entity,
+    prelude::Component,
+    query::With,
+    system::{Query, Res, ResMut},
+};
 use bytemuck::{Pod, Zeroable};
 use cgmath::*;
 use repr_trait::C;
 
-use crate::render::resource::bind::{GpuUniform, StageLockedUniform, UpdateGpuUniform};
+use crate::{
+    render::{
+        resource::{
+            bind::{StageLockedUniform, UpdateGpuUniform},
+            uniform_layout::gpu_uniform,
+        },
+        DepthConvention,
+    },
+    transform::Transform,
+    window::{util::PhysicalVec2, WindowId, Windows},
+    Headless,
+};
 
 pub struct Camera {
     pub view_matrix: Matrix4<f32>,
@@ -15,6 +33,19 @@ impl UpdateGpuUniform for Camera {
         gpu_uniform.view_proj = (self.projection_matrix * self.view_matrix).into();
     }
 }
+impl Camera {
+    pub fn frustum(&self) -> Frustum {
+        Frustum::from_view_proj(&(self.projection_matrix * self.view_matrix))
+    }
+
+    /// World position of the eye, `None` if the view matrix can not be inverted
+    pub fn eye(&self) -> Option<Point3<f32>> {
+        self.view_matrix
+            .invert()
+            .map(|camera_to_world| Point3::from_vec(camera_to_world.w.truncate()))
+    }
+}
+
 impl Default for Camera {
     fn default() -> Self {
         Self {
@@ -24,12 +55,13 @@ impl Default for Camera {
     }
 }
 
-#[repr(C)]
-#[derive(Debug, Clone, Copy, C, Pod, Zeroable)]
-pub struct CameraUniform {
-    pub view_proj: [[f32; 4]; 4],
+gpu_uniform! {
+    #[repr(C)]
+    #[derive(Debug, Clone, Copy, C, Pod, Zeroable)]
+    pub struct CameraUniform {
+        pub view_proj: [[f32; 4]; 4] as mat4x4,
+    }
 }
-impl GpuUniform for CameraUniform {}
 impl StageLockedUniform for CameraUniform {
     const FORCE_STAGE: wgpu::ShaderStages = wgpu::ShaderStages::VERTEX;
 }
@@ -41,6 +73,7 @@ impl Default for CameraUniform {
     }
 }
 
+#[derive(Component)]
 pub struct CameraView {
     pub eye: Point3<f32>,
     pub target: Point3<f32>,
@@ -48,9 +81,106 @@ pub struct CameraView {
 }
 
 impl CameraView {
+    /// Stops the eye this far short of `up` and `-up`, the view matrix degenerates on them
+    const PITCH_MARGIN: f32 = 0.01;
+
+    /// Looks along `-z` of the transform with its `+y` up. The target is one unit in front,
+    /// [`CameraView::to_transform`] does not keep the distance
+    pub fn from_transform(transform: &Transform) -> Self {
+        let eye = Point3::from_vec(transform.translation);
+        Self {
+            eye,
+            target: eye + transform.rotation.rotate_vector(-Vector3::unit_z()),
+            up: transform.rotation.rotate_vector(Vector3::unit_y()),
+        }
+    }
+
+    /// Camera to world, the inverse of [`CameraView::build_view_matrix`]
+    pub fn to_transform(&self) -> Transform {
+        let rotation = Matrix3::from_cols(self.right(), self.true_up(), -self.forward());
+        Transform {
+            translation: self.eye.to_vec(),
+            rotation: Quaternion::from(rotation).normalize(),
+            ..Default::default()
+        }
+    }
+
     pub fn build_view_matrix(&self) -> Matrix4<f32> {
         Matrix4::look_at_rh(self.eye, self.target, self.up)
     }
+
+    pub fn set_look_at(&mut self, eye: Point3<f32>, target: Point3<f32>) {
+        self.eye = eye;
+        self.target = target;
+    }
+
+    /// Normalized, from the eye to the target
+    pub fn forward(&self) -> Vector3<f32> {
+        (self.target - self.eye).normalize()
+    }
+
+    /// Normalized, `+x` of the view
+    pub fn right(&self) -> Vector3<f32> {
+        self.forward().cross(self.up).normalize()
+    }
+
+    /// `up` made orthogonal to `forward`, `+y` of the view
+    fn true_up(&self) -> Vector3<f32> {
+        self.right().cross(self.forward())
+    }
+
+    /// Moves the eye and target together, `x` to the right, `y` up and `z` backwards
+    /// like the axes of the view
+    pub fn translate_local(&mut self, offset: Vector3<f32>) {
+        let offset =
+            self.right() * offset.x + self.true_up() * offset.y - self.forward() * offset.z;
+        self.eye += offset;
+        self.target += offset;
+    }
+
+    /// Moves the eye along the view direction, e.g. by `AccumulatedMouseScroll::delta_this_frame.y`.
+    /// Distance to the target is clamped to `[min_distance, max_distance]`. An eye on the
+    /// target has no view direction and stays there
+    pub fn dolly(&mut self, amount: f32, min_distance: f32, max_distance: f32) {
+        let to_eye = self.eye - self.target;
+        if to_eye.magnitude2() == 0.0 {
+            return;
+        }
+        let distance = (to_eye.magnitude() - amount).clamp(min_distance, max_distance);
+        self.eye = self.target + to_eye.normalize() * distance;
+    }
+
+    /// Rotates the eye around the target, e.g. by `AccumulatedMouseMotion::delta`.
+    /// `x` turns around `up`, positive `y` raises the eye, which stops short of `up`
+    pub fn orbit(&mut self, delta: Vector2<f32>, radians_per_unit: f32) {
+        self.orbit_around(
+            self.target,
+            Rad(-delta.x * radians_per_unit),
+            Rad(delta.y * radians_per_unit),
+        );
+    }
+
+    /// Looks at `target` and rotates the eye around it, keeping the distance.
+    /// Positive `yaw` turns counterclockwise around `up`, positive `pitch` raises the eye,
+    /// which stops short of `up` and `-up`
+    pub fn orbit_around(&mut self, target: Point3<f32>, yaw: Rad<f32>, pitch: Rad<f32>) {
+        self.target = target;
+        let up = self.up.normalize();
+        let to_eye = Quaternion::from_axis_angle(up, yaw).rotate_vector(self.eye - target);
+
+        let axis = up.cross(to_eye);
+        if axis.magnitude2() > 0.0 {
+            let from_up = to_eye.angle(up).0;
+            let pitched = (from_up - pitch.0).clamp(
+                Self::PITCH_MARGIN,
+                std::f32::consts::PI - Self::PITCH_MARGIN,
+            );
+            let pitch = Quaternion::from_axis_angle(axis.normalize(), Rad(pitched - from_up));
+            self.eye = target + pitch.rotate_vector(to_eye);
+        } else {
+            self.eye = target + to_eye;
+        }
+    }
 }
 
 impl Default for CameraView {
@@ -67,16 +197,50 @@ impl Default for CameraView {
     }
 }
 
+#[derive(Component)]
 pub struct PerspectiveProjection {
     pub aspect: f32,
     pub fovy: f32,
     pub znear: f32,
+    /// May be infinite with [`DepthConvention::ReverseZ`]
     pub zfar: f32,
+    /// Follows the [`DepthConvention`] resource while the camera is active
+    pub depth_convention: DepthConvention,
 }
 
 impl PerspectiveProjection {
+    pub const MIN_FOVY: f32 = std::f32::consts::PI / 180.0;
+    pub const MAX_FOVY: f32 = std::f32::consts::PI * 170.0 / 180.0;
+
+    /// Widens the vertical field of view by `fovy_delta` radians, negative zooms in.
+    /// Clamped to `[MIN_FOVY, MAX_FOVY]`
+    pub fn zoom(&mut self, fovy_delta: f32) {
+        self.fovy = (self.fovy + fovy_delta).clamp(Self::MIN_FOVY, Self::MAX_FOVY);
+    }
+
+    /// In OpenGL clip space, the GPU gets `OPENGL_TO_WGPU_MATRIX *` it. With
+    /// [`DepthConvention::ReverseZ`] that product maps `znear` to depth 1 and `zfar` to 0
     pub fn build_projection_matrix(&self) -> Matrix4<f32> {
-        cgmath::perspective(Rad(self.fovy), self.aspect, self.znear, self.zfar)
+        if self.depth_convention == DepthConvention::Standard {
+            return cgmath::perspective(Rad(self.fovy), self.aspect, self.znear, self.zfar);
+        }
+        let f = 1.0 / (self.fovy / 2.0).tan();
+        // depth = (a * view z + b) / -view z, only b is left for an infinite far plane
+        let (a, b) = if self.zfar.is_finite() {
+            let range = self.zfar - self.znear;
+            (self.znear / range, self.znear * self.zfar / range)
+        } else {
+            (0.0, self.znear)
+        };
+        // NOTE: z is written for the (z + w) / 2 of OPENGL_TO_WGPU_MATRIX
+        #[rustfmt::skip]
+        let projection = Matrix4::new(
+            f / self.aspect, 0.0, 0.0, 0.0,
+            0.0, f, 0.0, 0.0,
+            0.0, 0.0, 2.0 * a + 1.0, -1.0,
+            0.0, 0.0, 2.0 * b, 0.0,
+        );
+        projection
     }
 }
 
@@ -87,10 +251,165 @@ impl Default for PerspectiveProjection {
             fovy: std::f32::consts::PI / 4.0,
             znear: 0.1,
             zfar: 1000.0,
+            depth_convention: DepthConvention::Standard,
         }
     }
 }
 
+pub struct OrthographicProjection {
+    pub left: f32,
+    pub right: f32,
+    pub bottom: f32,
+    pub top: f32,
+    pub znear: f32,
+    pub zfar: f32,
+}
+
+impl OrthographicProjection {
+    /// Symmetric box, `height` world units tall
+    pub fn from_height(height: f32, aspect: f32, znear: f32, zfar: f32) -> Self {
+        let half_height = height / 2.0;
+        let half_width = half_height * aspect;
+        Self {
+            left: -half_width,
+            right: half_width,
+            bottom: -half_height,
+            top: half_height,
+            znear,
+            zfar,
+        }
+    }
+
+    pub fn build_projection_matrix(&self) -> Matrix4<f32> {
+        cgmath::ortho(
+            self.left,
+            self.right,
+            self.bottom,
+            self.top,
+            self.znear,
+            self.zfar,
+        )
+    }
+}
+
+impl Default for OrthographicProjection {
+    fn default() -> Self {
+        Self::from_height(2.0, 1.0, 0.1, 1000.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ray {
+    pub origin: Point3<f32>,
+    /// Normalized
+    pub dir: Vector3<f32>,
+}
+
+impl Ray {
+    pub fn at(&self, distance: f32) -> Point3<f32> {
+        self.origin + self.dir * distance
+    }
+}
+
+pub struct ViewportToWorld;
+
+impl ViewportToWorld {
+    /// Ray starting on the near plane under the cursor.
+    ///
+    /// `cursor_pos` and `viewport_size` are in physical pixels.
+    /// `projection` must map depth to `[0, 1]` like the matrices sent to the
+    /// GPU do, see [`OPENGL_TO_WGPU_MATRIX`], with the near plane at the end of
+    /// `depth_convention`. Works for any projection since both ends of the ray
+    /// are unprojected.
+    /// Returns `None` if the view projection is not invertible.
+    pub fn ray_from_cursor(
+        camera_view: &Matrix4<f32>,
+        projection: &Matrix4<f32>,
+        cursor_pos: PhysicalVec2,
+        viewport_size: Vector2<f32>,
+        depth_convention: DepthConvention,
+    ) -> Option<Ray> {
+        let cursor_pos = cursor_pos.0;
+        let inverse_view_proj = (projection * camera_view).invert()?;
+        let ndc = Vector2::new(
+            2.0 * cursor_pos.x / viewport_size.x - 1.0,
+            1.0 - 2.0 * cursor_pos.y / viewport_size.y,
+        );
+
+        let unproject = |depth: f32| {
+            Point3::from_homogeneous(inverse_view_proj * ndc.extend(depth).extend(1.0))
+        };
+        let (near, far) = match depth_convention {
+            DepthConvention::Standard => (unproject(0.0), unproject(1.0)),
+            // the far plane may be infinitely far, any point behind the near one will do
+            DepthConvention::ReverseZ => (unproject(1.0), unproject(0.5)),
+        };
+
+        Some(Ray {
+            origin: near,
+            dir: (far - near).normalize(),
+        })
+    }
+}
+
+/// Planes of a view projection that maps depth to `[0, 1]`, see [`OPENGL_TO_WGPU_MATRIX`].
+/// With [`DepthConvention::ReverseZ`] the near and far planes swap places, an infinite far
+/// plane keeps everything in front of the near one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frustum {
+    /// `(normal, distance)`, points inside are on the positive side of every plane.
+    /// Not normalized, only the sign of the distance is meaningful
+    pub planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    pub fn from_view_proj(view_proj: &Matrix4<f32>) -> Self {
+        let (x, y, z, w) = (
+            view_proj.row(0),
+            view_proj.row(1),
+            view_proj.row(2),
+            view_proj.row(3),
+        );
+        Self {
+            // left, right, bottom, top, near, far
+            planes: [w + x, w - x, w + y, w - y, z, w - z],
+        }
+    }
+
+    /// Conservative, boxes near the corners of the frustum may pass while outside
+    pub fn intersects_aabb(&self, min: Point3<f32>, max: Point3<f32>) -> bool {
+        self.planes.iter().all(|plane| {
+            // the corner furthest along the plane normal
+            let corner = Vector4::new(
+                if plane.x >= 0.0 { max.x } else { min.x },
+                if plane.y >= 0.0 { max.y } else { min.y },
+                if plane.z >= 0.0 { max.z } else { min.z },
+                1.0,
+            );
+            plane.dot(corner) >= 0.0
+        })
+    }
+
+    /// Conservative like [`Frustum::intersects_aabb`]
+    pub fn intersects_sphere(&self, center: Point3<f32>, radius: f32) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| Self::plane_distance(plane, center) >= -radius)
+    }
+
+    /// The whole sphere is inside
+    pub fn contains_sphere(&self, center: Point3<f32>, radius: f32) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| Self::plane_distance(plane, center) >= radius)
+    }
+
+    fn plane_distance(plane: &Vector4<f32>, point: Point3<f32>) -> f32 {
+        let normal = plane.truncate();
+        (normal.dot(point.to_vec()) + plane.w) / normal.magnitude()
+    }
+}
+
 #[rustfmt::skip]
 pub const OPENGL_TO_WGPU_MATRIX: Matrix4<f32> = Matrix4::new(
     1.0, 0.0, 0.0, 0.0,
@@ -98,3 +417,463 @@ pub const OPENGL_TO_WGPU_MATRIX: Matrix4<f32> = Matrix4::new(
     0.0, 0.0, 0.5, 0.0,
     0.0, 0.0, 0.5, 1.0,
 );
+
+/// Marks an entity the renderer can view through, with a [`CameraView`] and a
+/// [`PerspectiveProjection`]
+#[derive(Component, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RenderCamera;
+
+#[derive(Bundle, Default)]
+pub struct CameraBundle {
+    pub camera: RenderCamera,
+    pub view: CameraView,
+    pub projection: PerspectiveProjection,
+}
+
+/// The [`RenderCamera`] entity whose view and projection are written into the [`Camera`]
+/// resource each frame, by [`sync_active_camera_system`]. Without any camera entities the
+/// `Camera` resource is left to the app.
+///
+/// Enumerate the cameras with a `Query<Entity, With<RenderCamera>>`, [`ActiveCamera::set`]
+/// switches before the next frame is drawn
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ActiveCamera {
+    entity: Option<Entity>,
+    // every camera entity is gone, nothing is drawn until one is spawned
+    lost: bool,
+}
+
+impl ActiveCamera {
+    pub fn get(&self) -> Option<Entity> {
+        self.entity
+    }
+
+    /// An entity without a [`RenderCamera`] falls back to another camera
+    pub fn set(&mut self, entity: Entity) {
+        self.entity = Some(entity);
+    }
+
+    /// Switches to the camera after the active one in `cameras`, wrapping around,
+    /// and returns it
+    pub fn cycle(&mut self, cameras: impl IntoIterator<Item = Entity>) -> Option<Entity> {
+        let cameras: Vec<Entity> = cameras.into_iter().collect();
+        let next = match self
+            .entity
+            .and_then(|entity| cameras.iter().position(|&camera| camera == entity))
+        {
+            Some(i) => cameras[(i + 1) % cameras.len()],
+            None => *cameras.first()?,
+        };
+        self.entity = Some(next);
+        Some(next)
+    }
+
+    /// False once every camera entity was despawned, the main pass then draws nothing
+    pub fn has_camera(&self) -> bool {
+        !self.lost
+    }
+}
+
+/// Writes the view and projection of the [`ActiveCamera`] into the [`Camera`] resource,
+/// its projection fitted to the primary window, or the offscreen target of a
+/// [`Headless`] app, and to the [`DepthConvention`]. A despawned active camera falls back
+/// to the camera with the lowest id
+pub fn sync_active_camera_system(
+    windows: Res<Windows>,
+    headless: Option<Res<Headless>>,
+    depth_convention: Option<Res<DepthConvention>>,
+    mut active: ResMut<ActiveCamera>,
+    mut camera: ResMut<Camera>,
+    mut cameras: Query<(Entity, &CameraView, &mut PerspectiveProjection), With<RenderCamera>>,
+) {
+    let entity = match active.entity.filter(|&entity| cameras.get(entity).is_ok()) {
+        Some(entity) => Some(entity),
+        None => {
+            let fallback = cameras.iter().map(|(entity, ..)| entity).min();
+            match (active.entity, fallback) {
+                (Some(gone), Some(fallback)) => {
+                    log::warn!("Camera {:?} is gone, switched to {:?}", gone, fallback)
+                }
+                (Some(gone), None) => {
+                    log::error!(
+                        "Camera {:?} is gone and no camera is left, nothing is drawn",
+                        gone
+                    )
+                }
+                _ => {}
+            }
+            fallback
+        }
+    };
+    active.lost = entity.is_none() && (active.entity.is_some() || active.lost);
+    active.entity = entity;
+
+    let (_, view, mut projection) = match entity.and_then(|entity| cameras.get_mut(entity).ok()) {
+        Some(camera) => camera,
+        None => return,
+    };
+    let (width, height) = match (windows.map.get(&WindowId::primary()), headless) {
+        (Some(window), _) => window.resolution(),
+        (None, Some(headless)) => (headless.width, headless.height),
+        (None, None) => (0, 0),
+    };
+    // NOTE: zero while minimized, the last aspect is kept
+    if width > 0 && height > 0 {
+        let aspect = width as f32 / height as f32;
+        if projection.aspect != aspect {
+            projection.aspect = aspect;
+        }
+    }
+    let depth_convention = depth_convention.map_or_else(Default::default, |c| *c);
+    if projection.depth_convention != depth_convention {
+        projection.depth_convention = depth_convention;
+    }
+    camera.view_matrix = view.build_view_matrix();
+    camera.projection_matrix = OPENGL_TO_WGPU_MATRIX * projection.build_projection_matrix();
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_ecs::{
+        schedule::{Stage, SystemStage},
+        world::World,
+    };
+
+    use super::*;
+
+    fn view() -> Matrix4<f32> {
+        CameraView {
+            eye: Point3::new(0.0, 0.0, 5.0),
+            target: Point3::origin(),
+            up: Vector3::unit_y(),
+        }
+        .build_view_matrix()
+    }
+
+    fn assert_close(a: Vector3<f32>, b: Vector3<f32>) {
+        assert!((a - b).magnitude() < 1e-4, "{a:?} != {b:?}");
+    }
+
+    #[test]
+    fn orbit_keeps_the_distance_and_stops_below_up() {
+        let mut view = CameraView {
+            eye: Point3::new(0.0, 0.0, 5.0),
+            target: Point3::origin(),
+            up: Vector3::unit_y(),
+        };
+        view.orbit(
+            Vector2::new(100.0, 0.0),
+            std::f32::consts::FRAC_PI_2 / 100.0,
+        );
+        assert_close(view.eye.to_vec(), Vector3::new(-5.0, 0.0, 0.0));
+
+        view.orbit(Vector2::new(0.0, 1.0), std::f32::consts::FRAC_PI_4);
+        assert!(view.eye.y > 3.5 && view.eye.y < 3.6, "{:?}", view.eye);
+
+        // far past the pole
+        view.orbit(Vector2::new(0.0, 10.0), 1.0);
+        assert!((view.eye.to_vec().magnitude() - 5.0).abs() < 1e-4);
+        assert!(view.eye.y < 5.0 && view.eye.x < 0.0, "{:?}", view.eye);
+    }
+
+    #[test]
+    fn orbit_by_a_full_turn_returns_to_the_start() {
+        let start = Point3::new(1.0, 2.0, 3.0);
+        let mut view = CameraView {
+            eye: start,
+            ..Default::default()
+        };
+        for _ in 0..8 {
+            view.orbit_around(Point3::origin(), Rad(std::f32::consts::FRAC_PI_4), Rad(0.0));
+        }
+        assert_close(view.eye.to_vec(), start.to_vec());
+
+        // pitching back and forth within the limits as well
+        view.orbit_around(Point3::origin(), Rad(0.0), Rad(0.3));
+        view.orbit_around(Point3::origin(), Rad(0.0), Rad(-0.3));
+        assert_close(view.eye.to_vec(), start.to_vec());
+
+        // around another target, the eye stops short of up so the basis stays valid
+        let target = Point3::new(1.5, 0.0, 3.0);
+        view.orbit_around(target, Rad(0.0), Rad(10.0));
+        assert!(((view.eye - target).magnitude() - 4.25f32.sqrt()).abs() < 1e-4);
+        assert!(view.forward().y < -0.99, "{:?}", view.forward());
+        assert!((view.right().magnitude() - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn basis_is_orthonormal_and_right_handed() {
+        let view = CameraView {
+            eye: Point3::new(3.0, 1.0, -2.0),
+            target: Point3::new(-1.0, 0.5, 4.0),
+            up: Vector3::unit_y(),
+        };
+        let (forward, right) = (view.forward(), view.right());
+        assert!((forward.magnitude() - 1.0).abs() < 1e-5);
+        assert!((right.magnitude() - 1.0).abs() < 1e-5);
+        assert!(forward.dot(right).abs() < 1e-5);
+        assert!(right.y.abs() < 1e-5);
+        // the up of the view, tilted towards forward
+        let up = right.cross(forward);
+        assert!(up.dot(Vector3::unit_y()) > 0.9);
+
+        let default = CameraView {
+            eye: Point3::new(0.0, 0.0, 5.0),
+            target: Point3::origin(),
+            up: Vector3::unit_y(),
+        };
+        assert_close(default.forward(), -Vector3::unit_z());
+        assert_close(default.right(), Vector3::unit_x());
+        assert_close(default.right().cross(default.forward()), Vector3::unit_y());
+    }
+
+    #[test]
+    fn transform_is_the_inverse_of_the_view_matrix() {
+        let mut view = CameraView::default();
+        view.set_look_at(Point3::new(3.0, 1.0, -2.0), Point3::new(-1.0, 0.5, 4.0));
+        let camera_to_world = view.to_transform().compute_matrix();
+        let identity = camera_to_world * view.build_view_matrix();
+        for (column, expected) in [
+            (identity.x, Vector4::unit_x()),
+            (identity.y, Vector4::unit_y()),
+            (identity.z, Vector4::unit_z()),
+            (identity.w, Vector4::unit_w()),
+        ] {
+            assert!((column - expected).magnitude() < 1e-4, "{identity:?}");
+        }
+
+        let round_trip = CameraView::from_transform(&view.to_transform());
+        assert_close(round_trip.eye.to_vec(), view.eye.to_vec());
+        assert_close(round_trip.forward(), view.forward());
+        assert_close(round_trip.right(), view.right());
+    }
+
+    #[test]
+    fn translate_local_moves_along_the_view_axes() {
+        let mut view = CameraView {
+            eye: Point3::new(0.0, 0.0, 5.0),
+            target: Point3::origin(),
+            up: Vector3::unit_y(),
+        };
+        view.translate_local(Vector3::new(1.0, 2.0, 3.0));
+        assert_close(view.eye.to_vec(), Vector3::new(1.0, 2.0, 8.0));
+        assert_close(view.target.to_vec(), Vector3::new(1.0, 2.0, 3.0));
+        assert_close(view.forward(), -Vector3::unit_z());
+    }
+
+    #[test]
+    fn dolly_is_clamped_and_keeps_an_eye_on_the_target() {
+        let mut view = CameraView {
+            eye: Point3::new(0.0, 0.0, 5.0),
+            target: Point3::origin(),
+            up: Vector3::unit_y(),
+        };
+        view.dolly(4.5, 1.0, 10.0);
+        assert_close(view.eye.to_vec(), Vector3::new(0.0, 0.0, 1.0));
+        view.dolly(-20.0, 1.0, 10.0);
+        assert_close(view.eye.to_vec(), Vector3::new(0.0, 0.0, 10.0));
+
+        view.eye = view.target;
+        view.dolly(1.0, 1.0, 10.0);
+        assert_eq!(view.eye, view.target);
+    }
+
+    #[test]
+    fn zoom_is_clamped() {
+        let mut projection = PerspectiveProjection::default();
+        let fovy = projection.fovy;
+        projection.zoom(-0.1);
+        assert!((projection.fovy - (fovy - 0.1)).abs() < 1e-6);
+        projection.zoom(-10.0);
+        assert_eq!(projection.fovy, PerspectiveProjection::MIN_FOVY);
+        projection.zoom(10.0);
+        assert_eq!(projection.fovy, PerspectiveProjection::MAX_FOVY);
+    }
+
+    #[test]
+    fn perspective_center_looks_at_target() {
+        let projection = OPENGL_TO_WGPU_MATRIX
+            * PerspectiveProjection {
+                aspect: 2.0,
+                ..Default::default()
+            }
+            .build_projection_matrix();
+        let viewport = Vector2::new(800.0, 400.0);
+
+        let ray = ViewportToWorld::ray_from_cursor(
+            &view(),
+            &projection,
+            PhysicalVec2(viewport / 2.0),
+            viewport,
+            DepthConvention::Standard,
+        )
+        .unwrap();
+        assert_close(ray.dir, -Vector3::unit_z());
+        assert_close(ray.origin.to_vec(), Vector3::new(0.0, 0.0, 4.9));
+
+        // top left corner points up and to the left
+        let ray = ViewportToWorld::ray_from_cursor(
+            &view(),
+            &projection,
+            PhysicalVec2::default(),
+            viewport,
+            DepthConvention::Standard,
+        )
+        .unwrap();
+        assert!(ray.dir.x < 0.0 && ray.dir.y > 0.0 && ray.dir.z < 0.0);
+    }
+
+    #[test]
+    fn orthographic_rays_are_parallel() {
+        let projection = OPENGL_TO_WGPU_MATRIX
+            * OrthographicProjection::from_height(4.0, 1.0, 0.1, 100.0).build_projection_matrix();
+        let viewport = Vector2::new(400.0, 400.0);
+
+        let ray = ViewportToWorld::ray_from_cursor(
+            &view(),
+            &projection,
+            PhysicalVec2(viewport / 2.0),
+            viewport,
+            DepthConvention::Standard,
+        )
+        .unwrap();
+        assert_close(ray.dir, -Vector3::unit_z());
+        assert_close(ray.origin.to_vec(), Vector3::new(0.0, 0.0, 4.9));
+
+        let ray = ViewportToWorld::ray_from_cursor(
+            &view(),
+            &projection,
+            PhysicalVec2::default(),
+            viewport,
+            DepthConvention::Standard,
+        )
+        .unwrap();
+        assert_close(ray.dir, -Vector3::unit_z());
+        assert_close(ray.origin.to_vec(), Vector3::new(-2.0, 2.0, 4.9));
+    }
+
+    #[test]
+    fn reverse_z_maps_near_to_one() {
+        let standard = PerspectiveProjection {
+            znear: 0.5,
+            zfar: 50.0,
+            ..Default::default()
+        };
+        let reverse = PerspectiveProjection {
+            depth_convention: DepthConvention::ReverseZ,
+            ..standard
+        };
+        let infinite = PerspectiveProjection {
+            zfar: f32::INFINITY,
+            ..reverse
+        };
+        let project = |projection: &PerspectiveProjection, z: f32| {
+            let clip = OPENGL_TO_WGPU_MATRIX
+                * projection.build_projection_matrix()
+                * Vector4::new(1.0, 2.0, z, 1.0);
+            clip.truncate() / clip.w
+        };
+
+        // only the depth changes
+        let xy = |ndc: Vector3<f32>| Vector3::new(ndc.x, ndc.y, 0.0);
+        for z in [-0.5, -3.0, -50.0] {
+            assert_close(xy(project(&reverse, z)), xy(project(&standard, z)));
+        }
+        assert!((project(&reverse, -0.5).z - 1.0).abs() < 1e-6);
+        assert!(project(&reverse, -50.0).z.abs() < 1e-6);
+        assert!((project(&infinite, -0.5).z - 1.0).abs() < 1e-6);
+        // distant points keep distinct depths, Depth32Float has the most precision near 0
+        let (far, farther) = (project(&infinite, -900.0).z, project(&infinite, -900.05).z);
+        assert!(far > farther && farther > 0.0);
+        let (far, farther) = (project(&standard, -900.0).z, project(&standard, -900.05).z);
+        assert!(far - farther <= f32::EPSILON);
+
+        let viewport = Vector2::new(800.0, 400.0);
+        let ray = |projection: &PerspectiveProjection, cursor| {
+            ViewportToWorld::ray_from_cursor(
+                &view(),
+                &(OPENGL_TO_WGPU_MATRIX * projection.build_projection_matrix()),
+                PhysicalVec2(cursor),
+                viewport,
+                projection.depth_convention,
+            )
+            .unwrap()
+        };
+        for cursor in [viewport / 2.0, Vector2::new(10.0, 350.0)] {
+            let (standard, infinite) = (ray(&standard, cursor), ray(&infinite, cursor));
+            assert_close(infinite.dir, standard.dir);
+            assert_close(infinite.origin.to_vec(), standard.origin.to_vec());
+        }
+    }
+
+    #[test]
+    fn active_camera_switches_and_falls_back() {
+        let mut world = World::new();
+        world.insert_resource(Windows::default());
+        world.insert_resource(Headless {
+            width: 800,
+            height: 400,
+        });
+        world.insert_resource(DepthConvention::ReverseZ);
+        world.init_resource::<ActiveCamera>();
+        world.init_resource::<Camera>();
+        let mut stage = SystemStage::single_threaded().with_system(sync_active_camera_system);
+        let camera = |eye: Point3<f32>| CameraBundle {
+            view: CameraView {
+                eye,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let a = world
+            .spawn()
+            .insert_bundle(camera(Point3::new(0.0, 0.0, 5.0)))
+            .id();
+        let b = world
+            .spawn()
+            .insert_bundle(camera(Point3::new(5.0, 0.0, 0.0)))
+            .id();
+        let uniform = |world: &World| {
+            let mut uniform = CameraUniform::default();
+            world.resource::<Camera>().update_uniform(&mut uniform);
+            uniform.view_proj
+        };
+
+        stage.run(&mut world);
+        let uniform_a = uniform(&world);
+        assert_eq!(world.resource::<ActiveCamera>().get(), Some(a));
+        assert_eq!(world.get::<PerspectiveProjection>(a).unwrap().aspect, 2.0);
+        // the projection follows the resource, spawned with the standard convention
+        let projection = world.get::<PerspectiveProjection>(a).unwrap();
+        assert_eq!(projection.depth_convention, DepthConvention::ReverseZ);
+
+        let next = world.resource_mut::<ActiveCamera>().cycle([a, b]);
+        assert_eq!(next, Some(b));
+        stage.run(&mut world);
+        let uniform_b = uniform(&world);
+        assert_ne!(uniform_b, uniform_a);
+        assert_eq!(world.resource_mut::<ActiveCamera>().cycle([a, b]), Some(a));
+        world.resource_mut::<ActiveCamera>().set(b);
+
+        // the active camera is despawned, the other one takes over
+        world.despawn(b);
+        stage.run(&mut world);
+        assert_eq!(world.resource::<ActiveCamera>().get(), Some(a));
+        assert_eq!(uniform(&world), uniform_a);
+        assert!(world.resource::<ActiveCamera>().has_camera());
+
+        // none is left, the last matrices stay but nothing is drawn
+        world.despawn(a);
+        stage.run(&mut world);
+        assert_eq!(world.resource::<ActiveCamera>().get(), None);
+        assert!(!world.resource::<ActiveCamera>().has_camera());
+        assert_eq!(uniform(&world), uniform_a);
+
+        world
+            .spawn()
+            .insert_bundle(camera(Point3::new(5.0, 0.0, 0.0)));
+        stage.run(&mut world);
+        assert!(world.resource::<ActiveCamera>().has_camera());
+        assert_eq!(uniform(&world), uniform_b);
+    }
+}
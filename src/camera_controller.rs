@@ -0,0 +1,535 @@
+use std::f32::consts::FRAC_PI_2;
+
+use bevy_app::{CoreStage, Plugin};
+use bevy_ecs::{
+    bundle::Bundle,
+    prelude::Component,
+    schedule::ParallelSystemDescriptorCoercion,
+    system::{Query, Res, ResMut},
+};
+use cgmath::{InnerSpace, MetricSpace, Point3, Rad, Vector2, Vector3};
+
+use crate::{
+    camera::{
+        sync_active_camera_system, ActiveCamera, CameraView, PerspectiveProjection, RenderCamera,
+    },
+    input::{
+        capture::InputCapture,
+        keyboard::KeyCode,
+        mouse::{AccumulatedMouseMotion, AccumulatedMouseScroll, MouseButton},
+        Input,
+    },
+    time::Time,
+    window::{commands::CursorMode, WindowId, Windows},
+    FlatSystemLabels,
+};
+
+/// Moves the camera of an [`FpsCameraBundle`] or [`OrbitCameraBundle`] by the input of
+/// the frame while it is the [`ActiveCamera`], the other rigs stay where they are. The
+/// active camera is written into the [`Camera`](crate::camera::Camera) resource in
+/// `CoreStage::PostUpdate`, before the uniforms are synced.
+pub struct FlatCameraControllerPlugin;
+impl Plugin for FlatCameraControllerPlugin {
+    fn build(&self, app: &mut bevy_app::App) {
+        app.init_resource::<ActiveCamera>()
+            .add_system(fly_camera_system)
+            .add_system(orbit_camera_system)
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                sync_active_camera_system
+                    .after(FlatSystemLabels::TransformPropagate)
+                    .before(FlatSystemLabels::UniformSync),
+            );
+    }
+}
+
+/// First person flight: WASD moves along the view, Space and left shift up and down.
+/// Looks around with the mouse while the cursor is locked, a left click locks it and
+/// Escape releases it
+#[derive(Component, Debug, Clone, PartialEq)]
+pub struct FlyCameraController {
+    /// World units per second
+    pub speed: f32,
+    /// Radians per unit of `AccumulatedMouseMotion`
+    pub sensitivity: f32,
+    /// Lock the cursor of the primary window on a left click
+    pub lock_on_click: bool,
+    /// Counterclockwise around `+y`, zero looks along `-z`
+    pub yaw: Rad<f32>,
+    /// Up from the horizon, within `±max_pitch`
+    pub pitch: Rad<f32>,
+    pub max_pitch: Rad<f32>,
+}
+
+impl Default for FlyCameraController {
+    fn default() -> Self {
+        Self {
+            speed: 5.0,
+            sensitivity: 0.002,
+            lock_on_click: true,
+            yaw: Rad(0.0),
+            pitch: Rad(0.0),
+            max_pitch: Rad(FRAC_PI_2 - 0.01),
+        }
+    }
+}
+
+impl FlyCameraController {
+    /// Normalized, where `yaw` and `pitch` look
+    pub fn look_direction(&self) -> Vector3<f32> {
+        let (yaw, pitch) = (self.yaw.0, self.pitch.0);
+        Vector3::new(
+            -yaw.sin() * pitch.cos(),
+            pitch.sin(),
+            -yaw.cos() * pitch.cos(),
+        )
+    }
+
+    /// Turns by a mouse `delta`, moving the mouse up looks up
+    pub fn look(&mut self, delta: Vector2<f32>) {
+        self.yaw -= Rad(delta.x * self.sensitivity);
+        self.pitch =
+            Rad((self.pitch.0 - delta.y * self.sensitivity)
+                .clamp(-self.max_pitch.0, self.max_pitch.0));
+    }
+
+    /// `local` is right, up and forward, moved by `speed * dt`. Forward stays horizontal
+    pub fn translation(&self, local: Vector3<f32>, dt: f32) -> Vector3<f32> {
+        if local == Vector3::new(0.0, 0.0, 0.0) {
+            return local;
+        }
+        let forward = Vector3::new(-self.yaw.0.sin(), 0.0, -self.yaw.0.cos());
+        let right = Vector3::new(self.yaw.0.cos(), 0.0, -self.yaw.0.sin());
+        let direction = right * local.x + Vector3::unit_y() * local.y + forward * local.z;
+        direction.normalize() * self.speed * dt
+    }
+
+    /// Looks along [`FlyCameraController::look_direction`] from the eye of `view`
+    pub fn apply(&self, view: &mut CameraView) {
+        view.target = view.eye + self.look_direction();
+        view.up = Vector3::unit_y();
+    }
+}
+
+/// Orbits `focus` at `distance`: left drag rotates, scrolling zooms and middle drag pans
+/// the focus in the plane of the view
+#[derive(Component, Debug, Clone, PartialEq)]
+pub struct OrbitCameraController {
+    pub focus: Point3<f32>,
+    pub distance: f32,
+    pub min_distance: f32,
+    pub max_distance: f32,
+    /// Counterclockwise around `+y`, zero puts the eye on `+z` of the focus
+    pub yaw: Rad<f32>,
+    /// Of the eye above the focus, within `[min_pitch, max_pitch]`
+    pub pitch: Rad<f32>,
+    pub min_pitch: Rad<f32>,
+    pub max_pitch: Rad<f32>,
+    /// Radians per unit of `AccumulatedMouseMotion`
+    pub orbit_sensitivity: f32,
+    /// Of the distance per unit of `AccumulatedMouseMotion`, so panning keeps pace
+    /// with the view at any zoom
+    pub pan_sensitivity: f32,
+    /// Fraction of the distance a line of scroll zooms
+    pub zoom_speed: f32,
+}
+
+impl Default for OrbitCameraController {
+    fn default() -> Self {
+        Self {
+            focus: Point3::new(0.0, 0.0, 0.0),
+            distance: 5.0,
+            min_distance: 0.5,
+            max_distance: 100.0,
+            yaw: Rad(0.0),
+            pitch: Rad(0.4),
+            min_pitch: Rad(-FRAC_PI_2 + 0.01),
+            max_pitch: Rad(FRAC_PI_2 - 0.01),
+            orbit_sensitivity: 0.005,
+            pan_sensitivity: 0.001,
+            zoom_speed: 0.1,
+        }
+    }
+}
+
+impl OrbitCameraController {
+    /// Looking at `focus` from `distance` along `+z`, before `yaw` and `pitch` turn it
+    fn unturned_view(&self) -> CameraView {
+        CameraView {
+            eye: self.focus + Vector3::unit_z() * self.distance,
+            target: self.focus,
+            up: Vector3::unit_y(),
+        }
+    }
+
+    /// Looking at `focus` from the eye
+    fn view(&self) -> CameraView {
+        let mut view = self.unturned_view();
+        view.orbit_around(self.focus, self.yaw, self.pitch);
+        view
+    }
+
+    /// `focus` plus `distance` along the direction of `yaw` and `pitch`
+    pub fn eye(&self) -> Point3<f32> {
+        self.view().eye
+    }
+
+    /// By a mouse `delta`, dragging right turns the eye to the left and dragging down
+    /// raises it. Only the angles change, [`OrbitCameraController::eye`] turns the view
+    pub fn rotate(&mut self, delta: Vector2<f32>) {
+        self.yaw -= Rad(delta.x * self.orbit_sensitivity);
+        self.pitch = Rad((self.pitch.0 + delta.y * self.orbit_sensitivity)
+            .clamp(self.min_pitch.0, self.max_pitch.0));
+    }
+
+    /// Positive `lines` move closer, e.g. `AccumulatedMouseScroll::delta_this_frame.y`
+    pub fn zoom(&mut self, lines: f32) {
+        // NOTE: along +z the distance survives the round trip exactly
+        let mut view = self.unturned_view();
+        let closer = self.distance * (1.0 - (1.0 - self.zoom_speed).powf(lines));
+        view.dolly(closer, self.min_distance, self.max_distance);
+        self.distance = view.eye.distance(self.focus);
+    }
+
+    /// Drags the scene along with a mouse `delta`, the focus moves the other way
+    pub fn pan(&mut self, delta: Vector2<f32>) {
+        let mut view = self.view();
+        let scale = self.pan_sensitivity * self.distance;
+        view.translate_local(Vector3::new(-delta.x, delta.y, 0.0) * scale);
+        self.focus = view.target;
+    }
+
+    pub fn apply(&self, view: &mut CameraView) {
+        let orbited = self.view();
+        view.set_look_at(orbited.eye, orbited.target);
+        view.up = orbited.up;
+    }
+}
+
+/// Spawns a first person camera, see [`FlyCameraController`]
+#[derive(Bundle)]
+pub struct FpsCameraBundle {
+    pub camera: RenderCamera,
+    pub view: CameraView,
+    pub projection: PerspectiveProjection,
+    pub controller: FlyCameraController,
+}
+
+impl FpsCameraBundle {
+    /// At `eye`, looking along `-z`
+    pub fn new(eye: Point3<f32>) -> Self {
+        Self {
+            camera: RenderCamera,
+            view: CameraView {
+                eye,
+                target: eye - Vector3::unit_z(),
+                up: Vector3::unit_y(),
+            },
+            projection: PerspectiveProjection {
+                fovy: 70f32.to_radians(),
+                ..Default::default()
+            },
+            controller: FlyCameraController::default(),
+        }
+    }
+}
+
+impl Default for FpsCameraBundle {
+    fn default() -> Self {
+        Self::new(Point3::new(0.0, 1.0, 2.0))
+    }
+}
+
+/// Spawns a camera orbiting a point, see [`OrbitCameraController`]
+#[derive(Bundle)]
+pub struct OrbitCameraBundle {
+    pub camera: RenderCamera,
+    pub view: CameraView,
+    pub projection: PerspectiveProjection,
+    pub controller: OrbitCameraController,
+}
+
+impl OrbitCameraBundle {
+    pub fn new(focus: Point3<f32>, distance: f32) -> Self {
+        Self::with_controller(OrbitCameraController {
+            focus,
+            distance,
+            ..Default::default()
+        })
+    }
+
+    /// `distance` and `pitch` are clamped to their limits
+    pub fn with_controller(mut controller: OrbitCameraController) -> Self {
+        controller.distance = controller
+            .distance
+            .clamp(controller.min_distance, controller.max_distance);
+        controller.pitch = Rad(controller
+            .pitch
+            .0
+            .clamp(controller.min_pitch.0, controller.max_pitch.0));
+        let mut view = CameraView::default();
+        controller.apply(&mut view);
+        Self {
+            camera: RenderCamera,
+            view,
+            projection: PerspectiveProjection::default(),
+            controller,
+        }
+    }
+}
+
+impl Default for OrbitCameraBundle {
+    fn default() -> Self {
+        Self::with_controller(OrbitCameraController::default())
+    }
+}
+
+/// Not while the UI takes the pointer, see [`InputCapture`]
+fn pointer_free(capture: &Option<Res<InputCapture>>) -> bool {
+    !capture
+        .as_ref()
+        .map_or(false, |capture| capture.pointer_captured())
+}
+
+pub fn fly_camera_system(
+    time: Res<Time>,
+    keys: Res<Input<KeyCode>>,
+    buttons: Res<Input<MouseButton>>,
+    motion: Res<AccumulatedMouseMotion>,
+    capture: Option<Res<InputCapture>>,
+    active: Res<ActiveCamera>,
+    mut windows: ResMut<Windows>,
+    mut cameras: Query<(&mut CameraView, &mut FlyCameraController)>,
+) {
+    let mut window = windows.map.get_mut(&WindowId::primary());
+    let mut locked = window
+        .as_ref()
+        .map_or(false, |window| window.cursor_mode() != CursorMode::Free);
+    let keyboard_free = !capture
+        .as_ref()
+        .map_or(false, |capture| capture.keyboard_captured());
+
+    // NOTE: released even when another rig became active while locked
+    if let Some(window) = window.as_deref_mut() {
+        if locked && keyboard_free && keys.just_pressed(KeyCode::Escape) {
+            window.set_cursor_mode(CursorMode::Free);
+            locked = false;
+        }
+    }
+
+    let (mut view, mut controller) =
+        match active.get().and_then(|entity| cameras.get_mut(entity).ok()) {
+            Some(camera) => camera,
+            None => return,
+        };
+    if let Some(window) = window {
+        if !locked
+            && controller.lock_on_click
+            && buttons.just_pressed(MouseButton::Left)
+            && pointer_free(&capture)
+        {
+            window.set_cursor_mode(CursorMode::Locked);
+        }
+    }
+    if locked {
+        controller.look(motion.delta);
+    }
+
+    if keyboard_free {
+        let axis = |positive: KeyCode, negative: KeyCode| {
+            keys.pressed(positive) as i32 as f32 - keys.pressed(negative) as i32 as f32
+        };
+        let local = Vector3::new(
+            axis(KeyCode::D, KeyCode::A),
+            axis(KeyCode::Space, KeyCode::LShift),
+            axis(KeyCode::W, KeyCode::S),
+        );
+        view.eye += controller.translation(local, time.delta_seconds());
+    }
+    controller.apply(&mut view);
+}
+
+pub fn orbit_camera_system(
+    buttons: Res<Input<MouseButton>>,
+    motion: Res<AccumulatedMouseMotion>,
+    scroll: Res<AccumulatedMouseScroll>,
+    capture: Option<Res<InputCapture>>,
+    active: Res<ActiveCamera>,
+    mut cameras: Query<(&mut CameraView, &mut OrbitCameraController)>,
+) {
+    let (mut view, mut controller) =
+        match active.get().and_then(|entity| cameras.get_mut(entity).ok()) {
+            Some(camera) => camera,
+            None => return,
+        };
+    if pointer_free(&capture) {
+        if buttons.pressed(MouseButton::Left) {
+            controller.rotate(motion.delta);
+        }
+        if buttons.pressed(MouseButton::Middle) {
+            controller.pan(motion.delta);
+        }
+        if scroll.delta_this_frame.y != 0.0 {
+            controller.zoom(scroll.delta_this_frame.y);
+        }
+    }
+    controller.apply(&mut view);
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_ecs::{
+        schedule::{Stage, SystemStage},
+        world::World,
+    };
+    use cgmath::{InnerSpace, MetricSpace, Point3, Rad, Vector2, Vector3};
+
+    use crate::{
+        camera::{ActiveCamera, CameraView},
+        input::{
+            mouse::{AccumulatedMouseMotion, AccumulatedMouseScroll, MouseButton},
+            Input,
+        },
+    };
+
+    use super::{
+        orbit_camera_system, FlyCameraController, OrbitCameraBundle, OrbitCameraController,
+    };
+
+    fn close(a: Point3<f32>, b: Point3<f32>) -> bool {
+        a.distance(b) < 1e-4
+    }
+
+    #[test]
+    fn orbit_eye_is_at_the_distance_from_the_focus() {
+        let mut orbit = OrbitCameraController {
+            focus: Point3::new(1.0, 2.0, 3.0),
+            distance: 4.0,
+            yaw: Rad(0.0),
+            pitch: Rad(0.0),
+            ..Default::default()
+        };
+        assert!(close(orbit.eye(), Point3::new(1.0, 2.0, 7.0)));
+
+        orbit.yaw = Rad(std::f32::consts::FRAC_PI_2);
+        assert!(close(orbit.eye(), Point3::new(5.0, 2.0, 3.0)));
+
+        orbit.pitch = Rad(std::f32::consts::FRAC_PI_6);
+        assert!((orbit.eye().distance(orbit.focus) - 4.0).abs() < 1e-4);
+        assert!((orbit.eye().y - 4.0).abs() < 1e-4);
+
+        let mut view = CameraView::default();
+        orbit.apply(&mut view);
+        assert!(close(view.target, orbit.focus));
+        assert!(close(view.eye, orbit.eye()));
+    }
+
+    #[test]
+    fn orbit_pitch_and_distance_stay_within_their_limits() {
+        let mut orbit = OrbitCameraController {
+            pitch: Rad(0.0),
+            min_pitch: Rad(-0.5),
+            max_pitch: Rad(1.0),
+            orbit_sensitivity: 0.5,
+            distance: 10.0,
+            min_distance: 2.0,
+            max_distance: 20.0,
+            zoom_speed: 0.5,
+            ..Default::default()
+        };
+        orbit.rotate(Vector2::new(0.0, 1.0));
+        assert_eq!(orbit.pitch, Rad(0.5));
+        orbit.rotate(Vector2::new(0.0, 1000.0));
+        assert_eq!(orbit.pitch, Rad(1.0));
+        orbit.rotate(Vector2::new(0.0, -1000.0));
+        assert_eq!(orbit.pitch, Rad(-0.5));
+
+        orbit.zoom(1.0);
+        assert_eq!(orbit.distance, 5.0);
+        orbit.zoom(10.0);
+        assert_eq!(orbit.distance, 2.0);
+        orbit.zoom(-100.0);
+        assert_eq!(orbit.distance, 20.0);
+
+        // bundles start within the limits
+        let bundle = OrbitCameraBundle::with_controller(OrbitCameraController {
+            distance: 1000.0,
+            pitch: Rad(3.0),
+            ..orbit.clone()
+        });
+        assert_eq!(bundle.controller.distance, 20.0);
+        assert_eq!(bundle.controller.pitch, Rad(1.0));
+    }
+
+    #[test]
+    fn orbit_pan_moves_the_focus_in_the_view_plane() {
+        let mut orbit = OrbitCameraController {
+            focus: Point3::new(0.0, 0.0, 0.0),
+            distance: 10.0,
+            yaw: Rad(0.0),
+            pitch: Rad(0.0),
+            pan_sensitivity: 0.01,
+            ..Default::default()
+        };
+        // dragging right moves the scene right, the focus left
+        orbit.pan(Vector2::new(10.0, 0.0));
+        assert!(close(orbit.focus, Point3::new(-1.0, 0.0, 0.0)));
+        orbit.pan(Vector2::new(0.0, 10.0));
+        assert!(close(orbit.focus, Point3::new(-1.0, 1.0, 0.0)));
+        // the distance is kept
+        assert!((orbit.eye().distance(orbit.focus) - 10.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn fly_look_is_clamped_and_moves_along_the_view() {
+        let mut fly = FlyCameraController {
+            sensitivity: 0.01,
+            max_pitch: Rad(1.0),
+            speed: 2.0,
+            ..Default::default()
+        };
+        assert!((fly.look_direction() - -Vector3::unit_z()).magnitude() < 1e-6);
+        fly.look(Vector2::new(0.0, -1000.0));
+        assert_eq!(fly.pitch, Rad(1.0));
+
+        fly.pitch = Rad(0.0);
+        fly.look(Vector2::new(-std::f32::consts::FRAC_PI_2 * 100.0, 0.0));
+        // turned left, looking along -x
+        assert!((fly.look_direction() - -Vector3::unit_x()).magnitude() < 1e-5);
+        let step = fly.translation(Vector3::new(0.0, 0.0, 1.0), 0.5);
+        assert!((step - -Vector3::unit_x()).magnitude() < 1e-5);
+        assert_eq!(
+            fly.translation(Vector3::new(0.0, 0.0, 0.0), 0.5),
+            Vector3::new(0.0, 0.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn only_the_active_rig_moves() {
+        let mut world = World::new();
+        let mut buttons = Input::<MouseButton>::default();
+        buttons.press(MouseButton::Left);
+        world.insert_resource(buttons);
+        world.insert_resource(AccumulatedMouseMotion {
+            delta: Vector2::new(100.0, 0.0),
+        });
+        world.init_resource::<AccumulatedMouseScroll>();
+        world.init_resource::<ActiveCamera>();
+        let mut stage = SystemStage::single_threaded().with_system(orbit_camera_system);
+        let a = world
+            .spawn()
+            .insert_bundle(OrbitCameraBundle::default())
+            .id();
+        let b = world
+            .spawn()
+            .insert_bundle(OrbitCameraBundle::default())
+            .id();
+        let eye = |world: &World, entity| world.get::<CameraView>(entity).unwrap().eye;
+        let start = eye(&world, a);
+
+        world.resource_mut::<ActiveCamera>().set(b);
+        stage.run(&mut world);
+        assert!(close(eye(&world, a), start));
+        assert!(!close(eye(&world, b), start));
+    }
+}
@@ -0,0 +1,150 @@
+use serde::{Deserialize, Serialize};
+
+/// RGBA color stored in linear space, which is what shaders and blending expect.
+///
+/// Colors picked in an editor or written as hex are sRGB, build them with [`Color::srgb`]
+/// or [`Color::srgba_u8`]. Render targets with an `*Srgb` format encode on write, so the
+/// linear values are passed to the GPU as they are.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Color {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+impl Color {
+    pub const WHITE: Self = Self::linear_rgba(1.0, 1.0, 1.0, 1.0);
+    pub const BLACK: Self = Self::linear_rgba(0.0, 0.0, 0.0, 1.0);
+    pub const TRANSPARENT: Self = Self::linear_rgba(0.0, 0.0, 0.0, 0.0);
+
+    pub const fn linear(r: f32, g: f32, b: f32) -> Self {
+        Self::linear_rgba(r, g, b, 1.0)
+    }
+
+    pub const fn linear_rgba(r: f32, g: f32, b: f32, a: f32) -> Self {
+        Self { r, g, b, a }
+    }
+
+    /// Components in `0.0..=1.0` sRGB
+    pub fn srgb(r: f32, g: f32, b: f32) -> Self {
+        Self::srgba(r, g, b, 1.0)
+    }
+
+    /// Alpha is linear in both spaces
+    pub fn srgba(r: f32, g: f32, b: f32, a: f32) -> Self {
+        Self::linear_rgba(srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b), a)
+    }
+
+    pub fn srgba_u8(r: u8, g: u8, b: u8, a: u8) -> Self {
+        let unorm = |c: u8| c as f32 / 255.0;
+        Self::srgba(unorm(r), unorm(g), unorm(b), unorm(a))
+    }
+
+    /// Back to sRGB components, e.g. for a color picker
+    pub fn to_srgba(self) -> [f32; 4] {
+        [
+            linear_to_srgb(self.r),
+            linear_to_srgb(self.g),
+            linear_to_srgb(self.b),
+            self.a,
+        ]
+    }
+
+    pub fn to_srgba_u8(self) -> [u8; 4] {
+        self.to_srgba()
+            .map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8)
+    }
+}
+
+impl Default for Color {
+    fn default() -> Self {
+        Self::WHITE
+    }
+}
+
+/// Linear components, for uniforms
+impl From<Color> for [f32; 4] {
+    fn from(color: Color) -> Self {
+        [color.r, color.g, color.b, color.a]
+    }
+}
+
+/// Linear components, for clear colors
+impl From<Color> for wgpu::Color {
+    fn from(color: Color) -> Self {
+        wgpu::Color {
+            r: color.r as f64,
+            g: color.g as f64,
+            b: color.b as f64,
+            a: color.a as f64,
+        }
+    }
+}
+
+/// The sRGB transfer function (IEC 61966-2-1) decoded
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{linear_to_srgb, srgb_to_linear, Color};
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-5,
+            "{actual} is not {expected}"
+        );
+    }
+
+    #[test]
+    fn conversions_match_reference_values() {
+        // (sRGB, linear)
+        let reference = [
+            (0.0, 0.0),
+            (0.04045, 0.0031308),
+            (0.2, 0.0331048),
+            (0.5, 0.2140411),
+            (0.7353570, 0.5),
+            (1.0, 1.0),
+        ];
+        for (srgb, linear) in reference {
+            assert_close(srgb_to_linear(srgb), linear);
+            assert_close(linear_to_srgb(linear), srgb);
+        }
+
+        let gray = Color::srgba_u8(128, 128, 128, 128);
+        assert_close(gray.r, 0.2158605);
+        // alpha is not converted
+        assert_close(gray.a, 128.0 / 255.0);
+        assert_eq!(
+            wgpu::Color::from(Color::srgb(1.0, 0.5, 0.0)),
+            wgpu::Color {
+                r: 1.0,
+                g: srgb_to_linear(0.5) as f64,
+                b: 0.0,
+                a: 1.0,
+            }
+        );
+    }
+
+    #[test]
+    fn every_byte_round_trips() {
+        for c in 0..=255 {
+            assert_eq!(Color::srgba_u8(c, c, c, c).to_srgba_u8(), [c; 4]);
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use std::fmt;
+
+use crate::render::resource::{preprocess::ShaderPreprocessError, reflect::ReflectionError};
+
+/// Errors the engine reports instead of panicking, by domain. Asset loaders return them
+/// through `anyhow`, with the path as context.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FlatError {
+    /// An asset could not be decoded
+    Asset { path: String, message: String },
+    /// A shader could not be preprocessed or does not match its pipeline
+    Shader(String),
+    /// Something a draw needs is missing, the draw is skipped
+    Render(String),
+    /// An event or command for a window that does not exist (anymore)
+    Window(String),
+}
+
+impl fmt::Display for FlatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FlatError::Asset { path, message } => write!(f, "asset {}: {}", path, message),
+            FlatError::Shader(message) => write!(f, "shader: {}", message),
+            FlatError::Render(message) => write!(f, "render: {}", message),
+            FlatError::Window(message) => write!(f, "window: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for FlatError {}
+
+impl From<ShaderPreprocessError> for FlatError {
+    fn from(error: ShaderPreprocessError) -> Self {
+        FlatError::Shader(error.to_string())
+    }
+}
+
+impl From<ReflectionError> for FlatError {
+    fn from(error: ReflectionError) -> Self {
+        FlatError::Shader(error.to_string())
+    }
+}
+
+/// Text of a loaded file, without the byte order mark some editors write
+pub fn decode_utf8(path: &str, bytes: &[u8]) -> Result<String, FlatError> {
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+    String::from_utf8(bytes.to_vec()).map_err(|error| FlatError::Asset {
+        path: path.to_string(),
+        message: error.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_utf8, FlatError};
+
+    #[test]
+    fn invalid_utf8_is_an_asset_error() {
+        assert_eq!(decode_utf8("a.txt", b"\xEF\xBB\xBFtext").unwrap(), "text");
+        match decode_utf8("broken.wgsl", b"fn main() {\xFF}") {
+            Err(FlatError::Asset { path, .. }) => assert_eq!(path, "broken.wgsl"),
+            other => panic!("{:?}", other),
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use bevy_asset::AssetServer;
+#[cfg(not(target_arch = "wasm32"))]
+use bevy_asset::FileAssetIo;
+#[cfg(target_arch = "wasm32")]
+use bevy_asset::WasmAssetIo;
+use winit::{
+    event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
+    event_loop::{ControlFlow, EventLoop},
+    window::{Window, WindowBuilder},
+};
+
+use crate::{
+    render::{
+        gpu_info::{GpuInfo, SurfaceInfo},
+        settings::{negotiate_surface_format, WgpuSettings},
+    },
+    request_device_async, State,
+};
+
+/// Async startup of [`State`], nothing blocks so it also runs on wasm.
+///
+/// The adapter and device are awaited, the surface is configured right away only if the
+/// window already has a size, otherwise on the first non-zero `Resized` or on `Resumed`.
+#[derive(Debug, Default)]
+pub struct EngineInit {
+    critical_assets: Vec<PathBuf>,
+    // from the environment if not set
+    wgpu_settings: Option<WgpuSettings>,
+}
+
+impl EngineInit {
+    pub async fn start(window: &Window) -> State {
+        Self::default().init(window).await
+    }
+
+    /// Loaded before the state is returned, read them with [`State::critical_asset`]
+    pub fn with_critical_assets(
+        mut self,
+        paths: impl IntoIterator<Item = impl Into<PathBuf>>,
+    ) -> Self {
+        self.critical_assets
+            .extend(paths.into_iter().map(|path| path.into()));
+        self
+    }
+
+    pub fn with_wgpu_settings(mut self, settings: WgpuSettings) -> Self {
+        self.wgpu_settings = Some(settings);
+        self
+    }
+
+    pub async fn init(self, window: &Window) -> State {
+        let size = window.inner_size();
+
+        let settings = self.wgpu_settings.unwrap_or_default();
+        let instance = wgpu::Instance::new(settings.backends);
+        let surface = unsafe { instance.create_surface(window) };
+        let (adapter, device, queue) =
+            match request_device_async(&instance, Some(&surface), &settings).await {
+                Ok(resources) => resources,
+                Err(err) => panic!("Could not initialize the GPU: {:#}", err),
+            };
+
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::COPY_SRC | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: negotiate_surface_format(
+                &surface.get_supported_formats(&adapter),
+                settings.prefer_hdr,
+            ),
+            width: size.width,
+            height: size.height,
+            present_mode: wgpu::PresentMode::Fifo,
+        };
+        GpuInfo::new(
+            &adapter,
+            &device,
+            Some(SurfaceInfo::new(&surface, &adapter, &config)),
+        )
+        .log_summary();
+
+        #[cfg(not(target_arch = "wasm32"))]
+        let asset_server = AssetServer::new(FileAssetIo::new(".", false));
+        #[cfg(target_arch = "wasm32")]
+        let asset_server = AssetServer::new(WasmAssetIo::new("."));
+
+        let requested = self.critical_assets.len();
+        let mut critical_assets = HashMap::new();
+        for path in self.critical_assets {
+            match asset_server.asset_io().load_path(&path).await {
+                Ok(bytes) => {
+                    critical_assets.insert(path, bytes);
+                }
+                Err(err) => log::error!("Could not load critical asset {:?}: {}", path, err),
+            }
+        }
+        let loaded = critical_assets.len() == requested;
+
+        let mut state = State {
+            surface,
+            device,
+            queue,
+            config,
+            size,
+            depth_texture: None,
+            asset_server,
+            critical_assets,
+            loaded,
+        };
+        // NOTE: unsized canvases report zero here
+        state.resize(size);
+        state
+    }
+}
+
+/// Runs [`State`] on a new window, blocks on native and returns right away on wasm
+pub fn run(init: EngineInit) {
+    #[cfg(not(target_arch = "wasm32"))]
+    pollster::block_on(run_event_loop(init));
+    #[cfg(target_arch = "wasm32")]
+    wasm_bindgen_futures::spawn_local(run_event_loop(init));
+}
+
+async fn run_event_loop(init: EngineInit) {
+    let event_loop = EventLoop::new();
+    let window = WindowBuilder::new().build(&event_loop).unwrap();
+
+    #[cfg(target_arch = "wasm32")]
+    {
+        use winit::platform::web::WindowExtWebSys;
+
+        web_sys::window()
+            .and_then(|win| win.document())
+            .and_then(|document| document.body())
+            .and_then(|body| {
+                body.append_child(&web_sys::Element::from(window.canvas()))
+                    .ok()
+            })
+            .expect("Could not append the canvas to the document body");
+    }
+
+    let mut state = init.init(&window).await;
+
+    event_loop.run(move |event, _, control_flow| match event {
+        Event::Resumed => state.resume(window.inner_size()),
+        Event::RedrawRequested(window_id) if window_id == window.id() => {
+            state.update();
+            match state.render() {
+                Ok(_) => {}
+                // Reconfigure the surface if lost
+                Err(wgpu::SurfaceError::Lost) => state.resize(state.size),
+                // The system is out of memory, we should probably quit
+                Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
+                // All other errors (Outdated, Timeout) should be resolved by the next frame
+                Err(e) => log::warn!("{:?}", e),
+            }
+        }
+        Event::MainEventsCleared => {
+            // RedrawRequested will only trigger once, unless we manually request it
+            window.request_redraw();
+        }
+        Event::WindowEvent { event, window_id }
+            if window_id == window.id() && !state.input(&event) =>
+        {
+            match event {
+                WindowEvent::CloseRequested
+                | WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(VirtualKeyCode::Escape),
+                            ..
+                        },
+                    ..
+                } => *control_flow = ControlFlow::Exit,
+                WindowEvent::Resized(physical_size) => state.resize(physical_size),
+                WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                    state.resize(*new_inner_size)
+                }
+                _ => {}
+            }
+        }
+        _ => {}
+    });
+}
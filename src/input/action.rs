@@ -0,0 +1,414 @@
+use std::{collections::HashMap, hash::Hash, ops::Deref};
+
+use bevy_app::App;
+use bevy_ecs::{
+    schedule::ParallelSystemDescriptorCoercion,
+    system::{Res, ResMut},
+};
+#[cfg(feature = "serde")]
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::CoreStage;
+
+use super::{
+    capture::{InputCapture, InputCaptureSystem},
+    keyboard::{KeyCode, ScanCode},
+    mouse::MouseButton,
+    Input, InputSystem, ModifiersState,
+};
+
+/// Implemented for every user enum usable as an action
+pub trait ActionLabel: Copy + Eq + Hash + Send + Sync + 'static {}
+impl<T: Copy + Eq + Hash + Send + Sync + 'static> ActionLabel for T {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum BindingInput {
+    Key(KeyCode),
+    /// Layout independent, see [`ScanCode`]
+    Physical(ScanCode),
+    Mouse(MouseButton),
+    // TODO: Gamepad(GamepadButton)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Binding {
+    pub input: BindingInput,
+    /// Have to be held together with the input, other modifiers are ignored
+    pub modifiers: ModifiersState,
+}
+
+impl Binding {
+    pub fn key(key: KeyCode) -> Self {
+        Self {
+            input: BindingInput::Key(key),
+            modifiers: ModifiersState::empty(),
+        }
+    }
+
+    pub fn physical(scancode: ScanCode) -> Self {
+        Self {
+            input: BindingInput::Physical(scancode),
+            modifiers: ModifiersState::empty(),
+        }
+    }
+
+    pub fn mouse(button: MouseButton) -> Self {
+        Self {
+            input: BindingInput::Mouse(button),
+            modifiers: ModifiersState::empty(),
+        }
+    }
+
+    pub fn with(mut self, modifiers: ModifiersState) -> Self {
+        self.modifiers |= modifiers;
+        self
+    }
+
+    fn input_pressed(
+        &self,
+        keys: &Input<KeyCode>,
+        scans: &Input<ScanCode>,
+        buttons: &Input<MouseButton>,
+    ) -> bool {
+        match self.input {
+            BindingInput::Key(key) => keys.pressed(key),
+            BindingInput::Physical(scancode) => scans.pressed(scancode),
+            BindingInput::Mouse(button) => buttons.pressed(button),
+        }
+    }
+}
+
+pub struct ActionMap<A: ActionLabel> {
+    bindings: HashMap<A, Vec<Binding>>,
+}
+
+impl<A: ActionLabel> Default for ActionMap<A> {
+    fn default() -> Self {
+        Self {
+            bindings: Default::default(),
+        }
+    }
+}
+
+impl<A: ActionLabel> ActionMap<A> {
+    /// Adds a binding to `action`, returns `false` if it was already bound
+    pub fn insert(&mut self, action: A, binding: Binding) -> bool {
+        let bindings = self.bindings.entry(action).or_default();
+        if bindings.contains(&binding) {
+            return false;
+        }
+        bindings.push(binding);
+        true
+    }
+
+    /// Returns `false` if `binding` was not bound to `action`
+    pub fn remove(&mut self, action: A, binding: &Binding) -> bool {
+        let bindings = match self.bindings.get_mut(&action) {
+            Some(bindings) => bindings,
+            None => return false,
+        };
+        let len = bindings.len();
+        bindings.retain(|b| b != binding);
+        len != bindings.len()
+    }
+
+    pub fn clear(&mut self, action: A) -> Vec<Binding> {
+        self.bindings.remove(&action).unwrap_or_default()
+    }
+
+    pub fn bindings(&self, action: A) -> &[Binding] {
+        self.bindings.get(&action).map_or(&[], Vec::as_slice)
+    }
+
+    /// Actions whose bindings are held this frame.
+    ///
+    /// When bindings on the same input are held, only the ones requiring
+    /// the most modifiers win: Ctrl+S shadows S, but actions bound to
+    /// exactly the same input and modifiers all fire.
+    pub fn resolve(
+        &self,
+        keys: &Input<KeyCode>,
+        scans: &Input<ScanCode>,
+        buttons: &Input<MouseButton>,
+    ) -> Vec<A> {
+        let modifiers = modifiers_from_keys(keys);
+
+        // (action, modifier count) of held bindings, grouped by input
+        let mut held: HashMap<BindingInput, Vec<(A, u32)>> = HashMap::new();
+        for (action, bindings) in self.bindings.iter() {
+            for binding in bindings {
+                if modifiers.contains(binding.modifiers)
+                    && binding.input_pressed(keys, scans, buttons)
+                {
+                    held.entry(binding.input)
+                        .or_default()
+                        .push((*action, binding.modifiers.bits().count_ones()));
+                }
+            }
+        }
+
+        let mut active = Vec::new();
+        for candidates in held.values() {
+            let most = candidates.iter().map(|(_, count)| *count).max().unwrap_or(0);
+            for (action, count) in candidates {
+                if *count == most && !active.contains(action) {
+                    active.push(*action);
+                }
+            }
+        }
+        active
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<A: ActionLabel + Serialize> ActionMap<A> {
+    pub fn to_ron(&self) -> anyhow::Result<String> {
+        Ok(ron::ser::to_string_pretty(
+            &self.bindings,
+            ron::ser::PrettyConfig::default(),
+        )?)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<A: ActionLabel + DeserializeOwned> ActionMap<A> {
+    pub fn from_ron(ron: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            bindings: ron::from_str(ron)?,
+        })
+    }
+}
+
+pub fn modifiers_from_keys(keys: &Input<KeyCode>) -> ModifiersState {
+    let mut modifiers = ModifiersState::empty();
+    if keys.any_pressed([KeyCode::LShift, KeyCode::RShift]) {
+        modifiers |= ModifiersState::SHIFT;
+    }
+    if keys.any_pressed([KeyCode::LControl, KeyCode::RControl]) {
+        modifiers |= ModifiersState::CTRL;
+    }
+    if keys.any_pressed([KeyCode::LAlt, KeyCode::RAlt]) {
+        modifiers |= ModifiersState::ALT;
+    }
+    if keys.any_pressed([KeyCode::LWin, KeyCode::RWin]) {
+        modifiers |= ModifiersState::LOGO;
+    }
+    modifiers
+}
+
+/// Same API as [`Input`], but for actions
+pub struct ActionState<A: ActionLabel> {
+    input: Input<A>,
+}
+
+impl<A: ActionLabel> Default for ActionState<A> {
+    fn default() -> Self {
+        Self {
+            input: Default::default(),
+        }
+    }
+}
+
+impl<A: ActionLabel> Deref for ActionState<A> {
+    type Target = Input<A>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.input
+    }
+}
+
+impl<A: ActionLabel> ActionState<A> {
+    /// 1.0 while pressed, 0.0 otherwise
+    // NOTE: analog once gamepad axes can be bound
+    pub fn value(&self, action: A) -> f32 {
+        if self.pressed(action) {
+            1.0
+        } else {
+            0.0
+        }
+    }
+
+    pub fn update(&mut self, active: &[A]) {
+        self.input.clear();
+        let released: Vec<A> = self
+            .input
+            .get_pressed()
+            .filter(|action| !active.contains(action))
+            .copied()
+            .collect();
+        for action in released {
+            self.input.release(action);
+        }
+        for action in active {
+            self.input.press(*action);
+        }
+    }
+}
+
+/// Captured inputs are seen as released, see [`InputCapture`]
+pub fn action_system<A: ActionLabel>(
+    action_map: Res<ActionMap<A>>,
+    keys: Res<Input<KeyCode>>,
+    scans: Res<Input<ScanCode>>,
+    buttons: Res<Input<MouseButton>>,
+    capture: Option<Res<InputCapture>>,
+    mut action_state: ResMut<ActionState<A>>,
+) {
+    let keyboard = capture
+        .as_ref()
+        .map_or(false, |capture| capture.keyboard_captured());
+    let pointer = capture
+        .as_ref()
+        .map_or(false, |capture| capture.pointer_captured());
+    let (no_keys, no_scans, no_buttons) = Default::default();
+    let keys = if keyboard { &no_keys } else { &*keys };
+    let scans = if keyboard { &no_scans } else { &*scans };
+    let buttons = if pointer { &no_buttons } else { &*buttons };
+    let active = action_map.resolve(keys, scans, buttons);
+    action_state.update(&active);
+}
+
+pub trait AddActions {
+    /// Bindings are added through the [`ActionMap<A>`] resource
+    fn add_actions<A: ActionLabel>(&mut self) -> &mut Self;
+}
+
+impl AddActions for App {
+    fn add_actions<A: ActionLabel>(&mut self) -> &mut Self {
+        self.init_resource::<ActionMap<A>>()
+            .init_resource::<ActionState<A>>()
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                action_system::<A>
+                    .after(InputSystem)
+                    .after(InputCaptureSystem),
+            )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+    enum Action {
+        Save,
+        MoveBack,
+        Fire,
+        Interact,
+    }
+
+    fn keys(pressed: &[KeyCode]) -> Input<KeyCode> {
+        let mut keys = Input::default();
+        for key in pressed {
+            keys.press(*key);
+        }
+        keys
+    }
+
+    fn action_map() -> ActionMap<Action> {
+        let mut map = ActionMap::default();
+        map.insert(Action::Save, Binding::key(KeyCode::S).with(ModifiersState::CTRL));
+        map.insert(Action::MoveBack, Binding::key(KeyCode::S));
+        map.insert(Action::Fire, Binding::mouse(MouseButton::Left));
+        map.insert(Action::Interact, Binding::key(KeyCode::E));
+        map.insert(Action::Fire, Binding::key(KeyCode::E));
+        map
+    }
+
+    #[test]
+    fn ctrl_s_shadows_s() {
+        let map = action_map();
+        let scans = Input::default();
+        let buttons = Input::default();
+
+        assert_eq!(
+            map.resolve(&keys(&[KeyCode::LControl, KeyCode::S]), &scans, &buttons),
+            vec![Action::Save]
+        );
+        assert_eq!(
+            map.resolve(&keys(&[KeyCode::S]), &scans, &buttons),
+            vec![Action::MoveBack]
+        );
+        // unrequired modifiers are ignored
+        assert_eq!(
+            map.resolve(&keys(&[KeyCode::RShift, KeyCode::S]), &scans, &buttons),
+            vec![Action::MoveBack]
+        );
+        assert!(map
+            .resolve(&keys(&[KeyCode::LControl]), &scans, &buttons)
+            .is_empty());
+    }
+
+    #[test]
+    fn conflicting_bindings_all_fire() {
+        let map = action_map();
+        let scans = Input::default();
+        let mut active = map.resolve(&keys(&[KeyCode::E]), &scans, &Input::default());
+        active.sort_by_key(|action| *action as u8);
+        assert_eq!(active, vec![Action::Fire, Action::Interact]);
+
+        let mut buttons = Input::default();
+        buttons.press(MouseButton::Left);
+        assert_eq!(map.resolve(&keys(&[]), &scans, &buttons), vec![Action::Fire]);
+    }
+
+    #[test]
+    fn physical_bindings_follow_scancodes() {
+        let mut map = action_map();
+        // W on QWERTY, Z on AZERTY
+        map.insert(Action::MoveBack, Binding::physical(ScanCode(17)));
+        let buttons = Input::default();
+
+        let mut scans = Input::default();
+        scans.press(ScanCode(17));
+        assert_eq!(
+            map.resolve(&keys(&[KeyCode::Z]), &scans, &buttons),
+            vec![Action::MoveBack]
+        );
+        assert!(map
+            .resolve(&keys(&[KeyCode::W]), &Input::default(), &buttons)
+            .is_empty());
+    }
+
+    #[test]
+    fn state_tracks_transitions() {
+        let mut state = ActionState::default();
+
+        state.update(&[Action::Save]);
+        assert!(state.just_pressed(Action::Save));
+        assert_eq!(state.value(Action::Save), 1.0);
+
+        state.update(&[Action::Save]);
+        assert!(state.pressed(Action::Save));
+        assert!(!state.just_pressed(Action::Save));
+
+        state.update(&[]);
+        assert!(state.just_released(Action::Save));
+        assert_eq!(state.value(Action::Save), 0.0);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn rebinding_and_ron_round_trip() {
+        let mut map = action_map();
+        assert!(!map.insert(Action::Interact, Binding::key(KeyCode::E)));
+        assert!(map.remove(Action::Interact, &Binding::key(KeyCode::E)));
+        assert!(!map.remove(Action::Interact, &Binding::key(KeyCode::E)));
+        map.insert(Action::Interact, Binding::key(KeyCode::F));
+        map.insert(Action::Interact, Binding::physical(ScanCode(33)));
+
+        let loaded = ActionMap::<Action>::from_ron(&map.to_ron().unwrap()).unwrap();
+        assert_eq!(
+            loaded.bindings(Action::Interact),
+            &[Binding::key(KeyCode::F), Binding::physical(ScanCode(33))]
+        );
+        assert_eq!(
+            loaded.bindings(Action::Save),
+            &[Binding::key(KeyCode::S).with(ModifiersState::CTRL)]
+        );
+    }
+}
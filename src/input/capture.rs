@@ -0,0 +1,74 @@
+use std::collections::HashSet;
+
+use bevy_ecs::{event::EventId, schedule::SystemLabel, system::ResMut};
+
+use super::{keyboard::KeyboardInput, mouse::MouseButtonInput};
+
+/// UI systems that capture input run in `CoreStage::PreUpdate` with this label, after
+/// [`InputSystem`](super::InputSystem). Picking and actions run after them
+#[derive(SystemLabel)]
+pub struct InputCaptureSystem;
+
+/// Input taken by the UI this frame, so a click on a button does not also fire a game
+/// action. Picking, [`ActionState`](super::action::ActionState) and the cursor lock
+/// toggle check it, raw [`Input`](super::Input) and events are left untouched.
+///
+/// NOTE: cleared in `CoreStage::First`, UI systems set it again every frame
+#[derive(Debug, Default)]
+pub struct InputCapture {
+    pointer: bool,
+    keyboard: bool,
+    // ids of the events consumed this frame
+    buttons: HashSet<usize>,
+    keys: HashSet<usize>,
+}
+
+impl InputCapture {
+    /// Mouse buttons go to the UI, e.g. while the cursor is over a panel
+    pub fn capture_pointer(&mut self, capture: bool) {
+        self.pointer = capture;
+    }
+
+    /// Keys go to the UI, e.g. while a text field is focused
+    pub fn capture_keyboard(&mut self, capture: bool) {
+        self.keyboard = capture;
+    }
+
+    pub fn pointer_captured(&self) -> bool {
+        self.pointer
+    }
+
+    pub fn keyboard_captured(&self) -> bool {
+        self.keyboard
+    }
+
+    /// Only this event goes to the UI, read with `EventReader::iter_with_id`
+    pub fn consume_button(&mut self, id: EventId<MouseButtonInput>) {
+        self.buttons.insert(id.id);
+    }
+
+    pub fn consume_key(&mut self, id: EventId<KeyboardInput>) {
+        self.keys.insert(id.id);
+    }
+
+    /// Consumed, or all buttons are captured
+    pub fn button_consumed(&self, id: EventId<MouseButtonInput>) -> bool {
+        self.pointer || self.buttons.contains(&id.id)
+    }
+
+    /// Consumed, or the keyboard is captured
+    pub fn key_consumed(&self, id: EventId<KeyboardInput>) -> bool {
+        self.keyboard || self.keys.contains(&id.id)
+    }
+
+    pub fn clear(&mut self) {
+        self.pointer = false;
+        self.keyboard = false;
+        self.buttons.clear();
+        self.keys.clear();
+    }
+}
+
+pub fn clear_input_capture_system(mut capture: ResMut<InputCapture>) {
+    capture.clear();
+}
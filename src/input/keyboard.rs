@@ -1,18 +1,50 @@
-use bevy_ecs::{prelude::EventReader, system::ResMut};
+use std::{fmt, str::FromStr};
 
-use super::{ButtonState, Input};
+use bevy_ecs::{
+    prelude::EventReader,
+    system::{Res, ResMut},
+};
 
+use crate::profiler::Profiler;
+
+use super::{ButtonState, Input, ParseInputError};
+
+#[derive(Debug, Clone)]
 pub struct KeyboardInput {
     scancode: ScanCode,
     state: ButtonState,
     keycode: Option<KeyCode>,
 }
 
+impl KeyboardInput {
+    pub fn new(scancode: ScanCode, state: ButtonState, keycode: Option<KeyCode>) -> Self {
+        Self {
+            scancode,
+            state,
+            keycode,
+        }
+    }
+
+    pub fn scancode(&self) -> ScanCode {
+        self.scancode
+    }
+
+    pub fn state(&self) -> ButtonState {
+        self.state.clone()
+    }
+
+    pub fn keycode(&self) -> Option<KeyCode> {
+        self.keycode
+    }
+}
+
 pub fn keyboard_input_system(
     mut scan_input: ResMut<Input<ScanCode>>,
     mut key_input: ResMut<Input<KeyCode>>,
     mut key_events: EventReader<KeyboardInput>,
+    profiler: Option<Res<Profiler>>,
 ) {
+    let _scope = profiler.as_ref().map(|profiler| profiler.scope("input"));
     scan_input.clear();
     key_input.clear();
     for event in key_events.iter() {
@@ -49,11 +81,39 @@ impl From<winit::event::KeyboardInput> for KeyboardInput {
     }
 }
 
+/// Physical key position, independent of the keyboard layout
+///
+/// Bind WASD-style movement to scancodes so it stays in place on
+/// AZERTY and other layouts. Values are platform specific.
 #[derive(Debug, Hash, Ord, PartialOrd, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ScanCode(pub u32);
 
+impl fmt::Display for ScanCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Scan {}", self.0)
+    }
+}
+
+/// Accepts `"Scan 30"` or just `"30"`
+impl FromStr for ScanCode {
+    type Err = ParseInputError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let code = match s.get(..4) {
+            Some(prefix) if prefix.eq_ignore_ascii_case("scan") => &s[4..],
+            _ => s,
+        };
+        code.trim()
+            .parse()
+            .map(ScanCode)
+            .map_err(|_| ParseInputError::new("scancode", s))
+    }
+}
+
 #[derive(Debug, Hash, Ord, PartialOrd, PartialEq, Eq, Clone, Copy)]
-// #[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u32)]
 pub enum KeyCode {
     /// The `1` key over the letters.
@@ -401,9 +461,378 @@ pub enum KeyCode {
     /// The `Paste` key.
     Paste,
     /// The `Cut` key.
+    // NOTE: stays last, `KeyCode::ALL` is checked against it
     Cut,
 }
 
+// NOTE: the test checks the order, this catches variants appended without being added
+const _: () = assert!(
+    KeyCode::ALL.len() == KeyCode::Cut as usize + 1
+        && KeyCode::ALL[KeyCode::ALL.len() - 1] as usize == KeyCode::Cut as usize
+);
+
+impl KeyCode {
+    /// Every variant, in declaration order
+    pub const ALL: [KeyCode; 163] = [
+        KeyCode::Key1,
+        KeyCode::Key2,
+        KeyCode::Key3,
+        KeyCode::Key4,
+        KeyCode::Key5,
+        KeyCode::Key6,
+        KeyCode::Key7,
+        KeyCode::Key8,
+        KeyCode::Key9,
+        KeyCode::Key0,
+        KeyCode::A,
+        KeyCode::B,
+        KeyCode::C,
+        KeyCode::D,
+        KeyCode::E,
+        KeyCode::F,
+        KeyCode::G,
+        KeyCode::H,
+        KeyCode::I,
+        KeyCode::J,
+        KeyCode::K,
+        KeyCode::L,
+        KeyCode::M,
+        KeyCode::N,
+        KeyCode::O,
+        KeyCode::P,
+        KeyCode::Q,
+        KeyCode::R,
+        KeyCode::S,
+        KeyCode::T,
+        KeyCode::U,
+        KeyCode::V,
+        KeyCode::W,
+        KeyCode::X,
+        KeyCode::Y,
+        KeyCode::Z,
+        KeyCode::Escape,
+        KeyCode::F1,
+        KeyCode::F2,
+        KeyCode::F3,
+        KeyCode::F4,
+        KeyCode::F5,
+        KeyCode::F6,
+        KeyCode::F7,
+        KeyCode::F8,
+        KeyCode::F9,
+        KeyCode::F10,
+        KeyCode::F11,
+        KeyCode::F12,
+        KeyCode::F13,
+        KeyCode::F14,
+        KeyCode::F15,
+        KeyCode::F16,
+        KeyCode::F17,
+        KeyCode::F18,
+        KeyCode::F19,
+        KeyCode::F20,
+        KeyCode::F21,
+        KeyCode::F22,
+        KeyCode::F23,
+        KeyCode::F24,
+        KeyCode::Snapshot,
+        KeyCode::Scroll,
+        KeyCode::Pause,
+        KeyCode::Insert,
+        KeyCode::Home,
+        KeyCode::Delete,
+        KeyCode::End,
+        KeyCode::PageDown,
+        KeyCode::PageUp,
+        KeyCode::Left,
+        KeyCode::Up,
+        KeyCode::Right,
+        KeyCode::Down,
+        KeyCode::Back,
+        KeyCode::Return,
+        KeyCode::Space,
+        KeyCode::Compose,
+        KeyCode::Caret,
+        KeyCode::Numlock,
+        KeyCode::Numpad0,
+        KeyCode::Numpad1,
+        KeyCode::Numpad2,
+        KeyCode::Numpad3,
+        KeyCode::Numpad4,
+        KeyCode::Numpad5,
+        KeyCode::Numpad6,
+        KeyCode::Numpad7,
+        KeyCode::Numpad8,
+        KeyCode::Numpad9,
+        KeyCode::AbntC1,
+        KeyCode::AbntC2,
+        KeyCode::NumpadAdd,
+        KeyCode::Apostrophe,
+        KeyCode::Apps,
+        KeyCode::Asterisk,
+        KeyCode::Plus,
+        KeyCode::At,
+        KeyCode::Ax,
+        KeyCode::Backslash,
+        KeyCode::Calculator,
+        KeyCode::Capital,
+        KeyCode::Colon,
+        KeyCode::Comma,
+        KeyCode::Convert,
+        KeyCode::NumpadDecimal,
+        KeyCode::NumpadDivide,
+        KeyCode::Equals,
+        KeyCode::Grave,
+        KeyCode::Kana,
+        KeyCode::Kanji,
+        KeyCode::LAlt,
+        KeyCode::LBracket,
+        KeyCode::LControl,
+        KeyCode::LShift,
+        KeyCode::LWin,
+        KeyCode::Mail,
+        KeyCode::MediaSelect,
+        KeyCode::MediaStop,
+        KeyCode::Minus,
+        KeyCode::NumpadMultiply,
+        KeyCode::Mute,
+        KeyCode::MyComputer,
+        KeyCode::NavigateForward,
+        KeyCode::NavigateBackward,
+        KeyCode::NextTrack,
+        KeyCode::NoConvert,
+        KeyCode::NumpadComma,
+        KeyCode::NumpadEnter,
+        KeyCode::NumpadEquals,
+        KeyCode::OEM102,
+        KeyCode::Period,
+        KeyCode::PlayPause,
+        KeyCode::Power,
+        KeyCode::PrevTrack,
+        KeyCode::RAlt,
+        KeyCode::RBracket,
+        KeyCode::RControl,
+        KeyCode::RShift,
+        KeyCode::RWin,
+        KeyCode::Semicolon,
+        KeyCode::Slash,
+        KeyCode::Sleep,
+        KeyCode::Stop,
+        KeyCode::NumpadSubtract,
+        KeyCode::Sysrq,
+        KeyCode::Tab,
+        KeyCode::Underline,
+        KeyCode::Unlabeled,
+        KeyCode::VolumeDown,
+        KeyCode::VolumeUp,
+        KeyCode::Wake,
+        KeyCode::WebBack,
+        KeyCode::WebFavorites,
+        KeyCode::WebForward,
+        KeyCode::WebHome,
+        KeyCode::WebRefresh,
+        KeyCode::WebSearch,
+        KeyCode::WebStop,
+        KeyCode::Yen,
+        KeyCode::Copy,
+        KeyCode::Paste,
+        KeyCode::Cut,
+    ];
+
+    /// Shown to users, e.g. `"Left Ctrl"`, parsed back by `FromStr`
+    pub fn name(self) -> &'static str {
+        match self {
+            KeyCode::Key1 => "1",
+            KeyCode::Key2 => "2",
+            KeyCode::Key3 => "3",
+            KeyCode::Key4 => "4",
+            KeyCode::Key5 => "5",
+            KeyCode::Key6 => "6",
+            KeyCode::Key7 => "7",
+            KeyCode::Key8 => "8",
+            KeyCode::Key9 => "9",
+            KeyCode::Key0 => "0",
+            KeyCode::A => "A",
+            KeyCode::B => "B",
+            KeyCode::C => "C",
+            KeyCode::D => "D",
+            KeyCode::E => "E",
+            KeyCode::F => "F",
+            KeyCode::G => "G",
+            KeyCode::H => "H",
+            KeyCode::I => "I",
+            KeyCode::J => "J",
+            KeyCode::K => "K",
+            KeyCode::L => "L",
+            KeyCode::M => "M",
+            KeyCode::N => "N",
+            KeyCode::O => "O",
+            KeyCode::P => "P",
+            KeyCode::Q => "Q",
+            KeyCode::R => "R",
+            KeyCode::S => "S",
+            KeyCode::T => "T",
+            KeyCode::U => "U",
+            KeyCode::V => "V",
+            KeyCode::W => "W",
+            KeyCode::X => "X",
+            KeyCode::Y => "Y",
+            KeyCode::Z => "Z",
+            KeyCode::Escape => "Escape",
+            KeyCode::F1 => "F1",
+            KeyCode::F2 => "F2",
+            KeyCode::F3 => "F3",
+            KeyCode::F4 => "F4",
+            KeyCode::F5 => "F5",
+            KeyCode::F6 => "F6",
+            KeyCode::F7 => "F7",
+            KeyCode::F8 => "F8",
+            KeyCode::F9 => "F9",
+            KeyCode::F10 => "F10",
+            KeyCode::F11 => "F11",
+            KeyCode::F12 => "F12",
+            KeyCode::F13 => "F13",
+            KeyCode::F14 => "F14",
+            KeyCode::F15 => "F15",
+            KeyCode::F16 => "F16",
+            KeyCode::F17 => "F17",
+            KeyCode::F18 => "F18",
+            KeyCode::F19 => "F19",
+            KeyCode::F20 => "F20",
+            KeyCode::F21 => "F21",
+            KeyCode::F22 => "F22",
+            KeyCode::F23 => "F23",
+            KeyCode::F24 => "F24",
+            KeyCode::Snapshot => "Print Screen",
+            KeyCode::Scroll => "Scroll Lock",
+            KeyCode::Pause => "Pause",
+            KeyCode::Insert => "Insert",
+            KeyCode::Home => "Home",
+            KeyCode::Delete => "Delete",
+            KeyCode::End => "End",
+            KeyCode::PageDown => "Page Down",
+            KeyCode::PageUp => "Page Up",
+            KeyCode::Left => "Left Arrow",
+            KeyCode::Up => "Up Arrow",
+            KeyCode::Right => "Right Arrow",
+            KeyCode::Down => "Down Arrow",
+            KeyCode::Back => "Backspace",
+            KeyCode::Return => "Enter",
+            KeyCode::Space => "Space",
+            KeyCode::Compose => "Compose",
+            KeyCode::Caret => "Caret",
+            KeyCode::Numlock => "Num Lock",
+            KeyCode::Numpad0 => "Numpad 0",
+            KeyCode::Numpad1 => "Numpad 1",
+            KeyCode::Numpad2 => "Numpad 2",
+            KeyCode::Numpad3 => "Numpad 3",
+            KeyCode::Numpad4 => "Numpad 4",
+            KeyCode::Numpad5 => "Numpad 5",
+            KeyCode::Numpad6 => "Numpad 6",
+            KeyCode::Numpad7 => "Numpad 7",
+            KeyCode::Numpad8 => "Numpad 8",
+            KeyCode::Numpad9 => "Numpad 9",
+            KeyCode::AbntC1 => "Abnt C1",
+            KeyCode::AbntC2 => "Abnt C2",
+            KeyCode::NumpadAdd => "Numpad Add",
+            KeyCode::Apostrophe => "Apostrophe",
+            KeyCode::Apps => "Apps",
+            KeyCode::Asterisk => "Asterisk",
+            KeyCode::Plus => "Plus",
+            KeyCode::At => "At",
+            KeyCode::Ax => "Ax",
+            KeyCode::Backslash => "Backslash",
+            KeyCode::Calculator => "Calculator",
+            KeyCode::Capital => "Caps Lock",
+            KeyCode::Colon => "Colon",
+            KeyCode::Comma => "Comma",
+            KeyCode::Convert => "Convert",
+            KeyCode::NumpadDecimal => "Numpad Decimal",
+            KeyCode::NumpadDivide => "Numpad Divide",
+            KeyCode::Equals => "Equals",
+            KeyCode::Grave => "Grave",
+            KeyCode::Kana => "Kana",
+            KeyCode::Kanji => "Kanji",
+            KeyCode::LAlt => "Left Alt",
+            KeyCode::LBracket => "Left Bracket",
+            KeyCode::LControl => "Left Ctrl",
+            KeyCode::LShift => "Left Shift",
+            KeyCode::LWin => "Left Win",
+            KeyCode::Mail => "Mail",
+            KeyCode::MediaSelect => "Media Select",
+            KeyCode::MediaStop => "Media Stop",
+            KeyCode::Minus => "Minus",
+            KeyCode::NumpadMultiply => "Numpad Multiply",
+            KeyCode::Mute => "Mute",
+            KeyCode::MyComputer => "My Computer",
+            KeyCode::NavigateForward => "Navigate Forward",
+            KeyCode::NavigateBackward => "Navigate Backward",
+            KeyCode::NextTrack => "Next Track",
+            KeyCode::NoConvert => "No Convert",
+            KeyCode::NumpadComma => "Numpad Comma",
+            KeyCode::NumpadEnter => "Numpad Enter",
+            KeyCode::NumpadEquals => "Numpad Equals",
+            KeyCode::OEM102 => "OEM 102",
+            KeyCode::Period => "Period",
+            KeyCode::PlayPause => "Play/Pause",
+            KeyCode::Power => "Power",
+            KeyCode::PrevTrack => "Previous Track",
+            KeyCode::RAlt => "Right Alt",
+            KeyCode::RBracket => "Right Bracket",
+            KeyCode::RControl => "Right Ctrl",
+            KeyCode::RShift => "Right Shift",
+            KeyCode::RWin => "Right Win",
+            KeyCode::Semicolon => "Semicolon",
+            KeyCode::Slash => "Slash",
+            KeyCode::Sleep => "Sleep",
+            KeyCode::Stop => "Stop",
+            KeyCode::NumpadSubtract => "Numpad Subtract",
+            KeyCode::Sysrq => "SysRq",
+            KeyCode::Tab => "Tab",
+            KeyCode::Underline => "Underline",
+            KeyCode::Unlabeled => "Unlabeled",
+            KeyCode::VolumeDown => "Volume Down",
+            KeyCode::VolumeUp => "Volume Up",
+            KeyCode::Wake => "Wake",
+            KeyCode::WebBack => "Web Back",
+            KeyCode::WebFavorites => "Web Favorites",
+            KeyCode::WebForward => "Web Forward",
+            KeyCode::WebHome => "Web Home",
+            KeyCode::WebRefresh => "Web Refresh",
+            KeyCode::WebSearch => "Web Search",
+            KeyCode::WebStop => "Web Stop",
+            KeyCode::Yen => "Yen",
+            KeyCode::Copy => "Copy",
+            KeyCode::Paste => "Paste",
+            KeyCode::Cut => "Cut",
+        }
+    }
+}
+
+impl fmt::Display for KeyCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Accepts [`KeyCode::name`] and the variant name, ignoring case
+impl FromStr for KeyCode {
+    type Err = ParseInputError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        KeyCode::ALL
+            .into_iter()
+            .find(|key| key.name().eq_ignore_ascii_case(s))
+            .or_else(|| {
+                KeyCode::ALL
+                    .into_iter()
+                    .find(|key| format!("{:?}", key).eq_ignore_ascii_case(s))
+            })
+            .ok_or_else(|| ParseInputError::new("key", s))
+    }
+}
+
 impl From<winit::event::VirtualKeyCode> for KeyCode {
     fn from(val: winit::event::VirtualKeyCode) -> Self {
         match val {
@@ -573,3 +1002,36 @@ impl From<winit::event::VirtualKeyCode> for KeyCode {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::{KeyCode, ScanCode};
+
+    #[test]
+    fn every_key_name_round_trips() {
+        let mut names = HashSet::new();
+        for (i, key) in KeyCode::ALL.into_iter().enumerate() {
+            // catches variants inserted without being added to `ALL`
+            assert_eq!(key as usize, i, "{:?} is out of place in KeyCode::ALL", key);
+            assert!(names.insert(key.name()), "{:?} has a duplicate name", key);
+
+            assert_eq!(key.to_string().parse::<KeyCode>(), Ok(key));
+            assert_eq!(key.name().to_lowercase().parse::<KeyCode>(), Ok(key));
+            assert_eq!(format!("{:?}", key).parse::<KeyCode>(), Ok(key));
+        }
+        assert_eq!(KeyCode::LControl.to_string(), "Left Ctrl");
+        assert_eq!(KeyCode::Key1.to_string(), "1");
+        assert!("Left Control Key".parse::<KeyCode>().is_err());
+    }
+
+    #[test]
+    fn scancode_round_trips() {
+        for code in [0, 30, u32::MAX] {
+            assert_eq!(ScanCode(code).to_string().parse(), Ok(ScanCode(code)));
+        }
+        assert_eq!(" 57 ".parse(), Ok(ScanCode(57)));
+        assert!("Scan".parse::<ScanCode>().is_err());
+    }
+}
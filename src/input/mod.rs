@@ -1,19 +1,38 @@
-use std::{collections::HashSet, hash::Hash};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    hash::Hash,
+    str::FromStr,
+};
 
 use bevy_app::Plugin;
-use bevy_ecs::schedule::{ParallelSystemDescriptorCoercion, SystemLabel};
+use bevy_ecs::{
+    event::{EventReader, Events, ManualEventReader},
+    schedule::{ParallelSystemDescriptorCoercion, SystemLabel},
+    system::{Local, ResMut},
+};
 
-use crate::CoreStage;
+use crate::{window::events::FocusChanged, CoreStage, FlatSystemLabels};
 
 use self::mouse::MouseButton;
 use self::{
+    capture::{clear_input_capture_system, InputCapture},
     keyboard::{keyboard_input_system, KeyCode, KeyboardInput, ScanCode},
-    mouse::{mouse_button_input_system, MouseButtonInput, MouseMotion, MouseWheel},
+    mouse::{
+        mouse_button_input_system, mouse_motion_accumulation_system,
+        mouse_scroll_accumulation_system, AccumulatedMouseMotion, AccumulatedMouseScroll,
+        MouseButtonInput, MouseMotion, MouseWheel,
+    },
+    touch::{touch_gesture_system, touch_screen_input_system, TouchGestures, TouchInput, Touches},
 };
 
+pub mod action;
+pub mod capture;
 pub mod keyboard;
 pub mod mouse;
+pub mod touch;
 
+/// The input systems, [`FlatSystemLabels::InputCollect`] is the engine wide label
 #[derive(SystemLabel)]
 pub struct InputSystem;
 
@@ -26,7 +45,9 @@ impl Plugin for FlatInputPlugin {
             .init_resource::<Input<KeyCode>>()
             .add_system_to_stage(
                 CoreStage::PreUpdate,
-                keyboard_input_system.label(InputSystem),
+                keyboard_input_system
+                    .label(InputSystem)
+                    .label(FlatSystemLabels::InputCollect),
             )
             .add_event::<MouseButtonInput>()
             .add_event::<MouseWheel>()
@@ -34,11 +55,101 @@ impl Plugin for FlatInputPlugin {
             .init_resource::<Input<MouseButton>>()
             .add_system_to_stage(
                 CoreStage::PreUpdate,
-                mouse_button_input_system.label(InputSystem),
-            );
+                mouse_button_input_system
+                    .label(InputSystem)
+                    .label(FlatSystemLabels::InputCollect),
+            )
+            .init_resource::<AccumulatedMouseMotion>()
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                mouse_motion_accumulation_system
+                    .label(InputSystem)
+                    .label(FlatSystemLabels::InputCollect),
+            )
+            .init_resource::<AccumulatedMouseScroll>()
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                mouse_scroll_accumulation_system
+                    .label(InputSystem)
+                    .label(FlatSystemLabels::InputCollect),
+            )
+            .add_event::<TouchInput>()
+            .init_resource::<Touches>()
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                touch_screen_input_system
+                    .label(InputSystem)
+                    .label(FlatSystemLabels::InputCollect),
+            )
+            .init_resource::<TouchGestures>()
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                touch_gesture_system
+                    .label(InputSystem)
+                    .label(FlatSystemLabels::InputCollect)
+                    .after(touch_screen_input_system),
+            )
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                release_input_on_focus_lost_system.after(InputSystem),
+            )
+            .init_resource::<InputCapture>()
+            .add_system_to_stage(CoreStage::First, clear_input_capture_system);
     }
 }
 
+/// Winit stops delivering releases once the window is unfocused,
+/// so every held input is released when focus is lost. Mouse motion of
+/// the frame is dropped too, it would turn a mouse look camera.
+///
+/// Runs after [`InputSystem`]: the input systems clear the `just_released`
+/// state at the start of the frame, releasing afterwards keeps it visible
+/// for this frame. The synthetic release events are re-read by the input
+/// systems next frame, where releasing an already released input is a no-op.
+pub fn release_input_on_focus_lost_system(
+    mut held_keys: Local<HashMap<ScanCode, Option<KeyCode>>>,
+    mut key_event_reader: Local<ManualEventReader<KeyboardInput>>,
+    mut focus_events: EventReader<FocusChanged>,
+    mut key_events: ResMut<Events<KeyboardInput>>,
+    mut mouse_button_events: ResMut<Events<MouseButtonInput>>,
+    mut mouse_motion_events: ResMut<Events<MouseMotion>>,
+    mut scan_input: ResMut<Input<ScanCode>>,
+    mut key_input: ResMut<Input<KeyCode>>,
+    mut mouse_button_input: ResMut<Input<MouseButton>>,
+    mut mouse_motion: ResMut<AccumulatedMouseMotion>,
+) {
+    for event in key_event_reader.iter(&key_events) {
+        match event.state() {
+            ButtonState::Pressed => {
+                held_keys.insert(event.scancode(), event.keycode());
+            }
+            ButtonState::Released => {
+                held_keys.remove(&event.scancode());
+            }
+        }
+    }
+
+    if !focus_events.iter().any(|event| !event.focused) {
+        return;
+    }
+
+    for (scancode, keycode) in held_keys.drain() {
+        key_events.send(KeyboardInput::new(scancode, ButtonState::Released, keycode));
+    }
+    for button in mouse_button_input.get_pressed() {
+        mouse_button_events.send(MouseButtonInput {
+            button: *button,
+            state: ButtonState::Released,
+        });
+    }
+
+    scan_input.release_all();
+    key_input.release_all();
+    mouse_button_input.release_all();
+    mouse_motion_events.clear();
+    *mouse_motion = AccumulatedMouseMotion::default();
+}
+
 #[derive(Debug, Clone)]
 pub enum ButtonState {
     Pressed,
@@ -61,6 +172,7 @@ bitflags::bitflags! {
     ///
     /// Each flag represents a modifier and is set if this modifier is active.
     #[derive(Default)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct ModifiersState: u32 {
         // left and right modifiers are currently commented out, but we should be able to support
         // them in a future release
@@ -83,6 +195,75 @@ bitflags::bitflags! {
     }
 }
 
+/// `"Ctrl+Shift"`, empty when no modifier is set
+impl fmt::Display for ModifiersState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = [
+            (ModifiersState::CTRL, "Ctrl"),
+            (ModifiersState::SHIFT, "Shift"),
+            (ModifiersState::ALT, "Alt"),
+            (ModifiersState::LOGO, "Logo"),
+        ];
+        let mut first = true;
+        for (modifier, name) in names {
+            if self.contains(modifier) {
+                if !first {
+                    f.write_str("+")?;
+                }
+                f.write_str(name)?;
+                first = false;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Modifiers joined by `+` in any order, ignoring case
+impl FromStr for ModifiersState {
+    type Err = ParseInputError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut state = ModifiersState::empty();
+        if s.trim().is_empty() {
+            return Ok(state);
+        }
+        for name in s.split('+').map(str::trim) {
+            state |= match name.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => ModifiersState::CTRL,
+                "shift" => ModifiersState::SHIFT,
+                "alt" | "option" => ModifiersState::ALT,
+                "logo" | "super" | "win" | "cmd" => ModifiersState::LOGO,
+                _ => return Err(ParseInputError::new("modifier", name)),
+            };
+        }
+        Ok(state)
+    }
+}
+
+/// Returned by the `FromStr` implementations of the input types
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseInputError {
+    kind: &'static str,
+    input: String,
+}
+
+impl ParseInputError {
+    pub(crate) fn new(kind: &'static str, input: &str) -> Self {
+        Self {
+            kind,
+            input: input.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ParseInputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} is not a {} name", self.input, self.kind)
+    }
+}
+
+impl std::error::Error for ParseInputError {}
+
 impl From<winit::event::ModifiersState> for ModifiersState {
     fn from(val: winit::event::ModifiersState) -> Self {
         let mut state = ModifiersState::empty();
@@ -234,3 +415,158 @@ where
         self.just_released.iter()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy_ecs::{
+        event::Events,
+        schedule::{ParallelSystemDescriptorCoercion, Stage, SystemStage},
+        world::World,
+    };
+
+    use cgmath::Vector2;
+
+    use crate::window::{events::FocusChanged, WindowId};
+
+    use super::{
+        keyboard::{keyboard_input_system, KeyCode, KeyboardInput, ScanCode},
+        mouse::{
+            mouse_button_input_system, mouse_motion_accumulation_system, AccumulatedMouseMotion,
+            MouseButton, MouseButtonInput, MouseMotion,
+        },
+        release_input_on_focus_lost_system, ButtonState, Input, InputSystem, ModifiersState,
+    };
+
+    fn input_world() -> (World, SystemStage) {
+        let mut world = World::new();
+        world.init_resource::<Input<KeyCode>>();
+        world.init_resource::<Input<ScanCode>>();
+        world.init_resource::<Input<MouseButton>>();
+        world.init_resource::<Events<KeyboardInput>>();
+        world.init_resource::<Events<MouseButtonInput>>();
+        world.init_resource::<Events<MouseMotion>>();
+        world.init_resource::<AccumulatedMouseMotion>();
+        world.init_resource::<Events<FocusChanged>>();
+
+        let stage = SystemStage::single_threaded()
+            .with_system(keyboard_input_system.label(InputSystem))
+            .with_system(mouse_button_input_system.label(InputSystem))
+            .with_system(mouse_motion_accumulation_system.label(InputSystem))
+            .with_system(release_input_on_focus_lost_system.after(InputSystem));
+
+        (world, stage)
+    }
+
+    #[test]
+    fn focus_lost_releases_all() {
+        let (mut world, mut stage) = input_world();
+
+        world
+            .resource_mut::<Events<KeyboardInput>>()
+            .send(KeyboardInput::new(
+                ScanCode(17),
+                ButtonState::Pressed,
+                Some(KeyCode::W),
+            ));
+        world
+            .resource_mut::<Events<MouseButtonInput>>()
+            .send(MouseButtonInput {
+                button: MouseButton::Left,
+                state: ButtonState::Pressed,
+            });
+        stage.run(&mut world);
+        assert!(world.resource::<Input<KeyCode>>().pressed(KeyCode::W));
+        assert!(world.resource::<Input<MouseButton>>().pressed(MouseButton::Left));
+
+        world.resource_mut::<Events<FocusChanged>>().send(FocusChanged {
+            window_id: WindowId::primary(),
+            focused: false,
+        });
+        world.resource_mut::<Events<MouseMotion>>().send(MouseMotion {
+            delta: Vector2::new(40.0, -12.0),
+        });
+        stage.run(&mut world);
+
+        assert_eq!(
+            world.resource::<AccumulatedMouseMotion>().delta,
+            Vector2::new(0.0, 0.0)
+        );
+        assert!(world.resource::<Events<MouseMotion>>().is_empty());
+        let key_input = world.resource::<Input<KeyCode>>();
+        assert!(!key_input.pressed(KeyCode::W));
+        assert!(key_input.just_released(KeyCode::W));
+        let scan_input = world.resource::<Input<ScanCode>>();
+        assert!(!scan_input.pressed(ScanCode(17)));
+        assert!(scan_input.just_released(ScanCode(17)));
+        let mouse_input = world.resource::<Input<MouseButton>>();
+        assert!(!mouse_input.pressed(MouseButton::Left));
+        assert!(mouse_input.just_released(MouseButton::Left));
+
+        // synthetic releases do not re-trigger anything
+        stage.run(&mut world);
+        assert!(!world.resource::<Input<KeyCode>>().just_released(KeyCode::W));
+    }
+
+    #[test]
+    fn scancodes_tracked_without_keycode() {
+        let (mut world, mut stage) = input_world();
+
+        let send = |world: &mut World, scancode: u32, state: ButtonState| {
+            world
+                .resource_mut::<Events<KeyboardInput>>()
+                .send(KeyboardInput::new(ScanCode(scancode), state, None));
+        };
+
+        send(&mut world, 30, ButtonState::Pressed);
+        send(&mut world, 31, ButtonState::Pressed);
+        stage.run(&mut world);
+        let scan_input = world.resource::<Input<ScanCode>>();
+        assert!(scan_input.just_pressed(ScanCode(30)));
+        assert!(scan_input.pressed(ScanCode(31)));
+        assert_eq!(world.resource::<Input<KeyCode>>().get_pressed().len(), 0);
+
+        send(&mut world, 30, ButtonState::Released);
+        stage.run(&mut world);
+        let scan_input = world.resource::<Input<ScanCode>>();
+        assert!(scan_input.just_released(ScanCode(30)));
+        assert!(!scan_input.just_pressed(ScanCode(31)));
+        assert!(scan_input.pressed(ScanCode(31)));
+
+        world.resource_mut::<Events<FocusChanged>>().send(FocusChanged {
+            window_id: WindowId::primary(),
+            focused: false,
+        });
+        stage.run(&mut world);
+        let scan_input = world.resource::<Input<ScanCode>>();
+        assert_eq!(scan_input.get_pressed().len(), 0);
+        assert!(scan_input.just_released(ScanCode(31)));
+    }
+
+    #[test]
+    fn modifiers_round_trip() {
+        let all = [
+            ModifiersState::SHIFT,
+            ModifiersState::CTRL,
+            ModifiersState::ALT,
+            ModifiersState::LOGO,
+        ];
+        for subset in 0..16 {
+            let mut modifiers = ModifiersState::empty();
+            for (i, modifier) in all.into_iter().enumerate() {
+                if subset & (1 << i) != 0 {
+                    modifiers |= modifier;
+                }
+            }
+            assert_eq!(modifiers.to_string().parse(), Ok(modifiers));
+        }
+        assert_eq!(
+            (ModifiersState::SHIFT | ModifiersState::CTRL).to_string(),
+            "Ctrl+Shift"
+        );
+        assert_eq!(
+            "shift + Control".parse(),
+            Ok(ModifiersState::SHIFT | ModifiersState::CTRL)
+        );
+        assert!("Ctrl+Hyper".parse::<ModifiersState>().is_err());
+    }
+}
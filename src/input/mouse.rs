@@ -1,6 +1,15 @@
-use super::{ButtonState, Input};
-use bevy_ecs::{event::EventReader, system::ResMut};
-use cgmath::Vector2;
+use std::{fmt, str::FromStr};
+
+use super::{ButtonState, Input, ParseInputError};
+use crate::{
+    profiler::Profiler,
+    window::util::{LogicalVec2, PhysicalVec2},
+};
+use bevy_ecs::{
+    event::EventReader,
+    system::{Res, ResMut},
+};
+use cgmath::{Vector2, Zero};
 
 /// Copied from bevy_input-0.8.1 - crate::mouse
 #[derive(Debug, Clone)]
@@ -13,7 +22,7 @@ pub struct MouseButtonInput {
 
 /// Copied from bevy_input-0.8.1 - crate::mouse
 #[derive(Debug, Hash, PartialEq, Eq, Clone, Copy)]
-// #[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum MouseButton {
     /// The left mouse button.
     Left,
@@ -25,12 +34,59 @@ pub enum MouseButton {
     Other(u16),
 }
 
+impl fmt::Display for MouseButton {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MouseButton::Left => f.write_str("Left Mouse"),
+            MouseButton::Right => f.write_str("Right Mouse"),
+            MouseButton::Middle => f.write_str("Middle Mouse"),
+            MouseButton::Other(id) => write!(f, "Mouse{}", id),
+        }
+    }
+}
+
+/// Accepts the `Display` names and `"Left"`, `"Right"`, `"Middle"`, ignoring case
+impl FromStr for MouseButton {
+    type Err = ParseInputError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let lower = s.to_ascii_lowercase();
+        match lower.as_str() {
+            "left mouse" | "left" => Ok(MouseButton::Left),
+            "right mouse" | "right" => Ok(MouseButton::Right),
+            "middle mouse" | "middle" => Ok(MouseButton::Middle),
+            _ => lower
+                .strip_prefix("mouse")
+                .and_then(|id| id.trim().parse().ok())
+                .map(MouseButton::Other)
+                .ok_or_else(|| ParseInputError::new("mouse button", s)),
+        }
+    }
+}
+
+/// Raw device motion, several arrive per frame, read [`AccumulatedMouseMotion`] instead
+/// of summing them
 #[derive(Debug, Clone)]
 pub struct MouseMotion {
     /// The change in the position of the pointing device since the last event was sent.
     pub delta: Vector2<f32>,
 }
 
+/// Sum of the [`MouseMotion`] deltas of this frame, unscaled device units
+#[derive(Debug, Clone)]
+pub struct AccumulatedMouseMotion {
+    pub delta: Vector2<f32>,
+}
+
+impl Default for AccumulatedMouseMotion {
+    fn default() -> Self {
+        Self {
+            delta: Vector2::zero(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum MouseScrollUnit {
     /// The line scroll unit.
@@ -41,7 +97,7 @@ pub enum MouseScrollUnit {
     /// The pixel scroll unit.
     ///
     /// The delta of the associated [`MouseWheel`](crate::mouse::MouseWheel) event corresponds
-    /// to the amount of pixels to scroll.
+    /// to the amount of logical pixels to scroll.
     Pixel,
 }
 
@@ -49,16 +105,67 @@ pub enum MouseScrollUnit {
 pub struct MouseWheel {
     /// The mouse scroll unit.
     pub unit: MouseScrollUnit,
-    /// The horizontal scroll value.
+    /// The horizontal scroll value, positive to the right.
     pub x: f32,
-    /// The vertical scroll value.
+    /// The vertical scroll value, positive for scrolling up.
     pub y: f32,
 }
 
+impl MouseWheel {
+    pub fn normalized(&self, pixels_per_line: f32) -> Vector2<f32> {
+        match self.unit {
+            MouseScrollUnit::Line => Vector2::new(self.x, self.y),
+            MouseScrollUnit::Pixel => Vector2::new(self.x, self.y) / pixels_per_line,
+        }
+    }
+}
+
+/// Scroll in lines, pixel deltas are divided by `pixels_per_line`
+#[derive(Debug, Clone)]
+pub struct AccumulatedMouseScroll {
+    pub pixels_per_line: f32,
+    pub delta_this_frame: Vector2<f32>,
+    pub total: Vector2<f32>,
+}
+
+impl Default for AccumulatedMouseScroll {
+    fn default() -> Self {
+        Self {
+            pixels_per_line: 20.0,
+            delta_this_frame: Vector2::zero(),
+            total: Vector2::zero(),
+        }
+    }
+}
+
+pub fn mouse_scroll_accumulation_system(
+    mut scroll: ResMut<AccumulatedMouseScroll>,
+    mut mouse_wheel_events: EventReader<MouseWheel>,
+) {
+    let mut delta = Vector2::zero();
+    for event in mouse_wheel_events.iter() {
+        delta += event.normalized(scroll.pixels_per_line);
+    }
+    scroll.delta_this_frame = delta;
+    scroll.total += delta;
+}
+
+pub fn mouse_motion_accumulation_system(
+    mut motion: ResMut<AccumulatedMouseMotion>,
+    mut mouse_motion_events: EventReader<MouseMotion>,
+) {
+    motion.delta = Vector2::zero();
+    for event in mouse_motion_events.iter() {
+        motion.delta += event.delta;
+    }
+}
+
 pub fn mouse_button_input_system(
     mut mouse_button_input: ResMut<Input<MouseButton>>,
     mut mouse_button_input_events: EventReader<MouseButtonInput>,
+    profiler: Option<Res<Profiler>>,
 ) {
+    let _scope = profiler.as_ref().map(|profiler| profiler.scope("input"));
     mouse_button_input.clear();
     for event in mouse_button_input_events.iter() {
         match event.state {
@@ -88,35 +195,175 @@ impl From<winit::event::MouseButton> for MouseButton {
     }
 }
 
-impl From<winit::event::MouseScrollDelta> for MouseWheel {
-    fn from(val: winit::event::MouseScrollDelta) -> Self {
-        match val {
-            winit::event::MouseScrollDelta::LineDelta(y, x) => MouseWheel {
+impl MouseWheel {
+    /// Pixel deltas are converted to logical pixels of a window with `scale_factor`
+    pub fn from_winit(delta: winit::event::MouseScrollDelta, scale_factor: f64) -> Self {
+        match delta {
+            // NOTE: horizontal first, as winit orders them
+            winit::event::MouseScrollDelta::LineDelta(x, y) => MouseWheel {
                 unit: MouseScrollUnit::Line,
                 x,
                 y,
             },
-            winit::event::MouseScrollDelta::PixelDelta(pos) => MouseWheel {
-                unit: MouseScrollUnit::Pixel,
-                x: pos.x as f32,
-                y: pos.y as f32,
-            },
+            winit::event::MouseScrollDelta::PixelDelta(pos) => {
+                let LogicalVec2(delta) =
+                    PhysicalVec2::new(pos.x as f32, pos.y as f32).delta_to_logical(scale_factor);
+                MouseWheel {
+                    unit: MouseScrollUnit::Pixel,
+                    x: delta.x,
+                    y: delta.y,
+                }
+            }
         }
     }
 }
 
-impl From<winit::dpi::PhysicalPosition<f64>> for MouseMotion {
-    fn from(val: winit::dpi::PhysicalPosition<f64>) -> Self {
+/// From `DeviceEvent::MouseMotion`, cursor positions are not deltas
+impl From<(f64, f64)> for MouseMotion {
+    fn from(val: (f64, f64)) -> Self {
         MouseMotion {
-            delta: cgmath::Vector2::new(val.x as f32, val.y as f32),
+            delta: cgmath::Vector2::new(val.0 as f32, val.1 as f32),
         }
     }
 }
 
-impl From<(f64, f64)> for MouseMotion {
-    fn from(val: (f64, f64)) -> Self {
-        MouseMotion {
-            delta: cgmath::Vector2::new(val.0 as f32, val.1 as f32),
+#[cfg(test)]
+mod tests {
+    use bevy_ecs::{
+        event::Events,
+        schedule::{Stage, SystemStage},
+        world::World,
+    };
+    use cgmath::Vector2;
+
+    use super::{
+        mouse_motion_accumulation_system, mouse_scroll_accumulation_system, AccumulatedMouseMotion,
+        AccumulatedMouseScroll, MouseButton, MouseMotion, MouseScrollUnit, MouseWheel,
+    };
+
+    fn scroll_after(events: Vec<MouseWheel>) -> AccumulatedMouseScroll {
+        let mut world = World::new();
+        world.insert_resource(AccumulatedMouseScroll {
+            pixels_per_line: 10.0,
+            ..Default::default()
+        });
+        world.init_resource::<Events<MouseWheel>>();
+        for event in events {
+            world.resource_mut::<Events<MouseWheel>>().send(event);
+        }
+
+        let mut stage = SystemStage::single_threaded().with_system(mouse_scroll_accumulation_system);
+        stage.run(&mut world);
+
+        world.resource::<AccumulatedMouseScroll>().clone()
+    }
+
+    #[test]
+    fn winit_deltas_keep_their_axes() {
+        let line =
+            MouseWheel::from_winit(winit::event::MouseScrollDelta::LineDelta(1.0, -2.0), 2.0);
+        assert_eq!(
+            (line.unit, line.x, line.y),
+            (MouseScrollUnit::Line, 1.0, -2.0)
+        );
+
+        // pixel deltas are in logical pixels
+        let pixel = MouseWheel::from_winit(
+            winit::event::MouseScrollDelta::PixelDelta(winit::dpi::PhysicalPosition::new(
+                6.0, -20.0,
+            )),
+            2.0,
+        );
+        assert_eq!(
+            (pixel.unit, pixel.x, pixel.y),
+            (MouseScrollUnit::Pixel, 3.0, -10.0)
+        );
+    }
+
+    #[test]
+    fn line_and_pixel_normalize_equally() {
+        let line = scroll_after(vec![MouseWheel {
+            unit: MouseScrollUnit::Line,
+            x: 0.5,
+            y: 2.0,
+        }]);
+        let pixel = scroll_after(vec![
+            MouseWheel {
+                unit: MouseScrollUnit::Pixel,
+                x: 5.0,
+                y: 5.0,
+            },
+            MouseWheel {
+                unit: MouseScrollUnit::Pixel,
+                x: 0.0,
+                y: 15.0,
+            },
+        ]);
+
+        assert_eq!(line.delta_this_frame, Vector2::new(0.5, 2.0));
+        assert_eq!(pixel.delta_this_frame, line.delta_this_frame);
+        assert_eq!(pixel.total, line.total);
+    }
+
+    #[test]
+    fn delta_clears_each_frame() {
+        let mut world = World::new();
+        world.init_resource::<AccumulatedMouseScroll>();
+        world.init_resource::<Events<MouseWheel>>();
+        let mut stage = SystemStage::single_threaded().with_system(mouse_scroll_accumulation_system);
+
+        world.resource_mut::<Events<MouseWheel>>().send(MouseWheel {
+            unit: MouseScrollUnit::Line,
+            x: 0.0,
+            y: 1.0,
+        });
+        stage.run(&mut world);
+        stage.run(&mut world);
+
+        let scroll = world.resource::<AccumulatedMouseScroll>();
+        assert_eq!(scroll.delta_this_frame, Vector2::new(0.0, 0.0));
+        assert_eq!(scroll.total, Vector2::new(0.0, 1.0));
+    }
+
+    #[test]
+    fn motion_sums_the_frame_and_resets() {
+        let mut world = World::new();
+        world.init_resource::<AccumulatedMouseMotion>();
+        world.init_resource::<Events<MouseMotion>>();
+        let mut stage =
+            SystemStage::single_threaded().with_system(mouse_motion_accumulation_system);
+
+        for (x, y) in [(1.0, 2.0), (-3.0, 0.5), (0.5, 0.5)] {
+            world
+                .resource_mut::<Events<MouseMotion>>()
+                .send(MouseMotion::from((x, y)));
+        }
+        stage.run(&mut world);
+        assert_eq!(
+            world.resource::<AccumulatedMouseMotion>().delta,
+            Vector2::new(-1.5, 3.0)
+        );
+
+        stage.run(&mut world);
+        assert_eq!(
+            world.resource::<AccumulatedMouseMotion>().delta,
+            Vector2::new(0.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn button_names_round_trip() {
+        for button in [
+            MouseButton::Left,
+            MouseButton::Right,
+            MouseButton::Middle,
+            MouseButton::Other(4),
+            MouseButton::Other(u16::MAX),
+        ] {
+            assert_eq!(button.to_string().parse(), Ok(button));
         }
+        assert_eq!(MouseButton::Other(4).to_string(), "Mouse4");
+        assert_eq!("middle".parse(), Ok(MouseButton::Middle));
+        assert!("Mouse".parse::<MouseButton>().is_err());
     }
 }
@@ -0,0 +1,372 @@
+use std::collections::HashMap;
+
+use bevy_ecs::{
+    event::EventReader,
+    system::{Res, ResMut},
+};
+use cgmath::{MetricSpace, Vector2, Zero};
+
+use crate::window::{
+    util::{LogicalVec2, PhysicalVec2},
+    WindowId,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TouchPhase {
+    Started,
+    Moved,
+    Ended,
+    /// The system took the touch over, e.g. for a gesture of its own
+    Cancelled,
+}
+
+impl From<winit::event::TouchPhase> for TouchPhase {
+    fn from(val: winit::event::TouchPhase) -> Self {
+        match val {
+            winit::event::TouchPhase::Started => TouchPhase::Started,
+            winit::event::TouchPhase::Moved => TouchPhase::Moved,
+            winit::event::TouchPhase::Ended => TouchPhase::Ended,
+            winit::event::TouchPhase::Cancelled => TouchPhase::Cancelled,
+        }
+    }
+}
+
+/// A finger on a touch screen, sent by the runner for every phase of it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TouchInput {
+    pub window_id: WindowId,
+    /// Unique among the touches that are down, may be reused afterwards
+    pub id: u64,
+    pub phase: TouchPhase,
+    /// Like the cursor, logical pixels from the bottom left corner
+    pub position: LogicalVec2,
+    /// `0` to `1`, `None` where the screen does not report it
+    pub force: Option<f32>,
+}
+
+impl TouchInput {
+    /// `window_height` is in physical pixels, as winit reports the position
+    pub fn from_winit(
+        window_id: WindowId,
+        touch: winit::event::Touch,
+        window_height: u32,
+        scale_factor: f64,
+    ) -> Self {
+        Self {
+            window_id,
+            id: touch.id,
+            phase: touch.phase.into(),
+            position: PhysicalVec2::new(touch.location.x as f32, touch.location.y as f32)
+                .to_logical(window_height, scale_factor),
+            force: touch.force.map(|force| force.normalized() as f32),
+        }
+    }
+}
+
+/// A touch that is down or was lifted this frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Touch {
+    pub id: u64,
+    pub start_position: Vector2<f32>,
+    /// At the end of the last frame, the start position in the frame it started
+    pub previous_position: Vector2<f32>,
+    pub position: Vector2<f32>,
+    pub force: Option<f32>,
+}
+
+impl Touch {
+    fn new(event: &TouchInput) -> Self {
+        Self {
+            id: event.id,
+            start_position: event.position.0,
+            previous_position: event.position.0,
+            position: event.position.0,
+            force: event.force,
+        }
+    }
+
+    /// Moved this frame
+    pub fn delta(&self) -> Vector2<f32> {
+        self.position - self.previous_position
+    }
+
+    /// Moved since it started
+    pub fn distance(&self) -> Vector2<f32> {
+        self.position - self.start_position
+    }
+}
+
+/// The touches that are down, with `just_pressed` and `just_released` like [`Input`](super::Input).
+/// Updated from the [`TouchInput`] events by `touch_screen_input_system`
+#[derive(Debug, Clone, Default)]
+pub struct Touches {
+    pressed: HashMap<u64, Touch>,
+    just_pressed: HashMap<u64, Touch>,
+    just_released: HashMap<u64, Touch>,
+    just_cancelled: HashMap<u64, Touch>,
+}
+
+impl Touches {
+    pub fn get_pressed(&self, id: u64) -> Option<&Touch> {
+        self.pressed.get(&id)
+    }
+
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = &Touch> {
+        self.pressed.values()
+    }
+
+    pub fn just_pressed(&self, id: u64) -> bool {
+        self.just_pressed.contains_key(&id)
+    }
+
+    pub fn iter_just_pressed(&self) -> impl ExactSizeIterator<Item = &Touch> {
+        self.just_pressed.values()
+    }
+
+    /// Lifted this frame, not cancelled
+    pub fn just_released(&self, id: u64) -> bool {
+        self.just_released.contains_key(&id)
+    }
+
+    pub fn iter_just_released(&self) -> impl ExactSizeIterator<Item = &Touch> {
+        self.just_released.values()
+    }
+
+    pub fn just_cancelled(&self, id: u64) -> bool {
+        self.just_cancelled.contains_key(&id)
+    }
+
+    pub fn iter_just_cancelled(&self) -> impl ExactSizeIterator<Item = &Touch> {
+        self.just_cancelled.values()
+    }
+
+    /// The start of a frame, the previous positions catch up
+    pub fn clear(&mut self) {
+        self.just_pressed.clear();
+        self.just_released.clear();
+        self.just_cancelled.clear();
+        for touch in self.pressed.values_mut() {
+            touch.previous_position = touch.position;
+        }
+    }
+
+    pub fn process(&mut self, event: &TouchInput) {
+        match event.phase {
+            TouchPhase::Started => {
+                let touch = Touch::new(event);
+                self.pressed.insert(event.id, touch);
+                self.just_pressed.insert(event.id, touch);
+            }
+            TouchPhase::Moved => {
+                if let Some(touch) = self.pressed.get_mut(&event.id) {
+                    touch.position = event.position.0;
+                    touch.force = event.force;
+                }
+            }
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                let mut touch = match self.pressed.remove(&event.id) {
+                    Some(touch) => touch,
+                    None => return,
+                };
+                touch.position = event.position.0;
+                touch.force = event.force;
+                if event.phase == TouchPhase::Ended {
+                    self.just_released.insert(event.id, touch);
+                } else {
+                    self.just_cancelled.insert(event.id, touch);
+                }
+            }
+        }
+    }
+
+    /// Cancels every touch that is down
+    pub fn cancel_all(&mut self) {
+        self.just_cancelled.extend(self.pressed.drain());
+    }
+}
+
+pub fn touch_screen_input_system(
+    mut touches: ResMut<Touches>,
+    mut touch_input_events: EventReader<TouchInput>,
+) {
+    touches.clear();
+    for event in touch_input_events.iter() {
+        touches.process(event);
+    }
+}
+
+/// Two finger gestures of this frame, in logical pixels. Zero unless exactly two touches
+/// are down and neither started this frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TouchGestures {
+    /// Change of the distance between the fingers, positive when they spread
+    pub pinch_delta: f32,
+    /// Movement of the point between the fingers
+    pub pan: Vector2<f32>,
+}
+
+impl Default for TouchGestures {
+    fn default() -> Self {
+        Self {
+            pinch_delta: 0.0,
+            pan: Vector2::zero(),
+        }
+    }
+}
+
+impl TouchGestures {
+    pub fn from_touches(touches: &Touches) -> Self {
+        let mut pressed = touches.iter();
+        let (a, b) = match (pressed.next(), pressed.next(), pressed.next()) {
+            (Some(a), Some(b), None) => (a, b),
+            _ => return Self::default(),
+        };
+        if touches.just_pressed(a.id) || touches.just_pressed(b.id) {
+            return Self::default();
+        }
+
+        let distance = |a: Vector2<f32>, b: Vector2<f32>| a.distance(b);
+        let center = |a: Vector2<f32>, b: Vector2<f32>| (a + b) / 2.0;
+        Self {
+            pinch_delta: distance(a.position, b.position)
+                - distance(a.previous_position, b.previous_position),
+            pan: center(a.position, b.position) - center(a.previous_position, b.previous_position),
+        }
+    }
+}
+
+/// Runs after `touch_screen_input_system`
+pub fn touch_gesture_system(touches: Res<Touches>, mut gestures: ResMut<TouchGestures>) {
+    *gestures = TouchGestures::from_touches(&touches);
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_ecs::{
+        event::Events,
+        schedule::{ParallelSystemDescriptorCoercion, Stage, SystemStage},
+        world::World,
+    };
+    use cgmath::Vector2;
+
+    use crate::window::{util::LogicalVec2, WindowId};
+
+    use super::{
+        touch_gesture_system, touch_screen_input_system, TouchGestures, TouchInput, TouchPhase,
+        Touches,
+    };
+
+    fn touch_world() -> (World, SystemStage) {
+        let mut world = World::new();
+        world.init_resource::<Touches>();
+        world.init_resource::<TouchGestures>();
+        world.init_resource::<Events<TouchInput>>();
+        let stage = SystemStage::single_threaded()
+            .with_system(touch_screen_input_system)
+            .with_system(touch_gesture_system.after(touch_screen_input_system));
+        (world, stage)
+    }
+
+    fn send(world: &mut World, id: u64, phase: TouchPhase, x: f32, y: f32) {
+        world.resource_mut::<Events<TouchInput>>().send(TouchInput {
+            window_id: WindowId::primary(),
+            id,
+            phase,
+            position: LogicalVec2::new(x, y),
+            force: None,
+        });
+    }
+
+    #[test]
+    fn down_move_up() {
+        let (mut world, mut stage) = touch_world();
+
+        send(&mut world, 1, TouchPhase::Started, 10.0, 20.0);
+        stage.run(&mut world);
+        let touches = world.resource::<Touches>();
+        assert!(touches.just_pressed(1));
+        assert_eq!(touches.iter().len(), 1);
+
+        send(&mut world, 1, TouchPhase::Moved, 12.0, 20.0);
+        send(&mut world, 1, TouchPhase::Moved, 15.0, 24.0);
+        stage.run(&mut world);
+        let touches = world.resource::<Touches>();
+        assert!(!touches.just_pressed(1));
+        let touch = touches.get_pressed(1).unwrap();
+        assert_eq!(touch.delta(), Vector2::new(5.0, 4.0));
+        assert_eq!(touch.distance(), Vector2::new(5.0, 4.0));
+
+        // still down, did not move
+        stage.run(&mut world);
+        let touch = *world.resource::<Touches>().get_pressed(1).unwrap();
+        assert_eq!(touch.delta(), Vector2::new(0.0, 0.0));
+
+        send(&mut world, 1, TouchPhase::Ended, 16.0, 24.0);
+        stage.run(&mut world);
+        let touches = world.resource::<Touches>();
+        assert!(touches.just_released(1));
+        assert!(touches.get_pressed(1).is_none());
+        let released = touches.iter_just_released().next().unwrap();
+        assert_eq!(released.distance(), Vector2::new(6.0, 4.0));
+
+        stage.run(&mut world);
+        assert!(!world.resource::<Touches>().just_released(1));
+    }
+
+    #[test]
+    fn cancelled_is_not_released() {
+        let (mut world, mut stage) = touch_world();
+
+        // started and cancelled within a frame
+        send(&mut world, 7, TouchPhase::Started, 0.0, 0.0);
+        send(&mut world, 7, TouchPhase::Cancelled, 0.0, 0.0);
+        // a stray end of an unknown touch
+        send(&mut world, 8, TouchPhase::Ended, 0.0, 0.0);
+        stage.run(&mut world);
+        let touches = world.resource::<Touches>();
+        assert!(touches.just_pressed(7));
+        assert!(touches.just_cancelled(7));
+        assert!(!touches.just_released(7));
+        assert!(!touches.just_released(8));
+        assert_eq!(touches.iter().len(), 0);
+    }
+
+    #[test]
+    fn two_finger_pinch_and_pan() {
+        let (mut world, mut stage) = touch_world();
+
+        send(&mut world, 1, TouchPhase::Started, 100.0, 100.0);
+        send(&mut world, 2, TouchPhase::Started, 200.0, 100.0);
+        stage.run(&mut world);
+        // the fingers just went down
+        assert_eq!(*world.resource::<TouchGestures>(), TouchGestures::default());
+
+        // spread apart by 40 around the same center
+        send(&mut world, 1, TouchPhase::Moved, 80.0, 100.0);
+        send(&mut world, 2, TouchPhase::Moved, 220.0, 100.0);
+        stage.run(&mut world);
+        let gestures = *world.resource::<TouchGestures>();
+        assert!((gestures.pinch_delta - 40.0).abs() < 1e-4);
+        assert_eq!(gestures.pan, Vector2::new(0.0, 0.0));
+
+        // both move up by 10, then pinch in with one finger
+        send(&mut world, 1, TouchPhase::Moved, 80.0, 110.0);
+        send(&mut world, 2, TouchPhase::Moved, 220.0, 110.0);
+        stage.run(&mut world);
+        let gestures = *world.resource::<TouchGestures>();
+        assert!(gestures.pinch_delta.abs() < 1e-4);
+        assert_eq!(gestures.pan, Vector2::new(0.0, 10.0));
+
+        send(&mut world, 2, TouchPhase::Moved, 180.0, 110.0);
+        stage.run(&mut world);
+        let gestures = *world.resource::<TouchGestures>();
+        assert!((gestures.pinch_delta + 40.0).abs() < 1e-4);
+        assert_eq!(gestures.pan, Vector2::new(-20.0, 0.0));
+
+        // a third finger is not a two finger gesture
+        send(&mut world, 3, TouchPhase::Started, 0.0, 0.0);
+        send(&mut world, 2, TouchPhase::Moved, 300.0, 110.0);
+        stage.run(&mut world);
+        assert_eq!(*world.resource::<TouchGestures>(), TouchGestures::default());
+    }
+}
@@ -1,5 +1,4 @@
 
-pub mod asset;
 pub mod camera;
 pub mod texture;
 pub mod skybox;
\ No newline at end of file
@@ -243,7 +243,8 @@ impl<const N: usize> TextureArray<N> {
                     view_dimension: wgpu::TextureViewDimension::D2Array,
                     multisampled: false,
                 },
-                count: std::num::NonZeroU32::new(N as u32),
+                // one view over all layers, not a binding array
+                count: None,
             },
             wgpu::BindGroupLayoutEntry {
                 binding: 1,
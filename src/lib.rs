@@ -1,36 +1,87 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Context;
+use animation::FlatAnimationPlugin;
 use asset::FlatAssetPlugin;
-use bevy_app::{CoreStage, Plugin, PluginGroup};
-use bevy_asset::{AssetLoader, AssetServer, FileAssetIo, LoadedAsset};
+use audio::FlatAudioPlugin;
+use bevy_app::{App, CoreStage, Plugin, PluginGroup};
+use bevy_asset::{AssetLoader, AssetServer, LoadedAsset};
 use bevy_ecs::{
     schedule::{StageLabel, SystemStage},
-    system::{Commands, Res},
+    system::{Command, Commands, Res},
+    world::World,
 };
 use bevy_reflect::TypeUuid;
+use camera::Camera;
+use camera_controller::FlatCameraControllerPlugin;
 use cgmath::*;
+use color::Color;
+use init::EngineInit;
 use input::FlatInputPlugin;
-use render::{mesh::GpuMesh, resource::buffer::Vertex, FlatRenderPlugin};
+use picking::FlatPickingPlugin;
+use profiler::{finish_profiler_frame_system, Profiler};
+use render::{
+    gpu_error::WgpuErrors,
+    gpu_info::{GpuInfo, SurfaceInfo},
+    mesh::GpuMesh,
+    offscreen::OffscreenTarget,
+    postprocess::FlatPostProcessPlugin,
+    resource::buffer::Vertex,
+    settings::{negotiate_present_mode, negotiate_surface_format, rank_adapters, WgpuSettings},
+    ClearColor, DepthFormat, DepthTexture, FlatRenderPlugin,
+};
+use scene::FlatScenePlugin;
+use time::{time_system, Time};
 use wgpu::{include_wgsl, util::DeviceExt};
-use window::{FlatWinitPlugin, FlatWindowPlugin};
+use window::{
+    commands::PresentMode, FlatWindowPlugin, FlatWinitPlugin, WindowDescriptor, WindowId, Windows,
+    WinitWindows,
+};
 use winit::{event::*, window::Window};
 
 // pub mod legacy;
+pub mod animation;
+pub mod atlas;
 pub mod camera;
+pub mod camera_controller;
+pub mod color;
+pub mod error;
+pub mod light;
+pub mod particles;
+pub mod picking;
+pub mod profiler;
 pub mod render;
+pub mod scene;
+pub mod schedule;
+pub mod testing;
 pub mod text;
 pub mod texture;
+pub mod tilemap;
+pub mod time;
+pub mod transform;
+pub mod ui;
 pub mod util;
 
 pub mod asset;
+pub mod audio;
+pub mod init;
 pub mod input;
 pub mod window;
 
+pub use schedule::FlatSystemLabels;
+
 /*
 TypeUuid
 
 6948DF80-14BD-4E04-8842-7668D9C001F5 - Text
 4B8302DA-21AD-401F-AF45-1DFD956B80B5 - ShaderSource
-8628FE7C-A4E9-4056-91BD-FD6AA7817E39
-10929DF8-15C5-472B-9398-7158AB89A0A6
+8628FE7C-A4E9-4056-91BD-FD6AA7817E39 - Image
+10929DF8-15C5-472B-9398-7158AB89A0A6 - SceneDescriptor
 ED280816-E404-444A-A2D9-FFD2D171F928
 D952EB9F-7AD2-4B1B-B3CE-386735205990
 3F897E85-62CE-4B2C-A957-FCF0CCE649FD
@@ -39,9 +90,16 @@ D952EB9F-7AD2-4B1B-B3CE-386735205990
 AA97B177-9383-4934-8543-0F91A7A02836
 */
 
+/// Run in order after `CoreStage::Last`, the frame is acquired in `Prepare`
+/// and submitted in `Present`. Custom passes record into
+/// [`FrameEncoders`](render::FrameEncoders) in `MainPass` or `PostProcess`.
 #[derive(StageLabel)]
 pub enum RenderStage {
-    Render,
+    Compute,
+    Prepare,
+    MainPass,
+    PostProcess,
+    Present,
 }
 
 pub struct FlatEngineCore;
@@ -52,8 +110,14 @@ impl PluginGroup for FlatEngineCore {
         group
             .add(FlatCorePlugin)
             .add(FlatInputPlugin)
-            .add(FlatAssetPlugin)
+            .add(FlatCameraControllerPlugin)
+            .add(FlatPickingPlugin)
+            .add(FlatAssetPlugin::default())
+            .add_after::<FlatAssetPlugin, FlatAudioPlugin>(FlatAudioPlugin)
+            .add_after::<FlatAssetPlugin, FlatAnimationPlugin>(FlatAnimationPlugin)
             .add_after::<FlatAssetPlugin, FlatRenderPlugin>(FlatRenderPlugin)
+            .add_after::<FlatRenderPlugin, FlatPostProcessPlugin>(FlatPostProcessPlugin)
+            .add_after::<FlatPostProcessPlugin, FlatScenePlugin>(FlatScenePlugin)
             .add(FlatWindowPlugin)
             .add(FlatWinitPlugin::default());
     }
@@ -63,17 +127,183 @@ impl PluginGroup for FlatEngineComplete {
     fn build(&mut self, group: &mut bevy_app::PluginGroupBuilder) {
         let mut flat_engine_core = FlatEngineCore;
         flat_engine_core.build(group);
+        group.add_after::<FlatWinitPlugin, FlatWgpuPlugin>(FlatWgpuPlugin);
+    }
+}
+
+/// Builds an [`App`] with the plugins of [`FlatEngineComplete`] in order and the
+/// resources they expect, e.g. the [`Camera`] materials are drawn with.
+///
+/// ```no_run
+/// try_wgpu::FlatEngine::default_app().run();
+/// ```
+#[derive(Default)]
+pub struct FlatEngine {
+    window: WindowDescriptor,
+    clear_color: ClearColor,
+    headless: Option<Headless>,
+}
+
+impl FlatEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A window with the default descriptor
+    pub fn default_app() -> App {
+        Self::new().build()
+    }
+
+    pub fn with_window(mut self, window: WindowDescriptor) -> Self {
+        self.window = window;
+        self
+    }
+
+    pub fn with_clear_color(mut self, color: Color) -> Self {
+        self.clear_color = ClearColor(color);
+        self
+    }
+
+    /// Renders into an [`OffscreenTarget`] without a window or event loop,
+    /// `App::run` then runs a single frame
+    pub fn headless(mut self, width: u32, height: u32) -> Self {
+        self.headless = Some(Headless { width, height });
+        self
+    }
+
+    pub fn build(self) -> App {
+        let mut app = App::new();
+        // NOTE: read by the plugins while they are built
+        app.insert_resource(self.window);
+        match self.headless {
+            Some(headless) => {
+                app.insert_resource(headless)
+                    .add_plugins_with(FlatEngineComplete, |group| {
+                        group.disable::<FlatWinitPlugin>()
+                    });
+            }
+            None => {
+                app.add_plugins(FlatEngineComplete);
+            }
+        }
+        app.insert_resource(self.clear_color)
+            .init_resource::<Camera>();
+        app
+    }
+}
+
+/// Size of the [`OffscreenTarget`] of a headless app, see [`FlatEngine::headless`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Headless {
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Creates the device, and the surface of the primary window unless the app is [`Headless`].
+/// Built after [`FlatWinitPlugin`], which creates the window.
+///
+/// The device is awaited through [`request_device_async`]. Native apps block on it while
+/// the plugin is built, on wasm the resources are inserted in `CoreStage::First` once
+/// they are ready and the render systems skip the frames before.
+pub struct FlatWgpuPlugin;
+impl Plugin for FlatWgpuPlugin {
+    fn build(&self, app: &mut bevy_app::App) {
+        if let Some(headless) = app.world.get_resource::<Headless>().copied() {
+            if !create_headless_wgpu_resources(&mut app.world, headless.width, headless.height) {
+                log::error!("No adapter available, nothing is rendered");
+            }
+            return;
+        }
+        let settings = app
+            .world
+            .get_resource_or_insert_with(WgpuSettings::default)
+            .clone();
+        let present_mode = app
+            .world
+            .get_resource::<Windows>()
+            .and_then(|windows| windows.map.get(&WindowId::primary()))
+            .map_or_else(PresentMode::default, |window| window.present_mode());
+        let request = app
+            .world
+            .get_resource::<WinitWindows>()
+            .and_then(|windows| windows.get_window(WindowId::primary()))
+            .map(|window| SurfaceResources::request(window, settings, present_mode.into()));
+        let request = match request {
+            Some(request) => request,
+            None => {
+                log::error!("No primary window, nothing is rendered");
+                return;
+            }
+        };
+
+        #[cfg(not(target_arch = "wasm32"))]
+        pollster::block_on(request)
+            .unwrap_or_else(gpu_init_failed)
+            .write(&mut app.world);
+        #[cfg(target_arch = "wasm32")]
+        {
+            let pending = PendingSurfaceResources::default();
+            let slot = pending.0.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                let resources = request.await.unwrap_or_else(gpu_init_failed);
+                *slot.lock().unwrap() = Some(resources);
+            });
+            app.insert_resource(pending)
+                .add_system_to_stage(CoreStage::First, insert_surface_resources_system);
+        }
+    }
+}
+
+/// Filled by the future [`FlatWgpuPlugin`] spawns
+#[cfg(target_arch = "wasm32")]
+#[derive(Default)]
+struct PendingSurfaceResources(Arc<std::sync::Mutex<Option<SurfaceResources>>>);
+
+#[cfg(target_arch = "wasm32")]
+fn insert_surface_resources_system(mut commands: Commands, pending: Res<PendingSurfaceResources>) {
+    if let Some(resources) = pending.0.lock().unwrap().take() {
+        commands.add(resources);
     }
 }
 
+fn gpu_init_failed<T>(err: anyhow::Error) -> T {
+    panic!("Could not initialize the GPU: {:#}", err)
+}
+
 pub struct FlatCorePlugin;
 impl Plugin for FlatCorePlugin {
     fn build(&self, app: &mut bevy_app::App) {
+        // NOTE: every render stage is created here, plugins can only add systems to
+        // stages that already exist
         app.add_stage_after(
             CoreStage::Last,
-            RenderStage::Render,
+            RenderStage::Compute,
             SystemStage::parallel(),
-        );
+        )
+        .add_stage_after(
+            RenderStage::Compute,
+            RenderStage::Prepare,
+            SystemStage::parallel(),
+        )
+        .add_stage_after(
+            RenderStage::Prepare,
+            RenderStage::MainPass,
+            SystemStage::parallel(),
+        )
+        .add_stage_after(
+            RenderStage::MainPass,
+            RenderStage::PostProcess,
+            SystemStage::parallel(),
+        )
+        .add_stage_after(
+            RenderStage::PostProcess,
+            RenderStage::Present,
+            SystemStage::parallel(),
+        )
+        .init_resource::<Time>()
+        .add_system_to_stage(CoreStage::First, time_system)
+        .init_resource::<Profiler>()
+        .add_system_to_stage(CoreStage::First, finish_profiler_frame_system);
     }
 }
 
@@ -88,9 +318,9 @@ impl AssetLoader for TextLoader {
         load_context: &'a mut bevy_asset::LoadContext,
     ) -> bevy_asset::BoxedFuture<'a, anyhow::Result<(), anyhow::Error>> {
         Box::pin(async move {
-            load_context.set_default_asset(LoadedAsset::new(Text(
-                String::from_utf8(bytes.to_owned()).unwrap(),
-            )));
+            let path = load_context.path().to_string_lossy().to_string();
+            let text = error::decode_utf8(&path, bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(Text(text)));
             Ok(())
         })
     }
@@ -100,56 +330,279 @@ impl AssetLoader for TextLoader {
     }
 }
 
-pub fn create_wgpu_resources(window: Res<winit::window::Window>, mut commands: Commands) {
-    let size = window.inner_size();
-
-    let instance = wgpu::Instance::new(wgpu::Backends::all());
-    let surface = unsafe { instance.create_surface(window.as_ref()) };
-    let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
-        power_preference: wgpu::PowerPreference::HighPerformance,
-        force_fallback_adapter: false,
-        compatible_surface: Some(&surface),
-    }))
-    .unwrap();
-
-    let (device, queue) = pollster::block_on(adapter.request_device(
-        &wgpu::DeviceDescriptor {
-            label: None,
-            features: wgpu::Features::empty() | wgpu::Features::TEXTURE_BINDING_ARRAY,
-            limits: if cfg!(target_arch = "wasm32") {
-                wgpu::Limits::downlevel_webgl2_defaults()
+/// `instance` is created with [`WgpuSettings::backends`]
+pub fn request_device(
+    instance: &wgpu::Instance,
+    compatible_surface: Option<&wgpu::Surface>,
+    settings: &WgpuSettings,
+) -> anyhow::Result<(wgpu::Adapter, wgpu::Device, wgpu::Queue)> {
+    pollster::block_on(request_device_async(instance, compatible_surface, settings))
+}
+
+/// The best ranked adapter of every backend, see [`rank_adapters`]. Logs what each backend
+/// offered first
+#[cfg(not(target_arch = "wasm32"))]
+async fn request_adapter(
+    instance: &wgpu::Instance,
+    compatible_surface: Option<&wgpu::Surface>,
+    settings: &WgpuSettings,
+) -> Option<wgpu::Adapter> {
+    let mut adapters: Vec<wgpu::Adapter> = instance.enumerate_adapters(settings.backends).collect();
+    for adapter in adapters.iter() {
+        let info = adapter.get_info();
+        log::info!(
+            "{:?} offers {} ({:?}){}",
+            info.backend,
+            info.name,
+            info.device_type,
+            if compatible_surface.map_or(true, |surface| adapter.is_surface_supported(surface)) {
+                ""
             } else {
-                wgpu::Limits::default()
+                ", not compatible with the surface"
+            }
+        );
+    }
+    adapters.retain(|adapter| {
+        compatible_surface.map_or(true, |surface| adapter.is_surface_supported(surface))
+            && adapter.features().contains(settings.required_features)
+    });
+
+    let infos: Vec<wgpu::AdapterInfo> = adapters.iter().map(|adapter| adapter.get_info()).collect();
+    let best = *rank_adapters(&infos, settings).first()?;
+    Some(adapters.swap_remove(best))
+}
+
+/// NOTE: adapters can not be enumerated on wasm
+#[cfg(target_arch = "wasm32")]
+async fn request_adapter(
+    instance: &wgpu::Instance,
+    compatible_surface: Option<&wgpu::Surface>,
+    settings: &WgpuSettings,
+) -> Option<wgpu::Adapter> {
+    instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: settings.power_preference,
+            force_fallback_adapter: settings.force_fallback_adapter,
+            compatible_surface,
+        })
+        .await
+}
+
+/// [`request_device`] without blocking, wasm can only await the adapter and device
+pub async fn request_device_async(
+    instance: &wgpu::Instance,
+    compatible_surface: Option<&wgpu::Surface>,
+    settings: &WgpuSettings,
+) -> anyhow::Result<(wgpu::Adapter, wgpu::Device, wgpu::Queue)> {
+    let power_preference = settings.power_preference;
+    let adapter = request_adapter(instance, compatible_surface, settings).await;
+    let fallback = || {
+        instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::default(),
+            force_fallback_adapter: true,
+            compatible_surface,
+        })
+    };
+    let adapter = match (adapter, compatible_surface) {
+        (Some(adapter), _) => adapter,
+        // NOTE: headless, any adapter will do
+        (None, None) => fallback().await.ok_or_else(|| {
+            anyhow::anyhow!(
+                "No adapter found with {:?} and no fallback adapter is available",
+                power_preference
+            )
+        })?,
+        (None, Some(_)) => anyhow::bail!(
+            "No adapter compatible with the window surface found with {:?}, {}",
+            power_preference,
+            match fallback().await {
+                Some(fallback) => format!(
+                    "a fallback adapter would be available: {}",
+                    fallback.get_info().name
+                ),
+                None => "no fallback adapter is available either".to_string(),
+            }
+        ),
+    };
+
+    let info = adapter.get_info();
+    log::info!("Chose {} ({:?})", info.name, info.backend);
+    let (device, queue) = adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                features: adapter.features()
+                    & (wgpu::Features::TEXTURE_BINDING_ARRAY
+                        | wgpu::Features::TIMESTAMP_QUERY
+                        | wgpu::Features::POLYGON_MODE_LINE
+                        | wgpu::Features::MULTI_DRAW_INDIRECT)
+                    | settings.required_features,
+                limits: settings.limits_preset.limits(&adapter),
             },
-        },
-        None, // trace_path
-    ))
-    .unwrap();
-
-    let config = wgpu::SurfaceConfiguration {
-        usage: wgpu::TextureUsages::COPY_SRC | wgpu::TextureUsages::RENDER_ATTACHMENT,
-        format: surface.get_supported_formats(&adapter)[0],
-        width: size.width,
-        height: size.height,
-        present_mode: wgpu::PresentMode::Fifo,
+            None, // trace_path
+        )
+        .await
+        .with_context(|| {
+            format!(
+                "Device request failed on {} ({:?}, {:?})",
+                info.name, info.backend, power_preference
+            )
+        })?;
+
+    Ok((adapter, device, queue))
+}
+
+pub fn create_wgpu_resources(
+    window: Res<winit::window::Window>,
+    settings: Option<Res<WgpuSettings>>,
+    mut commands: Commands,
+) {
+    let settings = settings.map_or_else(WgpuSettings::default, |settings| settings.clone());
+    let request = SurfaceResources::request(&window, settings, PresentMode::default().into());
+    commands.add(pollster::block_on(request).unwrap_or_else(gpu_init_failed));
+}
+
+struct SurfaceResources {
+    gpu_info: GpuInfo,
+    errors: WgpuErrors,
+    surface: wgpu::Surface,
+    adapter: wgpu::Adapter,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    config: wgpu::SurfaceConfiguration,
+}
+
+impl SurfaceResources {
+    /// Vsync as the window asks for it, with the formats and modes of the surface.
+    /// The surface is created right away, the future awaits the adapter and device
+    fn request(
+        window: &winit::window::Window,
+        settings: WgpuSettings,
+        present_mode: wgpu::PresentMode,
+    ) -> impl Future<Output = anyhow::Result<Self>> {
+        let size = window.inner_size();
+        let instance = wgpu::Instance::new(settings.backends);
+        let surface = unsafe { instance.create_surface(window) };
+        async move {
+            let (adapter, device, queue) =
+                request_device_async(&instance, Some(&surface), &settings).await?;
+            let errors = WgpuErrors::default();
+            errors.install(&device);
+
+            let config = wgpu::SurfaceConfiguration {
+                usage: wgpu::TextureUsages::COPY_SRC | wgpu::TextureUsages::RENDER_ATTACHMENT,
+                format: negotiate_surface_format(
+                    &surface.get_supported_formats(&adapter),
+                    settings.prefer_hdr,
+                ),
+                width: size.width,
+                height: size.height,
+                present_mode: negotiate_present_mode(
+                    &surface.get_supported_modes(&adapter),
+                    present_mode,
+                ),
+            };
+
+            // NOTE: unsized canvases report zero, configured on the first resize
+            if size.width > 0 && size.height > 0 {
+                surface.configure(&device, &config);
+            }
+
+            let gpu_info = GpuInfo::new(
+                &adapter,
+                &device,
+                Some(SurfaceInfo::new(&surface, &adapter, &config)),
+            );
+            gpu_info.log_summary();
+
+            Ok(Self {
+                gpu_info,
+                errors,
+                surface,
+                adapter,
+                device,
+                queue,
+                config,
+            })
+        }
+    }
+}
+
+impl Command for SurfaceResources {
+    fn write(self, world: &mut World) {
+        // NOTE: the surface falls back to Fifo when the mode of the window is not supported
+        if let Some(mut windows) = world.get_resource_mut::<Windows>() {
+            if let Some(window) = windows.map.get_mut(&WindowId::primary()) {
+                if wgpu::PresentMode::from(window.present_mode()) != self.config.present_mode {
+                    window.update_present_mode(PresentMode::Fifo);
+                }
+            }
+        }
+        world.insert_resource(self.gpu_info);
+        world.insert_resource(self.errors);
+        world.insert_resource(self.surface);
+        world.insert_resource(self.adapter);
+        // NOTE: shared with the pipeline compiler threads
+        world.insert_resource(Arc::new(self.device));
+        world.insert_resource(self.queue);
+        world.insert_resource(self.config);
+    }
+}
+
+/// Returns false if no adapter could be found. Uses the [`WgpuSettings`] of `world`,
+/// inserts the default ones if there are none
+pub fn create_headless_wgpu_resources(world: &mut World, width: u32, height: u32) -> bool {
+    let settings = world
+        .get_resource_or_insert_with(WgpuSettings::default)
+        .clone();
+    let instance = wgpu::Instance::new(settings.backends);
+    let (adapter, device, queue) = match request_device(&instance, None, &settings) {
+        Ok(resources) => resources,
+        Err(err) => {
+            log::warn!("{:#}", err);
+            return false;
+        }
     };
+    let gpu_info = GpuInfo::new(&adapter, &device, None);
+    gpu_info.log_summary();
+    let errors = WgpuErrors::default();
+    errors.install(&device);
+
+    let target = OffscreenTarget::new(&device, width, height);
+    let depth_format = world.get_resource_or_insert_with(DepthFormat::default).0;
+    let depth_texture = DepthTexture::new(
+        texture::Texture::create_depth_texture_with_format(
+            &device,
+            width,
+            height,
+            depth_format,
+            "Depth Texture",
+        ),
+        depth_format,
+    );
 
-    surface.configure(&device, &config);
+    world.insert_resource(gpu_info);
+    world.insert_resource(errors);
+    world.insert_resource(target);
+    world.insert_resource(Some(depth_texture));
+    world.insert_resource(Arc::new(device));
+    world.insert_resource(queue);
 
-    commands.insert_resource(surface);
-    commands.insert_resource(device);
-    commands.insert_resource(queue);
-    commands.insert_resource(config);
+    true
 }
 
+/// Created with [`EngineInit`](init::EngineInit). The surface is only configured once the
+/// window has a non-zero size, until then frames are skipped.
 pub struct State {
     surface: wgpu::Surface,
     device: wgpu::Device,
     queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
     pub size: winit::dpi::PhysicalSize<u32>,
-    depth_texture: texture::Texture,
+    // None until the surface is configured
+    depth_texture: Option<texture::Texture>,
     asset_server: AssetServer,
+    critical_assets: HashMap<PathBuf, Vec<u8>>,
     loaded: bool,
 }
 
@@ -208,69 +661,24 @@ impl State {
     const BACKGROUND_INDICES: &'static [u16] = &[0, 1, 2, 0, 2, 3];
 
     pub async fn new(window: &Window) -> Self {
-        let size = window.inner_size();
-
-        let instance = wgpu::Instance::new(wgpu::Backends::all());
-        let surface = unsafe { instance.create_surface(window) };
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::HighPerformance,
-                force_fallback_adapter: false,
-                compatible_surface: Some(&surface),
-            })
-            .await
-            .unwrap();
-
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    label: None,
-                    features: wgpu::Features::empty() | wgpu::Features::TEXTURE_BINDING_ARRAY,
-                    limits: if cfg!(target_arch = "wasm32") {
-                        wgpu::Limits::downlevel_webgl2_defaults()
-                    } else {
-                        wgpu::Limits::default()
-                    },
-                },
-                None, // trace_path
-            )
-            .await
-            .unwrap();
-
-        let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::COPY_SRC | wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface.get_supported_formats(&adapter)[0],
-            width: size.width,
-            height: size.height,
-            present_mode: wgpu::PresentMode::Fifo,
-        };
+        EngineInit::start(window).await
+    }
 
-        surface.configure(&device, &config);
+    pub fn is_configured(&self) -> bool {
+        self.depth_texture.is_some()
+    }
 
-        let depth_texture =
-            texture::Texture::create_depth_texture(&device, &config, "Depth Texture");
+    /// Bytes of an asset awaited by [`EngineInit::with_critical_assets`]
+    pub fn critical_asset(&self, path: impl AsRef<Path>) -> Option<&[u8]> {
+        self.critical_assets.get(path.as_ref()).map(Vec::as_slice)
+    }
 
-        let asset_server = AssetServer::new(FileAssetIo::new(".", false));
-        for file in ["posx", "negx", "posy", "negy", "posz", "negz"] {
-            let path = format!("res/skybox/{file}.jpg");
-            // asset_server.load_bytes(&path);
-            // futures_lite::future::block_on(asset_server.load_bytes_async(path));
-        }
-        let loaded = false;
-
-        Self {
-            surface,
-            device,
-            queue,
-            config,
-            size,
-            depth_texture,
-
-            asset_server,
-            loaded,
-        }
+    /// Configures the surface on `Resumed`, the surface may be gone while suspended
+    pub fn resume(&mut self, size: winit::dpi::PhysicalSize<u32>) {
+        self.resize(size);
     }
 
+    /// Zero sizes are ignored, minimized windows and unsized canvases keep the last configuration
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
         if new_size.width > 0 && new_size.height > 0 {
             self.size = new_size; // Copy
@@ -278,8 +686,11 @@ impl State {
             self.config.height = new_size.height;
             self.surface.configure(&self.device, &self.config);
 
-            self.depth_texture =
-                texture::Texture::create_depth_texture(&self.device, &self.config, "Depth Texture");
+            self.depth_texture = Some(texture::Texture::create_depth_texture(
+                &self.device,
+                &self.config,
+                "Depth Texture",
+            ));
         }
     }
 
@@ -302,6 +713,10 @@ impl State {
     }
 
     pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        let depth_texture = match &self.depth_texture {
+            Some(depth_texture) => depth_texture,
+            None => return Ok(()),
+        };
         let output = self.surface.get_current_texture()?;
         let view = output
             .texture
@@ -325,7 +740,7 @@ impl State {
                     },
                 })],
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.depth_texture.view,
+                    view: &depth_texture.view,
                     depth_ops: Some(wgpu::Operations {
                         load: wgpu::LoadOp::Clear(1.0),
                         store: true,
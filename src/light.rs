@@ -0,0 +1,451 @@
+use std::sync::Arc;
+
+use bevy_ecs::{
+    entity::Entity,
+    prelude::Component,
+    system::{Commands, Query, Res, ResMut},
+};
+use bytemuck::{Pod, Zeroable};
+use cgmath::*;
+use repr_trait::C;
+
+use crate::{
+    camera::{Camera, OPENGL_TO_WGPU_MATRIX},
+    render::{
+        resource::bind::{BindingSet, GpuUniform, Uniform, UpdateGpuUniform},
+        visibility::{visible, ComputedVisibility},
+    },
+    transform::Transform,
+    util::Store,
+};
+
+/// Lights the scene and casts the shadows of the [`ShadowMap`](crate::render::shadow::ShadowMap)
+/// as a resource. As a component it only lights, the brightest one is used
+#[derive(Component)]
+pub struct DirectionalLight {
+    /// The light travels along it
+    pub direction: Vector3<f32>,
+    /// Linear
+    pub color: [f32; 3],
+    pub intensity: f32,
+    // half size of the orthographic box the shadow map covers
+    pub shadow_projection_extent: f32,
+    pub shadow_center: Point3<f32>,
+    // depth compare bias, too large values cause peter-panning
+    pub shadow_bias: f32,
+}
+
+impl DirectionalLight {
+    pub fn build_view_matrix(&self) -> Matrix4<f32> {
+        let direction = self.direction.normalize();
+        let eye = self.shadow_center - direction * self.shadow_projection_extent;
+        let up = if direction.y.abs() > 0.99 {
+            Vector3::unit_z()
+        } else {
+            Vector3::unit_y()
+        };
+        Matrix4::look_at_rh(eye, self.shadow_center, up)
+    }
+
+    pub fn build_projection_matrix(&self) -> Matrix4<f32> {
+        let e = self.shadow_projection_extent;
+        cgmath::ortho(-e, e, -e, e, 0.0, 2.0 * e)
+    }
+
+    pub fn build_view_projection_matrix(&self) -> Matrix4<f32> {
+        OPENGL_TO_WGPU_MATRIX * self.build_projection_matrix() * self.build_view_matrix()
+    }
+}
+
+impl UpdateGpuUniform for DirectionalLight {
+    type GU = LightUniform;
+
+    fn update_uniform(&self, gpu_uniform: &mut Self::GU) {
+        let direction = self.direction.normalize();
+        gpu_uniform.view_proj = self.build_view_projection_matrix().into();
+        gpu_uniform.direction = [direction.x, direction.y, direction.z, 0.0];
+        gpu_uniform.color = [self.color[0], self.color[1], self.color[2], 1.0];
+        gpu_uniform.shadow_params = [self.shadow_bias, 0.0, 0.0, 0.0];
+    }
+}
+
+impl Default for DirectionalLight {
+    fn default() -> Self {
+        Self {
+            direction: Vector3::new(-1.0, -1.0, -1.0),
+            color: [1.0, 1.0, 1.0],
+            intensity: 1.0,
+            shadow_projection_extent: 20.0,
+            shadow_center: Point3::origin(),
+            shadow_bias: 0.005,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, C, Pod, Zeroable)]
+pub struct LightUniform {
+    pub view_proj: [[f32; 4]; 4],
+    pub direction: [f32; 4],
+    pub color: [f32; 4],
+    pub shadow_params: [f32; 4], // x: bias
+}
+impl GpuUniform for LightUniform {}
+impl Default for LightUniform {
+    fn default() -> Self {
+        Self {
+            view_proj: Matrix4::identity().into(),
+            direction: [0.0, -1.0, 0.0, 0.0],
+            color: [1.0; 4],
+            shadow_params: [0.0; 4],
+        }
+    }
+}
+
+/// Point lights the [`LightsUniform`] holds, see [`select_point_lights`] for which are kept
+pub const MAX_POINT_LIGHTS: usize = 8;
+
+/// At the translation of the [`Transform`] of its entity, the origin without one.
+/// Falls off to nothing at `range`
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct PointLight {
+    /// Linear
+    pub color: [f32; 3],
+    pub intensity: f32,
+    pub range: f32,
+}
+
+impl Default for PointLight {
+    fn default() -> Self {
+        Self {
+            color: [1.0, 1.0, 1.0],
+            intensity: 1.0,
+            range: 10.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, C, Pod, Zeroable)]
+pub struct PackedPointLight {
+    /// xyz: world position, w: range
+    pub position_range: [f32; 4],
+    /// rgb: color, a: intensity
+    pub color_intensity: [f32; 4],
+}
+
+impl PackedPointLight {
+    pub fn new(position: Point3<f32>, light: &PointLight) -> Self {
+        let [r, g, b] = light.color;
+        Self {
+            position_range: [position.x, position.y, position.z, light.range],
+            color_intensity: [r, g, b, light.intensity],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, C, Pod, Zeroable)]
+pub struct PackedDirectionalLight {
+    /// xyz: normalized direction the light travels along
+    pub direction: [f32; 4],
+    /// rgb: color, a: intensity, zero without a directional light
+    pub color_intensity: [f32; 4],
+}
+
+impl From<&DirectionalLight> for PackedDirectionalLight {
+    fn from(light: &DirectionalLight) -> Self {
+        let direction = light.direction.normalize();
+        let [r, g, b] = light.color;
+        Self {
+            direction: [direction.x, direction.y, direction.z, 0.0],
+            color_intensity: [r, g, b, light.intensity],
+        }
+    }
+}
+
+/// `LightsUniform` of `res/common/lighting.wgsl`, every member is 16 byte aligned
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, C, Pod, Zeroable)]
+pub struct LightsUniform {
+    /// Of `point_lights`, the rest are zeroed
+    pub count: u32,
+    pub _padding: [u32; 3],
+    /// xyz: eye of the camera, for the specular highlights
+    pub eye: [f32; 4],
+    pub dir_light: PackedDirectionalLight,
+    pub point_lights: [PackedPointLight; MAX_POINT_LIGHTS],
+}
+impl GpuUniform for LightsUniform {}
+
+/// The lights of the frame, collected by `collect_lights_system`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Lights {
+    pub eye: [f32; 3],
+    pub directional: Option<PackedDirectionalLight>,
+    /// At most [`MAX_POINT_LIGHTS`]
+    pub point: Vec<PackedPointLight>,
+}
+
+impl UpdateGpuUniform for Lights {
+    type GU = LightsUniform;
+
+    fn update_uniform(&self, gpu_uniform: &mut Self::GU) {
+        let [x, y, z] = self.eye;
+        let count = self.point.len().min(MAX_POINT_LIGHTS);
+        gpu_uniform.count = count as u32;
+        gpu_uniform.eye = [x, y, z, 1.0];
+        gpu_uniform.dir_light = self.directional.unwrap_or_default();
+        gpu_uniform.point_lights = Default::default();
+        gpu_uniform.point_lights[..count].copy_from_slice(&self.point[..count]);
+    }
+}
+
+/// A point light in view, before the [`MAX_POINT_LIGHTS`] are picked
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PointLightCandidate {
+    pub entity: Entity,
+    pub position: Point3<f32>,
+    pub light: PointLight,
+}
+
+impl PointLightCandidate {
+    /// Intensity falling off with the squared distance
+    fn contribution(&self, eye: Point3<f32>) -> f32 {
+        self.light.intensity / (1.0 + self.position.distance2(eye))
+    }
+}
+
+/// The `max` lights contributing the most at `eye`, first. The dimmest and farthest are
+/// dropped, ties go to the nearer light and then the lower entity, so a scene keeps the
+/// same lights from frame to frame
+pub fn select_point_lights(
+    mut candidates: Vec<PointLightCandidate>,
+    eye: Point3<f32>,
+    max: usize,
+) -> Vec<PointLightCandidate> {
+    candidates.sort_by(|a, b| {
+        b.contribution(eye)
+            .total_cmp(&a.contribution(eye))
+            .then_with(|| {
+                a.position
+                    .distance2(eye)
+                    .total_cmp(&b.position.distance2(eye))
+            })
+            .then_with(|| a.entity.cmp(&b.entity))
+    });
+    candidates.truncate(max);
+    candidates
+}
+
+/// Fills [`Lights`] in `CoreStage::PostUpdate`. Point lights whose range does not reach
+/// into the view of the [`Camera`] are skipped. Without a [`DirectionalLight`] component
+/// the resource is used
+pub fn collect_lights_system(
+    camera: Option<Res<Camera>>,
+    resource_light: Option<Res<DirectionalLight>>,
+    directional: Query<((Entity, &DirectionalLight), Option<&ComputedVisibility>)>,
+    points: Query<(
+        (Entity, &PointLight, Option<&Transform>),
+        Option<&ComputedVisibility>,
+    )>,
+    mut lights: ResMut<Lights>,
+) {
+    let default_camera = Camera::default();
+    let camera = camera.as_deref().unwrap_or(&default_camera);
+    let frustum = camera.frustum();
+    let eye = camera.eye().unwrap_or_else(Point3::origin);
+
+    let brightest = visible(directional.iter())
+        .min_by(|(a_entity, a), (b_entity, b)| {
+            b.intensity
+                .total_cmp(&a.intensity)
+                .then_with(|| a_entity.cmp(b_entity))
+        })
+        .map(|(_, light)| light);
+    let directional = brightest
+        .or(resource_light.as_deref())
+        .map(PackedDirectionalLight::from);
+
+    let candidates = visible(points.iter())
+        .map(|(entity, light, transform)| PointLightCandidate {
+            entity,
+            position: Point3::from_vec(transform.map_or(Vector3::zero(), |t| t.translation)),
+            light: *light,
+        })
+        .filter(|candidate| frustum.intersects_sphere(candidate.position, candidate.light.range))
+        .collect();
+    let point = select_point_lights(candidates, eye, MAX_POINT_LIGHTS)
+        .iter()
+        .map(|candidate| PackedPointLight::new(candidate.position, &candidate.light))
+        .collect();
+
+    let collected = Lights {
+        eye: eye.into(),
+        directional,
+        point,
+    };
+    // NOTE: compared first, so change detection only fires on actual changes
+    if *lights != collected {
+        *lights = collected;
+    }
+}
+
+/// The [`Lights`] uniform and its bind group, which lit materials bind at
+/// [`GpuLights::GROUP`], see [`Material::lit`](crate::render::material::Material::lit)
+pub struct GpuLights {
+    pub uniform: Uniform<Lights>,
+    /// In `Store<wgpu::BindGroup>`
+    pub bind_group: usize,
+}
+
+impl GpuLights {
+    /// Group of `LightsUniform` in `res/common/lighting.wgsl`, after the material group
+    pub const GROUP: u32 = 1;
+
+    pub fn new(device: &wgpu::Device, bind_groups: &mut Store<wgpu::BindGroup>) -> Self {
+        let uniform = Uniform::new_default(device, wgpu::ShaderStages::FRAGMENT);
+        let bind_group = bind_groups.insert((&uniform).into_bind_group(device));
+        Self {
+            uniform,
+            bind_group,
+        }
+    }
+
+    pub fn layout_entries(&self) -> Vec<wgpu::BindGroupLayoutEntry> {
+        (&self.uniform).layout_desc().entries
+    }
+}
+
+/// Creates the [`GpuLights`] and writes the [`Lights`] of the frame into it,
+/// runs after `collect_lights_system`
+pub fn prepare_lights_system(
+    mut commands: Commands,
+    device: Option<Res<Arc<wgpu::Device>>>,
+    queue: Option<Res<wgpu::Queue>>,
+    lights: Res<Lights>,
+    gpu_lights: Option<ResMut<GpuLights>>,
+    mut bind_groups: ResMut<Store<wgpu::BindGroup>>,
+) {
+    let (device, queue) = match (device, queue) {
+        (Some(device), Some(queue)) => (device, queue),
+        _ => return,
+    };
+    let mut gpu_lights = match gpu_lights {
+        Some(gpu_lights) => gpu_lights,
+        None => {
+            let mut gpu_lights = GpuLights::new(&device, &mut bind_groups);
+            gpu_lights.uniform.update(&lights);
+            gpu_lights.uniform.sync_buffer(&queue);
+            commands.insert_resource(gpu_lights);
+            return;
+        }
+    };
+    if lights.is_changed() {
+        gpu_lights.uniform.update(&lights);
+        gpu_lights.uniform.sync_buffer(&queue);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_ecs::entity::Entity;
+    use cgmath::{Point3, Vector3};
+
+    use super::{
+        select_point_lights, Lights, LightsUniform, PackedDirectionalLight, PackedPointLight,
+        PointLight, PointLightCandidate, MAX_POINT_LIGHTS,
+    };
+    use crate::render::resource::bind::UpdateGpuUniform;
+
+    #[test]
+    fn uniform_follows_the_16_byte_rules() {
+        assert_eq!(std::mem::size_of::<PackedPointLight>(), 32);
+        assert_eq!(std::mem::size_of::<PackedDirectionalLight>(), 32);
+        // count and padding, eye, directional, point lights
+        assert_eq!(
+            std::mem::size_of::<LightsUniform>(),
+            16 + 16 + 32 + 32 * MAX_POINT_LIGHTS
+        );
+        assert_eq!(std::mem::size_of::<LightsUniform>() % 16, 0);
+
+        let uniform = LightsUniform::default();
+        let base = &uniform as *const _ as usize;
+        let offset = |field: *const u8| field as usize - base;
+        assert_eq!(offset(&uniform.eye as *const _ as *const u8), 16);
+        assert_eq!(offset(&uniform.dir_light as *const _ as *const u8), 32);
+        assert_eq!(offset(&uniform.point_lights as *const _ as *const u8), 64);
+    }
+
+    #[test]
+    fn lights_are_packed_and_the_rest_zeroed() {
+        let light = PointLight {
+            color: [1.0, 0.5, 0.25],
+            intensity: 3.0,
+            range: 7.0,
+        };
+        let packed = PackedPointLight::new(Point3::new(1.0, 2.0, 3.0), &light);
+        assert_eq!(packed.position_range, [1.0, 2.0, 3.0, 7.0]);
+        assert_eq!(packed.color_intensity, [1.0, 0.5, 0.25, 3.0]);
+
+        let mut uniform = LightsUniform::default();
+        Lights {
+            eye: [0.0, 1.0, 2.0],
+            directional: None,
+            point: vec![packed; 3],
+        }
+        .update_uniform(&mut uniform);
+        assert_eq!(uniform.count, 3);
+        assert_eq!(uniform.eye, [0.0, 1.0, 2.0, 1.0]);
+        assert_eq!(uniform.dir_light.color_intensity[3], 0.0);
+
+        Lights {
+            point: vec![packed],
+            ..Default::default()
+        }
+        .update_uniform(&mut uniform);
+        assert_eq!(uniform.count, 1);
+        assert_eq!(uniform.point_lights[1], PackedPointLight::default());
+    }
+
+    #[test]
+    fn dimmest_and_farthest_are_dropped() {
+        let candidate = |id: u32, x: f32, intensity: f32| PointLightCandidate {
+            entity: Entity::from_raw(id),
+            position: Point3::new(x, 0.0, 0.0),
+            light: PointLight {
+                intensity,
+                ..Default::default()
+            },
+        };
+        let eye = Point3::new(0.0, 0.0, 0.0);
+        let candidates = vec![
+            candidate(0, 10.0, 1.0),
+            // as bright as 0 but nearer
+            candidate(1, 1.0, 1.0),
+            // far but bright enough to be kept
+            candidate(2, 20.0, 100.0),
+            candidate(3, 2.0, 0.1),
+            // the same as 4, the lower entity wins
+            candidate(5, 3.0, 1.0),
+            candidate(4, -3.0, 1.0),
+        ];
+        let ids = |selected: Vec<PointLightCandidate>| -> Vec<u32> {
+            selected.iter().map(|c| c.entity.id()).collect()
+        };
+
+        assert_eq!(
+            ids(select_point_lights(candidates.clone(), eye, 3)),
+            [1, 2, 4]
+        );
+        assert_eq!(
+            ids(select_point_lights(candidates.clone(), eye, 10)),
+            [1, 2, 4, 5, 3, 0]
+        );
+        // from another eye, in any input order
+        let mut reversed = candidates;
+        reversed.reverse();
+        let eye = Point3::new(20.0, 0.0, 0.0) + Vector3::new(0.0, 1.0, 0.0);
+        assert_eq!(ids(select_point_lights(reversed, eye, 2)), [2, 0]);
+    }
+}
@@ -1,72 +1,11 @@
-use bevy_app::App;
-use try_wgpu::FlatEngineComplete;
-use winit::{
-    event::*,
-    event_loop::{ControlFlow, EventLoop},
-    window::WindowBuilder,
+use try_wgpu::{
+    render::gpu_error::{install_crash_report_panic_hook, CrashReportSettings},
+    FlatEngine,
 };
 
-async fn run() {
-    env_logger::init();
-
-    let event_loop = EventLoop::new();
-    let window = WindowBuilder::new().build(&event_loop).unwrap();
-
-    let mut state = try_wgpu::State::new(&window).await;
-
-    event_loop.run(move |event0, _, control_flow| {
-        match event0 {
-            Event::RedrawRequested(window_id) if window_id == window.id() => {
-                state.update();
-                match state.render() {
-                    Ok(_) => {}
-                    // Reconfigure the surface if lost
-                    Err(wgpu::SurfaceError::Lost) => state.resize(state.size),
-                    // The system is out of memory, we should probably quit
-                    Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
-                    // All other errors (Outdated, Timeout) should be resolved by the next frame
-                    Err(e) => eprintln!("{:?}", e),
-                }
-            }
-            Event::MainEventsCleared => {
-                // RedrawRequested will only trigger once, unless we manually
-                // request it.
-                window.request_redraw();
-            }
-            Event::WindowEvent { event, window_id }
-                if window_id == window.id() && !state.input(&event) =>
-            {
-                match event {
-                    WindowEvent::CloseRequested
-                    | WindowEvent::KeyboardInput {
-                        input:
-                            KeyboardInput {
-                                state: ElementState::Pressed,
-                                virtual_keycode: Some(VirtualKeyCode::Escape),
-                                ..
-                            },
-                        ..
-                    } => {
-                        *control_flow = ControlFlow::Exit;
-                        return;
-                    }
-                    WindowEvent::Resized(physical_size) => {
-                        state.resize(physical_size); // TUTORIAL: *physical_size
-                    }
-                    WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-                        state.resize(*new_inner_size); // TUTORIAL: **new_inner_size
-                    }
-                    _ => (),
-                }
-            }
-            _ => (),
-        }
-    });
-}
-
 fn main() {
-    // pollster::block_on(run());
+    install_crash_report_panic_hook(CrashReportSettings::default());
+    // env_logger::init();
 
-    let mut app = App::new();
-    app.add_plugins(FlatEngineComplete).run();
+    FlatEngine::default_app().run();
 }
@@ -0,0 +1,644 @@
+use std::sync::Arc;
+
+use bevy_app::{CoreStage, Plugin};
+use bevy_ecs::{
+    entity::Entity,
+    prelude::Component,
+    query::Without,
+    schedule::ParallelSystemDescriptorCoercion,
+    system::{Commands, Query, Res, ResMut},
+};
+use bytemuck::{Pod, Zeroable};
+use cgmath::{Matrix4, Vector3, Zero};
+use repr_trait::C;
+
+use crate::{
+    camera::Camera,
+    color::Color,
+    render::{
+        mesh::{GpuMesh, Mesh},
+        prepare_frame_system,
+        resource::{
+            bind::{BindSlots, BindingSet, GpuUniform, StageLockedUniform, UniformBuffer},
+            buffer::{Indices, InstanceUnit, MeshVertex, Vertex},
+            pipeline::RenderPipeline,
+            shader::Shader,
+        },
+        targets::RenderTargets,
+        DepthConvention, DepthFormat, InstanceData,
+    },
+    time::Time,
+    transform::Transform,
+    util::{Refer, SampleRng, Store},
+    FlatSystemLabels, RenderStage,
+};
+
+/// CPU simulated particles drawn as camera facing quads in the main pass.
+///
+/// Spawn a [`ParticleEmitter`], its [`Particles`] are added on the next update.
+/// Emitters with a [`Transform`] emit from its translation.
+pub struct FlatParticlePlugin;
+impl Plugin for FlatParticlePlugin {
+    fn build(&self, app: &mut bevy_app::App) {
+        app.init_resource::<Option<ParticleRenderer>>()
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                simulate_particles_system.after(FlatSystemLabels::TransformPropagate),
+            )
+            .add_system_to_stage(
+                RenderStage::Prepare,
+                prepare_particles_system
+                    .label(FlatSystemLabels::RenderPrepare)
+                    .after(prepare_frame_system),
+            );
+    }
+}
+
+#[derive(Component, Debug, Clone, PartialEq)]
+pub struct ParticleEmitter {
+    /// Particles per second
+    pub rate: f32,
+    /// Emitted at once on the first step, e.g. an explosion with a `rate` of zero
+    pub burst: u32,
+    /// Seconds a particle lives
+    pub lifetime: f32,
+    pub initial_velocity: Vector3<f32>,
+    /// Each component of the initial velocity varies by up to `±spread`
+    pub spread: Vector3<f32>,
+    pub gravity: Vector3<f32>,
+    pub start_color: Color,
+    pub end_color: Color,
+    pub start_size: f32,
+    pub end_size: f32,
+    /// Emission stops at the cap until particles die
+    pub max_particles: u32,
+    /// Seconds of emission, `None` emits forever.
+    /// Once it stopped and the last particle died the emitter is dead and frees its buffers
+    pub duration: Option<f32>,
+    /// The same seed and steps give the same particles
+    pub seed: u64,
+}
+
+impl Default for ParticleEmitter {
+    fn default() -> Self {
+        Self {
+            rate: 20.0,
+            burst: 0,
+            lifetime: 1.0,
+            initial_velocity: Vector3::new(0.0, 2.0, 0.0),
+            spread: Vector3::new(0.5, 0.2, 0.5),
+            gravity: Vector3::new(0.0, -9.81, 0.0),
+            start_color: Color::WHITE,
+            end_color: Color::TRANSPARENT,
+            start_size: 0.1,
+            end_size: 0.05,
+            max_particles: 1000,
+            duration: None,
+            seed: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Particle {
+    pub position: Vector3<f32>,
+    pub velocity: Vector3<f32>,
+    /// Seconds since it was emitted
+    pub age: f32,
+}
+
+/// Live particles of a [`ParticleEmitter`]. Room for `max_particles` is reserved once,
+/// stepping does not allocate after that
+#[derive(Component, Debug, Clone)]
+pub struct Particles {
+    particles: Vec<Particle>,
+    // written by `step`, reused every frame
+    instances: Vec<ParticleInstance>,
+    rng: SampleRng,
+    // fraction of a particle carried over to the next step
+    pending: f32,
+    bursts: u32,
+    elapsed: f32,
+    started: bool,
+}
+
+impl Particles {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            particles: Vec::new(),
+            instances: Vec::new(),
+            rng: SampleRng(seed),
+            pending: 0.0,
+            bursts: 0,
+            elapsed: 0.0,
+            started: false,
+        }
+    }
+
+    pub fn particles(&self) -> &[Particle] {
+        &self.particles
+    }
+
+    /// One per live particle, as of the last step
+    pub fn instances(&self) -> &[ParticleInstance] {
+        &self.instances
+    }
+
+    /// Emits `count` more on the next step, within the cap. Revives a dead emitter
+    pub fn burst(&mut self, count: u32) {
+        self.bursts += count;
+    }
+
+    /// Emission stopped and every particle died
+    pub fn is_dead(&self, emitter: &ParticleEmitter) -> bool {
+        self.started
+            && self.bursts == 0
+            && self.particles.is_empty()
+            && emitter
+                .duration
+                .map_or(false, |duration| self.elapsed >= duration)
+    }
+
+    /// Ages and moves the particles by `dt` seconds, removes the dead ones
+    /// and emits new ones at `origin`
+    pub fn step(&mut self, emitter: &ParticleEmitter, origin: Vector3<f32>, dt: f32) {
+        let max = emitter.max_particles as usize;
+        // only grows when the cap is raised, and after the buffers were freed
+        if self.particles.capacity() < max {
+            self.particles.reserve_exact(max - self.particles.len());
+        }
+        if self.instances.capacity() < max {
+            self.instances.reserve_exact(max - self.instances.len());
+        }
+
+        self.particles.retain_mut(|particle| {
+            particle.age += dt;
+            if particle.age >= emitter.lifetime {
+                return false;
+            }
+            particle.velocity += emitter.gravity * dt;
+            particle.position += particle.velocity * dt;
+            true
+        });
+
+        let mut count = std::mem::take(&mut self.bursts);
+        if !self.started {
+            count += emitter.burst;
+            self.started = true;
+        }
+        let emitting = emitter
+            .duration
+            .map_or(true, |duration| self.elapsed < duration);
+        if emitting {
+            self.pending += emitter.rate * dt;
+            let whole = self.pending.floor();
+            self.pending -= whole;
+            count += whole as u32;
+        }
+        let room = max.saturating_sub(self.particles.len());
+        for _ in 0..(count as usize).min(room) {
+            let particle = self.emit(emitter, origin);
+            self.particles.push(particle);
+        }
+        self.elapsed += dt;
+
+        self.instances.clear();
+        self.instances.extend(
+            self.particles
+                .iter()
+                .map(|particle| ParticleInstance::new(emitter, particle)),
+        );
+    }
+
+    fn emit(&mut self, emitter: &ParticleEmitter, origin: Vector3<f32>) -> Particle {
+        let mut vary = |spread: f32| (self.rng.next_f32() * 2.0 - 1.0) * spread;
+        let velocity = emitter.initial_velocity
+            + Vector3::new(
+                vary(emitter.spread.x),
+                vary(emitter.spread.y),
+                vary(emitter.spread.z),
+            );
+        Particle {
+            position: origin,
+            velocity,
+            age: 0.0,
+        }
+    }
+
+    /// Releases the memory of a dead emitter, [`Particles::step`] reserves it again
+    pub fn free(&mut self) {
+        self.particles = Vec::new();
+        self.instances = Vec::new();
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, C, Pod, Zeroable)]
+pub struct ParticleInstance {
+    pub position: [f32; 3],
+    pub size: f32,
+    pub color: [f32; 4],
+}
+
+impl ParticleInstance {
+    /// Size and color interpolated over the lifetime
+    pub fn new(emitter: &ParticleEmitter, particle: &Particle) -> Self {
+        let t = (particle.age / emitter.lifetime).clamp(0.0, 1.0);
+        let lerp = |a: f32, b: f32| a + (b - a) * t;
+        let (start, end) = (emitter.start_color, emitter.end_color);
+        Self {
+            position: particle.position.into(),
+            size: lerp(emitter.start_size, emitter.end_size),
+            color: [
+                lerp(start.r, end.r),
+                lerp(start.g, end.g),
+                lerp(start.b, end.b),
+                lerp(start.a, end.a),
+            ],
+        }
+    }
+}
+
+impl InstanceUnit for ParticleInstance {
+    const ATTRIBUTES: &'static [wgpu::VertexAttribute] = &wgpu::vertex_attr_array![
+        5 => Float32x3,
+        6 => Float32,
+        7 => Float32x4,
+    ];
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, C, Pod, Zeroable)]
+pub struct BillboardCameraUniform {
+    pub view_proj: [[f32; 4]; 4],
+    /// World space axes of the view, `w` is unused. [`Text3d`](crate::text::world::Text3d)
+    /// puts the nearest depth in the one of `right`
+    pub right: [f32; 4],
+    pub up: [f32; 4],
+}
+impl GpuUniform for BillboardCameraUniform {}
+impl StageLockedUniform for BillboardCameraUniform {
+    const FORCE_STAGE: wgpu::ShaderStages = wgpu::ShaderStages::VERTEX;
+}
+
+impl From<&Camera> for BillboardCameraUniform {
+    fn from(camera: &Camera) -> Self {
+        // rows of the rotation part of the view matrix
+        let view: Matrix4<f32> = camera.view_matrix;
+        Self {
+            view_proj: (camera.projection_matrix * view).into(),
+            right: [view.x.x, view.y.x, view.z.x, 0.0],
+            up: [view.x.y, view.y.y, view.z.y, 0.0],
+        }
+    }
+}
+
+/// Shared by every emitter, created with the first device and main pass format
+pub struct ParticleRenderer {
+    camera: UniformBuffer<BillboardCameraUniform>,
+    pipeline: usize,
+    bind_group: usize,
+    quad: usize,
+    format: (wgpu::TextureFormat, DepthFormat, DepthConvention),
+}
+
+impl ParticleRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        depth_format: DepthFormat,
+        depth_convention: DepthConvention,
+        pipelines: &mut Store<RenderPipeline>,
+        bind_groups: &mut Store<wgpu::BindGroup>,
+        meshes: &mut Store<GpuMesh>,
+    ) -> Self {
+        let camera = UniformBuffer::new_init(
+            device,
+            BillboardCameraUniform::from(&Camera::default()),
+            Some("particles camera"),
+        );
+        let bind_group = bind_groups.insert((&camera).into_bind_group(device));
+        let pipeline = pipelines.insert(Self::create_pipeline(
+            device,
+            &camera,
+            format,
+            depth_format,
+            depth_convention,
+        ));
+        let quad = meshes.insert(GpuMesh::from_mesh(
+            &billboard_quad(),
+            device,
+            Some("particle quad"),
+        ));
+
+        Self {
+            camera,
+            pipeline,
+            bind_group,
+            quad,
+            format: (format, depth_format, depth_convention),
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        camera: &UniformBuffer<BillboardCameraUniform>,
+        format: wgpu::TextureFormat,
+        depth_format: DepthFormat,
+        depth_convention: DepthConvention,
+    ) -> RenderPipeline {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Billboard Bind Group Layout"),
+            entries: &camera.layout_desc().entries,
+        });
+        let module = device.create_shader_module(wgpu::include_wgsl!("../res/billboard.wgsl"));
+        let shader = Shader::with_final(
+            module,
+            vec![Vertex::layout(), ParticleInstance::layout()],
+            vec![Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        )
+        .with_depth_stencil(depth_format.0, Default::default())
+        .with_depth_convention(depth_convention)
+        // blended, a particle in front must not hide the ones drawn after it
+        .without_depth_write();
+        RenderPipeline::create_usual(
+            device,
+            &[&layout],
+            &shader,
+            wgpu::PrimitiveTopology::TriangleList,
+            Some("particles"),
+        )
+    }
+
+    /// Rebuilds the pipeline when the main pass format changed,
+    /// e.g. post-processing was toggled
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        camera: &Camera,
+        format: wgpu::TextureFormat,
+        depth_format: DepthFormat,
+        depth_convention: DepthConvention,
+        pipelines: &mut Store<RenderPipeline>,
+    ) {
+        self.camera
+            .update(queue, BillboardCameraUniform::from(camera));
+        if self.format == (format, depth_format, depth_convention) {
+            return;
+        }
+        self.format = (format, depth_format, depth_convention);
+        if let Some(pipeline) = pipelines.get_mut(self.pipeline) {
+            *pipeline =
+                Self::create_pipeline(device, &self.camera, format, depth_format, depth_convention);
+        }
+    }
+}
+
+/// Unit quad in the xy plane facing `+z`, the vertex shader turns it to the camera
+fn billboard_quad() -> Mesh<Vertex> {
+    let vertex = |x: f32, y: f32| Vertex {
+        position: [x, y, 0.0],
+        tex_coords: [x + 0.5, 0.5 - y],
+    };
+    Mesh::with_all(
+        wgpu::PrimitiveTopology::TriangleList,
+        vec![
+            vertex(-0.5, -0.5),
+            vertex(0.5, -0.5),
+            vertex(0.5, 0.5),
+            vertex(-0.5, 0.5),
+        ],
+        Some(Indices::U16(vec![0, 1, 2, 2, 3, 0])),
+    )
+}
+
+/// Steps every emitter by the frame time, new emitters get their [`Particles`]
+pub fn simulate_particles_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    new_emitters: Query<(Entity, &ParticleEmitter, Option<&Transform>), Without<Particles>>,
+    mut emitters: Query<(&ParticleEmitter, &mut Particles, Option<&Transform>)>,
+) {
+    let dt = time.delta_seconds();
+    let origin = |transform: Option<&Transform>| {
+        transform.map_or(Vector3::zero(), |transform| transform.translation)
+    };
+
+    for (emitter, mut particles, transform) in emitters.iter_mut() {
+        if particles.is_dead(emitter) {
+            continue;
+        }
+        particles.step(emitter, origin(transform), dt);
+    }
+    for (entity, emitter, transform) in new_emitters.iter() {
+        let mut particles = Particles::new(emitter.seed);
+        particles.step(emitter, origin(transform), dt);
+        commands.entity(entity).insert(particles);
+    }
+}
+
+/// Writes the live particles into the instance buffers of their emitters.
+/// Dead emitters lose their buffers and are no longer drawn
+pub fn prepare_particles_system(
+    mut commands: Commands,
+    (device, queue): (Option<Res<Arc<wgpu::Device>>>, Option<Res<wgpu::Queue>>),
+    camera: Option<Res<Camera>>,
+    targets: Res<Option<RenderTargets>>,
+    mut renderer: ResMut<Option<ParticleRenderer>>,
+    mut pipelines: ResMut<Store<RenderPipeline>>,
+    mut bind_groups: ResMut<Store<wgpu::BindGroup>>,
+    mut meshes: ResMut<Store<GpuMesh>>,
+    mut emitters: Query<(
+        Entity,
+        &ParticleEmitter,
+        &mut Particles,
+        Option<&mut InstanceData>,
+    )>,
+) {
+    let (device, queue) = match (device, queue) {
+        (Some(device), Some(queue)) => (device, queue),
+        _ => return,
+    };
+    let (format, depth_format, depth_convention) = match *targets {
+        Some(targets) => (
+            targets.main_pass_format(),
+            targets.depth_format,
+            targets.depth_convention,
+        ),
+        None => return,
+    };
+
+    let renderer = renderer.get_or_insert_with(|| {
+        ParticleRenderer::new(
+            &device,
+            format,
+            depth_format,
+            depth_convention,
+            &mut pipelines,
+            &mut bind_groups,
+            &mut meshes,
+        )
+    });
+    let default_camera = Camera::default();
+    renderer.prepare(
+        &device,
+        &queue,
+        camera.as_deref().unwrap_or(&default_camera),
+        format,
+        depth_format,
+        depth_convention,
+        &mut pipelines,
+    );
+
+    for (entity, emitter, mut particles, instance_data) in emitters.iter_mut() {
+        if particles.is_dead(emitter) {
+            if instance_data.is_some() {
+                particles.free();
+                commands
+                    .entity(entity)
+                    .remove::<InstanceData>()
+                    .remove::<Refer<RenderPipeline>>()
+                    .remove::<BindSlots>()
+                    .remove::<Refer<GpuMesh>>();
+            }
+            continue;
+        }
+
+        match instance_data {
+            Some(mut instance_data) if instance_data.capacity() >= emitter.max_particles => {
+                instance_data.write_units(&queue, particles.instances());
+            }
+            _ => {
+                let mut instance_data = InstanceData::with_capacity_of::<ParticleInstance>(
+                    &device,
+                    emitter.max_particles,
+                );
+                instance_data.write_units(&queue, particles.instances());
+                commands.entity(entity).insert_bundle((
+                    instance_data,
+                    Refer::<RenderPipeline>::new(renderer.pipeline),
+                    BindSlots::new().with(0, renderer.bind_group),
+                    Refer::<GpuMesh>::new(renderer.quad),
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Vector3;
+
+    use super::{ParticleEmitter, Particles};
+
+    const DT: f32 = 1.0 / 60.0;
+
+    fn fountain(seed: u64) -> ParticleEmitter {
+        ParticleEmitter {
+            rate: 120.0,
+            seed,
+            ..Default::default()
+        }
+    }
+
+    fn run(emitter: &ParticleEmitter, steps: usize) -> Particles {
+        let mut particles = Particles::new(emitter.seed);
+        for _ in 0..steps {
+            particles.step(emitter, Vector3::new(1.0, 2.0, 3.0), DT);
+        }
+        particles
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_particles() {
+        let a = run(&fountain(7), 90);
+        let b = run(&fountain(7), 90);
+        assert!(!a.particles().is_empty());
+        assert_eq!(a.particles(), b.particles());
+        assert_eq!(a.instances(), b.instances());
+
+        let c = run(&fountain(8), 90);
+        assert_ne!(a.particles(), c.particles());
+    }
+
+    #[test]
+    fn rate_accumulates_fractions() {
+        let emitter = ParticleEmitter {
+            rate: 4.0,
+            lifetime: 10.0,
+            ..Default::default()
+        };
+        let mut particles = Particles::new(0);
+        for _ in 0..8 {
+            particles.step(&emitter, Vector3::new(0.0, 0.0, 0.0), 0.125);
+        }
+        assert_eq!(particles.particles().len(), 4);
+    }
+
+    #[test]
+    fn bursts_are_capped_and_the_emitter_dies() {
+        let emitter = ParticleEmitter {
+            rate: 0.0,
+            burst: 50,
+            lifetime: 0.5,
+            max_particles: 30,
+            duration: Some(0.0),
+            ..Default::default()
+        };
+        let mut particles = Particles::new(0);
+        assert!(!particles.is_dead(&emitter));
+
+        particles.step(&emitter, Vector3::new(0.0, 0.0, 0.0), DT);
+        assert_eq!(particles.particles().len(), 30);
+        assert_eq!(particles.instances().len(), 30);
+
+        particles.step(&emitter, Vector3::new(0.0, 0.0, 0.0), 0.5);
+        assert!(particles.particles().is_empty());
+        assert!(particles.is_dead(&emitter));
+
+        particles.free();
+        particles.burst(5);
+        assert!(!particles.is_dead(&emitter));
+        particles.step(&emitter, Vector3::new(0.0, 0.0, 0.0), DT);
+        assert_eq!(particles.particles().len(), 5);
+    }
+
+    #[test]
+    fn stepping_100k_particles_does_not_allocate() {
+        let emitter = ParticleEmitter {
+            rate: 60_000.0,
+            burst: 100_000,
+            lifetime: 0.5,
+            max_particles: 100_000,
+            ..Default::default()
+        };
+        let mut particles = Particles::new(1);
+        particles.step(&emitter, Vector3::new(0.0, 0.0, 0.0), DT);
+        assert_eq!(particles.particles().len(), 100_000);
+
+        let buffers = |particles: &Particles| {
+            (
+                particles.particles.as_ptr(),
+                particles.particles.capacity(),
+                particles.instances.as_ptr(),
+                particles.instances.capacity(),
+            )
+        };
+        let before = buffers(&particles);
+        // past the lifetime, the first burst dies and the rate refills the cap
+        for _ in 0..60 {
+            particles.step(&emitter, Vector3::new(0.0, 0.0, 0.0), DT);
+            assert_eq!(buffers(&particles), before);
+        }
+        assert_eq!(particles.instances().len(), particles.particles().len());
+        assert!(particles
+            .particles()
+            .iter()
+            .all(|p| p.age < emitter.lifetime));
+    }
+}
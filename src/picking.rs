@@ -0,0 +1,464 @@
+use bevy_app::{CoreStage, Plugin};
+use bevy_ecs::{
+    entity::Entity,
+    event::{EventReader, EventWriter},
+    prelude::Component,
+    schedule::ParallelSystemDescriptorCoercion,
+    system::{Query, Res},
+};
+use cgmath::{
+    EuclideanSpace, InnerSpace, Matrix4, MetricSpace, Point3, SquareMatrix, Transform as _, Vector2,
+};
+
+use crate::{
+    camera::{Camera, Ray, ViewportToWorld},
+    input::{
+        capture::{InputCapture, InputCaptureSystem},
+        mouse::{MouseButton, MouseButtonInput},
+        ButtonState,
+    },
+    render::DepthConvention,
+    transform::Transform,
+    window::{WindowId, Windows},
+    FlatSystemLabels,
+};
+
+pub struct FlatPickingPlugin;
+impl Plugin for FlatPickingPlugin {
+    fn build(&self, app: &mut bevy_app::App) {
+        app.add_event::<EntityPicked>().add_system_to_stage(
+            CoreStage::PreUpdate,
+            picking_system
+                .after(FlatSystemLabels::InputCollect)
+                .after(InputCaptureSystem),
+        );
+    }
+}
+
+/// Model space bounds, entities need it along with a [`Transform`] to be pickable
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    /// `None` if `points` is empty
+    pub fn from_points(points: impl IntoIterator<Item = Point3<f32>>) -> Option<Self> {
+        let mut points = points.into_iter();
+        let first = points.next()?;
+        Some(points.fold(
+            Self {
+                min: first,
+                max: first,
+            },
+            |aabb, p| Self {
+                min: Point3::new(
+                    aabb.min.x.min(p.x),
+                    aabb.min.y.min(p.y),
+                    aabb.min.z.min(p.z),
+                ),
+                max: Point3::new(
+                    aabb.max.x.max(p.x),
+                    aabb.max.y.max(p.y),
+                    aabb.max.z.max(p.z),
+                ),
+            },
+        ))
+    }
+}
+
+/// Model space bounds that stay tight under rotation, kept next to the [`Aabb`].
+/// Frustum culling tests it before the box, level of detail measures to its surface
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Point3<f32>,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    /// Ritter's sphere, up to a few percent larger than the smallest one.
+    /// `None` if `points` is empty
+    pub fn from_points(points: impl IntoIterator<Item = Point3<f32>>) -> Option<Self> {
+        let points: Vec<Point3<f32>> = points.into_iter().collect();
+        let farthest = |from: Point3<f32>| {
+            points.iter().copied().fold(from, |far, p| {
+                if from.distance2(p) > from.distance2(far) {
+                    p
+                } else {
+                    far
+                }
+            })
+        };
+        let a = farthest(*points.first()?);
+        let b = farthest(a);
+
+        let mut center = a.midpoint(b);
+        let mut radius = a.distance(b) / 2.0;
+        for &p in &points {
+            let distance = center.distance(p);
+            if distance > radius {
+                let grown = (radius + distance) / 2.0;
+                center += (p - center) * ((grown - radius) / distance);
+                radius = grown;
+            }
+        }
+        // NOTE: exact for the final center, growing leaves points a rounding error outside
+        let radius = points
+            .iter()
+            .map(|p| center.distance(*p))
+            .fold(radius, f32::max);
+        Some(Self { center, radius })
+    }
+
+    /// Placed by `model_matrix`, the radius is scaled by the longest axis so the sphere
+    /// still bounds the points under rotation and any scale
+    pub fn transformed(&self, model_matrix: &Matrix4<f32>) -> Self {
+        let scale = [model_matrix.x, model_matrix.y, model_matrix.z]
+            .iter()
+            .map(|axis| axis.truncate().magnitude())
+            .fold(0.0, f32::max);
+        Self {
+            center: model_matrix.transform_point(self.center),
+            radius: self.radius * scale,
+        }
+    }
+
+    /// From `point` to the surface, 0.0 inside
+    pub fn distance_to(&self, point: Point3<f32>) -> f32 {
+        (point.distance(self.center) - self.radius).max(0.0)
+    }
+}
+
+/// Sent when the primary window is left clicked over an entity, unless the click went
+/// to the UI, see [`InputCapture`]
+#[derive(Debug, Clone)]
+pub struct EntityPicked {
+    pub entity: Entity,
+    /// Along the ray from the near plane
+    pub distance: f32,
+    pub point: Point3<f32>,
+}
+
+/// Distance along `ray` to where it enters `aabb` placed by `model_matrix`.
+///
+/// The ray is moved into model space instead of transforming the box, so rotated
+/// boxes stay tight. Returns 0.0 when the ray starts inside the box.
+pub fn ray_aabb_intersect(ray: &Ray, aabb: &Aabb, model_matrix: &Matrix4<f32>) -> Option<f32> {
+    let inverse_model = model_matrix.invert()?;
+    // NOTE: dir is not renormalized, distances stay in world units
+    let origin = inverse_model.transform_point(ray.origin);
+    let dir = inverse_model.transform_vector(ray.dir);
+
+    let mut t_min = f32::NEG_INFINITY;
+    let mut t_max = f32::INFINITY;
+    for axis in 0..3 {
+        if dir[axis] == 0.0 {
+            if origin[axis] < aabb.min[axis] || origin[axis] > aabb.max[axis] {
+                return None;
+            }
+            continue;
+        }
+        let t0 = (aabb.min[axis] - origin[axis]) / dir[axis];
+        let t1 = (aabb.max[axis] - origin[axis]) / dir[axis];
+        t_min = t_min.max(t0.min(t1));
+        t_max = t_max.min(t0.max(t1));
+    }
+
+    if t_max < t_min.max(0.0) {
+        return None;
+    }
+    Some(t_min.max(0.0))
+}
+
+/// Nearest hit along `ray`
+pub fn pick<'a>(
+    ray: &Ray,
+    candidates: impl IntoIterator<Item = (Entity, &'a Aabb, &'a Transform)>,
+) -> Option<EntityPicked> {
+    candidates
+        .into_iter()
+        .filter_map(|(entity, aabb, transform)| {
+            ray_aabb_intersect(ray, aabb, &transform.compute_matrix())
+                .map(|distance| (entity, distance))
+        })
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(entity, distance)| EntityPicked {
+            entity,
+            distance,
+            point: ray.at(distance),
+        })
+}
+
+pub fn picking_system(
+    camera: Option<Res<Camera>>,
+    config: Option<Res<wgpu::SurfaceConfiguration>>,
+    depth_convention: Option<Res<DepthConvention>>,
+    windows: Res<Windows>,
+    capture: Option<Res<InputCapture>>,
+    mut button_events: EventReader<MouseButtonInput>,
+    pickables: Query<(Entity, &Aabb, &Transform)>,
+    mut picked_events: EventWriter<EntityPicked>,
+) {
+    // NOTE: counted, not `any`, so the reader does not stop early and see the rest again
+    let clicks = button_events
+        .iter_with_id()
+        .filter(|(event, id)| {
+            event.button == MouseButton::Left
+                && matches!(event.state, ButtonState::Pressed)
+                && !capture
+                    .as_ref()
+                    .map_or(false, |capture| capture.button_consumed(*id))
+        })
+        .count();
+    if clicks == 0 {
+        return;
+    }
+    let (camera, config) = match (camera, config) {
+        (Some(camera), Some(config)) => (camera, config),
+        _ => return,
+    };
+    let ray = match cursor_ray(
+        &camera,
+        &config,
+        &windows,
+        depth_convention.map_or_else(Default::default, |convention| *convention),
+    ) {
+        Some(ray) => ray,
+        None => return,
+    };
+
+    if let Some(picked) = pick(&ray, pickables.iter()) {
+        picked_events.send(picked);
+    }
+}
+
+/// World space ray through the cursor of the primary window, `None` while the cursor is
+/// outside of it
+pub fn cursor_ray(
+    camera: &Camera,
+    config: &wgpu::SurfaceConfiguration,
+    windows: &Windows,
+    depth_convention: DepthConvention,
+) -> Option<Ray> {
+    let cursor_pos = windows.map.get(&WindowId::primary()).and_then(|window| {
+        window
+            .cursor_position()
+            .map(|position| position.to_physical(window.resolution().1, window.scale_factor()))
+    })?;
+
+    let viewport_size = Vector2::new(config.width as f32, config.height as f32);
+    ViewportToWorld::ray_from_cursor(
+        &camera.view_matrix,
+        &camera.projection_matrix,
+        cursor_pos,
+        viewport_size,
+        depth_convention,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_ecs::{
+        event::Events,
+        schedule::{Stage, SystemStage},
+        system::ResMut,
+        world::World,
+    };
+    use cgmath::{EuclideanSpace, Quaternion, Rad, Rotation3, Vector3};
+
+    use crate::{
+        camera::{CameraView, PerspectiveProjection, OPENGL_TO_WGPU_MATRIX},
+        render::mesh::primitive::create_unit_cube,
+        window::{
+            util::{LogicalVec2, PhysicalVec2},
+            Window, WindowDescriptor,
+        },
+    };
+
+    use super::*;
+
+    fn unit_cube() -> Aabb {
+        Aabb {
+            min: Point3::new(-0.5, -0.5, -0.5),
+            max: Point3::new(0.5, 0.5, 0.5),
+        }
+    }
+
+    fn camera() -> Camera {
+        Camera {
+            view_matrix: CameraView {
+                eye: Point3::new(0.0, 0.0, 5.0),
+                target: Point3::origin(),
+                up: Vector3::unit_y(),
+            }
+            .build_view_matrix(),
+            projection_matrix: OPENGL_TO_WGPU_MATRIX
+                * PerspectiveProjection::default().build_projection_matrix(),
+        }
+    }
+
+    fn center_ray() -> Ray {
+        let camera = camera();
+        let viewport = Vector2::new(640.0, 480.0);
+        ViewportToWorld::ray_from_cursor(
+            &camera.view_matrix,
+            &camera.projection_matrix,
+            PhysicalVec2(viewport / 2.0),
+            viewport,
+            DepthConvention::Standard,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn ray_hits_transformed_box() {
+        let ray = center_ray();
+        let cube = unit_cube();
+
+        // origin is on the near plane at z = 4.9
+        let distance = ray_aabb_intersect(&ray, &cube, &Matrix4::identity()).unwrap();
+        assert!((distance - 4.4).abs() < 1e-4);
+
+        let scaled = Matrix4::from_scale(2.0);
+        let distance = ray_aabb_intersect(&ray, &cube, &scaled).unwrap();
+        assert!((distance - 3.9).abs() < 1e-4);
+
+        let aside = Matrix4::from_translation(Vector3::new(2.0, 0.0, 0.0));
+        assert_eq!(ray_aabb_intersect(&ray, &cube, &aside), None);
+
+        let behind = Matrix4::from_translation(Vector3::new(0.0, 0.0, 10.0));
+        assert_eq!(ray_aabb_intersect(&ray, &cube, &behind), None);
+    }
+
+    #[test]
+    fn nearest_entity_is_picked() {
+        let mut world = World::new();
+        let far = world.spawn().id();
+        let near = world.spawn().id();
+        let aside = world.spawn().id();
+        let cube = unit_cube();
+        let far_transform = Transform::from_translation(Vector3::new(0.0, 0.0, -3.0));
+        let near_transform = Transform::default();
+        let aside_transform = Transform::from_translation(Vector3::new(3.0, 0.0, 2.0));
+
+        let picked = pick(
+            &center_ray(),
+            [
+                (far, &cube, &far_transform),
+                (aside, &cube, &aside_transform),
+                (near, &cube, &near_transform),
+            ],
+        )
+        .unwrap();
+        assert_eq!(picked.entity, near);
+        assert!((picked.point.z - 0.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn aabb_from_points() {
+        let aabb = Aabb::from_points([
+            Point3::new(1.0, -2.0, 0.0),
+            Point3::new(-1.0, 3.0, 0.5),
+            Point3::new(0.0, 0.0, -4.0),
+        ])
+        .unwrap();
+        assert_eq!(aabb.min, Point3::new(-1.0, -2.0, -4.0));
+        assert_eq!(aabb.max, Point3::new(1.0, 3.0, 0.5));
+        assert_eq!(Aabb::from_points(std::iter::empty()), None);
+    }
+
+    #[test]
+    fn bounding_sphere_of_the_unit_cube() {
+        let sphere = create_unit_cube().compute_bounding_sphere().unwrap();
+        assert!((sphere.center - Point3::origin()).magnitude() < 1e-4);
+        assert!((sphere.radius - 3f32.sqrt() / 2.0).abs() < 1e-4);
+        assert_eq!(BoundingSphere::from_points(std::iter::empty()), None);
+
+        // rotation keeps the radius, the largest scale axis grows it
+        let transform = Transform {
+            translation: Vector3::new(1.0, 2.0, 3.0),
+            scale: Vector3::new(1.0, 2.0, 0.5),
+            rotation: Quaternion::from_angle_y(Rad(0.7)),
+        };
+        let moved = sphere.transformed(&transform.compute_matrix());
+        assert!((moved.center - Point3::new(1.0, 2.0, 3.0)).magnitude() < 1e-4);
+        assert!((moved.radius - 3f32.sqrt()).abs() < 1e-4);
+        assert!(
+            (moved.distance_to(Point3::new(1.0, 2.0, 13.0)) - (10.0 - 3f32.sqrt())).abs() < 1e-4
+        );
+        assert_eq!(moved.distance_to(Point3::new(1.0, 2.0, 3.0)), 0.0);
+    }
+
+    // a cube under the cursor in the middle of the primary window, left clicked
+    fn clicked_world() -> World {
+        let mut world = World::new();
+        world.insert_resource(camera());
+        world.insert_resource(wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: wgpu::TextureFormat::Bgra8UnormSrgb,
+            width: 640,
+            height: 480,
+            present_mode: wgpu::PresentMode::Fifo,
+        });
+        let mut windows = Windows::default();
+        let mut window = Window::new(WindowId::primary(), WindowDescriptor::default());
+        window.update_resolution((640, 480));
+        window.update_cursor_position(Some(LogicalVec2::new(320.0, 240.0)));
+        windows.add(window);
+        world.insert_resource(windows);
+        world.insert_resource(InputCapture::default());
+        world.insert_resource(Events::<EntityPicked>::default());
+        let mut button_events = Events::<MouseButtonInput>::default();
+        button_events.send(MouseButtonInput {
+            button: MouseButton::Left,
+            state: ButtonState::Pressed,
+        });
+        world.insert_resource(button_events);
+        world
+            .spawn()
+            .insert(unit_cube())
+            .insert(Transform::default());
+        world
+    }
+
+    fn picked_count(world: &World) -> usize {
+        let picked = world.resource::<Events<EntityPicked>>();
+        picked.get_reader().iter(picked).count()
+    }
+
+    #[test]
+    fn clicks_captured_by_the_ui_do_not_pick() {
+        let mut world = clicked_world();
+        SystemStage::parallel()
+            .with_system(picking_system.after(InputCaptureSystem))
+            .run(&mut world);
+        assert_eq!(picked_count(&world), 1);
+
+        // the ui runs before picking, whatever the order the systems were added in
+        fn panel_under_cursor(mut capture: ResMut<InputCapture>) {
+            capture.capture_pointer(true);
+        }
+        let mut world = clicked_world();
+        SystemStage::parallel()
+            .with_system(picking_system.after(InputCaptureSystem))
+            .with_system(panel_under_cursor.label(InputCaptureSystem))
+            .run(&mut world);
+        assert_eq!(picked_count(&world), 0);
+
+        fn button_under_cursor(
+            mut button_events: EventReader<MouseButtonInput>,
+            mut capture: ResMut<InputCapture>,
+        ) {
+            for (_, id) in button_events.iter_with_id() {
+                capture.consume_button(id);
+            }
+        }
+        let mut world = clicked_world();
+        SystemStage::parallel()
+            .with_system(picking_system.after(InputCaptureSystem))
+            .with_system(button_under_cursor.label(InputCaptureSystem))
+            .run(&mut world);
+        assert_eq!(picked_count(&world), 0);
+    }
+}
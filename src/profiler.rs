@@ -0,0 +1,268 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::Mutex,
+    thread::{self, ThreadId},
+    time::{Duration, Instant},
+};
+
+use bevy_ecs::system::ResMut;
+
+/// Wall-clock time of the engine systems per named span, kept for the last frames.
+///
+/// Systems hold a [`Profiler::scope`] guard while they work. Time spent in a nested scope
+/// counts for the innermost span only, spans opened more than once in a frame add up.
+/// Disabled by default, then scopes do nothing but check `enabled`.
+pub struct Profiler {
+    /// Checked when a scope opens, scopes open while it is toggled still close
+    pub enabled: bool,
+    frames: usize,
+    state: Mutex<ProfilerState>,
+    // span times of the last `frames` frames that had any, oldest first
+    history: VecDeque<Vec<(&'static str, Duration)>>,
+}
+
+#[derive(Default)]
+struct ProfilerState {
+    open: Vec<OpenScope>,
+    // of the current frame
+    spans: Vec<(&'static str, Duration)>,
+}
+
+struct OpenScope {
+    name: &'static str,
+    // scopes nest per thread, systems run in parallel
+    thread: ThreadId,
+    start: Instant,
+    // spent in the nested scopes
+    children: Duration,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_FRAMES)
+    }
+}
+
+impl Profiler {
+    pub const DEFAULT_FRAMES: usize = 120;
+
+    /// Disabled, reports over the last `frames` frames
+    pub fn new(frames: usize) -> Self {
+        Self {
+            enabled: false,
+            frames: frames.max(1),
+            state: Default::default(),
+            history: VecDeque::with_capacity(frames.max(1)),
+        }
+    }
+
+    /// Records the time until the guard is dropped under `name`
+    pub fn scope(&self, name: &'static str) -> ProfileScope<'_> {
+        if !self.enabled {
+            return ProfileScope { profiler: None };
+        }
+        self.open(name, Instant::now());
+        ProfileScope {
+            profiler: Some(self),
+        }
+    }
+
+    fn open(&self, name: &'static str, now: Instant) {
+        self.state.lock().unwrap().open.push(OpenScope {
+            name,
+            thread: thread::current().id(),
+            start: now,
+            children: Duration::ZERO,
+        });
+    }
+
+    /// Closes the innermost scope of the current thread
+    fn close(&self, now: Instant) {
+        let mut state = self.state.lock().unwrap();
+        let thread = thread::current().id();
+        let index = match state.open.iter().rposition(|scope| scope.thread == thread) {
+            Some(index) => index,
+            None => return,
+        };
+        let scope = state.open.remove(index);
+        let elapsed = now.saturating_duration_since(scope.start);
+        let own = elapsed.saturating_sub(scope.children);
+        match state.spans.iter_mut().find(|(name, _)| *name == scope.name) {
+            Some((_, total)) => *total += own,
+            None => state.spans.push((scope.name, own)),
+        }
+        if let Some(parent) = state
+            .open
+            .iter_mut()
+            .rev()
+            .find(|scope| scope.thread == thread)
+        {
+            parent.children += elapsed;
+        }
+    }
+
+    /// Moves the spans of the current frame to the history,
+    /// scopes still open are recorded in the next frame
+    pub fn finish_frame(&mut self) {
+        let spans = std::mem::take(&mut self.state.get_mut().unwrap().spans);
+        if spans.is_empty() {
+            return;
+        }
+        if self.history.len() == self.frames {
+            self.history.pop_front();
+        }
+        self.history.push_back(spans);
+    }
+
+    /// Frames in the history, at most the `frames` the profiler was created with
+    pub fn frame_count(&self) -> usize {
+        self.history.len()
+    }
+
+    /// Per span over the frames it was recorded in, by name
+    pub fn report(&self) -> Vec<SpanReport> {
+        let mut samples: BTreeMap<&'static str, Vec<Duration>> = BTreeMap::new();
+        for spans in &self.history {
+            for (name, duration) in spans {
+                samples.entry(*name).or_default().push(*duration);
+            }
+        }
+        samples
+            .into_iter()
+            .map(|(name, samples)| SpanReport::new(name, samples))
+            .collect()
+    }
+}
+
+/// Closes its scope when dropped, see [`Profiler::scope`]
+#[must_use = "the scope closes when the guard is dropped"]
+pub struct ProfileScope<'a> {
+    // None while the profiler is disabled
+    profiler: Option<&'a Profiler>,
+}
+
+impl Drop for ProfileScope<'_> {
+    fn drop(&mut self) {
+        if let Some(profiler) = self.profiler {
+            profiler.close(Instant::now());
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpanReport {
+    pub name: &'static str,
+    pub mean: Duration,
+    /// Nearest rank, the slowest 5% of the frames took longer
+    pub p95: Duration,
+    /// Recorded in, of the [`Profiler::frame_count`]
+    pub frames: usize,
+}
+
+impl SpanReport {
+    /// `samples` are the times of the span, one per frame
+    pub fn new(name: &'static str, mut samples: Vec<Duration>) -> Self {
+        if samples.is_empty() {
+            return Self {
+                name,
+                mean: Duration::ZERO,
+                p95: Duration::ZERO,
+                frames: 0,
+            };
+        }
+        samples.sort_unstable();
+        let frames = samples.len();
+        let sum: Duration = samples.iter().sum();
+        let rank = (frames * 95 + 99) / 100;
+        Self {
+            name,
+            mean: sum / frames as u32,
+            p95: samples[rank - 1],
+            frames,
+        }
+    }
+}
+
+pub fn finish_profiler_frame_system(mut profiler: ResMut<Profiler>) {
+    profiler.finish_frame();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{Profiler, SpanReport};
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn mean_and_p95_of_the_samples() {
+        let report = SpanReport::new("span", (1..=100).rev().map(ms).collect());
+        assert_eq!(report.mean, Duration::from_micros(50_500));
+        assert_eq!(report.p95, ms(95));
+        assert_eq!(report.frames, 100);
+
+        // 95% of 10 rounds up to the slowest
+        let report = SpanReport::new("span", (1..=10).map(ms).collect());
+        assert_eq!(report.p95, ms(10));
+        let report = SpanReport::new("span", vec![ms(7)]);
+        assert_eq!((report.mean, report.p95), (ms(7), ms(7)));
+        assert_eq!(SpanReport::new("span", Vec::new()).frames, 0);
+    }
+
+    #[test]
+    fn nested_scopes_count_for_the_innermost_span() {
+        let mut profiler = Profiler::new(4);
+        let start = Instant::now();
+        // outer 0..10, inner 2..5, innermost 3..4, inner again 6..7
+        profiler.open("outer", start);
+        profiler.open("inner", start + ms(2));
+        profiler.open("innermost", start + ms(3));
+        profiler.close(start + ms(4));
+        profiler.close(start + ms(5));
+        profiler.open("inner", start + ms(6));
+        profiler.close(start + ms(7));
+        profiler.close(start + ms(10));
+        profiler.finish_frame();
+
+        let report = profiler.report();
+        let mean = |name| report.iter().find(|span| span.name == name).unwrap().mean;
+        assert_eq!(report.len(), 3);
+        assert_eq!(mean("outer"), ms(6));
+        assert_eq!(mean("inner"), ms(3));
+        assert_eq!(mean("innermost"), ms(1));
+    }
+
+    #[test]
+    fn history_keeps_the_last_frames() {
+        let mut profiler = Profiler::new(2);
+        let start = Instant::now();
+        for i in 1..=3 {
+            profiler.open("frame", start);
+            profiler.close(start + ms(i));
+            profiler.finish_frame();
+        }
+        // frames without spans are not kept
+        profiler.finish_frame();
+
+        assert_eq!(profiler.frame_count(), 2);
+        let report = profiler.report();
+        assert_eq!(report[0].frames, 2);
+        assert_eq!(report[0].mean, Duration::from_micros(2_500));
+    }
+
+    #[test]
+    fn disabled_scopes_record_nothing() {
+        let mut profiler = Profiler::new(2);
+        drop(profiler.scope("off"));
+        profiler.finish_frame();
+        assert!(profiler.report().is_empty());
+
+        profiler.enabled = true;
+        drop(profiler.scope("on"));
+        profiler.finish_frame();
+        assert_eq!(profiler.report()[0].name, "on");
+    }
+}
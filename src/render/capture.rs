@@ -0,0 +1,350 @@
+use std::sync::{
+    mpsc::{self, Receiver, Sender},
+    Arc, Mutex,
+};
+
+use bevy_ecs::system::{Res, ResMut};
+
+use super::{offscreen::OffscreenTarget, CurrentFrame, FrameEncoders};
+
+/// Copies rendered frames back to the CPU without blocking the frame, e.g. for recording.
+///
+/// Frame `i` is copied into staging buffer `i % RING_SIZE` before it is presented and mapped
+/// right after submission, a later frame delivers it once the mapping resolved. When that
+/// buffer is still in flight the frame is dropped and counted instead of waited for.
+/// Frames arrive in order through the receiver returned by [`FrameCapture::start`],
+/// usually read by an encoder thread.
+#[derive(Default)]
+pub struct FrameCapture {
+    frames: Option<Mutex<Sender<CapturedFrame>>>,
+    ring: Option<(ReadbackLayout, Ring<wgpu::Buffer>)>,
+    next_frame: u64,
+    stats: CaptureStats,
+}
+
+impl FrameCapture {
+    pub const RING_SIZE: usize = 3;
+
+    /// Frames in flight from a previous capture are discarded
+    pub fn start(&mut self) -> Receiver<CapturedFrame> {
+        let (frames, receiver) = mpsc::channel();
+        self.frames = Some(Mutex::new(frames));
+        self.ring = None;
+        self.next_frame = 0;
+        self.stats = CaptureStats::default();
+        receiver
+    }
+
+    /// Closes the channel, frames still in flight are not delivered
+    pub fn stop(&mut self) {
+        self.frames = None;
+        self.ring = None;
+    }
+
+    pub fn is_capturing(&self) -> bool {
+        self.frames.is_some()
+    }
+
+    pub fn stats(&self) -> CaptureStats {
+        self.stats
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CaptureStats {
+    pub delivered: u64,
+    /// The ring was full or the mapping failed
+    pub dropped: u64,
+}
+
+pub struct CapturedFrame {
+    /// Frames since the capture started, dropped ones leave a gap
+    pub index: u64,
+    pub width: u32,
+    pub height: u32,
+    /// Of the surface, e.g. BGRA
+    pub format: wgpu::TextureFormat,
+    /// Tightly packed rows
+    pub pixels: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ReadbackLayout {
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+}
+
+impl ReadbackLayout {
+    fn unpadded_bytes_per_row(&self) -> u32 {
+        self.format.describe().block_size as u32 * self.width
+    }
+
+    fn padded_bytes_per_row(&self) -> u32 {
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        (self.unpadded_bytes_per_row() + align - 1) / align * align
+    }
+
+    fn buffer_size(&self) -> wgpu::BufferAddress {
+        (self.padded_bytes_per_row() * self.height) as wgpu::BufferAddress
+    }
+}
+
+// set by the map_async callback
+type MapResult = Arc<Mutex<Option<bool>>>;
+
+enum SlotState {
+    Free,
+    // the copy is recorded, mapped once submitted
+    Copied(u64),
+    Mapping(u64, MapResult),
+}
+
+/// Staging buffers of `FrameCapture`, generic so it can be tested without a device
+struct Ring<B> {
+    slots: Vec<(B, SlotState)>,
+}
+
+impl<B> Ring<B> {
+    fn new(buffers: impl IntoIterator<Item = B>) -> Self {
+        Self {
+            slots: buffers
+                .into_iter()
+                .map(|buffer| (buffer, SlotState::Free))
+                .collect(),
+        }
+    }
+
+    /// The buffer to copy `frame` into, `None` if it is still in flight
+    fn acquire(&mut self, frame: u64) -> Option<&B> {
+        let len = self.slots.len() as u64;
+        let (buffer, state) = &mut self.slots[(frame % len) as usize];
+        match state {
+            SlotState::Free => {
+                *state = SlotState::Copied(frame);
+                Some(buffer)
+            }
+            _ => None,
+        }
+    }
+
+    /// Called after the copies are submitted, `map` requests the mapping
+    fn request_maps(&mut self, mut map: impl FnMut(&B, MapResult)) {
+        for (buffer, state) in &mut self.slots {
+            if let SlotState::Copied(frame) = *state {
+                let result = MapResult::default();
+                map(buffer, result.clone());
+                *state = SlotState::Mapping(frame, result);
+            }
+        }
+    }
+
+    /// Hands out resolved mappings oldest first, stops at the first unresolved one so
+    /// frames stay in order. `f` gets whether the mapping succeeded.
+    fn consume(&mut self, mut f: impl FnMut(u64, &B, bool)) {
+        loop {
+            let oldest = self
+                .slots
+                .iter_mut()
+                .filter_map(|(buffer, state)| {
+                    let frame = match state {
+                        SlotState::Free => return None,
+                        SlotState::Copied(frame) | SlotState::Mapping(frame, _) => *frame,
+                    };
+                    Some((frame, buffer, state))
+                })
+                .min_by_key(|(frame, _, _)| *frame);
+            let (frame, buffer, state) = match oldest {
+                Some(oldest) => oldest,
+                None => return,
+            };
+            let mapped = match state {
+                SlotState::Mapping(_, result) => *result.lock().unwrap(),
+                _ => None,
+            };
+            match mapped {
+                Some(mapped) => {
+                    f(frame, buffer, mapped);
+                    *state = SlotState::Free;
+                }
+                None => return,
+            }
+        }
+    }
+}
+
+/// Records the copy of the frame, in `RenderStage::Present` before it is submitted and
+/// after the debug overlay is drawn, captures include it while it is shown
+pub fn capture_frame_system(
+    device: Option<Res<Arc<wgpu::Device>>>,
+    frame: Res<Option<CurrentFrame>>,
+    offscreen: Option<Res<OffscreenTarget>>,
+    mut encoders: ResMut<FrameEncoders>,
+    mut capture: ResMut<FrameCapture>,
+) {
+    let (device, frame) = match (device, frame.as_ref().as_ref()) {
+        (Some(device), Some(frame)) if capture.is_capturing() => (device, frame),
+        _ => return,
+    };
+    let texture = match frame.texture(offscreen.as_deref()) {
+        Some(texture) => texture,
+        None => return,
+    };
+    let layout = ReadbackLayout {
+        width: frame.width,
+        height: frame.height,
+        format: frame.format,
+    };
+
+    let capture = &mut *capture;
+    if capture.ring.as_ref().map(|(current, _)| *current) != Some(layout) {
+        // NOTE: frames in flight at the old size are lost
+        let buffers = (0..FrameCapture::RING_SIZE).map(|_| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Frame Capture Buffer"),
+                size: layout.buffer_size(),
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            })
+        });
+        capture.ring = Some((layout, Ring::new(buffers)));
+    }
+    let (_, ring) = capture.ring.as_mut().unwrap();
+
+    let index = capture.next_frame;
+    capture.next_frame += 1;
+    let buffer = match ring.acquire(index) {
+        Some(buffer) => buffer,
+        None => {
+            capture.stats.dropped += 1;
+            return;
+        }
+    };
+
+    encoders.encoder(&device).copy_texture_to_buffer(
+        texture.as_image_copy(),
+        wgpu::ImageCopyBuffer {
+            buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(layout.padded_bytes_per_row()),
+                rows_per_image: std::num::NonZeroU32::new(layout.height),
+            },
+        },
+        wgpu::Extent3d {
+            width: layout.width,
+            height: layout.height,
+            depth_or_array_layers: 1,
+        },
+    );
+}
+
+/// Maps the buffers copied into this frame, runs after the frame is submitted
+pub fn map_captured_frames_system(mut capture: ResMut<FrameCapture>) {
+    if let Some((_, ring)) = &mut capture.ring {
+        ring.request_maps(|buffer, result| {
+            buffer
+                .slice(..)
+                .map_async(wgpu::MapMode::Read, move |mapped| {
+                    *result.lock().unwrap() = Some(mapped.is_ok());
+                });
+        });
+    }
+}
+
+/// Polls the device without waiting and delivers the frames whose mapping resolved,
+/// runs early in the frame
+pub fn deliver_captured_frames_system(
+    device: Option<Res<Arc<wgpu::Device>>>,
+    mut capture: ResMut<FrameCapture>,
+) {
+    let device = match device {
+        Some(device) => device,
+        None => return,
+    };
+    let capture = &mut *capture;
+    let (layout, ring) = match &mut capture.ring {
+        Some(ring) => ring,
+        None => return,
+    };
+    device.poll(wgpu::Maintain::Poll);
+
+    let (frames, stats) = (&capture.frames, &mut capture.stats);
+    let mut disconnected = false;
+    ring.consume(|index, buffer, mapped| {
+        if !mapped {
+            stats.dropped += 1;
+            return;
+        }
+        let unpadded_bytes_per_row = layout.unpadded_bytes_per_row() as usize;
+        let pixels = buffer
+            .slice(..)
+            .get_mapped_range()
+            .chunks(layout.padded_bytes_per_row() as usize)
+            .flat_map(|row| &row[..unpadded_bytes_per_row])
+            .copied()
+            .collect();
+        buffer.unmap();
+
+        let frame = CapturedFrame {
+            index,
+            width: layout.width,
+            height: layout.height,
+            format: layout.format,
+            pixels,
+        };
+        match frames {
+            Some(frames) if frames.lock().unwrap().send(frame).is_ok() => stats.delivered += 1,
+            _ => disconnected = true,
+        }
+    });
+    if disconnected {
+        log::warn!("Frame capture receiver was dropped, stopping the capture");
+        capture.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Ring;
+
+    #[test]
+    fn frames_are_consumed_in_order_once_mapped() {
+        let buffers = ["a", "b", "c"];
+        let mut ring = Ring::new(buffers);
+        let mut maps = Vec::new();
+
+        for frame in 0..3 {
+            assert_eq!(ring.acquire(frame), Some(&buffers[frame as usize]));
+        }
+        ring.request_maps(|_, result| maps.push(result));
+        // the ring is full, frame 3 would go into "a" which is still mapping
+        assert_eq!(ring.acquire(3), None);
+
+        let mut consumed = Vec::new();
+        // resolved out of order, nothing goes before frame 0 is mapped
+        *maps[2].lock().unwrap() = Some(true);
+        *maps[1].lock().unwrap() = Some(true);
+        ring.consume(|frame, buffer, mapped| consumed.push((frame, *buffer, mapped)));
+        assert!(consumed.is_empty());
+
+        *maps[0].lock().unwrap() = Some(false);
+        ring.consume(|frame, buffer, mapped| consumed.push((frame, *buffer, mapped)));
+        assert_eq!(consumed, [(0, "a", false), (1, "b", true), (2, "c", true)]);
+
+        // the slots are free again
+        assert_eq!(ring.acquire(4), Some(&"b"));
+        assert_eq!(ring.acquire(5), Some(&"c"));
+        ring.request_maps(|_, result| maps.push(result));
+        // copied but not submitted yet
+        assert_eq!(ring.acquire(6), Some(&"a"));
+
+        consumed.clear();
+        *maps[4].lock().unwrap() = Some(true);
+        ring.consume(|frame, buffer, mapped| consumed.push((frame, *buffer, mapped)));
+        assert!(consumed.is_empty());
+        *maps[3].lock().unwrap() = Some(true);
+        ring.consume(|frame, buffer, mapped| consumed.push((frame, *buffer, mapped)));
+        assert_eq!(consumed, [(4, "b", true), (5, "c", true)]);
+    }
+}
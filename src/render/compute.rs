@@ -0,0 +1,168 @@
+use std::sync::Arc;
+
+use bevy_ecs::{
+    prelude::Component,
+    system::{Query, Res},
+};
+use bytemuck::{Pod, Zeroable};
+use repr_trait::C;
+
+use crate::util::{Refer, Store};
+
+use super::{
+    resource::{
+        bind::{BindSlots, BindingSet, GpuUniform, StorageBuffer, UniformBuffer},
+        buffer::{Instance, InstanceRaw},
+        pipeline::ComputePipeline,
+        shader::ComputeShader,
+    },
+    InstanceData,
+};
+
+#[derive(Component)]
+pub struct ComputeDispatch {
+    pub workgroups: (u32, u32, u32),
+}
+
+impl ComputeDispatch {
+    pub fn linear(invocations: u32, workgroup_size: u32) -> Self {
+        Self {
+            workgroups: ((invocations + workgroup_size - 1) / workgroup_size, 1, 1),
+        }
+    }
+}
+
+/// Runs in RenderStage::Compute, before anything reads the results in RenderStage::MainPass.
+/// Dispatches whose pipeline or bind groups are not there (yet) are skipped
+pub fn compute_system(
+    device: Option<Res<Arc<wgpu::Device>>>,
+    queue: Option<Res<wgpu::Queue>>,
+    pipelines: Res<Store<ComputePipeline>>,
+    bind_groups: Res<Store<wgpu::BindGroup>>,
+    dispatches: Query<(
+        &Refer<ComputePipeline>,
+        &BindSlots,
+        &ComputeDispatch,
+    )>,
+) {
+    let (device, queue) = match (device, queue) {
+        (Some(device), Some(queue)) => (device, queue),
+        _ => return,
+    };
+    if dispatches.is_empty() {
+        return;
+    }
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Compute Encoder"),
+    });
+
+    {
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Compute Pass"),
+        });
+
+        for (pipeline, binds, dispatch) in dispatches.iter() {
+            let pipeline = match pipeline.get(&pipelines) {
+                Some(pipeline) => pipeline,
+                None => continue,
+            };
+            let groups: Option<Vec<_>> = binds
+                .iter()
+                .map(|(group, bind)| Some((group, bind_groups.get(bind)?)))
+                .collect();
+            let groups = match groups {
+                Some(groups) => groups,
+                None => continue,
+            };
+            compute_pass.set_pipeline(&pipeline.0);
+            for (group, bind_group) in groups {
+                compute_pass.set_bind_group(group, bind_group, &[]);
+            }
+            let (x, y, z) = dispatch.workgroups;
+            compute_pass.dispatch_workgroups(x, y, z);
+        }
+    } // drop(compute_pass) <- mut borrow encoder
+
+    queue.submit(std::iter::once(encoder.finish()));
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, C, Pod, Zeroable)]
+pub struct ParticleParams {
+    pub time: [f32; 4], // x: delta seconds
+}
+impl GpuUniform for ParticleParams {}
+
+/// Example: `res/particles.wgsl` moves every instance by its velocity and
+/// the same buffer is then read as the instance buffer by the render pass.
+pub struct ParticleSimulation {
+    pub pipeline: ComputePipeline,
+    pub bind_group: wgpu::BindGroup,
+    pub dispatch: ComputeDispatch,
+    params: UniformBuffer<ParticleParams>,
+}
+
+impl ParticleSimulation {
+    const WORKGROUP_SIZE: u32 = 64;
+
+    pub fn new(
+        device: &wgpu::Device,
+        shader: &ComputeShader,
+        instances: &[Instance],
+        velocities: &[[f32; 4]],
+    ) -> (Self, InstanceData) {
+        assert_eq!(instances.len(), velocities.len());
+
+        let raw: Vec<InstanceRaw> = instances.iter().map(Instance::to_raw).collect();
+        let instance_storage = StorageBuffer::new_init(
+            device,
+            wgpu::ShaderStages::COMPUTE,
+            false,
+            &raw,
+            wgpu::BufferUsages::VERTEX,
+        );
+        let velocity_storage = StorageBuffer::new_init(
+            device,
+            wgpu::ShaderStages::COMPUTE,
+            true,
+            velocities,
+            wgpu::BufferUsages::empty(),
+        );
+        let params = UniformBuffer::new_init_at(
+            device,
+            wgpu::ShaderStages::COMPUTE,
+            ParticleParams { time: [0.0; 4] },
+        );
+
+        let binding_set = (&instance_storage, &velocity_storage, &params);
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Particle Bind Group Layout"),
+            entries: &binding_set.layout_desc().entries,
+        });
+        let bind_group = binding_set.into_bind_group(device);
+        let pipeline = ComputePipeline::from_shader(device, &[&bind_group_layout], shader);
+
+        let count = instance_storage.len() as u32;
+        let instance_data = InstanceData::new(instance_storage.into_buffer(), count);
+
+        (
+            Self {
+                pipeline,
+                bind_group,
+                dispatch: ComputeDispatch::linear(count, Self::WORKGROUP_SIZE),
+                params,
+            },
+            instance_data,
+        )
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue, delta_seconds: f32) {
+        self.params.update(
+            queue,
+            ParticleParams {
+                time: [delta_seconds, 0.0, 0.0, 0.0],
+            },
+        );
+    }
+}
@@ -0,0 +1,373 @@
+use std::sync::Arc;
+
+use bevy_ecs::{
+    system::{Command, Commands, Res, ResMut},
+    world::World,
+};
+use bytemuck::{Pod, Zeroable};
+use cgmath::Matrix4;
+use repr_trait::C;
+
+use crate::camera::Camera;
+
+use super::{
+    resource::bind::{
+        Binding, BindingLayoutEntry, BindingSet, GpuUniform, StageLockedUniform, UniformBuffer,
+    },
+    shadow::DepthTextureBinding,
+    DepthTexture,
+};
+
+/// Near and far plane distances of a projection, to turn depth back into view distance
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DepthPlanes {
+    pub znear: f32,
+    /// Infinite for a perspective projection without a far plane
+    pub zfar: f32,
+    /// Depth is linear in the view distance
+    pub orthographic: bool,
+    /// The near plane is at depth 1, see [`DepthConvention::ReverseZ`](super::DepthConvention)
+    pub reversed: bool,
+}
+
+impl DepthPlanes {
+    /// Of a projection mapping depth to `[0, 1]`, e.g. `OPENGL_TO_WGPU_MATRIX * projection`.
+    /// `None` without planes in front of the camera, like the identity
+    pub fn from_projection(projection: &Matrix4<f32>) -> Option<Self> {
+        // clip z = a * view z + b, view z is negative in front of the camera
+        let (a, b) = (projection.z.z, projection.w.z);
+        // view distance of a depth, both ends are swapped when depth falls with the distance
+        let (znear, zfar, orthographic, reversed) = if (projection.z.w + 1.0).abs() < 1e-6 {
+            // clip w = -view z, depth = b / distance - a
+            let infinite = a.abs() < 1e-9;
+            let distance = |depth: f32| b / (depth + a);
+            let reversed = b > 0.0;
+            let (near, far) = if reversed { (1.0, 0.0) } else { (0.0, 1.0) };
+            let zfar = if infinite {
+                f32::INFINITY
+            } else {
+                distance(far)
+            };
+            (distance(near), zfar, false, reversed)
+        } else if projection.z.w.abs() < 1e-6 {
+            // depth = b - a * distance
+            let distance = |depth: f32| (b - depth) / a;
+            let reversed = a > 0.0;
+            let (near, far) = if reversed { (1.0, 0.0) } else { (0.0, 1.0) };
+            (distance(near), distance(far), true, reversed)
+        } else {
+            return None;
+        };
+
+        let far_valid = zfar.is_finite() || (reversed && !orthographic && zfar > 0.0);
+        let valid = znear.is_finite() && far_valid && znear >= 0.0 && zfar > znear;
+        valid.then(|| Self {
+            znear,
+            zfar,
+            orthographic,
+            reversed,
+        })
+    }
+
+    /// View distance of the depth `raw`, `sample_linear_depth` of `res/common/depth.wgsl`
+    pub fn linearize(&self, raw: f32) -> f32 {
+        // measured from the near plane
+        let depth = if self.reversed { 1.0 - raw } else { raw };
+        if self.orthographic {
+            self.znear + depth * (self.zfar - self.znear)
+        } else if self.zfar.is_infinite() {
+            self.znear / raw
+        } else {
+            self.znear * self.zfar / (self.zfar - depth * (self.zfar - self.znear))
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, C, Pod, Zeroable)]
+pub struct DepthPlanesUniform {
+    // znear, zfar or 0 when infinite, 1 for orthographic projections, 1 when reversed
+    pub planes: [f32; 4],
+}
+impl GpuUniform for DepthPlanesUniform {}
+impl StageLockedUniform for DepthPlanesUniform {
+    const FORCE_STAGE: wgpu::ShaderStages = wgpu::ShaderStages::FRAGMENT;
+}
+impl From<DepthPlanes> for DepthPlanesUniform {
+    fn from(planes: DepthPlanes) -> Self {
+        Self {
+            planes: [
+                planes.znear,
+                if planes.zfar.is_finite() {
+                    planes.zfar
+                } else {
+                    0.0
+                },
+                planes.orthographic as u32 as f32,
+                planes.reversed as u32 as f32,
+            ],
+        }
+    }
+}
+
+/// A sampler without filtering, depth textures can only be read with one or a comparison
+pub struct NonFilteringSamplerBinding<'a>(pub &'a wgpu::Sampler);
+impl Binding for NonFilteringSamplerBinding<'_> {
+    fn get_layout_entry(&self) -> BindingLayoutEntry {
+        BindingLayoutEntry {
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
+            count: None,
+        }
+    }
+
+    fn get_resource<'a>(&'a self) -> wgpu::BindingResource<'a> {
+        wgpu::BindingResource::Sampler(self.0)
+    }
+}
+
+/// The depth texture of the main pass and the planes of the [`Camera`], for effects that
+/// need the view distance, e.g. fog. Bound at group 1 by shaders including
+/// [`DepthBindGroup::INCLUDE`].
+///
+/// [`DepthBindGroup::get`] returns the bind group only to `RenderStage::PostProcess`
+/// systems, the main pass still renders to the texture before.
+pub struct DepthBindGroup {
+    planes: UniformBuffer<DepthPlanesUniform>,
+    current_planes: DepthPlanesUniform,
+    sampler: wgpu::Sampler,
+    // rebuilt with the depth texture, e.g. on resize
+    bind_group: Option<wgpu::BindGroup>,
+    readable: bool,
+}
+
+impl DepthBindGroup {
+    /// Include path of the bindings and `linearize_depth`
+    pub const INCLUDE: &'static str = "common/depth.wgsl";
+    pub const INCLUDE_SOURCE: &'static str = include_str!("../../res/common/depth.wgsl");
+
+    pub fn new(device: &wgpu::Device) -> Self {
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Depth Read Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        let current_planes = DepthPlanesUniform::zeroed();
+
+        Self {
+            planes: UniformBuffer::new_init(device, current_planes, Some("depth planes")),
+            current_planes,
+            sampler,
+            bind_group: None,
+            readable: false,
+        }
+    }
+
+    /// Layout of the bind group, for pipelines created before the first frame
+    pub fn layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let fragment = wgpu::ShaderStages::FRAGMENT;
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Depth Read Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: fragment,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: fragment,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Depth,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: fragment,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
+                    count: None,
+                },
+            ],
+        })
+    }
+
+    /// `None` outside of `RenderStage::PostProcess` and before the depth texture exists
+    pub fn get(&self) -> Option<&wgpu::BindGroup> {
+        self.bind_group.as_ref().filter(|_| self.readable)
+    }
+
+    fn rebuild(&mut self, device: &wgpu::Device, depth: &DepthTexture) {
+        // NOTE: depth-stencil formats are sampled through a depth only view
+        let view = depth
+            .texture
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor {
+                label: Some("Depth Read View"),
+                aspect: wgpu::TextureAspect::DepthOnly,
+                ..Default::default()
+            });
+        let set = (
+            &self.planes,
+            &DepthTextureBinding(&view),
+            &NonFilteringSamplerBinding(&self.sampler),
+        );
+        self.bind_group = Some(set.into_bind_group(device));
+    }
+
+    fn update_planes(&mut self, queue: &wgpu::Queue, planes: DepthPlanesUniform) {
+        if planes != self.current_planes {
+            self.planes.update(queue, planes);
+            self.current_planes = planes;
+        }
+    }
+}
+
+/// Rebuilds the [`DepthBindGroup`] when the depth texture was recreated and hides it
+/// until `RenderStage::PostProcess`
+pub fn prepare_depth_bind_group_system(
+    device: Option<Res<Arc<wgpu::Device>>>,
+    queue: Option<Res<wgpu::Queue>>,
+    camera: Option<Res<Camera>>,
+    depth_texture: Res<Option<DepthTexture>>,
+    mut depth_bind_group: ResMut<Option<DepthBindGroup>>,
+) {
+    let (device, queue, depth) = match (device, queue, depth_texture.as_ref()) {
+        (Some(device), Some(queue), Some(depth)) => (device, queue, depth),
+        _ => return,
+    };
+
+    let rebuild = depth_texture.is_changed() || depth_bind_group.is_none();
+    let depth_bind_group = depth_bind_group.get_or_insert_with(|| DepthBindGroup::new(&device));
+    depth_bind_group.readable = false;
+    if rebuild {
+        depth_bind_group.rebuild(&device, depth);
+    }
+
+    let planes = camera
+        .and_then(|camera| DepthPlanes::from_projection(&camera.projection_matrix))
+        .map_or_else(DepthPlanesUniform::zeroed, DepthPlanesUniform::from);
+    depth_bind_group.update_planes(&queue, planes);
+}
+
+/// Exposes the [`DepthBindGroup`] once every system of `RenderStage::MainPass` ran,
+/// commands are applied at the end of the stage
+pub fn expose_depth_bind_group_system(mut commands: Commands) {
+    commands.add(ExposeDepthBindGroup);
+}
+
+struct ExposeDepthBindGroup;
+impl Command for ExposeDepthBindGroup {
+    fn write(self, world: &mut World) {
+        if let Some(mut depth_bind_group) = world.get_resource_mut::<Option<DepthBindGroup>>() {
+            if let Some(depth_bind_group) = depth_bind_group.as_mut() {
+                depth_bind_group.readable = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Matrix4, SquareMatrix};
+
+    use crate::{
+        camera::{OrthographicProjection, PerspectiveProjection, OPENGL_TO_WGPU_MATRIX},
+        render::DepthConvention,
+    };
+
+    use super::{DepthPlanes, DepthPlanesUniform};
+
+    // NOTE: the far plane is recovered from a difference close to zero, relative error
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-4 * b.abs().max(10.0), "{} != {}", a, b);
+    }
+
+    #[test]
+    fn planes_of_a_perspective_projection() {
+        let projection = PerspectiveProjection {
+            aspect: 1.5,
+            fovy: 1.0,
+            znear: 0.1,
+            zfar: 100.0,
+            ..Default::default()
+        };
+        let planes = DepthPlanes::from_projection(
+            &(OPENGL_TO_WGPU_MATRIX * projection.build_projection_matrix()),
+        )
+        .unwrap();
+        assert!(!planes.orthographic);
+        assert_close(planes.znear, 0.1);
+        assert_close(planes.zfar, 100.0);
+
+        assert_close(planes.linearize(0.0), 0.1);
+        assert_close(planes.linearize(1.0), 100.0);
+        // most of the depth range is spent close to the near plane
+        assert!(planes.linearize(0.9) < 1.0);
+    }
+
+    #[test]
+    fn planes_of_an_orthographic_projection() {
+        let projection = OrthographicProjection::from_height(10.0, 1.0, 1.0, 11.0);
+        let planes = DepthPlanes::from_projection(
+            &(OPENGL_TO_WGPU_MATRIX * projection.build_projection_matrix()),
+        )
+        .unwrap();
+        assert!(planes.orthographic);
+        assert_close(planes.linearize(0.0), 1.0);
+        assert_close(planes.linearize(0.5), 6.0);
+        assert_close(planes.linearize(1.0), 11.0);
+        assert_eq!(DepthPlanesUniform::from(planes).planes[2..], [1.0, 0.0]);
+    }
+
+    #[test]
+    fn planes_of_a_reversed_projection() {
+        let projection = PerspectiveProjection {
+            znear: 0.1,
+            zfar: 100.0,
+            depth_convention: DepthConvention::ReverseZ,
+            ..Default::default()
+        };
+        let planes = DepthPlanes::from_projection(
+            &(OPENGL_TO_WGPU_MATRIX * projection.build_projection_matrix()),
+        )
+        .unwrap();
+        assert!(planes.reversed);
+        assert_close(planes.znear, 0.1);
+        assert_close(planes.zfar, 100.0);
+        assert_close(planes.linearize(1.0), 0.1);
+        assert_close(planes.linearize(0.0), 100.0);
+        assert_eq!(DepthPlanesUniform::from(planes).planes[2..], [0.0, 1.0]);
+
+        let infinite = PerspectiveProjection {
+            zfar: f32::INFINITY,
+            ..projection
+        };
+        let planes = DepthPlanes::from_projection(
+            &(OPENGL_TO_WGPU_MATRIX * infinite.build_projection_matrix()),
+        )
+        .unwrap();
+        assert!(planes.reversed && planes.zfar.is_infinite());
+        assert_close(planes.linearize(1.0), 0.1);
+        assert_close(planes.linearize(0.001), 100.0);
+        assert_eq!(
+            DepthPlanesUniform::from(planes).planes[1..],
+            [0.0, 0.0, 1.0]
+        );
+    }
+
+    #[test]
+    fn identity_has_no_planes() {
+        assert_eq!(DepthPlanes::from_projection(&Matrix4::identity()), None);
+    }
+}
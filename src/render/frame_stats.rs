@@ -0,0 +1,266 @@
+use std::time::Duration;
+
+use bevy_ecs::system::{Local, Res, ResMut};
+
+use crate::{
+    time::Time,
+    window::{commands::WindowCommands, WindowId, Windows},
+};
+
+/// Frame times of the rendered frames, from the real [`Time`] delta so a fixed game
+/// logic timestep does not skew them. Updated in `RenderStage::Present`.
+pub struct FrameStats {
+    // seconds, a ring of the last `window` frames
+    frame_times: Vec<f32>,
+    next: usize,
+    window: usize,
+    last: f32,
+    frame_count: u64,
+    // sorted on demand, allocated once with the ring
+    scratch: Vec<f32>,
+}
+
+impl Default for FrameStats {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_WINDOW)
+    }
+}
+
+impl FrameStats {
+    pub const DEFAULT_WINDOW: usize = 120;
+
+    /// Averages over the last `window` frames, at least one
+    pub fn new(window: usize) -> Self {
+        let window = window.max(1);
+        Self {
+            frame_times: Vec::with_capacity(window),
+            next: 0,
+            window,
+            last: 0.0,
+            frame_count: 0,
+            scratch: Vec::with_capacity(window),
+        }
+    }
+
+    /// The first frame has no delta, it is only counted
+    pub fn push(&mut self, frame_time: Duration) {
+        self.frame_count += 1;
+        if frame_time.is_zero() {
+            return;
+        }
+        let frame_time = frame_time.as_secs_f32();
+        self.last = frame_time;
+        if self.frame_times.len() < self.window {
+            self.frame_times.push(frame_time);
+        } else {
+            self.frame_times[self.next] = frame_time;
+        }
+        self.next = (self.next + 1) % self.window;
+    }
+
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    /// Frames rendered since startup
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /// Of the last frame
+    pub fn frame_time_ms(&self) -> f32 {
+        self.last * 1000.0
+    }
+
+    /// Over the window, zero before the first frame time
+    pub fn average_frame_time_ms(&self) -> f32 {
+        if self.frame_times.is_empty() {
+            return 0.0;
+        }
+        let sum: f32 = self.frame_times.iter().sum();
+        sum / self.frame_times.len() as f32 * 1000.0
+    }
+
+    /// From the average frame time, zero before the first frame time
+    pub fn fps(&self) -> f32 {
+        to_fps(self.average_frame_time_ms())
+    }
+
+    /// Frame time of the window at `percentile`, by nearest rank. Zero before the first
+    /// frame time
+    pub fn percentile_frame_time_ms(&mut self, percentile: f32) -> f32 {
+        if self.frame_times.is_empty() {
+            return 0.0;
+        }
+        let len = self.frame_times.len();
+        let rank = (percentile.clamp(0.0, 100.0) * len as f32 / 100.0).ceil() as usize;
+        let index = rank.clamp(1, len) - 1;
+        self.partition(index)[index] * 1000.0
+    }
+
+    /// Fps of the average of the slowest 1% of the window, at least one frame
+    pub fn one_percent_low(&mut self) -> f32 {
+        if self.frame_times.is_empty() {
+            return 0.0;
+        }
+        let len = self.frame_times.len();
+        let slowest = (len / 100).max(1);
+        let sum: f32 = self.partition(len - slowest)[len - slowest..].iter().sum();
+        to_fps(sum / slowest as f32 * 1000.0)
+    }
+
+    // the window with the frame time of `index` in sorted order at `index`, shorter
+    // ones before and longer ones after, without touching the ring
+    fn partition(&mut self, index: usize) -> &[f32] {
+        self.scratch.clear();
+        self.scratch.extend_from_slice(&self.frame_times);
+        self.scratch
+            .select_nth_unstable_by(index, |a, b| a.total_cmp(b));
+        &self.scratch
+    }
+}
+
+fn to_fps(frame_time_ms: f32) -> f32 {
+    if frame_time_ms > 0.0 {
+        1000.0 / frame_time_ms
+    } else {
+        0.0
+    }
+}
+
+pub fn frame_stats_system(time: Option<Res<Time>>, mut stats: ResMut<FrameStats>) {
+    if let Some(time) = time {
+        stats.push(time.delta());
+    }
+}
+
+/// Title of the primary window that [`show_fps_in_title_system`] appends the fps to
+pub struct FpsTitle {
+    pub title: String,
+    /// Between title updates, every frame would flicker and cost a window call
+    pub interval: Duration,
+}
+
+impl Default for FpsTitle {
+    fn default() -> Self {
+        Self {
+            title: String::new(),
+            interval: Duration::from_millis(500),
+        }
+    }
+}
+
+impl FpsTitle {
+    pub fn format(&self, stats: &FrameStats) -> String {
+        let fps = format!(
+            "{:.0} fps ({:.2} ms)",
+            stats.fps(),
+            stats.average_frame_time_ms()
+        );
+        if self.title.is_empty() {
+            fps
+        } else {
+            format!("{} - {}", self.title, fps)
+        }
+    }
+}
+
+/// Shows the fps in the title of the primary window, with an [`FpsTitle`] resource.
+/// Not added by the plugins
+pub fn show_fps_in_title_system(
+    title: Option<Res<FpsTitle>>,
+    stats: Res<FrameStats>,
+    time: Res<Time>,
+    mut windows: ResMut<Windows>,
+    mut since_update: Local<Duration>,
+) {
+    let title = match title {
+        Some(title) => title,
+        None => return,
+    };
+    *since_update += time.delta();
+    if *since_update < title.interval {
+        return;
+    }
+    *since_update = Duration::ZERO;
+    windows.execute(
+        WindowId::primary(),
+        WindowCommands::SetTitle {
+            title: title.format(&stats),
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{FpsTitle, FrameStats};
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn average_over_the_window() {
+        let mut stats = FrameStats::new(4);
+        assert_eq!(stats.fps(), 0.0);
+        assert_eq!(stats.one_percent_low(), 0.0);
+
+        // the first frame of Time has no delta
+        stats.push(Duration::ZERO);
+        for frame_time in [10, 10, 20, 20] {
+            stats.push(ms(frame_time));
+        }
+        assert_eq!(stats.frame_count(), 5);
+        assert!((stats.average_frame_time_ms() - 15.0).abs() < 1e-4);
+        // the oldest two 10 ms frames leave the window
+        stats.push(ms(30));
+        stats.push(ms(30));
+        assert!((stats.frame_time_ms() - 30.0).abs() < 1e-4);
+        assert!((stats.average_frame_time_ms() - 25.0).abs() < 1e-4);
+        assert!((stats.fps() - 40.0).abs() < 1e-2);
+        assert_eq!(stats.frame_count(), 7);
+    }
+
+    #[test]
+    fn percentiles_by_nearest_rank() {
+        let mut stats = FrameStats::new(200);
+        // 1..=200 ms, shuffled by a stride coprime to 200
+        for i in 0..200 {
+            stats.push(ms((i * 77 % 200) as u64 + 1));
+        }
+        assert!((stats.percentile_frame_time_ms(50.0) - 100.0).abs() < 1e-3);
+        assert!((stats.percentile_frame_time_ms(99.0) - 198.0).abs() < 1e-3);
+        assert!((stats.percentile_frame_time_ms(100.0) - 200.0).abs() < 1e-3);
+        assert!((stats.percentile_frame_time_ms(0.0) - 1.0).abs() < 1e-3);
+        // the slowest 2 frames
+        assert!((stats.one_percent_low() - 1000.0 / 199.5).abs() < 1e-3);
+        // the ring is left as it was
+        assert!((stats.frame_time_ms() - (199 * 77 % 200 + 1) as f32).abs() < 1e-3);
+    }
+
+    #[test]
+    fn one_percent_low_catches_a_single_stutter() {
+        let mut stats = FrameStats::new(100);
+        for _ in 0..99 {
+            stats.push(ms(10));
+        }
+        stats.push(ms(50));
+        assert!((stats.fps() - 1000.0 / 10.4).abs() < 1e-2);
+        assert!((stats.one_percent_low() - 20.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn title_appends_the_fps() {
+        let mut stats = FrameStats::new(2);
+        stats.push(ms(20));
+        let mut title = FpsTitle {
+            title: "game".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(title.format(&stats), "game - 50 fps (20.00 ms)");
+        title.title.clear();
+        assert_eq!(title.format(&stats), "50 fps (20.00 ms)");
+    }
+}
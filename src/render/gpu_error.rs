@@ -0,0 +1,323 @@
+use std::{
+    fmt::{self, Write},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bevy_app::AppExit;
+use bevy_ecs::{
+    event::{EventReader, EventWriter},
+    system::Res,
+};
+
+use super::{gpu_info::GpuInfo, RenderStats};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WgpuErrorKind {
+    OutOfMemory,
+    /// The device was lost, e.g. the driver was reset, nothing can be rendered anymore
+    DeviceLost,
+    /// A command did not pass validation and was skipped, the message names the labels
+    /// of the objects involved
+    Validation,
+}
+
+impl WgpuErrorKind {
+    /// The app exits on fatal errors, after writing a [`CrashReport`]
+    pub fn is_fatal(&self) -> bool {
+        !matches!(self, WgpuErrorKind::Validation)
+    }
+}
+
+/// An error wgpu raised outside of an error scope, sent in `CoreStage::First` of the frame
+/// after it happened
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WgpuError {
+    pub kind: WgpuErrorKind,
+    pub message: String,
+}
+
+impl From<wgpu::Error> for WgpuError {
+    fn from(error: wgpu::Error) -> Self {
+        match error {
+            wgpu::Error::OutOfMemory { source } => WgpuError {
+                kind: WgpuErrorKind::OutOfMemory,
+                message: source.to_string(),
+            },
+            wgpu::Error::Validation {
+                source,
+                description,
+            } => WgpuError {
+                kind: Self::classify(&description),
+                message: format!("{}: {}", description, source),
+            },
+        }
+    }
+}
+
+impl WgpuError {
+    /// NOTE: wgpu 0.13 has no device lost callback, the loss only shows in the description
+    /// of the errors of the commands after it
+    fn classify(description: &str) -> WgpuErrorKind {
+        let lowercase = description.to_lowercase();
+        if lowercase.contains("device is lost") || lowercase.contains("device lost") {
+            WgpuErrorKind::DeviceLost
+        } else {
+            WgpuErrorKind::Validation
+        }
+    }
+}
+
+impl fmt::Display for WgpuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.kind, self.message)
+    }
+}
+
+/// Collects the uncaptured errors of the device, instead of the default handler which
+/// panics inside the wgpu callback. Inserted along with the device
+#[derive(Clone, Default)]
+pub struct WgpuErrors(Arc<Mutex<Vec<WgpuError>>>);
+
+impl WgpuErrors {
+    pub fn install(&self, device: &wgpu::Device) {
+        let errors = self.clone();
+        device.on_uncaptured_error(move |error| errors.push(error.into()));
+    }
+
+    pub fn push(&self, error: WgpuError) {
+        log::error!("wgpu: {}", error);
+        self.0.lock().unwrap().push(error);
+    }
+
+    pub fn drain(&self) -> Vec<WgpuError> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+/// Sends the collected [`WgpuErrors`] as [`WgpuError`] events
+pub fn forward_wgpu_errors_system(
+    errors: Option<Res<WgpuErrors>>,
+    mut events: EventWriter<WgpuError>,
+) {
+    if let Some(errors) = errors {
+        events.send_batch(errors.drain().into_iter());
+    }
+}
+
+/// Where [`CrashReport`]s are written, the working directory by default
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrashReportSettings {
+    pub dir: PathBuf,
+}
+
+impl Default for CrashReportSettings {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("."),
+        }
+    }
+}
+
+/// What went wrong and what the renderer was running on, written as text next to the app
+/// so it can still be read once the console closed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrashReport {
+    pub reason: String,
+    /// [`GpuInfo::summary`], `None` before the device was created
+    pub gpu: Option<String>,
+    /// Of the last frame
+    pub render_stats: Option<String>,
+}
+
+impl CrashReport {
+    pub fn new(reason: impl Into<String>) -> Self {
+        Self {
+            reason: reason.into(),
+            gpu: None,
+            render_stats: None,
+        }
+    }
+
+    pub fn with_gpu_info(mut self, gpu_info: &GpuInfo) -> Self {
+        self.gpu = Some(gpu_info.summary());
+        self
+    }
+
+    pub fn with_render_stats(mut self, stats: &RenderStats) -> Self {
+        self.render_stats = Some(format!("{:#?}", stats));
+        self
+    }
+
+    pub fn format(&self) -> String {
+        let mut report = String::new();
+        // NOTE: writing to a String can not fail
+        let _ = writeln!(report, "try-wgpu crash report");
+        let _ = writeln!(report);
+        let _ = writeln!(report, "{}", self.reason);
+        let _ = writeln!(report);
+        let _ = writeln!(
+            report,
+            "{}",
+            self.gpu.as_deref().unwrap_or("GPU: not created")
+        );
+        if let Some(stats) = &self.render_stats {
+            let _ = writeln!(report);
+            let _ = writeln!(report, "Render stats: {}", stats);
+        }
+        report
+    }
+
+    /// `crash-<unix seconds>.txt` in `dir`, returns the path
+    pub fn write(&self, dir: &std::path::Path) -> std::io::Result<PathBuf> {
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        let path = dir.join(format!("crash-{}.txt", seconds));
+        std::fs::write(&path, self.format())?;
+        Ok(path)
+    }
+}
+
+/// Writes a [`CrashReport`] with the panic message before the default hook prints it,
+/// e.g. for a console that closes with the app. Not installed by the plugins, tests and
+/// apps embedding the engine keep their own hook
+pub fn install_crash_report_panic_hook(settings: CrashReportSettings) {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        match CrashReport::new(format!("Panicked: {}", info)).write(&settings.dir) {
+            Ok(path) => eprintln!("Crash report written to {}", path.display()),
+            Err(err) => eprintln!("Could not write the crash report: {}", err),
+        }
+        default_hook(info);
+    }));
+}
+
+/// Writes a [`CrashReport`] and exits on the first fatal [`WgpuError`]
+pub fn exit_on_fatal_wgpu_error_system(
+    mut errors: EventReader<WgpuError>,
+    settings: Res<CrashReportSettings>,
+    gpu_info: Option<Res<GpuInfo>>,
+    stats: Option<Res<RenderStats>>,
+    mut app_exit_events: EventWriter<AppExit>,
+) {
+    let error = match errors.iter().find(|error| error.kind.is_fatal()) {
+        Some(error) => error,
+        None => return,
+    };
+    let mut report = CrashReport::new(format!("Fatal wgpu error: {}", error));
+    if let Some(gpu_info) = &gpu_info {
+        report = report.with_gpu_info(gpu_info);
+    }
+    if let Some(stats) = &stats {
+        report = report.with_render_stats(stats);
+    }
+    match report.write(&settings.dir) {
+        Ok(path) => log::error!("{}, crash report written to {}", error, path.display()),
+        Err(err) => log::error!("{}, could not write the crash report: {}", error, err),
+    }
+    app_exit_events.send(AppExit);
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_app::AppExit;
+    use bevy_ecs::{
+        event::Events,
+        schedule::{ParallelSystemDescriptorCoercion, Stage, SystemStage},
+        world::World,
+    };
+
+    use crate::render::RenderStats;
+
+    use super::{
+        exit_on_fatal_wgpu_error_system, forward_wgpu_errors_system, CrashReport,
+        CrashReportSettings, WgpuError, WgpuErrorKind, WgpuErrors,
+    };
+
+    fn error(kind: WgpuErrorKind) -> WgpuError {
+        WgpuError {
+            kind,
+            message: "Buffer \"Vertex Buffer: cube\" is invalid".to_string(),
+        }
+    }
+
+    #[test]
+    fn errors_are_routed_to_events_and_fatal_ones_exit() {
+        let dir = std::env::temp_dir().join(format!("try-wgpu-crash-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut world = World::new();
+        let errors = WgpuErrors::default();
+        world.insert_resource(errors.clone());
+        world.insert_resource(CrashReportSettings { dir: dir.clone() });
+        world.init_resource::<RenderStats>();
+        world.init_resource::<Events<WgpuError>>();
+        world.init_resource::<Events<AppExit>>();
+        let mut stage = SystemStage::single_threaded()
+            .with_system(forward_wgpu_errors_system)
+            .with_system(exit_on_fatal_wgpu_error_system.after(forward_wgpu_errors_system));
+
+        // as the device callback does
+        errors.push(error(WgpuErrorKind::Validation));
+        stage.run(&mut world);
+        let events = world.resource::<Events<WgpuError>>();
+        let sent: Vec<_> = events.get_reader().iter(events).cloned().collect();
+        assert_eq!(sent, [error(WgpuErrorKind::Validation)]);
+        assert!(errors.drain().is_empty());
+        assert!(world.resource::<Events<AppExit>>().is_empty());
+
+        errors.push(error(WgpuErrorKind::DeviceLost));
+        stage.run(&mut world);
+        assert!(!world.resource::<Events<AppExit>>().is_empty());
+        let reports: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(reports.len(), 1);
+        let report = std::fs::read_to_string(&reports[0]).unwrap();
+        assert!(report.contains("Fatal wgpu error: DeviceLost"));
+        assert!(report.contains("Render stats: RenderStats {"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn errors_are_classified() {
+        assert_eq!(
+            WgpuError::classify("Parent device is lost"),
+            WgpuErrorKind::DeviceLost
+        );
+        assert_eq!(
+            WgpuError::classify("In a draw command, indexed:true"),
+            WgpuErrorKind::Validation
+        );
+        assert!(WgpuErrorKind::OutOfMemory.is_fatal());
+        assert!(!WgpuErrorKind::Validation.is_fatal());
+    }
+
+    #[test]
+    fn crash_report_has_every_section() {
+        let report = CrashReport::new("Fatal wgpu error: OutOfMemory: out of memory");
+        assert_eq!(
+            report.format(),
+            "try-wgpu crash report\n\
+             \n\
+             Fatal wgpu error: OutOfMemory: out of memory\n\
+             \n\
+             GPU: not created\n"
+        );
+
+        let stats = RenderStats {
+            draw_calls: 3,
+            ..Default::default()
+        };
+        let report = CrashReport {
+            gpu: Some("GPU: Test Adapter (Vulkan, DiscreteGpu)".to_string()),
+            ..report.with_render_stats(&stats)
+        }
+        .format();
+        assert!(report.contains("\nGPU: Test Adapter (Vulkan, DiscreteGpu)\n\nRender stats: "));
+        assert!(report.contains("draw_calls: 3,"));
+    }
+}
@@ -0,0 +1,126 @@
+use std::fmt::Write;
+
+/// What the renderer ended up running on, inserted along with the device.
+/// Check [`GpuInfo::supports`] before creating pipelines that need optional features.
+#[derive(Debug, Clone)]
+pub struct GpuInfo {
+    pub adapter: wgpu::AdapterInfo,
+    pub limits: wgpu::Limits,
+    pub features: wgpu::Features,
+    pub downlevel: wgpu::DownlevelCapabilities,
+    /// `None` when rendering headless
+    pub surface: Option<SurfaceInfo>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SurfaceInfo {
+    pub format: wgpu::TextureFormat,
+    pub present_modes: Vec<wgpu::PresentMode>,
+}
+
+impl SurfaceInfo {
+    pub fn new(
+        surface: &wgpu::Surface,
+        adapter: &wgpu::Adapter,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> Self {
+        Self {
+            format: config.format,
+            present_modes: surface.get_supported_present_modes(adapter),
+        }
+    }
+}
+
+impl GpuInfo {
+    pub fn new(
+        adapter: &wgpu::Adapter,
+        device: &wgpu::Device,
+        surface: Option<SurfaceInfo>,
+    ) -> Self {
+        Self {
+            adapter: adapter.get_info(),
+            limits: device.limits(),
+            features: device.features(),
+            downlevel: adapter.get_downlevel_properties(),
+            surface,
+        }
+    }
+
+    /// Enabled on the device, not only available on the adapter
+    pub fn supports(&self, features: wgpu::Features) -> bool {
+        self.features.contains(features)
+    }
+
+    pub fn summary(&self) -> String {
+        let mut summary = String::new();
+        // NOTE: writing to a String can not fail
+        let _ = writeln!(
+            summary,
+            "GPU: {} ({:?}, {:?})",
+            self.adapter.name, self.adapter.backend, self.adapter.device_type
+        );
+        let _ = writeln!(
+            summary,
+            "  vendor: {:#06x}, device: {:#06x}",
+            self.adapter.vendor, self.adapter.device
+        );
+        let _ = writeln!(summary, "  features: {:?}", self.features);
+        let _ = writeln!(
+            summary,
+            "  max texture 2d: {}, max bind groups: {}, max storage buffers per stage: {}",
+            self.limits.max_texture_dimension_2d,
+            self.limits.max_bind_groups,
+            self.limits.max_storage_buffers_per_shader_stage
+        );
+        if !self.downlevel.is_webgpu_compliant() {
+            let _ = writeln!(summary, "  downlevel flags: {:?}", self.downlevel.flags);
+        }
+        match &self.surface {
+            Some(surface) => {
+                let _ = write!(
+                    summary,
+                    "  surface: {:?}, present modes: {:?}",
+                    surface.format, surface.present_modes
+                );
+            }
+            None => {
+                let _ = write!(summary, "  surface: headless");
+            }
+        }
+        summary
+    }
+
+    pub fn log_summary(&self) {
+        log::info!("{}", self.summary());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GpuInfo;
+
+    #[test]
+    fn supports_checks_device_features() {
+        let info = GpuInfo {
+            adapter: wgpu::AdapterInfo {
+                name: "Test Adapter".to_string(),
+                vendor: 0x10de,
+                device: 0x1234,
+                device_type: wgpu::DeviceType::DiscreteGpu,
+                backend: wgpu::Backend::Vulkan,
+            },
+            limits: wgpu::Limits::default(),
+            features: wgpu::Features::TEXTURE_BINDING_ARRAY,
+            downlevel: wgpu::DownlevelCapabilities::default(),
+            surface: None,
+        };
+
+        assert!(info.supports(wgpu::Features::TEXTURE_BINDING_ARRAY));
+        assert!(!info
+            .supports(wgpu::Features::TEXTURE_BINDING_ARRAY | wgpu::Features::MULTI_DRAW_INDIRECT));
+
+        let summary = info.summary();
+        assert!(summary.starts_with("GPU: Test Adapter (Vulkan, DiscreteGpu)"));
+        assert!(summary.ends_with("surface: headless"));
+    }
+}
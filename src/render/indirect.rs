@@ -0,0 +1,258 @@
+use std::sync::Arc;
+
+use bevy_ecs::{
+    prelude::Component,
+    system::{Query, Res},
+};
+use bytemuck::{Pod, Zeroable};
+use repr_trait::C;
+use wgpu::util::DeviceExt;
+
+use crate::util::{Refer, Store};
+
+use super::{
+    mesh::{GpuMesh, GpuMeshAssembly},
+    resolve_mesh, WithMesh,
+};
+
+// NOTE: Same layout as wgpu::util::DrawIndirect
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, C, Pod, Zeroable)]
+pub struct DrawIndirectArgs {
+    pub vertex_count: u32,
+    pub instance_count: u32,
+    pub base_vertex: u32,
+    pub base_instance: u32,
+}
+
+// NOTE: Same layout as wgpu::util::DrawIndexedIndirect
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, C, Pod, Zeroable)]
+pub struct DrawIndexedIndirectArgs {
+    pub index_count: u32,
+    pub instance_count: u32,
+    pub base_index: u32,
+    pub vertex_offset: i32,
+    pub base_instance: u32,
+}
+
+/// A sub-draw inside an [`IndirectBatch`].
+/// `first` and `count` are in indices for indexed meshes, vertices otherwise.
+/// Non-zero `base_instance` requires Features::INDIRECT_FIRST_INSTANCE
+#[derive(Clone, Copy, Debug)]
+pub struct IndirectDraw {
+    pub first: u32,
+    pub count: u32,
+    pub vertex_offset: i32,
+    pub base_instance: u32,
+    pub instance_count: u32,
+}
+
+/// Groups many draws sharing the pipeline, bind groups and vertex layout
+/// of the entity it is attached to. The entity's [`GpuMesh`] is expected to
+/// hold all the sub-meshes (see `BatchMesh`) and each [`IndirectDraw`]
+/// selects a range of it.
+#[derive(Component)]
+pub struct IndirectBatch {
+    pub draws: Vec<IndirectDraw>,
+    buffer: Option<wgpu::Buffer>,
+    capacity: usize,
+    packed_count: u32,
+}
+
+impl IndirectBatch {
+    pub fn new(draws: Vec<IndirectDraw>) -> Self {
+        Self {
+            draws,
+            buffer: None,
+            capacity: 0,
+            packed_count: 0,
+        }
+    }
+
+    pub fn get_buffer(&self) -> Option<&wgpu::Buffer> {
+        self.buffer.as_ref()
+    }
+
+    pub fn packed_count(&self) -> u32 {
+        self.packed_count
+    }
+
+    pub fn stride(indexed: bool) -> wgpu::BufferAddress {
+        if indexed {
+            std::mem::size_of::<DrawIndexedIndirectArgs>() as wgpu::BufferAddress
+        } else {
+            std::mem::size_of::<DrawIndirectArgs>() as wgpu::BufferAddress
+        }
+    }
+
+    pub fn pack(draws: &[IndirectDraw], indexed: bool) -> Vec<u8> {
+        if indexed {
+            let args: Vec<DrawIndexedIndirectArgs> = draws
+                .iter()
+                .map(|draw| DrawIndexedIndirectArgs {
+                    index_count: draw.count,
+                    instance_count: draw.instance_count,
+                    base_index: draw.first,
+                    vertex_offset: draw.vertex_offset,
+                    base_instance: draw.base_instance,
+                })
+                .collect();
+            bytemuck::cast_slice(&args).to_vec()
+        } else {
+            let args: Vec<DrawIndirectArgs> = draws
+                .iter()
+                .map(|draw| DrawIndirectArgs {
+                    vertex_count: draw.count,
+                    instance_count: draw.instance_count,
+                    base_vertex: draw.first,
+                    base_instance: draw.base_instance,
+                })
+                .collect();
+            bytemuck::cast_slice(&args).to_vec()
+        }
+    }
+
+    fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, indexed: bool) {
+        let bytes = Self::pack(&self.draws, indexed);
+        self.packed_count = self.draws.len() as u32;
+        if bytes.is_empty() {
+            return;
+        }
+
+        match &self.buffer {
+            Some(buffer) if self.capacity >= bytes.len() => {
+                queue.write_buffer(buffer, 0, &bytes);
+            }
+            _ => {
+                self.buffer = Some(device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Indirect Buffer"),
+                    contents: &bytes,
+                    usage: wgpu::BufferUsages::INDIRECT | wgpu::BufferUsages::COPY_DST,
+                }));
+                self.capacity = bytes.len();
+            }
+        }
+    }
+}
+
+/// Uploads the draws of every [`IndirectBatch`], in `RenderStage::Prepare`
+pub fn pack_indirect_batches_system(
+    device: Option<Res<Arc<wgpu::Device>>>,
+    queue: Option<Res<wgpu::Queue>>,
+    meshes: Res<Store<GpuMesh>>,
+    mut batches: Query<
+        (&mut IndirectBatch, Option<&GpuMesh>, Option<&Refer<GpuMesh>>),
+        WithMesh,
+    >,
+) {
+    let (device, queue) = match (device, queue) {
+        (Some(device), Some(queue)) => (device, queue),
+        _ => return,
+    };
+    for (mut batch, owned, shared) in batches.iter_mut() {
+        let mesh = match resolve_mesh(owned, shared, &meshes) {
+            Some(mesh) => mesh,
+            None => continue,
+        };
+        let indexed = matches!(mesh.assembly, GpuMeshAssembly::Indexed { .. });
+        batch.upload(&device, &queue, indexed);
+    }
+}
+
+pub fn draw_indirect_batch<'a>(
+    render_pass: &mut wgpu::RenderPass<'a>,
+    batch: &'a IndirectBatch,
+    mesh: &'a GpuMesh,
+    multi_draw: bool,
+) -> u32 {
+    let buffer = match batch.get_buffer() {
+        Some(buffer) if batch.packed_count > 0 => buffer,
+        _ => return 0,
+    };
+
+    match &mesh.assembly {
+        GpuMeshAssembly::Indexed {
+            index_buffer,
+            index_format,
+            ..
+        } => {
+            render_pass.set_index_buffer(index_buffer.slice(..), *index_format);
+            if multi_draw {
+                render_pass.multi_draw_indexed_indirect(buffer, 0, batch.packed_count);
+            } else {
+                let stride = IndirectBatch::stride(true);
+                for i in 0..batch.packed_count as wgpu::BufferAddress {
+                    render_pass.draw_indexed_indirect(buffer, i * stride);
+                }
+            }
+        }
+        GpuMeshAssembly::NonIndexed { .. } => {
+            if multi_draw {
+                render_pass.multi_draw_indirect(buffer, 0, batch.packed_count);
+            } else {
+                let stride = IndirectBatch::stride(false);
+                for i in 0..batch.packed_count as wgpu::BufferAddress {
+                    render_pass.draw_indirect(buffer, i * stride);
+                }
+            }
+        }
+    }
+
+    if multi_draw {
+        1
+    } else {
+        batch.packed_count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DrawIndexedIndirectArgs, IndirectBatch, IndirectDraw};
+
+    #[test]
+    fn pack_layout() {
+        let draws = [IndirectDraw {
+            first: 6,
+            count: 36,
+            vertex_offset: -2,
+            base_instance: 0,
+            instance_count: 4,
+        }];
+
+        let bytes = IndirectBatch::pack(&draws, true);
+        assert_eq!(bytes.len() as u64, IndirectBatch::stride(true));
+
+        let args: &[DrawIndexedIndirectArgs] = bytemuck::cast_slice(&bytes);
+        assert_eq!(
+            args[0],
+            DrawIndexedIndirectArgs {
+                index_count: 36,
+                instance_count: 4,
+                base_index: 6,
+                vertex_offset: -2,
+                base_instance: 0,
+            }
+        );
+    }
+
+    #[test]
+    #[ignore]
+    fn pack_10k_draws() {
+        let draws: Vec<IndirectDraw> = (0..10_000)
+            .map(|i| IndirectDraw {
+                first: i * 36,
+                count: 36,
+                vertex_offset: 0,
+                base_instance: 0,
+                instance_count: 1,
+            })
+            .collect();
+
+        let indexed = IndirectBatch::pack(&draws, true);
+        let non_indexed = IndirectBatch::pack(&draws, false);
+
+        assert_eq!(indexed.len() as u64, 10_000 * IndirectBatch::stride(true));
+        assert_eq!(non_indexed.len() as u64, 10_000 * IndirectBatch::stride(false));
+    }
+}
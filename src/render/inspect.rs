@@ -0,0 +1,402 @@
+use std::fmt::Write;
+
+use bevy_ecs::{
+    entity::Entity,
+    world::{EntityRef, World},
+};
+use cgmath::Matrix4;
+
+use crate::{
+    camera::Camera,
+    input::{action::modifiers_from_keys, keyboard::KeyCode, Input, ModifiersState},
+    picking::{cursor_ray, pick, Aabb},
+    transform::Transform,
+    util::{Refer, ReferMany, Store},
+    window::Windows,
+};
+
+use super::{
+    mesh::{GpuMesh, GpuMeshAssembly},
+    resource::{bind::BindSlots, pipeline::RenderPipeline},
+    visibility::{ComputedVisibility, InstanceSource, Visibility},
+    DepthConvention, InstanceData,
+};
+
+/// Key chord of [`dump_entity_under_cursor_system`], Ctrl+Shift+I by default
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntityDumpKeys {
+    pub key: KeyCode,
+    /// Have to be held with the key, others are ignored
+    pub modifiers: ModifiersState,
+}
+
+impl Default for EntityDumpKeys {
+    fn default() -> Self {
+        Self {
+            key: KeyCode::I,
+            modifiers: ModifiersState::CTRL | ModifiersState::SHIFT,
+        }
+    }
+}
+
+impl EntityDumpKeys {
+    pub fn just_pressed(&self, keys: &Input<KeyCode>) -> bool {
+        keys.just_pressed(self.key) && modifiers_from_keys(keys).contains(self.modifiers)
+    }
+}
+
+/// What the render systems look at of `entity`, as plain text for an issue report.
+/// Ends with the reasons it is not drawn that could be found, e.g. a missing component,
+/// a pipeline still compiling or a removed bind group.
+pub fn debug_dump_entity(world: &World, entity: Entity) -> String {
+    let entity_ref = match world.get_entity(entity) {
+        Some(entity_ref) => entity_ref,
+        None => return format!("{:?} does not exist\n", entity),
+    };
+    let mut dump = EntityDump {
+        out: format!("{:?}\n", entity),
+        problems: Vec::new(),
+    };
+
+    let pipelines = world.get_resource::<Store<RenderPipeline>>();
+    let pipeline = match entity_ref.get::<Refer<RenderPipeline>>() {
+        Some(refer) => match pipelines.and_then(|pipelines| refer.get(pipelines)) {
+            Some(pipeline) => {
+                dump.line(format!(
+                    "Refer<RenderPipeline>: {}, {:?}, {} bind groups",
+                    **refer,
+                    pipeline.topology(),
+                    pipeline.bind_group_count()
+                ));
+                Some(pipeline)
+            }
+            None => {
+                dump.line(format!(
+                    "Refer<RenderPipeline>: {}, not in the store",
+                    **refer
+                ));
+                dump.problem(format!(
+                    "pipeline {} is still compiling or was removed",
+                    **refer
+                ));
+                None
+            }
+        },
+        None => {
+            dump.line("Refer<RenderPipeline>: none");
+            dump.problem("missing Refer<RenderPipeline>");
+            None
+        }
+    };
+
+    dump_bind_groups(&mut dump, world, &entity_ref, pipeline);
+    let mesh = dump_mesh(&mut dump, world, &entity_ref);
+    if let (Some(pipeline), Some(mesh)) = (pipeline, mesh) {
+        if pipeline.topology() != mesh.primitive_topology {
+            dump.problem(format!(
+                "the pipeline draws {:?} but the mesh is {:?}",
+                pipeline.topology(),
+                mesh.primitive_topology
+            ));
+        }
+    }
+
+    match entity_ref.get::<InstanceData>() {
+        Some(instances) => {
+            dump.line(format!(
+                "InstanceData: {} of {} instances",
+                instances.count(),
+                instances.capacity()
+            ));
+            if instances.count() == 0 {
+                dump.problem("no instances are drawn, all are culled or none were written");
+            }
+        }
+        None => dump.line("InstanceData: none, drawn once"),
+    }
+    if let Some(source) = entity_ref.get::<InstanceSource>() {
+        dump.line(format!("InstanceSource: {} instances", source.0.len()));
+        if !entity_ref.contains::<Aabb>() {
+            dump.problem("InstanceSource needs an Aabb to be culled");
+        }
+    }
+
+    let visibility = entity_ref.get::<Visibility>();
+    let computed = entity_ref.get::<ComputedVisibility>();
+    match visibility {
+        Some(visibility) => dump.line(format!("Visibility: visible {}", visibility.visible)),
+        None => dump.line("Visibility: none, visible"),
+    }
+    match computed {
+        Some(computed) => dump.line(format!(
+            "ComputedVisibility: visible {}",
+            computed.is_visible()
+        )),
+        None => dump.line("ComputedVisibility: none, not computed yet"),
+    }
+    // NOTE: a new Visibility is computed in `CoreStage::PostUpdate`
+    let visible = computed.map_or_else(
+        || visibility.map_or(true, |visibility| visibility.visible),
+        ComputedVisibility::is_visible,
+    );
+    if !visible {
+        dump.problem("hidden by its Visibility");
+    }
+
+    match entity_ref.get::<Aabb>() {
+        Some(aabb) => dump.line(format!(
+            "Aabb: min {:?} max {:?}",
+            <[f32; 3]>::from(aabb.min),
+            <[f32; 3]>::from(aabb.max)
+        )),
+        None => dump.line("Aabb: none, not pickable"),
+    }
+    match entity_ref.get::<Transform>() {
+        Some(transform) => {
+            let rotation = transform.rotation;
+            dump.line(format!(
+                "Transform: translation {:?} rotation {:?} scale {:?}",
+                <[f32; 3]>::from(transform.translation),
+                [rotation.v.x, rotation.v.y, rotation.v.z, rotation.s],
+                <[f32; 3]>::from(transform.scale)
+            ));
+            dump_matrix(&mut dump, &transform.compute_matrix());
+        }
+        None => dump.line("Transform: none"),
+    }
+
+    dump.finish()
+}
+
+struct EntityDump {
+    out: String,
+    problems: Vec<String>,
+}
+
+impl EntityDump {
+    fn line(&mut self, line: impl AsRef<str>) {
+        let _ = writeln!(self.out, "  {}", line.as_ref());
+    }
+
+    fn problem(&mut self, problem: impl Into<String>) {
+        self.problems.push(problem.into());
+    }
+
+    fn finish(mut self) -> String {
+        if self.problems.is_empty() {
+            self.out.push_str("No problems found\n");
+        } else {
+            self.out.push_str("Problems:\n");
+            for problem in &self.problems {
+                let _ = writeln!(self.out, "  - {}", problem);
+            }
+        }
+        self.out
+    }
+}
+
+fn dump_bind_groups(
+    dump: &mut EntityDump,
+    world: &World,
+    entity_ref: &EntityRef,
+    pipeline: Option<&RenderPipeline>,
+) {
+    let bind_groups = world.get_resource::<Store<wgpu::BindGroup>>();
+    let exists = |key: usize| bind_groups.map_or(false, |store| store.get(key).is_some());
+    match entity_ref.get::<BindSlots>() {
+        Some(binds) => {
+            let slots: Vec<String> = binds
+                .iter()
+                .map(|(group, key)| {
+                    if exists(key) {
+                        format!("{}: {}", group, key)
+                    } else {
+                        format!("{}: {} (removed)", group, key)
+                    }
+                })
+                .collect();
+            dump.line(format!("BindSlots: [{}]", slots.join(", ")));
+            for (group, key) in binds.iter().filter(|(_, key)| !exists(*key)) {
+                dump.problem(format!("bind group {} in slot {} was removed", key, group));
+            }
+            if let Some(group) = pipeline.and_then(|p| binds.missing(p.bind_group_count())) {
+                dump.problem(format!("no bind group in slot {} the pipeline uses", group));
+            }
+        }
+        None => {
+            dump.line("BindSlots: none");
+            dump.problem("missing BindSlots");
+        }
+    }
+    if let Some(keys) = entity_ref.get::<ReferMany<wgpu::BindGroup>>() {
+        let missing = keys.iter().filter(|key| !exists(**key)).count();
+        dump.line(format!(
+            "ReferMany<BindGroup>: {:?}, {} removed, not read by the draws",
+            **keys, missing
+        ));
+    }
+}
+
+fn dump_mesh<'a>(
+    dump: &mut EntityDump,
+    world: &'a World,
+    entity_ref: &EntityRef<'a>,
+) -> Option<&'a GpuMesh> {
+    let mesh = match (
+        entity_ref.get::<GpuMesh>(),
+        entity_ref.get::<Refer<GpuMesh>>(),
+    ) {
+        (Some(mesh), _) => {
+            dump.line("GpuMesh: owned");
+            mesh
+        }
+        (None, Some(refer)) => {
+            let meshes = world.get_resource::<Store<GpuMesh>>();
+            match meshes.and_then(|meshes| refer.get(meshes)) {
+                Some(mesh) => {
+                    dump.line(format!("Refer<GpuMesh>: {}", **refer));
+                    mesh
+                }
+                None => {
+                    dump.line(format!("Refer<GpuMesh>: {}, not in the store", **refer));
+                    dump.problem(format!("mesh {} was removed", **refer));
+                    return None;
+                }
+            }
+        }
+        (None, None) => {
+            dump.line("GpuMesh: none");
+            dump.problem("missing GpuMesh or Refer<GpuMesh>");
+            return None;
+        }
+    };
+    let assembly = match &mesh.assembly {
+        GpuMeshAssembly::Indexed {
+            index_count,
+            index_format,
+            ..
+        } => format!("{} indices {:?}", index_count, index_format),
+        GpuMeshAssembly::NonIndexed { vertex_count } => format!("{} vertices", vertex_count),
+    };
+    dump.line(format!(
+        "  {:?}, {}, {} bytes",
+        mesh.primitive_topology, assembly, mesh.buffer_size
+    ));
+    Some(mesh)
+}
+
+fn dump_matrix(dump: &mut EntityDump, matrix: &Matrix4<f32>) {
+    // NOTE: cgmath matrices are column major, printed by rows
+    for row in 0..4 {
+        dump.line(format!(
+            "  [{:>9.3} {:>9.3} {:>9.3} {:>9.3}]",
+            matrix.x[row], matrix.y[row], matrix.z[row], matrix.w[row]
+        ));
+    }
+}
+
+/// Logs [`debug_dump_entity`] of the entity under the cursor of the primary window when
+/// the [`EntityDumpKeys`] are pressed. Needs the world, not added by the plugins:
+///
+/// ```ignore
+/// app.add_system_to_stage(CoreStage::PostUpdate, dump_entity_under_cursor_system.exclusive_system());
+/// ```
+pub fn dump_entity_under_cursor_system(world: &mut World) {
+    let chord = world
+        .get_resource::<EntityDumpKeys>()
+        .copied()
+        .unwrap_or_default();
+    let pressed = world
+        .get_resource::<Input<KeyCode>>()
+        .map_or(false, |keys| chord.just_pressed(keys));
+    if !pressed {
+        return;
+    }
+    let ray = match (
+        world.get_resource::<Camera>(),
+        world.get_resource::<wgpu::SurfaceConfiguration>(),
+        world.get_resource::<Windows>(),
+    ) {
+        (Some(camera), Some(config), Some(windows)) => cursor_ray(
+            camera,
+            config,
+            windows,
+            world
+                .get_resource::<DepthConvention>()
+                .copied()
+                .unwrap_or_default(),
+        ),
+        _ => None,
+    };
+    let ray = match ray {
+        Some(ray) => ray,
+        None => {
+            log::info!("No cursor over the primary window, nothing to dump");
+            return;
+        }
+    };
+    let mut pickables = world.query::<(Entity, &Aabb, &Transform)>();
+    match pick(&ray, pickables.iter(world)) {
+        Some(picked) => log::info!("{}", debug_dump_entity(world, picked.entity)),
+        None => log::info!("No entity with an Aabb under the cursor"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_ecs::world::World;
+    use cgmath::Vector3;
+
+    use crate::{
+        render::{
+            mesh::GpuMesh,
+            resource::{bind::BindSlots, pipeline::RenderPipeline},
+            visibility::Visibility,
+        },
+        transform::Transform,
+        util::{Refer, Store},
+    };
+
+    use super::debug_dump_entity;
+
+    #[test]
+    fn dump_names_what_is_missing() {
+        let mut world = World::new();
+        world.insert_resource(Store::<RenderPipeline>::default());
+        world.insert_resource(Store::<GpuMesh>::default());
+
+        let bare = world
+            .spawn()
+            .insert(Transform::from_translation(Vector3::new(1.0, 2.0, 3.0)))
+            .id();
+        let dump = debug_dump_entity(&world, bare);
+        assert!(dump.starts_with(&format!("{:?}\n", bare)));
+        assert!(dump.contains("missing Refer<RenderPipeline>"));
+        assert!(dump.contains("missing BindSlots"));
+        assert!(dump.contains("missing GpuMesh or Refer<GpuMesh>"));
+        assert!(dump.contains("translation [1.0, 2.0, 3.0]"));
+        // the translation column, as the last entry of the first three rows
+        assert!(dump.contains("    2.000]\n"));
+        assert!(dump.contains("    3.000]\n"));
+
+        // keys that point nowhere, and hidden
+        let dangling = world
+            .spawn()
+            .insert(Refer::<RenderPipeline>::new(7))
+            .insert(Refer::<GpuMesh>::new(3))
+            .insert(BindSlots::new().with(0, 5))
+            .insert(Visibility::hidden())
+            .id();
+        let dump = debug_dump_entity(&world, dangling);
+        assert!(!dump.contains("missing"));
+        assert!(dump.contains("pipeline 7 is still compiling or was removed"));
+        assert!(dump.contains("mesh 3 was removed"));
+        assert!(dump.contains("bind group 5 in slot 0 was removed"));
+        assert!(dump.contains("BindSlots: [0: 5 (removed)]"));
+        assert!(dump.contains("hidden by its Visibility"));
+        assert!(dump.contains("Transform: none"));
+
+        world.despawn(bare);
+        assert!(debug_dump_entity(&world, bare).contains("does not exist"));
+    }
+}
@@ -0,0 +1,299 @@
+use std::cmp::Ordering;
+
+use bevy_ecs::{
+    entity::Entity,
+    prelude::Component,
+    system::{Commands, Query, Res, ResMut},
+};
+use cgmath::{EuclideanSpace, MetricSpace, Point3};
+
+use crate::{
+    camera::Camera,
+    picking::BoundingSphere,
+    transform::Transform,
+    util::{Refer, Store},
+};
+
+use super::{
+    mesh::{GpuMesh, Mesh},
+    resource::buffer::PositionVertex,
+    RenderStats,
+};
+
+/// Meshes of decreasing detail in `Store<GpuMesh>`, picked by the distance from the camera.
+/// The selected one is written to the `Refer<GpuMesh>` of the entity in `CoreStage::PostUpdate`.
+#[derive(Component, Debug, Clone, PartialEq)]
+pub struct MeshLods {
+    // (max distance, mesh key) by increasing distance, the last one is also used beyond it
+    levels: Vec<(f32, usize)>,
+    /// Fraction of a threshold the distance has to pass it by before the level changes,
+    /// so an entity on the boundary does not switch every frame
+    pub hysteresis: f32,
+}
+
+impl MeshLods {
+    pub const DEFAULT_HYSTERESIS: f32 = 0.1;
+
+    /// `levels` are (max distance, key into `Store<GpuMesh>`) pairs, sorted by distance here
+    pub fn new(mut levels: Vec<(f32, usize)>) -> Self {
+        assert!(
+            !levels.is_empty(),
+            "an entity needs at least one level of detail"
+        );
+        levels.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+        Self {
+            levels,
+            hysteresis: Self::DEFAULT_HYSTERESIS,
+        }
+    }
+
+    /// Uploads `mesh` and its [`Mesh::simplify`] by each of `ratios` to `meshes`, e.g.
+    /// `&[0.5, 0.25, 0.1]`. The full mesh is drawn up to `distance`, each simplified one up
+    /// to twice the distance of the level before, the last one beyond
+    pub fn generate<V: PositionVertex>(
+        device: &wgpu::Device,
+        meshes: &mut Store<GpuMesh>,
+        mesh: &Mesh<V>,
+        ratios: &[f32],
+        distance: f32,
+    ) -> Self {
+        let mut levels = vec![(
+            distance,
+            meshes.insert(GpuMesh::from_mesh(mesh, device, Some("LOD 0"))),
+        )];
+        for (i, ratio) in ratios.iter().enumerate() {
+            let level = i + 1;
+            let max_distance = if level == ratios.len() {
+                f32::INFINITY
+            } else {
+                distance * 2f32.powi(level as i32)
+            };
+            let simplified = mesh.simplify(*ratio);
+            let label = format!("LOD {}", level);
+            let key = meshes.insert(GpuMesh::from_mesh(&simplified, device, Some(&label)));
+            levels.push((max_distance, key));
+        }
+        Self::new(levels)
+    }
+
+    pub fn with_hysteresis(mut self, hysteresis: f32) -> Self {
+        self.hysteresis = hysteresis.max(0.0);
+        self
+    }
+
+    pub fn levels(&self) -> &[(f32, usize)] {
+        &self.levels
+    }
+
+    pub fn mesh(&self, level: usize) -> usize {
+        self.levels[level.min(self.levels.len() - 1)].1
+    }
+
+    /// Level for `distance`, coming from `current`. A coarser level is taken once the
+    /// distance is past the threshold by the hysteresis, a finer one once it is short of it
+    pub fn select(&self, distance: f32, current: Option<usize>) -> usize {
+        let last = self.levels.len() - 1;
+        let mut level = match current {
+            Some(current) => current.min(last),
+            None => {
+                return self
+                    .levels
+                    .iter()
+                    .position(|(max_distance, _)| distance <= *max_distance)
+                    .unwrap_or(last)
+            }
+        };
+        let coarser_at = |level: usize| self.levels[level].0 * (1.0 + self.hysteresis);
+        let finer_at = |level: usize| self.levels[level].0 * (1.0 - self.hysteresis);
+
+        while level < last && distance > coarser_at(level) {
+            level += 1;
+        }
+        while level > 0 && distance < finer_at(level - 1) {
+            level -= 1;
+        }
+        level
+    }
+}
+
+/// The level of detail [`select_lod_system`] picked for the entity
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SelectedLod(pub usize);
+
+/// Draws every [`MeshLods`] entity at this level, clamped to its levels. For debugging
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ForcedLod(pub Option<usize>);
+
+/// Distances are from the eye of the [`Camera`] to the surface of the [`BoundingSphere`]
+/// of the entity, or to its translation without one. Without a camera or a [`Transform`]
+/// the finest level is used
+pub fn select_lod_system(
+    mut commands: Commands,
+    camera: Option<Res<Camera>>,
+    forced: Res<ForcedLod>,
+    mut stats: ResMut<RenderStats>,
+    mut entities: Query<(
+        Entity,
+        &MeshLods,
+        Option<&Transform>,
+        Option<&BoundingSphere>,
+        Option<&mut SelectedLod>,
+        Option<&mut Refer<GpuMesh>>,
+    )>,
+) {
+    let eye = camera.and_then(|camera| camera.eye());
+    stats.lod_entities.clear();
+
+    for (entity, lods, transform, sphere, selected, mesh) in entities.iter_mut() {
+        let distance = match (eye, transform, sphere) {
+            (Some(eye), Some(transform), Some(sphere)) => sphere
+                .transformed(&transform.compute_matrix())
+                .distance_to(eye),
+            (Some(eye), Some(transform), None) => {
+                eye.distance(Point3::from_vec(transform.translation))
+            }
+            _ => 0.0,
+        };
+        let current = selected.as_ref().map(|selected| selected.0);
+        let level = match forced.0 {
+            Some(level) => level.min(lods.levels.len() - 1),
+            None => lods.select(distance, current),
+        };
+
+        if stats.lod_entities.len() <= level {
+            stats.lod_entities.resize(level + 1, 0);
+        }
+        stats.lod_entities[level] += 1;
+
+        let key = lods.mesh(level);
+        match mesh {
+            Some(mut mesh) if **mesh != key => **mesh = key,
+            Some(_) => {}
+            None => {
+                commands.entity(entity).insert(Refer::<GpuMesh>::new(key));
+            }
+        }
+        match selected {
+            Some(mut selected) if selected.0 != level => selected.0 = level,
+            Some(_) => {}
+            None => {
+                commands.entity(entity).insert(SelectedLod(level));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_ecs::{
+        schedule::{Stage, SystemStage},
+        world::World,
+    };
+    use cgmath::{Matrix4, Point3, Vector3};
+
+    use crate::{
+        camera::{Camera, CameraView},
+        picking::BoundingSphere,
+        render::{mesh::GpuMesh, RenderStats},
+        transform::Transform,
+        util::Refer,
+    };
+
+    use super::{select_lod_system, ForcedLod, MeshLods, SelectedLod};
+
+    fn lods() -> MeshLods {
+        // meshes 10, 11 and 12, out of order on purpose
+        MeshLods::new(vec![(30.0, 11), (10.0, 10), (f32::INFINITY, 12)])
+    }
+
+    #[test]
+    fn levels_switch_past_the_hysteresis() {
+        let lods = lods();
+        assert_eq!(lods.levels()[0], (10.0, 10));
+
+        // without a previous level the thresholds are exact
+        assert_eq!(lods.select(5.0, None), 0);
+        assert_eq!(lods.select(10.5, None), 1);
+        assert_eq!(lods.select(100.0, None), 2);
+
+        // moving away, coarser only past 11
+        assert_eq!(lods.select(10.5, Some(0)), 0);
+        assert_eq!(lods.select(11.5, Some(0)), 1);
+        // moving back, finer only short of 9
+        assert_eq!(lods.select(9.5, Some(1)), 1);
+        assert_eq!(lods.select(8.5, Some(1)), 0);
+        // jumps over several levels
+        assert_eq!(lods.select(50.0, Some(0)), 2);
+        assert_eq!(lods.select(1.0, Some(2)), 0);
+
+        let lods = lods.with_hysteresis(0.0);
+        assert_eq!(lods.select(10.5, Some(0)), 1);
+        assert_eq!(lods.select(9.5, Some(1)), 0);
+    }
+
+    fn camera_at(z: f32) -> Camera {
+        Camera {
+            view_matrix: CameraView {
+                eye: Point3::new(0.0, 0.0, z),
+                target: Point3::new(0.0, 0.0, z - 1.0),
+                up: Vector3::unit_y(),
+            }
+            .build_view_matrix(),
+            projection_matrix: Matrix4::from_scale(1.0),
+        }
+    }
+
+    #[test]
+    fn system_follows_the_camera_across_thresholds() {
+        let mut world = World::new();
+        world.insert_resource(RenderStats::default());
+        world.insert_resource(ForcedLod::default());
+        world.insert_resource(camera_at(5.0));
+        let mut stage = SystemStage::single(select_lod_system);
+
+        let entity = world
+            .spawn()
+            .insert(lods())
+            .insert(Transform::from_translation(Vector3::new(0.0, 0.0, 0.0)))
+            .id();
+        let mut level_at = |world: &mut World, z: f32| {
+            world.insert_resource(camera_at(z));
+            stage.run(world);
+            let mesh = **world.get::<Refer<GpuMesh>>(entity).unwrap();
+            (world.get::<SelectedLod>(entity).unwrap().0, mesh)
+        };
+
+        assert_eq!(level_at(&mut world, 5.0), (0, 10));
+        // inside the band around 10 the level holds in both directions
+        assert_eq!(level_at(&mut world, 10.5), (0, 10));
+        assert_eq!(level_at(&mut world, 11.5), (1, 11));
+        assert_eq!(level_at(&mut world, 9.5), (1, 11));
+        assert_eq!(level_at(&mut world, 8.0), (0, 10));
+        assert_eq!(level_at(&mut world, 40.0), (2, 12));
+        assert_eq!(world.resource::<RenderStats>().lod_entities, vec![0, 0, 1]);
+
+        world.insert_resource(ForcedLod(Some(7)));
+        assert_eq!(level_at(&mut world, 5.0), (2, 12));
+        world.insert_resource(ForcedLod(Some(0)));
+        assert_eq!(level_at(&mut world, 40.0), (0, 10));
+        assert_eq!(world.resource::<RenderStats>().lod_entities, vec![1]);
+
+        // measured to the surface, a sphere of radius 2 scaled by 3 reaches 6 closer
+        world.insert_resource(ForcedLod::default());
+        world
+            .entity_mut(entity)
+            .remove::<SelectedLod>()
+            .insert_bundle((
+                BoundingSphere {
+                    center: Point3::new(0.0, 0.0, 0.0),
+                    radius: 2.0,
+                },
+                Transform {
+                    scale: Vector3::new(1.0, 3.0, 1.0),
+                    ..Default::default()
+                },
+            ));
+        assert_eq!(level_at(&mut world, 15.0), (0, 10));
+        assert_eq!(level_at(&mut world, 20.0), (1, 11));
+    }
+}
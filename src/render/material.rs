@@ -0,0 +1,688 @@
+use std::{
+    collections::{HashMap, HashSet},
+    marker::PhantomData,
+    sync::Arc,
+};
+
+use bevy_app::{App, CoreStage};
+use bevy_asset::{AssetServer, Assets, Handle, HandleId, LoadState};
+use bevy_ecs::{
+    entity::Entity,
+    event::EventReader,
+    prelude::Component,
+    query::{Changed, With, Without},
+    schedule::ParallelSystemDescriptorCoercion,
+    system::{Commands, Query, Res, ResMut},
+    world::Mut,
+};
+use bytemuck::{Pod, Zeroable};
+use repr_trait::C;
+
+use crate::{
+    asset::AssetReloaded,
+    camera::Camera,
+    color::Color,
+    light::GpuLights,
+    profiler::Profiler,
+    texture::{
+        BoundTexture, DefaultTextures, Texture, TextureHandle, TextureStore, TextureUnloaded,
+    },
+    transform::Transform,
+    util::{Refer, Store},
+    FlatSystemLabels,
+};
+
+use super::{
+    resource::{
+        bind::{
+            AsBindingSet, BindSlots, BindingSet, Uniform, UniformSyncBatcher, UniformSyncStats,
+            UpdateGpuUniform,
+        },
+        buffer::{MeshVertex, Vertex, VertexColored, VertexFull},
+        compiler::PipelineCompiler,
+        pipeline::RenderPipeline,
+        pipeline_cache::{PipelineCache, PipelineKey},
+        preprocess::{resolve_includes, ShaderDefs},
+        shader::{create_wgsl_module, Shader, ShaderSource, ShaderTargets},
+        shader_lib::ShaderInclude,
+        uniform_layout::gpu_uniform,
+    },
+    retire::StoreUsers,
+    targets::RenderTargets,
+    DepthConvention, DepthFormat, DepthPrepass,
+};
+
+/// A pipeline and its bind group, created from the shader at `shader_path`.
+/// The bind group is always bound at group 0, the [`GpuLights`] of lit materials at
+/// [`GpuLights::GROUP`].
+pub trait Material: Component + Sized {
+    /// GPU side of the material, its binding set is the material bind group
+    type Gpu: for<'a> AsBindingSet<'a> + Send + Sync + 'static;
+
+    /// Relative to the asset folder
+    fn shader_path() -> &'static str;
+    fn vertex_layouts() -> Vec<wgpu::VertexBufferLayout<'static>>;
+    /// Materials with different defs get different pipelines from the same shader
+    fn shader_defs(&self) -> ShaderDefs {
+        ShaderDefs::default()
+    }
+    /// The shader includes `common/lighting.wgsl` and is drawn with the lights of the frame
+    fn lit() -> bool {
+        false
+    }
+
+    fn prepare(&self, device: &wgpu::Device) -> Self::Gpu;
+    /// Called every frame before rendering, unchanged uniforms are not written
+    fn update(
+        &self,
+        gpu: &mut Self::Gpu,
+        uniforms: &mut UniformSyncBatcher,
+        camera: &Camera,
+        transform: &Transform,
+    );
+}
+
+#[derive(Component)]
+pub struct PreparedMaterial<M: Material>(pub M::Gpu);
+
+/// A material sampling one texture, which is replaced when it is unloaded,
+/// see [`replace_unloaded_textures_system`]
+pub trait TexturedMaterial: Material {
+    fn texture_mut(&mut self) -> &mut Arc<Texture>;
+}
+
+/// Drawn in place of a material whose shader could not be loaded or preprocessed, in a
+/// solid magenta. It only reads the object uniforms and the vertex position, which every
+/// material in this module starts with, so it fits their layouts
+pub const ERROR_MATERIAL_SHADER: &str = include_str!("../../res/error_material.wgsl");
+
+pub fn error_material_wgsl() -> String {
+    resolve_includes(
+        "error_material.wgsl",
+        ERROR_MATERIAL_SHADER,
+        &ShaderInclude::files(),
+    )
+    .expect("the error material only includes the shader library")
+}
+
+pub struct MaterialPipeline<M: Material> {
+    shader: Option<Handle<ShaderSource>>,
+    // target format of the pipelines, whether they have the depth prepass variants,
+    // their depth format and convention
+    target: Option<(wgpu::TextureFormat, bool, DepthFormat, DepthConvention)>,
+    // keyed by ShaderDefs::cache_key
+    pipelines: HashMap<u64, usize>,
+    _marker: PhantomData<fn() -> M>,
+}
+
+impl<M: Material> Default for MaterialPipeline<M> {
+    fn default() -> Self {
+        Self {
+            shader: None,
+            target: None,
+            pipelines: Default::default(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<M: Material> MaterialPipeline<M> {
+    pub fn pipeline(&self, defs: &ShaderDefs) -> Option<usize> {
+        self.pipelines.get(&defs.cache_key()).copied()
+    }
+}
+
+pub trait AddMaterial {
+    fn add_material<M: Material>(&mut self) -> &mut Self;
+}
+
+impl AddMaterial for App {
+    fn add_material<M: Material>(&mut self) -> &mut Self {
+        self.init_resource::<MaterialPipeline<M>>()
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                material_system::<M>
+                    .label(FlatSystemLabels::UniformSync)
+                    .after(FlatSystemLabels::VisibilityCompute),
+            )
+    }
+}
+
+/// Prepares added materials and wires `Refer<RenderPipeline>` and `BindSlots`
+/// once the shader is loaded. Pipelines are created by the [`PipelineCompiler`],
+/// entities are not drawn until theirs arrives. Material types requesting the same
+/// pipeline share it through the [`PipelineCache`].
+pub fn material_system<M: Material>(
+    mut commands: Commands,
+    device: Option<Res<Arc<wgpu::Device>>>,
+    queue: Option<Res<wgpu::Queue>>,
+    (render_targets, depth_prepass): (Res<Option<RenderTargets>>, Option<Res<DepthPrepass>>),
+    (camera, lights): (Option<Res<Camera>>, Option<Res<GpuLights>>),
+    asset_server: Res<AssetServer>,
+    sources: Res<Assets<ShaderSource>>,
+    mut material_pipeline: ResMut<MaterialPipeline<M>>,
+    (mut pipelines, mut compiler, mut cache): (
+        ResMut<Store<RenderPipeline>>,
+        ResMut<PipelineCompiler>,
+        ResMut<PipelineCache>,
+    ),
+    (mut bind_groups, mut bind_group_users): (
+        ResMut<Store<wgpu::BindGroup>>,
+        ResMut<StoreUsers<BindSlots>>,
+    ),
+    mut reloaded: EventReader<AssetReloaded<ShaderSource>>,
+    (mut uniform_stats, profiler): (ResMut<UniformSyncStats>, Option<Res<Profiler>>),
+    added: Query<(Entity, &M), Without<PreparedMaterial<M>>>,
+    unwired: Query<Entity, (With<PreparedMaterial<M>>, Without<Refer<RenderPipeline>>)>,
+    mut materials: Query<(&M, &mut PreparedMaterial<M>, Option<&Transform>)>,
+) {
+    let (device, queue) = match (device, queue) {
+        (Some(device), Some(queue)) => (device, queue),
+        _ => return,
+    };
+    // NOTE: lit materials wait for the lights, created in RenderStage::Prepare
+    let lights = match (M::lit(), lights) {
+        (false, _) => None,
+        (true, Some(lights)) => Some(lights),
+        (true, None) => return,
+    };
+    let render_targets = match *render_targets {
+        Some(render_targets) => render_targets,
+        None => return,
+    };
+    let (format, depth_format, depth_convention) = (
+        render_targets.main_pass_format(),
+        render_targets.depth_format,
+        render_targets.depth_convention,
+    );
+    let depth_prepass = depth_prepass.map_or(false, |prepass| prepass.0);
+
+    let handle = material_pipeline
+        .shader
+        .get_or_insert_with(|| asset_server.load(M::shader_path()))
+        .clone();
+
+    // NOTE: the removed pipelines are swept from entities in `CoreStage::Last`,
+    // they are wired to the recompiled ones on the next run
+    let reloaded = reloaded.iter().any(|event| event.handle.id == handle.id);
+    let target = (format, depth_prepass, depth_format, depth_convention);
+    let retargeted = material_pipeline.target.replace(target) != Some(target);
+    if reloaded || retargeted {
+        for (_, pipeline) in material_pipeline.pipelines.drain() {
+            // other material types may still draw with it
+            if cache.release(pipeline) {
+                pipelines.remove_with_events(pipeline);
+                compiler.cancel(pipeline);
+            }
+        }
+    }
+
+    for (entity, material) in added.iter() {
+        let gpu = material.prepare(&device);
+        let bind_group = bind_groups.insert(gpu.as_binding_set().into_bind_group(&device));
+        // NOTE: the bind group is the entity's own, freed once it is despawned
+        bind_group_users.reclaim_unused(bind_group);
+        let mut slots = BindSlots::new().with(0, bind_group);
+        if let Some(lights) = &lights {
+            slots = slots.with(GpuLights::GROUP, lights.bind_group);
+        }
+        commands
+            .entity(entity)
+            .insert_bundle((PreparedMaterial::<M>(gpu), slots));
+    }
+
+    let default_camera = Camera::default();
+    let camera = camera.as_deref().unwrap_or(&default_camera);
+    let scope = profiler
+        .as_ref()
+        .map(|profiler| profiler.scope("uniform sync"));
+    let mut uniforms = UniformSyncBatcher::new(&queue);
+    for (material, mut prepared, transform) in materials.iter_mut() {
+        material.update(
+            &mut prepared.0,
+            &mut uniforms,
+            camera,
+            &transform.copied().unwrap_or_default(),
+        );
+    }
+    *uniform_stats += uniforms.stats;
+    drop(scope);
+
+    // NOTE: without its shader every entity is drawn with the error material
+    let source = match sources.get(&handle) {
+        Some(source) => Some(source),
+        None if asset_server.get_load_state(&handle) == LoadState::Failed => None,
+        None => return,
+    };
+    for entity in unwired.iter() {
+        let (material, prepared, _) = match materials.get(entity) {
+            Ok(material) => material,
+            Err(_) => continue,
+        };
+        let defs = material.shader_defs();
+        let pipeline = match material_pipeline.pipeline(&defs) {
+            Some(pipeline) => pipeline,
+            None => {
+                let key = defs.cache_key();
+                let wgsl = match source.map(|source| source.preprocess(&defs)) {
+                    Some(Ok(wgsl)) => wgsl,
+                    Some(Err(error)) => {
+                        log::error!(
+                            "{}: {}, drawn with the error material",
+                            M::shader_path(),
+                            error
+                        );
+                        error_material_wgsl()
+                    }
+                    None => {
+                        log::error!(
+                            "{} could not be loaded, drawn with the error material",
+                            M::shader_path()
+                        );
+                        error_material_wgsl()
+                    }
+                };
+                let targets = ShaderTargets {
+                    defs,
+                    ..render_targets
+                        .shader_targets(M::vertex_layouts(), Some(wgpu::BlendState::REPLACE))
+                };
+                // NOTE: all materials of a type share the same layout
+                let entries = prepared.0.as_binding_set().layout_desc().entries;
+                let lights_entries = lights.as_ref().map(|lights| lights.layout_entries());
+                let mut layout_entries = vec![&entries[..]];
+                layout_entries.extend(lights_entries.as_deref());
+                let cache_key = PipelineKey::new(
+                    &wgsl,
+                    &layout_entries,
+                    &targets,
+                    wgpu::PrimitiveTopology::TriangleList,
+                    None,
+                    depth_prepass,
+                );
+                let pipeline = cache.acquire(cache_key, || {
+                    let layouts: Vec<wgpu::BindGroupLayout> = layout_entries
+                        .iter()
+                        .map(|entries| {
+                            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                                label: Some("Material Bind Group Layout"),
+                                entries,
+                            })
+                        })
+                        .collect();
+                    let pipeline = pipelines.reserve();
+                    compiler.compile(&device, pipeline, move |device| {
+                        let shader =
+                            Shader::with_targets(create_wgsl_module(device, wgsl), targets);
+                        RenderPipeline::create_with(
+                            device,
+                            &layouts.iter().collect::<Vec<_>>(),
+                            &shader,
+                            wgpu::PrimitiveTopology::TriangleList,
+                            None,
+                            depth_prepass,
+                        )
+                    });
+                    pipeline
+                });
+                material_pipeline.pipelines.insert(key, pipeline);
+                pipeline
+            }
+        };
+        commands
+            .entity(entity)
+            .insert(Refer::<RenderPipeline>::new(pipeline));
+    }
+}
+
+/// Removes the bind group of a material whose texture was swapped, it still holds the
+/// old one. `material_system` prepares the material again and keeps its pipeline
+fn unbind_material<M: TexturedMaterial>(
+    commands: &mut Commands,
+    bind_groups: &mut Store<wgpu::BindGroup>,
+    entity: Entity,
+    slots: Option<Mut<BindSlots>>,
+) {
+    if let Some(mut slots) = slots {
+        if let Some(key) = slots.get(0) {
+            bind_groups.remove_with_events(key);
+            slots.clear(0);
+        }
+    }
+    commands.entity(entity).remove::<PreparedMaterial<M>>();
+}
+
+/// Swaps [`DefaultTextures::white`] into materials whose [`TextureHandle`] was unloaded,
+/// they are not drawn until `material_system` prepares them again. The handle is kept and
+/// bound as a [`BoundTexture::Placeholder`], the image is uploaded again once the entity
+/// is visible
+pub fn replace_unloaded_textures_system<M: TexturedMaterial>(
+    mut commands: Commands,
+    mut events: EventReader<TextureUnloaded>,
+    defaults: Option<Res<DefaultTextures>>,
+    mut bind_groups: ResMut<Store<wgpu::BindGroup>>,
+    mut materials: Query<(
+        Entity,
+        &TextureHandle,
+        &mut M,
+        Option<&mut BoundTexture>,
+        Option<&mut BindSlots>,
+    )>,
+) {
+    let unloaded: HashSet<HandleId> = events.iter().map(|event| event.0).collect();
+    let defaults = match defaults {
+        Some(defaults) if !unloaded.is_empty() => defaults,
+        _ => return,
+    };
+    for (entity, handle, mut material, bound, slots) in materials.iter_mut() {
+        if !unloaded.contains(&handle.0) {
+            continue;
+        }
+        *material.texture_mut() = defaults.white.texture.clone();
+        unbind_material::<M>(&mut commands, &mut bind_groups, entity, slots);
+        match bound {
+            Some(mut bound) => *bound = BoundTexture::Placeholder,
+            None => {
+                commands.entity(entity).insert(BoundTexture::Placeholder);
+            }
+        }
+    }
+}
+
+/// Swaps the texture [`resolve_textures_system`](crate::texture::resolve_textures_system)
+/// bound into the material. Only the bind group is recreated, not the pipeline
+pub fn bind_resolved_textures_system<M: TexturedMaterial>(
+    mut commands: Commands,
+    defaults: Option<Res<DefaultTextures>>,
+    store: Res<TextureStore>,
+    mut bind_groups: ResMut<Store<wgpu::BindGroup>>,
+    mut materials: Query<
+        (
+            Entity,
+            &TextureHandle,
+            &BoundTexture,
+            &mut M,
+            Option<&mut BindSlots>,
+        ),
+        Changed<BoundTexture>,
+    >,
+) {
+    let defaults = match defaults {
+        Some(defaults) => defaults,
+        None => return,
+    };
+    for (entity, handle, bound, mut material, slots) in materials.iter_mut() {
+        let image = match defaults.get(*bound).or_else(|| store.get(&handle.0)) {
+            Some(image) => image,
+            None => continue,
+        };
+        if Arc::ptr_eq(material.texture_mut(), &image.texture) {
+            continue;
+        }
+        *material.texture_mut() = image.texture.clone();
+        unbind_material::<M>(&mut commands, &mut bind_groups, entity, slots);
+    }
+}
+
+impl UpdateGpuUniform for Color {
+    type GU = ColorUniform;
+
+    fn update_uniform(&self, gpu_uniform: &mut Self::GU) {
+        gpu_uniform.color = (*self).into();
+    }
+}
+
+gpu_uniform! {
+    /// Linear, see [`Color`]
+    #[repr(C)]
+    #[derive(Debug, Clone, Copy, C, Pod, Zeroable)]
+    pub struct ColorUniform {
+        pub color: [f32; 4] as vec4,
+    }
+}
+impl Default for ColorUniform {
+    fn default() -> Self {
+        Self {
+            color: [1.0, 1.0, 1.0, 1.0],
+        }
+    }
+}
+
+/// The per-object uniforms every material in this module starts with
+pub struct ObjectUniforms {
+    pub camera: Uniform<Camera>,
+    pub model: Uniform<Transform>,
+}
+
+impl ObjectUniforms {
+    pub fn new(device: &wgpu::Device) -> Self {
+        Self {
+            camera: Uniform::new_default(device, wgpu::ShaderStages::VERTEX),
+            model: Uniform::new_default(device, wgpu::ShaderStages::VERTEX),
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        uniforms: &mut UniformSyncBatcher,
+        camera: &Camera,
+        transform: &Transform,
+    ) {
+        self.camera.update(camera);
+        self.model.update(transform);
+        uniforms.sync(&mut self.camera);
+        uniforms.sync(&mut self.model);
+    }
+}
+
+#[derive(Component)]
+pub struct ColorMaterial {
+    pub color: Color,
+}
+
+pub struct GpuColorMaterial {
+    pub object: ObjectUniforms,
+    pub color: Uniform<Color>,
+}
+
+impl<'a> AsBindingSet<'a> for GpuColorMaterial {
+    type Set = (&'a Uniform<Camera>, &'a Uniform<Transform>, &'a Uniform<Color>);
+
+    fn as_binding_set(&'a self) -> Self::Set {
+        (&self.object.camera, &self.object.model, &self.color)
+    }
+}
+
+impl Material for ColorMaterial {
+    type Gpu = GpuColorMaterial;
+
+    fn shader_path() -> &'static str {
+        "color_material.wgsl"
+    }
+
+    fn vertex_layouts() -> Vec<wgpu::VertexBufferLayout<'static>> {
+        vec![Vertex::layout()]
+    }
+
+    fn prepare(&self, device: &wgpu::Device) -> Self::Gpu {
+        GpuColorMaterial {
+            object: ObjectUniforms::new(device),
+            color: Uniform::new_default(device, wgpu::ShaderStages::FRAGMENT),
+        }
+    }
+
+    fn update(
+        &self,
+        gpu: &mut Self::Gpu,
+        uniforms: &mut UniformSyncBatcher,
+        camera: &Camera,
+        transform: &Transform,
+    ) {
+        gpu.object.update(uniforms, camera, transform);
+        gpu.color.update(&self.color);
+        uniforms.sync(&mut gpu.color);
+    }
+}
+
+/// [`ColorMaterial`] lit by the lights of the frame, see `res/common/lighting.wgsl`.
+/// Needs the normals of a [`VertexFull`] mesh
+#[derive(Component)]
+pub struct LitColorMaterial {
+    pub color: Color,
+}
+
+impl Material for LitColorMaterial {
+    type Gpu = GpuColorMaterial;
+
+    fn shader_path() -> &'static str {
+        "lit_color_material.wgsl"
+    }
+
+    fn vertex_layouts() -> Vec<wgpu::VertexBufferLayout<'static>> {
+        vec![VertexFull::layout()]
+    }
+
+    fn lit() -> bool {
+        true
+    }
+
+    fn prepare(&self, device: &wgpu::Device) -> Self::Gpu {
+        GpuColorMaterial {
+            object: ObjectUniforms::new(device),
+            color: Uniform::new_default(device, wgpu::ShaderStages::FRAGMENT),
+        }
+    }
+
+    fn update(
+        &self,
+        gpu: &mut Self::Gpu,
+        uniforms: &mut UniformSyncBatcher,
+        camera: &Camera,
+        transform: &Transform,
+    ) {
+        gpu.object.update(uniforms, camera, transform);
+        gpu.color.update(&self.color);
+        uniforms.sync(&mut gpu.color);
+    }
+}
+
+#[derive(Component)]
+pub struct TextureMaterial {
+    pub texture: Arc<Texture>,
+}
+
+pub struct GpuTextureMaterial {
+    pub object: ObjectUniforms,
+    pub texture: Arc<Texture>,
+}
+
+impl<'a> AsBindingSet<'a> for GpuTextureMaterial {
+    type Set = (
+        &'a Uniform<Camera>,
+        &'a Uniform<Transform>,
+        &'a wgpu::TextureView,
+        &'a wgpu::Sampler,
+    );
+
+    fn as_binding_set(&'a self) -> Self::Set {
+        (
+            &self.object.camera,
+            &self.object.model,
+            &self.texture.view,
+            &self.texture.sampler,
+        )
+    }
+}
+
+impl Material for TextureMaterial {
+    type Gpu = GpuTextureMaterial;
+
+    fn shader_path() -> &'static str {
+        "texture_material.wgsl"
+    }
+
+    fn vertex_layouts() -> Vec<wgpu::VertexBufferLayout<'static>> {
+        vec![Vertex::layout()]
+    }
+
+    fn prepare(&self, device: &wgpu::Device) -> Self::Gpu {
+        GpuTextureMaterial {
+            object: ObjectUniforms::new(device),
+            texture: self.texture.clone(),
+        }
+    }
+
+    fn update(
+        &self,
+        gpu: &mut Self::Gpu,
+        uniforms: &mut UniformSyncBatcher,
+        camera: &Camera,
+        transform: &Transform,
+    ) {
+        gpu.object.update(uniforms, camera, transform);
+    }
+}
+
+impl TextureMaterial {
+    /// Samples [`DefaultTextures::white`] until the image of `id` is uploaded, and the
+    /// error texture if it fails to load
+    pub fn pending(
+        defaults: &DefaultTextures,
+        id: HandleId,
+    ) -> (Self, TextureHandle, BoundTexture) {
+        (
+            Self {
+                texture: defaults.white.texture.clone(),
+            },
+            TextureHandle(id),
+            BoundTexture::Placeholder,
+        )
+    }
+}
+
+impl TexturedMaterial for TextureMaterial {
+    fn texture_mut(&mut self) -> &mut Arc<Texture> {
+        &mut self.texture
+    }
+}
+
+/// [`TextureMaterial`] tinted by the vertex colors of a [`VertexColored`] mesh
+#[derive(Component)]
+pub struct VertexColorMaterial {
+    pub texture: Arc<Texture>,
+}
+
+impl Material for VertexColorMaterial {
+    type Gpu = GpuTextureMaterial;
+
+    fn shader_path() -> &'static str {
+        "vertex_color_material.wgsl"
+    }
+
+    fn vertex_layouts() -> Vec<wgpu::VertexBufferLayout<'static>> {
+        vec![VertexColored::layout()]
+    }
+
+    fn prepare(&self, device: &wgpu::Device) -> Self::Gpu {
+        GpuTextureMaterial {
+            object: ObjectUniforms::new(device),
+            texture: self.texture.clone(),
+        }
+    }
+
+    fn update(
+        &self,
+        gpu: &mut Self::Gpu,
+        uniforms: &mut UniformSyncBatcher,
+        camera: &Camera,
+        transform: &Transform,
+    ) {
+        gpu.object.update(uniforms, camera, transform);
+    }
+}
+
+impl TexturedMaterial for VertexColorMaterial {
+    fn texture_mut(&mut self) -> &mut Arc<Texture> {
+        &mut self.texture
+    }
+}
@@ -1,7 +1,31 @@
+use std::{
+    collections::{HashMap, HashSet},
+    hash::{Hash, Hasher},
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::bail;
+use bevy_asset::{AssetLoader, AssetPath, Handle, LoadedAsset};
 use bevy_ecs::prelude::Component;
+use bevy_reflect::TypeUuid;
+use cgmath::{InnerSpace, Point3, Vector2, Vector3, Zero};
 use wgpu::util::DeviceExt;
 
-use super::resource::buffer::{FromRawVertex, Indices, MeshVertex};
+use crate::{
+    asset::DecodePool,
+    picking::{Aabb, BoundingSphere},
+    util::{Refer, Store},
+};
+
+use super::resource::{
+    buffer::{
+        raw_element, raw_element_or, FromRawVertex, Indices, MeshVertex, PositionVertex,
+        TangentVertex, Vertex,
+    },
+    debug_label,
+};
 
 pub mod primitive;
 pub mod util;
@@ -10,6 +34,314 @@ pub struct Model<V: MeshVertex> {
     pub meshes: Vec<Mesh<V>>,
 }
 
+/// An OBJ file, parsed on the [`DecodePool`] by the [`ObjLoader`]. Every mesh and material
+/// is a labeled asset of its own, e.g. `asset_server.load("ship.obj#mesh/Hull")` or
+/// `"ship.obj#material/Red_Paint"`, see [`obj_labels`] for the labels.
+#[derive(TypeUuid)]
+#[uuid = "3A1F6C52-9B0E-4D7A-8E25-6F4B1C90D2E8"]
+pub struct ObjModel {
+    /// In file order, with their labels at the same index
+    pub meshes: Vec<Handle<ObjMesh>>,
+    pub mesh_labels: Vec<String>,
+    pub materials: Vec<Handle<ObjMaterial>>,
+    pub material_labels: Vec<String>,
+}
+
+impl ObjModel {
+    /// Label without the `mesh/` prefix
+    pub fn mesh(&self, label: &str) -> Option<&Handle<ObjMesh>> {
+        let index = self.mesh_labels.iter().position(|l| l == label)?;
+        self.meshes.get(index)
+    }
+
+    /// Label without the `material/` prefix
+    pub fn material(&self, label: &str) -> Option<&Handle<ObjMaterial>> {
+        let index = self.material_labels.iter().position(|l| l == label)?;
+        self.materials.get(index)
+    }
+}
+
+/// An object or group of an OBJ file
+#[derive(TypeUuid)]
+#[uuid = "8C0B2E4F-61A7-4F3D-9B58-2D7E0A94C3B1"]
+pub struct ObjMesh {
+    pub mesh: Mesh<Vertex>,
+    pub material: Option<Handle<ObjMaterial>>,
+}
+
+/// A material of the mtl files an OBJ file references, texture paths are as written there
+#[derive(Debug, Clone, PartialEq, TypeUuid)]
+#[uuid = "E4A97D13-5C2B-4B86-A0F1-7B3C6D29E85A"]
+pub struct ObjMaterial {
+    pub name: String,
+    pub ambient: [f32; 3],
+    pub diffuse: [f32; 3],
+    pub specular: [f32; 3],
+    pub shininess: f32,
+    /// Opacity, 1 is opaque
+    pub dissolve: f32,
+    pub diffuse_texture: Option<String>,
+    pub normal_texture: Option<String>,
+}
+
+impl From<tobj::Material> for ObjMaterial {
+    fn from(material: tobj::Material) -> Self {
+        let texture = |path: String| if path.is_empty() { None } else { Some(path) };
+        Self {
+            name: material.name,
+            ambient: material.ambient,
+            diffuse: material.diffuse,
+            specular: material.specular,
+            shininess: material.shininess,
+            dissolve: material.dissolve,
+            diffuse_texture: texture(material.diffuse_texture),
+            normal_texture: texture(material.normal_texture),
+        }
+    }
+}
+
+/// Labels of the named meshes or materials of an OBJ file, in the same order. Characters
+/// other than ASCII alphanumerics, `_`, `-` and `.` become `_`, so `#` and `/` cannot
+/// break the asset path. Repeated names get the first free numeric suffix, `Hull`,
+/// `Hull_1`, `Hull_2`, and empty ones are `unnamed`.
+pub fn obj_labels<'a>(names: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    let mut taken = HashSet::new();
+    names
+        .into_iter()
+        .map(|name| {
+            let mut label: String = name
+                .trim()
+                .chars()
+                .map(|c| match c {
+                    c if c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.') => c,
+                    _ => '_',
+                })
+                .collect();
+            if label.is_empty() {
+                label = "unnamed".to_string();
+            }
+            if taken.contains(&label) {
+                label = (1..)
+                    .map(|suffix| format!("{}_{}", label, suffix))
+                    .find(|label| !taken.contains(label))
+                    .unwrap();
+            }
+            taken.insert(label.clone());
+            label
+        })
+        .collect()
+}
+
+// the files named by `mtllib` lines, as tobj reads them
+fn obj_mtl_libs(bytes: &[u8]) -> Vec<String> {
+    String::from_utf8_lossy(bytes)
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("mtllib "))
+        .map(|name| name.trim().to_string())
+        .collect()
+}
+
+/// How the triangles of OBJ files become meshes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ObjLoadOptions {
+    /// `v -> 1 - v`, OBJ puts `v = 0` at the bottom of the image and this crate at the top
+    pub flip_v: bool,
+    /// Reverses every triangle, for files with clockwise front faces
+    pub flip_winding: bool,
+    /// Of the positions
+    pub scale: f32,
+    /// Flat normals for meshes without any, the triangles stop sharing vertices
+    pub generate_normals_if_missing: bool,
+}
+
+impl Default for ObjLoadOptions {
+    fn default() -> Self {
+        Self {
+            flip_v: true,
+            flip_winding: false,
+            scale: 1.0,
+            generate_normals_if_missing: false,
+        }
+    }
+}
+
+impl ObjLoadOptions {
+    fn apply(&self, mesh: &mut tobj::Mesh) {
+        if self.scale != 1.0 {
+            mesh.positions.iter_mut().for_each(|p| *p *= self.scale);
+        }
+        if self.flip_v {
+            for uv in mesh.texcoords.chunks_exact_mut(2) {
+                uv[1] = 1.0 - uv[1];
+            }
+        }
+        if self.flip_winding {
+            for triangle in mesh.indices.chunks_exact_mut(3) {
+                triangle.swap(1, 2);
+            }
+        }
+        // after the winding flip, the normals point out of the front faces
+        if self.generate_normals_if_missing && mesh.normals.is_empty() {
+            flat_normals(mesh);
+        }
+    }
+}
+
+// every triangle gets its own vertices, with the normal of its plane
+fn flat_normals(mesh: &mut tobj::Mesh) {
+    let indices = &mesh.indices[..mesh.indices.len() / 3 * 3];
+    let unshare = |values: &[f32], size: usize| -> Vec<f32> {
+        if values.is_empty() {
+            return Vec::new();
+        }
+        indices
+            .iter()
+            .flat_map(|&i| values[size * i as usize..][..size].iter().copied())
+            .collect()
+    };
+    let positions = unshare(&mesh.positions, 3);
+    let texcoords = unshare(&mesh.texcoords, 2);
+    let vertex_color = unshare(&mesh.vertex_color, 3);
+    let normals = positions
+        .chunks_exact(9)
+        .flat_map(|triangle| {
+            let corner = |i: usize| Vector3::new(triangle[i], triangle[i + 1], triangle[i + 2]);
+            let normal = (corner(3) - corner(0)).cross(corner(6) - corner(0));
+            let normal = if normal.magnitude2() > 0.0 {
+                normal.normalize()
+            } else {
+                Vector3::zero()
+            };
+            [normal.x, normal.y, normal.z].repeat(3)
+        })
+        .collect();
+    mesh.indices = (0..indices.len() as u32).collect();
+    mesh.positions = positions;
+    mesh.texcoords = texcoords;
+    mesh.vertex_color = vertex_color;
+    mesh.normals = normals;
+}
+
+struct ParsedObj {
+    // name, mesh and material index
+    meshes: Vec<(String, Mesh<Vertex>, Option<usize>)>,
+    materials: Vec<ObjMaterial>,
+}
+
+/// Materials come from `mtl_files`, by the name the OBJ file uses. A missing or broken one
+/// is logged and the meshes are loaded without materials.
+fn parse_obj(
+    bytes: &[u8],
+    mtl_files: &HashMap<PathBuf, Vec<u8>>,
+    options: &ObjLoadOptions,
+) -> anyhow::Result<ParsedObj> {
+    let (models, materials) = tobj::load_obj_buf(
+        &mut BufReader::new(bytes),
+        &tobj::GPU_LOAD_OPTIONS,
+        |path| match mtl_files.get(path) {
+            Some(mtl) => tobj::load_mtl_buf(&mut BufReader::new(mtl.as_slice())),
+            None => Err(tobj::LoadError::OpenFileFailed),
+        },
+    )?;
+    let materials = materials.unwrap_or_else(|error| {
+        log::warn!("OBJ materials could not be loaded: {}", error);
+        Vec::new()
+    });
+    let meshes = models
+        .into_iter()
+        .map(|model| {
+            let material = model.mesh.material_id.filter(|&id| id < materials.len());
+            (model.name, Mesh::from_obj(model.mesh, options), material)
+        })
+        .collect();
+    Ok(ParsedObj {
+        meshes,
+        materials: materials.into_iter().map(ObjMaterial::from).collect(),
+    })
+}
+
+pub struct ObjLoader {
+    pool: Arc<DecodePool>,
+    options: ObjLoadOptions,
+}
+
+impl ObjLoader {
+    pub fn new(pool: Arc<DecodePool>, options: ObjLoadOptions) -> Self {
+        Self { pool, options }
+    }
+}
+
+impl AssetLoader for ObjLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut bevy_asset::LoadContext,
+    ) -> bevy_asset::BoxedFuture<'a, anyhow::Result<(), anyhow::Error>> {
+        let bytes = bytes.to_vec();
+        let (pool, options) = (self.pool.clone(), self.options);
+        Box::pin(async move {
+            let folder = load_context
+                .path()
+                .parent()
+                .unwrap_or_else(|| Path::new(""));
+            let mut mtl_files = HashMap::new();
+            for name in obj_mtl_libs(&bytes) {
+                match load_context.read_asset_bytes(folder.join(&name)).await {
+                    Ok(mtl) => {
+                        mtl_files.insert(PathBuf::from(name), mtl);
+                    }
+                    Err(error) => log::warn!("Material library {} not read: {}", name, error),
+                }
+            }
+            let parsed = pool
+                .decode(move || parse_obj(&bytes, &mtl_files, &options))
+                .await?;
+
+            let path = load_context.path().to_path_buf();
+            let asset_path = |label: String| AssetPath::new(path.clone(), Some(label));
+
+            let material_labels = obj_labels(
+                parsed
+                    .materials
+                    .iter()
+                    .map(|material| material.name.as_str()),
+            );
+            let materials: Vec<Handle<ObjMaterial>> = material_labels
+                .iter()
+                .map(|label| load_context.get_handle(asset_path(format!("material/{}", label))))
+                .collect();
+            let mesh_labels = obj_labels(parsed.meshes.iter().map(|(name, ..)| name.as_str()));
+            let meshes: Vec<Handle<ObjMesh>> = mesh_labels
+                .iter()
+                .map(|label| load_context.get_handle(asset_path(format!("mesh/{}", label))))
+                .collect();
+
+            for (material, label) in parsed.materials.into_iter().zip(&material_labels) {
+                load_context
+                    .set_labeled_asset(&format!("material/{}", label), LoadedAsset::new(material));
+            }
+            for ((_, mesh, material), label) in parsed.meshes.into_iter().zip(&mesh_labels) {
+                let material = material.map(|index| materials[index].clone());
+                load_context.set_labeled_asset(
+                    &format!("mesh/{}", label),
+                    LoadedAsset::new(ObjMesh { mesh, material }),
+                );
+            }
+            load_context.set_default_asset(LoadedAsset::new(ObjModel {
+                meshes,
+                mesh_labels,
+                materials,
+                material_labels,
+            }));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["obj"]
+    }
+}
+
 pub struct Mesh<V: MeshVertex> {
     primitive_topology: wgpu::PrimitiveTopology,
     vertices: Vec<V>,
@@ -17,8 +349,6 @@ pub struct Mesh<V: MeshVertex> {
 }
 
 impl<V: MeshVertex> Mesh<V> {
-    const ZERO: f32 = 0.0;
-
     pub fn new(primitive_topology: wgpu::PrimitiveTopology) -> Self {
         Self {
             primitive_topology,
@@ -39,65 +369,80 @@ impl<V: MeshVertex> Mesh<V> {
         }
     }
 
-    pub fn load_obj(filepath: &str) -> Model<V>
+    pub fn load_obj(filepath: &str, options: &ObjLoadOptions) -> Model<V>
     where
         V: FromRawVertex,
     {
         let (models, _) = tobj::load_obj(filepath, &tobj::GPU_LOAD_OPTIONS)
             .expect("Obj file could not be loaded");
+        Self::model_from_obj(models, options)
+    }
+
+    /// Materials are not loaded, the [`ObjLoader`] loads them as labeled assets
+    pub fn load_obj_from_bytes(bytes: &[u8], options: &ObjLoadOptions) -> anyhow::Result<Model<V>>
+    where
+        V: FromRawVertex,
+    {
+        let (models, _) =
+            tobj::load_obj_buf(&mut BufReader::new(bytes), &tobj::GPU_LOAD_OPTIONS, |_| {
+                Ok(Default::default())
+            })?;
+        Ok(Self::model_from_obj(models, options))
+    }
 
+    fn model_from_obj(models: Vec<tobj::Model>, options: &ObjLoadOptions) -> Model<V>
+    where
+        V: FromRawVertex,
+    {
         let meshes: Vec<Mesh<V>> = models
             .into_iter()
-            .map(|model| {
-                let vertices: Vec<V> = (0..model.mesh.positions.len() / 3)
-                    .into_iter()
-                    .map(|i| {
-                        V::from_raw(
-                            &model.mesh.positions.as_slice()[i..i + 3]
-                                .try_into()
-                                .unwrap(),
-                            &[
-                                *model.mesh.texcoords.get(i).unwrap_or(&Self::ZERO),
-                                *model.mesh.texcoords.get(i + 1).unwrap_or(&Self::ZERO),
-                            ],
-                            &[
-                                *model.mesh.normals.get(i).unwrap_or(&Self::ZERO),
-                                *model.mesh.normals.get(i + 1).unwrap_or(&Self::ZERO),
-                                *model.mesh.normals.get(i + 2).unwrap_or(&Self::ZERO),
-                            ],
-                            &[
-                                *model.mesh.vertex_color.get(i).unwrap_or(&Self::ZERO),
-                                *model.mesh.vertex_color.get(i + 1).unwrap_or(&Self::ZERO),
-                                *model.mesh.vertex_color.get(i + 2).unwrap_or(&Self::ZERO),
-                            ],
-                            // &[0.0, 0.0],
-                            // &[0.0, 0.0, 0.0],
-                            // &[0.0, 0.0, 0.0],
-                            // &model.mesh.texcoords.as_slice()[i..i+2].try_into().unwrap_or([0.0, 0.0]),
-                            // &model.mesh.normals.as_slice()[i..i+3].try_into().unwrap_or([0.0, 0.0, 0.0]),
-                            // &model.mesh.vertex_color.as_slice()[i..i+3].try_into().unwrap_or([0.0, 0.0, 0.0]),
-                        )
-                    })
-                    .collect();
-
-                // V::from_raw(
-                //     &model.mesh.positions,
-                //     &model.mesh.texcoords,
-                //     &model.mesh.normals,
-                //     &model.mesh.vertex_color
-                // );
-
-                Self::with_all(
-                    wgpu::PrimitiveTopology::TriangleList,
-                    vertices,
-                    Some(Indices::U32(model.mesh.indices)),
-                )
-            })
+            .map(|model| Self::from_obj(model.mesh, options))
             .collect();
 
         Model { meshes }
     }
 
+    fn from_obj(mut mesh: tobj::Mesh, options: &ObjLoadOptions) -> Self
+    where
+        V: FromRawVertex,
+    {
+        options.apply(&mut mesh);
+        let vertices = Self::vertices_from_raw(
+            &mesh.positions,
+            &mesh.texcoords,
+            &mesh.normals,
+            &mesh.vertex_color,
+        );
+
+        Self::with_all(
+            wgpu::PrimitiveTopology::TriangleList,
+            vertices,
+            Some(Indices::U32(mesh.indices)),
+        )
+    }
+
+    /// One vertex per position triple, missing attributes are zero and missing colors white
+    pub fn vertices_from_raw(
+        positions: &[f32],
+        texcoords: &[f32],
+        normals: &[f32],
+        vertex_color: &[f32],
+    ) -> Vec<V>
+    where
+        V: FromRawVertex,
+    {
+        (0..positions.len() / 3)
+            .map(|i| {
+                V::from_raw(
+                    &raw_element(positions, i),
+                    &raw_element(texcoords, i),
+                    &raw_element(normals, i),
+                    &raw_element_or(vertex_color, i, 1.0),
+                )
+            })
+            .collect()
+    }
+
     pub fn get_vertices(&self) -> &[V] {
         &self.vertices
     }
@@ -130,6 +475,15 @@ impl<V: MeshVertex> Mesh<V> {
         self.primitive_topology
     }
 
+    /// What pipelines drawing this mesh need as `strip_index_format`,
+    /// `None` unless the mesh is an indexed strip
+    pub fn strip_index_format(&self) -> Option<wgpu::IndexFormat> {
+        match (is_strip(self.primitive_topology), &self.indices) {
+            (true, Some(indices)) => Some(indices.into()),
+            _ => None,
+        }
+    }
+
     pub fn get_index_buffer_bytes(&self) -> Option<&[u8]> {
         self.indices.as_ref().map(|inds| match inds {
             Indices::U16(ivals) => bytemuck::cast_slice(&ivals[..]),
@@ -153,6 +507,106 @@ impl<V: MeshVertex> Mesh<V> {
     pub fn vertex_count(&self) -> usize {
         self.vertices.len()
     }
+
+    /// Hash of the topology, vertex and index bytes, see [`MeshKey::Content`]
+    pub fn content_hash(&self) -> u64 {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        self.primitive_topology.hash(&mut hasher);
+        self.get_vertex_buffer_bytes().hash(&mut hasher);
+        self.get_index_buffer_bytes().hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+impl<V: PositionVertex> Mesh<V> {
+    /// Model space bounds, `None` without vertices
+    pub fn compute_bounding_sphere(&self) -> Option<BoundingSphere> {
+        BoundingSphere::from_points(
+            self.vertices
+                .iter()
+                .map(|vertex| Point3::from(vertex.position())),
+        )
+    }
+
+    /// Model space bounds, `None` without vertices
+    pub fn compute_aabb(&self) -> Option<Aabb> {
+        Aabb::from_points(
+            self.vertices
+                .iter()
+                .map(|vertex| Point3::from(vertex.position())),
+        )
+    }
+}
+
+impl<V: TangentVertex> Mesh<V> {
+    const DEGENERATE_EPSILON: f32 = 1e-8;
+
+    /// Accumulates per-triangle tangents and bitangents, then orthonormalizes
+    /// them against the vertex normal. Handedness is stored in `tangent.w`.
+    /// Triangles with degenerate uv mapping are skipped.
+    pub fn compute_tangents(&mut self) {
+        assert_eq!(
+            self.primitive_topology,
+            wgpu::PrimitiveTopology::TriangleList,
+            "Tangents can only be computed for TriangleList meshes"
+        );
+
+        let vertex_count = self.vertices.len();
+        let mut tangents = vec![Vector3::<f32>::zero(); vertex_count];
+        let mut bitangents = vec![Vector3::<f32>::zero(); vertex_count];
+
+        let indices: Vec<usize> = match &self.indices {
+            Some(indices) => indices.iter().map(|i| i as usize).collect(),
+            None => (0..vertex_count).collect(),
+        };
+
+        for tri in indices.chunks_exact(3) {
+            let (i0, i1, i2) = (tri[0], tri[1], tri[2]);
+            let (v0, v1, v2) = (&self.vertices[i0], &self.vertices[i1], &self.vertices[i2]);
+
+            let p0 = Vector3::from(v0.position());
+            let e1 = Vector3::from(v1.position()) - p0;
+            let e2 = Vector3::from(v2.position()) - p0;
+
+            let uv0 = Vector2::from(v0.tex_coords());
+            let d1 = Vector2::from(v1.tex_coords()) - uv0;
+            let d2 = Vector2::from(v2.tex_coords()) - uv0;
+
+            let det = d1.x * d2.y - d2.x * d1.y;
+            if det.abs() < Self::DEGENERATE_EPSILON {
+                continue;
+            }
+            let r = 1.0 / det;
+
+            let tangent = (e1 * d2.y - e2 * d1.y) * r;
+            let bitangent = (e2 * d1.x - e1 * d2.x) * r;
+
+            for i in [i0, i1, i2] {
+                tangents[i] += tangent;
+                bitangents[i] += bitangent;
+            }
+        }
+
+        for (i, vertex) in self.vertices.iter_mut().enumerate() {
+            let normal = Vector3::from(vertex.normal());
+            let tangent = tangents[i];
+
+            // Gram-Schmidt
+            let tangent = tangent - normal * normal.dot(tangent);
+            if tangent.magnitude2() < Self::DEGENERATE_EPSILON {
+                continue;
+            }
+            let tangent = tangent.normalize();
+
+            let handedness = if normal.cross(tangent).dot(bitangents[i]) < 0.0 {
+                -1.0
+            } else {
+                1.0
+            };
+
+            vertex.set_tangent([tangent.x, tangent.y, tangent.z, handedness]);
+        }
+    }
 }
 
 pub struct BatchMesh<V: MeshVertex> {
@@ -168,47 +622,86 @@ impl<V: MeshVertex> BatchMesh<V> {
         }
     }
 
-    pub fn add(&mut self, mesh: Mesh<V>) {
+    /// Indices of `mesh` are shifted past the vertices already in the batch,
+    /// promoting the batch to u32 indices once they do not fit in u16.
+    ///
+    /// Strip topologies are cut with a restart index between meshes, so their
+    /// batches need to be indexed and drawn with a pipeline created with
+    /// [`Mesh::strip_index_format`].
+    pub fn add(&mut self, mesh: Mesh<V>) -> anyhow::Result<()> {
+        let topology = self.inner_mesh.get_primitive_topology();
+        if mesh.primitive_topology != topology {
+            bail!(
+                "Can not batch a {:?} mesh into a {:?} batch",
+                mesh.primitive_topology,
+                topology
+            );
+        }
+        // TODO: OR: may convert non-indexed into indexed by triplet indexing
+        if self.indexed != mesh.indices.is_some() {
+            bail!(
+                "Index requirements does not match, the batch is {}",
+                if self.indexed {
+                    "indexed"
+                } else {
+                    "not indexed"
+                }
+            );
+        }
+        let strip = is_strip(topology);
+        if strip && !self.indexed && self.inner_mesh.vertex_count() > 0 {
+            bail!(
+                "{:?} batches need indices to restart the strip between meshes",
+                topology
+            );
+        }
+
         let (vertices, indices) = (mesh.vertices, mesh.indices);
-        let offset = vertices.len() as u32;
+        let offset = self.inner_mesh.vertex_count() as u32;
 
         self.inner_mesh.push_vertices(vertices);
 
-        match self.inner_mesh.get_indices_mut() {
-            Some(inner_indices) => {
-                match indices {
-                    Some(mut indices) => {
-                        indices.shift(offset);
-                        inner_indices.extend(indices);
-                    }
-                    // TODO: OR: may convert non-indexed into indexed
-                    // by triplet indexing
-                    None => panic!("Index requirements does not match"),
-                }
+        if let Some(mut indices) = indices {
+            if strip {
+                indices.shift_strip(offset);
+            } else {
+                indices.shift(offset);
             }
-            None => {
-                match (self.indexed, indices) {
-                    (true, Some(mut indices)) => {
-                        indices.shift(offset);
-                        self.inner_mesh.set_indices(indices);
+            match self.inner_mesh.get_indices_mut() {
+                Some(inner_indices) if strip => {
+                    // NOTE: same format on both sides, extend would not keep restart indices
+                    if let Indices::U32(_) = indices {
+                        inner_indices.promote_strip();
                     }
-                    (false, None) => {
-                        // Normal Case
+                    if let Indices::U32(_) = inner_indices {
+                        indices.promote_strip();
                     }
-                    // TODO: OR: may produce garbage gracefully
-                    _ => panic!("Index requirements does not match"),
+                    inner_indices.push_restart();
+                    inner_indices.extend(indices);
                 }
+                Some(inner_indices) => inner_indices.extend(indices),
+                None => self.inner_mesh.set_indices(indices),
             }
         }
+
+        Ok(())
     }
 
-    pub fn add_all(&mut self, meshes: impl IntoIterator<Item = Mesh<V>>) {
+    pub fn add_all(&mut self, meshes: impl IntoIterator<Item = Mesh<V>>) -> anyhow::Result<()> {
         for mesh in meshes {
-            self.add(mesh);
+            self.add(mesh)?;
         }
+        Ok(())
     }
 }
 
+fn is_strip(topology: wgpu::PrimitiveTopology) -> bool {
+    matches!(
+        topology,
+        wgpu::PrimitiveTopology::LineStrip | wgpu::PrimitiveTopology::TriangleStrip
+    )
+}
+
 impl<'a, V: MeshVertex> Into<&'a Mesh<V>> for &'a BatchMesh<V> {
     fn into(self) -> &'a Mesh<V> {
         &self.inner_mesh
@@ -232,37 +725,499 @@ pub struct GpuMesh {
     pub vertex_buffer: wgpu::Buffer,
     pub assembly: GpuMeshAssembly,
     pub primitive_topology: wgpu::PrimitiveTopology,
+    // vertex + index buffer bytes
+    pub buffer_size: wgpu::BufferAddress,
 }
 
 impl GpuMesh {
-    pub fn from_mesh<'a, V, M>(mesh: M, device: &wgpu::Device) -> GpuMesh
+    /// `label` names the buffers in validation errors, e.g. `Vertex Buffer: cube`
+    pub fn from_mesh<'a, V, M>(mesh: M, device: &wgpu::Device, label: Option<&str>) -> GpuMesh
     where
         V: MeshVertex,
         M: Into<&'a Mesh<V>>,
     {
         let mesh: &Mesh<V> = mesh.into();
+        let buffer_size = mesh.get_vertex_buffer_bytes().len()
+            + mesh.get_index_buffer_bytes().map_or(0, |bytes| bytes.len());
         GpuMesh {
             vertex_buffer_layout: mesh.get_vertex_buffer_layout(),
             vertex_buffer: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("Vertex Buffer"),
+                label: Some(&debug_label("Vertex Buffer", label)),
                 contents: &mesh.get_vertex_buffer_bytes(),
                 usage: wgpu::BufferUsages::VERTEX,
             }),
-            assembly: match mesh.get_index_buffer_bytes() {
-                Some(indices) => GpuMeshAssembly::Indexed {
+            // NOTE: the format follows the final variant, batches may have been promoted to u32
+            assembly: match (mesh.get_indices(), mesh.get_index_buffer_bytes()) {
+                (Some(indices), Some(bytes)) => GpuMeshAssembly::Indexed {
                     index_buffer: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                        label: Some("Index Buffer"),
-                        contents: indices,
+                        label: Some(&debug_label("Index Buffer", label)),
+                        contents: bytes,
                         usage: wgpu::BufferUsages::INDEX,
                     }),
-                    index_count: mesh.get_indices().unwrap().len(),
-                    index_format: mesh.get_indices().unwrap().into(),
+                    index_count: indices.len(),
+                    index_format: indices.into(),
                 },
-                None => GpuMeshAssembly::NonIndexed {
+                _ => GpuMeshAssembly::NonIndexed {
                     vertex_count: mesh.vertex_count(),
                 },
             },
             primitive_topology: mesh.get_primitive_topology(),
+            buffer_size: buffer_size as wgpu::BufferAddress,
+        }
+    }
+
+    pub fn index_format(&self) -> Option<wgpu::IndexFormat> {
+        match &self.assembly {
+            GpuMeshAssembly::Indexed { index_format, .. } => Some(*index_format),
+            GpuMeshAssembly::NonIndexed { .. } => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MeshKey {
+    Name(String),
+    Content(u64),
+}
+
+impl From<&str> for MeshKey {
+    fn from(name: &str) -> Self {
+        MeshKey::Name(name.to_string())
+    }
+}
+
+/// Keys of meshes in `Store<GpuMesh>` that are shared between entities
+#[derive(Default)]
+pub struct MeshCache(HashMap<MeshKey, usize>);
+
+impl MeshCache {
+    pub fn get(&self, key: &MeshKey) -> Option<Refer<GpuMesh>> {
+        self.0.get(key).map(|key| Refer::new(*key))
+    }
+
+    /// `create` is only called, and the mesh only uploaded, the first time `key` is seen
+    pub fn get_or_create<V: MeshVertex>(
+        &mut self,
+        device: &wgpu::Device,
+        meshes: &mut Store<GpuMesh>,
+        key: impl Into<MeshKey>,
+        create: impl FnOnce() -> Mesh<V>,
+    ) -> Refer<GpuMesh> {
+        let key = key.into();
+        let label = match &key {
+            MeshKey::Name(name) => Some(name.clone()),
+            MeshKey::Content(_) => None,
+        };
+        let key = *self.0.entry(key).or_insert_with(|| {
+            meshes.insert(GpuMesh::from_mesh(&create(), device, label.as_deref()))
+        });
+        Refer::new(key)
+    }
+}
+
+pub fn create_gpu_mesh_shared<V: MeshVertex>(
+    device: &wgpu::Device,
+    meshes: &mut Store<GpuMesh>,
+    cache: &mut MeshCache,
+    name: &str,
+    create: impl FnOnce() -> Mesh<V>,
+) -> Refer<GpuMesh> {
+    cache.get_or_create(device, meshes, name, create)
+}
+
+pub fn create_gpu_mesh_hashed<V: MeshVertex>(
+    device: &wgpu::Device,
+    meshes: &mut Store<GpuMesh>,
+    cache: &mut MeshCache,
+    mesh: &Mesh<V>,
+) -> Refer<GpuMesh> {
+    let key = MeshKey::Content(mesh.content_hash());
+    match cache.get(&key) {
+        Some(refer) => refer,
+        None => {
+            let refer = Refer::new(meshes.insert(GpuMesh::from_mesh(mesh, device, None)));
+            cache.0.insert(key, *refer);
+            refer
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, path::PathBuf};
+
+    use crate::render::resource::buffer::{Indices, Vertex, VertexColored, VertexFull};
+
+    use super::{obj_labels, obj_mtl_libs, parse_obj, BatchMesh, Mesh, ObjLoadOptions};
+
+    #[test]
+    fn obj_vertices_are_strided() {
+        let positions: Vec<f32> = (0..9).map(|v| v as f32).collect();
+        let texcoords: Vec<f32> = (0..6).map(|v| v as f32 * 0.1).collect();
+        let normals: Vec<f32> = (0..9).map(|v| -(v as f32)).collect();
+
+        let vertices: Vec<VertexFull> =
+            Mesh::vertices_from_raw(&positions, &texcoords, &normals, &[]);
+
+        for (i, vertex) in vertices.iter().enumerate() {
+            assert_eq!(vertex.position, positions[3 * i..3 * i + 3]);
+            assert_eq!(vertex.tex_coords, texcoords[2 * i..2 * i + 2]);
+            assert_eq!(vertex.normal, normals[3 * i..3 * i + 3]);
+        }
+    }
+
+    #[test]
+    fn obj_vertex_colors_round_trip() {
+        let model = Mesh::<VertexColored>::load_obj("res/vertex_colors.obj", &Default::default());
+        assert_eq!(model.meshes.len(), 1);
+        let mesh = &model.meshes[0];
+        assert_eq!(mesh.get_vertices().len(), 3);
+        assert_eq!(mesh.get_indices().map(Indices::len), Some(3));
+        // (position, color)
+        let corners = [
+            ([0.0, 0.0, 0.0], [1.0, 0.0, 0.0]),
+            ([1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+            ([0.0, 1.0, 0.0], [0.0, 0.0, 1.0]),
+        ];
+        for vertex in mesh.get_vertices() {
+            let (_, color) = corners
+                .iter()
+                .find(|(position, _)| *position == vertex.position)
+                .expect("a corner of the triangle");
+            assert_eq!(vertex.color, *color);
+            // v is flipped to a top left origin
+            assert_eq!(
+                vertex.tex_coords,
+                [vertex.position[0], 1.0 - vertex.position[1]]
+            );
+        }
+
+        let bytes = std::fs::read("res/vertex_colors.obj").unwrap();
+        let from_bytes =
+            Mesh::<VertexColored>::load_obj_from_bytes(&bytes, &Default::default()).unwrap();
+        assert_eq!(from_bytes.meshes[0].get_vertices(), mesh.get_vertices());
+
+        // most files have no colors, those are white rather than black
+        let positions = [0.0; 6];
+        let vertices: Vec<VertexColored> = Mesh::vertices_from_raw(&positions, &[], &[], &[]);
+        assert!(vertices.iter().all(|vertex| vertex.color == [1.0; 3]));
+    }
+
+    fn vertex(position: [f32; 3], tex_coords: [f32; 2]) -> VertexFull {
+        VertexFull {
+            position,
+            tex_coords,
+            normal: [0.0, 0.0, 1.0],
+            tangent: [0.0; 4],
+        }
+    }
+
+    #[test]
+    fn unit_quad_tangents() {
+        let mut quad = Mesh::with_all(
+            wgpu::PrimitiveTopology::TriangleList,
+            vec![
+                vertex([-0.5, -0.5, 0.0], [0.0, 0.0]),
+                vertex([0.5, -0.5, 0.0], [1.0, 0.0]),
+                vertex([0.5, 0.5, 0.0], [1.0, 1.0]),
+                vertex([-0.5, 0.5, 0.0], [0.0, 1.0]),
+            ],
+            Some(Indices::U16(vec![0, 1, 2, 2, 3, 0])),
+        );
+
+        quad.compute_tangents();
+
+        for v in quad.get_vertices() {
+            assert_eq!(v.tangent, [1.0, 0.0, 0.0, 1.0]);
+        }
+    }
+
+    #[test]
+    fn degenerate_uv_is_skipped() {
+        let mut tri = Mesh::with_all(
+            wgpu::PrimitiveTopology::TriangleList,
+            vec![
+                vertex([0.0, 0.0, 0.0], [0.5, 0.5]),
+                vertex([1.0, 0.0, 0.0], [0.5, 0.5]),
+                vertex([0.0, 1.0, 0.0], [0.5, 0.5]),
+            ],
+            Some(Indices::U16(vec![0, 1, 2])),
+        );
+
+        tri.compute_tangents();
+
+        for v in tri.get_vertices() {
+            assert!(v.tangent.iter().all(|c| !c.is_nan()));
         }
     }
+
+    #[test]
+    fn content_hash_identifies_equal_meshes() {
+        let triangle = |y: f32| {
+            Mesh::with_all(
+                wgpu::PrimitiveTopology::TriangleList,
+                vec![
+                    Vertex {
+                        position: [0.0, 0.0, 0.0],
+                        tex_coords: [0.0, 0.0],
+                    },
+                    Vertex {
+                        position: [1.0, 0.0, 0.0],
+                        tex_coords: [1.0, 0.0],
+                    },
+                    Vertex {
+                        position: [0.0, y, 0.0],
+                        tex_coords: [0.0, 1.0],
+                    },
+                ],
+                Some(Indices::U16(vec![0, 1, 2])),
+            )
+        };
+
+        assert_eq!(triangle(1.0).content_hash(), triangle(1.0).content_hash());
+        assert_ne!(triangle(1.0).content_hash(), triangle(2.0).content_hash());
+    }
+
+    fn quad() -> Mesh<Vertex> {
+        let vertex = |x: f32, y: f32| Vertex {
+            position: [x, y, 0.0],
+            tex_coords: [x, y],
+        };
+        Mesh::with_all(
+            wgpu::PrimitiveTopology::TriangleList,
+            vec![
+                vertex(0.0, 0.0),
+                vertex(1.0, 0.0),
+                vertex(1.0, 1.0),
+                vertex(0.0, 1.0),
+            ],
+            Some(Indices::U16(vec![0, 1, 2, 2, 3, 0])),
+        )
+    }
+
+    #[test]
+    fn batch_promotes_indices_past_u16() {
+        let mut batch = BatchMesh::new(wgpu::PrimitiveTopology::TriangleList, true);
+        batch.add_all((0..2).map(|_| quad())).unwrap();
+        let mesh: &Mesh<Vertex> = (&batch).into();
+        assert!(matches!(mesh.get_indices(), Some(Indices::U16(_))));
+        assert_eq!(
+            mesh.get_indices().unwrap().iter().collect::<Vec<_>>(),
+            [0, 1, 2, 2, 3, 0, 4, 5, 6, 6, 7, 4]
+        );
+
+        // 4 vertices each, 16384 quads fill the u16 range exactly
+        let mut batch = BatchMesh::new(wgpu::PrimitiveTopology::TriangleList, true);
+        batch.add_all((0..20_000).map(|_| quad())).unwrap();
+        let mesh: &Mesh<Vertex> = (&batch).into();
+        let indices = mesh.get_indices().unwrap();
+        assert!(matches!(indices, Indices::U32(_)));
+        let format: wgpu::IndexFormat = indices.into();
+        assert_eq!(format, wgpu::IndexFormat::Uint32);
+        assert_eq!(indices.len(), 20_000 * 6);
+        assert_eq!(indices.iter().max(), Some(mesh.vertex_count() as u32 - 1));
+    }
+
+    fn strip(x: f32) -> Mesh<Vertex> {
+        let vertex = |x: f32, y: f32| Vertex {
+            position: [x, y, 0.0],
+            tex_coords: [x, y],
+        };
+        Mesh::with_all(
+            wgpu::PrimitiveTopology::TriangleStrip,
+            vec![
+                vertex(x, 0.0),
+                vertex(x, 1.0),
+                vertex(x + 1.0, 0.0),
+                vertex(x + 1.0, 1.0),
+            ],
+            Some(Indices::U16(vec![0, 1, 2, 3])),
+        )
+    }
+
+    #[test]
+    fn strips_are_restarted_between_meshes() {
+        let mut batch = BatchMesh::new(wgpu::PrimitiveTopology::TriangleStrip, true);
+        batch.add_all([strip(0.0), strip(2.0)]).unwrap();
+        let mesh: &Mesh<Vertex> = (&batch).into();
+        assert_eq!(
+            mesh.get_indices().unwrap().iter().collect::<Vec<_>>(),
+            [0, 1, 2, 3, 0xFFFF, 4, 5, 6, 7]
+        );
+        assert_eq!(mesh.strip_index_format(), Some(wgpu::IndexFormat::Uint16));
+
+        // u16 indices can not reach the restart value, the batch is promoted before
+        let mut batch = BatchMesh::new(wgpu::PrimitiveTopology::TriangleStrip, true);
+        batch.add_all((0..16_384).map(|_| strip(0.0))).unwrap();
+        let mesh: &Mesh<Vertex> = (&batch).into();
+        let indices = mesh.get_indices().unwrap();
+        assert!(matches!(indices, Indices::U32(_)));
+        assert_eq!(indices.iter().filter(|i| *i == u32::MAX).count(), 16_383);
+        assert_eq!(
+            indices.iter().filter(|i| *i != u32::MAX).max(),
+            Some(16_384 * 4 - 1)
+        );
+    }
+
+    #[test]
+    fn mismatched_topology_is_rejected() {
+        let mut batch = BatchMesh::new(wgpu::PrimitiveTopology::TriangleList, true);
+        let err = batch.add(strip(0.0)).unwrap_err().to_string();
+        assert!(err.contains("TriangleStrip"), "{err}");
+        assert!(err.contains("TriangleList"), "{err}");
+        let mesh: &Mesh<Vertex> = (&batch).into();
+        assert_eq!(mesh.vertex_count(), 0);
+
+        let mut batch = BatchMesh::new(wgpu::PrimitiveTopology::TriangleStrip, false);
+        let unindexed = |x| {
+            let mut mesh = strip(x);
+            mesh.indices = None;
+            mesh
+        };
+        batch.add(unindexed(0.0)).unwrap();
+        assert!(batch.add(unindexed(2.0)).is_err());
+    }
+
+    #[test]
+    fn shift_promotes_on_overflow() {
+        let mut indices = Indices::U16(vec![0, 1, 2]);
+        indices.shift(65_534);
+        assert!(matches!(&indices, Indices::U32(vec) if vec == &[65_534, 65_535, 65_536]));
+
+        let mut indices = Indices::U16(vec![0, 1, 2]);
+        indices.shift(65_533);
+        assert!(matches!(&indices, Indices::U16(vec) if vec == &[65_533, 65_534, 65_535]));
+    }
+
+    #[test]
+    fn obj_labels_are_sanitized_and_unique() {
+        assert_eq!(
+            obj_labels(["Hull", "Sail", "Hull", "Hull_1", "Hull", ""]),
+            vec!["Hull", "Sail", "Hull_1", "Hull_1_1", "Hull_2", "unnamed"]
+        );
+        // nothing that would end or nest an asset label
+        assert_eq!(
+            obj_labels([" Paint/Red ", "a#b c", "v1.2-final"]),
+            vec!["Paint_Red", "a_b_c", "v1.2-final"]
+        );
+    }
+
+    #[test]
+    fn obj_materials_come_from_the_mtl_files() {
+        let obj = std::fs::read("res/ship.obj").unwrap();
+        assert_eq!(obj_mtl_libs(&obj), vec!["ship.mtl"]);
+
+        let mtl_files = HashMap::from([(
+            PathBuf::from("ship.mtl"),
+            std::fs::read("res/ship.mtl").unwrap(),
+        )]);
+        let parsed = parse_obj(&obj, &mtl_files, &Default::default()).unwrap();
+        let names: Vec<&str> = parsed
+            .meshes
+            .iter()
+            .map(|(name, ..)| name.as_str())
+            .collect();
+        assert_eq!(names, vec!["Hull", "Sail", "Hull"]);
+        assert_eq!(parsed.materials.len(), 2);
+        let material = |mesh: usize| {
+            let index = parsed.meshes[mesh].2.unwrap();
+            &parsed.materials[index]
+        };
+        assert_eq!(material(0).name, "Paint/Red");
+        assert_eq!(material(0).diffuse, [1.0, 0.0, 0.0]);
+        assert_eq!(material(1).diffuse_texture.as_deref(), Some("canvas.png"));
+        assert_eq!(material(0).diffuse_texture, None);
+
+        // the meshes still load without their materials
+        let parsed = parse_obj(&obj, &HashMap::new(), &Default::default()).unwrap();
+        assert_eq!(parsed.meshes.len(), 3);
+        assert!(parsed.materials.is_empty());
+        assert!(parsed
+            .meshes
+            .iter()
+            .all(|(.., material)| material.is_none()));
+    }
+
+    fn obj_quad(options: ObjLoadOptions) -> Mesh<VertexFull> {
+        let mut model = Mesh::load_obj("res/quad.obj", &options);
+        assert_eq!(model.meshes.len(), 1);
+        model.meshes.remove(0)
+    }
+
+    fn tex_coords_at(mesh: &Mesh<VertexFull>, position: [f32; 3]) -> [f32; 2] {
+        mesh.get_vertices()
+            .iter()
+            .find(|vertex| vertex.position == position)
+            .expect("a corner of the quad")
+            .tex_coords
+    }
+
+    #[test]
+    fn obj_v_is_flipped_to_a_top_left_origin() {
+        // the top left corner samples the first row of the image
+        let mesh = obj_quad(ObjLoadOptions::default());
+        assert_eq!(tex_coords_at(&mesh, [0.0, 1.0, 0.0]), [0.0, 0.0]);
+        assert_eq!(tex_coords_at(&mesh, [1.0, 0.0, 0.0]), [1.0, 1.0]);
+
+        let mesh = obj_quad(ObjLoadOptions {
+            flip_v: false,
+            scale: 2.0,
+            ..Default::default()
+        });
+        assert_eq!(tex_coords_at(&mesh, [0.0, 2.0, 0.0]), [0.0, 1.0]);
+        assert_eq!(tex_coords_at(&mesh, [2.0, 0.0, 0.0]), [1.0, 0.0]);
+    }
+
+    // z of the normal of each triangle by its winding, positive when counter-clockwise
+    // seen from +Z
+    fn winding_z(mesh: &Mesh<VertexFull>) -> Vec<f32> {
+        let indices: Vec<u32> = mesh.get_indices().unwrap().iter().collect();
+        let position = |i: u32| mesh.get_vertices()[i as usize].position;
+        indices
+            .chunks_exact(3)
+            .map(|t| {
+                let (a, b, c) = (position(t[0]), position(t[1]), position(t[2]));
+                (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn generated_normals_face_the_counter_clockwise_side() {
+        let mesh = obj_quad(ObjLoadOptions {
+            generate_normals_if_missing: true,
+            ..Default::default()
+        });
+        assert_eq!(mesh.vertex_count(), 6);
+        assert!(winding_z(&mesh).iter().all(|&z| z > 0.0));
+        assert!(mesh
+            .get_vertices()
+            .iter()
+            .all(|vertex| vertex.normal == [0.0, 0.0, 1.0]));
+
+        // a clockwise file: the front faces are counter-clockwise again, from -Z
+        let flipped = obj_quad(ObjLoadOptions {
+            flip_winding: true,
+            generate_normals_if_missing: true,
+            ..Default::default()
+        });
+        assert!(winding_z(&flipped).iter().all(|&z| z < 0.0));
+        assert!(flipped
+            .get_vertices()
+            .iter()
+            .all(|vertex| vertex.normal == [0.0, 0.0, -1.0]));
+        // the same corners and uvs per triangle, only in the other order
+        assert_eq!(
+            flipped.get_vertices()[0].tex_coords,
+            mesh.get_vertices()[0].tex_coords
+        );
+
+        // without the option normals stay missing and vertices shared
+        let shared = obj_quad(ObjLoadOptions::default());
+        assert_eq!(shared.vertex_count(), 4);
+        assert!(shared
+            .get_vertices()
+            .iter()
+            .all(|vertex| vertex.normal == [0.0; 3]));
+    }
 }
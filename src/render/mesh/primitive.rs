@@ -183,3 +183,49 @@ pub fn create_aa_plane(
         Some(Indices::U32(indices)),
     )
 }
+
+/// `sectors` around +y and `stacks` from pole to pole, `u` grows with the angle around.
+/// Vertices on the seam and the poles are duplicated for the UVs, at the exact same positions
+pub fn create_uv_sphere(radius: f32, sectors: u32, stacks: u32) -> Mesh<Vertex> {
+    let sectors = sectors.max(3);
+    let stacks = stacks.max(2);
+    let mut vertices = Vec::with_capacity(((stacks + 1) * (sectors + 1)) as usize);
+    let mut indices = Vec::with_capacity((2 * sectors * (stacks - 1) * 3) as usize);
+
+    for i in 0..stacks + 1 {
+        let v = i as f32 / stacks as f32;
+        let polar = v * std::f32::consts::PI;
+        let (y, ring) = match i {
+            0 => (radius, 0.0),
+            i if i == stacks => (-radius, 0.0),
+            _ => (radius * polar.cos(), radius * polar.sin()),
+        };
+        for j in 0..sectors + 1 {
+            let u = j as f32 / sectors as f32;
+            // the last column is the first one again
+            let azimuth = (j % sectors) as f32 / sectors as f32 * std::f32::consts::TAU;
+            vertices.push(Vertex {
+                position: [ring * azimuth.sin(), y, ring * azimuth.cos()],
+                tex_coords: [u, v],
+            });
+        }
+    }
+
+    let ind = |i: u32, j: u32| -> u32 { i * (sectors + 1) + j };
+    for i in 0..stacks {
+        for j in 0..sectors {
+            if i != 0 {
+                indices.extend(&[ind(i, j), ind(i + 1, j), ind(i, j + 1)]);
+            }
+            if i != stacks - 1 {
+                indices.extend(&[ind(i, j + 1), ind(i + 1, j), ind(i + 1, j + 1)]);
+            }
+        }
+    }
+
+    Mesh::with_all(
+        wgpu::PrimitiveTopology::TriangleList,
+        vertices,
+        Some(Indices::U32(indices)),
+    )
+}
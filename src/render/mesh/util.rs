@@ -1,23 +1,719 @@
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap},
+};
+
 use noise::{NoiseFn, Perlin, Seedable};
 
-use crate::render::resource::buffer::Vertex;
+use crate::{
+    picking::Aabb,
+    render::resource::buffer::{Indices, PositionVertex, TangentVertex},
+};
 
 use super::Mesh;
 
-pub fn randomize_y(mesh: &mut Mesh<Vertex>) {
-    let perlin = Perlin::new();
-    let perlin = perlin.set_seed(72189);
-    // let perlin: Clamp<[f64; 2]> = Clamp::new(&perlin);
-    // let perlin = perlin.set_bounds(-10.0, 10.0);
-    let vertices_full = mesh.get_vertices_mut();
-    let len = vertices_full.len();
-    let vertices = &mut vertices_full[0..len / 2];
-    for vertex in vertices {
-        let coord = [
-            0.5 + vertex.position[0] as f64,
-            0.5 + vertex.position[2] as f64,
+#[derive(Debug, Clone, Copy)]
+pub struct NoiseParams {
+    pub seed: u32,
+    pub frequency: f64,
+    pub amplitude: f32,
+    pub octaves: u32,
+}
+
+impl NoiseParams {
+    const LACUNARITY: f64 = 2.0;
+    const PERSISTENCE: f32 = 0.5;
+    // NOTE: perlin noise is zero on integer lattice points
+    const OFFSET: f64 = 0.5;
+
+    /// Fractal Brownian motion, in `[-amplitude, amplitude]`
+    pub fn fbm(&self, perlin: &Perlin, point: [f64; 3]) -> f32 {
+        let mut frequency = self.frequency;
+        let mut amplitude = 1.0;
+        let mut total = 0.0;
+        let mut total_amplitude = 0.0;
+        for _ in 0..self.octaves.max(1) {
+            let coord = point.map(|c| (Self::OFFSET + c) * frequency);
+            total += perlin.get(coord) as f32 * amplitude;
+            total_amplitude += amplitude;
+            frequency *= Self::LACUNARITY;
+            amplitude *= Self::PERSISTENCE;
+        }
+        self.amplitude * total / total_amplitude
+    }
+
+    fn perlin(&self) -> Perlin {
+        Perlin::new().set_seed(self.seed)
+    }
+}
+
+impl Default for NoiseParams {
+    fn default() -> Self {
+        Self {
+            seed: 72189,
+            frequency: 1.0,
+            amplitude: 1.0,
+            octaves: 1,
+        }
+    }
+}
+
+/// Displaces every vertex along +y by noise sampled at its xz position, `bounds` of the
+/// mesh are recomputed
+pub fn displace_y<V: PositionVertex>(
+    mesh: &mut Mesh<V>,
+    params: NoiseParams,
+    bounds: Option<&mut Aabb>,
+) {
+    let perlin = params.perlin();
+    for vertex in mesh.get_vertices_mut() {
+        let position = vertex.position_mut();
+        position[1] += params.fbm(&perlin, [position[0] as f64, 0.0, position[2] as f64]);
+    }
+    update_bounds(mesh, bounds);
+}
+
+/// Displaces every vertex along its normal by noise sampled at its position, `bounds` of
+/// the mesh are recomputed
+pub fn displace_along_normals<V: PositionVertex + TangentVertex>(
+    mesh: &mut Mesh<V>,
+    params: NoiseParams,
+    bounds: Option<&mut Aabb>,
+) {
+    let perlin = params.perlin();
+    for vertex in mesh.get_vertices_mut() {
+        let normal = vertex.normal();
+        let position = vertex.position_mut();
+        let val = params.fbm(&perlin, position.map(|c| c as f64));
+        for i in 0..3 {
+            position[i] += normal[i] * val;
+        }
+    }
+    update_bounds(mesh, bounds);
+}
+
+fn update_bounds<V: PositionVertex>(mesh: &Mesh<V>, bounds: Option<&mut Aabb>) {
+    if let (Some(bounds), Some(aabb)) = (bounds, mesh.compute_aabb()) {
+        *bounds = aabb;
+    }
+}
+
+/// [`Mesh::simplify`] does not go below this many triangles
+pub const MIN_SIMPLIFIED_TRIANGLES: usize = 8;
+
+impl<V: PositionVertex> Mesh<V> {
+    /// Collapses the edges of an indexed `TriangleList` by the least quadric error until
+    /// `target_ratio` of the triangles are left, at least [`MIN_SIMPLIFIED_TRIANGLES`].
+    /// Vertices with the same position are one point, a seam of the UVs is not a boundary.
+    /// Points on a boundary do not move, the other attributes are copied from the endpoint
+    /// closer to where an edge collapsed to. The same mesh always simplifies the same way
+    pub fn simplify(&self, target_ratio: f32) -> Mesh<V> {
+        assert_eq!(
+            self.primitive_topology,
+            wgpu::PrimitiveTopology::TriangleList,
+            "only triangle lists can be simplified"
+        );
+        let indices = self
+            .indices
+            .as_ref()
+            .expect("only indexed meshes can be simplified");
+        let positions: Vec<[f32; 3]> = self
+            .vertices
+            .iter()
+            .map(|vertex| vertex.position())
+            .collect();
+        let mut simplifier = Simplifier::new(&positions, indices.iter());
+        let target = (indices.len() / 3) as f32 * target_ratio.clamp(0.0, 1.0);
+        simplifier.collapse_to((target.round() as usize).max(MIN_SIMPLIFIED_TRIANGLES));
+
+        // only the vertices still referenced are kept, in their order
+        let mut remap = vec![u32::MAX; self.vertices.len()];
+        let mut vertices = Vec::new();
+        let mut new_indices = Vec::with_capacity(simplifier.triangle_count() * 3);
+        for vertex in simplifier.triangles().flatten() {
+            if remap[vertex as usize] == u32::MAX {
+                remap[vertex as usize] = vertices.len() as u32;
+                let mut simplified = self.vertices[vertex as usize];
+                *simplified.position_mut() = simplifier.position(vertex);
+                vertices.push(simplified);
+            }
+            new_indices.push(remap[vertex as usize]);
+        }
+        let indices = match indices {
+            Indices::U16(_) => Indices::U16(new_indices.into_iter().map(|i| i as u16).collect()),
+            Indices::U32(_) => Indices::U32(new_indices),
+        };
+        Mesh::with_all(self.primitive_topology, vertices, Some(indices))
+    }
+}
+
+/// Error of a position to a set of planes, `A`, `b` and `c` of `x^T A x + 2 b^T x + c`
+#[derive(Debug, Clone, Copy, Default)]
+struct Quadric {
+    // xx, xy, xz, yy, yz, zz
+    a: [f64; 6],
+    b: [f64; 3],
+    c: f64,
+}
+
+impl Quadric {
+    /// Of the plane through the triangle, weighted by its area
+    fn from_triangle(p: [[f64; 3]; 3]) -> Self {
+        let normal = cross(sub(p[1], p[0]), sub(p[2], p[0]));
+        let double_area = dot(normal, normal).sqrt();
+        if double_area == 0.0 {
+            return Self::default();
+        }
+        let n = normal.map(|c| c / double_area);
+        let d = -dot(n, p[0]);
+        let weight = double_area / 2.0;
+        Self {
+            a: [
+                n[0] * n[0],
+                n[0] * n[1],
+                n[0] * n[2],
+                n[1] * n[1],
+                n[1] * n[2],
+                n[2] * n[2],
+            ]
+            .map(|c| c * weight),
+            b: n.map(|c| c * d * weight),
+            c: d * d * weight,
+        }
+    }
+
+    fn add(&self, other: &Quadric) -> Quadric {
+        let mut sum = *self;
+        for (a, other) in sum.a.iter_mut().zip(other.a) {
+            *a += other;
+        }
+        for (b, other) in sum.b.iter_mut().zip(other.b) {
+            *b += other;
+        }
+        sum.c += other.c;
+        sum
+    }
+
+    fn error(&self, x: [f64; 3]) -> f64 {
+        let [xx, xy, xz, yy, yz, zz] = self.a;
+        let ax = [
+            xx * x[0] + xy * x[1] + xz * x[2],
+            xy * x[0] + yy * x[1] + yz * x[2],
+            xz * x[0] + yz * x[1] + zz * x[2],
         ];
-        let val = perlin.get(coord) as f32;
-        vertex.position[1] += val;
+        // NOTE: rounding can take a zero error just below
+        (dot(x, ax) + 2.0 * dot(self.b, x) + self.c).max(0.0)
+    }
+
+    /// The position of the least error, `None` when the planes are close to parallel
+    /// and it is far off or not unique
+    fn minimum(&self) -> Option<[f64; 3]> {
+        let [xx, xy, xz, yy, yz, zz] = self.a;
+        let det = xx * (yy * zz - yz * yz) - xy * (xy * zz - yz * xz) + xz * (xy * yz - yy * xz);
+        let trace = xx + yy + zz;
+        if det.abs() <= 1e-6 * trace * trace * trace {
+            return None;
+        }
+        let rhs = self.b.map(|c| -c);
+        let solve = |column: usize| {
+            let mut m = [[xx, xy, xz], [xy, yy, yz], [xz, yz, zz]];
+            for (row, value) in m.iter_mut().zip(rhs) {
+                row[column] = value;
+            }
+            let [r0, r1, r2] = m;
+            (r0[0] * (r1[1] * r2[2] - r1[2] * r2[1]) - r0[1] * (r1[0] * r2[2] - r1[2] * r2[0])
+                + r0[2] * (r1[0] * r2[1] - r1[1] * r2[0]))
+                / det
+        };
+        Some([solve(0), solve(1), solve(2)])
+    }
+}
+
+fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+/// An edge between points, `removed` moves into `kept` at `position`
+#[derive(Debug, Clone, Copy)]
+struct Collapse {
+    error: f64,
+    kept: usize,
+    removed: usize,
+    position: [f64; 3],
+    // of the points when it was queued, stale once either changed
+    versions: (u32, u32),
+}
+
+impl Collapse {
+    fn edge(&self) -> (usize, usize) {
+        (self.kept.min(self.removed), self.kept.max(self.removed))
+    }
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Collapse {
+    /// The least error is the greatest, ties by the lowest edge so the order is the
+    /// same every run
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .error
+            .total_cmp(&self.error)
+            .then_with(|| other.edge().cmp(&self.edge()))
+    }
+}
+
+/// Triangles keep the indices of the original vertices, vertices are grouped into points
+/// by their position and the edges are collapsed between points
+struct Simplifier {
+    point_of: Vec<usize>,
+    points: Vec<[f64; 3]>,
+    quadrics: Vec<Quadric>,
+    // on a boundary, or an edge of more than two triangles
+    locked: Vec<bool>,
+    versions: Vec<u32>,
+    point_vertices: Vec<Vec<u32>>,
+    point_triangles: Vec<Vec<usize>>,
+    triangles: Vec<[u32; 3]>,
+    alive: Vec<bool>,
+    alive_count: usize,
+}
+
+impl Simplifier {
+    fn new(positions: &[[f32; 3]], indices: impl Iterator<Item = u32>) -> Self {
+        let mut point_ids = HashMap::new();
+        let mut points = Vec::new();
+        let mut point_vertices: Vec<Vec<u32>> = Vec::new();
+        let point_of: Vec<usize> = positions
+            .iter()
+            .enumerate()
+            .map(|(vertex, position)| {
+                // NOTE: -0.0 + 0.0 is 0.0, both zeros are one point
+                let key = position.map(|c| (c + 0.0).to_bits());
+                let point = *point_ids.entry(key).or_insert_with(|| {
+                    points.push(position.map(|c| c as f64));
+                    point_vertices.push(Vec::new());
+                    points.len() - 1
+                });
+                point_vertices[point].push(vertex as u32);
+                point
+            })
+            .collect();
+
+        let indices: Vec<u32> = indices.collect();
+        let mut simplifier = Self {
+            quadrics: vec![Quadric::default(); points.len()],
+            locked: vec![false; points.len()],
+            versions: vec![0; points.len()],
+            point_triangles: vec![Vec::new(); points.len()],
+            point_of,
+            points,
+            point_vertices,
+            triangles: Vec::with_capacity(indices.len() / 3),
+            alive: Vec::with_capacity(indices.len() / 3),
+            alive_count: 0,
+        };
+        for triangle in indices.chunks_exact(3) {
+            let triangle = [triangle[0], triangle[1], triangle[2]];
+            let [a, b, c] = simplifier.corner_points(triangle);
+            // degenerate triangles have no area to keep
+            let alive = a != b && b != c && c != a;
+            let t = simplifier.triangles.len();
+            simplifier.triangles.push(triangle);
+            simplifier.alive.push(alive);
+            if alive {
+                simplifier.alive_count += 1;
+                let quadric = Quadric::from_triangle([a, b, c].map(|p| simplifier.points[p]));
+                for point in [a, b, c] {
+                    simplifier.quadrics[point] = simplifier.quadrics[point].add(&quadric);
+                    simplifier.point_triangles[point].push(t);
+                }
+            }
+        }
+
+        let mut edges = simplifier.edges();
+        edges.dedup_by(|next, first| {
+            if next.0 == first.0 {
+                first.1 += 1;
+            }
+            next.0 == first.0
+        });
+        for ((a, b), count) in edges {
+            if count != 2 {
+                simplifier.locked[a] = true;
+                simplifier.locked[b] = true;
+            }
+        }
+        simplifier
+    }
+
+    /// Sorted, an edge once for every triangle it is a side of, each counting 1
+    fn edges(&self) -> Vec<((usize, usize), usize)> {
+        let mut edges: Vec<_> = self
+            .alive_triangles()
+            .flat_map(|[a, b, c]| [(a, b), (b, c), (c, a)])
+            .map(|(a, b)| ((a.min(b), a.max(b)), 1))
+            .collect();
+        edges.sort_unstable();
+        edges
+    }
+
+    fn corner_points(&self, triangle: [u32; 3]) -> [usize; 3] {
+        triangle.map(|vertex| self.point_of[vertex as usize])
+    }
+
+    fn alive_triangles(&self) -> impl Iterator<Item = [usize; 3]> + '_ {
+        self.triangles
+            .iter()
+            .zip(&self.alive)
+            .filter(|(_, alive)| **alive)
+            .map(|(triangle, _)| self.corner_points(*triangle))
+    }
+
+    fn triangle_count(&self) -> usize {
+        self.alive_count
+    }
+
+    /// Of the original vertices, the ones of removed points are not referenced anymore
+    fn triangles(&self) -> impl Iterator<Item = [u32; 3]> + '_ {
+        self.triangles
+            .iter()
+            .zip(&self.alive)
+            .filter(|(_, alive)| **alive)
+            .map(|(triangle, _)| *triangle)
+    }
+
+    fn position(&self, vertex: u32) -> [f32; 3] {
+        self.points[self.point_of[vertex as usize]].map(|c| c as f32)
+    }
+
+    /// Sorted, without `point`
+    fn neighbors(&self, point: usize) -> Vec<usize> {
+        let mut neighbors: Vec<usize> = self.point_triangles[point]
+            .iter()
+            .filter(|t| self.alive[**t])
+            .flat_map(|t| self.corner_points(self.triangles[*t]))
+            .filter(|neighbor| *neighbor != point)
+            .collect();
+        neighbors.sort_unstable();
+        neighbors.dedup();
+        neighbors
+    }
+
+    fn plan(&self, a: usize, b: usize) -> Option<Collapse> {
+        let quadric = self.quadrics[a].add(&self.quadrics[b]);
+        let (pa, pb) = (self.points[a], self.points[b]);
+        let position = match (self.locked[a], self.locked[b]) {
+            (true, true) => return None,
+            (true, false) => pa,
+            (false, true) => pb,
+            (false, false) => quadric.minimum().unwrap_or_else(|| {
+                let midpoint = [0, 1, 2].map(|i| (pa[i] + pb[i]) / 2.0);
+                [pa, pb, midpoint]
+                    .into_iter()
+                    .min_by(|x, y| quadric.error(*x).total_cmp(&quadric.error(*y)))
+                    .unwrap()
+            }),
+        };
+        let distance = |p: [f64; 3]| dot(sub(p, position), sub(p, position));
+        let (kept, removed) = if distance(pb) < distance(pa) {
+            (b, a)
+        } else {
+            (a, b)
+        };
+        Some(Collapse {
+            error: quadric.error(position),
+            kept,
+            removed,
+            position,
+            versions: (self.versions[kept], self.versions[removed]),
+        })
+    }
+
+    /// The points around the edge stay a disc and no triangle turns over
+    fn can_collapse(&self, collapse: &Collapse) -> bool {
+        let (kept, removed) = (collapse.kept, collapse.removed);
+        let shared = |t: &usize| {
+            let corners = self.corner_points(self.triangles[*t]);
+            corners.contains(&kept) && corners.contains(&removed)
+        };
+        let edge_triangles = self.point_triangles[removed]
+            .iter()
+            .filter(|t| self.alive[**t] && shared(t))
+            .count();
+        let kept_neighbors = self.neighbors(kept);
+        let common = self
+            .neighbors(removed)
+            .iter()
+            .filter(|neighbor| kept_neighbors.binary_search(neighbor).is_ok())
+            .count();
+        if common != edge_triangles {
+            return false;
+        }
+
+        [kept, removed]
+            .iter()
+            .flat_map(|point| &self.point_triangles[*point])
+            .filter(|t| self.alive[**t] && !shared(t))
+            .all(|t| {
+                let corners = self.corner_points(self.triangles[*t]);
+                let before = corners.map(|p| self.points[p]);
+                let after = corners.map(|p| {
+                    if p == kept || p == removed {
+                        collapse.position
+                    } else {
+                        self.points[p]
+                    }
+                });
+                let normal = |p: [[f64; 3]; 3]| cross(sub(p[1], p[0]), sub(p[2], p[0]));
+                let (before, after) = (normal(before), normal(after));
+                dot(before, after) > 0.0 || dot(before, before) == 0.0
+            })
+    }
+
+    fn collapse(&mut self, collapse: &Collapse) {
+        let (kept, removed) = (collapse.kept, collapse.removed);
+        // vertices of the removed point take the ones of the kept point across the edge,
+        // the rest, e.g. the other side of a seam, move along with their own attributes
+        let mut replacement: HashMap<u32, u32> = HashMap::new();
+        for t in std::mem::take(&mut self.point_triangles[removed]) {
+            if !self.alive[t] {
+                continue;
+            }
+            let triangle = self.triangles[t];
+            let corners = self.corner_points(triangle);
+            if let Some(k) = corners.iter().position(|p| *p == kept) {
+                let r = corners.iter().position(|p| *p == removed).unwrap();
+                replacement.entry(triangle[r]).or_insert(triangle[k]);
+                self.alive[t] = false;
+                self.alive_count -= 1;
+            } else {
+                self.point_triangles[kept].push(t);
+            }
+        }
+        for vertex in std::mem::take(&mut self.point_vertices[removed]) {
+            if !replacement.contains_key(&vertex) {
+                self.point_of[vertex as usize] = kept;
+                self.point_vertices[kept].push(vertex);
+            }
+        }
+        for t in self.point_triangles[kept].iter() {
+            for vertex in self.triangles[*t].iter_mut() {
+                if let Some(replaced) = replacement.get(vertex) {
+                    *vertex = *replaced;
+                }
+            }
+        }
+        let alive = &self.alive;
+        self.point_triangles[kept].retain(|t| alive[*t]);
+
+        self.points[kept] = collapse.position;
+        self.quadrics[kept] = self.quadrics[kept].add(&self.quadrics[removed]);
+        self.versions[kept] += 1;
+        self.versions[removed] += 1;
+    }
+
+    fn collapse_to(&mut self, target: usize) {
+        let mut edges = self.edges();
+        edges.dedup();
+        let mut queue: BinaryHeap<Collapse> = edges
+            .into_iter()
+            .filter_map(|((a, b), _)| self.plan(a, b))
+            .collect();
+
+        while self.alive_count > target {
+            let collapse = match queue.pop() {
+                Some(collapse) => collapse,
+                None => break,
+            };
+            let (kept, removed) = (collapse.kept, collapse.removed);
+            if collapse.versions != (self.versions[kept], self.versions[removed])
+                || self.point_vertices[removed].is_empty()
+                || !self.can_collapse(&collapse)
+            {
+                continue;
+            }
+            self.collapse(&collapse);
+            for neighbor in self.neighbors(kept) {
+                queue.extend(self.plan(kept.min(neighbor), kept.max(neighbor)));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use cgmath::{InnerSpace, Vector3};
+
+    use crate::render::{
+        mesh::primitive::{create_aa_plane, create_uv_sphere, PlaneAlign},
+        resource::buffer::Vertex,
+    };
+
+    use super::*;
+
+    fn grid(n: usize) -> Mesh<Vertex> {
+        let vertices = (0..n * n)
+            .map(|i| Vertex {
+                position: [(i % n) as f32 * 0.37, 0.0, (i / n) as f32 * 0.37],
+                tex_coords: [0.0, 0.0],
+            })
+            .collect();
+        Mesh::with_all(wgpu::PrimitiveTopology::PointList, vertices, None)
+    }
+
+    fn height_checksum(mesh: &mut Mesh<Vertex>) -> f32 {
+        mesh.get_vertices_mut().iter().map(|v| v.position[1]).sum()
+    }
+
+    #[test]
+    fn displace_y_is_deterministic() {
+        // NOTE: recorded from noise 0.7, a change means the same seed gives other terrain
+        const CHECKSUM: f32 = -0.1719611;
+        let params = NoiseParams {
+            seed: 7,
+            frequency: 0.5,
+            amplitude: 2.0,
+            octaves: 4,
+        };
+
+        let mut a = grid(16);
+        displace_y(&mut a, params, None);
+        let checksum = height_checksum(&mut a);
+        assert!((checksum - CHECKSUM).abs() < 1e-4, "{checksum}");
+
+        let mut c = grid(16);
+        displace_y(&mut c, NoiseParams { seed: 8, ..params }, None);
+        assert_ne!(height_checksum(&mut a), height_checksum(&mut c));
+    }
+
+    #[test]
+    fn displacing_recomputes_the_bounds() {
+        let mut mesh = grid(8);
+        let mut bounds = mesh.compute_aabb().unwrap();
+        assert_eq!((bounds.min.y, bounds.max.y), (0.0, 0.0));
+
+        displace_y(&mut mesh, NoiseParams::default(), Some(&mut bounds));
+        assert_eq!(Some(bounds), mesh.compute_aabb());
+        assert!(bounds.min.y < 0.0 && bounds.max.y > 0.0);
+    }
+
+    #[test]
+    fn displace_y_covers_all_vertices_within_amplitude() {
+        let params = NoiseParams {
+            amplitude: 0.25,
+            octaves: 3,
+            ..Default::default()
+        };
+        let mut mesh = grid(8);
+        displace_y(&mut mesh, params, None);
+
+        let vertices = mesh.get_vertices_mut();
+        let last_half = &vertices[vertices.len() / 2..];
+        assert!(last_half.iter().any(|v| v.position[1] != 0.0));
+        assert!(vertices.iter().all(|v| v.position[1].abs() <= 0.25));
+    }
+
+    fn indices(mesh: &Mesh<Vertex>) -> Vec<u32> {
+        mesh.get_indices().unwrap().iter().collect()
+    }
+
+    #[test]
+    fn simplified_sphere_hits_the_targets() {
+        let sphere = create_uv_sphere(1.0, 32, 16);
+        let triangles = sphere.get_indices().unwrap().len() / 3;
+        assert_eq!(triangles, 960);
+
+        for ratio in [0.5, 0.25, 0.1] {
+            let simplified = sphere.simplify(ratio);
+            let indices = indices(&simplified);
+            let target = triangles as f32 * ratio;
+            let count = indices.len() / 3;
+            assert!(
+                (count as f32 - target).abs() <= 2.0,
+                "{count} triangles for {target}"
+            );
+
+            // every vertex left is used, none of the removed ones is
+            let used: HashSet<u32> = indices.iter().copied().collect();
+            assert_eq!(used.len(), simplified.vertex_count());
+            assert!(indices
+                .iter()
+                .all(|i| (*i as usize) < simplified.vertex_count()));
+            // still close to the sphere
+            for vertex in simplified.get_vertices() {
+                let radius = Vector3::from(vertex.position).magnitude();
+                assert!((radius - 1.0).abs() < 0.05, "{:?}", vertex.position);
+            }
+
+            let again = sphere.simplify(ratio);
+            assert_eq!(
+                again.get_vertex_buffer_bytes(),
+                simplified.get_vertex_buffer_bytes()
+            );
+            assert_eq!(
+                again.get_index_buffer_bytes(),
+                simplified.get_index_buffer_bytes()
+            );
+        }
+
+        let floor = sphere.simplify(0.0);
+        assert_eq!(indices(&floor).len(), MIN_SIMPLIFIED_TRIANGLES * 3);
+    }
+
+    #[test]
+    fn simplify_keeps_the_boundary() {
+        let plane = create_aa_plane(PlaneAlign::XZ, 4.0, 4.0, 8, 8, Vector3::new(0.0, 0.0, 0.0));
+        let on_boundary = |position: [f32; 3]| position[0].abs() == 2.0 || position[2].abs() == 2.0;
+        let boundary: Vec<[f32; 3]> = plane
+            .get_vertices()
+            .iter()
+            .map(|vertex| vertex.position)
+            .filter(|position| on_boundary(*position))
+            .collect();
+        assert_eq!(boundary.len(), 32);
+
+        let simplified = plane.simplify(0.1);
+        assert!(indices(&simplified).len() / 3 < 128);
+        for position in boundary {
+            assert!(
+                simplified
+                    .get_vertices()
+                    .iter()
+                    .any(|vertex| vertex.position == position),
+                "{position:?} was removed"
+            );
+        }
+        // the inside collapsed into it
+        assert!(simplified
+            .get_vertices()
+            .iter()
+            .all(|vertex| on_boundary(vertex.position)));
     }
 }
@@ -1,33 +1,334 @@
-use bevy_app::Plugin;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use bevy_app::{AppExit, CoreStage, Plugin};
 use bevy_asset::AddAsset;
 use bevy_ecs::{
+    entity::Entity,
+    event::{EventReader, EventWriter},
     prelude::Component,
-    system::{Query, Res},
+    query::{Or, With, Without},
+    schedule::ParallelSystemDescriptorCoercion,
+    system::{Local, Query, Res, ResMut},
 };
 
 use crate::{
-    texture,
-    util::{Refer, ReferMany, Store},
+    camera::ActiveCamera,
+    color::Color,
+    error::FlatError,
+    light::{collect_lights_system, prepare_lights_system, Lights},
+    profiler::Profiler,
+    texture::{
+        self, detect_failed_textures_system, prepare_images_system, resolve_textures_system,
+        track_texture_use_system, unload_textures_system, MissingTextures, PendingImages,
+        TextureLoadFailed, TextureStore, TextureUnloaded,
+    },
+    util::{publish_store_removals_system, AddStoreCleanup, Refer, Store},
+    window::{
+        commands::PresentMode,
+        events::{PresentModeChanged, WindowResized},
+        WindowId, Windows,
+    },
+    FlatSystemLabels, RenderStage,
 };
 
 use self::{
-    mesh::GpuMesh,
-    resource::pipeline::RenderPipeline,
+    capture::{
+        capture_frame_system, deliver_captured_frames_system, map_captured_frames_system,
+        FrameCapture,
+    },
+    compute::compute_system,
+    depth::{expose_depth_bind_group_system, prepare_depth_bind_group_system, DepthBindGroup},
+    frame_stats::{frame_stats_system, FrameStats},
+    gpu_error::{
+        exit_on_fatal_wgpu_error_system, forward_wgpu_errors_system, CrashReportSettings,
+        WgpuError,
+    },
+    indirect::{draw_indirect_batch, pack_indirect_batches_system, IndirectBatch},
+    lod::{select_lod_system, ForcedLod},
+    material::{
+        bind_resolved_textures_system, replace_unloaded_textures_system, AddMaterial,
+        ColorMaterial, LitColorMaterial, TextureMaterial, VertexColorMaterial,
+    },
+    mesh::{GpuMesh, MeshCache},
+    offscreen::OffscreenTarget,
+    render_target::{prepare_render_targets_system, render_to_texture_system},
+    shadow::{prepare_shadow_map_system, ShadowCaster, ShadowMap},
+    resource::bind::{sweep_removed_bind_slots_system, BindSlots, UniformSyncStats},
+    resource::buffer::{InstanceRaw, InstanceUnit},
+    resource::compiler::{
+        receive_pipelines_system, PipelineCompiler, PipelineProgress, PipelinesReady,
+    },
+    resource::pipeline::{ComputePipeline, DepthMode, RenderPipeline, WireframeVariant},
+    resource::pipeline_cache::PipelineCache,
+    resource::pool::{recycle_buffer_pool_system, BufferPool},
     resource::shader::{ShaderSource, ShaderSourceLoader, Shaders},
+    retire::{despawn_gpu_cleanup_system, end_retired_frame_system, RetiredResources, StoreUsers},
+    settings::negotiate_present_mode,
+    targets::{update_render_targets_system, RenderTargets},
+    timing::{read_pass_timings_system, PassTimer, PassTimings, TimedPass},
+    upload::{upload_system, UploadQueue},
+    visibility::{
+        compute_visibility_system, cull_instances_system, visible, ComputedVisibility,
+        VisibilityChanged,
+    },
+    wireframe::{wireframe_pipeline, Wireframe, WireframeEdges, WireframeMode},
 };
 
+pub mod capture;
+pub mod compute;
+pub mod depth;
+pub mod frame_stats;
+pub mod gpu_error;
+pub mod gpu_info;
+pub mod indirect;
+pub mod inspect;
+pub mod lod;
+pub mod material;
 pub mod mesh;
 pub mod mesh_bevy;
+pub mod offscreen;
+pub mod overlay;
+pub mod postprocess;
+pub mod render_target;
+pub mod shadow;
 pub mod resource;
+pub mod retire;
+pub mod settings;
+pub mod skin;
+pub mod targets;
+pub mod timing;
+pub mod upload;
+pub mod visibility;
+pub mod wireframe;
 
 pub struct FlatRenderPlugin;
 impl Plugin for FlatRenderPlugin {
     fn build(&self, app: &mut bevy_app::App) {
         app.init_resource::<Store<RenderPipeline>>()
+            .init_resource::<Store<ComputePipeline>>()
             .init_resource::<Store<wgpu::BindGroup>>()
+            .init_resource::<Store<GpuMesh>>()
+            .add_store_cleanup::<RenderPipeline>()
+            .add_store_cleanup::<ComputePipeline>()
+            .add_store_cleanup::<wgpu::BindGroup>()
+            .add_store_cleanup::<GpuMesh>()
+            .add_system_to_stage(
+                CoreStage::Last,
+                sweep_removed_bind_slots_system
+                    .after(publish_store_removals_system::<wgpu::BindGroup>),
+            )
+            .init_resource::<RetiredResources>()
+            .init_resource::<StoreUsers<Refer<RenderPipeline>>>()
+            .init_resource::<StoreUsers<Refer<GpuMesh>>>()
+            .init_resource::<StoreUsers<BindSlots>>()
+            .init_resource::<MeshCache>()
             .init_resource::<Shaders>()
+            .init_resource::<PipelineCompiler>()
+            .init_resource::<PipelineCache>()
+            .init_resource::<PipelineProgress>()
+            .add_event::<PipelinesReady>()
+            .init_resource::<RenderStats>()
+            .add_event::<WgpuError>()
+            .init_resource::<CrashReportSettings>()
+            .add_system_to_stage(CoreStage::First, forward_wgpu_errors_system)
+            .add_system_to_stage(
+                CoreStage::First,
+                exit_on_fatal_wgpu_error_system.after(forward_wgpu_errors_system),
+            )
+            .init_resource::<UniformSyncStats>()
+            .add_system_to_stage(CoreStage::First, reset_uniform_sync_stats_system)
+            .init_resource::<TextureStore>()
+            .add_event::<TextureUnloaded>()
+            .init_resource::<PendingImages>()
+            .init_resource::<UploadQueue>()
+            .add_system_to_stage(CoreStage::PreUpdate, prepare_images_system)
+            .init_resource::<MissingTextures>()
+            .add_event::<TextureLoadFailed>()
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                detect_failed_textures_system.before(resolve_textures_system),
+            )
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                resolve_textures_system.after(prepare_images_system),
+            )
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                bind_resolved_textures_system::<TextureMaterial>.after(resolve_textures_system),
+            )
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                bind_resolved_textures_system::<VertexColorMaterial>.after(resolve_textures_system),
+            )
+            .add_system_to_stage(CoreStage::Last, unload_textures_system)
+            .add_system_to_stage(
+                CoreStage::Last,
+                replace_unloaded_textures_system::<TextureMaterial>
+                    .after(unload_textures_system)
+                    .before(publish_store_removals_system::<wgpu::BindGroup>),
+            )
+            .add_system_to_stage(
+                CoreStage::Last,
+                replace_unloaded_textures_system::<VertexColorMaterial>
+                    .after(unload_textures_system)
+                    .before(publish_store_removals_system::<wgpu::BindGroup>),
+            )
+            .add_event::<VisibilityChanged>()
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                compute_visibility_system
+                    .label(FlatSystemLabels::VisibilityCompute)
+                    .after(FlatSystemLabels::TransformPropagate),
+            )
+            .init_resource::<ForcedLod>()
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                select_lod_system
+                    .label(FlatSystemLabels::VisibilityCompute)
+                    .after(FlatSystemLabels::TransformPropagate),
+            )
+            .init_resource::<Lights>()
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                collect_lights_system
+                    .label(FlatSystemLabels::UniformSync)
+                    .after(FlatSystemLabels::VisibilityCompute),
+            )
+            .add_system_to_stage(RenderStage::Compute, compute_system)
+            .init_resource::<Option<CurrentFrame>>()
+            .init_resource::<Option<DepthTexture>>()
+            .init_resource::<DepthFormat>()
+            .init_resource::<DepthConvention>()
+            .init_resource::<Option<RenderTargets>>()
+            .add_system_to_stage(CoreStage::PreUpdate, update_render_targets_system)
+            .init_resource::<FrameEncoders>()
+            .init_resource::<BufferPool>()
+            .init_resource::<DepthPrepass>()
+            .init_resource::<WireframeMode>()
+            .init_resource::<ClearColor>()
+            .init_resource::<PassTimer>()
+            .init_resource::<FrameStats>()
+            .init_resource::<FrameCapture>()
+            .add_system_to_stage(CoreStage::First, deliver_captured_frames_system)
+            .add_system_to_stage(
+                RenderStage::Prepare,
+                apply_present_mode_system
+                    .label(FlatSystemLabels::RenderPrepare)
+                    .before(prepare_frame_system),
+            )
+            .add_system_to_stage(
+                RenderStage::Prepare,
+                resize_surface_system
+                    .label(FlatSystemLabels::RenderPrepare)
+                    .before(prepare_frame_system),
+            )
+            .add_system_to_stage(
+                RenderStage::Prepare,
+                prepare_frame_system.label(FlatSystemLabels::RenderPrepare),
+            )
+            .add_system_to_stage(
+                RenderStage::Prepare,
+                upload_system.label(FlatSystemLabels::RenderPrepare),
+            )
+            .add_system_to_stage(
+                RenderStage::Prepare,
+                receive_pipelines_system.label(FlatSystemLabels::RenderPrepare),
+            )
+            .add_system_to_stage(
+                RenderStage::Prepare,
+                cull_instances_system.label(FlatSystemLabels::RenderPrepare),
+            )
+            .add_system_to_stage(
+                RenderStage::Prepare,
+                pack_indirect_batches_system.label(FlatSystemLabels::RenderPrepare),
+            )
+            .add_system_to_stage(
+                RenderStage::Prepare,
+                track_texture_use_system.label(FlatSystemLabels::RenderPrepare),
+            )
+            .add_system_to_stage(
+                RenderStage::Prepare,
+                despawn_gpu_cleanup_system::<Refer<RenderPipeline>>
+                    .label(FlatSystemLabels::RenderPrepare),
+            )
+            .add_system_to_stage(
+                RenderStage::Prepare,
+                despawn_gpu_cleanup_system::<Refer<GpuMesh>>.label(FlatSystemLabels::RenderPrepare),
+            )
+            .add_system_to_stage(
+                RenderStage::Prepare,
+                despawn_gpu_cleanup_system::<BindSlots>.label(FlatSystemLabels::RenderPrepare),
+            )
+            .add_system_to_stage(
+                RenderStage::Prepare,
+                prepare_lights_system.label(FlatSystemLabels::RenderPrepare),
+            )
+            .add_system_to_stage(
+                RenderStage::Prepare,
+                prepare_shadow_map_system.label(FlatSystemLabels::RenderPrepare),
+            )
+            .add_system_to_stage(
+                RenderStage::MainPass,
+                main_pass_system.label(FlatSystemLabels::RenderMain),
+            )
+            .add_system_to_stage(
+                RenderStage::Prepare,
+                prepare_render_targets_system.label(FlatSystemLabels::RenderPrepare),
+            )
+            .add_system_to_stage(
+                RenderStage::MainPass,
+                render_to_texture_system
+                    .label(FlatSystemLabels::RenderMain)
+                    .before(main_pass_system),
+            )
+            .init_resource::<Option<DepthBindGroup>>()
+            .add_system_to_stage(
+                RenderStage::Prepare,
+                prepare_depth_bind_group_system
+                    .label(FlatSystemLabels::RenderPrepare)
+                    .after(resize_surface_system),
+            )
+            .add_system_to_stage(
+                RenderStage::MainPass,
+                expose_depth_bind_group_system.label(FlatSystemLabels::RenderMain),
+            )
+            .add_system_to_stage(
+                RenderStage::Present,
+                present_frame_system.label(FlatSystemLabels::RenderPresent),
+            )
+            .add_system_to_stage(
+                RenderStage::Present,
+                recycle_buffer_pool_system.after(present_frame_system),
+            )
+            .add_system_to_stage(
+                RenderStage::Present,
+                read_pass_timings_system.after(present_frame_system),
+            )
+            .add_system_to_stage(
+                RenderStage::Present,
+                end_retired_frame_system.after(present_frame_system),
+            )
+            .add_system_to_stage(
+                RenderStage::Present,
+                frame_stats_system.after(present_frame_system),
+            )
+            .add_system_to_stage(
+                RenderStage::Present,
+                capture_frame_system.before(present_frame_system),
+            )
+            .add_system_to_stage(
+                RenderStage::Present,
+                map_captured_frames_system.after(present_frame_system),
+            )
             .add_asset_loader(ShaderSourceLoader)
-            .add_asset::<ShaderSource>();
+            .add_asset::<ShaderSource>()
+            .add_material::<ColorMaterial>()
+            .add_material::<LitColorMaterial>()
+            .add_material::<TextureMaterial>()
+            .add_material::<VertexColorMaterial>();
     }
 }
 
@@ -39,52 +340,700 @@ impl Plugin for FlatRenderPlugin {
 // }
 
 #[derive(Component)]
-pub struct InstanceData(wgpu::Buffer, u32);
+pub struct InstanceData {
+    buffer: wgpu::Buffer,
+    // instances drawn, the first ones in the buffer
+    count: u32,
+    capacity: u32,
+}
+
+impl InstanceData {
+    pub fn new(buffer: wgpu::Buffer, count: u32) -> Self {
+        Self {
+            buffer,
+            count,
+            capacity: count,
+        }
+    }
+
+    /// Room for `capacity` instances, none drawn until [`write`](InstanceData::write)
+    pub fn with_capacity(device: &wgpu::Device, capacity: u32) -> Self {
+        Self::with_capacity_of::<InstanceRaw>(device, capacity)
+    }
+
+    /// [`with_capacity`](InstanceData::with_capacity) for another instance unit,
+    /// written with [`write_units`](InstanceData::write_units)
+    pub fn with_capacity_of<I: InstanceUnit>(device: &wgpu::Device, capacity: u32) -> Self {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Instance Buffer"),
+            size: capacity.max(1) as u64 * I::size(),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Self {
+            buffer,
+            count: 0,
+            capacity,
+        }
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    /// Draws only `instances`, the buffer is not shrunk.
+    /// Needs a buffer with `COPY_DST` and `instances` within the capacity
+    pub fn write(&mut self, queue: &wgpu::Queue, instances: &[InstanceRaw]) {
+        self.write_units(queue, instances);
+    }
+
+    /// The buffer has to be created for `I`
+    pub fn write_units<I: InstanceUnit>(&mut self, queue: &wgpu::Queue, instances: &[I]) {
+        assert!(instances.len() <= self.capacity as usize);
+        if !instances.is_empty() {
+            queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(instances));
+        }
+        self.count = instances.len() as u32;
+    }
+}
+
+/// Entities either own their `GpuMesh` or refer to a shared one in `Store<GpuMesh>`
+pub type WithMesh = Or<(With<GpuMesh>, With<Refer<GpuMesh>>)>;
+
+pub fn resolve_mesh<'a>(
+    owned: Option<&'a GpuMesh>,
+    shared: Option<&Refer<GpuMesh>>,
+    meshes: &'a Store<GpuMesh>,
+) -> Option<&'a GpuMesh> {
+    owned.or_else(|| shared.and_then(|key| key.get(meshes)))
+}
+
+pub struct DepthTexture {
+    texture: texture::Texture,
+    format: wgpu::TextureFormat,
+}
+
+impl DepthTexture {
+    /// `format` is the one `texture` was created with
+    pub fn new(texture: texture::Texture, format: wgpu::TextureFormat) -> Self {
+        Self { texture, format }
+    }
+
+    pub fn format(&self) -> wgpu::TextureFormat {
+        self.format
+    }
+}
+
+/// Format of the depth texture, pipelines drawn in the main pass are created with it.
+/// Read when the depth texture is created, so change it before the wgpu resources are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DepthFormat(pub wgpu::TextureFormat);
+
+impl DepthFormat {
+    /// For [`StencilMask`](resource::pipeline::StencilMask)
+    pub const STENCIL: Self = Self(wgpu::TextureFormat::Depth24PlusStencil8);
+
+    pub fn has_stencil(&self) -> bool {
+        matches!(
+            self.0,
+            wgpu::TextureFormat::Depth24PlusStencil8 | wgpu::TextureFormat::Depth32FloatStencil8
+        )
+    }
+}
+
+impl Default for DepthFormat {
+    fn default() -> Self {
+        Self(texture::Texture::DEPTH_FORMAT)
+    }
+}
+
+/// Which end of the depth range is near. [`DepthConvention::ReverseZ`] keeps the precision
+/// of float depth formats far from the camera, where the standard one z-fights.
+/// The depth clear and pipelines created from [`RenderTargets::shader_targets`] have to
+/// agree, the [`PerspectiveProjection`](crate::camera::PerspectiveProjection) of the active
+/// camera follows it. Change it before the wgpu resources are created, like the
+/// [`DepthFormat`].
+///
+/// NOTE: the shadow map keeps the standard convention, it has its own depth texture
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DepthConvention {
+    /// Near plane at depth 0, far at 1
+    Standard,
+    /// Near plane at depth 1, far at 0, or infinitely far with an infinite `zfar`
+    ReverseZ,
+}
+
+impl Default for DepthConvention {
+    fn default() -> Self {
+        Self::Standard
+    }
+}
+
+impl DepthConvention {
+    /// The depth pass is cleared to
+    pub fn clear_depth(self) -> f32 {
+        match self {
+            DepthConvention::Standard => 1.0,
+            DepthConvention::ReverseZ => 0.0,
+        }
+    }
+
+    /// Depth of the near plane
+    pub fn nearest(self) -> f32 {
+        1.0 - self.clear_depth()
+    }
+
+    /// `depth` measured from the near plane, e.g. for screen space layers
+    pub fn from_near(self, depth: f32) -> f32 {
+        match self {
+            DepthConvention::Standard => depth,
+            DepthConvention::ReverseZ => 1.0 - depth,
+        }
+    }
+
+    /// `compare` written for the standard convention, closer is `Less`
+    pub fn compare(self, compare: wgpu::CompareFunction) -> wgpu::CompareFunction {
+        use wgpu::CompareFunction::*;
+        match (self, compare) {
+            (DepthConvention::ReverseZ, Less) => Greater,
+            (DepthConvention::ReverseZ, LessEqual) => GreaterEqual,
+            (DepthConvention::ReverseZ, Greater) => Less,
+            (DepthConvention::ReverseZ, GreaterEqual) => LessEqual,
+            (_, compare) => compare,
+        }
+    }
+}
+
+/// Stencil reference of the draw, `0` without it
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StencilRef(pub u32);
+
+impl StencilRef {
+    fn reference(stencil: Option<&Self>) -> u32 {
+        stencil.map_or(0, |stencil| stencil.0)
+    }
+}
+
+/// Draws the depth of every mesh before the main pass, which then only shades the
+/// front-most fragments. Cuts overdraw with expensive fragment shaders, costs a second
+/// vertex pass. Pipelines created without [`DepthMode`] variants are drawn as usual.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DepthPrepass(pub bool);
+
+/// Background of the main pass, black by default
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClearColor(pub Color);
+
+impl Default for ClearColor {
+    fn default() -> Self {
+        Self(Color::BLACK)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RenderStats {
+    pub draw_calls: u32,
+    /// Draws of the main pass with another pipeline than the draw before
+    pub pipeline_switches: u32,
+    pub indirect_batches: u32,
+    pub indirect_draws: u32,
+    // draws of meshes referenced from Store<GpuMesh>
+    pub shared_mesh_draws: u32,
+    // buffer bytes that would have been uploaded again without sharing
+    pub shared_mesh_bytes_saved: u64,
+    pub depth_prepass_draws: u32,
+    pub pass_timings: PassTimings,
+    /// Entities drawn at each level of detail, see [`lod::MeshLods`]
+    pub lod_entities: Vec<u32>,
+}
+
+impl RenderStats {
+    pub fn reset(&mut self) {
+        // NOTE: counted in CoreStage::PostUpdate, before the main pass resets
+        let lod_entities = std::mem::take(&mut self.lod_entities);
+        *self = Self {
+            lod_entities,
+            ..Default::default()
+        };
+    }
+}
+
+pub fn reset_uniform_sync_stats_system(mut stats: ResMut<UniformSyncStats>) {
+    stats.reset();
+}
+
+/// Reconfigures the surface of the primary window when its present mode changes.
+/// Falls back to Fifo when the requested mode is not supported, the window then reports
+/// Fifo as its present mode.
+pub fn apply_present_mode_system(
+    mut events: EventReader<PresentModeChanged>,
+    surface: Option<Res<wgpu::Surface>>,
+    adapter: Option<Res<wgpu::Adapter>>,
+    device: Option<Res<Arc<wgpu::Device>>>,
+    config: Option<ResMut<wgpu::SurfaceConfiguration>>,
+    mut windows: Option<ResMut<Windows>>,
+) {
+    let event = match events.iter().filter(|e| e.window_id.is_primary()).last() {
+        Some(event) => event,
+        None => return,
+    };
+    let (surface, adapter, device, mut config) = match (surface, adapter, device, config) {
+        (Some(surface), Some(adapter), Some(device), Some(config)) => {
+            (surface, adapter, device, config)
+        }
+        _ => return,
+    };
+
+    let requested = event.present_mode.into();
+    let present_mode = negotiate_present_mode(&surface.get_supported_modes(&adapter), requested);
+    if present_mode != requested {
+        let window = windows
+            .as_mut()
+            .and_then(|windows| windows.map.get_mut(&WindowId::primary()));
+        if let Some(window) = window {
+            window.update_present_mode(PresentMode::Fifo);
+        }
+    }
+
+    if config.present_mode != present_mode {
+        config.present_mode = present_mode;
+        surface.configure(&device, &config);
+    }
+}
+
+/// Reconfigures the surface of the primary window at its new size and recreates the depth
+/// texture with it. Zero sizes are ignored, minimized windows keep the last configuration.
+pub fn resize_surface_system(
+    mut events: EventReader<WindowResized>,
+    surface: Option<Res<wgpu::Surface>>,
+    device: Option<Res<Arc<wgpu::Device>>>,
+    config: Option<ResMut<wgpu::SurfaceConfiguration>>,
+    depth_format: Res<DepthFormat>,
+    mut depth_texture: ResMut<Option<DepthTexture>>,
+) {
+    let event = match events.iter().filter(|e| e.window_id.is_primary()).last() {
+        Some(event) => event,
+        None => return,
+    };
+    let (surface, device, mut config) = match (surface, device, config) {
+        (Some(surface), Some(device), Some(config)) => (surface, device, config),
+        _ => return,
+    };
+    if event.width == 0 || event.height == 0 {
+        return;
+    }
+    if config.width == event.width && config.height == event.height {
+        return;
+    }
+
+    config.width = event.width;
+    config.height = event.height;
+    surface.configure(&device, &config);
+
+    let format = depth_texture
+        .as_ref()
+        .map_or(depth_format.0, DepthTexture::format);
+    *depth_texture = Some(DepthTexture::new(
+        texture::Texture::create_depth_texture_with_format(
+            &device,
+            config.width,
+            config.height,
+            format,
+            "Depth Texture",
+        ),
+        format,
+    ));
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SurfaceErrorAction {
+    /// Reconfigure the surface with the stored configuration and skip the frame
+    Reconfigure,
+    /// Skip the frame, should resolve itself by the next one
+    Skip,
+    Exit,
+}
+
+impl From<&wgpu::SurfaceError> for SurfaceErrorAction {
+    fn from(error: &wgpu::SurfaceError) -> Self {
+        match error {
+            wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated => {
+                SurfaceErrorAction::Reconfigure
+            }
+            wgpu::SurfaceError::Timeout => SurfaceErrorAction::Skip,
+            wgpu::SurfaceError::OutOfMemory => SurfaceErrorAction::Exit,
+        }
+    }
+}
+
+/// Target of the frame being rendered, acquired in `RenderStage::Prepare` and
+/// presented in `RenderStage::Present`. Stays `None` when there is nothing to draw to.
+pub struct CurrentFrame {
+    // None when rendering to the OffscreenTarget
+    surface_texture: Option<wgpu::SurfaceTexture>,
+    pub view: wgpu::TextureView,
+    // set when the main pass draws somewhere else, e.g. the post-process HDR target
+    main_pass_view: Option<wgpu::TextureView>,
+    pub format: wgpu::TextureFormat,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl CurrentFrame {
+    pub fn main_pass_view(&self) -> &wgpu::TextureView {
+        self.main_pass_view.as_ref().unwrap_or(&self.view)
+    }
+
+    /// Pipelines drawn in the main pass have to target the format of `view`
+    pub fn redirect_main_pass(&mut self, view: wgpu::TextureView) {
+        self.main_pass_view = Some(view);
+    }
+
+    /// Post-process passes copy from this, the surface is configured with `COPY_SRC`
+    pub fn texture<'a>(
+        &'a self,
+        offscreen: Option<&'a OffscreenTarget>,
+    ) -> Option<&'a wgpu::Texture> {
+        match &self.surface_texture {
+            Some(surface_texture) => Some(&surface_texture.texture),
+            None => offscreen.map(|offscreen| &offscreen.texture),
+        }
+    }
+}
+
+/// Command buffers of the frame, submitted in order in `RenderStage::Present`.
+///
+/// Systems either record into the shared encoder or finish their own,
+/// which is submitted after everything recorded before it.
+#[derive(Default)]
+pub struct FrameEncoders {
+    shared: Option<wgpu::CommandEncoder>,
+    finished: Vec<wgpu::CommandBuffer>,
+}
+
+impl FrameEncoders {
+    pub fn encoder(&mut self, device: &wgpu::Device) -> &mut wgpu::CommandEncoder {
+        self.shared.get_or_insert_with(|| {
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Frame Encoder"),
+            })
+        })
+    }
+
+    pub fn finish(&mut self, encoder: wgpu::CommandEncoder) {
+        self.finish_shared();
+        self.finished.push(encoder.finish());
+    }
+
+    fn finish_shared(&mut self) {
+        if let Some(shared) = self.shared.take() {
+            self.finished.push(shared.finish());
+        }
+    }
+
+    pub fn submit(&mut self, queue: &wgpu::Queue) {
+        self.finish_shared();
+        if !self.finished.is_empty() {
+            queue.submit(self.finished.drain(..));
+        }
+    }
+}
+
+pub fn prepare_frame_system(
+    surface: Option<Res<wgpu::Surface>>,
+    config: Option<Res<wgpu::SurfaceConfiguration>>,
+    offscreen: Option<Res<OffscreenTarget>>,
+    device: Option<Res<Arc<wgpu::Device>>>,
+    mut frame: ResMut<Option<CurrentFrame>>,
+    mut app_exit_events: EventWriter<AppExit>,
+) {
+    *frame = None;
+
+    let surface_texture = match (&surface, &config, &device) {
+        // NOTE: not configured while the window has no size
+        (_, Some(config), _) if config.width == 0 || config.height == 0 => None,
+        (Some(surface), Some(config), Some(device)) => match surface.get_current_texture() {
+            Ok(surface_texture) => Some((surface_texture, config)),
+            Err(error) => {
+                match SurfaceErrorAction::from(&error) {
+                    SurfaceErrorAction::Reconfigure => surface.configure(device, config),
+                    SurfaceErrorAction::Skip => {}
+                    SurfaceErrorAction::Exit => {
+                        log::error!("Surface error: {:?}, exiting", error);
+                        app_exit_events.send(AppExit);
+                    }
+                }
+                return;
+            }
+        },
+        _ => None,
+    };
 
-pub struct DepthTexture(texture::Texture);
+    *frame = match (surface_texture, &offscreen) {
+        (Some((surface_texture, config)), _) => Some(CurrentFrame {
+            view: surface_texture
+                .texture
+                .create_view(&wgpu::TextureViewDescriptor::default()),
+            surface_texture: Some(surface_texture),
+            main_pass_view: None,
+            format: config.format,
+            width: config.width,
+            height: config.height,
+        }),
+        (None, Some(offscreen)) => Some(CurrentFrame {
+            surface_texture: None,
+            main_pass_view: None,
+            view: offscreen
+                .texture
+                .create_view(&wgpu::TextureViewDescriptor::default()),
+            format: OffscreenTarget::FORMAT,
+            width: offscreen.width,
+            height: offscreen.height,
+        }),
+        (None, None) => None,
+    };
+}
 
-pub fn render_system(
-    surface: Res<wgpu::Surface>,
-    device: Res<wgpu::Device>,
-    queue: Res<wgpu::Queue>,
+/// Default `RenderStage::MainPass` system, draws the shadow map and every mesh entity
+pub fn main_pass_system(
+    frame: Res<Option<CurrentFrame>>,
+    device: Option<Res<Arc<wgpu::Device>>>,
+    mut encoders: ResMut<FrameEncoders>,
     depth_texture: Res<Option<DepthTexture>>,
     pipelines: Res<Store<RenderPipeline>>,
     bind_groups: Res<Store<wgpu::BindGroup>>,
-    objects: Query<(
-        &Refer<RenderPipeline>,
-        &ReferMany<wgpu::BindGroup>,
-        &GpuMesh,
-        Option<&InstanceData>,
-    )>,
+    meshes: Res<Store<GpuMesh>>,
+    mut stats: ResMut<RenderStats>,
+    objects: Query<
+        (
+            (
+                Entity,
+                &Refer<RenderPipeline>,
+                &BindSlots,
+                Option<&GpuMesh>,
+                Option<&Refer<GpuMesh>>,
+                Option<&InstanceData>,
+                Option<&StencilRef>,
+                (Option<&Wireframe>, Option<&WireframeEdges>),
+            ),
+            Option<&ComputedVisibility>,
+        ),
+        (WithMesh, Without<IndirectBatch>),
+    >,
+    batches: Query<
+        (
+            (
+                Entity,
+                &Refer<RenderPipeline>,
+                &BindSlots,
+                Option<&GpuMesh>,
+                Option<&Refer<GpuMesh>>,
+                Option<&InstanceData>,
+                &IndirectBatch,
+                Option<&StencilRef>,
+            ),
+            Option<&ComputedVisibility>,
+        ),
+        WithMesh,
+    >,
+    shadow_map: Option<Res<ShadowMap>>,
+    shadow_casters: Query<
+        (
+            (
+                Option<&GpuMesh>,
+                Option<&Refer<GpuMesh>>,
+                Option<&InstanceData>,
+            ),
+            Option<&ComputedVisibility>,
+        ),
+        (With<ShadowCaster>, WithMesh),
+    >,
+    (depth_prepass, wireframe_mode, depth_convention, active_camera): (
+        Res<DepthPrepass>,
+        Res<WireframeMode>,
+        Res<DepthConvention>,
+        Option<Res<ActiveCamera>>,
+    ),
+    clear_color: Res<ClearColor>,
+    (mut timer, profiler): (ResMut<PassTimer>, Option<Res<Profiler>>),
+    mut draw_validator: Local<DrawValidator>,
 ) {
-    let output = surface.get_current_texture().unwrap();
-    let view = output
-        .texture
-        .create_view(&wgpu::TextureViewDescriptor::default());
-
-    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-        label: Some("Render Encoder"),
+    let _scope = profiler
+        .as_ref()
+        .map(|profiler| profiler.scope("main pass"));
+    stats.reset();
+    stats.pass_timings = timer.latest();
+    let (frame, device) = match (frame.as_ref().as_ref(), device) {
+        (Some(frame), Some(device)) => (frame, device),
+        _ => return,
+    };
+    let view = frame.main_pass_view();
+    let multi_draw = device
+        .features()
+        .contains(wgpu::Features::MULTI_DRAW_INDIRECT);
+    let depth_texture = depth_texture.as_ref().as_ref();
+    let depth_view = depth_texture.map(|depth_texture| &depth_texture.texture.view);
+    let has_stencil = depth_texture.map_or(false, |depth_texture| {
+        DepthFormat(depth_texture.format).has_stencil()
     });
+    // NOTE: without a depth texture there is nothing to test against
+    let prepass = depth_prepass.0 && depth_view.is_some();
+    let depth_mode = if prepass {
+        DepthMode::Equal
+    } else {
+        DepthMode::Write
+    };
+
+    timer.begin_frame(&device);
+    let encoder = encoders.encoder(&device);
+
+    // NOTE: the frame is only cleared once every camera is gone
+    if !active_camera.map_or(true, |active| active.has_camera()) {
+        timer.begin(encoder, TimedPass::MainPass);
+        clear_main_pass(
+            encoder,
+            view,
+            depth_view,
+            has_stencil,
+            &clear_color,
+            *depth_convention,
+        );
+        timer.end(encoder, TimedPass::MainPass);
+        timer.finish_frame(encoder);
+        return;
+    }
+
+    if let Some(shadow_map) = shadow_map.as_ref() {
+        shadow_map.record_pass(
+            encoder,
+            visible(shadow_casters.iter()).filter_map(|(owned, shared, instance)| {
+                Some((resolve_mesh(owned, shared, &meshes)?, instance))
+            }),
+        );
+    }
+
+    if prepass {
+        timer.begin(encoder, TimedPass::DepthPrepass);
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Depth Prepass"),
+                color_attachments: &[],
+                depth_stencil_attachment: depth_view.map(|view| {
+                    wgpu::RenderPassDepthStencilAttachment {
+                        view,
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(depth_convention.clear_depth()),
+                            store: true,
+                        }),
+                        stencil_ops: has_stencil.then(|| wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(0),
+                            store: true,
+                        }),
+                    }
+                }),
+            });
+
+            for (entity, pipeline, binds, owned, shared, instance, stencil, (marker, _)) in
+                visible(objects.iter())
+            {
+                // NOTE: drawn with depth writes in the main pass, lines would not
+                // pass the equal test against the filled depth
+                if wireframe_mode.0 || marker.is_some() {
+                    continue;
+                }
+                let mesh = match resolve_mesh(owned, shared, &meshes) {
+                    Some(mesh) => mesh,
+                    None => continue,
+                };
+                let pipeline = match pipeline.get(&pipelines) {
+                    Some(pipeline) => pipeline,
+                    // still compiling
+                    None => continue,
+                };
+                let depth_only = match pipeline.variant(DepthMode::DepthOnly) {
+                    Some(depth_only) => depth_only,
+                    None => continue,
+                };
+                if !draw_validator.check(entity, pipeline, binds, mesh) {
+                    continue;
+                }
+                let groups = match draw_validator.bind_groups(entity, binds, &bind_groups) {
+                    Some(groups) => groups,
+                    None => continue,
+                };
+                render_pass.set_stencil_reference(StencilRef::reference(stencil));
+                draw_mesh(&mut render_pass, depth_only, groups, mesh, instance);
+                stats.depth_prepass_draws += 1;
+            }
 
+            for (entity, pipeline, binds, owned, shared, instance, batch, stencil) in
+                visible(batches.iter())
+            {
+                let mesh = match resolve_mesh(owned, shared, &meshes) {
+                    Some(mesh) => mesh,
+                    None => continue,
+                };
+                let pipeline = match pipeline.get(&pipelines) {
+                    Some(pipeline) => pipeline,
+                    // still compiling
+                    None => continue,
+                };
+                let depth_only = match pipeline.variant(DepthMode::DepthOnly) {
+                    Some(depth_only) => depth_only,
+                    None => continue,
+                };
+                if !draw_validator.check(entity, pipeline, binds, mesh) {
+                    continue;
+                }
+                let groups = match draw_validator.bind_groups(entity, binds, &bind_groups) {
+                    Some(groups) => groups,
+                    None => continue,
+                };
+                render_pass.set_stencil_reference(StencilRef::reference(stencil));
+                bind_mesh(&mut render_pass, depth_only, groups, mesh, instance);
+                stats.depth_prepass_draws +=
+                    draw_indirect_batch(&mut render_pass, batch, mesh, multi_draw);
+            }
+        }
+        timer.end(encoder, TimedPass::DepthPrepass);
+    }
+
+    timer.begin(encoder, TimedPass::MainPass);
     {
         let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("Render Pass"),
             color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view: &view,
+                view,
                 resolve_target: None,
                 ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    load: wgpu::LoadOp::Clear(clear_color.0.into()),
                     store: true,
                 },
             })],
-            depth_stencil_attachment: depth_texture.as_ref().as_ref().map(|dt| {
+            depth_stencil_attachment: depth_view.map(|view| {
                 wgpu::RenderPassDepthStencilAttachment {
-                    view: &dt.0.view,
+                    view,
                     depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
+                        // filled by the depth prepass
+                        load: if prepass {
+                            wgpu::LoadOp::Load
+                        } else {
+                            wgpu::LoadOp::Clear(depth_convention.clear_depth())
+                        },
+                        store: true,
+                    }),
+                    stencil_ops: has_stencil.then(|| wgpu::Operations {
+                        load: if prepass {
+                            wgpu::LoadOp::Load
+                        } else {
+                            wgpu::LoadOp::Clear(0)
+                        },
                         store: true,
                     }),
-                    stencil_ops: None,
                 }
             }),
             // depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
@@ -104,46 +1053,327 @@ pub fn render_system(
             // }),
         });
 
-        for (pipeline, binds, mesh, instance) in objects.iter() {
-            draw_mesh(
-                &mut render_pass,
-                pipelines.get(**pipeline).unwrap(),
-                (*binds)
-                    .iter()
-                    .map(|i| bind_groups.get(*i).unwrap())
-                    .collect::<Vec<_>>(),
+        let mut switches = PipelineSwitches::default();
+        let mut shared_uses: HashMap<usize, u32> = HashMap::new();
+        let mut count_shared = |owned: Option<&GpuMesh>, shared: Option<&Refer<GpuMesh>>| {
+            if let (None, Some(shared)) = (owned, shared) {
+                *shared_uses.entry(**shared).or_default() += 1;
+            }
+        };
+
+        for (entity, pipeline, binds, owned, shared, instance, stencil, (marker, edges)) in
+            visible(objects.iter())
+        {
+            let mesh = match resolve_mesh(owned, shared, &meshes) {
+                Some(mesh) => mesh,
+                None => continue,
+            };
+            let pipeline = match pipeline.get(&pipelines) {
+                Some(pipeline) => pipeline,
+                None => continue,
+            };
+            if !draw_validator.check(entity, pipeline, binds, mesh) {
+                continue;
+            }
+            let groups = match draw_validator.bind_groups(entity, binds, &bind_groups) {
+                Some(groups) => groups,
+                None => continue,
+            };
+            count_shared(owned, shared);
+            render_pass.set_stencil_reference(StencilRef::reference(stencil));
+            match (
+                wireframe_pipeline(*wireframe_mode, marker, pipeline.wireframe(), edges),
+                edges,
+            ) {
+                (Some(WireframeVariant::PolygonLine(wireframe)), _) => {
+                    switches.set(wireframe);
+                    draw_mesh(&mut render_pass, wireframe, groups, mesh, instance);
+                }
+                (Some(WireframeVariant::EdgeList(wireframe)), Some(edges)) => {
+                    switches.set(wireframe);
+                    let instance_count =
+                        bind_mesh(&mut render_pass, wireframe, groups, mesh, instance);
+                    edges.draw(&mut render_pass, instance_count);
+                }
+                _ => {
+                    let pipeline = pipeline.variant(depth_mode).unwrap_or(&pipeline.pipeline);
+                    switches.set(pipeline);
+                    draw_mesh(&mut render_pass, pipeline, groups, mesh, instance);
+                }
+            }
+            stats.draw_calls += 1;
+        }
+
+        for (entity, pipeline, binds, owned, shared, instance, batch, stencil) in
+            visible(batches.iter())
+        {
+            let mesh = match resolve_mesh(owned, shared, &meshes) {
+                Some(mesh) => mesh,
+                None => continue,
+            };
+            let pipeline = match pipeline.get(&pipelines) {
+                Some(pipeline) => pipeline,
+                None => continue,
+            };
+            if !draw_validator.check(entity, pipeline, binds, mesh) {
+                continue;
+            }
+            let groups = match draw_validator.bind_groups(entity, binds, &bind_groups) {
+                Some(groups) => groups,
+                None => continue,
+            };
+            count_shared(owned, shared);
+            render_pass.set_stencil_reference(StencilRef::reference(stencil));
+            let pipeline = pipeline.variant(depth_mode).unwrap_or(&pipeline.pipeline);
+            switches.set(pipeline);
+            bind_mesh(&mut render_pass, pipeline, groups, mesh, instance);
+            stats.draw_calls += draw_indirect_batch(&mut render_pass, batch, mesh, multi_draw);
+            stats.indirect_batches += 1;
+            stats.indirect_draws += batch.packed_count();
+        }
+        stats.pipeline_switches += switches.count;
+
+        for (key, uses) in shared_uses {
+            stats.shared_mesh_draws += uses;
+            let buffer_size = meshes.get(key).map_or(0, |mesh| mesh.buffer_size);
+            stats.shared_mesh_bytes_saved += (uses as u64 - 1) * buffer_size;
+        }
+    } // drop(render_pass) <- mut borrow encoder
+    timer.end(encoder, TimedPass::MainPass);
+    timer.finish_frame(encoder);
+}
+
+/// Submits the frame's command buffers and presents the surface texture
+pub fn present_frame_system(
+    queue: Option<Res<wgpu::Queue>>,
+    mut encoders: ResMut<FrameEncoders>,
+    mut frame: ResMut<Option<CurrentFrame>>,
+    profiler: Option<Res<Profiler>>,
+) {
+    let _scope = profiler.as_ref().map(|profiler| profiler.scope("present"));
+    let frame = frame.take();
+    let queue = match queue {
+        Some(queue) => queue,
+        None => return,
+    };
+    encoders.submit(&queue);
+
+    if let Some(surface_texture) = frame.and_then(|frame| frame.surface_texture) {
+        surface_texture.present();
+    }
+}
+
+/// Catches draws that would render garbage or fail validation: meshes drawn with a
+/// pipeline built for another primitive topology or strip index format, and bind groups
+/// missing from slots the pipeline layout uses. Only checks in debug builds.
+#[derive(Default)]
+pub struct DrawValidator {
+    warned: HashSet<Entity>,
+}
+
+impl DrawValidator {
+    /// Returns false if the draw should be skipped, warns once per entity
+    pub fn check(
+        &mut self,
+        entity: Entity,
+        pipeline: &RenderPipeline,
+        binds: &BindSlots,
+        mesh: &GpuMesh,
+    ) -> bool {
+        !cfg!(debug_assertions)
+            || (self.check_topology(entity, pipeline.topology(), mesh.primitive_topology)
+                && self.check_strip_index_format(
+                    entity,
+                    pipeline.strip_index_format(),
+                    mesh.index_format(),
+                )
+                && self.check_bind_slots(entity, pipeline.bind_group_count(), binds))
+    }
+
+    /// The bind groups to draw `entity` with, `None` if one of them was removed from the
+    /// store but the sweep has not cleared its slot yet
+    pub fn bind_groups<'a>(
+        &mut self,
+        entity: Entity,
+        binds: &BindSlots,
+        store: &'a Store<wgpu::BindGroup>,
+    ) -> Option<Vec<(u32, &'a wgpu::BindGroup)>> {
+        let groups: Option<Vec<_>> = binds
+            .iter()
+            .map(|(group, key)| Some((group, store.get(key)?)))
+            .collect();
+        if groups.is_none() && self.warned.insert(entity) {
+            log::warn!(
+                "{}",
+                FlatError::Render(format!(
+                    "{:?} refers to a removed bind group, skipping it",
+                    entity
+                ))
+            );
+        }
+        groups
+    }
+
+    pub fn check_bind_slots(
+        &mut self,
+        entity: Entity,
+        group_count: u32,
+        binds: &BindSlots,
+    ) -> bool {
+        let group = match binds.missing(group_count) {
+            Some(group) => group,
+            None => return true,
+        };
+        if self.warned.insert(entity) {
+            log::warn!(
+                "{:?} has no bind group in slot {} its pipeline uses, skipping it",
+                entity,
+                group
+            );
+        }
+        false
+    }
+
+    pub fn check_strip_index_format(
+        &mut self,
+        entity: Entity,
+        pipeline: Option<wgpu::IndexFormat>,
+        mesh: Option<wgpu::IndexFormat>,
+    ) -> bool {
+        let (pipeline, mesh) = match (pipeline, mesh) {
+            (Some(pipeline), Some(mesh)) if pipeline != mesh => (pipeline, mesh),
+            _ => return true,
+        };
+        if self.warned.insert(entity) {
+            log::warn!(
+                "{:?} has {:?} indices but its pipeline restarts strips at {:?}, skipping it",
+                entity,
                 mesh,
-                instance,
+                pipeline
             );
         }
-    } // drop(render_pass) <- mut borrow encoder <- mut borrow self
+        false
+    }
+
+    pub fn check_topology(
+        &mut self,
+        entity: Entity,
+        pipeline: wgpu::PrimitiveTopology,
+        mesh: wgpu::PrimitiveTopology,
+    ) -> bool {
+        if pipeline == mesh {
+            return true;
+        }
+        if self.warned.insert(entity) {
+            log::warn!(
+                "{:?} has a {:?} mesh but its pipeline draws {:?}, skipping it",
+                entity,
+                mesh,
+                pipeline
+            );
+        }
+        false
+    }
+}
 
-    queue.submit(std::iter::once(encoder.finish()));
+/// Begins and ends the main pass only to clear its targets
+fn clear_main_pass(
+    encoder: &mut wgpu::CommandEncoder,
+    view: &wgpu::TextureView,
+    depth_view: Option<&wgpu::TextureView>,
+    has_stencil: bool,
+    clear_color: &ClearColor,
+    depth_convention: DepthConvention,
+) {
+    encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some("Clear Pass"),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(clear_color.0.into()),
+                store: true,
+            },
+        })],
+        depth_stencil_attachment: depth_view.map(|view| wgpu::RenderPassDepthStencilAttachment {
+            view,
+            depth_ops: Some(wgpu::Operations {
+                load: wgpu::LoadOp::Clear(depth_convention.clear_depth()),
+                store: true,
+            }),
+            stencil_ops: has_stencil.then(|| wgpu::Operations {
+                load: wgpu::LoadOp::Clear(0),
+                store: true,
+            }),
+        }),
+    });
+}
 
-    output.present();
+/// Counts the pipelines set between draws, a pipeline set again in a row is not a switch
+#[derive(Default)]
+struct PipelineSwitches<'a> {
+    last: Option<&'a wgpu::RenderPipeline>,
+    count: u32,
 }
 
-fn draw_mesh<'a>(
+impl<'a> PipelineSwitches<'a> {
+    fn set(&mut self, pipeline: &'a wgpu::RenderPipeline) {
+        if !self.last.map_or(false, |last| std::ptr::eq(last, pipeline)) {
+            self.last = Some(pipeline);
+            self.count += 1;
+        }
+    }
+}
+
+fn bind_mesh<'a>(
     render_pass: &mut wgpu::RenderPass<'a>,
-    pipeline: &'a RenderPipeline,
-    bind_groups: Vec<&'a wgpu::BindGroup>,
+    pipeline: &'a wgpu::RenderPipeline,
+    bind_groups: Vec<(u32, &'a wgpu::BindGroup)>,
     mesh: &'a GpuMesh,
     instance: Option<&'a InstanceData>,
-) {
-    render_pass.set_pipeline(&pipeline.0);
+) -> u32 {
+    render_pass.set_pipeline(pipeline);
 
-    // TODO: binds are bound in the same order as they appear in RefMulti
-    for (index, bind_group) in bind_groups.into_iter().enumerate() {
-        render_pass.set_bind_group(index as u32, bind_group, &[]);
+    for (group, bind_group) in bind_groups {
+        render_pass.set_bind_group(group, bind_group, &[]);
     }
 
+    set_mesh_buffers(render_pass, mesh, instance)
+}
+
+/// Sets the vertex buffers of `mesh` and `instance`, returns the instance count
+pub(crate) fn set_mesh_buffers<'a>(
+    render_pass: &mut wgpu::RenderPass<'a>,
+    mesh: &'a GpuMesh,
+    instance: Option<&'a InstanceData>,
+) -> u32 {
     let mut instance_count = 1;
     render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
     if let Some(instance_data) = instance {
-        render_pass.set_vertex_buffer(1, instance_data.0.slice(..));
-        instance_count = instance_data.1;
+        render_pass.set_vertex_buffer(1, instance_data.buffer.slice(..));
+        instance_count = instance_data.count;
     }
 
+    instance_count
+}
+
+fn draw_mesh<'a>(
+    render_pass: &mut wgpu::RenderPass<'a>,
+    pipeline: &'a wgpu::RenderPipeline,
+    bind_groups: Vec<(u32, &'a wgpu::BindGroup)>,
+    mesh: &'a GpuMesh,
+    instance: Option<&'a InstanceData>,
+) {
+    let instance_count = bind_mesh(render_pass, pipeline, bind_groups, mesh, instance);
+    draw_assembly(render_pass, mesh, instance_count);
+}
+
+/// Draws the whole mesh, its buffers have to be set
+pub(crate) fn draw_assembly<'a>(
+    render_pass: &mut wgpu::RenderPass<'a>,
+    mesh: &'a GpuMesh,
+    instance_count: u32,
+) {
     match &mesh.assembly {
         mesh::GpuMeshAssembly::Indexed {
             index_buffer,
@@ -158,3 +1388,92 @@ fn draw_mesh<'a>(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy_ecs::entity::Entity;
+
+    use crate::util::Store;
+
+    use super::{resource::bind::BindSlots, DepthConvention, DrawValidator, SurfaceErrorAction};
+
+    #[test]
+    fn surface_error_actions() {
+        let action = |error| SurfaceErrorAction::from(&error);
+
+        assert_eq!(action(wgpu::SurfaceError::Lost), SurfaceErrorAction::Reconfigure);
+        assert_eq!(
+            action(wgpu::SurfaceError::Outdated),
+            SurfaceErrorAction::Reconfigure
+        );
+        assert_eq!(action(wgpu::SurfaceError::Timeout), SurfaceErrorAction::Skip);
+        assert_eq!(
+            action(wgpu::SurfaceError::OutOfMemory),
+            SurfaceErrorAction::Exit
+        );
+    }
+
+    #[test]
+    fn reverse_z_flips_the_ordering_comparisons() {
+        use wgpu::CompareFunction::*;
+
+        let reverse = DepthConvention::ReverseZ;
+        assert_eq!(reverse.compare(Less), Greater);
+        assert_eq!(reverse.compare(LessEqual), GreaterEqual);
+        assert_eq!(reverse.compare(Greater), Less);
+        // the prepass and always on top draws do not depend on the order
+        assert_eq!(reverse.compare(Equal), Equal);
+        assert_eq!(reverse.compare(Always), Always);
+        assert_eq!(DepthConvention::Standard.compare(Less), Less);
+
+        assert_eq!(reverse.clear_depth(), 0.0);
+        assert_eq!(reverse.nearest(), 1.0);
+        assert_eq!(reverse.from_near(0.25), 0.75);
+        assert_eq!(DepthConvention::Standard.from_near(0.25), 0.25);
+    }
+
+    #[test]
+    fn topology_mismatch_is_flagged() {
+        let mut validator = DrawValidator::default();
+        let (a, b) = (Entity::from_raw(0), Entity::from_raw(1));
+        let triangles = wgpu::PrimitiveTopology::TriangleList;
+        let lines = wgpu::PrimitiveTopology::LineList;
+
+        assert!(validator.check_topology(a, triangles, triangles));
+        assert!(!validator.check_topology(a, triangles, lines));
+        // still skipped after the warning
+        assert!(!validator.check_topology(a, triangles, lines));
+        assert!(!validator.check_topology(b, triangles, lines));
+        assert_eq!(validator.warned.len(), 2);
+    }
+
+    #[test]
+    fn missing_bind_slot_is_flagged() {
+        let mut validator = DrawValidator::default();
+        let entity = Entity::from_raw(0);
+
+        assert!(validator.check_bind_slots(entity, 0, &BindSlots::new()));
+        assert!(validator.check_bind_slots(entity, 2, &BindSlots::new().with(0, 0).with(1, 1)));
+        // the material was put in group 1, group 0 stays empty
+        assert!(!validator.check_bind_slots(entity, 2, &BindSlots::new().with(1, 1)));
+    }
+
+    #[test]
+    fn stale_bind_group_is_skipped() {
+        let mut validator = DrawValidator::default();
+        let entity = Entity::from_raw(0);
+        let store = Store::<wgpu::BindGroup>::default();
+
+        assert_eq!(
+            validator
+                .bind_groups(entity, &BindSlots::new(), &store)
+                .map(|groups| groups.len()),
+            Some(0)
+        );
+        // removed from the store, the sweep has not cleared the slot yet
+        let stale = BindSlots::new().with(0, 3);
+        assert!(validator.bind_groups(entity, &stale, &store).is_none());
+        assert!(validator.bind_groups(entity, &stale, &store).is_none());
+        assert_eq!(validator.warned.len(), 1);
+    }
+}
@@ -0,0 +1,101 @@
+/// Render target used when there is no `wgpu::Surface` resource,
+/// e.g. in tests and CI
+pub struct OffscreenTarget {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl OffscreenTarget {
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+    const PIXEL_SIZE: u32 = std::mem::size_of::<[u8; 4]>() as u32;
+
+    pub fn new(device: &wgpu::Device, width: u32, height: u32) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Offscreen Target"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Self {
+            texture,
+            view,
+            width,
+            height,
+        }
+    }
+
+    pub fn padded_bytes_per_row(&self) -> u32 {
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let unpadded_bytes_per_row = Self::PIXEL_SIZE * self.width;
+        let padding = (align - unpadded_bytes_per_row % align) % align;
+        unpadded_bytes_per_row + padding
+    }
+
+    /// Blocks until the copy is done, returns tightly packed RGBA8 rows
+    pub fn read_back(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Vec<u8> {
+        let unpadded_bytes_per_row = Self::PIXEL_SIZE * self.width;
+        let padded_bytes_per_row = self.padded_bytes_per_row();
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Offscreen Read Back Buffer"),
+            size: (padded_bytes_per_row * self.height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Read Back Encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            self.texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(padded_bytes_per_row),
+                    rows_per_image: std::num::NonZeroU32::new(self.height),
+                },
+            },
+            wgpu::Extent3d {
+                width: self.width,
+                height: self.height,
+                depth_or_array_layers: 1,
+            },
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let buffer_slice = buffer.slice(..);
+        let (tx, rx) = futures_intrusive::channel::shared::oneshot_channel();
+        buffer_slice.map_async(wgpu::MapMode::Read, move |result| {
+            tx.send(result).unwrap();
+        });
+        // wait for the GPU to finish
+        device.poll(wgpu::Maintain::Wait);
+
+        match pollster::block_on(rx.receive()) {
+            Some(Ok(())) => {
+                let padded_data = buffer_slice.get_mapped_range();
+                let data = padded_data
+                    .chunks(padded_bytes_per_row as _)
+                    .flat_map(|chunk| &chunk[..unpadded_bytes_per_row as _])
+                    .copied()
+                    .collect::<Vec<_>>();
+                drop(padded_data);
+                buffer.unmap();
+                data
+            }
+            _ => panic!("Offscreen target could not be read back"),
+        }
+    }
+}
@@ -0,0 +1,442 @@
+use std::{ops::Range, sync::Arc};
+
+use bevy_app::{CoreStage, Plugin};
+use bevy_asset::Assets;
+use bevy_ecs::{
+    schedule::ParallelSystemDescriptorCoercion,
+    system::{Res, ResMut},
+};
+use bytemuck::{Pod, Zeroable};
+use repr_trait::C;
+use winit::event::{ElementState, Event, WindowEvent};
+
+use crate::{
+    input::keyboard::KeyCode,
+    profiler::Profiler,
+    text::{
+        mesh::{create_styled_screen_text_mesh, Color, TextEffect, TextStyle},
+        AtlasTexture, TextAtlas,
+    },
+    texture::Image,
+    window::{runner::RawEventSubscribers, screen::ScreenSpace},
+    FlatSystemLabels, RenderStage, Text,
+};
+
+use super::{
+    capture::capture_frame_system,
+    frame_stats::FrameStats,
+    present_frame_system,
+    resource::{
+        bind::{BindingSet, GpuUniform, Uniform, UpdateGpuUniform},
+        buffer::{MeshVertex, VertexTextured2DColor},
+        pool::{BufferPool, PooledBuffer},
+        shader::{Shader, ShaderSource},
+    },
+    upload::UploadQueue,
+    CurrentFrame, FrameEncoders, RenderStats,
+};
+
+/// Text overlay drawn over the main pass. Systems push lines every frame
+/// with [`DebugOverlay::text`], the built-in panel shows render stats,
+/// frame time and loaded asset counts.
+///
+/// Drawing needs a [`DebugOverlayRenderer`] resource, created from a font atlas.
+pub struct FlatDebugOverlayPlugin {
+    pub enabled: bool,
+    pub toggle_key: KeyCode,
+}
+
+impl Default for FlatDebugOverlayPlugin {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            toggle_key: KeyCode::F3,
+        }
+    }
+}
+
+impl Plugin for FlatDebugOverlayPlugin {
+    fn build(&self, app: &mut bevy_app::App) {
+        app.insert_resource(DebugOverlay {
+            enabled: self.enabled,
+            lines: Vec::new(),
+        })
+        .add_system_to_stage(CoreStage::First, clear_debug_overlay_system)
+        .add_system_to_stage(
+            CoreStage::PostUpdate,
+            debug_stats_panel_system.after(FlatSystemLabels::UniformSync),
+        )
+        .add_system_to_stage(CoreStage::Last, prepare_debug_overlay_system)
+        .add_system_to_stage(
+            RenderStage::Present,
+            // NOTE: ordered here, an `after` on the capture would name a system that is
+            // missing without this plugin
+            debug_overlay_pass_system
+                .before(capture_frame_system)
+                .before(present_frame_system),
+        )
+        .init_non_send_resource::<RawEventSubscribers>();
+
+        let toggle_key = self.toggle_key;
+        app.world
+            .get_non_send_resource_mut::<RawEventSubscribers>()
+            .unwrap()
+            .subscribe(move |world, event| {
+                if let Event::WindowEvent {
+                    event: WindowEvent::KeyboardInput { input, .. },
+                    ..
+                } = event
+                {
+                    let pressed = input.state == ElementState::Pressed;
+                    let key = input.virtual_keycode.map(KeyCode::from);
+                    if pressed && key == Some(toggle_key) {
+                        if let Some(mut overlay) = world.get_resource_mut::<DebugOverlay>() {
+                            overlay.enabled = !overlay.enabled;
+                        }
+                    }
+                }
+            });
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OverlayLine {
+    pub text: String,
+    /// Byte ranges of `text`, white elsewhere
+    pub spans: Vec<(Range<usize>, Color)>,
+}
+
+pub struct DebugOverlay {
+    pub enabled: bool,
+    lines: Vec<OverlayLine>,
+}
+
+impl DebugOverlay {
+    pub fn text(&mut self, line: impl Into<String>) {
+        self.colored_text(line, Vec::new());
+    }
+
+    /// e.g. `overlay.colored_text("[ERROR] msg", vec![(0..7, [1.0, 0.0, 0.0, 1.0])])`
+    pub fn colored_text(&mut self, line: impl Into<String>, spans: Vec<(Range<usize>, Color)>) {
+        self.lines.push(OverlayLine {
+            text: line.into(),
+            spans,
+        });
+    }
+
+    pub fn lines(&self) -> &[OverlayLine] {
+        &self.lines
+    }
+}
+
+pub fn clear_debug_overlay_system(mut overlay: ResMut<DebugOverlay>) {
+    overlay.lines.clear();
+}
+
+pub fn debug_stats_panel_system(
+    mut overlay: ResMut<DebugOverlay>,
+    mut frame_stats: ResMut<FrameStats>,
+    stats: Res<RenderStats>,
+    pool: Res<BufferPool>,
+    uploads: Res<UploadQueue>,
+    shaders: Res<Assets<ShaderSource>>,
+    images: Res<Assets<Image>>,
+    texts: Res<Assets<Text>>,
+    profiler: Option<Res<Profiler>>,
+) {
+    if !overlay.enabled {
+        return;
+    }
+
+    if frame_stats.frame_count() > 0 {
+        let one_percent_low = frame_stats.one_percent_low();
+        overlay.text(format!(
+            "frame {:.2} ms (avg {:.2} ms, {:.0} fps, 1% low {:.0} fps)",
+            frame_stats.frame_time_ms(),
+            frame_stats.average_frame_time_ms(),
+            frame_stats.fps(),
+            one_percent_low
+        ));
+    }
+    // NOTE: stats are from the last rendered frame
+    overlay.text(format!(
+        "draw calls {} (indirect batches {}, draws {}), pipeline switches {}",
+        stats.draw_calls, stats.indirect_batches, stats.indirect_draws, stats.pipeline_switches
+    ));
+    overlay.text(format!(
+        "shared mesh draws {} ({} bytes saved)",
+        stats.shared_mesh_draws, stats.shared_mesh_bytes_saved
+    ));
+    let timings = stats.pass_timings;
+    if let (Some(main_pass), Some(source)) = (timings.main_pass, timings.source) {
+        let prepass = match timings.depth_prepass {
+            Some(prepass) => format!(
+                ", depth prepass {:.2} ms ({} draws)",
+                prepass.as_secs_f32() * 1000.0,
+                stats.depth_prepass_draws
+            ),
+            None => String::new(),
+        };
+        overlay.text(format!(
+            "main pass {:.2} ms{} ({:?})",
+            main_pass.as_secs_f32() * 1000.0,
+            prepass,
+            source
+        ));
+    }
+    if !stats.lod_entities.is_empty() {
+        let levels: Vec<String> = stats.lod_entities.iter().map(u32::to_string).collect();
+        overlay.text(format!("lod entities {}", levels.join(" / ")));
+    }
+    let pool = pool.stats();
+    overlay.text(format!(
+        "buffer pool hits {}, misses {} ({} of {} bytes in use)",
+        pool.hits, pool.misses, pool.bytes_outstanding, pool.bytes_allocated
+    ));
+    let uploads = uploads.stats();
+    if uploads.queued_bytes > 0 {
+        overlay.text(format!(
+            "uploads queued {} bytes (~{} frames)",
+            uploads.queued_bytes, uploads.frames_to_drain
+        ));
+    }
+    overlay.text(format!(
+        "assets: shaders {}, images {}, texts {}",
+        shaders.len(),
+        images.len(),
+        texts.len()
+    ));
+    if let Some(profiler) = profiler.filter(|profiler| profiler.enabled) {
+        overlay.text(format!("profiler, last {} frames:", profiler.frame_count()));
+        for span in profiler.report() {
+            overlay.text(format!(
+                "  {} {:.2} ms (p95 {:.2} ms)",
+                span.name,
+                span.mean.as_secs_f32() * 1000.0,
+                span.p95.as_secs_f32() * 1000.0
+            ));
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OverlayScreen {
+    pub width: f32,
+    pub height: f32,
+}
+
+impl UpdateGpuUniform for OverlayScreen {
+    type GU = OverlayScreenUniform;
+
+    fn update_uniform(&self, gpu_uniform: &mut Self::GU) {
+        gpu_uniform.size = [self.width, self.height, 0.0, 0.0];
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, C, Pod, Zeroable)]
+pub struct OverlayScreenUniform {
+    pub size: [f32; 4],
+}
+impl GpuUniform for OverlayScreenUniform {}
+impl Default for OverlayScreenUniform {
+    fn default() -> Self {
+        Self {
+            size: [1.0, 1.0, 0.0, 0.0],
+        }
+    }
+}
+
+pub struct DebugOverlayRenderer {
+    atlas: AtlasTexture,
+    screen: Uniform<OverlayScreen>,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+    vertex_buffer: Option<PooledBuffer>,
+    vertex_count: u32,
+    // lines of the current vertex buffer
+    drawn: Vec<OverlayLine>,
+}
+
+impl DebugOverlayRenderer {
+    const MARGIN: f32 = 8.0;
+    // keeps the text readable over bright scenes
+    const SHADOW: TextEffect = TextEffect::Shadow {
+        offset: (1.0, -1.0),
+        color: [0.0, 0.0, 0.0, 0.8],
+    };
+
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        format: wgpu::TextureFormat,
+        atlas: TextAtlas,
+    ) -> anyhow::Result<Self> {
+        // NOTE: the bytes are dropped with `atlas`, only the glyphs are kept
+        let atlas = AtlasTexture::new(device, queue, &atlas, Some("Debug Overlay Atlas"))?;
+        let screen: Uniform<OverlayScreen> =
+            Uniform::new_default(device, wgpu::ShaderStages::VERTEX);
+
+        let set = (&screen, &atlas.texture.view, &atlas.texture.sampler);
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Debug Overlay Bind Group Layout"),
+            entries: &set.layout_desc().entries,
+        });
+        let bind_group = set.into_bind_group(device);
+
+        let module =
+            device.create_shader_module(wgpu::include_wgsl!("../../res/debug_overlay.wgsl"));
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Debug Overlay Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Debug Overlay Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &module,
+                entry_point: Shader::VERTEX_ENTRY_POINT,
+                buffers: &[VertexTextured2DColor::layout()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &module,
+                entry_point: Shader::FRAGMENT_ENTRY_POINT,
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        Ok(Self {
+            atlas,
+            screen,
+            bind_group,
+            pipeline,
+            vertex_buffer: None,
+            vertex_count: 0,
+            drawn: Vec::new(),
+        })
+    }
+
+    /// Rebuilds the glyph quads when the lines change
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        pool: &mut BufferPool,
+        screen: &ScreenSpace,
+        lines: &[OverlayLine],
+    ) {
+        // NOTE: in logical pixels, the text keeps its size on high DPI screens
+        let size = screen.logical_size();
+        let screen = OverlayScreen {
+            width: size.x,
+            height: size.y,
+        };
+        self.screen.update(&screen);
+        self.screen.sync_buffer(queue);
+
+        if self.drawn == lines {
+            return;
+        }
+        self.drawn = lines.to_vec();
+
+        let line_height = self.atlas.glyphs.h as f32;
+        let mut vertices = Vec::new();
+        for (i, line) in lines.iter().enumerate() {
+            // NOTE: the atlas only has the first 128 characters
+            // NOTE: replaced byte by byte, span ranges stay valid
+            let text: String = line
+                .text
+                .bytes()
+                .map(|byte| if byte.is_ascii() { byte as char } else { '?' })
+                .collect();
+            let style = TextStyle {
+                spans: line.spans.clone(),
+                effect: Some(Self::SHADOW),
+                ..Default::default()
+            };
+            let baseline = screen.height - Self::MARGIN - line_height * (i + 1) as f32;
+            let mesh = create_styled_screen_text_mesh(
+                &self.atlas.glyphs,
+                &text,
+                (Self::MARGIN, baseline),
+                &style,
+            );
+            vertices.extend_from_slice(mesh.get_vertices());
+        }
+
+        self.vertex_count = vertices.len() as u32;
+        self.vertex_buffer = (!vertices.is_empty()).then(|| {
+            pool.get_init(
+                device,
+                queue,
+                bytemuck::cast_slice(&vertices),
+                wgpu::BufferUsages::VERTEX,
+            )
+        });
+    }
+
+    /// Draws over the contents of `view`
+    pub fn record_pass(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        let vertex_buffer = match &self.vertex_buffer {
+            Some(vertex_buffer) => vertex_buffer,
+            None => return,
+        };
+
+        let mut overlay_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Debug Overlay Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        overlay_pass.set_pipeline(&self.pipeline);
+        overlay_pass.set_bind_group(0, &self.bind_group, &[]);
+        overlay_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+        overlay_pass.draw(0..self.vertex_count, 0..1);
+    }
+}
+
+pub fn prepare_debug_overlay_system(
+    device: Option<Res<Arc<wgpu::Device>>>,
+    queue: Option<Res<wgpu::Queue>>,
+    screen: Res<ScreenSpace>,
+    overlay: Res<DebugOverlay>,
+    mut pool: ResMut<BufferPool>,
+    renderer: Option<ResMut<DebugOverlayRenderer>>,
+) {
+    let (device, queue, mut renderer) = match (device, queue, renderer) {
+        (Some(device), Some(queue), Some(renderer)) => (device, queue, renderer),
+        _ => return,
+    };
+
+    let lines: &[OverlayLine] = if overlay.enabled { overlay.lines() } else { &[] };
+    renderer.prepare(&device, &queue, &mut pool, &screen, lines);
+}
+
+/// Runs in `RenderStage::Present` so the overlay is drawn over any post-processing
+pub fn debug_overlay_pass_system(
+    frame: Res<Option<CurrentFrame>>,
+    device: Option<Res<Arc<wgpu::Device>>>,
+    mut encoders: ResMut<FrameEncoders>,
+    renderer: Option<Res<DebugOverlayRenderer>>,
+) {
+    let (frame, device, renderer) = match (frame.as_ref().as_ref(), device, renderer) {
+        (Some(frame), Some(device), Some(renderer)) => (frame, device, renderer),
+        _ => return,
+    };
+    renderer.record_pass(encoders.encoder(&device), &frame.view);
+}
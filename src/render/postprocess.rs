@@ -0,0 +1,428 @@
+use std::sync::Arc;
+
+use bevy_app::Plugin;
+use bevy_ecs::{
+    schedule::ParallelSystemDescriptorCoercion,
+    system::{Res, ResMut},
+};
+use bytemuck::{Pod, Zeroable};
+use repr_trait::C;
+
+use crate::{FlatSystemLabels, RenderStage};
+
+use super::{
+    offscreen::OffscreenTarget,
+    prepare_frame_system,
+    resource::{
+        bind::{BindingSet, GpuUniform, Uniform, UpdateGpuUniform},
+        shader::Shader,
+    },
+    targets::RenderTargets,
+    CurrentFrame, DepthConvention, DepthFormat, FrameEncoders,
+};
+
+/// Renders the main pass into an HDR texture and tonemaps it onto the frame.
+///
+/// Systems added to `RenderStage::PostProcess` that read the frame should run
+/// after [`post_process_system`]. Set [`PostProcessSettings::enabled`] to `false`
+/// to draw the main pass directly to the frame.
+pub struct FlatPostProcessPlugin;
+impl Plugin for FlatPostProcessPlugin {
+    fn build(&self, app: &mut bevy_app::App) {
+        app.init_resource::<PostProcessSettings>()
+            .init_resource::<Option<PostProcessRenderer>>()
+            .add_system_to_stage(
+                RenderStage::Prepare,
+                prepare_post_process_system
+                    .label(FlatSystemLabels::RenderPrepare)
+                    .after(prepare_frame_system),
+            )
+            .add_system_to_stage(RenderStage::PostProcess, post_process_system);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tonemapping {
+    /// Clamps to `[0, 1]`, only below zero on an HDR surface, see
+    /// [`WgpuSettings::prefer_hdr`](super::settings::WgpuSettings::prefer_hdr)
+    None,
+    Reinhard,
+    Aces,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Vignette {
+    /// Darkening at the corners, 0.0 to 1.0
+    pub intensity: f32,
+    /// Distance from the center where darkening starts, 0.0 at the center and 1.0 at the corners
+    pub radius: f32,
+}
+
+impl Default for Vignette {
+    fn default() -> Self {
+        Self {
+            intensity: 0.4,
+            radius: 0.5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PostProcessSettings {
+    /// `false` bypasses post-processing, the main pass draws to the frame directly
+    pub enabled: bool,
+    pub exposure: f32,
+    pub tonemapping: Tonemapping,
+    pub gamma: f32,
+    pub vignette: Option<Vignette>,
+}
+
+impl Default for PostProcessSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            exposure: 1.0,
+            tonemapping: Tonemapping::Aces,
+            gamma: 2.2,
+            vignette: None,
+        }
+    }
+}
+
+impl UpdateGpuUniform for PostProcessSettings {
+    type GU = PostProcessUniform;
+
+    fn update_uniform(&self, gpu_uniform: &mut Self::GU) {
+        let vignette = self.vignette.unwrap_or(Vignette {
+            intensity: 0.0,
+            radius: 1.0,
+        });
+        gpu_uniform.params = [
+            self.exposure,
+            self.gamma,
+            vignette.intensity,
+            vignette.radius,
+        ];
+        gpu_uniform.flags[0] = match self.tonemapping {
+            Tonemapping::None => 0,
+            Tonemapping::Reinhard => 1,
+            Tonemapping::Aces => 2,
+        };
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, C, Pod, Zeroable)]
+pub struct PostProcessUniform {
+    // exposure, gamma, vignette intensity, vignette radius
+    pub params: [f32; 4],
+    // tonemapping operator, 1 if the target takes linear values, 1 if it keeps values
+    // above one
+    pub flags: [u32; 4],
+}
+impl GpuUniform for PostProcessUniform {}
+impl Default for PostProcessUniform {
+    fn default() -> Self {
+        Self {
+            params: [1.0, 2.2, 0.0, 1.0],
+            flags: [0; 4],
+        }
+    }
+}
+
+fn is_float_format(format: wgpu::TextureFormat) -> bool {
+    matches!(
+        format,
+        wgpu::TextureFormat::Rgba16Float | wgpu::TextureFormat::Rgba32Float
+    )
+}
+
+/// Format the main pass pipelines have to target, systems read it from [`RenderTargets`]
+pub fn main_pass_format(
+    config: Option<&wgpu::SurfaceConfiguration>,
+    offscreen: Option<&OffscreenTarget>,
+    settings: Option<&PostProcessSettings>,
+) -> Option<wgpu::TextureFormat> {
+    RenderTargets::new(
+        config,
+        offscreen,
+        settings,
+        DepthFormat::default(),
+        DepthConvention::default(),
+    )
+    .map(|targets| targets.main_pass_format())
+}
+
+struct HdrTarget {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    bind_group: wgpu::BindGroup,
+    size: (u32, u32),
+}
+
+pub struct PostProcessRenderer {
+    settings: Uniform<PostProcessSettings>,
+    sampler: wgpu::Sampler,
+    // recreated when the frame is resized
+    target: Option<HdrTarget>,
+    // recreated when the frame format changes
+    pipeline: Option<(wgpu::TextureFormat, wgpu::RenderPipeline)>,
+}
+
+impl PostProcessRenderer {
+    pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+    pub fn new(device: &wgpu::Device) -> Self {
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Post Process Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            settings: Uniform::new_default(device, wgpu::ShaderStages::FRAGMENT),
+            sampler,
+            target: None,
+            pipeline: None,
+        }
+    }
+
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        settings: &PostProcessSettings,
+        format: wgpu::TextureFormat,
+        size: (u32, u32),
+    ) {
+        // NOTE: float surfaces are linear extended range, e.g. scRGB
+        let float = is_float_format(format);
+        let linear = format.describe().srgb || float;
+        self.settings.update(settings);
+        self.settings.modify(|gpu_uniform| {
+            gpu_uniform.flags[1] = linear as u32;
+            gpu_uniform.flags[2] = float as u32;
+        });
+        self.settings.sync_buffer(queue);
+
+        if self.target.as_ref().map(|target| target.size) != Some(size) {
+            self.target = Some(self.create_target(device, size));
+        }
+        if self.pipeline.as_ref().map(|(format, _)| *format) != Some(format) {
+            self.pipeline = Some((format, self.create_pipeline(device, format)));
+        }
+    }
+
+    /// Where the main pass draws while post-processing is enabled
+    pub fn main_pass_view(&self) -> Option<wgpu::TextureView> {
+        self.target.as_ref().map(|target| {
+            target
+                .texture
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        })
+    }
+
+    fn create_target(&self, device: &wgpu::Device, (width, height): (u32, u32)) -> HdrTarget {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Post Process HDR Target"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::HDR_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = (&self.settings, &view, &self.sampler).into_bind_group(device);
+
+        HdrTarget {
+            texture,
+            view,
+            bind_group,
+            size: (width, height),
+        }
+    }
+
+    fn create_pipeline(
+        &self,
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+    ) -> wgpu::RenderPipeline {
+        let target = self.target.as_ref().unwrap();
+        let set = (&self.settings, &target.view, &self.sampler);
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Post Process Bind Group Layout"),
+            entries: &set.layout_desc().entries,
+        });
+
+        let module =
+            device.create_shader_module(wgpu::include_wgsl!("../../res/post_process.wgsl"));
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Post Process Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Post Process Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &module,
+                entry_point: Shader::VERTEX_ENTRY_POINT,
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &module,
+                entry_point: Shader::FRAGMENT_ENTRY_POINT,
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    }
+
+    /// Tonemaps the HDR target onto `view`
+    pub fn record_pass(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        let (target, (_, pipeline)) = match (&self.target, &self.pipeline) {
+            (Some(target), Some(pipeline)) => (target, pipeline),
+            _ => return,
+        };
+
+        let mut post_process_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Post Process Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        post_process_pass.set_pipeline(pipeline);
+        post_process_pass.set_bind_group(0, &target.bind_group, &[]);
+        post_process_pass.draw(0..3, 0..1);
+    }
+}
+
+/// Redirects the main pass of the acquired frame into the HDR target
+pub fn prepare_post_process_system(
+    device: Option<Res<Arc<wgpu::Device>>>,
+    queue: Option<Res<wgpu::Queue>>,
+    settings: Res<PostProcessSettings>,
+    mut frame: ResMut<Option<CurrentFrame>>,
+    mut renderer: ResMut<Option<PostProcessRenderer>>,
+) {
+    if !settings.enabled {
+        return;
+    }
+    let (device, queue, frame) = match (device, queue, frame.as_mut().as_mut()) {
+        (Some(device), Some(queue), Some(frame)) => (device, queue, frame),
+        _ => return,
+    };
+
+    let renderer = renderer.get_or_insert_with(|| PostProcessRenderer::new(&device));
+    renderer.prepare(
+        &device,
+        &queue,
+        &settings,
+        frame.format,
+        (frame.width, frame.height),
+    );
+    if let Some(view) = renderer.main_pass_view() {
+        frame.redirect_main_pass(view);
+    }
+}
+
+pub fn post_process_system(
+    device: Option<Res<Arc<wgpu::Device>>>,
+    settings: Res<PostProcessSettings>,
+    frame: Res<Option<CurrentFrame>>,
+    renderer: Res<Option<PostProcessRenderer>>,
+    mut encoders: ResMut<FrameEncoders>,
+) {
+    if !settings.enabled {
+        return;
+    }
+    let (device, frame, renderer) =
+        match (device, frame.as_ref().as_ref(), renderer.as_ref().as_ref()) {
+            (Some(device), Some(frame), Some(renderer)) => (device, frame, renderer),
+            _ => return,
+        };
+    renderer.record_pass(encoders.encoder(&device), &frame.view);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::render::resource::bind::UpdateGpuUniform;
+
+    use super::{
+        main_pass_format, PostProcessRenderer, PostProcessSettings, PostProcessUniform,
+        Tonemapping, Vignette,
+    };
+
+    #[test]
+    fn settings_fill_the_uniform() {
+        let settings = PostProcessSettings {
+            exposure: 2.0,
+            tonemapping: Tonemapping::Reinhard,
+            vignette: Some(Vignette {
+                intensity: 0.5,
+                radius: 0.3,
+            }),
+            ..Default::default()
+        };
+        let mut gpu_uniform = PostProcessUniform::default();
+        settings.update_uniform(&mut gpu_uniform);
+        assert_eq!(gpu_uniform.params, [2.0, 2.2, 0.5, 0.3]);
+        assert_eq!(gpu_uniform.flags[0], 1);
+
+        let settings = PostProcessSettings {
+            vignette: None,
+            ..settings
+        };
+        settings.update_uniform(&mut gpu_uniform);
+        assert_eq!(gpu_uniform.params[2], 0.0);
+    }
+
+    #[test]
+    fn bypass_keeps_the_target_format() {
+        let enabled = PostProcessSettings::default();
+        let bypassed = PostProcessSettings {
+            enabled: false,
+            ..Default::default()
+        };
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: wgpu::TextureFormat::Bgra8Unorm,
+            width: 1,
+            height: 1,
+            present_mode: wgpu::PresentMode::Fifo,
+        };
+
+        assert_eq!(
+            main_pass_format(Some(&config), None, Some(&enabled)),
+            Some(PostProcessRenderer::HDR_FORMAT)
+        );
+        assert_eq!(
+            main_pass_format(Some(&config), None, Some(&bypassed)),
+            Some(wgpu::TextureFormat::Bgra8Unorm)
+        );
+        assert_eq!(
+            main_pass_format(Some(&config), None, None),
+            Some(config.format)
+        );
+        assert_eq!(main_pass_format(None, None, Some(&enabled)), None);
+    }
+}
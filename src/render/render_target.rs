@@ -0,0 +1,300 @@
+use std::sync::Arc;
+
+use bevy_ecs::{
+    entity::Entity,
+    prelude::Component,
+    query::Without,
+    system::{Local, Query, Res, ResMut},
+};
+
+use crate::{
+    color::Color,
+    texture::Texture,
+    util::{Refer, Store},
+};
+
+use super::{
+    bind_mesh, draw_mesh,
+    indirect::{draw_indirect_batch, IndirectBatch},
+    mesh::GpuMesh,
+    resolve_mesh,
+    resource::{bind::BindSlots, pipeline::RenderPipeline},
+    visibility::{visible, ComputedVisibility},
+    DepthConvention, DepthFormat, DepthTexture, DrawValidator, FrameEncoders, InstanceData,
+    StencilRef, WithMesh,
+};
+
+/// Draws the mesh entities into `target` in `RenderStage::MainPass`, before the main pass,
+/// e.g. for a minimap, mirror or portal. Bind `target` like any other texture, as
+/// `(&target.view, &target.sampler)`, to show the result.
+///
+/// The size is fixed at creation and does not follow the window, replace the component
+/// to resize it. `target` has to be created with the format the pipelines of the drawn
+/// entities target, see [`Texture::create_render_target`] and
+/// [`RenderTargets::main_pass_format`](super::targets::RenderTargets::main_pass_format).
+///
+/// NOTE: entities sampling `target` need [`HiddenFromRenderTargets`], a texture can not
+/// be sampled while it is rendered to
+#[derive(Component)]
+pub struct RenderToTexture {
+    pub target: Texture,
+    pub clear: Color,
+    /// Replaces the camera of every draw, see [`CameraBinding`]
+    pub camera: Option<CameraBinding>,
+    size: (u32, u32),
+    // created in RenderStage::Prepare, with the DepthFormat
+    depth: Option<DepthTexture>,
+}
+
+impl RenderToTexture {
+    /// `size` is the one `target` was created with
+    pub fn new(target: Texture, size: (u32, u32), clear: Color) -> Self {
+        Self {
+            target,
+            clear,
+            camera: None,
+            size,
+            depth: None,
+        }
+    }
+
+    /// A render target of `size`, see [`Texture::create_render_target`]
+    pub fn with_size(
+        device: &wgpu::Device,
+        size: (u32, u32),
+        format: wgpu::TextureFormat,
+        clear: Color,
+    ) -> Self {
+        Self::new(
+            Texture::create_render_target(device, size.0, size.1, format),
+            size,
+            clear,
+        )
+    }
+
+    pub fn with_camera(mut self, group: u32, bind_group: usize) -> Self {
+        self.camera = Some(CameraBinding { group, bind_group });
+        self
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+}
+
+/// Bind group of another camera, in `Store<wgpu::BindGroup>`, set in `group` instead of
+/// the one the entities were spawned with.
+///
+/// NOTE: instances are culled against the main camera
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CameraBinding {
+    pub group: u32,
+    pub bind_group: usize,
+}
+
+impl CameraBinding {
+    /// `binds` with the camera of the target, if it has one
+    pub fn apply(camera: Option<CameraBinding>, binds: &BindSlots) -> BindSlots {
+        let mut binds = binds.clone();
+        if let Some(camera) = camera {
+            binds.set(camera.group, camera.bind_group);
+        }
+        binds
+    }
+}
+
+/// Drawn in the main pass only, not into any [`RenderToTexture`]
+#[derive(Component, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HiddenFromRenderTargets;
+
+/// Creates the depth textures of the render targets, again when the [`DepthFormat`] changes
+pub fn prepare_render_targets_system(
+    device: Option<Res<Arc<wgpu::Device>>>,
+    depth_format: Res<DepthFormat>,
+    mut targets: Query<&mut RenderToTexture>,
+) {
+    let device = match device {
+        Some(device) => device,
+        None => return,
+    };
+    for mut target in targets.iter_mut() {
+        if target
+            .depth
+            .as_ref()
+            .map_or(false, |depth| depth.format() == depth_format.0)
+        {
+            continue;
+        }
+        let (width, height) = target.size;
+        target.depth = Some(DepthTexture::new(
+            Texture::create_depth_texture_with_format(
+                &device,
+                width,
+                height,
+                depth_format.0,
+                "Render Target Depth Texture",
+            ),
+            depth_format.0,
+        ));
+    }
+}
+
+/// Records a pass for each [`RenderToTexture`], runs before `main_pass_system`
+pub fn render_to_texture_system(
+    device: Option<Res<Arc<wgpu::Device>>>,
+    mut encoders: ResMut<FrameEncoders>,
+    pipelines: Res<Store<RenderPipeline>>,
+    bind_groups: Res<Store<wgpu::BindGroup>>,
+    meshes: Res<Store<GpuMesh>>,
+    depth_convention: Res<DepthConvention>,
+    targets: Query<&RenderToTexture>,
+    objects: Query<
+        (
+            (
+                Entity,
+                &Refer<RenderPipeline>,
+                &BindSlots,
+                Option<&GpuMesh>,
+                Option<&Refer<GpuMesh>>,
+                Option<&InstanceData>,
+                Option<&StencilRef>,
+            ),
+            Option<&ComputedVisibility>,
+        ),
+        (
+            WithMesh,
+            Without<IndirectBatch>,
+            Without<HiddenFromRenderTargets>,
+        ),
+    >,
+    batches: Query<
+        (
+            (
+                Entity,
+                &Refer<RenderPipeline>,
+                &BindSlots,
+                Option<&GpuMesh>,
+                Option<&Refer<GpuMesh>>,
+                Option<&InstanceData>,
+                &IndirectBatch,
+                Option<&StencilRef>,
+            ),
+            Option<&ComputedVisibility>,
+        ),
+        (WithMesh, Without<HiddenFromRenderTargets>),
+    >,
+    mut draw_validator: Local<DrawValidator>,
+) {
+    let device = match device {
+        Some(device) => device,
+        None => return,
+    };
+    let multi_draw = device
+        .features()
+        .contains(wgpu::Features::MULTI_DRAW_INDIRECT);
+
+    for target in targets.iter() {
+        // NOTE: created in RenderStage::Prepare once there is a device
+        let depth = match &target.depth {
+            Some(depth) => depth,
+            None => continue,
+        };
+        let has_stencil = DepthFormat(depth.format()).has_stencil();
+
+        let encoder = encoders.encoder(&device);
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Render To Texture Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &target.target.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(target.clear.into()),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &depth.texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(depth_convention.clear_depth()),
+                    store: true,
+                }),
+                stencil_ops: has_stencil.then(|| wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(0),
+                    store: true,
+                }),
+            }),
+        });
+
+        for (entity, pipeline, binds, owned, shared, instance, stencil) in visible(objects.iter()) {
+            let mesh = match resolve_mesh(owned, shared, &meshes) {
+                Some(mesh) => mesh,
+                None => continue,
+            };
+            let pipeline = match pipeline.get(&pipelines) {
+                Some(pipeline) => pipeline,
+                None => continue,
+            };
+            let binds = CameraBinding::apply(target.camera, binds);
+            if !draw_validator.check(entity, pipeline, &binds, mesh) {
+                continue;
+            }
+            let groups = match draw_validator.bind_groups(entity, &binds, &bind_groups) {
+                Some(groups) => groups,
+                None => continue,
+            };
+            render_pass.set_stencil_reference(StencilRef::reference(stencil));
+            draw_mesh(&mut render_pass, &pipeline.pipeline, groups, mesh, instance);
+        }
+
+        for (entity, pipeline, binds, owned, shared, instance, batch, stencil) in
+            visible(batches.iter())
+        {
+            let mesh = match resolve_mesh(owned, shared, &meshes) {
+                Some(mesh) => mesh,
+                None => continue,
+            };
+            let pipeline = match pipeline.get(&pipelines) {
+                Some(pipeline) => pipeline,
+                None => continue,
+            };
+            let binds = CameraBinding::apply(target.camera, binds);
+            if !draw_validator.check(entity, pipeline, &binds, mesh) {
+                continue;
+            }
+            let groups = match draw_validator.bind_groups(entity, &binds, &bind_groups) {
+                Some(groups) => groups,
+                None => continue,
+            };
+            render_pass.set_stencil_reference(StencilRef::reference(stencil));
+            bind_mesh(&mut render_pass, &pipeline.pipeline, groups, mesh, instance);
+            draw_indirect_batch(&mut render_pass, batch, mesh, multi_draw);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::render::resource::bind::BindSlots;
+
+    use super::CameraBinding;
+
+    #[test]
+    fn camera_binding_replaces_its_group_only() {
+        let binds = BindSlots::new().with(0, 3).with(1, 7);
+        assert_eq!(CameraBinding::apply(None, &binds), binds);
+
+        let camera = CameraBinding {
+            group: 0,
+            bind_group: 9,
+        };
+        assert_eq!(
+            CameraBinding::apply(Some(camera), &binds),
+            BindSlots::new().with(0, 9).with(1, 7)
+        );
+        // entities without a camera get one
+        assert_eq!(
+            CameraBinding::apply(Some(camera), &BindSlots::new().with(1, 7)),
+            BindSlots::new().with(0, 9).with(1, 7)
+        );
+    }
+}
@@ -1,9 +1,101 @@
 use std::{marker::PhantomData, num::NonZeroU32};
 
+use bevy_ecs::{event::EventReader, prelude::Component, system::Query};
 use bytemuck::{Pod, Zeroable};
 use repr_trait::C;
 use wgpu::util::DeviceExt;
 
+use crate::util::{ReferMany, StoreRemoved};
+
+use super::debug_label;
+
+/// `wgpu::Limits::default().max_bind_groups`
+pub const MAX_BIND_GROUPS: usize = 4;
+
+/// Bind groups of an entity by group index, keys into `Store<wgpu::BindGroup>`.
+///
+/// ```ignore
+/// BindSlots::new().with(0, camera_bind_group).with(1, material_bind_group)
+/// ```
+#[derive(Component, Debug, Default, Clone, PartialEq, Eq)]
+pub struct BindSlots([Option<usize>; MAX_BIND_GROUPS]);
+
+impl BindSlots {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, group: u32, key: usize) -> Self {
+        self.set(group, key);
+        self
+    }
+
+    pub fn set(&mut self, group: u32, key: usize) {
+        assert!(
+            (group as usize) < MAX_BIND_GROUPS,
+            "Bind group index {} is out of range",
+            group
+        );
+        self.0[group as usize] = Some(key);
+    }
+
+    pub fn clear(&mut self, group: u32) {
+        if let Some(slot) = self.0.get_mut(group as usize) {
+            *slot = None;
+        }
+    }
+
+    pub fn get(&self, group: u32) -> Option<usize> {
+        self.0.get(group as usize).copied().flatten()
+    }
+
+    /// Filled slots as (group, key), in group order
+    pub fn iter(&self) -> impl Iterator<Item = (u32, usize)> + '_ {
+        self.0
+            .iter()
+            .enumerate()
+            .filter_map(|(group, key)| key.map(|key| (group as u32, key)))
+    }
+
+    /// First of the groups `0..group_count` left empty
+    pub fn missing(&self, group_count: u32) -> Option<u32> {
+        (0..group_count).find(|group| self.get(*group).is_none())
+    }
+}
+
+/// Clears the slots of bind groups removed with `Store::remove_with_events`, draws then
+/// skip the entity with a warning until the slot is set again
+pub fn sweep_removed_bind_slots_system(
+    mut events: EventReader<StoreRemoved<wgpu::BindGroup>>,
+    mut slots: Query<&mut BindSlots>,
+) {
+    let removed: Vec<usize> = events.iter().map(|event| event.0).collect();
+    if removed.is_empty() {
+        return;
+    }
+    for mut binds in slots.iter_mut() {
+        let groups: Vec<u32> = binds
+            .iter()
+            .filter(|(_, key)| removed.contains(key))
+            .map(|(group, _)| group)
+            .collect();
+        for group in groups {
+            binds.clear(group);
+        }
+    }
+}
+
+/// Migration from the insertion ordered `ReferMany`, the n-th key goes to group n
+impl From<&ReferMany<wgpu::BindGroup>> for BindSlots {
+    fn from(keys: &ReferMany<wgpu::BindGroup>) -> Self {
+        keys.iter()
+            .enumerate()
+            .fold(Self::new(), |slots, (group, key)| {
+                slots.with(group as u32, *key)
+            })
+    }
+}
+
 #[derive(Debug)]
 pub struct BindingLayoutEntry {
     pub visibility: wgpu::ShaderStages,
@@ -103,7 +195,10 @@ pub struct Uniform<H>
 where
     H: UpdateGpuUniform,
 {
-    pub gpu_uniform: H::GU,
+    gpu_uniform: H::GU,
+    // contents of the buffer after the last write
+    synced: H::GU,
+    dirty: bool,
     buffer: UniformBuffer<H::GU>,
     _uniform_repr: PhantomData<H>,
 }
@@ -116,13 +211,90 @@ where
         let buffer = UniformBuffer::new_init_at(device, stage, gpu_uniform);
         Self {
             gpu_uniform,
+            synced: gpu_uniform,
+            dirty: false,
             buffer,
             _uniform_repr: PhantomData,
         }
     }
 
-    pub fn sync_buffer(&self, queue: &wgpu::Queue) {
+    pub fn get(&self) -> &H::GU {
+        &self.gpu_uniform
+    }
+
+    pub fn set(&mut self, gpu_uniform: H::GU) {
+        self.gpu_uniform = gpu_uniform;
+        self.dirty = true;
+    }
+
+    pub fn modify(&mut self, f: impl FnOnce(&mut H::GU)) {
+        f(&mut self.gpu_uniform);
+        self.dirty = true;
+    }
+
+    pub fn update(&mut self, source: &H) {
+        self.modify(|gpu_uniform| source.update_uniform(gpu_uniform));
+    }
+
+    /// Modified since the last write and different from the buffer contents
+    pub fn is_changed(&self) -> bool {
+        self.dirty && bytemuck::bytes_of(&self.gpu_uniform) != bytemuck::bytes_of(&self.synced)
+    }
+
+    /// Returns `false` if the write was skipped because nothing changed
+    pub fn sync_buffer(&mut self, queue: &wgpu::Queue) -> bool {
+        let changed = self.is_changed();
+        self.dirty = false;
+        if !changed {
+            return false;
+        }
         self.buffer.update(queue, self.gpu_uniform);
+        self.synced = self.gpu_uniform;
+        true
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct UniformSyncStats {
+    pub writes: u32,
+    pub skipped: u32,
+}
+
+impl UniformSyncStats {
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+impl std::ops::AddAssign for UniformSyncStats {
+    fn add_assign(&mut self, other: Self) {
+        self.writes += other.writes;
+        self.skipped += other.skipped;
+    }
+}
+
+/// Syncs uniforms through one queue, skipping the unchanged ones
+// TODO: coalesce the writes of the same GpuUniform type into a shared arena buffer,
+// needs dynamic offsets
+pub struct UniformSyncBatcher<'a> {
+    queue: &'a wgpu::Queue,
+    pub stats: UniformSyncStats,
+}
+
+impl<'a> UniformSyncBatcher<'a> {
+    pub fn new(queue: &'a wgpu::Queue) -> Self {
+        Self {
+            queue,
+            stats: Default::default(),
+        }
+    }
+
+    pub fn sync<H: UpdateGpuUniform>(&mut self, uniform: &mut Uniform<H>) {
+        if uniform.sync_buffer(self.queue) {
+            self.stats.writes += 1;
+        } else {
+            self.stats.skipped += 1;
+        }
     }
 }
 
@@ -175,9 +347,10 @@ impl<T: GpuUniform> UniformBuffer<T> {
 }
 
 impl<T: StageLockedUniform> UniformBuffer<T> {
-    pub fn new_init(device: &wgpu::Device, init: T) -> Self {
+    /// `label` names the buffer in validation errors
+    pub fn new_init(device: &wgpu::Device, init: T, label: Option<&str>) -> Self {
         let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: None,
+            label: Some(&debug_label("Uniform Buffer", label)),
             contents: bytemuck::cast_slice(&[init]),
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
@@ -207,6 +380,75 @@ impl<T: GpuUniform> Binding for UniformBuffer<T> {
     }
 }
 
+pub struct StorageBuffer<T: Pod> {
+    stage: wgpu::ShaderStages,
+    read_only: bool,
+    len: usize,
+    buffer: wgpu::Buffer,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Pod> StorageBuffer<T> {
+    /// `extra_usage` allows the same buffer to be bound elsewhere,
+    /// e.g. `BufferUsages::VERTEX` to read it back as an instance buffer
+    pub fn new_init(
+        device: &wgpu::Device,
+        stage: wgpu::ShaderStages,
+        read_only: bool,
+        contents: &[T],
+        extra_usage: wgpu::BufferUsages,
+    ) -> Self {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Storage Buffer"),
+            contents: bytemuck::cast_slice(contents),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST | extra_usage,
+        });
+        Self {
+            stage,
+            read_only,
+            len: contents.len(),
+            buffer,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue, vals: &[T]) {
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(vals));
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    pub fn into_buffer(self) -> wgpu::Buffer {
+        self.buffer
+    }
+}
+
+impl<T: Pod> Binding for StorageBuffer<T> {
+    fn get_layout_entry(&self) -> BindingLayoutEntry {
+        BindingLayoutEntry {
+            visibility: self.stage,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage {
+                    read_only: self.read_only,
+                },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }
+    }
+
+    fn get_resource<'a>(&'a self) -> wgpu::BindingResource<'a> {
+        self.buffer.as_entire_binding()
+    }
+}
+
 #[allow(unused)]
 #[cfg(test)]
 mod tests {
@@ -337,6 +579,18 @@ mod tests {
         }
     }
 
+    #[test]
+    fn bind_slots_keep_their_group() {
+        let slots = BindSlots::new().with(1, 7).with(0, 3);
+        assert_eq!(slots.iter().collect::<Vec<_>>(), [(0, 3), (1, 7)]);
+        assert_eq!(slots.missing(2), None);
+        assert_eq!(slots.missing(3), Some(2));
+        assert_eq!(BindSlots::new().with(1, 7).missing(2), Some(0));
+
+        let migrated = BindSlots::from(&ReferMany::<wgpu::BindGroup>::new(vec![3, 7]));
+        assert_eq!(migrated, slots);
+    }
+
     fn uniform_usage(device: &wgpu::Device, queue: &wgpu::Queue) {
         // Create high level reprs of uniforms
         let camera = Camera::default();
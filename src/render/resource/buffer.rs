@@ -14,10 +14,35 @@ impl Indices {
             Indices::U32(vec) => vec.len(),
         }
     }
+
+    pub fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+        let (u16s, u32s): (&[u16], &[u32]) = match self {
+            Indices::U16(vec) => (vec, &[]),
+            Indices::U32(vec) => (&[], vec),
+        };
+        u16s.iter().map(|i| *i as u32).chain(u32s.iter().copied())
+    }
+
+    /// Converts `U16` storage to `U32`, no-op for `U32`
+    pub fn promote(&mut self) {
+        if let Indices::U16(vec) = self {
+            *self = Indices::U32(vec.iter().map(|i| *i as u32).collect());
+        }
+    }
 }
 
 impl Indices {
+    /// Promotes to `U32` if a shifted index would not fit in a u16
     pub fn shift(&mut self, offset: u32) {
+        if let Indices::U16(vec) = self {
+            let max = vec.iter().copied().max().unwrap_or(0) as u32;
+            if max
+                .checked_add(offset)
+                .map_or(true, |max| max > u16::MAX as u32)
+            {
+                self.promote();
+            }
+        }
         match self {
             Indices::U16(vec) => {
                 for ind in vec {
@@ -26,13 +51,17 @@ impl Indices {
             }
             Indices::U32(vec) => {
                 for ind in vec {
-                    *ind += offset;
+                    *ind = ind.checked_add(offset).expect("Index overflows u32");
                 }
             }
         }
     }
 
+    /// Promotes to `U32` if `other` is `U32`
     pub fn extend(&mut self, other: Indices) {
+        if let Indices::U32(_) = other {
+            self.promote();
+        }
         match (self, other) {
             (Indices::U16(vs), Indices::U16(vo)) => {
                 vs.extend(vo);
@@ -40,12 +69,72 @@ impl Indices {
             (Indices::U32(vs), Indices::U32(vo)) => {
                 vs.extend(vo);
             }
-            (Indices::U16(vs), Indices::U32(vo)) => {
-                vs.extend(vo.iter().map(|a| *a as u16));
-            }
             (Indices::U32(vs), Indices::U16(vo)) => {
                 vs.extend(vo.iter().map(|a| *a as u32));
             }
+            (Indices::U16(_), Indices::U32(_)) => unreachable!("promoted above"),
+        }
+    }
+}
+
+/// Strips are cut with the maximum value of the format, see `strip_index_format`
+impl Indices {
+    pub fn restart_index(&self) -> u32 {
+        match self {
+            Indices::U16(_) => u16::MAX as u32,
+            Indices::U32(_) => u32::MAX,
+        }
+    }
+
+    pub fn push_restart(&mut self) {
+        match self {
+            Indices::U16(vec) => vec.push(u16::MAX),
+            Indices::U32(vec) => vec.push(u32::MAX),
+        }
+    }
+
+    /// [`promote`](Self::promote) keeping restart indices
+    pub fn promote_strip(&mut self) {
+        if let Indices::U16(vec) = self {
+            *self = Indices::U32(
+                vec.iter()
+                    .map(|i| if *i == u16::MAX { u32::MAX } else { *i as u32 })
+                    .collect(),
+            );
+        }
+    }
+
+    /// [`shift`](Self::shift) keeping restart indices, promotes before a shifted
+    /// index would take the restart value
+    pub fn shift_strip(&mut self, offset: u32) {
+        if let Indices::U16(vec) = self {
+            let max = vec
+                .iter()
+                .copied()
+                .filter(|i| *i != u16::MAX)
+                .max()
+                .unwrap_or(0) as u32;
+            if max
+                .checked_add(offset)
+                .map_or(true, |max| max >= u16::MAX as u32)
+            {
+                self.promote_strip();
+            }
+        }
+        match self {
+            Indices::U16(vec) => {
+                for ind in vec.iter_mut().filter(|i| **i != u16::MAX) {
+                    *ind += offset as u16;
+                }
+            }
+            Indices::U32(vec) => {
+                for ind in vec.iter_mut().filter(|i| **i != u32::MAX) {
+                    *ind = ind
+                        .checked_add(offset)
+                        .filter(|i| *i != u32::MAX)
+                        .expect("Index overflows u32");
+                }
+            }
         }
     }
 }
@@ -89,6 +178,20 @@ pub trait MeshVertex: Sized + C + Pod + Zeroable {
     }
 }
 
+/// The `i`th `N` wide element of a flat attribute array, zeros past the end
+pub fn raw_element<const N: usize>(values: &[f32], i: usize) -> [f32; N] {
+    raw_element_or(values, i, 0.0)
+}
+
+/// [`raw_element`] filled with `default` past the end, e.g. white for missing vertex colors
+pub fn raw_element_or<const N: usize>(values: &[f32], i: usize, default: f32) -> [f32; N] {
+    let mut element = [default; N];
+    if let Some(values) = values.get(N * i..N * (i + 1)) {
+        element.copy_from_slice(values);
+    }
+    element
+}
+
 pub trait FromRawVertices: MeshVertex {
     fn from_raw(
         positions: &[f32],
@@ -107,6 +210,17 @@ pub trait FromRawVertex: MeshVertex {
     ) -> Self;
 }
 
+pub trait PositionVertex: MeshVertex {
+    fn position(&self) -> [f32; 3];
+    fn position_mut(&mut self) -> &mut [f32; 3];
+}
+
+pub trait TangentVertex: PositionVertex {
+    fn tex_coords(&self) -> [f32; 2];
+    fn normal(&self) -> [f32; 3];
+    fn set_tangent(&mut self, tangent: [f32; 4]);
+}
+
 pub trait InstanceUnit: Sized + C + Pod + Zeroable {
     // const ATTR_NAMES: &'static [&'static str];
     const ATTRIBUTES: &'static [wgpu::VertexAttribute];
@@ -154,6 +268,16 @@ impl FromRawVertex for Vertex {
     }
 }
 
+impl PositionVertex for Vertex {
+    fn position(&self) -> [f32; 3] {
+        self.position
+    }
+
+    fn position_mut(&mut self) -> &mut [f32; 3] {
+        &mut self.position
+    }
+}
+
 impl FromRawVertices for Vertex {
     fn from_raw(
         positions: &[f32],
@@ -162,18 +286,195 @@ impl FromRawVertices for Vertex {
         _vertex_color: &[f32],
     ) -> Vec<Self> {
         (0..positions.len() / 3)
-            .into_iter()
             .map(|i| Vertex {
-                position: [positions[i], positions[i + 1], positions[i + 2]],
-                tex_coords: [
-                    *texcoords.get(i).unwrap_or(&0.0),
-                    *texcoords.get(i + 1).unwrap_or(&0.0),
-                ],
+                position: raw_element(positions, i),
+                tex_coords: raw_element(texcoords, i),
+            })
+            .collect()
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, C, Pod, Zeroable)]
+pub struct VertexFull {
+    pub position: [f32; 3],
+    pub tex_coords: [f32; 2],
+    pub normal: [f32; 3],
+    pub tangent: [f32; 4], // w: bitangent handedness
+}
+
+impl MeshVertex for VertexFull {
+    const ATTR_NAMES: &'static [&'static str] =
+        &["Position", "Texture Coordinates", "Normal", "Tangent"];
+
+    const ATTRIBUTES: &'static [wgpu::VertexAttribute] = &wgpu::vertex_attr_array![
+        0 => Float32x3,
+        1 => Float32x2,
+        2 => Float32x3,
+        3 => Float32x4,
+    ];
+}
+
+impl FromRawVertex for VertexFull {
+    fn from_raw(
+        position: &[f32; 3],
+        texcoord: &[f32; 2],
+        normal: &[f32; 3],
+        _vertex_color: &[f32; 3],
+    ) -> Self {
+        Self {
+            position: position.clone(),
+            tex_coords: texcoord.clone(),
+            normal: normal.clone(),
+            tangent: [0.0; 4],
+        }
+    }
+}
+
+impl PositionVertex for VertexFull {
+    fn position(&self) -> [f32; 3] {
+        self.position
+    }
+
+    fn position_mut(&mut self) -> &mut [f32; 3] {
+        &mut self.position
+    }
+}
+
+impl TangentVertex for VertexFull {
+    fn tex_coords(&self) -> [f32; 2] {
+        self.tex_coords
+    }
+
+    fn normal(&self) -> [f32; 3] {
+        self.normal
+    }
+
+    fn set_tangent(&mut self, tangent: [f32; 4]) {
+        self.tangent = tangent;
+    }
+}
+
+/// `color` multiplies the sampled texture, white where the OBJ has no vertex colors
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, C, Pod, Zeroable)]
+pub struct VertexColored {
+    pub position: [f32; 3],
+    pub tex_coords: [f32; 2],
+    pub color: [f32; 3],
+}
+
+impl MeshVertex for VertexColored {
+    const ATTR_NAMES: &'static [&'static str] = &["Position", "Texture Coordinates", "Color"];
+
+    const ATTRIBUTES: &'static [wgpu::VertexAttribute] = &wgpu::vertex_attr_array![
+        0 => Float32x3,
+        1 => Float32x2,
+        2 => Float32x3,
+    ];
+}
+
+impl FromRawVertex for VertexColored {
+    fn from_raw(
+        position: &[f32; 3],
+        texcoord: &[f32; 2],
+        _normal: &[f32; 3],
+        vertex_color: &[f32; 3],
+    ) -> Self {
+        Self {
+            position: *position,
+            tex_coords: *texcoord,
+            color: *vertex_color,
+        }
+    }
+}
+
+impl PositionVertex for VertexColored {
+    fn position(&self) -> [f32; 3] {
+        self.position
+    }
+
+    fn position_mut(&mut self) -> &mut [f32; 3] {
+        &mut self.position
+    }
+}
+
+impl FromRawVertices for VertexColored {
+    fn from_raw(
+        positions: &[f32],
+        texcoords: &[f32],
+        _normals: &[f32],
+        vertex_color: &[f32],
+    ) -> Vec<Self> {
+        (0..positions.len() / 3)
+            .map(|i| VertexColored {
+                position: raw_element(positions, i),
+                tex_coords: raw_element(texcoords, i),
+                color: raw_element_or(vertex_color, i, 1.0),
             })
             .collect()
     }
 }
 
+/// Screen space glyphs, `color` multiplies the sampled texture
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, C, Pod, Zeroable)]
+pub struct VertexTextured2DColor {
+    pub position: [f32; 2],
+    pub tex_coords: [f32; 2],
+    pub color: [f32; 4],
+}
+
+impl MeshVertex for VertexTextured2DColor {
+    const ATTR_NAMES: &'static [&'static str] = &["Position", "Texture Coordinates", "Color"];
+
+    const ATTRIBUTES: &'static [wgpu::VertexAttribute] = &wgpu::vertex_attr_array![
+        0 => Float32x2,
+        1 => Float32x2,
+        2 => Float32x4,
+    ];
+}
+
+/// Deformed by up to four joints of a [`Skeleton`](crate::render::skin::Skeleton),
+/// `weights` sum to one. Checked with `Mesh::validate_skin`
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, C, Pod, Zeroable)]
+pub struct VertexSkinned {
+    pub position: [f32; 3],
+    pub tex_coords: [f32; 2],
+    pub normal: [f32; 3],
+    pub joints: [u16; 4],
+    pub weights: [f32; 4],
+}
+
+impl MeshVertex for VertexSkinned {
+    const ATTR_NAMES: &'static [&'static str] = &[
+        "Position",
+        "Texture Coordinates",
+        "Normal",
+        "Joints",
+        "Weights",
+    ];
+
+    const ATTRIBUTES: &'static [wgpu::VertexAttribute] = &wgpu::vertex_attr_array![
+        0 => Float32x3,
+        1 => Float32x2,
+        2 => Float32x3,
+        3 => Uint16x4,
+        4 => Float32x4,
+    ];
+}
+
+impl PositionVertex for VertexSkinned {
+    fn position(&self) -> [f32; 3] {
+        self.position
+    }
+
+    fn position_mut(&mut self) -> &mut [f32; 3] {
+        &mut self.position
+    }
+}
+
 pub struct Instance {
     pub position: Vector3<f32>,
     pub scale: Vector3<f32>,
@@ -181,12 +482,15 @@ pub struct Instance {
 }
 
 impl Instance {
+    pub fn model_matrix(&self) -> cgmath::Matrix4<f32> {
+        cgmath::Matrix4::from_translation(self.position)
+            * cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+            * cgmath::Matrix4::from(self.rotation)
+    }
+
     pub fn to_raw(&self) -> InstanceRaw {
         InstanceRaw {
-            model: (cgmath::Matrix4::from_translation(self.position)
-                * cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
-                * cgmath::Matrix4::from(self.rotation))
-            .into(),
+            model: self.model_matrix().into(),
         }
     }
 }
@@ -205,3 +509,49 @@ impl InstanceUnit for InstanceRaw {
         8 => Float32x4,
     ];
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{raw_element, FromRawVertices, Vertex};
+
+    fn random_values(seed: &mut u64, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|_| {
+                *seed ^= *seed << 13;
+                *seed ^= *seed >> 7;
+                *seed ^= *seed << 17;
+                (*seed % 2000) as f32 / 100.0 - 10.0
+            })
+            .collect()
+    }
+
+    #[test]
+    fn raw_vertices_are_strided() {
+        let mut seed = 0x9E3779B97F4A7C15;
+        for count in [0, 1, 2, 7, 64] {
+            let positions = random_values(&mut seed, 3 * count);
+            let texcoords = random_values(&mut seed, 2 * count);
+
+            let vertices = <Vertex as FromRawVertices>::from_raw(&positions, &texcoords, &[], &[]);
+
+            assert_eq!(vertices.len(), count);
+            for ((vertex, position), tex_coords) in vertices
+                .iter()
+                .zip(positions.chunks_exact(3))
+                .zip(texcoords.chunks_exact(2))
+            {
+                assert_eq!(vertex.position, position);
+                assert_eq!(vertex.tex_coords, tex_coords);
+            }
+        }
+    }
+
+    #[test]
+    fn missing_attributes_are_zero() {
+        let vertices = <Vertex as FromRawVertices>::from_raw(&[1.0; 6], &[2.0, 3.0], &[], &[]);
+
+        assert_eq!(vertices[0].tex_coords, [2.0, 3.0]);
+        assert_eq!(vertices[1].tex_coords, [0.0, 0.0]);
+        assert_eq!(raw_element::<3>(&[1.0, 2.0], 0), [0.0; 3]);
+    }
+}
@@ -0,0 +1,257 @@
+use std::{
+    any::Any,
+    collections::HashSet,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use bevy_ecs::{event::EventWriter, system::ResMut};
+
+use crate::util::Store;
+
+use super::pipeline::RenderPipeline;
+
+/// Creates render pipelines on worker threads, so large shaders do not stall the first frames.
+///
+/// Pipelines are requested under a key from [`Store::reserve`] and moved into
+/// `Store<RenderPipeline>` by `receive_pipelines_system` once created, until then lookups
+/// of the key miss and the entities using it are not drawn. On wasm there are no threads,
+/// the pipeline is created on request and still delivered by the system.
+#[derive(Default)]
+pub struct PipelineCompiler {
+    // started on the first request, the device does not exist before
+    jobs: Option<Jobs<wgpu::Device, RenderPipeline>>,
+}
+
+impl PipelineCompiler {
+    pub const MAX_WORKERS: usize = 4;
+
+    pub fn compile(
+        &mut self,
+        device: &Arc<wgpu::Device>,
+        key: usize,
+        create: impl FnOnce(&wgpu::Device) -> RenderPipeline + Send + 'static,
+    ) {
+        self.jobs
+            .get_or_insert_with(|| Jobs::new(device.clone(), Self::workers()))
+            .push(key, create);
+    }
+
+    /// The pipeline is dropped when it arrives, e.g. when its shader was reloaded meanwhile
+    pub fn cancel(&mut self, key: usize) {
+        if let Some(jobs) = &mut self.jobs {
+            jobs.cancel(key);
+        }
+    }
+
+    fn workers() -> usize {
+        if cfg!(target_arch = "wasm32") {
+            return 0;
+        }
+        thread::available_parallelism()
+            .map_or(1, |workers| workers.get())
+            .min(Self::MAX_WORKERS)
+    }
+}
+
+/// Pipelines requested from the [`PipelineCompiler`], for loading screens
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PipelineProgress {
+    /// Cancelled requests are not counted
+    pub requested: u32,
+    pub ready: u32,
+    /// Creation panicked, e.g. on a validation error
+    pub failed: u32,
+}
+
+impl PipelineProgress {
+    pub fn pending(&self) -> u32 {
+        self.requested - self.ready - self.failed
+    }
+
+    /// `1.0` when nothing is pending
+    pub fn fraction(&self) -> f32 {
+        match self.requested {
+            0 => 1.0,
+            requested => (self.ready + self.failed) as f32 / requested as f32,
+        }
+    }
+}
+
+/// Sent when the last pending pipeline arrives, again after each later batch of requests,
+/// e.g. on a shader reload
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PipelinesReady(pub PipelineProgress);
+
+type Job<C, T> = Box<dyn FnOnce(&C) -> T + Send>;
+
+/// Bookkeeping of `PipelineCompiler`, generic so it can be tested without a device
+struct Jobs<C, T> {
+    context: Arc<C>,
+    // None runs the jobs inline
+    queue: Option<Mutex<Sender<(usize, Job<C, T>)>>>,
+    finished: Mutex<Sender<(usize, thread::Result<T>)>>,
+    results: Mutex<Receiver<(usize, thread::Result<T>)>>,
+    pending: HashSet<usize>,
+}
+
+impl<C: Send + Sync + 'static, T: Send + 'static> Jobs<C, T> {
+    fn new(context: Arc<C>, workers: usize) -> Self {
+        let (finished, results) = mpsc::channel();
+        let queue = (workers > 0).then(|| {
+            let (queue, jobs) = mpsc::channel::<(usize, Job<C, T>)>();
+            let jobs = Arc::new(Mutex::new(jobs));
+            for i in 0..workers {
+                let (context, jobs, finished) = (context.clone(), jobs.clone(), finished.clone());
+                thread::Builder::new()
+                    .name(format!("Pipeline Compiler {}", i))
+                    .spawn(move || loop {
+                        // NOTE: the lock is released before running the job
+                        let next = jobs.lock().unwrap().recv();
+                        // the queue was dropped
+                        let (key, job) = match next {
+                            Ok(next) => next,
+                            Err(_) => break,
+                        };
+                        if finished.send((key, run(job, &context))).is_err() {
+                            break;
+                        }
+                    })
+                    .expect("Could not spawn a pipeline compiler thread");
+            }
+            Mutex::new(queue)
+        });
+
+        Self {
+            context,
+            queue,
+            finished: Mutex::new(finished),
+            results: Mutex::new(results),
+            pending: HashSet::new(),
+        }
+    }
+
+    fn push(&mut self, key: usize, job: impl FnOnce(&C) -> T + Send + 'static) {
+        self.pending.insert(key);
+        match &self.queue {
+            Some(queue) => queue
+                .lock()
+                .unwrap()
+                .send((key, Box::new(job)))
+                .expect("workers live as long as the queue"),
+            None => {
+                let result = run(Box::new(job), &self.context);
+                let _ = self.finished.lock().unwrap().send((key, result));
+            }
+        }
+    }
+
+    fn cancel(&mut self, key: usize) {
+        self.pending.remove(&key);
+    }
+
+    /// Jobs finished since the last call, cancelled ones are left out
+    fn drain(&mut self, mut f: impl FnMut(usize, thread::Result<T>)) {
+        for (key, result) in self.results.get_mut().unwrap().try_iter() {
+            if self.pending.remove(&key) {
+                f(key, result);
+            }
+        }
+    }
+}
+
+fn run<C, T>(job: Job<C, T>, context: &C) -> thread::Result<T> {
+    panic::catch_unwind(AssertUnwindSafe(|| job(context)))
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown error")
+}
+
+/// Moves created pipelines into the store, runs in `RenderStage::Prepare`
+pub fn receive_pipelines_system(
+    mut compiler: ResMut<PipelineCompiler>,
+    mut pipelines: ResMut<Store<RenderPipeline>>,
+    mut progress: ResMut<PipelineProgress>,
+    mut ready: EventWriter<PipelinesReady>,
+) {
+    let jobs = match &mut compiler.jobs {
+        Some(jobs) => jobs,
+        None => return,
+    };
+    let mut received = false;
+    jobs.drain(|key, result| {
+        received = true;
+        match result {
+            Ok(pipeline) => {
+                pipelines.insert_reserved(key, pipeline);
+                progress.ready += 1;
+            }
+            Err(payload) => {
+                log::error!("Pipeline creation failed: {}", panic_message(&*payload));
+                progress.failed += 1;
+            }
+        }
+    });
+    progress.requested = progress.ready + progress.failed + jobs.pending.len() as u32;
+    if received && jobs.pending.is_empty() {
+        ready.send(PipelinesReady(*progress));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::Jobs;
+
+    #[test]
+    fn jobs_run_off_the_calling_thread() {
+        let caller = thread::current().id();
+        let mut jobs: Jobs<u32, (u32, bool)> = Jobs::new(Arc::new(10), 2);
+        for key in 0..4 {
+            jobs.push(key, move |base| {
+                (base + key as u32, thread::current().id() != caller)
+            });
+        }
+        jobs.cancel(2);
+        jobs.push(4, |_| panic!("invalid pipeline"));
+
+        let mut finished = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !jobs.pending.is_empty() && Instant::now() < deadline {
+            jobs.drain(|key, result| finished.push((key, result.ok())));
+            thread::sleep(Duration::from_millis(1));
+        }
+        finished.sort_by_key(|(key, _)| *key);
+        assert_eq!(
+            finished,
+            [
+                (0, Some((10, true))),
+                (1, Some((11, true))),
+                (3, Some((13, true))),
+                // a panicking job is reported, the worker keeps running
+                (4, None),
+            ]
+        );
+
+        // without workers the job runs right away, still delivered by drain
+        let mut inline: Jobs<u32, bool> = Jobs::new(Arc::new(0), 0);
+        inline.push(0, move |_| thread::current().id() == caller);
+        let mut finished = Vec::new();
+        inline.drain(|key, result| finished.push((key, result.ok())));
+        assert_eq!(finished, [(0, Some(true))]);
+    }
+}
@@ -1,4 +1,34 @@
 pub mod bind;
 pub mod buffer;
+pub mod compiler;
 pub mod pipeline;
+pub mod pipeline_cache;
+pub mod pool;
+pub mod preprocess;
+pub mod reflect;
 pub mod shader;
+pub mod shader_lib;
+pub mod uniform_layout;
+
+/// Label of a wgpu object, validation errors name the objects involved by it.
+/// `Vertex Buffer: cube` with a name, `Vertex Buffer` without
+pub fn debug_label(kind: &str, name: Option<&str>) -> String {
+    match name {
+        Some(name) => format!("{}: {}", kind, name),
+        None => kind.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::debug_label;
+
+    #[test]
+    fn labels_name_the_object() {
+        assert_eq!(
+            debug_label("Vertex Buffer", Some("cube")),
+            "Vertex Buffer: cube"
+        );
+        assert_eq!(debug_label("Vertex Buffer", None), "Vertex Buffer");
+    }
+}
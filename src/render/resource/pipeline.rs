@@ -1,61 +1,386 @@
-use super::shader;
+use super::{
+    debug_label,
+    reflect::ReflectionError,
+    shader::{self, ShaderTargets},
+};
 
-pub struct RenderPipeline(pub wgpu::RenderPipeline);
+/// How a pipeline variant uses the depth buffer, see [`DepthPrepass`](crate::render::DepthPrepass).
+/// The comparisons are of the standard [`DepthConvention`](crate::render::DepthConvention)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DepthMode {
+    /// Tests `Less` and writes, the usual main pass
+    Write,
+    /// No fragment stage, fills the depth buffer in the prepass
+    DepthOnly,
+    /// Tests `Equal` without writing, only the front-most fragments are shaded
+    Equal,
+}
+
+impl DepthMode {
+    pub fn depth_stencil(
+        self,
+        format: wgpu::TextureFormat,
+        stencil: wgpu::StencilState,
+    ) -> wgpu::DepthStencilState {
+        wgpu::DepthStencilState {
+            format,
+            depth_write_enabled: self != DepthMode::Equal,
+            depth_compare: match self {
+                DepthMode::Write | DepthMode::DepthOnly => wgpu::CompareFunction::Less,
+                DepthMode::Equal => wgpu::CompareFunction::Equal,
+            },
+            stencil,
+            bias: wgpu::DepthBiasState::default(),
+        }
+    }
+}
+
+/// Stencil states for masked drawing, e.g. UI clipping or portals. The mask mesh is drawn
+/// with [`StencilMask::write`], usually without color writes, then the masked meshes with
+/// [`StencilMask::inside`]. Both take the reference from their
+/// [`StencilRef`](crate::render::StencilRef) and need a [`DepthFormat`](crate::render::DepthFormat)
+/// with stencil.
+///
+/// NOTE: the mask writes depth like any mesh, draw it behind what it masks
+pub struct StencilMask;
+
+impl StencilMask {
+    /// Sets the stencil to the reference wherever the mesh is drawn
+    pub fn write() -> wgpu::StencilState {
+        let face = wgpu::StencilFaceState {
+            compare: wgpu::CompareFunction::Always,
+            fail_op: wgpu::StencilOperation::Keep,
+            depth_fail_op: wgpu::StencilOperation::Keep,
+            pass_op: wgpu::StencilOperation::Replace,
+        };
+        wgpu::StencilState {
+            front: face,
+            back: face,
+            read_mask: 0xff,
+            write_mask: 0xff,
+        }
+    }
+
+    /// Only draws where the stencil equals the reference
+    pub fn inside() -> wgpu::StencilState {
+        let face = wgpu::StencilFaceState {
+            compare: wgpu::CompareFunction::Equal,
+            fail_op: wgpu::StencilOperation::Keep,
+            depth_fail_op: wgpu::StencilOperation::Keep,
+            pass_op: wgpu::StencilOperation::Keep,
+        };
+        wgpu::StencilState {
+            front: face,
+            back: face,
+            read_mask: 0xff,
+            write_mask: 0,
+        }
+    }
+}
+
+/// How a triangle list pipeline draws wireframes, see
+/// [`Wireframe`](crate::render::wireframe::Wireframe)
+pub enum WireframeVariant {
+    /// `PolygonMode::Line`, draws the mesh as it is
+    PolygonLine(wgpu::RenderPipeline),
+    /// Line list topology for devices without `Features::POLYGON_MODE_LINE`, draws the
+    /// [`WireframeEdges`](crate::render::wireframe::WireframeEdges) of the mesh
+    EdgeList(wgpu::RenderPipeline),
+}
+
+pub struct RenderPipeline {
+    pub pipeline: wgpu::RenderPipeline,
+    // (DepthOnly, Equal), only created for pipelines drawn with the depth prepass
+    prepass: Option<(wgpu::RenderPipeline, wgpu::RenderPipeline)>,
+    wireframe: Option<WireframeVariant>,
+    topology: wgpu::PrimitiveTopology,
+    strip_index_format: Option<wgpu::IndexFormat>,
+    bind_group_count: u32,
+}
 
 impl RenderPipeline {
+    /// Meshes drawn with this pipeline need the same topology
+    pub fn topology(&self) -> wgpu::PrimitiveTopology {
+        self.topology
+    }
+
+    /// Indexed strip meshes need the same index format
+    pub fn strip_index_format(&self) -> Option<wgpu::IndexFormat> {
+        self.strip_index_format
+    }
+
+    /// Groups `0..bind_group_count` of the layout need a bind group at draw time
+    pub fn bind_group_count(&self) -> u32 {
+        self.bind_group_count
+    }
+
+    /// `None` if the pipeline was created without the prepass variants
+    pub fn variant(&self, mode: DepthMode) -> Option<&wgpu::RenderPipeline> {
+        match (mode, &self.prepass) {
+            (DepthMode::Write, _) => Some(&self.pipeline),
+            (DepthMode::DepthOnly, Some((depth_only, _))) => Some(depth_only),
+            (DepthMode::Equal, Some((_, equal))) => Some(equal),
+            _ => None,
+        }
+    }
+
+    /// `None` for pipelines of other topologies than `TriangleList`
+    pub fn wireframe(&self) -> Option<&WireframeVariant> {
+        self.wireframe.as_ref()
+    }
+
+    /// `label` names the pipeline and its variants in validation errors
     pub fn create_usual(
         device: &wgpu::Device,
         bind_group_layouts: &[&wgpu::BindGroupLayout],
         shader: &shader::Shader,
         primitive_topology: wgpu::PrimitiveTopology,
+        label: Option<&str>,
+    ) -> Self {
+        Self::create_from(
+            device,
+            bind_group_layouts,
+            &shader.module,
+            &shader.targets,
+            primitive_topology,
+            None,
+            false,
+            label,
+        )
+    }
+
+    /// `strip_index_format` enables primitive restart for strip topologies,
+    /// take it from [`Mesh::strip_index_format`](crate::render::mesh::Mesh::strip_index_format)
+    pub fn create_strip(
+        device: &wgpu::Device,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        shader: &shader::Shader,
+        primitive_topology: wgpu::PrimitiveTopology,
+        strip_index_format: Option<wgpu::IndexFormat>,
+    ) -> Self {
+        Self::create_with(
+            device,
+            bind_group_layouts,
+            shader,
+            primitive_topology,
+            strip_index_format,
+            false,
+        )
+    }
+
+    /// With `depth_prepass` the [`DepthMode::DepthOnly`] and [`DepthMode::Equal`] variants
+    /// are created too, they run the same vertex stage so the depth values match exactly
+    pub fn create_with(
+        device: &wgpu::Device,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        shader: &shader::Shader,
+        primitive_topology: wgpu::PrimitiveTopology,
+        strip_index_format: Option<wgpu::IndexFormat>,
+        depth_prepass: bool,
+    ) -> Self {
+        Self::create_from(
+            device,
+            bind_group_layouts,
+            &shader.module,
+            &shader.targets,
+            primitive_topology,
+            strip_index_format,
+            depth_prepass,
+            None,
+        )
+    }
+
+    /// Layouts and color targets come from the [`ShaderReflection`](super::reflect::ShaderReflection)
+    /// of `shader`, every color output targets `surface_format`. The vertex buffers of its
+    /// targets are checked against the vertex inputs, a mismatch names the attributes.
+    /// The bind group layouts are returned for creating the bind groups.
+    pub fn create_reflected(
+        device: &wgpu::Device,
+        shader: &shader::Shader,
+        surface_format: &wgpu::TextureFormat,
+    ) -> Result<(Self, Vec<wgpu::BindGroupLayout>), ReflectionError> {
+        let reflection = shader
+            .reflection
+            .as_ref()
+            .ok_or(ReflectionError::NotReflected)?;
+        reflection.check_vertex_buffers(&shader.targets.vertex_buffers)?;
+        let targets = ShaderTargets {
+            fragment_targets: reflection.color_targets(*surface_format)?,
+            ..shader.targets.clone()
+        };
+
+        let bind_group_layouts = reflection.create_bind_group_layouts(device);
+        let pipeline = Self::create_from(
+            device,
+            &bind_group_layouts.iter().collect::<Vec<_>>(),
+            &shader.module,
+            &targets,
+            wgpu::PrimitiveTopology::TriangleList,
+            None,
+            false,
+            None,
+        );
+        Ok((pipeline, bind_group_layouts))
+    }
+
+    fn create_from(
+        device: &wgpu::Device,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        module: &wgpu::ShaderModule,
+        targets: &ShaderTargets,
+        primitive_topology: wgpu::PrimitiveTopology,
+        strip_index_format: Option<wgpu::IndexFormat>,
+        depth_prepass: bool,
+        label: Option<&str>,
     ) -> Self {
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render Pipeline Layout"),
+                label: Some(&debug_label("Render Pipeline Layout", label)),
                 bind_group_layouts,
                 push_constant_ranges: &[],
             });
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
+        let primitive = wgpu::PrimitiveState {
+            topology: primitive_topology,
+            strip_index_format,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            // Setting this to anything other than Fill requires
+            // Features::POLYGON_MODE_LINE or Features::POLYGON_MODE_POINT
+            polygon_mode: wgpu::PolygonMode::Fill,
+            // Requires Features::DEPTH_CLIP_CONTROL
+            unclipped_depth: false,
+            // Requires Features::CONSERVATIVE_RASTERIZATION
+            conservative: false,
+        };
+        let create = |mode, primitive, variant: &str| {
+            Self::create_variant(
+                device,
+                &render_pipeline_layout,
+                module,
+                targets,
+                primitive,
+                mode,
+                &debug_label(variant, label),
+            )
+        };
+
+        // NOTE: every edge is drawn, back faces are not culled
+        let wireframe = (primitive_topology == wgpu::PrimitiveTopology::TriangleList).then(|| {
+            let variant = "Render Pipeline (Wireframe)";
+            let primitive = wgpu::PrimitiveState {
+                cull_mode: None,
+                ..primitive
+            };
+            if device
+                .features()
+                .contains(wgpu::Features::POLYGON_MODE_LINE)
+            {
+                let primitive = wgpu::PrimitiveState {
+                    polygon_mode: wgpu::PolygonMode::Line,
+                    ..primitive
+                };
+                WireframeVariant::PolygonLine(create(DepthMode::Write, primitive, variant))
+            } else {
+                let primitive = wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::LineList,
+                    ..primitive
+                };
+                WireframeVariant::EdgeList(create(DepthMode::Write, primitive, variant))
+            }
+        });
+
+        Self {
+            pipeline: create(DepthMode::Write, primitive, "Render Pipeline"),
+            prepass: depth_prepass.then(|| {
+                (
+                    create(
+                        DepthMode::DepthOnly,
+                        primitive,
+                        "Render Pipeline (Depth Only)",
+                    ),
+                    create(DepthMode::Equal, primitive, "Render Pipeline (Depth Equal)"),
+                )
+            }),
+            wireframe,
+            topology: primitive_topology,
+            strip_index_format,
+            bind_group_count: bind_group_layouts.len() as u32,
+        }
+    }
+
+    fn create_variant(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        module: &wgpu::ShaderModule,
+        targets: &ShaderTargets,
+        primitive: wgpu::PrimitiveState,
+        mode: DepthMode,
+        label: &str,
+    ) -> wgpu::RenderPipeline {
+        let mut depth_stencil = mode.depth_stencil(targets.depth_format, targets.stencil.clone());
+        depth_stencil.depth_compare = if targets.depth_test {
+            targets
+                .depth_convention
+                .compare(depth_stencil.depth_compare)
+        } else {
+            wgpu::CompareFunction::Always
+        };
+        depth_stencil.depth_write_enabled &= targets.depth_write;
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(label),
+            layout: Some(layout),
             vertex: wgpu::VertexState {
-                module: &shader.module,
+                module,
                 entry_point: shader::Shader::VERTEX_ENTRY_POINT,
-                buffers: &shader.targets.vertex_buffers,
+                buffers: &targets.vertex_buffers,
             },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader.module,
+            fragment: (mode != DepthMode::DepthOnly).then(|| wgpu::FragmentState {
+                module,
                 entry_point: shader::Shader::FRAGMENT_ENTRY_POINT,
-                targets: &shader.targets.fragment_targets,
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: primitive_topology,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                // Setting this to anything other than Fill requires
-                // Features::NON_FILL_POLYGON_MODE
-                polygon_mode: wgpu::PolygonMode::Fill,
-                // Requires Features::DEPTH_CLIP_CONTROL
-                unclipped_depth: false,
-                // Requires Features::CONSERVATIVE_RASTERIZATION
-                conservative: false,
-            },
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: wgpu::TextureFormat::Depth32Float, // texture::Texture::DEPTH_FORMAT,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less, // 1.
-                stencil: wgpu::StencilState::default(),     // 2.
-                bias: wgpu::DepthBiasState::default(),
+                targets: &targets.fragment_targets,
             }),
+            primitive,
+            depth_stencil: Some(depth_stencil),
             multisample: wgpu::MultisampleState {
                 count: 1,
                 mask: !0,
                 alpha_to_coverage_enabled: false,
             },
             multiview: None,
+        })
+    }
+}
+
+pub struct ComputePipeline(pub wgpu::ComputePipeline);
+
+impl ComputePipeline {
+    pub fn create(
+        device: &wgpu::Device,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        shader_module: &wgpu::ShaderModule,
+        entry_point: &str,
+    ) -> Self {
+        let compute_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Compute Pipeline Layout"),
+                bind_group_layouts,
+                push_constant_ranges: &[],
+            });
+        let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Compute Pipeline"),
+            layout: Some(&compute_pipeline_layout),
+            module: shader_module,
+            entry_point,
         });
 
-        Self(render_pipeline)
+        Self(compute_pipeline)
+    }
+
+    pub fn from_shader(
+        device: &wgpu::Device,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        shader: &shader::ComputeShader,
+    ) -> Self {
+        Self::create(device, bind_group_layouts, &shader.module, shader.entry_point)
     }
 }
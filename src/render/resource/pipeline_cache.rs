@@ -0,0 +1,217 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+};
+
+use crate::render::DepthConvention;
+
+use super::shader::ShaderTargets;
+
+/// Everything a [`RenderPipeline`](super::pipeline::RenderPipeline) is created from,
+/// pipelines with equal keys are interchangeable
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PipelineKey {
+    // of the preprocessed source, the defs are part of it
+    shader: u64,
+    bind_group_layouts: Vec<Vec<wgpu::BindGroupLayoutEntry>>,
+    vertex_buffers: Vec<wgpu::VertexBufferLayout<'static>>,
+    fragment_targets: Vec<Option<wgpu::ColorTargetState>>,
+    depth_format: wgpu::TextureFormat,
+    stencil: wgpu::StencilState,
+    depth_test: bool,
+    depth_write: bool,
+    depth_convention: DepthConvention,
+    topology: wgpu::PrimitiveTopology,
+    strip_index_format: Option<wgpu::IndexFormat>,
+    depth_prepass: bool,
+}
+
+impl PipelineKey {
+    pub fn new(
+        wgsl: &str,
+        bind_group_layouts: &[&[wgpu::BindGroupLayoutEntry]],
+        targets: &ShaderTargets,
+        topology: wgpu::PrimitiveTopology,
+        strip_index_format: Option<wgpu::IndexFormat>,
+        depth_prepass: bool,
+    ) -> Self {
+        let mut hasher = DefaultHasher::new();
+        wgsl.hash(&mut hasher);
+        Self {
+            shader: hasher.finish(),
+            bind_group_layouts: bind_group_layouts
+                .iter()
+                .map(|entries| entries.to_vec())
+                .collect(),
+            vertex_buffers: targets.vertex_buffers.clone(),
+            fragment_targets: targets.fragment_targets.clone(),
+            depth_format: targets.depth_format,
+            stencil: targets.stencil.clone(),
+            depth_test: targets.depth_test,
+            depth_write: targets.depth_write,
+            depth_convention: targets.depth_convention,
+            topology,
+            strip_index_format,
+            depth_prepass,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PipelineCacheStats {
+    /// Requests served by an existing pipeline
+    pub hits: u32,
+    pub misses: u32,
+}
+
+/// Keys of `Store<RenderPipeline>` by [`PipelineKey`], so identical requests, e.g. from
+/// two material types with the same shader, share one pipeline instead of each
+/// creating its own. Pipelines are counted per user and removed by the last one.
+///
+/// NOTE: wgpu does not expose the pipeline cache data of the backend, only the pipelines
+/// of a run are shared. Persisting it under `cache/` is for once it does.
+#[derive(Default)]
+pub struct PipelineCache {
+    // store key and users
+    pipelines: HashMap<PipelineKey, (usize, u32)>,
+    keys: HashMap<usize, PipelineKey>,
+    pub stats: PipelineCacheStats,
+}
+
+impl PipelineCache {
+    /// The pipeline of `key`, `create` reserves or inserts it into the store on a miss
+    pub fn acquire(&mut self, key: PipelineKey, create: impl FnOnce() -> usize) -> usize {
+        if let Some((pipeline, users)) = self.pipelines.get_mut(&key) {
+            *users += 1;
+            self.stats.hits += 1;
+            return *pipeline;
+        }
+        self.stats.misses += 1;
+        let pipeline = create();
+        self.keys.insert(pipeline, key.clone());
+        self.pipelines.insert(key, (pipeline, 1));
+        pipeline
+    }
+
+    /// `true` if the caller was the last user and should remove the pipeline from the
+    /// store, also for pipelines that were not acquired here
+    pub fn release(&mut self, pipeline: usize) -> bool {
+        let key = match self.keys.get(&pipeline) {
+            Some(key) => key,
+            None => return true,
+        };
+        let users = match self.pipelines.get_mut(key) {
+            Some((_, users)) => users,
+            None => return true,
+        };
+        *users -= 1;
+        if *users > 0 {
+            return false;
+        }
+        if let Some(key) = self.keys.remove(&pipeline) {
+            self.pipelines.remove(&key);
+        }
+        true
+    }
+
+    /// Distinct pipelines
+    pub fn len(&self) -> usize {
+        self.pipelines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pipelines.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::render::resource::{
+        buffer::{MeshVertex, Vertex},
+        shader::ShaderTargets,
+    };
+
+    use super::{PipelineCache, PipelineCacheStats, PipelineKey};
+
+    const WGSL: &str = include_str!("../../../res/color_material.wgsl");
+
+    fn uniform(binding: u32) -> wgpu::BindGroupLayoutEntry {
+        wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }
+    }
+
+    /// What `material_system` requests for a color material
+    fn material_key(format: wgpu::TextureFormat) -> PipelineKey {
+        let targets = ShaderTargets {
+            vertex_buffers: vec![Vertex::layout()],
+            fragment_targets: vec![Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            ..Default::default()
+        };
+        PipelineKey::new(
+            WGSL,
+            &[&[uniform(0), uniform(1), uniform(2)]],
+            &targets,
+            wgpu::PrimitiveTopology::TriangleList,
+            None,
+            false,
+        )
+    }
+
+    #[test]
+    fn identical_materials_share_one_pipeline() {
+        let mut cache = PipelineCache::default();
+        let mut created = Vec::new();
+        let create = |created: &mut Vec<usize>| {
+            created.push(created.len());
+            created.len() - 1
+        };
+
+        let format = wgpu::TextureFormat::Rgba8UnormSrgb;
+        let first = cache.acquire(material_key(format), || create(&mut created));
+        let second = cache.acquire(material_key(format), || create(&mut created));
+        assert_eq!(first, second);
+        assert_eq!(created, vec![0]);
+
+        // any difference is a separate pipeline
+        let other = cache.acquire(material_key(wgpu::TextureFormat::Bgra8UnormSrgb), || {
+            create(&mut created)
+        });
+        assert_ne!(other, first);
+        let mut edited = material_key(format);
+        edited.shader += 1;
+        assert_ne!(edited, material_key(format));
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.stats, PipelineCacheStats { hits: 1, misses: 2 });
+    }
+
+    #[test]
+    fn last_user_removes_the_pipeline() {
+        let mut cache = PipelineCache::default();
+        let key = material_key(wgpu::TextureFormat::Rgba8UnormSrgb);
+        let pipeline = cache.acquire(key.clone(), || 7);
+        cache.acquire(key.clone(), || unreachable!());
+
+        assert!(!cache.release(pipeline));
+        assert!(cache.release(pipeline));
+        assert!(cache.is_empty());
+        // not cached, e.g. created before the cache
+        assert!(cache.release(3));
+
+        // created again after it was removed
+        assert_eq!(cache.acquire(key, || 8), 8);
+        assert_eq!(cache.stats.misses, 2);
+    }
+}
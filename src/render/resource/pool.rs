@@ -0,0 +1,280 @@
+use std::{collections::HashMap, ops::Deref, sync::Arc};
+
+use bevy_ecs::system::{Local, ResMut};
+
+/// Reuses buffers for data rebuilt at runtime instead of creating one per change.
+///
+/// A [`PooledBuffer`] goes back to the pool when dropped, but is only handed out again
+/// [`FRAMES_IN_FLIGHT`](BufferPool::FRAMES_IN_FLIGHT) frames after the last frame it was
+/// used in, the GPU may still be reading it until then. Sizes are rounded up to a power
+/// of two so buffers of similar sizes share a bucket.
+#[derive(Default)]
+pub struct BufferPool {
+    buckets: Pool<wgpu::Buffer>,
+}
+
+impl BufferPool {
+    pub const FRAMES_IN_FLIGHT: u64 = 2;
+    /// Free buffers unused for this many frames are destroyed
+    pub const MAX_IDLE_FRAMES: u64 = 120;
+    pub const MIN_BUCKET_SIZE: u64 = 256;
+
+    /// The buffer is at least `size` bytes, its contents are left from previous use
+    pub fn get(
+        &mut self,
+        device: &wgpu::Device,
+        size: u64,
+        usage: wgpu::BufferUsages,
+    ) -> PooledBuffer {
+        self.buckets.get(size, usage, |bucket_size| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Pooled Buffer"),
+                size: bucket_size,
+                usage,
+                mapped_at_creation: false,
+            })
+        })
+    }
+
+    /// Replaces `create_buffer_init`, `COPY_DST` is added to `usage` for the write
+    pub fn get_init(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        contents: &[u8],
+        usage: wgpu::BufferUsages,
+    ) -> PooledBuffer {
+        let buffer = self.get(
+            device,
+            contents.len() as u64,
+            usage | wgpu::BufferUsages::COPY_DST,
+        );
+        let align = wgpu::COPY_BUFFER_ALIGNMENT as usize;
+        if contents.len() % align == 0 {
+            queue.write_buffer(&buffer, 0, contents);
+        } else {
+            let mut padded = contents.to_vec();
+            padded.resize((contents.len() + align - 1) / align * align, 0);
+            queue.write_buffer(&buffer, 0, &padded);
+        }
+        buffer
+    }
+
+    /// Called once per frame after submitting, `frame_index` is the frame to be recorded next
+    pub fn recycle(&mut self, frame_index: u64) {
+        self.buckets.recycle(frame_index);
+    }
+
+    pub fn stats(&self) -> BufferPoolStats {
+        self.buckets.stats()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BufferPoolStats {
+    /// Requests served from a free buffer
+    pub hits: u64,
+    /// Requests that created a buffer
+    pub misses: u64,
+    /// Size of the buffers currently held outside the pool
+    pub bytes_outstanding: u64,
+    /// Size of all the buffers the pool keeps alive
+    pub bytes_allocated: u64,
+}
+
+/// Dereferences to the buffer, the buffer may be larger than requested
+pub struct PooledBuffer<T = wgpu::Buffer> {
+    buffer: Arc<T>,
+    size: u64,
+}
+
+impl<T> PooledBuffer<T> {
+    /// The bucket size, not the requested size
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+impl<T> Deref for PooledBuffer<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.buffer
+    }
+}
+
+struct Entry<T> {
+    // NOTE: held elsewhere while the strong count is above 1
+    buffer: Arc<T>,
+    last_used: u64,
+    // held at some point since the last recycle
+    held: bool,
+}
+
+impl<T> Entry<T> {
+    fn is_held(&self) -> bool {
+        Arc::strong_count(&self.buffer) > 1
+    }
+}
+
+/// Bookkeeping of `BufferPool`, generic so it can be tested without a device
+struct Pool<T> {
+    frame: u64,
+    buckets: HashMap<(u64, wgpu::BufferUsages), Vec<Entry<T>>>,
+    hits: u64,
+    misses: u64,
+}
+
+impl<T> Default for Pool<T> {
+    fn default() -> Self {
+        Self {
+            frame: 0,
+            buckets: HashMap::new(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+}
+
+impl<T> Pool<T> {
+    fn bucket_size(size: u64) -> u64 {
+        size.max(BufferPool::MIN_BUCKET_SIZE).next_power_of_two()
+    }
+
+    fn get(
+        &mut self,
+        size: u64,
+        usage: wgpu::BufferUsages,
+        create: impl FnOnce(u64) -> T,
+    ) -> PooledBuffer<T> {
+        let frame = self.frame;
+        let bucket_size = Self::bucket_size(size);
+        let entries = self.buckets.entry((bucket_size, usage)).or_default();
+
+        let free = entries.iter().position(|entry| {
+            !entry.is_held() && entry.last_used + BufferPool::FRAMES_IN_FLIGHT <= frame
+        });
+        let index = match free {
+            Some(index) => {
+                self.hits += 1;
+                index
+            }
+            None => {
+                self.misses += 1;
+                entries.push(Entry {
+                    buffer: Arc::new(create(bucket_size)),
+                    last_used: frame,
+                    held: true,
+                });
+                entries.len() - 1
+            }
+        };
+        let entry = &mut entries[index];
+        entry.last_used = frame;
+        entry.held = true;
+
+        PooledBuffer {
+            buffer: entry.buffer.clone(),
+            size: bucket_size,
+        }
+    }
+
+    fn recycle(&mut self, frame_index: u64) {
+        let used = self.frame;
+        for entries in self.buckets.values_mut() {
+            entries.retain_mut(|entry| {
+                // dropped during the frame, may still be used by its commands
+                if entry.held {
+                    entry.last_used = used;
+                }
+                entry.held = entry.is_held();
+                entry.held || entry.last_used + BufferPool::MAX_IDLE_FRAMES > frame_index
+            });
+        }
+        self.buckets.retain(|_, entries| !entries.is_empty());
+        self.frame = frame_index;
+    }
+
+    fn stats(&self) -> BufferPoolStats {
+        let mut stats = BufferPoolStats {
+            hits: self.hits,
+            misses: self.misses,
+            ..Default::default()
+        };
+        for (&(bucket_size, _), entries) in &self.buckets {
+            for entry in entries {
+                stats.bytes_allocated += bucket_size;
+                if entry.is_held() {
+                    stats.bytes_outstanding += bucket_size;
+                }
+            }
+        }
+        stats
+    }
+}
+
+/// Runs after the frame is submitted
+pub fn recycle_buffer_pool_system(mut frame_index: Local<u64>, mut pool: ResMut<BufferPool>) {
+    *frame_index += 1;
+    pool.recycle(*frame_index);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BufferPoolStats, Pool};
+
+    const VERTEX: wgpu::BufferUsages = wgpu::BufferUsages::VERTEX;
+
+    #[test]
+    fn in_flight_buffers_are_not_reused() {
+        let mut pool: Pool<u32> = Pool::default();
+        let mut created = 0;
+        let mut create = |_: u64| {
+            created += 1;
+            created
+        };
+
+        // frame 0: 300 and 400 bytes share the 512 bucket
+        let a = pool.get(300, VERTEX, &mut create);
+        let b = pool.get(400, VERTEX, &mut create);
+        assert_eq!((*a, *b, a.size()), (1, 2, 512));
+        drop(a);
+        // dropped, but used this frame
+        let c = pool.get(500, VERTEX, &mut create);
+        assert_eq!(*c, 3);
+        pool.recycle(1);
+
+        // frame 1: `a` was used in frame 0, still in flight
+        let d = pool.get(100, VERTEX, &mut create);
+        assert_eq!(*d, 4);
+        assert_eq!(d.size(), 256);
+        let e = pool.get(512, VERTEX, &mut create);
+        assert_eq!(*e, 5);
+        drop((b, c));
+        pool.recycle(2);
+
+        // frame 2: `a` is free, `b` and `c` were dropped during frame 1
+        let f = pool.get(512, VERTEX, &mut create);
+        assert_eq!(*f, 1);
+        let g = pool.get(512, VERTEX, &mut create);
+        assert_eq!(*g, 6);
+        // other usages do not share buckets
+        let h = pool.get(512, wgpu::BufferUsages::INDEX, &mut create);
+        assert_eq!(*h, 7);
+
+        assert_eq!(
+            pool.stats(),
+            BufferPoolStats {
+                hits: 1,
+                misses: 7,
+                bytes_outstanding: 256 + 512 * 4,
+                bytes_allocated: 256 + 512 * 6,
+            }
+        );
+        drop((d, e, f, g, h));
+        pool.recycle(3);
+        pool.recycle(4);
+        assert_eq!(*pool.get(300, VERTEX, &mut create), 1);
+        assert_eq!(pool.stats().bytes_outstanding, 0);
+    }
+}
@@ -0,0 +1,282 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    hash::{Hash, Hasher},
+};
+
+const INCLUDE: &str = "//!include";
+const IF: &str = "//!if";
+const ELSE: &str = "//!else";
+const ENDIF: &str = "//!endif";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShaderDefs(pub HashSet<String>);
+
+impl ShaderDefs {
+    pub fn new<S: Into<String>>(defs: impl IntoIterator<Item = S>) -> Self {
+        Self(defs.into_iter().map(Into::into).collect())
+    }
+
+    pub fn contains(&self, def: &str) -> bool {
+        self.0.contains(def)
+    }
+
+    /// Independent of insertion order, variants of the same source differ only by this
+    pub fn cache_key(&self) -> u64 {
+        let mut defs: Vec<&String> = self.0.iter().collect();
+        defs.sort();
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        defs.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ShaderPreprocessError {
+    /// The chain starts at the root source and ends with the repeated include
+    IncludeCycle(Vec<String>),
+    MissingInclude { path: String, from: String },
+    MalformedInclude { line: usize },
+    UnexpectedDirective { directive: &'static str, line: usize },
+    UnclosedIf { line: usize },
+}
+
+impl fmt::Display for ShaderPreprocessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShaderPreprocessError::IncludeCycle(chain) => {
+                write!(f, "include cycle: {}", chain.join(" -> "))
+            }
+            ShaderPreprocessError::MissingInclude { path, from } => {
+                write!(f, "{} included from {} could not be found", path, from)
+            }
+            ShaderPreprocessError::MalformedInclude { line } => {
+                write!(f, "malformed include at line {}, expected {} \"path\"", line, INCLUDE)
+            }
+            ShaderPreprocessError::UnexpectedDirective { directive, line } => {
+                write!(f, "{} without {} at line {}", directive, IF, line)
+            }
+            ShaderPreprocessError::UnclosedIf { line } => {
+                write!(f, "{} at line {} is never closed", IF, line)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ShaderPreprocessError {}
+
+fn parse_include(line: &str, line_number: usize) -> Option<Result<String, ShaderPreprocessError>> {
+    let rest = line.trim().strip_prefix(INCLUDE)?;
+    let path = rest
+        .trim()
+        .strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+        .filter(|path| !path.is_empty())
+        .map(str::to_string)
+        .ok_or(ShaderPreprocessError::MalformedInclude { line: line_number });
+    Some(path)
+}
+
+/// Paths included directly by `source`, malformed includes are skipped
+/// and reported by [`resolve_includes`]
+pub fn include_paths(source: &str) -> Vec<String> {
+    source
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| parse_include(line, i + 1)?.ok())
+        .collect()
+}
+
+/// Replaces every include with the contents of `files[path]`, recursively.
+/// Each file is included at most once.
+pub fn resolve_includes(
+    root: &str,
+    source: &str,
+    files: &HashMap<String, String>,
+) -> Result<String, ShaderPreprocessError> {
+    fn expand(
+        source: &str,
+        files: &HashMap<String, String>,
+        chain: &mut Vec<String>,
+        included: &mut HashSet<String>,
+        out: &mut String,
+    ) -> Result<(), ShaderPreprocessError> {
+        for (i, line) in source.lines().enumerate() {
+            let path = match parse_include(line, i + 1) {
+                None => {
+                    out.push_str(line);
+                    out.push('\n');
+                    continue;
+                }
+                Some(path) => path?,
+            };
+
+            if chain.contains(&path) {
+                let mut cycle = chain.clone();
+                cycle.push(path);
+                return Err(ShaderPreprocessError::IncludeCycle(cycle));
+            }
+            if !included.insert(path.clone()) {
+                continue;
+            }
+            let included_source = files.get(&path).ok_or_else(|| {
+                ShaderPreprocessError::MissingInclude {
+                    path: path.clone(),
+                    from: chain.last().cloned().unwrap_or_default(),
+                }
+            })?;
+
+            chain.push(path);
+            expand(included_source, files, chain, included, out)?;
+            chain.pop();
+        }
+        Ok(())
+    }
+
+    let mut out = String::with_capacity(source.len());
+    expand(
+        source,
+        files,
+        &mut vec![root.to_string()],
+        &mut HashSet::new(),
+        &mut out,
+    )?;
+    Ok(out)
+}
+
+/// Keeps the lines of `//!if FLAG` / `//!if !FLAG` blocks whose condition holds.
+/// Directive and removed lines are left empty so line numbers in errors still match.
+pub fn apply_defs(source: &str, defs: &ShaderDefs) -> Result<String, ShaderPreprocessError> {
+    // (line of the if, condition of this block, active including parents)
+    let mut blocks: Vec<(usize, bool, bool)> = Vec::new();
+    let mut out = String::with_capacity(source.len());
+
+    for (i, line) in source.lines().enumerate() {
+        let line_number = i + 1;
+        let active = blocks.last().map_or(true, |(_, _, active)| *active);
+        let trimmed = line.trim();
+
+        if let Some(flag) = trimmed.strip_prefix(IF).filter(|rest| rest.starts_with(' ')) {
+            let flag = flag.trim();
+            let condition = match flag.strip_prefix('!') {
+                Some(flag) => !defs.contains(flag.trim()),
+                None => defs.contains(flag),
+            };
+            blocks.push((line_number, condition, active && condition));
+        } else if trimmed == ELSE {
+            let (_, condition, _) = blocks.pop().ok_or(
+                ShaderPreprocessError::UnexpectedDirective {
+                    directive: ELSE,
+                    line: line_number,
+                },
+            )?;
+            let parent_active = blocks.last().map_or(true, |(_, _, active)| *active);
+            blocks.push((line_number, !condition, parent_active && !condition));
+        } else if trimmed == ENDIF {
+            blocks
+                .pop()
+                .ok_or(ShaderPreprocessError::UnexpectedDirective {
+                    directive: ENDIF,
+                    line: line_number,
+                })?;
+        } else if active {
+            out.push_str(line);
+        }
+        out.push('\n');
+    }
+
+    match blocks.last() {
+        Some((line, _, _)) => Err(ShaderPreprocessError::UnclosedIf { line: *line }),
+        None => Ok(out),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn files(entries: &[(&str, &str)]) -> HashMap<String, String> {
+        entries
+            .iter()
+            .map(|(path, source)| (path.to_string(), source.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn includes_expand_once() {
+        let files = files(&[
+            ("common/camera.wgsl", "struct Camera {}"),
+            ("common/light.wgsl", "//!include \"common/camera.wgsl\"\nstruct Light {}"),
+        ]);
+        let source = "//!include \"common/light.wgsl\"\n//!include \"common/camera.wgsl\"\nfn main() {}";
+
+        assert_eq!(
+            include_paths(source),
+            vec!["common/light.wgsl", "common/camera.wgsl"]
+        );
+        assert_eq!(
+            resolve_includes("main.wgsl", source, &files).unwrap(),
+            "struct Camera {}\nstruct Light {}\nfn main() {}\n"
+        );
+    }
+
+    #[test]
+    fn include_cycle_reports_chain() {
+        let files = files(&[
+            ("a.wgsl", "//!include \"b.wgsl\""),
+            ("b.wgsl", "//!include \"a.wgsl\""),
+        ]);
+
+        assert_eq!(
+            resolve_includes("main.wgsl", "//!include \"a.wgsl\"", &files),
+            Err(ShaderPreprocessError::IncludeCycle(vec![
+                "main.wgsl".to_string(),
+                "a.wgsl".to_string(),
+                "b.wgsl".to_string(),
+                "a.wgsl".to_string(),
+            ]))
+        );
+    }
+
+    #[test]
+    fn defs_select_blocks() {
+        let source = "a\n//!if HAS_NORMAL_MAP\nb\n//!if !FLAT\nc\n//!endif\n//!else\nd\n//!endif\ne";
+
+        let with = apply_defs(source, &ShaderDefs::new(["HAS_NORMAL_MAP"])).unwrap();
+        let without = apply_defs(source, &ShaderDefs::default()).unwrap();
+        let lines = |s: &str| s.lines().filter(|l| !l.is_empty()).collect::<Vec<_>>().join("");
+
+        assert_eq!(lines(&with), "abce");
+        assert_eq!(lines(&without), "ade");
+        assert_eq!(with.lines().count(), source.lines().count());
+    }
+
+    #[test]
+    fn unbalanced_directives_error() {
+        assert_eq!(
+            apply_defs("//!if A\nx", &ShaderDefs::default()),
+            Err(ShaderPreprocessError::UnclosedIf { line: 1 })
+        );
+        assert_eq!(
+            apply_defs("x\n//!endif", &ShaderDefs::default()),
+            Err(ShaderPreprocessError::UnexpectedDirective {
+                directive: ENDIF,
+                line: 2
+            })
+        );
+    }
+
+    #[test]
+    fn cache_key_ignores_order() {
+        assert_eq!(
+            ShaderDefs::new(["A", "B"]).cache_key(),
+            ShaderDefs::new(["B", "A"]).cache_key()
+        );
+        assert_ne!(
+            ShaderDefs::new(["A"]).cache_key(),
+            ShaderDefs::default().cache_key()
+        );
+    }
+}
@@ -0,0 +1,637 @@
+use std::{collections::BTreeMap, fmt};
+
+use naga::{
+    valid::{Capabilities, GlobalUse, ValidationFlags, Validator},
+    AddressSpace, Binding, ImageClass, ImageDimension, ScalarKind, ShaderStage, StorageAccess,
+    StorageFormat, TypeInner, VectorSize,
+};
+
+use super::shader::Shader;
+
+/// What a WGSL source expects from the pipeline, parsed with naga when the shader is compiled.
+/// Only the [`Shader::VERTEX_ENTRY_POINT`] and [`Shader::FRAGMENT_ENTRY_POINT`] interfaces
+/// are reflected, bindings are visible to every entry point using them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShaderReflection {
+    /// Sorted by location
+    pub vertex_inputs: Vec<VertexInput>,
+    /// Sorted by location
+    pub color_outputs: Vec<ColorOutput>,
+    /// Indexed by group, sorted by binding. Groups the shader skips are empty.
+    pub bind_groups: Vec<Vec<wgpu::BindGroupLayoutEntry>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VertexInput {
+    pub location: u32,
+    /// The argument or struct member in the WGSL
+    pub name: String,
+    pub format: wgpu::VertexFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColorOutput {
+    pub location: u32,
+    pub kind: ScalarKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReflectionError {
+    /// Parsing or validation failed, with the message naga reports
+    Invalid(String),
+    UnsupportedVertexInput {
+        location: u32,
+        name: String,
+    },
+    UnsupportedBinding {
+        group: u32,
+        binding: u32,
+        name: String,
+    },
+    /// Every mismatched location, see [`ShaderReflection::check_vertex_buffers`]
+    VertexMismatch(Vec<VertexMismatch>),
+    /// Only float outputs can be drawn to the surface
+    NonFloatColorOutput {
+        location: u32,
+    },
+    /// The shader was not compiled from a [`ShaderSource`](super::shader::ShaderSource)
+    NotReflected,
+    /// The pipeline layout lacks a binding of an include, see
+    /// [`ShaderInclude::check_bind_groups`](super::shader_lib::ShaderInclude::check_bind_groups)
+    MissingBinding {
+        group: u32,
+        binding: u32,
+        include: &'static str,
+    },
+}
+
+impl fmt::Display for ReflectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReflectionError::Invalid(message) => write!(f, "invalid shader: {}", message),
+            ReflectionError::UnsupportedVertexInput { location, name } => write!(
+                f,
+                "vertex input `{}` at location {} is not a 32 bit scalar or vector",
+                name, location
+            ),
+            ReflectionError::UnsupportedBinding {
+                group,
+                binding,
+                name,
+            } => write!(
+                f,
+                "`{}` at group {} binding {} can not be reflected",
+                name, group, binding
+            ),
+            ReflectionError::VertexMismatch(mismatches) => {
+                write!(f, "the mesh does not match the shader")?;
+                for mismatch in mismatches {
+                    write!(f, "\n  {}", mismatch)?;
+                }
+                Ok(())
+            }
+            ReflectionError::NonFloatColorOutput { location } => write!(
+                f,
+                "color output at location {} is not float, it can not target the surface",
+                location
+            ),
+            ReflectionError::NotReflected => write!(f, "the shader has no reflection"),
+            ReflectionError::MissingBinding {
+                group,
+                binding,
+                include,
+            } => write!(
+                f,
+                "{} expects a binding at group {} binding {}, the pipeline layout has none",
+                include, group, binding
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ReflectionError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VertexMismatch {
+    pub location: u32,
+    pub name: String,
+    pub expected: wgpu::VertexFormat,
+    /// `None` if no vertex buffer has the location
+    pub found: Option<wgpu::VertexFormat>,
+}
+
+impl fmt::Display for VertexMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.found {
+            Some(found) => write!(
+                f,
+                "vertex input `{}` at location {} expects {:?}, the vertex buffers provide {:?}",
+                self.name, self.location, self.expected, found
+            ),
+            None => write!(
+                f,
+                "vertex input `{}` at location {} ({:?}) is missing from the vertex buffers",
+                self.name, self.location, self.expected
+            ),
+        }
+    }
+}
+
+impl ShaderReflection {
+    /// `source` is already preprocessed
+    pub fn from_wgsl(source: &str) -> Result<Self, ReflectionError> {
+        let module = naga::front::wgsl::parse_str(source)
+            .map_err(|error| ReflectionError::Invalid(error.emit_to_string(source)))?;
+        let info = Validator::new(ValidationFlags::all(), Capabilities::all())
+            .validate(&module)
+            .map_err(|error| ReflectionError::Invalid(error.into_inner().to_string()))?;
+
+        let entry_point = |stage, name| {
+            module
+                .entry_points
+                .iter()
+                .position(|entry_point| entry_point.stage == stage && entry_point.name == name)
+        };
+        let vertex = entry_point(ShaderStage::Vertex, Shader::VERTEX_ENTRY_POINT);
+        let fragment = entry_point(ShaderStage::Fragment, Shader::FRAGMENT_ENTRY_POINT);
+
+        let mut vertex_inputs = Vec::new();
+        if let Some(vertex) = vertex {
+            for argument in &module.entry_points[vertex].function.arguments {
+                for (location, name, ty) in
+                    locations(&module, &argument.name, argument.ty, &argument.binding)
+                {
+                    let format = vertex_format(&module.types[ty].inner).ok_or_else(|| {
+                        ReflectionError::UnsupportedVertexInput {
+                            location,
+                            name: name.clone(),
+                        }
+                    })?;
+                    vertex_inputs.push(VertexInput {
+                        location,
+                        name,
+                        format,
+                    });
+                }
+            }
+        }
+        vertex_inputs.sort_by_key(|input| input.location);
+
+        let mut color_outputs = Vec::new();
+        if let Some(result) = fragment.and_then(|i| module.entry_points[i].function.result.as_ref())
+        {
+            for (location, _, ty) in locations(&module, &None, result.ty, &result.binding) {
+                let kind = match module.types[ty].inner {
+                    TypeInner::Scalar { kind, .. } | TypeInner::Vector { kind, .. } => kind,
+                    _ => ScalarKind::Float,
+                };
+                color_outputs.push(ColorOutput { location, kind });
+            }
+        }
+        color_outputs.sort_by_key(|output| output.location);
+
+        let mut groups: BTreeMap<u32, Vec<wgpu::BindGroupLayoutEntry>> = BTreeMap::new();
+        for (handle, global) in module.global_variables.iter() {
+            let binding = match &global.binding {
+                Some(binding) => binding,
+                None => continue,
+            };
+            let mut visibility = wgpu::ShaderStages::NONE;
+            for (i, entry_point) in module.entry_points.iter().enumerate() {
+                if info.get_entry_point(i)[handle] != GlobalUse::empty() {
+                    visibility |= match entry_point.stage {
+                        ShaderStage::Vertex => wgpu::ShaderStages::VERTEX,
+                        ShaderStage::Fragment => wgpu::ShaderStages::FRAGMENT,
+                        ShaderStage::Compute => wgpu::ShaderStages::COMPUTE,
+                    };
+                }
+            }
+            // declared but never used, the layout still needs it
+            if visibility == wgpu::ShaderStages::NONE {
+                visibility = wgpu::ShaderStages::VERTEX_FRAGMENT;
+            }
+            let ty =
+                binding_type(global.space, &module.types[global.ty].inner).ok_or_else(|| {
+                    ReflectionError::UnsupportedBinding {
+                        group: binding.group,
+                        binding: binding.binding,
+                        name: global.name.clone().unwrap_or_default(),
+                    }
+                })?;
+            groups
+                .entry(binding.group)
+                .or_default()
+                .push(wgpu::BindGroupLayoutEntry {
+                    binding: binding.binding,
+                    visibility,
+                    ty,
+                    count: None,
+                });
+        }
+        let group_count = groups.keys().next_back().map_or(0, |group| group + 1);
+        let bind_groups = (0..group_count)
+            .map(|group| {
+                let mut entries = groups.remove(&group).unwrap_or_default();
+                entries.sort_by_key(|entry| entry.binding);
+                entries
+            })
+            .collect();
+
+        Ok(Self {
+            vertex_inputs,
+            color_outputs,
+            bind_groups,
+        })
+    }
+
+    /// Every vertex input needs an attribute at its location with the same scalar kind and
+    /// component count, e.g. the [`MeshVertex::ATTRIBUTES`](super::buffer::MeshVertex) of the
+    /// mesh and instance types. Attributes the shader does not read are allowed.
+    pub fn check_vertex_buffers(
+        &self,
+        vertex_buffers: &[wgpu::VertexBufferLayout],
+    ) -> Result<(), ReflectionError> {
+        let mismatches: Vec<VertexMismatch> = self
+            .vertex_inputs
+            .iter()
+            .filter_map(|input| {
+                let found = vertex_buffers
+                    .iter()
+                    .flat_map(|buffer| buffer.attributes)
+                    .find(|attribute| attribute.shader_location == input.location)
+                    .map(|attribute| attribute.format);
+                match found {
+                    Some(found) if shape(found) == shape(input.format) => None,
+                    _ => Some(VertexMismatch {
+                        location: input.location,
+                        name: input.name.clone(),
+                        expected: input.format,
+                        found,
+                    }),
+                }
+            })
+            .collect();
+        match mismatches.is_empty() {
+            true => Ok(()),
+            false => Err(ReflectionError::VertexMismatch(mismatches)),
+        }
+    }
+
+    /// One target per color output in `format` with blending replaced, locations the shader
+    /// skips are `None`
+    pub fn color_targets(
+        &self,
+        format: wgpu::TextureFormat,
+    ) -> Result<Vec<Option<wgpu::ColorTargetState>>, ReflectionError> {
+        let count = self
+            .color_outputs
+            .last()
+            .map_or(0, |output| output.location as usize + 1);
+        let mut targets = vec![None; count];
+        for output in &self.color_outputs {
+            if output.kind != ScalarKind::Float {
+                return Err(ReflectionError::NonFloatColorOutput {
+                    location: output.location,
+                });
+            }
+            targets[output.location as usize] = Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            });
+        }
+        Ok(targets)
+    }
+
+    pub fn create_bind_group_layouts(&self, device: &wgpu::Device) -> Vec<wgpu::BindGroupLayout> {
+        self.bind_groups
+            .iter()
+            .map(|entries| {
+                device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    label: Some("Reflected Bind Group Layout"),
+                    entries,
+                })
+            })
+            .collect()
+    }
+}
+
+/// The located values of an argument or result, struct members are flattened
+fn locations(
+    module: &naga::Module,
+    name: &Option<String>,
+    ty: naga::Handle<naga::Type>,
+    binding: &Option<Binding>,
+) -> Vec<(u32, String, naga::Handle<naga::Type>)> {
+    match (binding, &module.types[ty].inner) {
+        (Some(Binding::Location { location, .. }), _) => {
+            vec![(*location, name.clone().unwrap_or_default(), ty)]
+        }
+        (None, TypeInner::Struct { members, .. }) => members
+            .iter()
+            .filter_map(|member| match member.binding {
+                Some(Binding::Location { location, .. }) => {
+                    Some((location, member.name.clone().unwrap_or_default(), member.ty))
+                }
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn vertex_format(inner: &TypeInner) -> Option<wgpu::VertexFormat> {
+    use wgpu::VertexFormat as F;
+
+    let (kind, size) = match *inner {
+        TypeInner::Scalar { kind, width: 4 } => (kind, None),
+        TypeInner::Vector {
+            size,
+            kind,
+            width: 4,
+        } => (kind, Some(size)),
+        _ => return None,
+    };
+    Some(match (kind, size) {
+        (ScalarKind::Float, None) => F::Float32,
+        (ScalarKind::Float, Some(VectorSize::Bi)) => F::Float32x2,
+        (ScalarKind::Float, Some(VectorSize::Tri)) => F::Float32x3,
+        (ScalarKind::Float, Some(VectorSize::Quad)) => F::Float32x4,
+        (ScalarKind::Uint, None) => F::Uint32,
+        (ScalarKind::Uint, Some(VectorSize::Bi)) => F::Uint32x2,
+        (ScalarKind::Uint, Some(VectorSize::Tri)) => F::Uint32x3,
+        (ScalarKind::Uint, Some(VectorSize::Quad)) => F::Uint32x4,
+        (ScalarKind::Sint, None) => F::Sint32,
+        (ScalarKind::Sint, Some(VectorSize::Bi)) => F::Sint32x2,
+        (ScalarKind::Sint, Some(VectorSize::Tri)) => F::Sint32x3,
+        (ScalarKind::Sint, Some(VectorSize::Quad)) => F::Sint32x4,
+        (ScalarKind::Bool, _) => return None,
+    })
+}
+
+/// Scalar kind and component count the shader sees, normalized formats read as float
+fn shape(format: wgpu::VertexFormat) -> (ScalarKind, u32) {
+    use wgpu::VertexFormat as F;
+
+    match format {
+        F::Uint32 => (ScalarKind::Uint, 1),
+        F::Uint8x2 | F::Uint16x2 | F::Uint32x2 => (ScalarKind::Uint, 2),
+        F::Uint32x3 => (ScalarKind::Uint, 3),
+        F::Uint8x4 | F::Uint16x4 | F::Uint32x4 => (ScalarKind::Uint, 4),
+        F::Sint32 => (ScalarKind::Sint, 1),
+        F::Sint8x2 | F::Sint16x2 | F::Sint32x2 => (ScalarKind::Sint, 2),
+        F::Sint32x3 => (ScalarKind::Sint, 3),
+        F::Sint8x4 | F::Sint16x4 | F::Sint32x4 => (ScalarKind::Sint, 4),
+        F::Float32 | F::Float64 => (ScalarKind::Float, 1),
+        F::Unorm8x2
+        | F::Snorm8x2
+        | F::Unorm16x2
+        | F::Snorm16x2
+        | F::Float16x2
+        | F::Float32x2
+        | F::Float64x2 => (ScalarKind::Float, 2),
+        F::Float32x3 | F::Float64x3 => (ScalarKind::Float, 3),
+        F::Unorm8x4
+        | F::Snorm8x4
+        | F::Unorm16x4
+        | F::Snorm16x4
+        | F::Float16x4
+        | F::Float32x4
+        | F::Float64x4 => (ScalarKind::Float, 4),
+    }
+}
+
+fn binding_type(space: AddressSpace, inner: &TypeInner) -> Option<wgpu::BindingType> {
+    Some(match (space, inner) {
+        (AddressSpace::Uniform, _) => wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        (AddressSpace::Storage { access }, _) => wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage {
+                read_only: !access.contains(StorageAccess::STORE),
+            },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        (AddressSpace::Handle, TypeInner::Sampler { comparison }) => {
+            wgpu::BindingType::Sampler(match comparison {
+                true => wgpu::SamplerBindingType::Comparison,
+                false => wgpu::SamplerBindingType::Filtering,
+            })
+        }
+        (
+            AddressSpace::Handle,
+            TypeInner::Image {
+                dim,
+                arrayed,
+                class,
+            },
+        ) => {
+            let view_dimension = match (dim, arrayed) {
+                (ImageDimension::D1, _) => wgpu::TextureViewDimension::D1,
+                (ImageDimension::D2, false) => wgpu::TextureViewDimension::D2,
+                (ImageDimension::D2, true) => wgpu::TextureViewDimension::D2Array,
+                (ImageDimension::D3, _) => wgpu::TextureViewDimension::D3,
+                (ImageDimension::Cube, false) => wgpu::TextureViewDimension::Cube,
+                (ImageDimension::Cube, true) => wgpu::TextureViewDimension::CubeArray,
+            };
+            match *class {
+                // NOTE: float textures are assumed filterable, e.g. not R32Float
+                ImageClass::Sampled { kind, multi } => wgpu::BindingType::Texture {
+                    sample_type: match kind {
+                        ScalarKind::Float => wgpu::TextureSampleType::Float { filterable: true },
+                        ScalarKind::Sint => wgpu::TextureSampleType::Sint,
+                        ScalarKind::Uint => wgpu::TextureSampleType::Uint,
+                        ScalarKind::Bool => return None,
+                    },
+                    view_dimension,
+                    multisampled: multi,
+                },
+                ImageClass::Depth { multi } => wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Depth,
+                    view_dimension,
+                    multisampled: multi,
+                },
+                ImageClass::Storage { format, access } => wgpu::BindingType::StorageTexture {
+                    access: match (
+                        access.contains(StorageAccess::LOAD),
+                        access.contains(StorageAccess::STORE),
+                    ) {
+                        (true, true) => wgpu::StorageTextureAccess::ReadWrite,
+                        (true, false) => wgpu::StorageTextureAccess::ReadOnly,
+                        _ => wgpu::StorageTextureAccess::WriteOnly,
+                    },
+                    format: storage_format(format),
+                    view_dimension,
+                },
+            }
+        }
+        _ => return None,
+    })
+}
+
+fn storage_format(format: StorageFormat) -> wgpu::TextureFormat {
+    use wgpu::TextureFormat as T;
+
+    match format {
+        StorageFormat::R8Unorm => T::R8Unorm,
+        StorageFormat::R8Snorm => T::R8Snorm,
+        StorageFormat::R8Uint => T::R8Uint,
+        StorageFormat::R8Sint => T::R8Sint,
+        StorageFormat::R16Uint => T::R16Uint,
+        StorageFormat::R16Sint => T::R16Sint,
+        StorageFormat::R16Float => T::R16Float,
+        StorageFormat::Rg8Unorm => T::Rg8Unorm,
+        StorageFormat::Rg8Snorm => T::Rg8Snorm,
+        StorageFormat::Rg8Uint => T::Rg8Uint,
+        StorageFormat::Rg8Sint => T::Rg8Sint,
+        StorageFormat::R32Uint => T::R32Uint,
+        StorageFormat::R32Sint => T::R32Sint,
+        StorageFormat::R32Float => T::R32Float,
+        StorageFormat::Rg16Uint => T::Rg16Uint,
+        StorageFormat::Rg16Sint => T::Rg16Sint,
+        StorageFormat::Rg16Float => T::Rg16Float,
+        StorageFormat::Rgba8Unorm => T::Rgba8Unorm,
+        StorageFormat::Rgba8Snorm => T::Rgba8Snorm,
+        StorageFormat::Rgba8Uint => T::Rgba8Uint,
+        StorageFormat::Rgba8Sint => T::Rgba8Sint,
+        StorageFormat::Rgb10a2Unorm => T::Rgb10a2Unorm,
+        StorageFormat::Rg11b10Float => T::Rg11b10Float,
+        StorageFormat::Rg32Uint => T::Rg32Uint,
+        StorageFormat::Rg32Sint => T::Rg32Sint,
+        StorageFormat::Rg32Float => T::Rg32Float,
+        StorageFormat::Rgba16Uint => T::Rgba16Uint,
+        StorageFormat::Rgba16Sint => T::Rgba16Sint,
+        StorageFormat::Rgba16Float => T::Rgba16Float,
+        StorageFormat::Rgba32Uint => T::Rgba32Uint,
+        StorageFormat::Rgba32Sint => T::Rgba32Sint,
+        StorageFormat::Rgba32Float => T::Rgba32Float,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ReflectionError, ShaderReflection, VertexMismatch};
+    use crate::render::resource::buffer::{InstanceRaw, InstanceUnit, MeshVertex, Vertex};
+
+    const BASIC: &str = include_str!("../../../res/basic.wgsl");
+
+    #[test]
+    fn basic_shader_is_reflected() {
+        let reflection = ShaderReflection::from_wgsl(BASIC).unwrap();
+
+        let inputs: Vec<_> = reflection
+            .vertex_inputs
+            .iter()
+            .map(|input| (input.location, input.name.as_str(), input.format))
+            .collect();
+        assert_eq!(
+            inputs,
+            [
+                (0, "position", wgpu::VertexFormat::Float32x3),
+                (1, "tex_coords", wgpu::VertexFormat::Float32x2),
+                (5, "model_mx_0", wgpu::VertexFormat::Float32x4),
+                (6, "model_mx_1", wgpu::VertexFormat::Float32x4),
+                (7, "model_mx_2", wgpu::VertexFormat::Float32x4),
+                (8, "model_mx_3", wgpu::VertexFormat::Float32x4),
+            ]
+        );
+        let targets = reflection
+            .color_targets(wgpu::TextureFormat::Bgra8UnormSrgb)
+            .unwrap();
+        assert_eq!(targets.len(), 1);
+        assert_eq!(
+            targets[0].as_ref().unwrap().format,
+            wgpu::TextureFormat::Bgra8UnormSrgb
+        );
+
+        // the texture is group 0, the camera group 1
+        let groups: Vec<Vec<_>> = reflection
+            .bind_groups
+            .iter()
+            .map(|entries| {
+                entries
+                    .iter()
+                    .map(|entry| (entry.binding, entry.visibility, entry.ty))
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            groups,
+            [
+                vec![
+                    (
+                        0,
+                        wgpu::ShaderStages::FRAGMENT,
+                        wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        }
+                    ),
+                    (
+                        1,
+                        wgpu::ShaderStages::FRAGMENT,
+                        wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering)
+                    ),
+                ],
+                vec![(
+                    0,
+                    wgpu::ShaderStages::VERTEX,
+                    wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    }
+                )],
+            ]
+        );
+
+        reflection
+            .check_vertex_buffers(&[Vertex::layout(), InstanceRaw::layout()])
+            .unwrap();
+    }
+
+    #[test]
+    fn mismatched_vertex_buffers_name_the_attributes() {
+        let reflection = ShaderReflection::from_wgsl(BASIC).unwrap();
+
+        // tex_coords read as vec3, the instance buffer is left out
+        let attributes = wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3];
+        let error = reflection
+            .check_vertex_buffers(&[wgpu::VertexBufferLayout {
+                array_stride: 24,
+                step_mode: wgpu::VertexStepMode::Vertex,
+                attributes: &attributes,
+            }])
+            .unwrap_err();
+        let mismatches = match &error {
+            ReflectionError::VertexMismatch(mismatches) => mismatches,
+            error => panic!("unexpected error: {}", error),
+        };
+        assert_eq!(
+            mismatches[0],
+            VertexMismatch {
+                location: 1,
+                name: "tex_coords".to_string(),
+                expected: wgpu::VertexFormat::Float32x2,
+                found: Some(wgpu::VertexFormat::Float32x3),
+            }
+        );
+        let missing: Vec<u32> = mismatches[1..]
+            .iter()
+            .filter(|mismatch| mismatch.found.is_none())
+            .map(|mismatch| mismatch.location)
+            .collect();
+        assert_eq!(missing, [5, 6, 7, 8]);
+        assert!(error.to_string().contains("`tex_coords` at location 1"));
+    }
+
+    #[test]
+    fn invalid_source_is_reported() {
+        let error = ShaderReflection::from_wgsl("fn vs_main( {").unwrap_err();
+        assert!(matches!(error, ReflectionError::Invalid(_)));
+    }
+}
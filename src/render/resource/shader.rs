@@ -1,19 +1,44 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 
-use bevy_asset::{AssetEvent, AssetLoader, AssetServer, Assets, Handle, HandleId, LoadedAsset};
+use anyhow::Context;
+use bevy_asset::{
+    AssetEvent, AssetLoader, AssetPath, AssetServer, Assets, Handle, HandleId, LoadedAsset,
+};
 use bevy_ecs::{
     prelude::EventReader,
     system::{Res, ResMut},
 };
 use bevy_reflect::TypeUuid;
 
-use crate::util::{AssetStore};
+use crate::{
+    error::decode_utf8,
+    render::{targets::RenderTargets, DepthConvention},
+    texture::Texture,
+    util::AssetStore,
+};
 
-use super::buffer::{InstanceRaw, InstanceUnit, MeshVertex, Vertex};
+use super::{
+    buffer::{InstanceRaw, InstanceUnit, MeshVertex, Vertex},
+    preprocess::{self, ShaderDefs, ShaderPreprocessError},
+    reflect::ShaderReflection,
+};
 
+#[derive(Clone)]
 pub struct ShaderTargets {
     pub vertex_buffers: Vec<wgpu::VertexBufferLayout<'static>>, // TODO: lifetime again
     pub fragment_targets: Vec<Option<wgpu::ColorTargetState>>,
+    pub defs: ShaderDefs,
+    /// Has to match the depth texture of the pass, see [`DepthFormat`](crate::render::DepthFormat)
+    pub depth_format: wgpu::TextureFormat,
+    /// Needs a depth format with a stencil aspect, see [`StencilMask`](super::pipeline::StencilMask)
+    pub stencil: wgpu::StencilState,
+    /// Without it fragments pass whatever is in the depth buffer, the depth is still written
+    pub depth_test: bool,
+    /// Without it fragments are still tested but leave the depth buffer as it is, e.g. for
+    /// blended draws that should not hide each other
+    pub depth_write: bool,
+    /// Flips the depth comparisons, see [`DepthConvention`]
+    pub depth_convention: DepthConvention,
 }
 
 impl Default for ShaderTargets {
@@ -21,6 +46,12 @@ impl Default for ShaderTargets {
         Self {
             vertex_buffers: Default::default(),
             fragment_targets: Default::default(),
+            defs: Default::default(),
+            depth_format: Texture::DEPTH_FORMAT,
+            stencil: Default::default(),
+            depth_test: true,
+            depth_write: true,
+            depth_convention: DepthConvention::Standard,
         }
     }
 }
@@ -28,16 +59,20 @@ impl Default for ShaderTargets {
 pub struct Shader {
     pub module: wgpu::ShaderModule,
     pub targets: ShaderTargets,
+    /// Set when compiled from a [`ShaderSource`] that naga could parse
+    pub reflection: Option<ShaderReflection>,
 }
 
 impl Shader {
     pub const VERTEX_ENTRY_POINT: &'static str = "vs_main";
     pub const FRAGMENT_ENTRY_POINT: &'static str = "fs_main";
+    pub const COMPUTE_ENTRY_POINT: &'static str = "cs_main";
 
     pub fn with(module: wgpu::ShaderModule) -> Self {
         Self {
             module,
             targets: Default::default(),
+            reflection: None,
         }
     }
 
@@ -51,21 +86,87 @@ impl Shader {
             targets: ShaderTargets {
                 vertex_buffers,
                 fragment_targets,
+                ..Default::default()
             },
+            reflection: None,
         }
     }
 
     pub fn with_targets(module: wgpu::ShaderModule, targets: ShaderTargets) -> Self {
-        Self { module, targets }
+        Self {
+            module,
+            targets,
+            reflection: None,
+        }
+    }
+
+    pub fn with_reflection(mut self, reflection: ShaderReflection) -> Self {
+        self.reflection = Some(reflection);
+        self
     }
 
     pub fn add_vertex<V: MeshVertex>(&mut self) {
         self.targets.vertex_buffers.push(V::layout());
     }
 
+    pub fn add_instance<I: InstanceUnit>(&mut self) {
+        self.targets.vertex_buffers.push(I::layout());
+    }
+
+    /// For `InstanceInput` of [`ShaderInclude::MODEL`](super::shader_lib::ShaderInclude::MODEL),
+    /// after the vertex buffers of the mesh
+    pub fn add_model_instancing(&mut self) {
+        self.add_instance::<InstanceRaw>();
+    }
+
     pub fn add_fragment_target(&mut self, target: wgpu::ColorTargetState) {
         self.targets.fragment_targets.push(Some(target));
     }
+
+    pub fn with_depth_stencil(
+        mut self,
+        depth_format: wgpu::TextureFormat,
+        stencil: wgpu::StencilState,
+    ) -> Self {
+        self.targets.depth_format = depth_format;
+        self.targets.stencil = stencil;
+        self
+    }
+
+    pub fn with_depth_convention(mut self, depth_convention: DepthConvention) -> Self {
+        self.targets.depth_convention = depth_convention;
+        self
+    }
+
+    /// Drawn over what is already in the depth buffer, see [`ShaderTargets::depth_test`]
+    pub fn without_depth_test(mut self) -> Self {
+        self.targets.depth_test = false;
+        self
+    }
+
+    /// Tested against the depth buffer without writing it, see [`ShaderTargets::depth_write`]
+    pub fn without_depth_write(mut self) -> Self {
+        self.targets.depth_write = false;
+        self
+    }
+}
+
+pub struct ComputeShader {
+    pub module: wgpu::ShaderModule,
+    pub entry_point: &'static str,
+}
+
+/// Marks a loaded .wgsl asset to be compiled as a compute shader
+pub struct ComputeShaderSource {
+    pub entry_point: &'static str,
+}
+
+impl Default for ComputeShaderSource {
+    fn default() -> Self {
+        Self {
+            entry_point: Shader::COMPUTE_ENTRY_POINT,
+        }
+    }
 }
 
 #[derive(Default)]
@@ -74,26 +175,80 @@ pub struct Shaders(
     pub HashMap<HandleId, ShaderTargets>,
 );
 
+/// Identifies a compiled variant of a shader source, see [`ShaderDefs::cache_key`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ShaderVariantKey {
+    pub handle: HandleId,
+    pub defs: u64,
+}
+
+impl ShaderVariantKey {
+    pub fn new(handle: HandleId, defs: &ShaderDefs) -> Self {
+        Self {
+            handle,
+            defs: defs.cache_key(),
+        }
+    }
+}
+
+/// Includes are already resolved by the loader, `//!if` blocks are resolved at compile time
 #[derive(TypeUuid)]
 #[uuid = "4B8302DA-21AD-401F-AF45-1DFD956B80B5"]
 pub struct ShaderSource(String);
 
 impl ShaderSource {
-    pub fn compile(self, device: &wgpu::Device) -> Shader {
-        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: None,
-            source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Owned(self.0)),
-        });
-        Shader::with(module)
+    /// The WGSL to compile with `defs`, for modules created elsewhere with [`create_wgsl_module`]
+    pub fn preprocess(&self, defs: &ShaderDefs) -> Result<String, ShaderPreprocessError> {
+        preprocess::apply_defs(&self.0, defs)
+    }
+
+    pub fn create_module(
+        &self,
+        device: &wgpu::Device,
+        defs: &ShaderDefs,
+    ) -> Result<wgpu::ShaderModule, ShaderPreprocessError> {
+        Ok(create_wgsl_module(device, self.preprocess(defs)?))
+    }
+
+    pub fn compile_compute(
+        &self,
+        device: &wgpu::Device,
+        entry_point: &'static str,
+    ) -> Result<ComputeShader, ShaderPreprocessError> {
+        Ok(ComputeShader {
+            module: self.create_module(device, &ShaderDefs::default())?,
+            entry_point,
+        })
     }
 
-    pub fn compile_with_targets(self, device: &wgpu::Device, targets: ShaderTargets) -> Shader {
-        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: None,
-            source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Owned(self.0)),
-        });
-        Shader::with_targets(module, targets)
+    pub fn compile(&self, device: &wgpu::Device) -> Result<Shader, ShaderPreprocessError> {
+        self.compile_with_targets(device, ShaderTargets::default())
     }
+
+    /// Reflects the preprocessed source too, a source naga rejects is compiled without it
+    pub fn compile_with_targets(
+        &self,
+        device: &wgpu::Device,
+        targets: ShaderTargets,
+    ) -> Result<Shader, ShaderPreprocessError> {
+        let source = self.preprocess(&targets.defs)?;
+        let reflection = ShaderReflection::from_wgsl(&source)
+            .map_err(|error| log::warn!("Shader reflection failed: {}", error))
+            .ok();
+        Ok(Shader {
+            module: create_wgsl_module(device, source),
+            targets,
+            reflection,
+        })
+    }
+}
+
+/// `source` is already preprocessed
+pub fn create_wgsl_module(device: &wgpu::Device, source: String) -> wgpu::ShaderModule {
+    device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: None,
+        source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Owned(source)),
+    })
 }
 
 pub struct ShaderSourceLoader;
@@ -104,9 +259,33 @@ impl AssetLoader for ShaderSourceLoader {
         load_context: &'a mut bevy_asset::LoadContext,
     ) -> bevy_asset::BoxedFuture<'a, anyhow::Result<(), anyhow::Error>> {
         Box::pin(async move {
-            load_context.set_default_asset(LoadedAsset::new(ShaderSource(
-                String::from_utf8(bytes.to_owned()).unwrap(),
-            )));
+            let root = load_context.path().to_string_lossy().replace('\\', "/");
+            let source = decode_utf8(&root, bytes)?;
+
+            // NOTE: read every include up front, they are resolved synchronously
+            let mut files: HashMap<String, String> = HashMap::new();
+            let mut pending = preprocess::include_paths(&source);
+            while let Some(path) = pending.pop() {
+                if files.contains_key(&path) {
+                    continue;
+                }
+                let included = load_context
+                    .read_asset_bytes(&path)
+                    .await
+                    .with_context(|| format!("{} (included while loading {})", path, root))?;
+                let included = decode_utf8(&path, &included)
+                    .with_context(|| format!("included while loading {}", root))?;
+                pending.extend(preprocess::include_paths(&included));
+                files.insert(path, included);
+            }
+
+            let source = preprocess::resolve_includes(&root, &source, &files)?;
+            let mut asset = LoadedAsset::new(ShaderSource(source));
+            // dependencies are reloaded along with this source
+            for path in files.into_keys() {
+                asset = asset.with_dependency(AssetPath::new(path.into(), None));
+            }
+            load_context.set_default_asset(asset);
 
             Ok(())
         })
@@ -117,35 +296,62 @@ impl AssetLoader for ShaderSourceLoader {
     }
 }
 
+/// Compiles on load and recompiles on reload, so sources and targets are kept around
 pub fn compile_shaders(
-    device: Res<wgpu::Device>,
+    device: Res<Arc<wgpu::Device>>,
     mut events: EventReader<AssetEvent<ShaderSource>>,
-    mut sources: ResMut<Assets<ShaderSource>>,
+    sources: Res<Assets<ShaderSource>>,
     // mut shaders: ResMut<Shaders>,
     mut shaders: ResMut<AssetStore<Shader>>,
-    mut shader_targets: ResMut<AssetStore<ShaderTargets>>,
+    shader_targets: Res<AssetStore<ShaderTargets>>,
+    mut compute_shaders: ResMut<AssetStore<ComputeShader>>,
+    compute_sources: Res<AssetStore<ComputeShaderSource>>,
 ) {
     for event in events.iter() {
         match event {
-            AssetEvent::Created { handle } => {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+                let handle_id = handle.into();
+                let shader_source = match sources.get(handle) {
+                    Some(shader_source) => shader_source,
+                    None => continue,
+                };
+                // NOTE: the same source can be used by both paths
+                if let Some(compute_source) = compute_sources.get(&handle_id) {
+                    match shader_source.compile_compute(device.as_ref(), compute_source.entry_point)
+                    {
+                        Ok(compute_shader) => {
+                            compute_shaders.insert(handle_id, compute_shader);
+                        }
+                        Err(error) => log::error!("Compute shader preprocessing failed: {}", error),
+                    }
+                }
+                if let Some(targets) = shader_targets.get(&handle_id) {
+                    match shader_source.compile_with_targets(device.as_ref(), targets.clone()) {
+                        Ok(shader) => {
+                            shaders.insert(handle_id, shader);
+                        }
+                        Err(error) => log::error!("Shader preprocessing failed: {}", error),
+                    }
+                }
+            }
+            AssetEvent::Removed { handle } => {
                 let handle_id = handle.into();
-                let shader_source = sources.remove(handle).unwrap();
-                let shader = shader_source.compile_with_targets(
-                    device.as_ref(),
-                    shader_targets.remove(&handle_id).unwrap(),
-                );
-                shaders.insert(handle_id, shader);
+                shaders.remove(&handle_id);
+                compute_shaders.remove(&handle_id);
             }
-            _ => {}
         }
     }
 }
 
 pub fn load_test_shader(
-    config: Res<wgpu::SurfaceConfiguration>,
+    render_targets: Res<Option<RenderTargets>>,
     asset_server: Res<AssetServer>,
     mut shader_targets: ResMut<AssetStore<ShaderTargets>>,
 ) {
+    let render_targets = match *render_targets {
+        Some(render_targets) => render_targets,
+        None => return,
+    };
     let path = "res/basic.wgsl";
     let _shader_handle = load_shader(
         &asset_server,
@@ -153,11 +359,10 @@ pub fn load_test_shader(
         path,
         ShaderTargets {
             vertex_buffers: vec![Vertex::layout(), InstanceRaw::layout()],
-            fragment_targets: vec![Some(wgpu::ColorTargetState {
-                format: config.format,
-                blend: Some(wgpu::BlendState::REPLACE),
-                write_mask: wgpu::ColorWrites::ALL,
-            })],
+            fragment_targets: vec![Some(
+                render_targets.color_target(Some(wgpu::BlendState::REPLACE)),
+            )],
+            ..Default::default()
         },
     );
     let _shader_handle_weak: Handle<ShaderSource> = Handle::weak(HandleId::from(path));
@@ -174,3 +379,15 @@ pub fn load_shader(
 
     shader_handle
 }
+
+pub fn load_compute_shader(
+    asset_server: &AssetServer,
+    compute_sources: &mut AssetStore<ComputeShaderSource>,
+    path: &str,
+    entry_point: &'static str,
+) -> Handle<ShaderSource> {
+    let shader_handle: Handle<ShaderSource> = asset_server.load(path);
+    compute_sources.insert(shader_handle.id, ComputeShaderSource { entry_point });
+
+    shader_handle
+}
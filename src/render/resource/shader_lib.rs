@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+
+use crate::render::depth::DepthBindGroup;
+
+use super::reflect::ReflectionError;
+
+/// A file of `res/common/` that shaders include with `//!include "path"`, and the bindings
+/// it declares, so the pipelines drawing with it can be checked against them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShaderInclude {
+    /// Relative to the asset folder, as written in the include
+    pub path: &'static str,
+    pub source: &'static str,
+    /// (group, binding) of every binding it declares, sorted
+    pub bindings: &'static [(u32, u32)],
+}
+
+impl ShaderInclude {
+    /// `CameraUniform` of the [`Camera`](crate::camera::Camera)
+    pub const CAMERA: Self = Self {
+        path: "common/camera.wgsl",
+        source: include_str!("../../../res/common/camera.wgsl"),
+        bindings: &[(0, 0)],
+    };
+    /// `ModelUniform` of the [`Transform`](crate::transform::Transform), and `InstanceInput`
+    /// at the locations of [`InstanceRaw`](super::buffer::InstanceRaw), see
+    /// [`Shader::add_model_instancing`](super::shader::Shader::add_model_instancing)
+    pub const MODEL: Self = Self {
+        path: "common/model.wgsl",
+        source: include_str!("../../../res/common/model.wgsl"),
+        bindings: &[(0, 1)],
+    };
+    /// Camera and model, the [`ObjectUniforms`](crate::render::material::ObjectUniforms)
+    /// of the materials
+    pub const OBJECT: Self = Self {
+        path: "common/object.wgsl",
+        source: include_str!("../../../res/common/object.wgsl"),
+        bindings: &[(0, 0), (0, 1)],
+    };
+    /// The [`JointPalette`](crate::render::skin::JointPalette), after the object uniforms
+    pub const SKINNING: Self = Self {
+        path: "common/skinning.wgsl",
+        source: include_str!("../../../res/common/skinning.wgsl"),
+        bindings: &[(0, 2)],
+    };
+    /// The [`DepthBindGroup`] of post-processing effects
+    pub const DEPTH: Self = Self {
+        path: DepthBindGroup::INCLUDE,
+        source: DepthBindGroup::INCLUDE_SOURCE,
+        bindings: &[(1, 0), (1, 1), (1, 2)],
+    };
+    /// `fullscreen_triangle`, drawn with 3 vertices and no vertex buffer
+    pub const FULLSCREEN: Self = Self {
+        path: "common/fullscreen.wgsl",
+        source: include_str!("../../../res/common/fullscreen.wgsl"),
+        bindings: &[],
+    };
+    /// `srgb_to_linear` and `linear_to_srgb`
+    pub const SRGB: Self = Self {
+        path: "common/srgb.wgsl",
+        source: include_str!("../../../res/common/srgb.wgsl"),
+        bindings: &[],
+    };
+
+    /// `LightsUniform` of the [`GpuLights`](crate::light::GpuLights) and `calc_lighting`,
+    /// Lambert diffuse with Blinn-Phong specular
+    pub const LIGHTING: Self = Self {
+        path: "common/lighting.wgsl",
+        source: include_str!("../../../res/common/lighting.wgsl"),
+        bindings: &[(1, 0)],
+    };
+
+    pub const LIBRARY: &'static [Self] = &[
+        Self::CAMERA,
+        Self::MODEL,
+        Self::OBJECT,
+        Self::SKINNING,
+        Self::DEPTH,
+        Self::FULLSCREEN,
+        Self::SRGB,
+        Self::LIGHTING,
+    ];
+
+    /// The library as the `files` of [`resolve_includes`](super::preprocess::resolve_includes),
+    /// for shaders that are not loaded through the asset server
+    pub fn files() -> HashMap<String, String> {
+        Self::LIBRARY
+            .iter()
+            .map(|include| (include.path.to_string(), include.source.to_string()))
+            .collect()
+    }
+
+    /// Every binding of the include has an entry in `bind_group_layouts`, indexed by group
+    pub fn check_bind_groups(
+        &self,
+        bind_group_layouts: &[&[wgpu::BindGroupLayoutEntry]],
+    ) -> Result<(), ReflectionError> {
+        for &(group, binding) in self.bindings {
+            let found = bind_group_layouts
+                .get(group as usize)
+                .map_or(false, |entries| {
+                    entries.iter().any(|entry| entry.binding == binding)
+                });
+            if !found {
+                return Err(ReflectionError::MissingBinding {
+                    group,
+                    binding,
+                    include: self.path,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::render::resource::{
+        buffer::{InstanceRaw, InstanceUnit, MeshVertex, Vertex},
+        preprocess::resolve_includes,
+        reflect::{ReflectionError, ShaderReflection},
+    };
+
+    use super::ShaderInclude;
+
+    fn uniform(binding: u32) -> wgpu::BindGroupLayoutEntry {
+        wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::VERTEX,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }
+    }
+
+    #[test]
+    fn library_files_compile_standalone() {
+        let files = ShaderInclude::files();
+        for include in ShaderInclude::LIBRARY {
+            let source = resolve_includes(include.path, include.source, &files).unwrap();
+            let reflection = ShaderReflection::from_wgsl(&source)
+                .unwrap_or_else(|error| panic!("{}: {}", include.path, error));
+            let bindings: Vec<(u32, u32)> = reflection
+                .bind_groups
+                .iter()
+                .enumerate()
+                .flat_map(|(group, entries)| {
+                    entries
+                        .iter()
+                        .map(move |entry| (group as u32, entry.binding))
+                })
+                .collect();
+            assert_eq!(bindings, include.bindings, "{}", include.path);
+        }
+    }
+
+    #[test]
+    fn shaders_of_res_resolve_with_the_library() {
+        let files = ShaderInclude::files();
+        for (path, source) in [
+            (
+                "color_material.wgsl",
+                include_str!("../../../res/color_material.wgsl"),
+            ),
+            (
+                "texture_material.wgsl",
+                include_str!("../../../res/texture_material.wgsl"),
+            ),
+            (
+                "vertex_color_material.wgsl",
+                include_str!("../../../res/vertex_color_material.wgsl"),
+            ),
+            (
+                "skinned_color_material.wgsl",
+                include_str!("../../../res/skinned_color_material.wgsl"),
+            ),
+            (
+                "lit_color_material.wgsl",
+                include_str!("../../../res/lit_color_material.wgsl"),
+            ),
+            ("fog.wgsl", include_str!("../../../res/fog.wgsl")),
+            ("sprite.wgsl", include_str!("../../../res/sprite.wgsl")),
+            (
+                "error_material.wgsl",
+                include_str!("../../../res/error_material.wgsl"),
+            ),
+        ] {
+            let source = resolve_includes(path, source, &files).unwrap();
+            ShaderReflection::from_wgsl(&source)
+                .unwrap_or_else(|error| panic!("{}: {}", path, error));
+        }
+    }
+
+    #[test]
+    fn model_instancing_matches_the_instance_layout() {
+        let source = r#"
+//!include "common/object.wgsl"
+
+@vertex
+fn vs_main(
+    @location(0) position: vec3<f32>,
+    instance: InstanceInput,
+) -> @builtin(position) vec4<f32> {
+    return camera.view_proj * instance_model(instance) * vec4<f32>(position, 1.0);
+}
+"#;
+        let source = resolve_includes("instanced.wgsl", source, &ShaderInclude::files()).unwrap();
+        let reflection = ShaderReflection::from_wgsl(&source).unwrap();
+        reflection
+            .check_vertex_buffers(&[Vertex::layout(), InstanceRaw::layout()])
+            .unwrap();
+        assert!(reflection
+            .check_vertex_buffers(&[Vertex::layout()])
+            .is_err());
+    }
+
+    #[test]
+    fn bind_groups_are_checked_against_the_include() {
+        let object = [uniform(0), uniform(1)];
+        ShaderInclude::OBJECT
+            .check_bind_groups(&[&object[..]])
+            .unwrap();
+        ShaderInclude::FULLSCREEN.check_bind_groups(&[]).unwrap();
+        assert_eq!(
+            ShaderInclude::SKINNING.check_bind_groups(&[&object[..]]),
+            Err(ReflectionError::MissingBinding {
+                group: 0,
+                binding: 2,
+                include: "common/skinning.wgsl"
+            })
+        );
+        assert_eq!(
+            ShaderInclude::DEPTH.check_bind_groups(&[&object[..]]),
+            Err(ReflectionError::MissingBinding {
+                group: 1,
+                binding: 0,
+                include: "common/depth.wgsl"
+            })
+        );
+    }
+}
@@ -0,0 +1,325 @@
+use std::fmt;
+
+use super::bind::GpuUniform;
+
+/// Name, offset, size and WGSL alignment in bytes of a field of a uniform struct
+pub type UniformField = (&'static str, usize, usize, usize);
+
+/// Alignment of a member of a WGSL uniform struct, by its type without parameters, e.g.
+/// `"vec3"`. Arrays and structs align to 16 in uniforms, the elements of an array need a
+/// stride of 16 too. Panics on other types, at compile time in `gpu_uniform!`
+pub const fn wgsl_uniform_align(wgsl_type: &str) -> usize {
+    match wgsl_type.as_bytes() {
+        b"f32" | b"i32" | b"u32" => 4,
+        b"vec2" | b"mat2x2" | b"mat3x2" | b"mat4x2" => 8,
+        b"vec3" | b"vec4" | b"mat2x3" | b"mat3x3" | b"mat4x3" | b"mat2x4" | b"mat3x4"
+        | b"mat4x4" | b"array" | b"struct" => 16,
+        _ => panic!("not the type of a WGSL uniform member"),
+    }
+}
+
+const fn round_up(value: usize, align: usize) -> usize {
+    (value + align - 1) / align * align
+}
+
+/// Offsets of the fields of a `repr(C)` struct, from their names, sizes, Rust and WGSL
+/// alignments in declaration order
+pub const fn repr_c_fields<const N: usize>(
+    fields: [(&'static str, usize, usize, usize); N],
+) -> [UniformField; N] {
+    let mut laid_out = [("", 0, 0, 0); N];
+    let mut offset = 0;
+    let mut i = 0;
+    while i < N {
+        let (name, size, align, wgsl_align) = fields[i];
+        offset = round_up(offset, align);
+        laid_out[i] = (name, offset, size, wgsl_align);
+        offset += size;
+        i += 1;
+    }
+    laid_out
+}
+
+/// A field of a uniform struct is not where WGSL puts the member of the same type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UniformLayoutError {
+    /// `None` when the size of the struct is off
+    pub field: Option<&'static str>,
+    /// Of the field, or the size of the struct
+    pub offset: usize,
+    /// The offset WGSL puts the field at, or the size it rounds the struct to
+    pub expected: usize,
+}
+
+impl fmt::Display for UniformLayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.field {
+            Some(field) if self.offset < self.expected => write!(
+                f,
+                "`{}` is at byte {} but WGSL puts it at byte {}, add {} bytes of padding before it",
+                field,
+                self.offset,
+                self.expected,
+                self.expected - self.offset
+            ),
+            Some(field) => write!(
+                f,
+                "`{}` is at byte {} but WGSL puts it at byte {}, remove {} bytes of padding before it",
+                field,
+                self.offset,
+                self.expected,
+                self.offset - self.expected
+            ),
+            None => write!(
+                f,
+                "size is {} bytes but WGSL rounds it to {}, add {} bytes of padding at the end",
+                self.offset,
+                self.expected,
+                self.expected.saturating_sub(self.offset)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for UniformLayoutError {}
+
+/// Checks `fields` against the WGSL uniform layout of the same members, the first field
+/// out of place is returned. Fields named `_...` are padding, WGSL does not see them
+pub const fn check_uniform_layout(
+    fields: &[UniformField],
+    size: usize,
+) -> Option<UniformLayoutError> {
+    // end of the last member WGSL sees, padding only counts for the Rust offsets
+    let mut wgsl_end = 0;
+    let mut i = 0;
+    while i < fields.len() {
+        let (name, offset, field_size, wgsl_align) = fields[i];
+        let is_padding = !name.is_empty() && name.as_bytes()[0] == b'_';
+        if !is_padding {
+            let expected = round_up(wgsl_end, wgsl_align);
+            if offset != expected {
+                return Some(UniformLayoutError {
+                    field: Some(name),
+                    offset,
+                    expected,
+                });
+            }
+            wgsl_end = offset + field_size;
+        }
+        i += 1;
+    }
+    let expected = round_up(wgsl_end, 16);
+    if size != expected {
+        return Some(UniformLayoutError {
+            field: None,
+            offset: size,
+            expected,
+        });
+    }
+    None
+}
+
+/// For hand written [`GpuUniform`] impls, panics naming the misplaced field.
+/// `fields` are `(name, offset, size, wgsl_align)` in declaration order
+pub fn assert_uniform_layout<T: GpuUniform>(fields: &[UniformField]) {
+    if let Some(error) = check_uniform_layout(fields, std::mem::size_of::<T>()) {
+        panic!("{}: {}", std::any::type_name::<T>(), error);
+    }
+}
+
+/// Defines a [`GpuUniform`] struct, the `repr(C)` layout of its fields is checked against
+/// the WGSL uniform layout at compile time, the error names the field and the padding
+/// it needs. Every field names the WGSL type of its member, see [`wgsl_uniform_align`].
+/// The struct still derives `C`, `Pod` and `Zeroable` itself
+///
+/// ```ignore
+/// gpu_uniform! {
+///     #[repr(C)]
+///     #[derive(Debug, Clone, Copy, C, Pod, Zeroable)]
+///     pub struct ColorUniform {
+///         pub color: [f32; 4] as vec4,
+///     }
+/// }
+/// ```
+macro_rules! gpu_uniform {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $($(#[$field_meta:meta])* $field_vis:vis $field:ident: $ty:ty as $wgsl:tt),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis struct $name {
+            $($(#[$field_meta])* $field_vis $field: $ty),*
+        }
+
+        impl $crate::render::resource::bind::GpuUniform for $name {}
+
+        const _: () = {
+            use $crate::render::resource::uniform_layout::{
+                check_uniform_layout, repr_c_fields, wgsl_uniform_align, UniformField,
+                UniformLayoutError,
+            };
+
+            const FIELDS: &[UniformField] = &repr_c_fields([$((
+                stringify!($field),
+                ::std::mem::size_of::<$ty>(),
+                ::std::mem::align_of::<$ty>(),
+                wgsl_uniform_align(stringify!($wgsl)),
+            )),*]);
+            const ERROR: Option<UniformLayoutError> =
+                check_uniform_layout(FIELDS, ::std::mem::size_of::<$name>());
+            const FIELD: &str = match ERROR {
+                Some(UniformLayoutError { field: Some(field), .. }) => field,
+                _ => "",
+            };
+            const OFFSET: usize = match ERROR {
+                Some(error) => error.offset,
+                None => 0,
+            };
+            const EXPECTED: usize = match ERROR {
+                Some(error) => error.expected,
+                None => 0,
+            };
+            const PADDING: usize = EXPECTED.saturating_sub(OFFSET);
+            const EXCESS: usize = OFFSET.saturating_sub(EXPECTED);
+
+            match ERROR {
+                None => {}
+                Some(UniformLayoutError { field: Some(_), .. }) if PADDING > 0 => panic!("{}", const_format::formatcp!(
+                    "{}: `{}` is at byte {} but WGSL puts it at byte {}, add {} bytes of padding before it",
+                    stringify!($name),
+                    FIELD,
+                    OFFSET,
+                    EXPECTED,
+                    PADDING,
+                )),
+                Some(UniformLayoutError { field: Some(_), .. }) => panic!("{}", const_format::formatcp!(
+                    "{}: `{}` is at byte {} but WGSL puts it at byte {}, remove {} bytes of padding before it",
+                    stringify!($name),
+                    FIELD,
+                    OFFSET,
+                    EXPECTED,
+                    EXCESS,
+                )),
+                Some(UniformLayoutError { field: None, .. }) => panic!("{}", const_format::formatcp!(
+                    "{}: size is {} bytes but WGSL rounds it to {}, add {} bytes of padding at the end",
+                    stringify!($name),
+                    OFFSET,
+                    EXPECTED,
+                    PADDING,
+                )),
+            }
+        };
+    };
+}
+pub(crate) use gpu_uniform;
+
+#[cfg(test)]
+mod tests {
+    use bytemuck::{Pod, Zeroable};
+    use repr_trait::C;
+
+    use crate::{
+        camera::CameraUniform,
+        light::LightsUniform,
+        render::{material::ColorUniform, resource::bind::GpuUniform},
+        transform::ModelUniform,
+    };
+
+    use super::{
+        assert_uniform_layout, check_uniform_layout, repr_c_fields, wgsl_uniform_align,
+        UniformLayoutError,
+    };
+
+    /// Two `vec3<f32>`, WGSL aligns the second to 16
+    #[repr(C)]
+    #[derive(Debug, Clone, Copy, C, Pod, Zeroable)]
+    struct Misaligned {
+        a: [f32; 3],
+        b: [f32; 3],
+    }
+    impl GpuUniform for Misaligned {}
+
+    #[test]
+    #[should_panic(
+        expected = "Misaligned: `b` is at byte 12 but WGSL puts it at byte 16, add 4 bytes of padding before it"
+    )]
+    fn misaligned_fields_are_named() {
+        let uniform = Misaligned::zeroed();
+        let offset = |field: &[f32; 3]| field.as_ptr() as usize - uniform.a.as_ptr() as usize;
+        assert_uniform_layout::<Misaligned>(&[
+            ("a", offset(&uniform.a), 12, wgsl_uniform_align("vec3")),
+            ("b", offset(&uniform.b), 12, wgsl_uniform_align("vec3")),
+        ]);
+    }
+
+    #[test]
+    fn layouts_are_checked() {
+        let fields = repr_c_fields([("a", 12, 4, 16), ("b", 12, 4, 16)]);
+        assert_eq!(fields, [("a", 0, 12, 16), ("b", 12, 12, 16)]);
+        let error = check_uniform_layout(&fields, 24).unwrap();
+        assert_eq!(
+            error,
+            UniformLayoutError {
+                field: Some("b"),
+                offset: 12,
+                expected: 16
+            }
+        );
+
+        // a trailing vec3 still rounds the struct up to 16
+        let fields = repr_c_fields([("a", 16, 4, 16), ("b", 12, 4, 16)]);
+        let error = check_uniform_layout(&fields, 28).unwrap();
+        assert_eq!(
+            error.to_string(),
+            "size is 28 bytes but WGSL rounds it to 32, add 4 bytes of padding at the end"
+        );
+
+        // padding WGSL does not see, too much of it moves the next field off
+        let fields = repr_c_fields([
+            ("count", 4, 4, 4),
+            ("_padding", 28, 4, 4),
+            ("eye", 16, 4, 16),
+        ]);
+        assert_eq!(
+            check_uniform_layout(&fields, 48).unwrap().to_string(),
+            "`eye` is at byte 32 but WGSL puts it at byte 16, remove 16 bytes of padding before it"
+        );
+        let fields = repr_c_fields([
+            ("count", 4, 4, 4),
+            ("_padding", 12, 4, 4),
+            ("eye", 16, 4, 16),
+        ]);
+        assert_eq!(check_uniform_layout(&fields, 32), None);
+
+        // the same size aligns differently, a struct of two f32 is not a vec2
+        let fields = repr_c_fields([("count", 4, 4, 4), ("range", 8, 4, 8)]);
+        assert_eq!(check_uniform_layout(&fields, 16), None);
+        let fields = repr_c_fields([("count", 4, 4, 4), ("range", 8, 4, 16)]);
+        assert_eq!(
+            check_uniform_layout(&fields, 16).unwrap().to_string(),
+            "`range` is at byte 4 but WGSL puts it at byte 16, add 12 bytes of padding before it"
+        );
+    }
+
+    #[test]
+    fn engine_uniforms_match_wgsl() {
+        // the derived ones are checked at compile time, the hand written one here
+        assert_uniform_layout::<CameraUniform>(&[(
+            "view_proj",
+            0,
+            64,
+            wgsl_uniform_align("mat4x4"),
+        )]);
+        assert_uniform_layout::<ModelUniform>(&[("model", 0, 64, wgsl_uniform_align("mat4x4"))]);
+        assert_uniform_layout::<ColorUniform>(&[("color", 0, 16, wgsl_uniform_align("vec4"))]);
+        assert_uniform_layout::<LightsUniform>(&[
+            ("count", 0, 4, wgsl_uniform_align("u32")),
+            ("_padding", 4, 12, 4),
+            ("eye", 16, 16, wgsl_uniform_align("vec4")),
+            ("dir_light", 32, 32, wgsl_uniform_align("struct")),
+            ("point_lights", 64, 256, wgsl_uniform_align("array")),
+        ]);
+    }
+}
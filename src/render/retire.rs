@@ -0,0 +1,297 @@
+use std::{
+    any::Any,
+    collections::{HashMap, HashSet},
+    marker::PhantomData,
+};
+
+use bevy_ecs::{
+    entity::Entity,
+    prelude::Component,
+    query::Changed,
+    system::{Query, RemovedComponents, ResMut},
+};
+
+use crate::util::{Refer, Store};
+
+use super::resource::{bind::BindSlots, pool::BufferPool};
+
+const RING_FRAMES: usize = BufferPool::FRAMES_IN_FLIGHT as usize + 1;
+
+/// GPU objects no longer used by the world, kept alive until the frames that may still
+/// draw with them are done. Retired during frame `n`, dropped at the end of frame
+/// `n + FRAMES_IN_FLIGHT`, never in the frame they were retired in.
+pub struct RetiredResources {
+    frame: u64,
+    // indexed by frame
+    ring: [Vec<Box<dyn Any + Send + Sync>>; RING_FRAMES],
+}
+
+impl Default for RetiredResources {
+    fn default() -> Self {
+        Self {
+            frame: 0,
+            ring: Default::default(),
+        }
+    }
+}
+
+impl RetiredResources {
+    pub const FRAMES_IN_FLIGHT: u64 = BufferPool::FRAMES_IN_FLIGHT;
+
+    pub fn retire(&mut self, resource: impl Any + Send + Sync) {
+        let slot = self.slot(self.frame);
+        self.ring[slot].push(Box::new(resource));
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Resources waiting to be dropped
+    pub fn pending(&self) -> usize {
+        self.ring.iter().map(Vec::len).sum()
+    }
+
+    /// Drops what was retired `FRAMES_IN_FLIGHT` frames ago, returns how many
+    pub fn end_frame(&mut self) -> usize {
+        self.frame += 1;
+        let slot = self.slot(self.frame);
+        let dropped = self.ring[slot].len();
+        self.ring[slot].clear();
+        dropped
+    }
+
+    fn slot(&self, frame: u64) -> usize {
+        (frame % RING_FRAMES as u64) as usize
+    }
+}
+
+/// After the frame was presented
+pub fn end_retired_frame_system(mut retired: ResMut<RetiredResources>) {
+    retired.end_frame();
+}
+
+/// Components holding keys into `Store<Self::Stored>`
+pub trait StoreKeys: Component {
+    type Stored: Send + Sync + 'static;
+
+    fn keys(&self) -> Vec<usize>;
+}
+
+impl<T: Send + Sync + 'static> StoreKeys for Refer<T> {
+    type Stored = T;
+
+    fn keys(&self) -> Vec<usize> {
+        vec![**self]
+    }
+}
+
+impl StoreKeys for BindSlots {
+    type Stored = wgpu::BindGroup;
+
+    fn keys(&self) -> Vec<usize> {
+        self.iter().map(|(_, key)| key).collect()
+    }
+}
+
+/// Entities using each key of `Store<C::Stored>` through their `C`. Keys marked with
+/// [`StoreUsers::reclaim_unused`] are removed from the store and retired once the last
+/// of them is despawned or stops referring to it, e.g. the bind group of a material.
+///
+/// Shared keys owned by a cache, like material pipelines, are not marked, the cache
+/// still hands them out when no entity uses them.
+pub struct StoreUsers<C> {
+    entities: HashMap<Entity, Vec<usize>>,
+    users: HashMap<usize, u32>,
+    reclaimable: HashSet<usize>,
+    _marker: PhantomData<fn() -> C>,
+}
+
+impl<C> Default for StoreUsers<C> {
+    fn default() -> Self {
+        Self {
+            entities: HashMap::new(),
+            users: HashMap::new(),
+            reclaimable: HashSet::new(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<C> StoreUsers<C> {
+    pub fn reclaim_unused(&mut self, key: usize) {
+        self.reclaimable.insert(key);
+    }
+
+    pub fn users(&self, key: usize) -> u32 {
+        self.users.get(&key).copied().unwrap_or(0)
+    }
+
+    /// The reclaimable keys left without users
+    fn set(&mut self, entity: Entity, keys: Vec<usize>) -> Vec<usize> {
+        for key in &keys {
+            *self.users.entry(*key).or_insert(0) += 1;
+        }
+        match self.entities.insert(entity, keys) {
+            Some(previous) => self.release(previous),
+            None => Vec::new(),
+        }
+    }
+
+    fn remove(&mut self, entity: Entity) -> Vec<usize> {
+        match self.entities.remove(&entity) {
+            Some(previous) => self.release(previous),
+            None => Vec::new(),
+        }
+    }
+
+    fn release(&mut self, keys: Vec<usize>) -> Vec<usize> {
+        let mut unused = Vec::new();
+        for key in keys {
+            let users = match self.users.get_mut(&key) {
+                Some(users) => users,
+                None => continue,
+            };
+            *users -= 1;
+            if *users == 0 {
+                self.users.remove(&key);
+                if self.reclaimable.remove(&key) {
+                    unused.push(key);
+                }
+            }
+        }
+        unused
+    }
+}
+
+/// Counts the users of store keys and retires the reclaimable values left unused, with
+/// [`Store::remove_with_events`] so late references are still swept.
+///
+/// NOTE: removals are only seen until the end of the frame, this runs in
+/// `RenderStage::Prepare` after the despawns of the update stages. Components owning
+/// their wgpu objects, like `InstanceData`, are dropped with the entity, wgpu keeps those
+/// alive until the submitted work using them is done.
+pub fn despawn_gpu_cleanup_system<C: StoreKeys>(
+    mut users: ResMut<StoreUsers<C>>,
+    mut store: ResMut<Store<C::Stored>>,
+    mut retired: ResMut<RetiredResources>,
+    removed: RemovedComponents<C>,
+    changed: Query<(Entity, &C), Changed<C>>,
+) {
+    let mut unused = Vec::new();
+    for entity in removed.iter() {
+        unused.extend(users.remove(entity));
+    }
+    for (entity, component) in changed.iter() {
+        unused.extend(users.set(entity, component.keys()));
+    }
+    for key in unused {
+        // NOTE: a key freed and used again in the same frame is not reclaimable anymore,
+        // one removed elsewhere, e.g. with an unloaded texture, is already gone
+        if users.users(key) > 0 || store.get(key).is_none() {
+            continue;
+        }
+        if let Some(value) = store.remove_with_events(key) {
+            retired.retire(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bevy_ecs::{
+        schedule::{Stage, SystemStage},
+        world::World,
+    };
+
+    use crate::util::{Refer, Store};
+
+    use super::{
+        despawn_gpu_cleanup_system, end_retired_frame_system, RetiredResources, StoreUsers,
+    };
+
+    // stands in for a wgpu object, alive while the count is above one
+    struct Tracked(Arc<()>);
+
+    #[test]
+    fn retired_resources_outlive_the_frames_in_flight() {
+        let mut retired = RetiredResources::default();
+        let alive = Arc::new(());
+        retired.retire(alive.clone());
+        assert_eq!(retired.end_frame(), 0);
+        assert_eq!(retired.end_frame(), 0);
+        assert_eq!(Arc::strong_count(&alive), 2);
+        assert_eq!(retired.end_frame(), 1);
+        assert_eq!(Arc::strong_count(&alive), 1);
+        assert_eq!(retired.pending(), 0);
+    }
+
+    #[test]
+    fn despawned_users_retire_the_key_frames_later() {
+        let mut world = World::new();
+        world.init_resource::<RetiredResources>();
+        world.init_resource::<StoreUsers<Refer<Tracked>>>();
+        let alive = Arc::new(());
+        let kept = Arc::new(());
+        let (key, kept_key) = {
+            let mut store = Store::default();
+            let keys = (
+                store.insert(Tracked(alive.clone())),
+                store.insert(Tracked(kept.clone())),
+            );
+            world.insert_resource(store);
+            keys
+        };
+        world
+            .resource_mut::<StoreUsers<Refer<Tracked>>>()
+            .reclaim_unused(key);
+
+        let mut prepare = SystemStage::single(despawn_gpu_cleanup_system::<Refer<Tracked>>);
+        let mut present = SystemStage::single(end_retired_frame_system);
+        let mut run_frame = |world: &mut World| {
+            prepare.run(world);
+            present.run(world);
+            world.clear_trackers();
+        };
+
+        let first = world.spawn().insert(Refer::<Tracked>::new(key)).id();
+        let second = world.spawn().insert(Refer::<Tracked>::new(key)).id();
+        let other = world.spawn().insert(Refer::<Tracked>::new(kept_key)).id();
+        run_frame(&mut world);
+        assert_eq!(world.resource::<StoreUsers<Refer<Tracked>>>().users(key), 2);
+
+        world.despawn(first);
+        run_frame(&mut world);
+        assert!(world.resource::<Store<Tracked>>().get(key).is_some());
+
+        // the last user goes while frame 2 is in flight
+        world.despawn(second);
+        world.despawn(other);
+        let retired_at = world.resource::<RetiredResources>().frame();
+        prepare.run(&mut world);
+        assert!(world.resource::<Store<Tracked>>().get(key).is_none());
+        assert_eq!(Arc::strong_count(&alive), 2);
+        present.run(&mut world);
+        world.clear_trackers();
+        // retired, not dropped, in the same frame
+        assert_eq!(Arc::strong_count(&alive), 2);
+
+        while world.resource::<RetiredResources>().frame()
+            <= retired_at + RetiredResources::FRAMES_IN_FLIGHT
+        {
+            assert_eq!(Arc::strong_count(&alive), 2);
+            run_frame(&mut world);
+        }
+        assert_eq!(Arc::strong_count(&alive), 1);
+        assert_eq!(
+            world.resource::<RetiredResources>().frame(),
+            retired_at + RetiredResources::FRAMES_IN_FLIGHT + 1
+        );
+
+        // not marked, stays in the store without users
+        assert!(world.resource::<Store<Tracked>>().get(kept_key).is_some());
+        assert_eq!(Arc::strong_count(&kept), 2);
+    }
+}
@@ -0,0 +1,353 @@
+/// How the adapter and device are chosen, read when they are created. Insert it before
+/// [`FlatWgpuPlugin`](crate::FlatWgpuPlugin) is built, the default reads the environment:
+///
+/// - `FLAT_BACKEND`: `vulkan`, `dx12`, `dx11`, `metal` or `gl`, all backends if unset
+/// - `FLAT_POWER_PREF`: `low` or `high`, `high` if unset
+///
+/// Unknown values are logged and ignored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WgpuSettings {
+    pub backends: wgpu::Backends,
+    pub power_preference: wgpu::PowerPreference,
+    /// Only software adapters, e.g. WARP or llvmpipe
+    pub force_fallback_adapter: bool,
+    /// Adapters without them are skipped. The optional features the renderer uses are
+    /// enabled on top when the adapter has them
+    pub required_features: wgpu::Features,
+    pub limits_preset: LimitsPreset,
+    /// An `Rgba16Float` surface where supported, post processing then keeps values above
+    /// one. Otherwise an sRGB surface, see [`negotiate_surface_format`]
+    pub prefer_hdr: bool,
+}
+
+impl Default for WgpuSettings {
+    fn default() -> Self {
+        Self::from_env()
+    }
+}
+
+impl WgpuSettings {
+    pub const BACKEND_VAR: &'static str = "FLAT_BACKEND";
+    pub const POWER_PREFERENCE_VAR: &'static str = "FLAT_POWER_PREF";
+
+    /// Without reading the environment
+    pub fn new() -> Self {
+        Self {
+            backends: wgpu::Backends::all(),
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            force_fallback_adapter: false,
+            required_features: wgpu::Features::empty(),
+            limits_preset: LimitsPreset::Default,
+            prefer_hdr: false,
+        }
+    }
+
+    pub fn from_env() -> Self {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    /// [`WgpuSettings::new`] overridden by the variables `var` returns
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        let mut settings = Self::new();
+        if let Some(value) = var(Self::BACKEND_VAR) {
+            match parse_backends(&value) {
+                Some(backends) => {
+                    settings.backends = backends;
+                    // NOTE: GL adapters do not reach the default limits
+                    if backends == wgpu::Backends::GL {
+                        settings.limits_preset = LimitsPreset::Downlevel;
+                    }
+                }
+                None => log::warn!(
+                    "Unknown {}={:?}, expected vulkan, dx12, dx11, metal or gl, using all backends",
+                    Self::BACKEND_VAR,
+                    value
+                ),
+            }
+        }
+        if let Some(value) = var(Self::POWER_PREFERENCE_VAR) {
+            match parse_power_preference(&value) {
+                Some(power_preference) => settings.power_preference = power_preference,
+                None => log::warn!(
+                    "Unknown {}={:?}, expected low or high, using {:?}",
+                    Self::POWER_PREFERENCE_VAR,
+                    value,
+                    settings.power_preference
+                ),
+            }
+        }
+        settings
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitsPreset {
+    /// `wgpu::Limits::default()`, the WebGL2 limits on wasm
+    Default,
+    /// `wgpu::Limits::downlevel_defaults()`, for older GPUs and GL
+    Downlevel,
+    /// `wgpu::Limits::downlevel_webgl2_defaults()`
+    Webgl2,
+    /// Whatever the adapter supports
+    Adapter,
+}
+
+impl LimitsPreset {
+    pub fn limits(&self, adapter: &wgpu::Adapter) -> wgpu::Limits {
+        match self {
+            LimitsPreset::Default if cfg!(target_arch = "wasm32") => {
+                wgpu::Limits::downlevel_webgl2_defaults()
+            }
+            LimitsPreset::Default => wgpu::Limits::default(),
+            LimitsPreset::Downlevel => wgpu::Limits::downlevel_defaults(),
+            LimitsPreset::Webgl2 => wgpu::Limits::downlevel_webgl2_defaults(),
+            LimitsPreset::Adapter => adapter.limits(),
+        }
+    }
+}
+
+/// Case insensitive, `None` for unknown names
+pub fn parse_backends(value: &str) -> Option<wgpu::Backends> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "vulkan" | "vk" => Some(wgpu::Backends::VULKAN),
+        "dx12" | "d3d12" => Some(wgpu::Backends::DX12),
+        "dx11" | "d3d11" => Some(wgpu::Backends::DX11),
+        "metal" | "mtl" => Some(wgpu::Backends::METAL),
+        "gl" | "gles" | "opengl" => Some(wgpu::Backends::GL),
+        _ => None,
+    }
+}
+
+/// Case insensitive, `None` for unknown names
+pub fn parse_power_preference(value: &str) -> Option<wgpu::PowerPreference> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "low" => Some(wgpu::PowerPreference::LowPower),
+        "high" => Some(wgpu::PowerPreference::HighPerformance),
+        _ => None,
+    }
+}
+
+pub fn backend_bit(backend: wgpu::Backend) -> wgpu::Backends {
+    match backend {
+        wgpu::Backend::Empty => wgpu::Backends::empty(),
+        wgpu::Backend::Vulkan => wgpu::Backends::VULKAN,
+        wgpu::Backend::Metal => wgpu::Backends::METAL,
+        wgpu::Backend::Dx12 => wgpu::Backends::DX12,
+        wgpu::Backend::Dx11 => wgpu::Backends::DX11,
+        wgpu::Backend::Gl => wgpu::Backends::GL,
+        wgpu::Backend::BrowserWebGpu => wgpu::Backends::BROWSER_WEBGPU,
+    }
+}
+
+/// The format to configure a surface with, from the formats it supports. The sRGB
+/// formats the colors of the crate assume come first, `Rgba16Float` before them with
+/// `prefer_hdr`. Anything else is logged, preferably another sRGB or 8 bit format.
+pub fn negotiate_surface_format(
+    supported: &[wgpu::TextureFormat],
+    prefer_hdr: bool,
+) -> wgpu::TextureFormat {
+    use wgpu::TextureFormat::{
+        Bgra8Unorm, Bgra8UnormSrgb, Rgba16Float, Rgba8Unorm, Rgba8UnormSrgb,
+    };
+
+    if prefer_hdr {
+        if supported.contains(&Rgba16Float) {
+            return Rgba16Float;
+        }
+        log::warn!("The surface has no Rgba16Float format, HDR output is disabled");
+    }
+    if let Some(&format) = [Bgra8UnormSrgb, Rgba8UnormSrgb]
+        .iter()
+        .find(|format| supported.contains(format))
+    {
+        return format;
+    }
+    let fallback = supported
+        .iter()
+        .find(|format| format.describe().srgb)
+        .or_else(|| {
+            [Bgra8Unorm, Rgba8Unorm]
+                .iter()
+                .find(|format| supported.contains(format))
+        })
+        .or_else(|| supported.first())
+        .copied()
+        .unwrap_or(Bgra8UnormSrgb);
+    log::warn!(
+        "The surface supports none of the 8 bit sRGB formats, using {:?} of {:?}",
+        fallback,
+        supported
+    );
+    fallback
+}
+
+/// `requested` if the surface supports it, Fifo otherwise, which every surface does
+pub fn negotiate_present_mode(
+    supported: &[wgpu::PresentMode],
+    requested: wgpu::PresentMode,
+) -> wgpu::PresentMode {
+    if supported.contains(&requested) {
+        requested
+    } else {
+        log::warn!(
+            "Present mode {:?} is not supported by the surface, falling back to Fifo",
+            requested
+        );
+        wgpu::PresentMode::Fifo
+    }
+}
+
+/// Indices of `adapters` from the most to the least preferred, the ones `settings` rule
+/// out are left out. Equally ranked adapters keep their order
+pub fn rank_adapters(adapters: &[wgpu::AdapterInfo], settings: &WgpuSettings) -> Vec<usize> {
+    let rank = |device_type: wgpu::DeviceType| match (settings.power_preference, device_type) {
+        (wgpu::PowerPreference::LowPower, wgpu::DeviceType::IntegratedGpu) => 0,
+        (wgpu::PowerPreference::LowPower, wgpu::DeviceType::DiscreteGpu) => 1,
+        (_, wgpu::DeviceType::DiscreteGpu) => 0,
+        (_, wgpu::DeviceType::IntegratedGpu) => 1,
+        (_, wgpu::DeviceType::VirtualGpu) => 2,
+        (_, wgpu::DeviceType::Cpu) => 3,
+        (_, wgpu::DeviceType::Other) => 4,
+    };
+
+    let mut ranked: Vec<usize> = adapters
+        .iter()
+        .enumerate()
+        .filter(|(_, info)| settings.backends.contains(backend_bit(info.backend)))
+        .filter(|(_, info)| {
+            !settings.force_fallback_adapter || info.device_type == wgpu::DeviceType::Cpu
+        })
+        .map(|(i, _)| i)
+        .collect();
+    ranked.sort_by_key(|&i| rank(adapters[i].device_type));
+    ranked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        negotiate_present_mode, negotiate_surface_format, parse_backends, rank_adapters,
+        LimitsPreset, WgpuSettings,
+    };
+
+    fn vars<'a>(pairs: &'a [(&'a str, &'a str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        move |name| {
+            pairs
+                .iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.to_string())
+        }
+    }
+
+    fn adapter(
+        name: &str,
+        backend: wgpu::Backend,
+        device_type: wgpu::DeviceType,
+    ) -> wgpu::AdapterInfo {
+        wgpu::AdapterInfo {
+            name: name.to_string(),
+            vendor: 0,
+            device: 0,
+            device_type,
+            backend,
+        }
+    }
+
+    #[test]
+    fn env_overrides_the_defaults() {
+        assert_eq!(WgpuSettings::from_vars(vars(&[])), WgpuSettings::new());
+
+        let settings = WgpuSettings::from_vars(vars(&[
+            ("FLAT_BACKEND", "DX12"),
+            ("FLAT_POWER_PREF", "low"),
+        ]));
+        assert_eq!(settings.backends, wgpu::Backends::DX12);
+        assert_eq!(settings.power_preference, wgpu::PowerPreference::LowPower);
+        assert_eq!(settings.limits_preset, LimitsPreset::Default);
+
+        let settings = WgpuSettings::from_vars(vars(&[("FLAT_BACKEND", " gl ")]));
+        assert_eq!(settings.backends, wgpu::Backends::GL);
+        assert_eq!(settings.limits_preset, LimitsPreset::Downlevel);
+    }
+
+    #[test]
+    fn unknown_env_values_fall_back() {
+        let settings = WgpuSettings::from_vars(vars(&[
+            ("FLAT_BACKEND", "directx"),
+            ("FLAT_POWER_PREF", "medium"),
+        ]));
+        assert_eq!(settings, WgpuSettings::new());
+        assert_eq!(parse_backends(""), None);
+    }
+
+    #[test]
+    fn adapters_are_ranked_by_power_preference() {
+        let adapters = [
+            adapter("llvmpipe", wgpu::Backend::Vulkan, wgpu::DeviceType::Cpu),
+            adapter(
+                "Intel",
+                wgpu::Backend::Vulkan,
+                wgpu::DeviceType::IntegratedGpu,
+            ),
+            adapter(
+                "NVIDIA",
+                wgpu::Backend::Vulkan,
+                wgpu::DeviceType::DiscreteGpu,
+            ),
+            adapter("NVIDIA", wgpu::Backend::Dx12, wgpu::DeviceType::DiscreteGpu),
+        ];
+
+        let mut settings = WgpuSettings::new();
+        assert_eq!(rank_adapters(&adapters, &settings), vec![2, 3, 1, 0]);
+
+        settings.power_preference = wgpu::PowerPreference::LowPower;
+        assert_eq!(rank_adapters(&adapters, &settings), vec![1, 2, 3, 0]);
+
+        settings.backends = wgpu::Backends::DX12;
+        assert_eq!(rank_adapters(&adapters, &settings), vec![3]);
+
+        settings.backends = wgpu::Backends::all();
+        settings.force_fallback_adapter = true;
+        assert_eq!(rank_adapters(&adapters, &settings), vec![0]);
+
+        assert!(rank_adapters(&[], &settings).is_empty());
+    }
+
+    #[test]
+    fn surface_formats_prefer_srgb() {
+        use wgpu::TextureFormat::*;
+
+        // in the order the backends typically report them
+        let vulkan = [Bgra8Unorm, Bgra8UnormSrgb, Rgba16Float, Rgb10a2Unorm];
+        let dx12 = [Rgba16Float, Bgra8Unorm, Rgb10a2Unorm];
+        let metal = [Bgra8Unorm, Bgra8UnormSrgb, Rgba16Float, Rgb10a2Unorm];
+        let gl = [Rgba8UnormSrgb, Rgba8Unorm];
+
+        assert_eq!(negotiate_surface_format(&vulkan, false), Bgra8UnormSrgb);
+        assert_eq!(negotiate_surface_format(&metal, false), Bgra8UnormSrgb);
+        assert_eq!(negotiate_surface_format(&gl, false), Rgba8UnormSrgb);
+        // no srgb format at all, post processing encodes for 8 bit ones
+        assert_eq!(negotiate_surface_format(&dx12, false), Bgra8Unorm);
+        assert_eq!(
+            negotiate_surface_format(&[Rgb10a2Unorm], false),
+            Rgb10a2Unorm
+        );
+
+        assert_eq!(negotiate_surface_format(&vulkan, true), Rgba16Float);
+        assert_eq!(negotiate_surface_format(&dx12, true), Rgba16Float);
+        // without a float format hdr falls back to srgb
+        assert_eq!(negotiate_surface_format(&gl, true), Rgba8UnormSrgb);
+        assert_eq!(negotiate_surface_format(&[], false), Bgra8UnormSrgb);
+    }
+
+    #[test]
+    fn unsupported_present_modes_fall_back_to_fifo() {
+        use wgpu::PresentMode::*;
+
+        assert_eq!(
+            negotiate_present_mode(&[Fifo, Immediate], Immediate),
+            Immediate
+        );
+        assert_eq!(negotiate_present_mode(&[Fifo], Mailbox), Fifo);
+    }
+}
@@ -0,0 +1,297 @@
+use std::{collections::HashMap, sync::Arc};
+
+use bevy_ecs::{
+    prelude::Component,
+    query::With,
+    system::{Query, Res, ResMut},
+};
+
+use crate::{
+    light::DirectionalLight,
+    texture,
+    util::{Refer, Store},
+};
+
+use super::{
+    draw_assembly,
+    mesh::GpuMesh,
+    resolve_mesh,
+    resource::{
+        bind::{Binding, BindingLayoutEntry, BindingSet, Uniform},
+        buffer::{InstanceRaw, InstanceUnit},
+    },
+    set_mesh_buffers, InstanceData, WithMesh,
+};
+
+#[derive(Component)]
+pub struct ShadowCaster;
+
+pub struct ShadowSettings {
+    pub size: u32,
+    // rasterizer bias applied in the depth-only pass
+    pub depth_bias_constant: i32,
+    pub depth_bias_slope_scale: f32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            size: 2048,
+            depth_bias_constant: 2,
+            depth_bias_slope_scale: 2.0,
+        }
+    }
+}
+
+pub struct DepthTextureBinding<'a>(pub &'a wgpu::TextureView);
+impl Binding for DepthTextureBinding<'_> {
+    fn get_layout_entry(&self) -> BindingLayoutEntry {
+        BindingLayoutEntry {
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Depth,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        }
+    }
+
+    fn get_resource<'a>(&'a self) -> wgpu::BindingResource<'a> {
+        wgpu::BindingResource::TextureView(self.0)
+    }
+}
+
+pub struct ComparisonSamplerBinding<'a>(pub &'a wgpu::Sampler);
+impl Binding for ComparisonSamplerBinding<'_> {
+    fn get_layout_entry(&self) -> BindingLayoutEntry {
+        BindingLayoutEntry {
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+            count: None,
+        }
+    }
+
+    fn get_resource<'a>(&'a self) -> wgpu::BindingResource<'a> {
+        wgpu::BindingResource::Sampler(self.0)
+    }
+}
+
+pub struct ShadowMap {
+    pub settings: ShadowSettings,
+    pub depth: texture::Texture,
+    pub light_uniform: Uniform<DirectionalLight>,
+    module: wgpu::ShaderModule,
+    light_bind_group_layout: wgpu::BindGroupLayout,
+    light_bind_group: wgpu::BindGroup,
+    // (array_stride, instanced)
+    pipelines: HashMap<(wgpu::BufferAddress, bool), wgpu::RenderPipeline>,
+}
+
+impl ShadowMap {
+    const POSITION_ONLY: &'static [wgpu::VertexAttribute] =
+        &wgpu::vertex_attr_array![0 => Float32x3];
+    const INSTANCED_ENTRY_POINT: &'static str = "vs_instanced";
+
+    pub fn new(device: &wgpu::Device, settings: ShadowSettings) -> Self {
+        let depth = texture::Texture::create_depth_texture_sized(
+            device,
+            settings.size,
+            settings.size,
+            "Shadow Map",
+        );
+        let light_uniform: Uniform<DirectionalLight> = Uniform::new_default(
+            device,
+            wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+        );
+
+        let light_set = &light_uniform;
+        let light_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Shadow Light Bind Group Layout"),
+                entries: &light_set.layout_desc().entries,
+            });
+        let light_bind_group = light_set.into_bind_group(device);
+
+        let module = device.create_shader_module(wgpu::include_wgsl!("../../res/shadow.wgsl"));
+
+        Self {
+            settings,
+            depth,
+            light_uniform,
+            module,
+            light_bind_group_layout,
+            light_bind_group,
+            pipelines: Default::default(),
+        }
+    }
+
+    fn receiver_set(
+        &self,
+    ) -> (
+        &Uniform<DirectionalLight>,
+        DepthTextureBinding<'_>,
+        ComparisonSamplerBinding<'_>,
+    ) {
+        (
+            &self.light_uniform,
+            DepthTextureBinding(&self.depth.view),
+            ComparisonSamplerBinding(&self.depth.sampler),
+        )
+    }
+
+    /// Layout of the `ShadowReceiver` group: light uniform, shadow map, comparison sampler
+    pub fn receiver_layout(&self, device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let (light, depth, sampler) = self.receiver_set();
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Shadow Receiver Bind Group Layout"),
+            entries: &(light, &depth, &sampler).layout_desc().entries,
+        })
+    }
+
+    /// Builds the `ShadowReceiver` bind group and returns its key in the store
+    pub fn create_receiver_bind_group(
+        &self,
+        device: &wgpu::Device,
+        bind_groups: &mut Store<wgpu::BindGroup>,
+    ) -> usize {
+        let (light, depth, sampler) = self.receiver_set();
+        bind_groups.insert((light, &depth, &sampler).into_bind_group(device))
+    }
+
+    fn prepare_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        array_stride: wgpu::BufferAddress,
+        instanced: bool,
+    ) {
+        if self.pipelines.contains_key(&(array_stride, instanced)) {
+            return;
+        }
+
+        let mut buffers = vec![wgpu::VertexBufferLayout {
+            array_stride,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: Self::POSITION_ONLY,
+        }];
+        if instanced {
+            buffers.push(InstanceRaw::layout());
+        }
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow Pipeline Layout"),
+            bind_group_layouts: &[&self.light_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Shadow Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &self.module,
+                entry_point: if instanced {
+                    Self::INSTANCED_ENTRY_POINT
+                } else {
+                    super::resource::shader::Shader::VERTEX_ENTRY_POINT
+                },
+                buffers: &buffers,
+            },
+            fragment: None,
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState {
+                    constant: self.settings.depth_bias_constant,
+                    slope_scale: self.settings.depth_bias_slope_scale,
+                    clamp: 0.0,
+                },
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        self.pipelines.insert((array_stride, instanced), pipeline);
+    }
+
+    pub fn record_pass<'a>(
+        &'a self,
+        encoder: &mut wgpu::CommandEncoder,
+        casters: impl Iterator<Item = (&'a GpuMesh, Option<&'a InstanceData>)>,
+    ) {
+        let mut shadow_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Shadow Pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        shadow_pass.set_bind_group(0, &self.light_bind_group, &[]);
+
+        for (mesh, instance) in casters {
+            if mesh.primitive_topology != wgpu::PrimitiveTopology::TriangleList {
+                continue;
+            }
+            let key = (mesh.vertex_buffer_layout.array_stride, instance.is_some());
+            let pipeline = match self.pipelines.get(&key) {
+                Some(pipeline) => pipeline,
+                None => continue,
+            };
+            shadow_pass.set_pipeline(pipeline);
+
+            let instance_count = set_mesh_buffers(&mut shadow_pass, mesh, instance);
+            draw_assembly(&mut shadow_pass, mesh, instance_count);
+        }
+    }
+}
+
+/// Syncs the light uniform and creates the depth-only pipelines casters need, in
+/// `RenderStage::Prepare` so the main pass draws the casters of the same frame
+pub fn prepare_shadow_map_system(
+    device: Option<Res<Arc<wgpu::Device>>>,
+    queue: Option<Res<wgpu::Queue>>,
+    light: Option<Res<DirectionalLight>>,
+    shadow_map: Option<ResMut<ShadowMap>>,
+    meshes: Res<Store<GpuMesh>>,
+    casters: Query<
+        (Option<&GpuMesh>, Option<&Refer<GpuMesh>>, Option<&InstanceData>),
+        (With<ShadowCaster>, WithMesh),
+    >,
+) {
+    let (device, queue, light, mut shadow_map) = match (device, queue, light, shadow_map) {
+        (Some(device), Some(queue), Some(light), Some(shadow_map)) => {
+            (device, queue, light, shadow_map)
+        }
+        _ => return,
+    };
+
+    let shadow_map = &mut *shadow_map;
+    shadow_map.light_uniform.update(&light);
+    shadow_map.light_uniform.sync_buffer(&queue);
+
+    for (owned, shared, instance) in casters.iter() {
+        let mesh = match resolve_mesh(owned, shared, &meshes) {
+            Some(mesh) => mesh,
+            None => continue,
+        };
+        shadow_map.prepare_pipeline(
+            &device,
+            mesh.vertex_buffer_layout.array_stride,
+            instance.is_some(),
+        );
+    }
+}
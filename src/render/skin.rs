@@ -0,0 +1,478 @@
+use std::fmt;
+
+use bevy_ecs::prelude::Component;
+use bytemuck::{Pod, Zeroable};
+use cgmath::{Matrix4, Point3, SquareMatrix, Vector4};
+use repr_trait::C;
+
+use crate::{camera::Camera, color::Color, transform::Transform};
+
+use super::{
+    material::{Material, ObjectUniforms},
+    mesh::Mesh,
+    resource::{
+        bind::{AsBindingSet, GpuUniform, Uniform, UniformSyncBatcher, UpdateGpuUniform},
+        buffer::{MeshVertex, VertexSkinned},
+    },
+};
+
+/// Joints of a [`Skeleton`], the length of the palette in `res/common/skinning.wgsl`
+pub const MAX_JOINTS: usize = 64;
+/// Joints deforming one vertex
+pub const MAX_INFLUENCES: usize = 4;
+const WEIGHT_TOLERANCE: f32 = 1e-3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SkinError {
+    TooManyInfluences {
+        count: usize,
+    },
+    /// Weights have to be non-negative and sum to one
+    UnnormalizedWeights {
+        vertex: usize,
+        sum: f32,
+    },
+    JointOutOfRange {
+        vertex: usize,
+        joint: u16,
+        joint_count: usize,
+    },
+    TooManyJoints {
+        count: usize,
+    },
+    /// Parents come before their children, so the palette is computed in one pass
+    ParentNotBefore {
+        joint: usize,
+        parent: usize,
+    },
+}
+
+impl fmt::Display for SkinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkinError::TooManyInfluences { count } => write!(
+                f,
+                "a vertex has {} joint influences, at most {} are supported",
+                count, MAX_INFLUENCES
+            ),
+            SkinError::UnnormalizedWeights { vertex, sum } => write!(
+                f,
+                "the joint weights of vertex {} sum to {} instead of 1 or are negative",
+                vertex, sum
+            ),
+            SkinError::JointOutOfRange {
+                vertex,
+                joint,
+                joint_count,
+            } => write!(
+                f,
+                "vertex {} refers to joint {} of a skeleton with {} joints",
+                vertex, joint, joint_count
+            ),
+            SkinError::TooManyJoints { count } => write!(
+                f,
+                "the skeleton has {} joints, at most {} are supported",
+                count, MAX_JOINTS
+            ),
+            SkinError::ParentNotBefore { joint, parent } => {
+                write!(f, "joint {} comes before its parent {}", joint, parent)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SkinError {}
+
+impl VertexSkinned {
+    /// `influences` are (joint, weight) pairs, the unused ones get zero weight
+    pub fn new(
+        position: [f32; 3],
+        tex_coords: [f32; 2],
+        normal: [f32; 3],
+        influences: &[(u16, f32)],
+    ) -> Result<Self, SkinError> {
+        if influences.len() > MAX_INFLUENCES {
+            return Err(SkinError::TooManyInfluences {
+                count: influences.len(),
+            });
+        }
+        let mut vertex = Self {
+            position,
+            tex_coords,
+            normal,
+            joints: [0; MAX_INFLUENCES],
+            weights: [0.0; MAX_INFLUENCES],
+        };
+        for (i, (joint, weight)) in influences.iter().enumerate() {
+            vertex.joints[i] = *joint;
+            vertex.weights[i] = *weight;
+        }
+        Ok(vertex)
+    }
+}
+
+impl Mesh<VertexSkinned> {
+    /// Every vertex has normalized weights and only refers to the `joint_count` joints
+    /// of its skeleton, call before creating the `GpuMesh`
+    pub fn validate_skin(&self, joint_count: usize) -> Result<(), SkinError> {
+        for (i, vertex) in self.get_vertices().iter().enumerate() {
+            let sum: f32 = vertex.weights.iter().sum();
+            if (sum - 1.0).abs() > WEIGHT_TOLERANCE || vertex.weights.iter().any(|w| *w < 0.0) {
+                return Err(SkinError::UnnormalizedWeights { vertex: i, sum });
+            }
+            let out_of_range = vertex
+                .joints
+                .iter()
+                .zip(vertex.weights)
+                .find(|(joint, weight)| *weight > 0.0 && **joint as usize >= joint_count);
+            if let Some((joint, _)) = out_of_range {
+                return Err(SkinError::JointOutOfRange {
+                    vertex: i,
+                    joint: *joint,
+                    joint_count,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Joint {
+    /// Index in the skeleton, before this joint
+    pub parent: Option<usize>,
+    /// From mesh space to the joint in the bind pose
+    pub inverse_bind: Matrix4<f32>,
+    /// Relative to the parent, posed by the app
+    pub local: Matrix4<f32>,
+}
+
+/// Joints of a skinned mesh, the palette of [`Skeleton::compute_palette`] is what the
+/// vertices are blended with. Animation is up to the app, it poses the `local` matrices.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Skeleton {
+    joints: Vec<Joint>,
+}
+
+impl Skeleton {
+    pub fn new(joints: Vec<Joint>) -> Result<Self, SkinError> {
+        if joints.len() > MAX_JOINTS {
+            return Err(SkinError::TooManyJoints {
+                count: joints.len(),
+            });
+        }
+        for (i, joint) in joints.iter().enumerate() {
+            if let Some(parent) = joint.parent.filter(|parent| *parent >= i) {
+                return Err(SkinError::ParentNotBefore { joint: i, parent });
+            }
+        }
+        Ok(Self { joints })
+    }
+
+    /// Joints as (parent, local) in the bind pose, their inverse bind matrices are
+    /// computed from it
+    pub fn from_bind_pose(joints: Vec<(Option<usize>, Matrix4<f32>)>) -> Result<Self, SkinError> {
+        let mut skeleton = Self::new(
+            joints
+                .into_iter()
+                .map(|(parent, local)| Joint {
+                    parent,
+                    inverse_bind: Matrix4::identity(),
+                    local,
+                })
+                .collect(),
+        )?;
+        let mut bind = vec![Matrix4::identity(); skeleton.len()];
+        skeleton.world_matrices(&mut bind);
+        for (joint, bind) in skeleton.joints.iter_mut().zip(bind) {
+            // NOTE: a singular bind pose is left without correction
+            joint.inverse_bind = bind.invert().unwrap_or_else(Matrix4::identity);
+        }
+        Ok(skeleton)
+    }
+
+    pub fn joints(&self) -> &[Joint] {
+        &self.joints
+    }
+
+    /// For posing, parents are fixed once the skeleton is built
+    pub fn joint_mut(&mut self, joint: usize) -> Option<&mut Joint> {
+        self.joints.get_mut(joint)
+    }
+
+    pub fn len(&self) -> usize {
+        self.joints.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.joints.is_empty()
+    }
+
+    /// The mesh space matrix of every joint in the current pose relative to the bind
+    /// pose, identities in the bind pose. Joints past the end of `palette` are skipped
+    pub fn compute_palette(&self, palette: &mut [Matrix4<f32>]) {
+        self.world_matrices(palette);
+        for (matrix, joint) in palette.iter_mut().zip(&self.joints) {
+            *matrix = *matrix * joint.inverse_bind;
+        }
+    }
+
+    fn world_matrices(&self, world: &mut [Matrix4<f32>]) {
+        for i in 0..self.joints.len().min(world.len()) {
+            let joint = &self.joints[i];
+            world[i] = match joint.parent {
+                Some(parent) => world[parent] * joint.local,
+                None => joint.local,
+            };
+        }
+    }
+}
+
+/// What `skin` of `res/common/skinning.wgsl` computes for the position, on the CPU
+pub fn skin_position(palette: &[Matrix4<f32>], vertex: &VertexSkinned) -> Point3<f32> {
+    let position = Point3::from(vertex.position).to_homogeneous();
+    let skinned = vertex
+        .joints
+        .iter()
+        .zip(vertex.weights)
+        .fold(Vector4::new(0.0, 0.0, 0.0, 0.0), |sum, (joint, weight)| {
+            sum + palette[*joint as usize] * position * weight
+        });
+    Point3::from_homogeneous(skinned)
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, C, Pod, Zeroable)]
+pub struct JointPaletteUniform {
+    pub joints: [[[f32; 4]; 4]; MAX_JOINTS],
+}
+impl GpuUniform for JointPaletteUniform {}
+impl Default for JointPaletteUniform {
+    fn default() -> Self {
+        Self {
+            joints: [Matrix4::identity().into(); MAX_JOINTS],
+        }
+    }
+}
+
+impl UpdateGpuUniform for Skeleton {
+    type GU = JointPaletteUniform;
+
+    fn update_uniform(&self, gpu_uniform: &mut Self::GU) {
+        let mut palette = [Matrix4::identity(); MAX_JOINTS];
+        self.compute_palette(&mut palette);
+        for (joint, matrix) in gpu_uniform.joints.iter_mut().zip(palette) {
+            *joint = matrix.into();
+        }
+    }
+}
+
+/// Joint matrices of a [`Skeleton`] bound for `res/common/skinning.wgsl`
+pub type JointPalette = Uniform<Skeleton>;
+
+/// A [`ColorMaterial`](super::material::ColorMaterial) deformed by its skeleton, drawn
+/// with a `Mesh<VertexSkinned>`. Not added by default, see
+/// [`AddMaterial`](super::material::AddMaterial).
+#[derive(Component)]
+pub struct SkinnedColorMaterial {
+    pub color: Color,
+    pub skeleton: Skeleton,
+}
+
+pub struct GpuSkinnedColorMaterial {
+    pub object: ObjectUniforms,
+    pub palette: JointPalette,
+    pub color: Uniform<Color>,
+}
+
+impl<'a> AsBindingSet<'a> for GpuSkinnedColorMaterial {
+    type Set = (
+        &'a Uniform<Camera>,
+        &'a Uniform<Transform>,
+        &'a JointPalette,
+        &'a Uniform<Color>,
+    );
+
+    fn as_binding_set(&'a self) -> Self::Set {
+        (
+            &self.object.camera,
+            &self.object.model,
+            &self.palette,
+            &self.color,
+        )
+    }
+}
+
+impl Material for SkinnedColorMaterial {
+    type Gpu = GpuSkinnedColorMaterial;
+
+    fn shader_path() -> &'static str {
+        "skinned_color_material.wgsl"
+    }
+
+    fn vertex_layouts() -> Vec<wgpu::VertexBufferLayout<'static>> {
+        vec![VertexSkinned::layout()]
+    }
+
+    fn prepare(&self, device: &wgpu::Device) -> Self::Gpu {
+        GpuSkinnedColorMaterial {
+            object: ObjectUniforms::new(device),
+            palette: Uniform::new_default(device, wgpu::ShaderStages::VERTEX),
+            color: Uniform::new_default(device, wgpu::ShaderStages::FRAGMENT),
+        }
+    }
+
+    fn update(
+        &self,
+        gpu: &mut Self::Gpu,
+        uniforms: &mut UniformSyncBatcher,
+        camera: &Camera,
+        transform: &Transform,
+    ) {
+        gpu.object.update(uniforms, camera, transform);
+        gpu.palette.update(&self.skeleton);
+        uniforms.sync(&mut gpu.palette);
+        gpu.color.update(&self.color);
+        uniforms.sync(&mut gpu.color);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use cgmath::{Matrix4, Point3, Rad, SquareMatrix, Vector3};
+
+    use crate::render::{
+        mesh::Mesh,
+        resource::{
+            buffer::{MeshVertex, VertexSkinned},
+            preprocess::resolve_includes,
+            reflect::ShaderReflection,
+            shader_lib::ShaderInclude,
+        },
+    };
+
+    use super::{skin_position, Joint, Skeleton, SkinError, MAX_JOINTS};
+
+    fn assert_close(a: Point3<f32>, b: Point3<f32>) {
+        assert!(
+            (a.x - b.x).abs() < 1e-4 && (a.y - b.y).abs() < 1e-4 && (a.z - b.z).abs() < 1e-4,
+            "{:?} != {:?}",
+            a,
+            b
+        );
+    }
+
+    // a root at the origin and a child one unit up
+    fn arm() -> Skeleton {
+        Skeleton::from_bind_pose(vec![
+            (None, Matrix4::identity()),
+            (Some(0), Matrix4::from_translation(Vector3::unit_y())),
+        ])
+        .unwrap()
+    }
+
+    fn vertex(influences: &[(u16, f32)]) -> VertexSkinned {
+        VertexSkinned::new([0.0, 2.0, 0.0], [0.0; 2], [0.0; 3], influences).unwrap()
+    }
+
+    #[test]
+    fn posed_joints_deform_the_vertices() {
+        let mut skeleton = arm();
+        let mut palette = vec![Matrix4::from_scale(2.0); 2];
+        skeleton.compute_palette(&mut palette);
+        assert_eq!(palette, vec![Matrix4::identity(); 2]);
+
+        let tip = vertex(&[(1, 1.0)]);
+        let blended = vertex(&[(0, 0.5), (1, 0.5)]);
+        assert_close(skin_position(&palette, &tip), Point3::new(0.0, 2.0, 0.0));
+
+        // bending the child swings the tip around the child joint
+        let rotation = Matrix4::from_angle_z(Rad(FRAC_PI_2));
+        skeleton.joint_mut(1).unwrap().local =
+            Matrix4::from_translation(Vector3::unit_y()) * rotation;
+        skeleton.compute_palette(&mut palette);
+        assert_close(skin_position(&palette, &tip), Point3::new(-1.0, 1.0, 0.0));
+        // half of it follows the root, which stays
+        assert_close(
+            skin_position(&palette, &blended),
+            Point3::new(-0.5, 1.5, 0.0),
+        );
+
+        // rotating the root carries the child along
+        skeleton.joint_mut(0).unwrap().local = rotation;
+        skeleton.joint_mut(1).unwrap().local = Matrix4::from_translation(Vector3::unit_y());
+        skeleton.compute_palette(&mut palette);
+        assert_close(skin_position(&palette, &tip), Point3::new(-2.0, 0.0, 0.0));
+        assert_close(
+            skin_position(&palette, &blended),
+            Point3::new(-2.0, 0.0, 0.0),
+        );
+    }
+
+    #[test]
+    fn invalid_skins_are_reported() {
+        assert_eq!(
+            VertexSkinned::new([0.0; 3], [0.0; 2], [0.0; 3], &[(0, 0.2); 5]),
+            Err(SkinError::TooManyInfluences { count: 5 })
+        );
+
+        let mut mesh = Mesh::new(wgpu::PrimitiveTopology::TriangleList);
+        mesh.set_vertices(vec![vertex(&[(0, 1.0)]), vertex(&[(0, 0.5), (1, 0.25)])]);
+        assert_eq!(
+            mesh.validate_skin(2),
+            Err(SkinError::UnnormalizedWeights {
+                vertex: 1,
+                sum: 0.75
+            })
+        );
+        mesh.set_vertices(vec![vertex(&[(0, 0.5), (2, 0.5)])]);
+        assert_eq!(
+            mesh.validate_skin(2),
+            Err(SkinError::JointOutOfRange {
+                vertex: 0,
+                joint: 2,
+                joint_count: 2
+            })
+        );
+        assert_eq!(mesh.validate_skin(3), Ok(()));
+
+        let joint = |parent| Joint {
+            parent,
+            inverse_bind: Matrix4::identity(),
+            local: Matrix4::identity(),
+        };
+        assert_eq!(
+            Skeleton::new(vec![joint(Some(1)), joint(None)]),
+            Err(SkinError::ParentNotBefore {
+                joint: 0,
+                parent: 1
+            })
+        );
+        assert_eq!(
+            Skeleton::new(vec![joint(None); MAX_JOINTS + 1]),
+            Err(SkinError::TooManyJoints {
+                count: MAX_JOINTS + 1
+            })
+        );
+    }
+
+    #[test]
+    fn skinned_shader_matches_the_vertex_layout() {
+        let layout = VertexSkinned::layout();
+        assert_eq!(layout.array_stride, 56);
+        assert_eq!(layout.attributes[3].format, wgpu::VertexFormat::Uint16x4);
+        assert_eq!(layout.attributes[3].offset, 32);
+        assert_eq!(layout.attributes[4].offset, 40);
+
+        let source = resolve_includes(
+            "skinned_color_material.wgsl",
+            include_str!("../../res/skinned_color_material.wgsl"),
+            &ShaderInclude::files(),
+        )
+        .unwrap();
+        let reflection = ShaderReflection::from_wgsl(&source).unwrap();
+        reflection.check_vertex_buffers(&[layout]).unwrap();
+    }
+}
@@ -0,0 +1,199 @@
+use bevy_ecs::system::{Res, ResMut};
+
+use super::{
+    offscreen::OffscreenTarget,
+    postprocess::{PostProcessRenderer, PostProcessSettings},
+    resource::shader::ShaderTargets,
+    DepthConvention, DepthFormat,
+};
+
+/// Formats of the textures pipelines render to, `None` until the surface or the
+/// [`OffscreenTarget`] exists. Updated in `CoreStage::PreUpdate`, e.g. when the surface
+/// is configured with another format on a different adapter or monitor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RenderTargets {
+    /// Of the surface as [`negotiate_surface_format`](super::settings::negotiate_surface_format)
+    /// picked it, or of the [`OffscreenTarget`] of a headless app
+    pub surface_format: wgpu::TextureFormat,
+    /// Of the target the main pass renders to while post processing is enabled
+    pub hdr_format: wgpu::TextureFormat,
+    pub depth_format: DepthFormat,
+    pub depth_convention: DepthConvention,
+    pub post_process: bool,
+}
+
+impl RenderTargets {
+    pub fn new(
+        config: Option<&wgpu::SurfaceConfiguration>,
+        offscreen: Option<&OffscreenTarget>,
+        settings: Option<&PostProcessSettings>,
+        depth_format: DepthFormat,
+        depth_convention: DepthConvention,
+    ) -> Option<Self> {
+        let surface_format = match (config, offscreen) {
+            (Some(config), _) => config.format,
+            (None, Some(_)) => OffscreenTarget::FORMAT,
+            (None, None) => return None,
+        };
+        Some(Self {
+            surface_format,
+            hdr_format: PostProcessRenderer::HDR_FORMAT,
+            depth_format,
+            depth_convention,
+            post_process: settings.map_or(false, |settings| settings.enabled),
+        })
+    }
+
+    /// Format the main pass pipelines have to target
+    pub fn main_pass_format(&self) -> wgpu::TextureFormat {
+        if self.post_process {
+            self.hdr_format
+        } else {
+            self.surface_format
+        }
+    }
+
+    /// Of the surface, for passes drawing after post processing
+    pub fn color_target(&self, blend: Option<wgpu::BlendState>) -> wgpu::ColorTargetState {
+        Self::target(self.surface_format, blend)
+    }
+
+    pub fn hdr_target(&self, blend: Option<wgpu::BlendState>) -> wgpu::ColorTargetState {
+        Self::target(self.hdr_format, blend)
+    }
+
+    pub fn main_pass_target(&self, blend: Option<wgpu::BlendState>) -> wgpu::ColorTargetState {
+        Self::target(self.main_pass_format(), blend)
+    }
+
+    /// A single main pass target, the depth format and convention
+    pub fn shader_targets(
+        &self,
+        vertex_buffers: Vec<wgpu::VertexBufferLayout<'static>>,
+        blend: Option<wgpu::BlendState>,
+    ) -> ShaderTargets {
+        ShaderTargets {
+            vertex_buffers,
+            fragment_targets: vec![Some(self.main_pass_target(blend))],
+            depth_format: self.depth_format.0,
+            depth_convention: self.depth_convention,
+            ..Default::default()
+        }
+    }
+
+    fn target(
+        format: wgpu::TextureFormat,
+        blend: Option<wgpu::BlendState>,
+    ) -> wgpu::ColorTargetState {
+        wgpu::ColorTargetState {
+            format,
+            blend,
+            write_mask: wgpu::ColorWrites::ALL,
+        }
+    }
+}
+
+pub fn update_render_targets_system(
+    config: Option<Res<wgpu::SurfaceConfiguration>>,
+    offscreen: Option<Res<OffscreenTarget>>,
+    settings: Option<Res<PostProcessSettings>>,
+    (depth_format, depth_convention): (Res<DepthFormat>, Res<DepthConvention>),
+    mut targets: ResMut<Option<RenderTargets>>,
+) {
+    let updated = RenderTargets::new(
+        config.as_deref(),
+        offscreen.as_deref(),
+        settings.as_deref(),
+        *depth_format,
+        *depth_convention,
+    );
+    // NOTE: compared first, so change detection only fires on actual changes
+    if *targets != updated {
+        *targets = updated;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_ecs::{
+        schedule::{Stage, SystemStage},
+        world::World,
+    };
+
+    use crate::render::{postprocess::PostProcessSettings, DepthConvention, DepthFormat};
+
+    use super::{update_render_targets_system, RenderTargets};
+
+    fn config(format: wgpu::TextureFormat) -> wgpu::SurfaceConfiguration {
+        wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format,
+            width: 1,
+            height: 1,
+            present_mode: wgpu::PresentMode::Fifo,
+        }
+    }
+
+    #[test]
+    fn helpers_follow_a_reconfigured_surface() {
+        let mut world = World::new();
+        world.init_resource::<Option<RenderTargets>>();
+        world.init_resource::<DepthFormat>();
+        world.init_resource::<DepthConvention>();
+        world.insert_resource(PostProcessSettings {
+            enabled: false,
+            ..Default::default()
+        });
+        let mut stage = SystemStage::single(update_render_targets_system);
+
+        stage.run(&mut world);
+        assert_eq!(*world.resource::<Option<RenderTargets>>(), None);
+
+        world.insert_resource(config(wgpu::TextureFormat::Bgra8UnormSrgb));
+        stage.run(&mut world);
+        let targets = world.resource::<Option<RenderTargets>>().unwrap();
+        let blend = Some(wgpu::BlendState::ALPHA_BLENDING);
+        assert_eq!(
+            targets.color_target(blend).format,
+            wgpu::TextureFormat::Bgra8UnormSrgb
+        );
+        assert_eq!(targets.color_target(blend).blend, blend);
+        assert_eq!(targets.main_pass_format(), targets.surface_format);
+
+        // e.g. moved to a monitor of another adapter
+        world.resource_mut::<wgpu::SurfaceConfiguration>().format =
+            wgpu::TextureFormat::Rgba8UnormSrgb;
+        world.insert_resource(DepthFormat::STENCIL);
+        stage.run(&mut world);
+        let targets = world.resource::<Option<RenderTargets>>().unwrap();
+        assert_eq!(
+            targets.color_target(None).format,
+            wgpu::TextureFormat::Rgba8UnormSrgb
+        );
+        let shader_targets = targets.shader_targets(Vec::new(), None);
+        assert_eq!(
+            shader_targets.fragment_targets[0].as_ref().unwrap().format,
+            wgpu::TextureFormat::Rgba8UnormSrgb
+        );
+        assert_eq!(shader_targets.depth_format, DepthFormat::STENCIL.0);
+        assert_eq!(shader_targets.depth_convention, DepthConvention::Standard);
+
+        world.insert_resource(DepthConvention::ReverseZ);
+        stage.run(&mut world);
+        let targets = world.resource::<Option<RenderTargets>>().unwrap();
+        assert_eq!(
+            targets.shader_targets(Vec::new(), None).depth_convention,
+            DepthConvention::ReverseZ
+        );
+
+        // post processing moves the main pass to the HDR target, the surface stays
+        world.resource_mut::<PostProcessSettings>().enabled = true;
+        stage.run(&mut world);
+        let targets = world.resource::<Option<RenderTargets>>().unwrap();
+        assert_eq!(targets.main_pass_target(None), targets.hdr_target(None));
+        assert_eq!(
+            targets.color_target(None).format,
+            wgpu::TextureFormat::Rgba8UnormSrgb
+        );
+    }
+}
@@ -0,0 +1,246 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use bevy_ecs::system::{Res, ResMut};
+
+/// Passes timed by [`PassTimer`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimedPass {
+    DepthPrepass,
+    MainPass,
+}
+
+impl TimedPass {
+    const COUNT: usize = 2;
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimingSource {
+    /// GPU time of the pass, from timestamp queries
+    GpuTimestamps,
+    /// CPU time spent recording the pass, without `Features::TIMESTAMP_QUERY`
+    WallClock,
+}
+
+/// `None` for passes that did not run
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PassTimings {
+    pub depth_prepass: Option<Duration>,
+    pub main_pass: Option<Duration>,
+    pub source: Option<TimingSource>,
+}
+
+impl PassTimings {
+    fn new(durations: [Option<Duration>; TimedPass::COUNT], source: TimingSource) -> Self {
+        Self {
+            depth_prepass: durations[TimedPass::DepthPrepass.index()],
+            main_pass: durations[TimedPass::MainPass.index()],
+            source: Some(source),
+        }
+    }
+
+    /// `ticks` holds a begin and an end timestamp per pass, `period` is in nanoseconds
+    fn from_ticks(ticks: &[u64], recorded: [bool; TimedPass::COUNT], period: f32) -> Self {
+        let mut durations = [None; TimedPass::COUNT];
+        for (i, duration) in durations.iter_mut().enumerate() {
+            if recorded[i] {
+                let elapsed = ticks[2 * i + 1].saturating_sub(ticks[2 * i]);
+                *duration = Some(Duration::from_nanos(
+                    (elapsed as f64 * period as f64) as u64,
+                ));
+            }
+        }
+        Self::new(durations, TimingSource::GpuTimestamps)
+    }
+}
+
+/// Times the render passes of a frame, the results end up in
+/// [`RenderStats::pass_timings`](super::RenderStats::pass_timings).
+///
+/// Uses timestamp queries when the device has `Features::TIMESTAMP_QUERY`, read back a few
+/// frames later, and the wall-clock time of recording the pass otherwise.
+#[derive(Default)]
+pub struct PassTimer {
+    gpu: Option<GpuTimestamps>,
+    started: [Option<Instant>; TimedPass::COUNT],
+    elapsed: [Option<Duration>; TimedPass::COUNT],
+    // passes timed in the current frame
+    recorded: [bool; TimedPass::COUNT],
+    latest: PassTimings,
+}
+
+struct GpuTimestamps {
+    query_set: wgpu::QuerySet,
+    resolve: wgpu::Buffer,
+    readback: wgpu::Buffer,
+    // passes of the frame being read back, new timestamps are not resolved until it is done
+    in_flight: Option<[bool; TimedPass::COUNT]>,
+    // set by the map_async callback
+    mapped: Option<Arc<Mutex<Option<bool>>>>,
+}
+
+impl GpuTimestamps {
+    const SIZE: wgpu::BufferAddress = (2 * TimedPass::COUNT * std::mem::size_of::<u64>()) as u64;
+
+    fn new(device: &wgpu::Device) -> Self {
+        Self {
+            query_set: device.create_query_set(&wgpu::QuerySetDescriptor {
+                label: Some("Pass Timestamps"),
+                ty: wgpu::QueryType::Timestamp,
+                count: 2 * TimedPass::COUNT as u32,
+            }),
+            resolve: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Pass Timestamps Resolve"),
+                size: Self::SIZE,
+                usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            }),
+            readback: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Pass Timestamps Readback"),
+                size: Self::SIZE,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            }),
+            in_flight: None,
+            mapped: None,
+        }
+    }
+}
+
+impl PassTimer {
+    /// Called before the first timed pass of the frame
+    pub fn begin_frame(&mut self, device: &wgpu::Device) {
+        if self.gpu.is_none() && device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            self.gpu = Some(GpuTimestamps::new(device));
+        }
+        self.started = Default::default();
+        self.elapsed = Default::default();
+        self.recorded = Default::default();
+    }
+
+    pub fn begin(&mut self, encoder: &mut wgpu::CommandEncoder, pass: TimedPass) {
+        let i = pass.index();
+        match &self.gpu {
+            Some(gpu) => encoder.write_timestamp(&gpu.query_set, 2 * i as u32),
+            None => self.started[i] = Some(Instant::now()),
+        }
+    }
+
+    pub fn end(&mut self, encoder: &mut wgpu::CommandEncoder, pass: TimedPass) {
+        let i = pass.index();
+        match &self.gpu {
+            Some(gpu) => encoder.write_timestamp(&gpu.query_set, 2 * i as u32 + 1),
+            None => match self.started[i].take() {
+                Some(start) => self.elapsed[i] = Some(start.elapsed()),
+                None => return,
+            },
+        }
+        self.recorded[i] = true;
+    }
+
+    /// Called after the last timed pass, with the encoder it was recorded in
+    pub fn finish_frame(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let gpu = match &mut self.gpu {
+            Some(gpu) => gpu,
+            None => {
+                self.latest = PassTimings::new(self.elapsed, TimingSource::WallClock);
+                return;
+            }
+        };
+        if gpu.in_flight.is_some() || !self.recorded.contains(&true) {
+            return;
+        }
+        encoder.resolve_query_set(
+            &gpu.query_set,
+            0..2 * TimedPass::COUNT as u32,
+            &gpu.resolve,
+            0,
+        );
+        encoder.copy_buffer_to_buffer(&gpu.resolve, 0, &gpu.readback, 0, GpuTimestamps::SIZE);
+        gpu.in_flight = Some(self.recorded);
+    }
+
+    /// Called after the frame is submitted, maps the timestamps and reads them once mapped
+    pub fn read_back(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let gpu = match &mut self.gpu {
+            Some(gpu) => gpu,
+            None => return,
+        };
+        let recorded = match gpu.in_flight {
+            Some(recorded) => recorded,
+            None => return,
+        };
+
+        let mapped = gpu.mapped.get_or_insert_with(|| {
+            let mapped = Arc::new(Mutex::new(None));
+            let result = mapped.clone();
+            gpu.readback
+                .slice(..)
+                .map_async(wgpu::MapMode::Read, move |mapped| {
+                    *result.lock().unwrap() = Some(mapped.is_ok());
+                });
+            mapped
+        });
+        device.poll(wgpu::Maintain::Poll);
+        let mapped = match *mapped.lock().unwrap() {
+            Some(mapped) => mapped,
+            None => return,
+        };
+
+        if mapped {
+            let ticks: Vec<u64> =
+                bytemuck::cast_slice(&gpu.readback.slice(..).get_mapped_range()).to_vec();
+            gpu.readback.unmap();
+            self.latest = PassTimings::from_ticks(&ticks, recorded, queue.get_timestamp_period());
+        }
+        gpu.in_flight = None;
+        gpu.mapped = None;
+    }
+
+    /// The GPU timings lag a few frames behind
+    pub fn latest(&self) -> PassTimings {
+        self.latest
+    }
+}
+
+/// Runs after the frame is submitted
+pub fn read_pass_timings_system(
+    device: Option<Res<Arc<wgpu::Device>>>,
+    queue: Option<Res<wgpu::Queue>>,
+    mut timer: ResMut<PassTimer>,
+) {
+    if let (Some(device), Some(queue)) = (device, queue) {
+        timer.read_back(&device, &queue);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{PassTimings, TimingSource};
+
+    #[test]
+    fn timestamps_are_scaled_by_the_period() {
+        // depth prepass from 100 to 500 ticks, the main pass was not recorded
+        let timings = PassTimings::from_ticks(&[100, 500, 0, 0], [true, false], 2.5);
+        assert_eq!(
+            timings,
+            PassTimings {
+                depth_prepass: Some(Duration::from_nanos(1000)),
+                main_pass: None,
+                source: Some(TimingSource::GpuTimestamps),
+            }
+        );
+
+        // NOTE: some backends wrap or reorder timestamps, never underflow
+        let timings = PassTimings::from_ticks(&[0, 0, 700, 600], [false, true], 1.0);
+        assert_eq!(timings.main_pass, Some(Duration::ZERO));
+    }
+}
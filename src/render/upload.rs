@@ -0,0 +1,294 @@
+use std::{collections::VecDeque, sync::Arc};
+
+use bevy_ecs::system::{Res, ResMut};
+
+use crate::{
+    profiler::Profiler,
+    texture::{RawImage, Texture},
+};
+
+/// Uploads large data over several frames instead of all at once on the frame it loads.
+///
+/// At most [`budget`](UploadQueue::budget) bytes are written each frame by `upload_system`,
+/// in `RenderStage::Prepare`. Owners of the destination check [`UploadQueue::is_done`]
+/// before binding it, until then the contents are partially written.
+pub struct UploadQueue {
+    uploads: Uploads<UploadTarget>,
+}
+
+impl Default for UploadQueue {
+    fn default() -> Self {
+        Self {
+            uploads: Uploads::new(Self::DEFAULT_BUDGET),
+        }
+    }
+}
+
+impl UploadQueue {
+    pub const DEFAULT_BUDGET: u64 = 8 * 1024 * 1024;
+
+    /// Bytes per frame, at least one row or aligned chunk is written each frame
+    pub fn budget(&self) -> u64 {
+        self.uploads.budget
+    }
+
+    pub fn set_budget(&mut self, budget: u64) {
+        self.uploads.budget = budget;
+    }
+
+    /// `bytes` are padded to `COPY_BUFFER_ALIGNMENT`, the buffer needs `COPY_DST`
+    pub fn write_buffer(
+        &mut self,
+        buffer: Arc<wgpu::Buffer>,
+        offset: wgpu::BufferAddress,
+        mut bytes: Vec<u8>,
+    ) -> UploadId {
+        let align = wgpu::COPY_BUFFER_ALIGNMENT as usize;
+        bytes.resize((bytes.len() + align - 1) / align * align, 0);
+        self.uploads
+            .push(UploadTarget::Buffer { buffer, offset }, bytes, align)
+    }
+
+    /// The whole texture, `raw_img` must have the size and format it was created with
+    pub fn write_texture(&mut self, texture: Arc<Texture>, raw_img: &RawImage) -> UploadId {
+        let bytes_per_row = raw_img.bytes_per_row();
+        self.uploads.push(
+            UploadTarget::Texture {
+                texture,
+                bytes_per_row,
+                width: raw_img.dim.0,
+            },
+            raw_img.bytes.to_vec(),
+            bytes_per_row as usize,
+        )
+    }
+
+    pub fn is_done(&self, id: UploadId) -> bool {
+        self.uploads.is_done(id)
+    }
+
+    pub fn stats(&self) -> UploadStats {
+        self.uploads.stats()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct UploadId(u64);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UploadStats {
+    pub queued_bytes: u64,
+    /// Bytes written in the last frame
+    pub uploaded_bytes: u64,
+    /// With the current budget
+    pub frames_to_drain: u64,
+}
+
+pub enum UploadTarget {
+    Buffer {
+        buffer: Arc<wgpu::Buffer>,
+        offset: wgpu::BufferAddress,
+    },
+    /// Written in whole rows
+    Texture {
+        texture: Arc<Texture>,
+        bytes_per_row: u32,
+        width: u32,
+    },
+}
+
+impl UploadTarget {
+    /// `bytes` start at `start` bytes into the upload
+    fn write(&self, queue: &wgpu::Queue, start: usize, bytes: &[u8]) {
+        match self {
+            UploadTarget::Buffer { buffer, offset } => {
+                queue.write_buffer(buffer, offset + start as wgpu::BufferAddress, bytes);
+            }
+            UploadTarget::Texture {
+                texture,
+                bytes_per_row,
+                width,
+            } => {
+                let rows = bytes.len() as u32 / bytes_per_row;
+                queue.write_texture(
+                    wgpu::ImageCopyTexture {
+                        texture: &texture.texture,
+                        mip_level: 0,
+                        origin: wgpu::Origin3d {
+                            x: 0,
+                            y: start as u32 / bytes_per_row,
+                            z: 0,
+                        },
+                        aspect: wgpu::TextureAspect::All,
+                    },
+                    bytes,
+                    wgpu::ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: std::num::NonZeroU32::new(*bytes_per_row),
+                        rows_per_image: std::num::NonZeroU32::new(rows),
+                    },
+                    wgpu::Extent3d {
+                        width: *width,
+                        height: rows,
+                        depth_or_array_layers: 1,
+                    },
+                );
+            }
+        }
+    }
+}
+
+struct Upload<T> {
+    id: UploadId,
+    target: T,
+    bytes: Vec<u8>,
+    written: usize,
+    // chunks are multiples of it
+    granularity: usize,
+}
+
+/// Bookkeeping of `UploadQueue`, generic so it can be tested without a device
+struct Uploads<T> {
+    budget: u64,
+    next_id: u64,
+    pending: VecDeque<Upload<T>>,
+    uploaded_bytes: u64,
+}
+
+impl<T> Uploads<T> {
+    fn new(budget: u64) -> Self {
+        Self {
+            budget,
+            next_id: 0,
+            pending: VecDeque::new(),
+            uploaded_bytes: 0,
+        }
+    }
+
+    fn push(&mut self, target: T, bytes: Vec<u8>, granularity: usize) -> UploadId {
+        let id = UploadId(self.next_id);
+        self.next_id += 1;
+        self.pending.push_back(Upload {
+            id,
+            target,
+            bytes,
+            written: 0,
+            granularity: granularity.max(1),
+        });
+        id
+    }
+
+    fn is_done(&self, id: UploadId) -> bool {
+        !self.pending.iter().any(|upload| upload.id == id)
+    }
+
+    /// Writes up to the budget in queue order
+    fn tick(&mut self, mut write: impl FnMut(&T, usize, &[u8])) {
+        let mut budget = self.budget as usize;
+        self.uploaded_bytes = 0;
+        while let Some(upload) = self.pending.front_mut() {
+            let remaining = upload.bytes.len() - upload.written;
+            let mut len = remaining.min(budget - budget % upload.granularity);
+            if len == 0 {
+                if self.uploaded_bytes > 0 {
+                    break;
+                }
+                // NOTE: a chunk larger than the budget still goes, or it would never finish
+                len = remaining.min(upload.granularity);
+            }
+
+            let start = upload.written;
+            write(&upload.target, start, &upload.bytes[start..start + len]);
+            upload.written += len;
+            budget = budget.saturating_sub(len);
+            self.uploaded_bytes += len as u64;
+
+            if upload.written == upload.bytes.len() {
+                self.pending.pop_front();
+            }
+        }
+    }
+
+    fn stats(&self) -> UploadStats {
+        let queued_bytes: u64 = self
+            .pending
+            .iter()
+            .map(|upload| (upload.bytes.len() - upload.written) as u64)
+            .sum();
+        UploadStats {
+            queued_bytes,
+            uploaded_bytes: self.uploaded_bytes,
+            frames_to_drain: (queued_bytes + self.budget.max(1) - 1) / self.budget.max(1),
+        }
+    }
+}
+
+pub fn upload_system(
+    queue: Option<Res<wgpu::Queue>>,
+    mut uploads: ResMut<UploadQueue>,
+    profiler: Option<Res<Profiler>>,
+) {
+    let queue = match queue {
+        Some(queue) => queue,
+        None => return,
+    };
+    let _scope = profiler.as_ref().map(|profiler| profiler.scope("uploads"));
+    uploads
+        .uploads
+        .tick(|target, start, bytes| target.write(&queue, start, bytes));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{UploadStats, Uploads};
+
+    const MB: u64 = 1024 * 1024;
+
+    #[test]
+    fn large_upload_is_time_sliced() {
+        let mut uploads: Uploads<&str> = Uploads::new(8 * MB);
+        let big = uploads.push("big", vec![0; 32 * MB as usize], 4);
+
+        let mut writes = Vec::new();
+        for tick in 1..=4 {
+            assert!(!uploads.is_done(big), "done before tick {tick}");
+            uploads.tick(|target, start, bytes| writes.push((*target, start, bytes.len())));
+        }
+        assert!(uploads.is_done(big));
+        assert_eq!(
+            writes,
+            (0..4)
+                .map(|i| ("big", i * 8 * MB as usize, 8 * MB as usize))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn rows_are_not_split() {
+        let mut uploads: Uploads<&str> = Uploads::new(10);
+        let image = uploads.push("image", vec![0; 12], 4);
+        let small = uploads.push("small", vec![0; 4], 4);
+        assert_eq!(
+            uploads.stats(),
+            UploadStats {
+                queued_bytes: 16,
+                uploaded_bytes: 0,
+                frames_to_drain: 2,
+            }
+        );
+
+        let mut writes = Vec::new();
+        uploads.tick(|target, start, bytes| writes.push((*target, start, bytes.len())));
+        // 2 rows of the image, the last row does not fit in the remaining budget
+        assert_eq!(writes, [("image", 0, 8)]);
+        uploads.tick(|target, start, bytes| writes.push((*target, start, bytes.len())));
+        assert_eq!(writes[1..], [("image", 8, 4), ("small", 0, 4)]);
+        assert!(uploads.is_done(image) && uploads.is_done(small));
+
+        // rows larger than the budget still make progress
+        let wide = uploads.push("wide", vec![0; 32], 16);
+        uploads.tick(|_, _, bytes| assert_eq!(bytes.len(), 16));
+        assert!(!uploads.is_done(wide));
+        assert_eq!(uploads.stats().queued_bytes, 16);
+    }
+}
@@ -0,0 +1,379 @@
+use std::sync::Arc;
+
+use bevy_ecs::{
+    entity::Entity,
+    event::EventWriter,
+    prelude::Component,
+    query::{Changed, Without},
+    system::{Commands, Query, Res},
+};
+use cgmath::{EuclideanSpace, Transform, Vector4};
+
+use crate::{
+    camera::{Camera, Frustum},
+    picking::{Aabb, BoundingSphere},
+};
+
+use super::{
+    resource::buffer::{Instance, InstanceRaw},
+    InstanceData,
+};
+
+/// Hides an entity without removing its mesh, entities without it are drawn
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Visibility {
+    pub visible: bool,
+}
+
+impl Visibility {
+    pub fn hidden() -> Self {
+        Self { visible: false }
+    }
+}
+
+impl Default for Visibility {
+    fn default() -> Self {
+        Self { visible: true }
+    }
+}
+
+/// What the render systems check, inserted and updated in `CoreStage::PostUpdate`.
+///
+/// NOTE: only follows [`Visibility`] for now, frustum culling and parent
+/// visibility are to be combined here once they exist
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ComputedVisibility {
+    visible: bool,
+}
+
+impl ComputedVisibility {
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+}
+
+/// Sent when the computed visibility of an entity changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VisibilityChanged(pub Entity, pub bool);
+
+/// The items of `query` that should be drawn
+pub fn visible<'a, T>(
+    query: impl IntoIterator<Item = (T, Option<&'a ComputedVisibility>)>,
+) -> impl Iterator<Item = T> {
+    query
+        .into_iter()
+        .filter(|(_, computed)| computed.map_or(true, ComputedVisibility::is_visible))
+        .map(|(item, _)| item)
+}
+
+pub fn compute_visibility_system(
+    mut commands: Commands,
+    added: Query<(Entity, &Visibility), Without<ComputedVisibility>>,
+    mut changed: Query<(Entity, &Visibility, &mut ComputedVisibility), Changed<Visibility>>,
+    mut removed: Query<(Entity, &mut ComputedVisibility), Without<Visibility>>,
+    mut events: EventWriter<VisibilityChanged>,
+) {
+    // NOTE: drawn until now, so only hiding is a change
+    for (entity, visibility) in added.iter() {
+        commands.entity(entity).insert(ComputedVisibility {
+            visible: visibility.visible,
+        });
+        if !visibility.visible {
+            events.send(VisibilityChanged(entity, false));
+        }
+    }
+
+    for (entity, visibility, mut computed) in changed.iter_mut() {
+        if computed.visible != visibility.visible {
+            computed.visible = visibility.visible;
+            events.send(VisibilityChanged(entity, visibility.visible));
+        }
+    }
+    for (entity, mut computed) in removed.iter_mut() {
+        if !computed.visible {
+            computed.visible = true;
+            events.send(VisibilityChanged(entity, true));
+        }
+    }
+}
+
+/// World space instances of the entity's mesh, culled against the camera every frame
+/// and only the survivors written to its [`InstanceData`], which is created and resized here.
+/// The entity needs the model space [`Aabb`] of the mesh, a [`BoundingSphere`] next to it
+/// is tested first and settles most instances without the box.
+///
+/// TODO: GPU path, keep the whole buffer and write the instance count of an indirect
+/// draw from a culling compute shader
+#[derive(Component, Default)]
+pub struct InstanceSource(pub Vec<Instance>);
+
+/// The instances whose `bounds` intersect `frustum`. Instances whose `sphere` is outside
+/// are culled and those whose `sphere` is inside are kept without testing the box
+pub fn cull_instances(
+    frustum: &Frustum,
+    bounds: &Aabb,
+    sphere: Option<&BoundingSphere>,
+    instances: &[Instance],
+) -> Vec<InstanceRaw> {
+    let center = bounds.min.midpoint(bounds.max);
+    let extent = (bounds.max - bounds.min) / 2.0;
+    instances
+        .iter()
+        .filter(|instance| {
+            let model = instance.model_matrix();
+            if let Some(sphere) = sphere {
+                let sphere = sphere.transformed(&model);
+                if !frustum.intersects_sphere(sphere.center, sphere.radius) {
+                    return false;
+                }
+                if frustum.contains_sphere(sphere.center, sphere.radius) {
+                    return true;
+                }
+            }
+            let world_center = model.transform_point(center);
+            // half size of the world space box around the transformed bounds
+            let axis = |column: Vector4<f32>| column.truncate().map(f32::abs);
+            let world_extent =
+                axis(model.x) * extent.x + axis(model.y) * extent.y + axis(model.z) * extent.z;
+            frustum.intersects_aabb(world_center - world_extent, world_center + world_extent)
+        })
+        .map(Instance::to_raw)
+        .collect()
+}
+
+/// Without a camera every instance is drawn
+pub fn cull_instances_system(
+    mut commands: Commands,
+    device: Option<Res<Arc<wgpu::Device>>>,
+    queue: Option<Res<wgpu::Queue>>,
+    camera: Option<Res<Camera>>,
+    mut sources: Query<(
+        Entity,
+        &InstanceSource,
+        &Aabb,
+        Option<&BoundingSphere>,
+        Option<&mut InstanceData>,
+    )>,
+) {
+    let (device, queue) = match (device, queue) {
+        (Some(device), Some(queue)) => (device, queue),
+        _ => return,
+    };
+    let frustum = camera.map(|camera| camera.frustum());
+
+    for (entity, source, bounds, sphere, instance_data) in sources.iter_mut() {
+        let survivors = match &frustum {
+            Some(frustum) => cull_instances(frustum, bounds, sphere, &source.0),
+            None => source.0.iter().map(Instance::to_raw).collect(),
+        };
+        match instance_data {
+            Some(mut instance_data) if instance_data.capacity() as usize >= source.0.len() => {
+                instance_data.write(&queue, &survivors);
+            }
+            _ => {
+                // NOTE: room for all instances, so it is not recreated as the camera moves
+                let mut instance_data = InstanceData::with_capacity(&device, source.0.len() as u32);
+                instance_data.write(&queue, &survivors);
+                commands.entity(entity).insert(instance_data);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_ecs::{
+        entity::Entity,
+        event::Events,
+        schedule::{Stage, SystemStage},
+        world::World,
+    };
+
+    use cgmath::{
+        EuclideanSpace, InnerSpace, One, Point3, Quaternion, Rad, Rotation3, Transform, Vector3,
+        Vector4,
+    };
+
+    use crate::{
+        camera::{CameraView, Frustum, PerspectiveProjection, OPENGL_TO_WGPU_MATRIX},
+        picking::{Aabb, BoundingSphere},
+        render::{
+            mesh::Mesh,
+            resource::buffer::{Instance, Vertex},
+        },
+    };
+
+    use super::{
+        compute_visibility_system, cull_instances, visible, ComputedVisibility, Visibility,
+        VisibilityChanged,
+    };
+
+    #[test]
+    fn hidden_entities_are_not_drawn() {
+        let mut world = World::new();
+        world.init_resource::<Events<VisibilityChanged>>();
+        let mut stage = SystemStage::single(compute_visibility_system);
+
+        let shown = world.spawn().insert(Visibility::default()).id();
+        let hidden = world.spawn().insert(Visibility::default()).id();
+        let plain = world.spawn().id();
+        stage.run(&mut world);
+        world.get_mut::<Visibility>(hidden).unwrap().visible = false;
+        stage.run(&mut world);
+
+        let mut query = world.query::<(Entity, Option<&ComputedVisibility>)>();
+        let mut drawn: Vec<Entity> = visible(query.iter(&world)).collect();
+        drawn.sort();
+        assert_eq!(drawn, vec![shown, plain]);
+
+        let events = world.resource::<Events<VisibilityChanged>>();
+        let changes: Vec<_> = events.get_reader().iter(events).copied().collect();
+        assert_eq!(changes, vec![VisibilityChanged(hidden, false)]);
+
+        world.entity_mut(hidden).remove::<Visibility>();
+        stage.run(&mut world);
+        let mut drawn: Vec<Entity> = visible(query.iter(&world)).collect();
+        drawn.sort();
+        assert_eq!(drawn, vec![shown, hidden, plain]);
+    }
+
+    #[test]
+    fn culled_instances_match_reference() {
+        let bounds = Aabb {
+            min: Point3::new(-0.5, -0.5, -0.5),
+            max: Point3::new(0.5, 0.5, 0.5),
+        };
+        let instances: Vec<Instance> = (0..100)
+            .map(|i| Instance {
+                position: Vector3::new((i % 10) as f32 * 2.0, 0.0, (i / 10) as f32 * 2.0),
+                scale: Vector3::new(1.0, 1.0, 1.0),
+                rotation: Quaternion::one(),
+            })
+            .collect();
+        // looking at the corner of the grid at the origin from outside
+        let view_proj = OPENGL_TO_WGPU_MATRIX
+            * PerspectiveProjection::default().build_projection_matrix()
+            * CameraView {
+                eye: Point3::new(-4.0, 3.0, -4.0),
+                target: Point3::origin(),
+                up: Vector3::unit_y(),
+            }
+            .build_view_matrix();
+
+        // outside if all corners in clip space are beyond the same plane
+        let reference = instances
+            .iter()
+            .filter(|instance| {
+                let model = instance.model_matrix();
+                let corners: Vec<Vector4<f32>> = (0..8)
+                    .map(|i| {
+                        let pick = |axis: usize| match i & (1 << axis) {
+                            0 => bounds.min[axis],
+                            _ => bounds.max[axis],
+                        };
+                        view_proj * model * Point3::new(pick(0), pick(1), pick(2)).to_homogeneous()
+                    })
+                    .collect();
+                let beyond = |outside: fn(&Vector4<f32>) -> bool| corners.iter().all(outside);
+                !(beyond(|c| c.x < -c.w)
+                    || beyond(|c| c.x > c.w)
+                    || beyond(|c| c.y < -c.w)
+                    || beyond(|c| c.y > c.w)
+                    || beyond(|c| c.z < 0.0)
+                    || beyond(|c| c.z > c.w))
+            })
+            .count();
+
+        let visible = cull_instances(
+            &Frustum::from_view_proj(&view_proj),
+            &bounds,
+            None,
+            &instances,
+        );
+        assert_eq!(visible.len(), reference);
+        assert!(
+            0 < reference && reference < instances.len(),
+            "{reference} visible"
+        );
+    }
+
+    fn random(seed: &mut u64) -> f32 {
+        *seed = seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (*seed >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    #[test]
+    fn sphere_culling_keeps_every_visible_vertex() {
+        let view_proj = OPENGL_TO_WGPU_MATRIX
+            * PerspectiveProjection::default().build_projection_matrix()
+            * CameraView {
+                eye: Point3::new(0.0, 2.0, 10.0),
+                target: Point3::origin(),
+                up: Vector3::unit_y(),
+            }
+            .build_view_matrix();
+        let frustum = Frustum::from_view_proj(&view_proj);
+
+        let mut seed = 7;
+        let (mut culled, mut kept) = (0, 0);
+        for _ in 0..20 {
+            let vertices: Vec<Vertex> = (0..16)
+                .map(|_| {
+                    let mut coordinate = || random(&mut seed) * 2.0 - 1.0;
+                    Vertex {
+                        position: [coordinate(), coordinate() * 0.5, coordinate() * 3.0],
+                        tex_coords: [0.0; 2],
+                    }
+                })
+                .collect();
+            let mesh = Mesh::with_all(wgpu::PrimitiveTopology::TriangleList, vertices, None);
+            let sphere = mesh.compute_bounding_sphere().unwrap();
+            let bounds = Aabb::from_points(
+                mesh.get_vertices()
+                    .iter()
+                    .map(|vertex| Point3::from(vertex.position)),
+            )
+            .unwrap();
+
+            for _ in 0..20 {
+                let mut coordinate = || random(&mut seed) * 2.0 - 1.0;
+                let instance = Instance {
+                    position: Vector3::new(
+                        coordinate() * 12.0,
+                        coordinate() * 6.0,
+                        coordinate() * 12.0,
+                    ),
+                    scale: Vector3::new(1.0, 1.0, 1.0) * (1.0 + coordinate().abs()),
+                    rotation: Quaternion::from_axis_angle(
+                        Vector3::new(coordinate(), coordinate(), coordinate() + 2.0).normalize(),
+                        Rad(coordinate() * 3.0),
+                    ),
+                };
+                let model = instance.model_matrix();
+                let any_vertex_visible = mesh.get_vertices().iter().any(|vertex| {
+                    let c = view_proj * model * Point3::from(vertex.position).to_homogeneous();
+                    c.x.abs() <= c.w && c.y.abs() <= c.w && 0.0 <= c.z && c.z <= c.w
+                });
+
+                let visible =
+                    !cull_instances(&frustum, &bounds, Some(&sphere), &[instance]).is_empty();
+                // the sphere never culls what the vertices show
+                assert!(visible || !any_vertex_visible);
+
+                // and the transformed sphere still holds every vertex
+                let world = sphere.transformed(&model);
+                for vertex in mesh.get_vertices() {
+                    let p = model.transform_point(Point3::from(vertex.position));
+                    assert!((p - world.center).magnitude() <= world.radius * 1.0001);
+                }
+                match visible {
+                    true => kept += 1,
+                    false => culled += 1,
+                }
+            }
+        }
+        assert!(culled > 0 && kept > 0, "{culled} culled, {kept} kept");
+    }
+}
@@ -0,0 +1,131 @@
+use std::collections::HashSet;
+
+use bevy_ecs::prelude::Component;
+use wgpu::util::DeviceExt;
+
+use super::{
+    mesh::Mesh,
+    resource::{buffer::MeshVertex, pipeline::WireframeVariant},
+};
+
+/// Draws every entity with a triangle list pipeline as a wireframe, see [`Wireframe`]
+/// for single entities
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WireframeMode(pub bool);
+
+/// Draws the entity with the wireframe variant of its pipeline, with
+/// `PolygonMode::Line` where the device supports it. Otherwise the
+/// [`WireframeEdges`] of the entity are drawn, entities without them are drawn filled.
+///
+/// NOTE: wireframes are not drawn in the depth prepass
+#[derive(Component, Debug, Clone, Copy, Default)]
+pub struct Wireframe;
+
+/// Line list indices of the edges of a triangle list mesh, for
+/// [`WireframeVariant::EdgeList`]. [`GpuMesh`](super::mesh::GpuMesh) does not keep its indices, so these are
+/// built from the cpu mesh and inserted next to it.
+#[derive(Component)]
+pub struct WireframeEdges {
+    pub index_buffer: wgpu::Buffer,
+    pub index_count: u32,
+}
+
+impl WireframeEdges {
+    /// `None` for other topologies than `TriangleList`
+    pub fn from_mesh<V: MeshVertex>(mesh: &Mesh<V>, device: &wgpu::Device) -> Option<Self> {
+        if mesh.get_primitive_topology() != wgpu::PrimitiveTopology::TriangleList {
+            return None;
+        }
+        let edges = match mesh.get_indices() {
+            Some(indices) => triangle_edges(indices.iter()),
+            None => triangle_edges(0..mesh.vertex_count() as u32),
+        };
+        Some(Self {
+            index_buffer: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Wireframe Index Buffer"),
+                contents: bytemuck::cast_slice(&edges),
+                usage: wgpu::BufferUsages::INDEX,
+            }),
+            index_count: edges.len() as u32,
+        })
+    }
+
+    /// The vertex buffers of the mesh have to be set
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, instance_count: u32) {
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.draw_indexed(0..self.index_count, 0, 0..instance_count);
+    }
+}
+
+/// Whether the entity is drawn as a wireframe, and with which pipeline
+pub(crate) fn wireframe_pipeline<'a>(
+    mode: WireframeMode,
+    marker: Option<&Wireframe>,
+    variant: Option<&'a WireframeVariant>,
+    edges: Option<&WireframeEdges>,
+) -> Option<&'a WireframeVariant> {
+    if !mode.0 && marker.is_none() {
+        return None;
+    }
+    match variant? {
+        WireframeVariant::EdgeList(_) if edges.is_none() => None,
+        variant => Some(variant),
+    }
+}
+
+/// Every edge of the triangles once, as a line list in the order they first appear
+pub fn triangle_edges(indices: impl IntoIterator<Item = u32>) -> Vec<u32> {
+    let indices: Vec<u32> = indices.into_iter().collect();
+    let mut seen = HashSet::new();
+    let mut edges = Vec::new();
+    for triangle in indices.chunks_exact(3) {
+        for (a, b) in [
+            (triangle[0], triangle[1]),
+            (triangle[1], triangle[2]),
+            (triangle[2], triangle[0]),
+        ] {
+            if seen.insert((a.min(b), a.max(b))) {
+                edges.extend([a, b]);
+            }
+        }
+    }
+    edges
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::triangle_edges;
+
+    #[test]
+    fn cube_edges_are_shared_once() {
+        // 8 corners, 2 triangles per face
+        #[rustfmt::skip]
+        let cube: [u32; 36] = [
+            0, 1, 2, 2, 3, 0, // front
+            5, 4, 7, 7, 6, 5, // back
+            4, 0, 3, 3, 7, 4, // left
+            1, 5, 6, 6, 2, 1, // right
+            3, 2, 6, 6, 7, 3, // top
+            4, 5, 1, 1, 0, 4, // bottom
+        ];
+        let edges = triangle_edges(cube);
+        assert_eq!(edges.len() % 2, 0);
+        // 12 cube edges and a diagonal per face
+        assert_eq!(edges.len() / 2, 18);
+        let unique: HashSet<(u32, u32)> = edges
+            .chunks_exact(2)
+            .map(|edge| (edge[0].min(edge[1]), edge[0].max(edge[1])))
+            .collect();
+        assert_eq!(unique.len(), 18);
+        // first triangle first, in winding order
+        assert_eq!(&edges[..6], &[0, 1, 1, 2, 2, 0]);
+    }
+
+    #[test]
+    fn incomplete_triangles_are_ignored() {
+        assert_eq!(triangle_edges([0, 1, 2, 2, 1]), vec![0, 1, 1, 2, 2, 0]);
+        assert!(triangle_edges([]).is_empty());
+    }
+}
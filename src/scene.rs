@@ -0,0 +1,453 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use bevy_app::{CoreStage, Plugin};
+use bevy_asset::{
+    AddAsset, AssetLoader, AssetPath, AssetServer, Assets, Handle, LoadState, LoadedAsset,
+};
+use bevy_ecs::{
+    schedule::ParallelSystemDescriptorCoercion,
+    system::{Commands, Query, Res, ResMut},
+};
+use bevy_reflect::TypeUuid;
+use cgmath::{Quaternion, Vector3};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    color::Color,
+    render::{
+        material::{ColorMaterial, Material, TextureMaterial},
+        mesh::{
+            create_gpu_mesh_hashed,
+            primitive::{create_aa_plane, create_unit_cube, PlaneAlign},
+            GpuMesh, Mesh, MeshCache, ObjMesh, ObjModel,
+        },
+        resource::buffer::Vertex,
+    },
+    texture::{prepare_images_system, DefaultTextures, Image, TextureHandle},
+    transform::Transform,
+    util::{Refer, Store},
+};
+
+pub struct FlatScenePlugin;
+impl Plugin for FlatScenePlugin {
+    fn build(&self, app: &mut bevy_app::App) {
+        app.add_asset::<SceneDescriptor>()
+            .add_asset_loader(SceneLoader)
+            .init_resource::<SceneSpawner>()
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                scene_spawner_system.after(prepare_images_system),
+            );
+    }
+}
+
+/// Loaded from `.scene.ron` files, paths are relative to the asset folder
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TypeUuid)]
+#[uuid = "10929DF8-15C5-472B-9398-7158AB89A0A6"]
+#[serde(deny_unknown_fields)]
+pub struct SceneDescriptor {
+    pub entities: Vec<SceneEntity>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneEntity {
+    pub mesh: MeshDescriptor,
+    #[serde(default)]
+    pub transform: TransformDescriptor,
+    #[serde(default)]
+    pub material: MaterialDescriptor,
+    /// Spawns a copy for each, relative to `transform`
+    #[serde(default)]
+    pub instances: Vec<TransformDescriptor>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum MeshDescriptor {
+    Obj(String),
+    Primitive(Primitive),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Primitive {
+    Cube,
+    /// XZ plane facing +Y
+    Plane { size: f32, subdivisions: u32 },
+}
+
+impl Primitive {
+    fn cache_name(&self) -> String {
+        format!("primitive:{:?}", self)
+    }
+
+    fn create(&self) -> Mesh<Vertex> {
+        match self {
+            Primitive::Cube => create_unit_cube(),
+            Primitive::Plane { size, subdivisions } => create_aa_plane(
+                PlaneAlign::XZ,
+                *size,
+                *size,
+                (*subdivisions).max(1),
+                (*subdivisions).max(1),
+                Vector3::new(0.0, 0.0, 0.0),
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TransformDescriptor {
+    pub translation: [f32; 3],
+    /// Quaternion as [x, y, z, w]
+    pub rotation: [f32; 4],
+    pub scale: [f32; 3],
+}
+
+impl Default for TransformDescriptor {
+    fn default() -> Self {
+        Self {
+            translation: [0.0, 0.0, 0.0],
+            rotation: [0.0, 0.0, 0.0, 1.0],
+            scale: [1.0, 1.0, 1.0],
+        }
+    }
+}
+
+impl From<&TransformDescriptor> for Transform {
+    fn from(desc: &TransformDescriptor) -> Self {
+        let [x, y, z, w] = desc.rotation;
+        Transform {
+            translation: desc.translation.into(),
+            scale: desc.scale.into(),
+            rotation: Quaternion::new(w, x, y, z),
+        }
+    }
+}
+
+/// With a `texture` the entity gets a `TextureMaterial`, otherwise a `ColorMaterial`, unless
+/// `shader` picks one
+// TODO: the texture material has no tint, color is ignored with a texture
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MaterialDescriptor {
+    /// `"color_material.wgsl"` or `"texture_material.wgsl"`, the materials drawing the
+    /// `Vertex` layout of scene meshes. Picked by `texture` when not set
+    pub shader: Option<String>,
+    /// sRGB, as picked in an editor, alpha is linear
+    pub color: [f32; 4],
+    pub texture: Option<String>,
+}
+
+impl Default for MaterialDescriptor {
+    fn default() -> Self {
+        Self {
+            shader: None,
+            color: [1.0, 1.0, 1.0, 1.0],
+            texture: None,
+        }
+    }
+}
+
+impl MaterialDescriptor {
+    /// Whether the entity gets a `TextureMaterial`, errors on a shader no material has
+    pub fn textured(&self) -> anyhow::Result<bool> {
+        match self.shader.as_deref() {
+            None => Ok(self.texture.is_some()),
+            Some(shader) if shader == ColorMaterial::shader_path() => Ok(false),
+            Some(shader) if shader == TextureMaterial::shader_path() => match self.texture {
+                Some(_) => Ok(true),
+                None => Err(anyhow::anyhow!("Scene shader {} needs a texture", shader)),
+            },
+            Some(shader) => Err(anyhow::anyhow!(
+                "Scene shader {} is not the shader of a scene material",
+                shader
+            )),
+        }
+    }
+}
+
+impl SceneDescriptor {
+    pub fn from_ron(ron: &str) -> anyhow::Result<Self> {
+        Ok(ron::from_str(ron)?)
+    }
+
+    pub fn to_ron(&self) -> anyhow::Result<String> {
+        Ok(ron::ser::to_string_pretty(
+            self,
+            ron::ser::PrettyConfig::default(),
+        )?)
+    }
+
+    pub fn texture_paths(&self) -> impl Iterator<Item = &str> {
+        self.entities
+            .iter()
+            .filter_map(|entity| entity.material.texture.as_deref())
+    }
+
+    pub fn obj_paths(&self) -> impl Iterator<Item = &str> {
+        self.entities.iter().filter_map(|entity| match &entity.mesh {
+            MeshDescriptor::Obj(path) => Some(path.as_str()),
+            MeshDescriptor::Primitive(_) => None,
+        })
+    }
+}
+
+pub struct SceneLoader;
+impl AssetLoader for SceneLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut bevy_asset::LoadContext,
+    ) -> bevy_asset::BoxedFuture<'a, anyhow::Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let scene = SceneDescriptor::from_ron(std::str::from_utf8(bytes)?)?;
+            for entity in scene.entities.iter() {
+                entity.material.textured()?;
+            }
+            let dependencies: Vec<AssetPath> = scene
+                .texture_paths()
+                .chain(scene.obj_paths())
+                .map(|path| AssetPath::new(PathBuf::from(path), None))
+                .collect();
+            load_context
+                .set_default_asset(LoadedAsset::new(scene).with_dependencies(dependencies));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["scene.ron"]
+    }
+}
+
+/// Scenes are spawned once they and their OBJ models are loaded, their textures are bound
+/// as they arrive
+#[derive(Default)]
+pub struct SceneSpawner {
+    pending: Vec<Handle<SceneDescriptor>>,
+    // keeps the models of pending scenes loaded, their meshes are uploaded on spawn
+    models: HashMap<String, Handle<ObjModel>>,
+    // keeps the textures loaded while spawned entities sample them
+    textures: HashMap<String, Handle<Image>>,
+}
+
+impl SceneSpawner {
+    pub fn spawn_scene(&mut self, handle: Handle<SceneDescriptor>) {
+        self.pending.push(handle);
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+/// The meshes of a loaded model, `None` while it or one of its meshes is loading
+fn obj_meshes(
+    asset_server: &AssetServer,
+    models: &Assets<ObjModel>,
+    obj_meshes: &Assets<ObjMesh>,
+    handle: &Handle<ObjModel>,
+) -> Option<Vec<Handle<ObjMesh>>> {
+    match models.get(handle) {
+        Some(model) => model
+            .meshes
+            .iter()
+            .all(|mesh| obj_meshes.contains(mesh))
+            .then(|| model.meshes.clone()),
+        None if asset_server.get_load_state(handle) == LoadState::Failed => {
+            log::error!("Scene mesh could not be loaded, spawning nothing for it");
+            Some(Vec::new())
+        }
+        None => None,
+    }
+}
+
+pub fn scene_spawner_system(
+    mut commands: Commands,
+    device: Option<Res<Arc<wgpu::Device>>>,
+    asset_server: Res<AssetServer>,
+    scenes: Res<Assets<SceneDescriptor>>,
+    models: Res<Assets<ObjModel>>,
+    loaded_meshes: Res<Assets<ObjMesh>>,
+    defaults: Option<Res<DefaultTextures>>,
+    sampling: Query<&TextureHandle>,
+    mut spawner: ResMut<SceneSpawner>,
+    mut meshes: ResMut<Store<GpuMesh>>,
+    mut cache: ResMut<MeshCache>,
+) {
+    let (device, defaults) = match (device, defaults) {
+        (Some(device), Some(defaults)) => (device, defaults),
+        _ => return,
+    };
+
+    // NOTE: entities spawned below only show up in the query next frame
+    let mut sampled: HashSet<_> = sampling.iter().map(|TextureHandle(id)| *id).collect();
+    let mut needed_models = HashSet::new();
+
+    let pending = std::mem::take(&mut spawner.pending);
+    for handle in pending {
+        let scene = match scenes.get(&handle) {
+            Some(scene) => scene,
+            None => {
+                if asset_server.get_load_state(&handle) == LoadState::Failed {
+                    log::error!("Scene could not be loaded, not spawning it");
+                } else {
+                    spawner.pending.push(handle);
+                }
+                continue;
+            }
+        };
+
+        // NOTE: textures are bound once uploaded, see `resolve_textures_system`
+        for path in scene.texture_paths() {
+            let texture = spawner
+                .textures
+                .entry(path.to_string())
+                .or_insert_with(|| asset_server.load(path));
+            sampled.insert(texture.id);
+        }
+
+        let mut loading = false;
+        let mut scene_meshes = HashMap::new();
+        for path in scene.obj_paths() {
+            let model = spawner
+                .models
+                .entry(path.to_string())
+                .or_insert_with(|| asset_server.load(path));
+            match obj_meshes(&asset_server, &models, &loaded_meshes, model) {
+                Some(handles) => {
+                    scene_meshes.insert(path, handles);
+                }
+                None => loading = true,
+            }
+        }
+        if loading {
+            needed_models.extend(scene.obj_paths().map(str::to_string));
+            spawner.pending.push(handle);
+            continue;
+        }
+
+        for entity in scene.entities.iter() {
+            let mesh_refers: Vec<Refer<GpuMesh>> = match &entity.mesh {
+                MeshDescriptor::Obj(path) => scene_meshes[&path.as_str()]
+                    .iter()
+                    .filter_map(|handle| loaded_meshes.get(handle))
+                    .map(|obj_mesh| {
+                        create_gpu_mesh_hashed(&device, &mut meshes, &mut cache, &obj_mesh.mesh)
+                    })
+                    .collect(),
+                MeshDescriptor::Primitive(primitive) => vec![cache.get_or_create(
+                    &device,
+                    &mut meshes,
+                    primitive.cache_name().as_str(),
+                    || primitive.create(),
+                )],
+            };
+
+            // NOTE: checked by the loader
+            let textured = entity.material.textured().unwrap_or(false);
+            let texture = entity
+                .material
+                .texture
+                .as_ref()
+                .filter(|_| textured)
+                .map(|path| spawner.textures[path].id);
+
+            let base = Transform::from(&entity.transform);
+            let transforms: Vec<Transform> = if entity.instances.is_empty() {
+                vec![base]
+            } else {
+                entity
+                    .instances
+                    .iter()
+                    .map(|instance| base.mul_transform(&Transform::from(instance)))
+                    .collect()
+            };
+
+            for transform in transforms {
+                for mesh in mesh_refers.iter() {
+                    let mut spawned = commands.spawn();
+                    spawned.insert_bundle((transform, Refer::<GpuMesh>::new(**mesh)));
+                    match texture {
+                        Some(id) => spawned.insert_bundle(TextureMaterial::pending(&defaults, id)),
+                        None => {
+                            let [r, g, b, a] = entity.material.color;
+                            spawned.insert(ColorMaterial {
+                                color: Color::srgba(r, g, b, a),
+                            })
+                        }
+                    };
+                }
+            }
+        }
+    }
+
+    spawner
+        .models
+        .retain(|path, _| needed_models.contains(path));
+    spawner
+        .textures
+        .retain(|_, texture| sampled.contains(&texture.id));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MaterialDescriptor, MeshDescriptor, Primitive, SceneDescriptor};
+
+    const SCENE: &str = r#"(
+        entities: [
+            (
+                mesh: Primitive(Cube),
+                transform: (translation: (0.0, 1.0, 0.0)),
+                material: (color: (1.0, 0.0, 0.0, 1.0)),
+            ),
+            (
+                mesh: Obj("models/rock.obj"),
+                material: (
+                    shader: Some("texture_material.wgsl"),
+                    texture: Some("textures/rock.png"),
+                ),
+                instances: [(translation: (2.0, 0.0, 0.0)), (scale: (2.0, 2.0, 2.0))],
+            ),
+        ],
+    )"#;
+
+    #[test]
+    fn scene_round_trip() {
+        let scene = SceneDescriptor::from_ron(SCENE).unwrap();
+        assert_eq!(scene.entities[0].mesh, MeshDescriptor::Primitive(Primitive::Cube));
+        assert_eq!(scene.entities[0].transform.scale, [1.0, 1.0, 1.0]);
+        assert_eq!(scene.entities[1].instances.len(), 2);
+        assert_eq!(scene.texture_paths().collect::<Vec<_>>(), vec!["textures/rock.png"]);
+        assert_eq!(scene.obj_paths().collect::<Vec<_>>(), vec!["models/rock.obj"]);
+        assert!(scene.entities[1].material.textured().unwrap());
+
+        let reloaded = SceneDescriptor::from_ron(&scene.to_ron().unwrap()).unwrap();
+        assert_eq!(reloaded, scene);
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        let typo = "(entities: [(mesh: Primitive(Cube), transfrom: ())])";
+        assert!(SceneDescriptor::from_ron(typo).is_err());
+    }
+
+    #[test]
+    fn shaders_pick_the_material() {
+        let material = |shader: Option<&str>, texture: Option<&str>| MaterialDescriptor {
+            shader: shader.map(str::to_string),
+            texture: texture.map(str::to_string),
+            ..Default::default()
+        };
+        assert!(!material(None, None).textured().unwrap());
+        assert!(material(None, Some("rock.png")).textured().unwrap());
+        assert!(!material(Some("color_material.wgsl"), Some("rock.png")).textured().unwrap());
+        assert!(material(Some("texture_material.wgsl"), None).textured().is_err());
+        assert!(material(Some("res/basic.wgsl"), None).textured().is_err());
+    }
+}
@@ -0,0 +1,27 @@
+use bevy_ecs::schedule::SystemLabel;
+
+/// Labels of the engine systems, so systems of the app can be ordered against them,
+/// e.g. `.after(FlatSystemLabels::TransformPropagate)` to read the final transforms of
+/// the frame. Within `CoreStage::PostUpdate` the labelled systems run in the order
+/// `TransformPropagate`, `VisibilityCompute`, `UniformSync`.
+#[derive(SystemLabel, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FlatSystemLabels {
+    /// `CoreStage::PreUpdate`, window events into `Input`, `Touches` and the accumulated
+    /// mouse motion. The same systems as [`InputSystem`](crate::input::InputSystem)
+    InputCollect,
+    /// `CoreStage::PostUpdate`, the systems that move entities, e.g. the
+    /// [`animation_system`](crate::animation::animation_system)
+    TransformPropagate,
+    /// `CoreStage::PostUpdate`, `ComputedVisibility` and the selected LODs
+    VisibilityCompute,
+    /// `CoreStage::PostUpdate`, materials and renderers writing their uniforms,
+    /// and the collected lights
+    UniformSync,
+    /// `RenderStage::Prepare`, the frame is acquired and the GPU resources of the frame
+    /// are written
+    RenderPrepare,
+    /// `RenderStage::MainPass`, the main pass and the passes it depends on
+    RenderMain,
+    /// `RenderStage::Present`, the frame is submitted and presented
+    RenderPresent,
+}
@@ -0,0 +1,294 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use bevy_app::App;
+use image::{Rgba, RgbaImage};
+
+use crate::{
+    render::{offscreen::OffscreenTarget, resource::compiler::PipelineProgress},
+    FlatEngine,
+};
+
+/// Limits of a golden image comparison
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tolerance {
+    /// Largest difference of a channel that still counts as equal
+    pub channel: u8,
+    /// Pixels allowed to differ by more than `channel`, e.g. along edges
+    pub max_differing_pixels: usize,
+}
+
+impl Tolerance {
+    pub const EXACT: Self = Self::new(0, 0);
+
+    pub const fn new(channel: u8, max_differing_pixels: usize) -> Self {
+        Self {
+            channel,
+            max_differing_pixels,
+        }
+    }
+}
+
+impl Default for Tolerance {
+    /// Absorbs rounding differences between adapters
+    fn default() -> Self {
+        Self::new(2, 0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GoldenOutcome {
+    /// Within the tolerance
+    Matched { differing_pixels: usize },
+    /// [`GoldenTest::UPDATE_VAR`] is set, the frame was written as the golden image
+    Recorded,
+    /// No adapter is available, nothing was rendered
+    Skipped,
+}
+
+/// Renders one frame of a headless [`FlatEngine`] app and compares it with a stored PNG.
+///
+/// A mismatch panics after writing the frame and a diff image next to the golden image,
+/// as `<name>.actual.png` and `<name>.diff.png`, the differing pixels are red in the diff.
+/// A missing golden image panics after writing the frame, it is only recorded with
+/// [`GoldenTest::UPDATE_VAR`] set.
+/// Without an adapter the test is skipped, so the suite still passes on CI without a GPU.
+///
+/// ```no_run
+/// use try_wgpu::testing::{GoldenTest, Tolerance};
+///
+/// GoldenTest::render_and_compare(
+///     |app| {
+///         // app.add_startup_system(spawn_scene);
+///     },
+///     "tests/golden/scene.png",
+///     Tolerance::default(),
+/// );
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GoldenTest {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Default for GoldenTest {
+    fn default() -> Self {
+        Self::new(64, 64)
+    }
+}
+
+impl GoldenTest {
+    /// Set to rewrite the golden images instead of comparing, e.g. after an intended change
+    pub const UPDATE_VAR: &'static str = "GOLDEN_UPDATE";
+    /// How long to wait for the pipelines requested from the `PipelineCompiler`
+    pub const PIPELINE_TIMEOUT: Duration = Duration::from_secs(10);
+
+    pub fn new(width: u32, height: u32) -> Self {
+        Self { width, height }
+    }
+
+    /// [`GoldenTest::compare`] at the default size
+    pub fn render_and_compare(
+        setup: impl FnOnce(&mut App),
+        golden_path: impl AsRef<Path>,
+        tolerance: Tolerance,
+    ) -> GoldenOutcome {
+        Self::default().compare(setup, golden_path, tolerance)
+    }
+
+    pub fn compare(
+        &self,
+        setup: impl FnOnce(&mut App),
+        golden_path: impl AsRef<Path>,
+        tolerance: Tolerance,
+    ) -> GoldenOutcome {
+        let golden_path = golden_path.as_ref();
+        let frame = match self.render(setup) {
+            Some(frame) => frame,
+            None => {
+                eprintln!("No adapter available, skipping {}", golden_path.display());
+                return GoldenOutcome::Skipped;
+            }
+        };
+
+        if std::env::var_os(Self::UPDATE_VAR).is_some() {
+            save(&frame, golden_path);
+            eprintln!("Recorded the golden image {}", golden_path.display());
+            return GoldenOutcome::Recorded;
+        }
+        let actual_path = sibling(golden_path, "actual");
+        // NOTE: not recorded here, the next run would compare the render with itself
+        if !golden_path.exists() {
+            save(&frame, &actual_path);
+            panic!(
+                "There is no golden image {}, wrote the render to {}, \
+                check it and commit it as the golden image, or set {} to record them all",
+                golden_path.display(),
+                actual_path.display(),
+                Self::UPDATE_VAR
+            );
+        }
+        let golden = match image::open(golden_path) {
+            Ok(golden) => golden.to_rgba8(),
+            Err(err) => panic!("Could not read {}: {}", golden_path.display(), err),
+        };
+
+        if golden.dimensions() != frame.dimensions() {
+            save(&frame, &actual_path);
+            panic!(
+                "{} is {:?}, the frame is {:?}, wrote {}",
+                golden_path.display(),
+                golden.dimensions(),
+                frame.dimensions(),
+                actual_path.display()
+            );
+        }
+        let comparison = Comparison::new(&frame, &golden, tolerance.channel);
+        if comparison.differing_pixels <= tolerance.max_differing_pixels {
+            return GoldenOutcome::Matched {
+                differing_pixels: comparison.differing_pixels,
+            };
+        }
+
+        let diff_path = sibling(golden_path, "diff");
+        save(&frame, &actual_path);
+        save(&comparison.diff, &diff_path);
+        panic!(
+            "{} pixels differ from {} by more than {} (at most {} allowed), \
+            the largest channel difference is {}, wrote {} and {}",
+            comparison.differing_pixels,
+            golden_path.display(),
+            tolerance.channel,
+            tolerance.max_differing_pixels,
+            comparison.max_channel_difference,
+            actual_path.display(),
+            diff_path.display()
+        );
+    }
+
+    /// Runs `setup` and the frames until no pipeline is pending, then reads the frame back.
+    /// `None` without an adapter, `setup` is not run then.
+    pub fn render(&self, setup: impl FnOnce(&mut App)) -> Option<RgbaImage> {
+        let mut app = FlatEngine::new().headless(self.width, self.height).build();
+        if !app.world.contains_resource::<Arc<wgpu::Device>>() {
+            return None;
+        }
+        setup(&mut app);
+
+        // the startup systems and the first frame
+        app.update();
+        let deadline = Instant::now() + Self::PIPELINE_TIMEOUT;
+        while app.world.resource::<PipelineProgress>().pending() > 0 {
+            if Instant::now() > deadline {
+                panic!(
+                    "Pipelines were still pending after {:?}",
+                    Self::PIPELINE_TIMEOUT
+                );
+            }
+            thread::sleep(Duration::from_millis(1));
+            app.update();
+        }
+
+        let pixels = app.world.resource::<OffscreenTarget>().read_back(
+            app.world.resource::<Arc<wgpu::Device>>(),
+            app.world.resource::<wgpu::Queue>(),
+        );
+        RgbaImage::from_raw(self.width, self.height, pixels)
+    }
+}
+
+/// `<stem>.<suffix>.png` in the directory of `path`
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}.{}.png", stem, suffix))
+}
+
+fn save(image: &RgbaImage, path: &Path) {
+    if let Some(dir) = path.parent() {
+        let _ = std::fs::create_dir_all(dir);
+    }
+    if let Err(err) = image.save(path) {
+        panic!("Could not write {}: {}", path.display(), err);
+    }
+}
+
+struct Comparison {
+    differing_pixels: usize,
+    max_channel_difference: u8,
+    /// Differing pixels are red, the rest is the expected image dimmed to gray
+    diff: RgbaImage,
+}
+
+impl Comparison {
+    /// The images have the same size
+    fn new(actual: &RgbaImage, expected: &RgbaImage, channel_tolerance: u8) -> Self {
+        let mut differing_pixels = 0;
+        let mut max_channel_difference = 0;
+        let mut diff = RgbaImage::new(expected.width(), expected.height());
+        for ((a, e), d) in actual
+            .pixels()
+            .zip(expected.pixels())
+            .zip(diff.pixels_mut())
+        {
+            let difference =
+                a.0.iter()
+                    .zip(e.0)
+                    .map(|(a, e)| a.abs_diff(e))
+                    .max()
+                    .unwrap_or(0);
+            max_channel_difference = max_channel_difference.max(difference);
+            *d = if difference > channel_tolerance {
+                differing_pixels += 1;
+                Rgba([255, 0, 0, 255])
+            } else {
+                let [r, g, b, _] = e.0;
+                let gray = ((r as u32 + g as u32 + b as u32) / 12) as u8;
+                Rgba([gray, gray, gray, 255])
+            };
+        }
+
+        Self {
+            differing_pixels,
+            max_channel_difference,
+            diff,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use image::{Rgba, RgbaImage};
+
+    use super::{sibling, Comparison};
+
+    #[test]
+    fn differences_past_the_tolerance_are_counted_and_marked() {
+        let expected = RgbaImage::from_pixel(2, 2, Rgba([120, 120, 120, 255]));
+        let mut actual = expected.clone();
+        actual.put_pixel(0, 0, Rgba([122, 120, 120, 255]));
+        actual.put_pixel(1, 1, Rgba([120, 120, 130, 255]));
+
+        let comparison = Comparison::new(&actual, &expected, 2);
+        assert_eq!(comparison.differing_pixels, 1);
+        assert_eq!(comparison.max_channel_difference, 10);
+        assert_eq!(*comparison.diff.get_pixel(1, 1), Rgba([255, 0, 0, 255]));
+        // within the tolerance, dimmed
+        assert_eq!(*comparison.diff.get_pixel(0, 0), Rgba([30, 30, 30, 255]));
+
+        assert_eq!(Comparison::new(&actual, &expected, 10).differing_pixels, 0);
+    }
+
+    #[test]
+    fn outputs_are_written_next_to_the_golden_image() {
+        assert_eq!(
+            sibling(Path::new("tests/golden/unit_cube.png"), "diff"),
+            Path::new("tests/golden/unit_cube.diff.png")
+        );
+    }
+}
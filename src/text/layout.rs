@@ -0,0 +1,291 @@
+use std::ops::Range;
+
+use super::TextAtlas;
+
+/// Width of the rect [`TextLayoutResult::caret_rect`] returns, centered on the boundary
+pub const CARET_WIDTH: f32 = 1.0;
+
+/// In the pixels of the text mesh, y up
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextRect {
+    pub min: (f32, f32),
+    pub max: (f32, f32),
+}
+
+impl TextRect {
+    pub fn center(&self) -> (f32, f32) {
+        (
+            (self.min.0 + self.max.0) / 2.0,
+            (self.min.1 + self.max.1) / 2.0,
+        )
+    }
+
+    pub fn contains(&self, point: (f32, f32)) -> bool {
+        (self.min.0..self.max.0).contains(&point.0) && (self.min.1..self.max.1).contains(&point.1)
+    }
+}
+
+/// A character of the source and where its pen position landed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LaidOutGlyph {
+    pub byte_index: usize,
+    pub ch: char,
+    /// Of the atlas, `?` for characters it does not have
+    pub glyph: usize,
+    /// Pen position, the left edge of the advance
+    pub x: f32,
+    pub baseline: f32,
+    pub advance: f32,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineLayout {
+    /// Char indices of the source, a line ending in a newline includes it
+    pub chars: Range<usize>,
+    /// From the origin to the last advance, one line height tall
+    pub rect: TextRect,
+}
+
+/// Where every character of a text lands, the meshes are emitted from it
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextLayoutResult {
+    /// One per char of the source, newlines included with no advance
+    pub glyphs: Vec<LaidOutGlyph>,
+    /// There is always at least one, possibly empty
+    pub lines: Vec<LineLayout>,
+    pub line_height: f32,
+}
+
+impl TextLayoutResult {
+    pub fn char_count(&self) -> usize {
+        self.glyphs.len()
+    }
+
+    /// Caret indices of `line`, a wrapped line does not get the index its next line
+    /// starts with
+    fn line_carets(&self, line: usize) -> Range<usize> {
+        let chars = &self.lines[line].chars;
+        let is_last = line + 1 == self.lines.len();
+        chars.start..chars.end + is_last as usize
+    }
+
+    /// Pen position before `char_index` and its line
+    fn caret_position(&self, char_index: usize) -> (f32, usize) {
+        match self.glyphs.get(char_index) {
+            Some(glyph) => (glyph.x, glyph.line),
+            None => {
+                let line = self.lines.len() - 1;
+                (self.lines[line].rect.max.0, line)
+            }
+        }
+    }
+
+    /// The caret before `char_index`, `char_count` for the end of the text.
+    /// Larger indices are clamped
+    pub fn caret_rect(&self, char_index: usize) -> TextRect {
+        let (x, line) = self.caret_position(char_index.min(self.char_count()));
+        let rect = self.lines[line].rect;
+        TextRect {
+            min: (x - CARET_WIDTH / 2.0, rect.min.1),
+            max: (x + CARET_WIDTH / 2.0, rect.max.1),
+        }
+    }
+
+    /// The caret index closest to `point`, `None` outside the lines of the text.
+    /// Right of a shorter line is its end
+    pub fn hit_test(&self, point: (f32, f32)) -> Option<usize> {
+        let left = self.lines[0].rect.min.0;
+        let right = self
+            .lines
+            .iter()
+            .fold(left, |right, line| right.max(line.rect.max.0));
+        if point.0 < left || point.0 > right {
+            return None;
+        }
+        let line = self.lines.iter().position(|line| {
+            let rect = line.rect;
+            (rect.min.1..rect.max.1).contains(&point.1)
+        })?;
+        self.line_carets(line).min_by(|&a, &b| {
+            let distance = |i: usize| (self.caret_position(i).0 - point.0).abs();
+            distance(a).total_cmp(&distance(b))
+        })
+    }
+}
+
+/// Lays `src` out from `origin`, the baseline of the first line. Lines break at newlines,
+/// and with a `max_width` after the last space before the overflowing character, or
+/// before it when the line has no space
+pub fn layout_text(
+    atlas: &TextAtlas,
+    src: &str,
+    origin: (f32, f32),
+    max_width: Option<f32>,
+) -> TextLayoutResult {
+    let line_height = atlas.h as f32;
+    let descent = atlas
+        .descriptors
+        .iter()
+        .map(|desc| desc.h - desc.bearing_y)
+        .max()
+        .unwrap_or(0)
+        .max(0) as f32;
+    let baseline = |line: usize| origin.1 - line as f32 * line_height;
+
+    let mut glyphs: Vec<LaidOutGlyph> = Vec::with_capacity(src.len());
+    // char index the current line starts at
+    let mut line_start = 0;
+    let mut line = 0;
+    let mut x = origin.0;
+    for (byte_index, ch) in src.char_indices() {
+        let i = glyphs.len();
+        if ch == '\n' {
+            glyphs.push(LaidOutGlyph {
+                byte_index,
+                ch,
+                glyph: ch as usize,
+                x,
+                baseline: baseline(line),
+                advance: 0.0,
+                line,
+            });
+            line += 1;
+            line_start = i + 1;
+            x = origin.0;
+            continue;
+        }
+
+        let glyph = if (ch as usize) < atlas.descriptors.len() {
+            ch as usize
+        } else {
+            '?' as usize
+        };
+        let advance = (atlas.descriptors[glyph].advance >> 6) as f32;
+        let overflows = max_width.map_or(false, |max_width| x + advance - origin.0 > max_width);
+        if overflows && i > line_start {
+            // the chars after the last space move to the next line
+            let wrap_at = glyphs[line_start..i]
+                .iter()
+                .rposition(|glyph| glyph.ch == ' ')
+                .map_or(i, |space| line_start + space + 1);
+            line += 1;
+            line_start = wrap_at;
+            x = origin.0;
+            for moved in glyphs[wrap_at..].iter_mut() {
+                moved.x = x;
+                moved.baseline = baseline(line);
+                moved.line = line;
+                x += moved.advance;
+            }
+        }
+
+        glyphs.push(LaidOutGlyph {
+            byte_index,
+            ch,
+            glyph,
+            x,
+            baseline: baseline(line),
+            advance,
+            line,
+        });
+        x += advance;
+    }
+
+    let lines = (0..=line)
+        .map(|line| {
+            let start = glyphs.partition_point(|glyph| glyph.line < line);
+            let end = glyphs.partition_point(|glyph| glyph.line <= line);
+            let right = glyphs[start..end]
+                .last()
+                .map_or(origin.0, |glyph| glyph.x + glyph.advance);
+            let bottom = baseline(line) - descent;
+            LineLayout {
+                chars: start..end,
+                rect: TextRect {
+                    min: (origin.0, bottom),
+                    max: (right, bottom + line_height),
+                },
+            }
+        })
+        .collect();
+
+    TextLayoutResult {
+        glyphs,
+        lines,
+        line_height,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::text::{GlyphDesc, GlyphRect, TextAtlas};
+
+    use super::layout_text;
+
+    /// Every glyph is a 1x1 pixel advancing 2 pixels, lines are 4 pixels tall
+    fn atlas() -> TextAtlas {
+        TextAtlas {
+            descriptors: vec![
+                GlyphDesc {
+                    x_start: 0,
+                    h: 1,
+                    w: 1,
+                    pitch: 1,
+                    bearing_x: 0,
+                    bearing_y: 1,
+                    advance: 2 << 6,
+                };
+                128
+            ],
+            rects: (0..128).map(|i| GlyphRect::new((i, 0), (i, 0))).collect(),
+            w: 128,
+            h: 4,
+            stride: 128,
+            bytes: vec![255; 128 * 4],
+        }
+    }
+
+    #[test]
+    fn carets_hit_their_own_index() {
+        let src = "ab\ncde\n\nf";
+        let layout = layout_text(&atlas(), src, (10.0, 100.0), None);
+        assert_eq!(layout.char_count(), src.chars().count());
+        assert_eq!(layout.lines.len(), 4);
+        assert_eq!(layout.lines[1].chars, 3..7);
+        assert_eq!(layout.lines[2].chars, 7..8);
+
+        for i in 0..=layout.char_count() {
+            let caret = layout.caret_rect(i);
+            assert_eq!(layout.hit_test(caret.center()), Some(i), "caret {i}");
+        }
+
+        // the end of the first line is before its newline, the second line is one down
+        let end = layout.caret_rect(2);
+        assert_eq!(end.center(), (14.0, 102.0));
+        let start = layout.caret_rect(3);
+        assert_eq!(start.center(), (10.0, 98.0));
+        // right of a shorter line is its end, above the text is nothing
+        assert_eq!(layout.hit_test((15.5, 102.0)), Some(2));
+        assert_eq!(layout.hit_test((12.0, 110.0)), None);
+        assert_eq!(layout.caret_rect(100), layout.caret_rect(9));
+    }
+
+    #[test]
+    fn lines_wrap_after_the_last_space() {
+        let layout = layout_text(&atlas(), "ab cd", (0.0, 0.0), Some(7.0));
+        assert_eq!(layout.lines.len(), 2);
+        assert_eq!(layout.lines[0].chars, 0..3);
+        assert_eq!(layout.lines[1].chars, 3..5);
+        let c = layout.glyphs[3];
+        assert_eq!((c.x, c.baseline, c.line), (0.0, -4.0, 1));
+        for i in 0..=5 {
+            assert_eq!(layout.hit_test(layout.caret_rect(i).center()), Some(i));
+        }
+
+        // without a space the overflowing char starts the next line
+        let layout = layout_text(&atlas(), "abcd", (0.0, 0.0), Some(5.0));
+        let lines: Vec<_> = layout.lines.iter().map(|line| line.chars.clone()).collect();
+        assert_eq!(lines, [0..2, 2..4]);
+    }
+}
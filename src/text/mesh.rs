@@ -1,50 +1,244 @@
-use crate::render::{mesh::Mesh, resource::buffer::Vertex};
+use std::ops::Range;
 
-use super::TextAtlas;
+use crate::render::{mesh::Mesh, resource::buffer::VertexTextured2DColor};
 
-pub fn create_screen_text_mesh(atlas: &TextAtlas, src: &str, coord: (f32, f32)) -> Mesh<Vertex> {
-    let mut vertices = Vec::with_capacity(src.chars().count());
+use super::{
+    layout::{layout_text, TextLayoutResult},
+    TextAtlas,
+};
 
+pub type Color = [f32; 4];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TextEffect {
+    /// Copy of the glyphs drawn behind, shifted by `offset` pixels
+    Shadow { offset: (f32, f32), color: Color },
+    /// Copies of the glyphs drawn behind, shifted `thickness` pixels in 8 directions
+    Outline { thickness: f32, color: Color },
+}
+
+impl TextEffect {
+    fn offsets(&self) -> Vec<(f32, f32)> {
+        match *self {
+            TextEffect::Shadow { offset, .. } => vec![offset],
+            TextEffect::Outline { thickness: t, .. } => vec![
+                (-t, -t),
+                (0.0, -t),
+                (t, -t),
+                (-t, 0.0),
+                (t, 0.0),
+                (-t, t),
+                (0.0, t),
+                (t, t),
+            ],
+        }
+    }
+
+    fn color(&self) -> Color {
+        match *self {
+            TextEffect::Shadow { color, .. } | TextEffect::Outline { color, .. } => color,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextStyle {
+    pub color: Color,
+    /// Byte ranges of the source recolored, later spans win where they overlap
+    pub spans: Vec<(Range<usize>, Color)>,
+    pub effect: Option<TextEffect>,
+}
+
+impl TextStyle {
+    fn color_at(&self, byte_index: usize) -> Color {
+        self.spans
+            .iter()
+            .rev()
+            .find(|(range, _)| range.contains(&byte_index))
+            .map_or(self.color, |(_, color)| *color)
+    }
+}
+
+impl Default for TextStyle {
+    fn default() -> Self {
+        Self {
+            color: [1.0, 1.0, 1.0, 1.0],
+            spans: Vec::new(),
+            effect: None,
+        }
+    }
+}
+
+pub fn create_screen_text_mesh(
+    atlas: &TextAtlas,
+    src: &str,
+    coord: (f32, f32),
+) -> Mesh<VertexTextured2DColor> {
+    create_styled_screen_text_mesh(atlas, src, coord, &TextStyle::default())
+}
+
+/// The effect quads come first so a single draw call layers them behind the glyphs
+pub fn create_styled_screen_text_mesh(
+    atlas: &TextAtlas,
+    src: &str,
+    coord: (f32, f32),
+    style: &TextStyle,
+) -> Mesh<VertexTextured2DColor> {
+    create_laid_out_text_mesh(atlas, &layout_text(atlas, src, coord, None), style)
+}
+
+/// Wrapped at `max_width`, with the layout to place carets and hit test the text with
+pub fn create_wrapped_text_mesh(
+    atlas: &TextAtlas,
+    src: &str,
+    coord: (f32, f32),
+    max_width: f32,
+    style: &TextStyle,
+) -> (Mesh<VertexTextured2DColor>, TextLayoutResult) {
+    let layout = layout_text(atlas, src, coord, Some(max_width));
+    (create_laid_out_text_mesh(atlas, &layout, style), layout)
+}
+
+/// Glyph quads of the characters of `layout`, see [`layout_text`]
+pub fn create_laid_out_text_mesh(
+    atlas: &TextAtlas,
+    layout: &TextLayoutResult,
+    style: &TextStyle,
+) -> Mesh<VertexTextured2DColor> {
+    let mut vertices = Vec::new();
+
+    if let Some(effect) = &style.effect {
+        let color = effect.color();
+        for offset in effect.offsets() {
+            push_glyph_quads(&mut vertices, atlas, layout, offset, |_| color);
+        }
+    }
+    push_glyph_quads(&mut vertices, atlas, layout, (0.0, 0.0), |i| {
+        style.color_at(i)
+    });
+
+    Mesh::with_all(wgpu::PrimitiveTopology::TriangleList, vertices, None)
+}
+
+fn push_glyph_quads(
+    vertices: &mut Vec<VertexTextured2DColor>,
+    atlas: &TextAtlas,
+    layout: &TextLayoutResult,
+    offset: (f32, f32),
+    color_at: impl Fn(usize) -> Color,
+) {
     let (h, w) = (atlas.h as u32, atlas.w as u32);
-    let (mut x, y) = coord;
-    for ch in src.chars() {
-        let desc = &atlas.descriptors[ch as usize];
-        let (tl, br) = atlas.rects[ch as usize].normalized(h, w);
+    for glyph in layout.glyphs.iter().filter(|glyph| glyph.ch != '\n') {
+        let desc = &atlas.descriptors[glyph.glyph];
+        let (tl, br) = atlas.rects[glyph.glyph].normalized(h, w);
+        let color = color_at(glyph.byte_index);
 
         let decsend = desc.h - desc.bearing_y;
-        let x_start = x + desc.bearing_x as f32;
-        let y_start = y - decsend as f32;
+        let x_start = glyph.x + offset.0 + desc.bearing_x as f32;
+        let y_start = glyph.baseline + offset.1 - decsend as f32;
         let (h, w) = (desc.h as f32, desc.w as f32);
 
+        let vertex = |position: [f32; 2], tex_coords: [f32; 2]| VertexTextured2DColor {
+            position,
+            tex_coords,
+            color,
+        };
         vertices.extend(&[
-            Vertex {
-                position: [x_start, y_start + h, 0.0],
-                tex_coords: [tl.0, tl.1],
-            }, // tl
-            Vertex {
-                position: [x_start, y_start, 0.0],
-                tex_coords: [tl.0, br.1],
-            }, // bl
-            Vertex {
-                position: [x_start + w, y_start, 0.0],
-                tex_coords: [br.0, br.1],
-            }, // br
-            Vertex {
-                position: [x_start + w, y_start, 0.0],
-                tex_coords: [br.0, br.1],
-            }, // br
-            Vertex {
-                position: [x_start + w, y_start + h, 0.0],
-                tex_coords: [br.0, tl.1],
-            }, // tr
-            Vertex {
-                position: [x_start, y_start + h, 0.0],
-                tex_coords: [tl.0, tl.1],
-            }, // tl
+            vertex([x_start, y_start + h], [tl.0, tl.1]),     // tl
+            vertex([x_start, y_start], [tl.0, br.1]),         // bl
+            vertex([x_start + w, y_start], [br.0, br.1]),     // br
+            vertex([x_start + w, y_start], [br.0, br.1]),     // br
+            vertex([x_start + w, y_start + h], [br.0, tl.1]), // tr
+            vertex([x_start, y_start + h], [tl.0, tl.1]),     // tl
         ]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::text::{GlyphDesc, GlyphRect, TextAtlas};
 
-        x += (desc.advance >> 6) as f32;
+    use super::{create_styled_screen_text_mesh, create_wrapped_text_mesh, TextEffect, TextStyle};
+
+    const RED: [f32; 4] = [1.0, 0.0, 0.0, 1.0];
+    const BLACK: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+
+    /// Every glyph is a 1x1 pixel advancing 2 pixels
+    fn atlas() -> TextAtlas {
+        TextAtlas {
+            descriptors: vec![
+                GlyphDesc {
+                    x_start: 0,
+                    h: 1,
+                    w: 1,
+                    pitch: 1,
+                    bearing_x: 0,
+                    bearing_y: 1,
+                    advance: 2 << 6,
+                };
+                128
+            ],
+            rects: (0..128).map(|i| GlyphRect::new((i, 0), (i, 0))).collect(),
+            w: 128,
+            h: 1,
+            stride: 128,
+            bytes: vec![255; 128],
+        }
     }
 
-    Mesh::with_all(wgpu::PrimitiveTopology::TriangleList, vertices, None)
+    #[test]
+    fn spans_color_their_glyphs() {
+        let src = "[ERROR] msg";
+        let style = TextStyle {
+            spans: vec![(0..7, RED)],
+            ..Default::default()
+        };
+        let mesh = create_styled_screen_text_mesh(&atlas(), src, (0.0, 0.0), &style);
+
+        let vertices = mesh.get_vertices();
+        assert_eq!(vertices.len(), 6 * src.len());
+        for (i, quad) in vertices.chunks_exact(6).enumerate() {
+            let expected = if i < 7 { RED } else { style.color };
+            assert!(quad.iter().all(|v| v.color == expected), "glyph {i}");
+        }
+    }
+
+    #[test]
+    fn effects_are_drawn_first() {
+        let shadow = TextStyle {
+            effect: Some(TextEffect::Shadow {
+                offset: (1.0, -1.0),
+                color: BLACK,
+            }),
+            ..Default::default()
+        };
+        let mesh = create_styled_screen_text_mesh(&atlas(), "ab", (10.0, 10.0), &shadow);
+        let vertices = mesh.get_vertices();
+        assert_eq!(vertices.len(), 2 * 2 * 6);
+        assert!(vertices[..12].iter().all(|v| v.color == BLACK));
+        assert_eq!(vertices[0].position, [11.0, 10.0]);
+        assert_eq!(vertices[12].position, [10.0, 11.0]);
+
+        let outline = TextStyle {
+            effect: Some(TextEffect::Outline {
+                thickness: 1.0,
+                color: BLACK,
+            }),
+            ..Default::default()
+        };
+        let mesh = create_styled_screen_text_mesh(&atlas(), "ab", (10.0, 10.0), &outline);
+        assert_eq!(mesh.get_vertices().len(), 9 * 2 * 6);
+    }
+
+    #[test]
+    fn newlines_start_a_line_below() {
+        let (mesh, layout) =
+            create_wrapped_text_mesh(&atlas(), "ab\nc", (0.0, 10.0), 100.0, &Default::default());
+        let vertices = mesh.get_vertices();
+        // no quad for the newline
+        assert_eq!(vertices.len(), 3 * 6);
+        assert_eq!(vertices[12].position, [0.0, 10.0]);
+        assert_eq!(vertices[13].position, [0.0, 9.0]);
+        assert_eq!(layout.lines.len(), 2);
+    }
 }
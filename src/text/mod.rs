@@ -2,9 +2,11 @@ use std::{collections::HashMap, ffi::OsStr};
 
 use anyhow::*;
 
-use crate::texture;
+use crate::texture::{PixelFormat, RawImage, Texture};
 
+pub mod layout;
 pub mod mesh;
+pub mod world;
 
 const FONTS_DIR: &'static str = "C:/Windows/Fonts";
 macro_rules! font_path {
@@ -34,7 +36,7 @@ impl PixelBitSize for freetype::bitmap::PixelMode {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct GlyphRect {
     pub tl: (u32, u32),
     // pub bl: f32,
@@ -64,17 +66,17 @@ impl GlyphRect {
 
 #[derive(Clone, Debug)]
 pub struct GlyphDesc {
-    x_start: usize,
+    x_start: usize, // offset of the bitmap in the linear atlas
     h: i32,
     w: i32,
-    pitch: i32, // row stride, add this to go down one row
+    pitch: i32, // row stride, add this to go down one row, negative for bottom up bitmaps
     bearing_x: i32,
     bearing_y: i32,
     advance: i32, // in 1/64 pixels
 }
 
 pub struct LinearTextAtlas {
-    sum_pitch: usize,
+    sum_w: usize,
     max_y_max: usize,
     max_y_min: usize,
     pixel_mode: freetype::bitmap::PixelMode,
@@ -89,7 +91,7 @@ impl LinearTextAtlas {
         let mut descriptors = Vec::with_capacity(COUNT);
         let mut bytes = Vec::new();
 
-        let mut sum_pitch = 0;
+        let mut sum_w = 0;
         let (mut max_y_max, mut max_y_min) = (0, 0);
 
         let mut stride = 0;
@@ -102,30 +104,31 @@ impl LinearTextAtlas {
             let bitmap = glyph.bitmap();
             bytes.extend(bitmap.buffer());
 
-            pixel_mode = Some(bitmap.pixel_mode().unwrap());
-            dbg!(&pixel_mode);
+            let mode = bitmap.pixel_mode().unwrap();
+            if mode.get_size() % 8 != 0 {
+                bail!("Pixel mode {:?} is not byte aligned", mode);
+            }
+            pixel_mode = Some(mode);
 
             let desc = GlyphDesc {
                 x_start: stride,
                 h: bitmap.rows(),
                 w: bitmap.width(),
-                // TODO: what if pitch is negative
-                // NOTE: do not support for now and produce garbage
                 pitch: bitmap.pitch(),
                 bearing_x: glyph.bitmap_left(),
                 bearing_y: glyph.bitmap_top(),
                 advance: glyph.advance().x,
             };
-            sum_pitch += desc.pitch;
+            sum_w += desc.w;
             max_y_max = max_y_max.max(desc.bearing_y);
             max_y_min = max_y_min.max(desc.h - desc.bearing_y);
-            stride += (desc.h * desc.pitch) as usize;
+            stride += (desc.h * desc.pitch.abs()) as usize;
 
             descriptors.push(desc);
         }
 
         Ok(Self {
-            sum_pitch: sum_pitch as usize,
+            sum_w: sum_w as usize,
             max_y_max: max_y_max as usize,
             max_y_min: max_y_min as usize,
             pixel_mode: pixel_mode.unwrap(),
@@ -137,12 +140,28 @@ impl LinearTextAtlas {
     pub fn get_glyph_texture(&self, ch: usize) -> (&GlyphDesc, &[u8]) {
         let desc = &self.descriptors[ch];
         let stride = desc.x_start;
-        let size = (desc.h * desc.pitch) as usize;
+        let size = (desc.h * desc.pitch.abs()) as usize;
 
         (desc, &self.bytes[stride..stride + size])
     }
+
+    fn bytes_per_pixel(&self) -> usize {
+        (self.pixel_mode.get_size() / 8) as usize
+    }
 }
 
+/// Row `i` from the top of a glyph bitmap, `row_bytes` long without the padding of the pitch
+fn bitmap_row(texture: &[u8], desc: &GlyphDesc, row_bytes: usize, i: usize) -> &[u8] {
+    let pitch = desc.pitch.unsigned_abs() as usize;
+    // NOTE: negative pitch stores the bottom row first
+    let row = match desc.pitch < 0 {
+        true => desc.h as usize - 1 - i,
+        false => i,
+    };
+    &texture[pitch * row..pitch * row + row_bytes]
+}
+
+#[derive(Clone)]
 pub struct TextAtlas {
     pub descriptors: Vec<GlyphDesc>,
     pub rects: Vec<GlyphRect>,
@@ -155,72 +174,100 @@ pub struct TextAtlas {
 impl TextAtlas {
     // TODO: Bearings can be zero
     pub fn create(linear_atlas: &LinearTextAtlas) -> Self {
-        const COUNT: usize = 128;
-
-        let fit_w = linear_atlas.sum_pitch;
+        let bytes_per_pixel = linear_atlas.bytes_per_pixel();
+        let fit_w = linear_atlas.sum_w;
         let fit_h = linear_atlas.max_y_max + linear_atlas.max_y_min;
         let zero = linear_atlas.max_y_max as i32;
+        let stride = fit_w * bytes_per_pixel;
 
         let descriptors = linear_atlas.descriptors.clone();
         let mut rects = Vec::with_capacity(descriptors.len());
-        let mut bytes = vec![0; fit_h * fit_w];
-
-        // bytes[zero-bearing_y..zero-bearing_y+h, x0..x1] =
-        // linear_atlas.bytes[stride..stride+size].as_2d(h, pitch);
+        let mut bytes = vec![0; fit_h * stride];
 
         let mut x_start = 0;
-        for ch in 0..COUNT {
+        for ch in 0..descriptors.len() {
             let (desc, texture) = linear_atlas.get_glyph_texture(ch);
-            dbg!(ch, desc);
-
-            // let by = desc.bearing_y as usize;
-            // dbg!(zero, by);
-            // let (tl, bl) = (
-            //     zero - desc.bearing_y,
-            //     zero - desc.bearing_y + desc.h - 1,
-            // );
-            // let (br, tr) = (
-            //     tl + desc.pitch - 1,
-            //     bl + desc.pitch - 1,
-            // );
-            let tl = (x_start as u32, zero as u32 - desc.bearing_y as u32);
-            let br = (tl.0 + desc.w as u32 - 1, tl.1 + desc.h as u32 - 1);
 
+            let tl = (x_start as u32, (zero - desc.bearing_y) as u32);
+            let br = (
+                (tl.0 + desc.w as u32).saturating_sub(1),
+                (tl.1 + desc.h as u32).saturating_sub(1),
+            );
+
+            // the pitch is only the stride of the source, rows are often padded
+            let row_bytes = desc.w as usize * bytes_per_pixel;
             for i in 0..desc.h as usize {
-                // bytes[...] = texture[pitch*i .. pitch*(i+1)];
-                // (
-                //     zero - desc.bearing_y as usize + i .. zero - desc.bearing_y as usize + (i+1),
-                //     x_start .. x_start + desc.pitch
-                // );
-                let offset_factor_2d = (tl.1 as usize + i) * fit_w;
-                let offset = offset_factor_2d + x_start;
-                bytes[offset..offset + desc.pitch as usize]
-                    .as_mut()
-                    .clone_from_slice(
-                        &texture[desc.pitch as usize * i..desc.pitch as usize * (i + 1)],
-                    );
+                let offset = (tl.1 as usize + i) * stride + x_start * bytes_per_pixel;
+                bytes[offset..offset + row_bytes]
+                    .clone_from_slice(bitmap_row(texture, desc, row_bytes, i));
             }
 
             rects.push(GlyphRect::new(tl, br));
 
-            x_start += desc.pitch as usize;
+            x_start += desc.w as usize;
         }
 
         Self {
             descriptors,
             rects,
             h: fit_h,
-            w: fit_w / (linear_atlas.pixel_mode.get_size() / 8) as usize,
-            stride: fit_w,
+            w: fit_w,
+            stride,
             bytes,
         }
     }
+
+    /// The glyphs and their rects, for building meshes once the bytes are uploaded
+    pub fn without_bytes(&self) -> Self {
+        Self {
+            descriptors: self.descriptors.clone(),
+            rects: self.rects.clone(),
+            w: self.w,
+            h: self.h,
+            stride: self.stride,
+            bytes: Vec::new(),
+        }
+    }
+}
+
+/// A [`TextAtlas`] on the GPU, only the glyph metadata stays on the CPU
+pub struct AtlasTexture {
+    pub texture: Texture,
+    /// Its `bytes` are empty
+    pub glyphs: TextAtlas,
+}
+
+impl AtlasTexture {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        atlas: &TextAtlas,
+        label: Option<&str>,
+    ) -> Result<Self> {
+        let texture = Texture::from_raw_image(
+            device,
+            queue,
+            &RawImage::new(
+                &atlas.bytes,
+                (atlas.w as u32, atlas.h as u32),
+                PixelFormat::G8,
+            ),
+            label,
+        )?;
+        Ok(Self {
+            texture,
+            glyphs: atlas.without_bytes(),
+        })
+    }
 }
 
 pub struct FontContainer {
     face: freetype::face::Face,
-    linear_atlas: LinearTextAtlas,
+    // freed with the bytes of the atlas on upload
+    linear_atlas: Option<LinearTextAtlas>,
     pub atlas: TextAtlas,
+    /// Keeps the glyph bitmaps after [`FontContainer::upload`], e.g. to rebuild the atlas
+    pub keep_cpu_copy: bool,
 }
 
 impl FontContainer {
@@ -230,13 +277,43 @@ impl FontContainer {
         let atlas = TextAtlas::create(&linear_atlas);
         Ok(Self {
             face,
-            linear_atlas,
+            linear_atlas: Some(linear_atlas),
             atlas,
+            keep_cpu_copy: false,
         })
     }
 
-    pub fn get_glyph_texture(&self, ch: usize) -> (&GlyphDesc, &[u8]) {
-        self.linear_atlas.get_glyph_texture(ch)
+    /// `None` once the bitmaps were freed on upload
+    pub fn get_glyph_texture(&self, ch: usize) -> Option<(&GlyphDesc, &[u8])> {
+        self.linear_atlas
+            .as_ref()
+            .map(|linear_atlas| linear_atlas.get_glyph_texture(ch))
+    }
+
+    /// Bytes of glyph bitmaps held on the CPU
+    pub fn cpu_bytes(&self) -> usize {
+        let linear = self
+            .linear_atlas
+            .as_ref()
+            .map_or(0, |linear_atlas| linear_atlas.bytes.capacity());
+        linear + self.atlas.bytes.capacity()
+    }
+
+    /// Uploads the atlas and frees the bitmaps unless [`FontContainer::keep_cpu_copy`]
+    /// is set, the glyph metadata is kept
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> Result<AtlasTexture> {
+        let before = self.cpu_bytes();
+        let texture = AtlasTexture::new(device, queue, &self.atlas, Some("Font Atlas"))?;
+        if !self.keep_cpu_copy {
+            self.linear_atlas = None;
+            self.atlas.bytes = Vec::new();
+        }
+        log::debug!(
+            "Uploaded a font atlas, CPU bytes {} -> {}",
+            before,
+            self.cpu_bytes()
+        );
+        Ok(texture)
     }
 }
 
@@ -268,18 +345,130 @@ impl TextMap {
         let path = format!("{}/{}", FONTS_DIR, &font);
         self.generate_from_path(font, &path, face_index)
     }
+
+    /// Of every font, see [`FontContainer::cpu_bytes`]
+    pub fn cpu_bytes(&self) -> usize {
+        self.fonts.values().map(FontContainer::cpu_bytes).sum()
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{FontContainer, TextAtlas};
+    use std::sync::Arc;
+
+    use bevy_ecs::world::World;
+    use freetype::bitmap::PixelMode;
+
+    use crate::create_headless_wgpu_resources;
+
+    use super::{FontContainer, GlyphDesc, LinearTextAtlas, TextAtlas};
+
+    fn glyph_pixels(atlas: &TextAtlas, ch: usize) -> Vec<u8> {
+        let (rect, desc) = (&atlas.rects[ch], &atlas.descriptors[ch]);
+        (0..desc.h as usize)
+            .flat_map(|i| {
+                let offset = (rect.tl.1 as usize + i) * atlas.stride + rect.tl.0 as usize;
+                atlas.bytes[offset..offset + desc.w as usize]
+                    .iter()
+                    .copied()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn padded_and_bottom_up_rows_are_packed() {
+        let desc = |x_start, w, pitch, bearing_y| GlyphDesc {
+            x_start,
+            h: 2,
+            w,
+            pitch,
+            bearing_x: 0,
+            bearing_y,
+            advance: 0,
+        };
+        let linear_atlas = LinearTextAtlas {
+            sum_w: 5,
+            max_y_max: 2,
+            max_y_min: 1,
+            pixel_mode: PixelMode::Gray,
+            descriptors: vec![desc(0, 3, 4, 2), desc(8, 2, -2, 1)],
+            // rows padded to 4 bytes, then a bitmap stored bottom up
+            bytes: vec![1, 2, 3, 0xff, 4, 5, 6, 0xff, 9, 10, 7, 8],
+        };
+
+        let atlas = TextAtlas::create(&linear_atlas);
+        assert_eq!((atlas.w, atlas.h), (5, 3));
+        assert_eq!(glyph_pixels(&atlas, 0), [1, 2, 3, 4, 5, 6]);
+        assert_eq!(glyph_pixels(&atlas, 1), [7, 8, 9, 10]);
+        assert!(!atlas.bytes.contains(&0xff));
+    }
+
+    #[test]
+    fn atlas_glyphs_match_freetype_bitmaps() {
+        let library = freetype::Library::init().unwrap();
+        let fontc = FontContainer::new(&library, font_path!("arial.ttf"), 0).unwrap();
+
+        for ch in ['A', 'g', 'j', '~'] {
+            fontc.face.set_char_size(30 * 64, 0, 0, 0).unwrap();
+            fontc
+                .face
+                .load_char(ch as usize, freetype::face::LoadFlag::RENDER)
+                .unwrap();
+            let bitmap = fontc.face.glyph().bitmap();
+            let mut rows: Vec<&[u8]> = bitmap
+                .buffer()
+                .chunks(bitmap.pitch().unsigned_abs() as usize)
+                .map(|row| &row[..bitmap.width() as usize])
+                .collect();
+            if bitmap.pitch() < 0 {
+                rows.reverse();
+            }
+            assert_eq!(
+                glyph_pixels(&fontc.atlas, ch as usize),
+                rows.concat(),
+                "{ch}"
+            );
+        }
+    }
+
+    #[test]
+    fn upload_frees_the_cpu_copy() {
+        let mut world = World::new();
+        if !create_headless_wgpu_resources(&mut world, 1, 1) {
+            eprintln!("No adapter available, skipping");
+            return;
+        }
+        let device = world.resource::<Arc<wgpu::Device>>();
+        let queue = world.resource::<wgpu::Queue>();
+        let library = freetype::Library::init().unwrap();
+
+        let mut kept = FontContainer::new(&library, font_path!("arial.ttf"), 0).unwrap();
+        kept.keep_cpu_copy = true;
+        let before = kept.cpu_bytes();
+        assert!(before > 0);
+        kept.upload(device, queue).unwrap();
+        assert_eq!(kept.cpu_bytes(), before);
+        assert!(kept.get_glyph_texture('A' as usize).is_some());
+
+        let mut fontc = FontContainer::new(&library, font_path!("arial.ttf"), 0).unwrap();
+        let texture = fontc.upload(device, queue).unwrap();
+        assert_eq!(fontc.cpu_bytes(), 0);
+        assert!(fontc.get_glyph_texture('A' as usize).is_none());
+        assert!(texture.glyphs.bytes.is_empty());
+        // meshes are still built from the metadata
+        assert_eq!(
+            (texture.glyphs.w, texture.glyphs.h),
+            (kept.atlas.w, kept.atlas.h)
+        );
+        assert_eq!(texture.glyphs.rects.len(), kept.atlas.rects.len());
+    }
 
     #[test]
     fn create_atlas() {
         let library = freetype::Library::init().unwrap();
         let fontc = FontContainer::new(&library, font_path!("arial.ttf"), 0).unwrap();
 
-        let atlas = TextAtlas::create(&fontc.linear_atlas);
+        let atlas = TextAtlas::create(fontc.linear_atlas.as_ref().unwrap());
         dbg!(&atlas.descriptors[32]);
         dbg!(&atlas.rects[32]);
         image::save_buffer(
@@ -0,0 +1,644 @@
+use std::{collections::HashMap, sync::Arc};
+
+use bevy_app::{CoreStage, Plugin};
+use bevy_asset::HandleId;
+use bevy_ecs::{
+    entity::Entity,
+    prelude::Component,
+    schedule::ParallelSystemDescriptorCoercion,
+    system::{Commands, Query, Res, ResMut},
+};
+use bytemuck::{Pod, Zeroable};
+use cgmath::{One, Quaternion, Rotation, Vector3, Zero};
+use repr_trait::C;
+
+use crate::{
+    camera::Camera,
+    color::Color,
+    particles::BillboardCameraUniform,
+    render::{
+        mesh::{GpuMesh, Mesh},
+        resource::{
+            bind::{BindSlots, BindingSet, GpuUniform, Uniform, UniformBuffer, UpdateGpuUniform},
+            buffer::{MeshVertex, VertexTextured2DColor},
+            pipeline::RenderPipeline,
+            shader::Shader,
+        },
+        targets::RenderTargets,
+        DepthConvention, DepthFormat,
+    },
+    transform::Transform,
+    util::{AssetStore, Refer, Store},
+    FlatSystemLabels,
+};
+
+use super::{mesh::create_screen_text_mesh, AtlasTexture, TextAtlas};
+
+/// World space labels drawn in the main pass, e.g. name tags above entities.
+///
+/// Add the uploaded atlases to [`Text3dFonts`] and spawn a [`Text3d`]. Labels showing the same
+/// string in the same font share their mesh.
+pub struct FlatText3dPlugin;
+impl Plugin for FlatText3dPlugin {
+    fn build(&self, app: &mut bevy_app::App) {
+        app.init_resource::<Text3dFonts>()
+            .init_resource::<Option<Text3dRenderer>>()
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                text3d_system
+                    .label(FlatSystemLabels::UniformSync)
+                    .after(FlatSystemLabels::VisibilityCompute),
+            );
+    }
+}
+
+/// Label following the [`Transform`] of its entity, hidden behind geometry unless `on_top`
+#[derive(Component, Debug, Clone, PartialEq)]
+pub struct Text3d {
+    /// The atlas only has ASCII, other characters are drawn as `?`
+    pub text: String,
+    /// Name in [`Text3dFonts`], the label is not drawn until it is added
+    pub font: String,
+    /// Of the baseline center from the translation, not rotated with the entity
+    pub world_offset: Vector3<f32>,
+    /// World units per atlas pixel
+    pub scale: f32,
+    /// Multiplies the glyph coverage
+    pub color: Color,
+    /// Faces the camera, otherwise lies in the xy plane of the entity facing its `+z`
+    /// and is only seen from the front
+    pub billboard: bool,
+    /// Drawn over the geometry, overlapping labels in the order they are drawn
+    pub on_top: bool,
+}
+
+impl Default for Text3d {
+    fn default() -> Self {
+        Self {
+            text: String::new(),
+            font: String::new(),
+            world_offset: Vector3::zero(),
+            scale: 0.01,
+            color: Color::WHITE,
+            billboard: true,
+            on_top: false,
+        }
+    }
+}
+
+/// Glyph atlases of the [`Text3d`] labels by name, see
+/// [`FontContainer::upload`](super::FontContainer::upload). Replacing an atlas does not
+/// update the labels already drawn with it.
+#[derive(Default)]
+pub struct Text3dFonts {
+    // keyed by the font name
+    atlases: AssetStore<AtlasTexture>,
+}
+
+impl Text3dFonts {
+    pub fn insert(&mut self, font: &str, atlas: AtlasTexture) {
+        self.atlases.insert(HandleId::from(font), atlas);
+    }
+
+    pub fn get(&self, font: &str) -> Option<&AtlasTexture> {
+        self.atlases.get(&HandleId::from(font))
+    }
+}
+
+/// Glyph quads of `text` in atlas pixels, starting at `x = 0` with the baseline at `y = 0`,
+/// and the width of the text
+pub fn create_label_mesh(atlas: &TextAtlas, text: &str) -> (Mesh<VertexTextured2DColor>, f32) {
+    // NOTE: the atlas only has the first 128 characters
+    let text: String = text
+        .chars()
+        .map(|ch| if ch.is_ascii() { ch } else { '?' })
+        .collect();
+    let mesh = create_screen_text_mesh(atlas, &text, (0.0, 0.0));
+    let width = mesh
+        .get_vertices()
+        .iter()
+        .fold(0.0f32, |width, vertex| width.max(vertex.position[0]));
+    (mesh, width)
+}
+
+/// Where a label is drawn, centered on its anchor
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Text3dPlacement {
+    pub anchor: Vector3<f32>,
+    /// Of the entity, fixed labels lie in its xy plane
+    pub rotation: Quaternion<f32>,
+    /// Of the text, in atlas pixels
+    pub width: f32,
+    pub scale: f32,
+    pub color: Color,
+    pub billboard: bool,
+    pub on_top: bool,
+}
+
+impl Text3dPlacement {
+    pub fn new(label: &Text3d, transform: Option<&Transform>, width: f32) -> Self {
+        let (translation, rotation) = transform
+            .map_or((Vector3::zero(), Quaternion::one()), |transform| {
+                (transform.translation, transform.rotation)
+            });
+        Self {
+            anchor: translation + label.world_offset,
+            rotation,
+            width,
+            scale: label.scale,
+            color: label.color,
+            billboard: label.billboard,
+            on_top: label.on_top,
+        }
+    }
+}
+
+impl UpdateGpuUniform for Text3dPlacement {
+    type GU = Text3dUniform;
+
+    fn update_uniform(&self, gpu_uniform: &mut Self::GU) {
+        let flag = |on: bool| if on { 1.0 } else { 0.0 };
+        let right = self.rotation.rotate_vector(Vector3::unit_x());
+        let up = self.rotation.rotate_vector(Vector3::unit_y());
+        gpu_uniform.anchor = self.anchor.extend(flag(self.billboard)).into();
+        gpu_uniform.right = right.extend(0.0).into();
+        gpu_uniform.up = up.extend(0.0).into();
+        gpu_uniform.color = self.color.into();
+        gpu_uniform.layout = [self.width / 2.0, self.scale, flag(self.on_top), 0.0];
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, C, Pod, Zeroable)]
+pub struct Text3dUniform {
+    /// World position of the baseline center, `w` is 1 for billboards
+    pub anchor: [f32; 4],
+    /// World axes the glyphs of fixed labels are spanned along, `w` is unused
+    pub right: [f32; 4],
+    pub up: [f32; 4],
+    /// Linear, see [`Color`]
+    pub color: [f32; 4],
+    /// Half the width in pixels, world units per pixel, 1 when drawn on top
+    pub layout: [f32; 4],
+}
+impl GpuUniform for Text3dUniform {}
+impl Default for Text3dUniform {
+    fn default() -> Self {
+        Self {
+            anchor: [0.0, 0.0, 0.0, 1.0],
+            right: [1.0, 0.0, 0.0, 0.0],
+            up: [0.0, 1.0, 0.0, 0.0],
+            color: Color::WHITE.into(),
+            layout: [0.0, 0.01, 0.0, 0.0],
+        }
+    }
+}
+
+/// GPU side of a [`Text3d`], along with its `Refer<GpuMesh>`
+#[derive(Component)]
+pub struct PreparedText3d {
+    placement: Uniform<Text3dPlacement>,
+    bind_group: usize,
+    // what the mesh and pipeline were chosen for
+    font: String,
+    text: String,
+    on_top: bool,
+}
+
+struct CachedText {
+    mesh: usize,
+    width: f32,
+    // by a label this frame
+    used: bool,
+}
+
+/// Pipelines, atlases and text meshes shared by the labels,
+/// created with the first device and main pass format
+pub struct Text3dRenderer {
+    camera: UniformBuffer<BillboardCameraUniform>,
+    camera_bind_group: usize,
+    label_layout: wgpu::BindGroupLayout,
+    pipeline: usize,
+    on_top_pipeline: usize,
+    // atlas bind groups by font
+    fonts: HashMap<String, usize>,
+    // by font and string
+    texts: HashMap<(String, String), CachedText>,
+    format: (wgpu::TextureFormat, DepthFormat, DepthConvention),
+}
+
+impl Text3dRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        depth_format: DepthFormat,
+        depth_convention: DepthConvention,
+        pipelines: &mut Store<RenderPipeline>,
+        bind_groups: &mut Store<wgpu::BindGroup>,
+    ) -> Self {
+        let camera = UniformBuffer::new_init(
+            device,
+            BillboardCameraUniform::from(&Camera::default()),
+            Some("text3d camera"),
+        );
+        let camera_bind_group = bind_groups.insert((&camera).into_bind_group(device));
+        let label_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Text3d Label Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let mut create = |on_top| {
+            pipelines.insert(Self::create_pipeline(
+                device,
+                &camera,
+                &label_layout,
+                format,
+                depth_format,
+                depth_convention,
+                on_top,
+            ))
+        };
+        let (pipeline, on_top_pipeline) = (create(false), create(true));
+
+        Self {
+            camera,
+            camera_bind_group,
+            label_layout,
+            pipeline,
+            on_top_pipeline,
+            fonts: HashMap::new(),
+            texts: HashMap::new(),
+            format: (format, depth_format, depth_convention),
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        camera: &UniformBuffer<BillboardCameraUniform>,
+        label_layout: &wgpu::BindGroupLayout,
+        format: wgpu::TextureFormat,
+        depth_format: DepthFormat,
+        depth_convention: DepthConvention,
+        on_top: bool,
+    ) -> RenderPipeline {
+        let camera_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Text3d Camera Bind Group Layout"),
+            entries: &camera.layout_desc().entries,
+        });
+        let atlas_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Text3d Atlas Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let module = device.create_shader_module(wgpu::include_wgsl!("../../res/text3d.wgsl"));
+        let shader = Shader::with_final(
+            module,
+            vec![VertexTextured2DColor::layout()],
+            vec![Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        )
+        .with_depth_stencil(depth_format.0, Default::default())
+        .with_depth_convention(depth_convention);
+        // NOTE: the vertex shader moves these to the nearest depth, so later geometry
+        // fails against them and overlapping ones draw in order
+        let shader = if on_top {
+            shader.without_depth_test()
+        } else {
+            shader
+        };
+        RenderPipeline::create_usual(
+            device,
+            &[&camera_layout, &atlas_layout, label_layout],
+            &shader,
+            wgpu::PrimitiveTopology::TriangleList,
+            Some(if on_top { "text3d on top" } else { "text3d" }),
+        )
+    }
+
+    pub fn pipeline(&self, on_top: bool) -> usize {
+        match on_top {
+            true => self.on_top_pipeline,
+            false => self.pipeline,
+        }
+    }
+
+    /// Rebuilds the pipelines when the main pass format changed
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        camera: &Camera,
+        format: wgpu::TextureFormat,
+        depth_format: DepthFormat,
+        depth_convention: DepthConvention,
+        pipelines: &mut Store<RenderPipeline>,
+    ) {
+        let mut camera = BillboardCameraUniform::from(camera);
+        // on top labels are moved to it
+        camera.right[3] = depth_convention.nearest();
+        self.camera.update(queue, camera);
+        if self.format == (format, depth_format, depth_convention) {
+            return;
+        }
+        self.format = (format, depth_format, depth_convention);
+        for on_top in [false, true] {
+            if let Some(pipeline) = pipelines.get_mut(self.pipeline(on_top)) {
+                *pipeline = Self::create_pipeline(
+                    device,
+                    &self.camera,
+                    &self.label_layout,
+                    format,
+                    depth_format,
+                    depth_convention,
+                    on_top,
+                );
+            }
+        }
+    }
+
+    /// Binds the atlas of `font` the first time it is used
+    fn font_bind_group(
+        &mut self,
+        device: &wgpu::Device,
+        font: &str,
+        atlas: &AtlasTexture,
+        bind_groups: &mut Store<wgpu::BindGroup>,
+    ) -> usize {
+        if let Some(bind_group) = self.fonts.get(font) {
+            return *bind_group;
+        }
+        let texture = &atlas.texture;
+        let bind_group =
+            bind_groups.insert((&texture.view, &texture.sampler).into_bind_group(device));
+        self.fonts.insert(font.to_string(), bind_group);
+        bind_group
+    }
+
+    /// Mesh key and width of `text`, built the first time it is shown
+    fn text_mesh(
+        &mut self,
+        device: &wgpu::Device,
+        font: &str,
+        text: &str,
+        atlas: &TextAtlas,
+        meshes: &mut Store<GpuMesh>,
+    ) -> (usize, f32) {
+        let cached = self
+            .texts
+            .entry((font.to_string(), text.to_string()))
+            .or_insert_with(|| {
+                let (mesh, width) = create_label_mesh(atlas, text);
+                CachedText {
+                    mesh: meshes.insert(GpuMesh::from_mesh(&mesh, device, Some(text))),
+                    width,
+                    used: false,
+                }
+            });
+        cached.used = true;
+        (cached.mesh, cached.width)
+    }
+
+    /// Drops the meshes no label showed this frame
+    fn sweep(&mut self, meshes: &mut Store<GpuMesh>) {
+        self.texts.retain(|_, cached| {
+            if !cached.used {
+                meshes.remove_with_events(cached.mesh);
+            }
+            std::mem::replace(&mut cached.used, false)
+        });
+    }
+}
+
+/// Prepares added labels, swaps the meshes of changed ones and writes the placements
+pub fn text3d_system(
+    mut commands: Commands,
+    (device, queue): (Option<Res<Arc<wgpu::Device>>>, Option<Res<wgpu::Queue>>),
+    camera: Option<Res<Camera>>,
+    targets: Res<Option<RenderTargets>>,
+    fonts: Res<Text3dFonts>,
+    mut renderer: ResMut<Option<Text3dRenderer>>,
+    (mut pipelines, mut bind_groups, mut meshes): (
+        ResMut<Store<RenderPipeline>>,
+        ResMut<Store<wgpu::BindGroup>>,
+        ResMut<Store<GpuMesh>>,
+    ),
+    mut labels: Query<(
+        Entity,
+        &Text3d,
+        Option<&Transform>,
+        Option<&mut PreparedText3d>,
+    )>,
+) {
+    let (device, queue) = match (device, queue) {
+        (Some(device), Some(queue)) => (device, queue),
+        _ => return,
+    };
+    let (format, depth_format, depth_convention) = match *targets {
+        Some(targets) => (
+            targets.main_pass_format(),
+            targets.depth_format,
+            targets.depth_convention,
+        ),
+        None => return,
+    };
+
+    let renderer = renderer.get_or_insert_with(|| {
+        Text3dRenderer::new(
+            &device,
+            format,
+            depth_format,
+            depth_convention,
+            &mut pipelines,
+            &mut bind_groups,
+        )
+    });
+    let default_camera = Camera::default();
+    renderer.prepare(
+        &device,
+        &queue,
+        camera.as_deref().unwrap_or(&default_camera),
+        format,
+        depth_format,
+        depth_convention,
+        &mut pipelines,
+    );
+
+    for (entity, label, transform, prepared) in labels.iter_mut() {
+        let atlas = match fonts.get(&label.font) {
+            Some(atlas) => atlas,
+            None => continue,
+        };
+        let font_bind_group =
+            renderer.font_bind_group(&device, &label.font, atlas, &mut bind_groups);
+        let (mesh, width) = renderer.text_mesh(
+            &device,
+            &label.font,
+            &label.text,
+            &atlas.glyphs,
+            &mut meshes,
+        );
+        let placement = Text3dPlacement::new(label, transform, width);
+        let bind_slots = |bind_group| {
+            BindSlots::new()
+                .with(0, renderer.camera_bind_group)
+                .with(1, font_bind_group)
+                .with(2, bind_group)
+        };
+
+        let mut prepared = match prepared {
+            Some(prepared) => prepared,
+            None => {
+                let mut uniform: Uniform<Text3dPlacement> =
+                    Uniform::new_default(&device, wgpu::ShaderStages::VERTEX_FRAGMENT);
+                uniform.update(&placement);
+                uniform.sync_buffer(&queue);
+                let bind_group = bind_groups.insert((&uniform).into_bind_group(&device));
+                commands.entity(entity).insert_bundle((
+                    Refer::<GpuMesh>::new(mesh),
+                    Refer::<RenderPipeline>::new(renderer.pipeline(label.on_top)),
+                    bind_slots(bind_group),
+                    PreparedText3d {
+                        placement: uniform,
+                        bind_group,
+                        font: label.font.clone(),
+                        text: label.text.clone(),
+                        on_top: label.on_top,
+                    },
+                ));
+                continue;
+            }
+        };
+
+        prepared.placement.update(&placement);
+        prepared.placement.sync_buffer(&queue);
+        if prepared.font != label.font
+            || prepared.text != label.text
+            || prepared.on_top != label.on_top
+        {
+            prepared.font = label.font.clone();
+            prepared.text = label.text.clone();
+            prepared.on_top = label.on_top;
+            commands.entity(entity).insert_bundle((
+                Refer::<GpuMesh>::new(mesh),
+                Refer::<RenderPipeline>::new(renderer.pipeline(label.on_top)),
+                bind_slots(prepared.bind_group),
+            ));
+        }
+    }
+    renderer.sweep(&mut meshes);
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, Quaternion, Rotation3, Vector3};
+
+    use crate::{
+        render::resource::bind::UpdateGpuUniform,
+        text::{GlyphDesc, GlyphRect, TextAtlas},
+        transform::Transform,
+    };
+
+    use super::{create_label_mesh, Text3d, Text3dPlacement, Text3dUniform};
+
+    /// Every glyph is a 1x1 pixel advancing 2 pixels
+    fn atlas() -> TextAtlas {
+        TextAtlas {
+            descriptors: vec![
+                GlyphDesc {
+                    x_start: 0,
+                    h: 1,
+                    w: 1,
+                    pitch: 1,
+                    bearing_x: 0,
+                    bearing_y: 1,
+                    advance: 2 << 6,
+                };
+                128
+            ],
+            rects: (0..128).map(|i| GlyphRect::new((i, 0), (i, 0))).collect(),
+            w: 128,
+            h: 1,
+            stride: 128,
+            bytes: vec![255; 128],
+        }
+    }
+
+    fn close(a: [f32; 4], b: [f32; 4]) -> bool {
+        a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-5)
+    }
+
+    #[test]
+    fn label_mesh_starts_at_the_origin() {
+        let (mesh, width) = create_label_mesh(&atlas(), "abc");
+        assert_eq!(mesh.get_vertices().len(), 3 * 6);
+        assert_eq!(width, 5.0);
+
+        // non-ASCII characters are drawn as `?`, one quad each
+        let (mesh, _) = create_label_mesh(&atlas(), "é!");
+        let vertices = mesh.get_vertices();
+        assert_eq!(vertices.len(), 2 * 6);
+        let (question, _) = create_label_mesh(&atlas(), "?");
+        assert_eq!(
+            vertices[0].tex_coords,
+            question.get_vertices()[0].tex_coords
+        );
+    }
+
+    #[test]
+    fn placement_follows_the_transform() {
+        let label = Text3d {
+            world_offset: Vector3::new(0.0, 2.0, 0.0),
+            scale: 0.5,
+            billboard: false,
+            on_top: true,
+            ..Default::default()
+        };
+        let transform = Transform {
+            translation: Vector3::new(1.0, 0.0, -3.0),
+            rotation: Quaternion::from_angle_y(Deg(90.0)),
+            ..Default::default()
+        };
+        let placement = Text3dPlacement::new(&label, Some(&transform), 10.0);
+        assert_eq!(placement.anchor, Vector3::new(1.0, 2.0, -3.0));
+
+        let mut uniform = Text3dUniform::default();
+        placement.update_uniform(&mut uniform);
+        assert_eq!(uniform.anchor, [1.0, 2.0, -3.0, 0.0]);
+        // the glyph x runs along the rotated x axis
+        assert!(close(uniform.right, [0.0, 0.0, -1.0, 0.0]));
+        assert!(close(uniform.up, [0.0, 1.0, 0.0, 0.0]));
+        assert_eq!(uniform.layout, [5.0, 0.5, 1.0, 0.0]);
+
+        let placement = Text3dPlacement::new(&Text3d::default(), None, 0.0);
+        placement.update_uniform(&mut uniform);
+        assert_eq!(uniform.anchor, [0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(uniform.layout[2], 0.0);
+    }
+}
@@ -1,27 +1,69 @@
+use std::{
+    collections::{HashMap, HashSet},
+    num::NonZeroU32,
+    sync::Arc,
+};
+
 use anyhow::*;
+use bevy_asset::{
+    AssetEvent, AssetLoader, AssetServer, Assets, Handle, HandleId, LoadState, LoadedAsset,
+};
+use bevy_ecs::{
+    event::{EventReader, EventWriter},
+    prelude::Component,
+    system::{Commands, Query, Res, ResMut},
+};
+use bevy_reflect::TypeUuid;
 use image::GenericImageView;
 
-use crate::render::resource::bind::{AsBindingSet, Binding, BindingLayoutEntry, IntoBindingSet};
+use crate::{
+    asset::DecodePool,
+    render::{
+        resource::bind::{AsBindingSet, Binding, BindingLayoutEntry, IntoBindingSet},
+        upload::{UploadId, UploadQueue},
+        visibility::{visible, ComputedVisibility},
+    },
+};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PixelFormat {
     G8,
+    /// sRGB encoded color, decoded to linear when sampled
     RGBA8,
+    /// Sampled as stored, for normal maps and other data textures
+    RGBA8Linear,
 }
 
 impl PixelFormat {
+    pub fn rgba8(is_srgb: bool) -> Self {
+        match is_srgb {
+            true => PixelFormat::RGBA8,
+            false => PixelFormat::RGBA8Linear,
+        }
+    }
+
+    pub fn is_srgb(&self) -> bool {
+        matches!(self, PixelFormat::RGBA8)
+    }
+
     pub fn depth(&self) -> u32 {
         match self {
             PixelFormat::G8 => 1,
-            PixelFormat::RGBA8 => 4,
+            PixelFormat::RGBA8 | PixelFormat::RGBA8Linear => 4,
         }
     }
 
     pub fn bytes(&self) -> u32 {
         match self {
             PixelFormat::G8 => 1,
-            PixelFormat::RGBA8 => 4,
+            PixelFormat::RGBA8 | PixelFormat::RGBA8Linear => 4,
         }
     }
+
+    /// Of a single mip level of `dim`
+    pub fn image_bytes(&self, dim: (u32, u32)) -> u64 {
+        dim.0 as u64 * dim.1 as u64 * self.bytes() as u64
+    }
 }
 
 impl From<&PixelFormat> for wgpu::TextureFormat {
@@ -29,6 +71,7 @@ impl From<&PixelFormat> for wgpu::TextureFormat {
         match p {
             PixelFormat::G8 => wgpu::TextureFormat::R8Unorm,
             PixelFormat::RGBA8 => wgpu::TextureFormat::Rgba8UnormSrgb,
+            PixelFormat::RGBA8Linear => wgpu::TextureFormat::Rgba8Unorm,
         }
     }
 }
@@ -53,6 +96,42 @@ impl<'a> RawImage<'a> {
     }
 }
 
+/// What [`Texture::create`] allocates beyond the size and pixel format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextureDescriptorExt<'a> {
+    pub label: Option<&'a str>,
+    pub dim: (u32, u32),
+    pub pixel_format: PixelFormat,
+    pub usage: wgpu::TextureUsages,
+    /// Created with this instead of the format of `pixel_format`, e.g. to render into it
+    /// with the pipelines of the main pass
+    pub format_override: Option<wgpu::TextureFormat>,
+    /// Only the first level is written, the view covers all of them
+    pub mip_levels: u32,
+    /// Multisampled textures can not be bound through the `Binding` of `wgpu::TextureView`
+    pub sample_count: u32,
+}
+
+impl<'a> TextureDescriptorExt<'a> {
+    /// Sampled and written from the CPU, like [`Texture::create_empty`]
+    pub fn new(dim: (u32, u32), pixel_format: PixelFormat) -> Self {
+        Self {
+            label: None,
+            dim,
+            pixel_format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            format_override: None,
+            mip_levels: 1,
+            sample_count: 1,
+        }
+    }
+
+    pub fn format(&self) -> wgpu::TextureFormat {
+        self.format_override
+            .unwrap_or_else(|| (&self.pixel_format).into())
+    }
+}
+
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
@@ -64,16 +143,18 @@ impl Texture {
         todo!()
     }
 
+    /// `is_srgb` is false for normal maps and other data, see [`PixelFormat::RGBA8Linear`]
     pub fn from_bytes(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         bytes: &[u8],
+        is_srgb: bool,
         label: &str,
     ) -> Result<Self> {
         let img = image::load_from_memory(bytes)?;
         let rgba = img.to_rgba8();
         let dim = img.dimensions();
-        let raw_img = RawImage::new(&rgba, dim, PixelFormat::RGBA8);
+        let raw_img = RawImage::new(&rgba, dim, PixelFormat::rgba8(is_srgb));
         Self::from_raw_image(device, queue, &raw_img, Some(label))
     }
 
@@ -83,41 +164,49 @@ impl Texture {
         raw_img: &RawImage,
         label: Option<&str>,
     ) -> Result<Self> {
-        // let rgba = img.to_rgba8(); // RGBA Specific
-        // let dim = img.dimensions();
+        let texture = Self::create_empty(
+            device,
+            (raw_img.dim.0, raw_img.dim.1),
+            raw_img.pixel_format,
+            label,
+        );
+        Self::write_texture(&texture.texture, queue, raw_img);
+        Ok(texture)
+    }
 
+    /// Contents are written later, e.g. through the [`UploadQueue`](crate::render::upload::UploadQueue)
+    pub fn create_empty(
+        device: &wgpu::Device,
+        dim: (u32, u32),
+        pixel_format: PixelFormat,
+        label: Option<&str>,
+    ) -> Self {
+        Self::create(
+            device,
+            &TextureDescriptorExt {
+                label,
+                ..TextureDescriptorExt::new(dim, pixel_format)
+            },
+        )
+    }
+
+    pub fn create(device: &wgpu::Device, desc: &TextureDescriptorExt) -> Self {
         let size = wgpu::Extent3d {
-            width: raw_img.dim.0,
-            height: raw_img.dim.1,
+            width: desc.dim.0,
+            height: desc.dim.1,
             depth_or_array_layers: 1,
         };
 
         let texture = device.create_texture(&wgpu::TextureDescriptor {
-            label,
+            label: desc.label,
             size,
-            mip_level_count: 1,
-            sample_count: 1,
+            mip_level_count: desc.mip_levels,
+            sample_count: desc.sample_count,
             dimension: wgpu::TextureDimension::D2,
-            format: (&raw_img.pixel_format).into(), // wgpu::TextureFormat::Rgba8UnormSrgb, // RGBA Specific
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            format: desc.format(),
+            usage: desc.usage,
         });
 
-        queue.write_texture(
-            wgpu::ImageCopyTexture {
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            raw_img.bytes,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: std::num::NonZeroU32::new(raw_img.bytes_per_row()), // RGBA Specific
-                rows_per_image: std::num::NonZeroU32::new(raw_img.dim.1),
-            },
-            size,
-        );
-
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             // label,
@@ -134,11 +223,62 @@ impl Texture {
                                  // border_color,
         });
 
-        Ok(Self {
+        Self {
             texture,
             view,
             sampler,
-        })
+        }
+    }
+
+    /// Rendered into and then sampled, e.g. by a
+    /// [`RenderToTexture`](crate::render::render_target::RenderToTexture).
+    /// `format` has to be the one the pipelines drawing into it target.
+    /// The size is fixed, it does not follow the window
+    pub fn create_render_target(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+    ) -> Self {
+        Self::create(
+            device,
+            &TextureDescriptorExt {
+                label: Some("Render Target"),
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::COPY_SRC,
+                format_override: Some(format),
+                ..TextureDescriptorExt::new((width, height), PixelFormat::RGBA8)
+            },
+        )
+    }
+
+    /// Overwrites the contents in place, views and bind groups stay valid.
+    /// `raw_img` must have the size and format the texture was created with.
+    pub fn write_raw_image(&self, queue: &wgpu::Queue, raw_img: &RawImage) {
+        Self::write_texture(&self.texture, queue, raw_img);
+    }
+
+    fn write_texture(texture: &wgpu::Texture, queue: &wgpu::Queue, raw_img: &RawImage) {
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            raw_img.bytes,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(raw_img.bytes_per_row()), // RGBA Specific
+                rows_per_image: std::num::NonZeroU32::new(raw_img.dim.1),
+            },
+            wgpu::Extent3d {
+                width: raw_img.dim.0,
+                height: raw_img.dim.1,
+                depth_or_array_layers: 1,
+            },
+        );
     }
 
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float; // 1.
@@ -147,11 +287,31 @@ impl Texture {
         device: &wgpu::Device,
         config: &wgpu::SurfaceConfiguration,
         label: &str,
+    ) -> Self {
+        Self::create_depth_texture_sized(device, config.width, config.height, label)
+    }
+
+    pub fn create_depth_texture_sized(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        label: &str,
+    ) -> Self {
+        Self::create_depth_texture_with_format(device, width, height, Self::DEPTH_FORMAT, label)
+    }
+
+    /// `format` is a depth or depth-stencil format
+    pub fn create_depth_texture_with_format(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        label: &str,
     ) -> Self {
         let size = wgpu::Extent3d {
             // 2.
-            width: config.width,
-            height: config.height,
+            width,
+            height,
             depth_or_array_layers: 1,
         };
         let desc = wgpu::TextureDescriptor {
@@ -160,7 +320,7 @@ impl Texture {
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: Self::DEPTH_FORMAT,
+            format,
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT // 3.
                 | wgpu::TextureUsages::TEXTURE_BINDING,
         };
@@ -221,6 +381,165 @@ impl Binding for wgpu::Sampler {
     }
 }
 
+/// Same sized layers in one texture, e.g. the skybox faces
+pub struct TextureArray {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    pub sampler: wgpu::Sampler,
+    pub layers: u32,
+}
+
+impl TextureArray {
+    pub fn from_raw_images(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        raw_imgs: &[RawImage],
+        label: Option<&str>,
+    ) -> Result<Self> {
+        let first = match raw_imgs.first() {
+            Some(first) => first,
+            None => bail!("Texture array needs at least one layer"),
+        };
+        if raw_imgs
+            .iter()
+            .any(|raw_img| raw_img.dim != first.dim || raw_img.pixel_format != first.pixel_format)
+        {
+            bail!("Texture array layers differ in size or format");
+        }
+
+        let layers = raw_imgs.len() as u32;
+        let size = wgpu::Extent3d {
+            width: first.dim.0,
+            height: first.dim.1,
+            depth_or_array_layers: layers,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label,
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: (&first.pixel_format).into(),
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        });
+
+        for (layer, raw_img) in raw_imgs.iter().enumerate() {
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: layer as u32,
+                    },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                raw_img.bytes,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(raw_img.bytes_per_row()),
+                    rows_per_image: NonZeroU32::new(raw_img.dim.1),
+                },
+                wgpu::Extent3d {
+                    depth_or_array_layers: 1,
+                    ..size
+                },
+            );
+        }
+
+        // NOTE: a single layer would default to a D2 view
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            array_layer_count: NonZeroU32::new(layers),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Ok(Self {
+            texture,
+            view,
+            sampler,
+            layers,
+        })
+    }
+
+    /// Bind as `(&array.array_view(), &array.sampler)`
+    pub fn array_view(&self) -> TextureArrayView<'_> {
+        TextureArrayView(&self.view)
+    }
+}
+
+/// View over the layers of one texture, `texture_2d_array<f32>` in WGSL
+pub struct TextureArrayView<'a>(pub &'a wgpu::TextureView);
+
+impl TextureArrayView<'_> {
+    pub fn layout_entry() -> BindingLayoutEntry {
+        BindingLayoutEntry {
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2Array,
+                multisampled: false,
+            },
+            count: None,
+        }
+    }
+}
+
+impl Binding for TextureArrayView<'_> {
+    fn get_layout_entry(&self) -> BindingLayoutEntry {
+        Self::layout_entry()
+    }
+
+    fn get_resource<'a>(&'a self) -> wgpu::BindingResource<'a> {
+        wgpu::BindingResource::TextureView(self.0)
+    }
+}
+
+/// Separate textures bound together, `binding_array<texture_2d<f32>, N>` in WGSL.
+/// Needs `Features::TEXTURE_BINDING_ARRAY`.
+pub struct TextureViewArray<'a> {
+    views: &'a [&'a wgpu::TextureView],
+}
+
+impl<'a> TextureViewArray<'a> {
+    pub fn new(views: &'a [&'a wgpu::TextureView]) -> Self {
+        assert!(!views.is_empty(), "binding arrays can not be empty");
+        Self { views }
+    }
+
+    pub fn layout_entry(len: usize) -> BindingLayoutEntry {
+        BindingLayoutEntry {
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: NonZeroU32::new(len as u32),
+        }
+    }
+}
+
+impl Binding for TextureViewArray<'_> {
+    fn get_layout_entry(&self) -> BindingLayoutEntry {
+        Self::layout_entry(self.views.len())
+    }
+
+    fn get_resource<'a>(&'a self) -> wgpu::BindingResource<'a> {
+        wgpu::BindingResource::TextureViewArray(self.views)
+    }
+}
+
 impl<'a> AsBindingSet<'a> for Texture {
     type Set = (&'a wgpu::TextureView, &'a wgpu::Sampler);
 
@@ -235,3 +554,775 @@ impl<'a> IntoBindingSet for &'a Texture {
         (&self.view, &self.sampler)
     }
 }
+
+/// Decoded RGBA8 image, uploaded into the `TextureStore` by `prepare_images_system`
+#[derive(TypeUuid)]
+#[uuid = "8628FE7C-A4E9-4056-91BD-FD6AA7817E39"]
+pub struct Image {
+    pub bytes: Vec<u8>,
+    pub dim: (u32, u32),
+    /// Loaded images are sRGB, clear it through `Assets::get_mut` for normal maps and other
+    /// data, the texture is then recreated as `Rgba8Unorm`
+    pub is_srgb: bool,
+}
+
+impl Image {
+    pub fn as_raw_image(&self) -> RawImage<'_> {
+        RawImage::new(&self.bytes, self.dim, PixelFormat::rgba8(self.is_srgb))
+    }
+
+    /// A png or jpeg file, as sRGB
+    pub fn decode(bytes: &[u8]) -> anyhow::Result<Self> {
+        let img = image::load_from_memory(bytes)?;
+        Ok(Image {
+            bytes: img.to_rgba8().into_raw(),
+            dim: img.dimensions(),
+            is_srgb: true,
+        })
+    }
+}
+
+/// Decodes on the [`DecodePool`]
+pub struct ImageLoader {
+    pool: Arc<DecodePool>,
+}
+
+impl ImageLoader {
+    pub fn new(pool: Arc<DecodePool>) -> Self {
+        Self { pool }
+    }
+}
+
+impl AssetLoader for ImageLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut bevy_asset::LoadContext,
+    ) -> bevy_asset::BoxedFuture<'a, anyhow::Result<(), anyhow::Error>> {
+        let bytes = bytes.to_vec();
+        let image = self.pool.decode(move || Image::decode(&bytes));
+        Box::pin(async move {
+            load_context.set_default_asset(LoadedAsset::new(image.await?));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["png", "jpg", "jpeg"]
+    }
+}
+
+pub struct GpuImage {
+    pub texture: Arc<Texture>,
+    pub dim: (u32, u32),
+    pub pixel_format: PixelFormat,
+}
+
+impl GpuImage {
+    pub fn bytes(&self) -> u64 {
+        self.pixel_format.image_bytes(self.dim)
+    }
+}
+
+/// Uploaded images by handle, with their size for a VRAM budget.
+///
+/// [`TextureStore::unload`] drops an image and sends [`TextureUnloaded`], the materials
+/// sampling it through a [`TextureHandle`] then swap in [`DefaultTextures::white`] so the
+/// texture is freed with their bind groups. With a budget set the least recently drawn
+/// images are unloaded once it is exceeded, images drawn in the last frame are kept.
+/// Unloaded images are uploaded again once an entity sampling them is visible, or when
+/// their asset is modified.
+#[derive(Default)]
+pub struct TextureStore {
+    images: HashMap<HandleId, GpuImage>,
+    usage: TextureUsage,
+    budget: Option<u64>,
+    // published as TextureUnloaded events
+    unloaded: Vec<HandleId>,
+}
+
+impl TextureStore {
+    pub fn get(&self, id: &HandleId) -> Option<&GpuImage> {
+        self.images.get(id)
+    }
+
+    pub fn contains_key(&self, id: &HandleId) -> bool {
+        self.images.contains_key(id)
+    }
+
+    /// Counts as used this frame, so a new image is not evicted before it is drawn
+    pub fn insert(&mut self, id: HandleId, image: GpuImage) {
+        self.usage.insert(id, image.bytes());
+        self.images.insert(id, image);
+    }
+
+    /// Without [`TextureUnloaded`], e.g. when the asset itself is removed
+    pub fn remove(&mut self, id: &HandleId) -> Option<GpuImage> {
+        self.usage.remove(id);
+        self.images.remove(id)
+    }
+
+    /// Returns false if `id` was not loaded
+    pub fn unload(&mut self, id: HandleId) -> bool {
+        let unloaded = self.remove(&id).is_some();
+        if unloaded {
+            self.unloaded.push(id);
+        }
+        unloaded
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.usage.total_bytes
+    }
+
+    pub fn bytes(&self, id: &HandleId) -> Option<u64> {
+        self.usage.entries.get(id).map(|entry| entry.bytes)
+    }
+
+    pub fn budget(&self) -> Option<u64> {
+        self.budget
+    }
+
+    /// Bytes to keep loaded, `None` never evicts
+    pub fn set_budget(&mut self, budget: Option<u64>) {
+        self.budget = budget;
+    }
+
+    pub fn mark_used(&mut self, id: &HandleId) {
+        self.usage.mark_used(id);
+    }
+
+    /// Unloads the least recently used images until the budget is met,
+    /// returns how many were unloaded
+    pub fn evict_over_budget(&mut self) -> usize {
+        let budget = match self.budget {
+            Some(budget) => budget,
+            None => return 0,
+        };
+        let evicted = self.usage.over_budget(budget);
+        for id in evicted.iter() {
+            self.unload(*id);
+        }
+        evicted.len()
+    }
+}
+
+/// Byte accounting of `TextureStore`, separate so it can be tested without a device
+#[derive(Default)]
+struct TextureUsage {
+    entries: HashMap<HandleId, UsageEntry>,
+    total_bytes: u64,
+    frame: u64,
+}
+
+struct UsageEntry {
+    bytes: u64,
+    last_used: u64,
+}
+
+impl TextureUsage {
+    fn insert(&mut self, id: HandleId, bytes: u64) {
+        self.remove(&id);
+        self.entries.insert(
+            id,
+            UsageEntry {
+                bytes,
+                last_used: self.frame,
+            },
+        );
+        self.total_bytes += bytes;
+    }
+
+    fn remove(&mut self, id: &HandleId) {
+        if let Some(entry) = self.entries.remove(id) {
+            self.total_bytes -= entry.bytes;
+        }
+    }
+
+    fn mark_used(&mut self, id: &HandleId) {
+        if let Some(entry) = self.entries.get_mut(id) {
+            entry.last_used = self.frame;
+        }
+    }
+
+    fn next_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    /// Least recently used first, larger first among equally old ones.
+    /// Those used in the current frame are never picked, even if the budget stays exceeded.
+    fn over_budget(&self, budget: u64) -> Vec<HandleId> {
+        let mut candidates: Vec<(&HandleId, &UsageEntry)> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.last_used < self.frame)
+            .collect();
+        candidates.sort_by_key(|(_, entry)| (entry.last_used, std::cmp::Reverse(entry.bytes)));
+
+        let mut total = self.total_bytes;
+        candidates
+            .into_iter()
+            .take_while(|(_, entry)| {
+                let over = total > budget;
+                total -= entry.bytes;
+                over
+            })
+            .map(|(id, _)| *id)
+            .collect()
+    }
+}
+
+/// Sent when an image is unloaded from the [`TextureStore`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextureUnloaded(pub HandleId);
+
+/// The image the material of an entity samples, tracks its use in the [`TextureStore`]
+/// and swaps in the placeholder when it is unloaded
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextureHandle(pub HandleId);
+
+/// Starts the next frame of the [`TextureStore`] and marks the images of visible entities,
+/// runs in `RenderStage::Prepare`
+pub fn track_texture_use_system(
+    mut store: ResMut<TextureStore>,
+    textured: Query<(&TextureHandle, Option<&ComputedVisibility>)>,
+) {
+    store.usage.next_frame();
+    for handle in visible(textured.iter()) {
+        store.mark_used(&handle.0);
+    }
+}
+
+/// Evicts over the budget and publishes the unloads, the materials swap their textures
+/// before the render stages
+pub fn unload_textures_system(
+    mut store: ResMut<TextureStore>,
+    mut events: EventWriter<TextureUnloaded>,
+) {
+    store.evict_over_budget();
+    if !store.unloaded.is_empty() {
+        events.send_batch(store.unloaded.drain(..).map(TextureUnloaded));
+    }
+}
+
+/// Engine owned textures, bound in place of images that are not there. Created with the
+/// device by [`prepare_images_system`]
+pub struct DefaultTextures {
+    /// 1x1 white, for images still loading or unloaded
+    pub white: GpuImage,
+    /// 1x1 flat normal, for normal maps still loading
+    pub normal: GpuImage,
+    /// Magenta and black checkerboard, for images that failed to load
+    pub error: GpuImage,
+}
+
+impl DefaultTextures {
+    pub const NORMAL: [u8; 4] = [128, 128, 255, 255];
+    pub const ERROR_SIZE: u32 = 8;
+
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let create = |bytes: &[u8], dim: (u32, u32), pixel_format: PixelFormat, label: &str| {
+            let texture = Texture::from_raw_image(
+                device,
+                queue,
+                &RawImage::new(bytes, dim, pixel_format),
+                Some(label),
+            )
+            .expect("default textures can be created");
+            GpuImage {
+                texture: Arc::new(texture),
+                dim,
+                pixel_format,
+            }
+        };
+        let error = checkerboard(Self::ERROR_SIZE, 2, [255, 0, 255, 255], [0, 0, 0, 255]);
+        Self {
+            white: create(
+                &[255; 4],
+                (1, 1),
+                PixelFormat::RGBA8,
+                "Default White Texture",
+            ),
+            normal: create(
+                &Self::NORMAL,
+                (1, 1),
+                PixelFormat::RGBA8Linear,
+                "Default Normal Texture",
+            ),
+            error: create(
+                &error,
+                (Self::ERROR_SIZE, Self::ERROR_SIZE),
+                PixelFormat::RGBA8,
+                "Error Texture",
+            ),
+        }
+    }
+
+    pub fn get(&self, bound: BoundTexture) -> Option<&GpuImage> {
+        match bound {
+            BoundTexture::Placeholder => Some(&self.white),
+            BoundTexture::Error => Some(&self.error),
+            BoundTexture::Loaded => None,
+        }
+    }
+}
+
+/// RGBA8 rows of a `size` square, alternating `a` and `b` every `cell` pixels
+pub fn checkerboard(size: u32, cell: u32, a: [u8; 4], b: [u8; 4]) -> Vec<u8> {
+    (0..size)
+        .flat_map(|y| (0..size).map(move |x| (x / cell + y / cell) % 2 == 0))
+        .flat_map(|even| if even { a } else { b })
+        .collect()
+}
+
+/// Which texture the material of a [`TextureHandle`] samples, resolved by
+/// [`resolve_textures_system`]
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoundTexture {
+    /// [`DefaultTextures::white`] until the image is uploaded
+    Placeholder,
+    Loaded,
+    /// [`DefaultTextures::error`], for good once the image failed to load
+    Error,
+}
+
+/// Sent for an image that failed to load while a material waits for it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextureLoadFailed {
+    pub id: HandleId,
+    pub path: String,
+}
+
+/// Images that failed to load, with their path, each is warned about once
+#[derive(Default)]
+pub struct MissingTextures {
+    failed: HashMap<HandleId, String>,
+}
+
+impl MissingTextures {
+    /// Returns false if `id` already failed
+    pub fn fail(&mut self, id: HandleId, path: &str) -> bool {
+        if self.failed.contains_key(&id) {
+            return false;
+        }
+        log::warn!(
+            "Texture {} failed to load, drawn with the error texture",
+            path
+        );
+        self.failed.insert(id, path.to_string());
+        true
+    }
+
+    pub fn is_failed(&self, id: &HandleId) -> bool {
+        self.failed.contains_key(id)
+    }
+
+    /// Paths of the failed images, one warning was logged for each
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.failed.values().map(String::as_str)
+    }
+}
+
+/// Sends [`TextureLoadFailed`] for the images placeholders wait for that failed to load
+pub fn detect_failed_textures_system(
+    asset_server: Res<AssetServer>,
+    missing: Res<MissingTextures>,
+    mut events: EventWriter<TextureLoadFailed>,
+    textured: Query<(&TextureHandle, &BoundTexture)>,
+) {
+    let waiting: HashSet<HandleId> = textured
+        .iter()
+        .filter(|(handle, bound)| {
+            **bound == BoundTexture::Placeholder && !missing.is_failed(&handle.0)
+        })
+        .map(|(handle, _)| handle.0)
+        .collect();
+    for id in waiting {
+        if asset_server.get_load_state(id) == LoadState::Failed {
+            let path = asset_server.get_handle_path(id).map_or_else(
+                || format!("{:?}", id),
+                |path| path.path().display().to_string(),
+            );
+            events.send(TextureLoadFailed { id, path });
+        }
+    }
+}
+
+/// Binds uploaded images in place of their placeholders, and the error texture for good
+/// once an image failed. Only changes [`BoundTexture`], the materials swap their textures
+/// in [`bind_resolved_textures_system`](crate::render::material::bind_resolved_textures_system)
+pub fn resolve_textures_system(
+    store: Res<TextureStore>,
+    mut missing: ResMut<MissingTextures>,
+    mut events: EventReader<TextureLoadFailed>,
+    mut textured: Query<(&TextureHandle, &mut BoundTexture)>,
+) {
+    for event in events.iter() {
+        missing.fail(event.id, &event.path);
+    }
+    for (handle, mut bound) in textured.iter_mut() {
+        let resolved = if store.contains_key(&handle.0) {
+            BoundTexture::Loaded
+        } else if missing.is_failed(&handle.0) {
+            BoundTexture::Error
+        } else {
+            continue;
+        };
+        if *bound == BoundTexture::Placeholder {
+            *bound = resolved;
+        }
+    }
+}
+
+/// Images written through the `UploadQueue`, moved into the `TextureStore` once complete
+#[derive(Default)]
+pub struct PendingImages(HashMap<HandleId, (GpuImage, UploadId)>);
+
+impl PendingImages {
+    pub fn contains(&self, id: &HandleId) -> bool {
+        self.0.contains_key(id)
+    }
+}
+
+/// Uploads created images through the `UploadQueue` and re-uploads modified ones, and
+/// unloaded ones a visible entity waits for. Same sized images are written in place so
+/// existing bind groups see the new contents.
+///
+/// NOTE: in place writes are immediate, a partially written texture would already be bound
+pub fn prepare_images_system(
+    mut commands: Commands,
+    device: Option<Res<Arc<wgpu::Device>>>,
+    queue: Option<Res<wgpu::Queue>>,
+    mut events: EventReader<AssetEvent<Image>>,
+    images: Res<Assets<Image>>,
+    mut gpu_images: ResMut<TextureStore>,
+    mut pending: ResMut<PendingImages>,
+    mut uploads: ResMut<UploadQueue>,
+    defaults: Option<Res<DefaultTextures>>,
+    textured: Query<(&TextureHandle, &BoundTexture, Option<&ComputedVisibility>)>,
+) {
+    let (device, queue) = match (device, queue) {
+        (Some(device), Some(queue)) => (device, queue),
+        _ => return,
+    };
+    if defaults.is_none() {
+        commands.insert_resource(DefaultTextures::new(&device, &queue));
+    }
+
+    for event in events.iter() {
+        match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+                let image = match images.get(handle) {
+                    Some(image) => image,
+                    None => continue,
+                };
+                let raw_img = image.as_raw_image();
+                match gpu_images.get(&handle.id) {
+                    Some(gpu_image)
+                        if gpu_image.dim == image.dim
+                            && gpu_image.pixel_format == raw_img.pixel_format =>
+                    {
+                        gpu_image.texture.write_raw_image(&queue, &raw_img);
+                    }
+                    previous => {
+                        if previous.is_some() {
+                            log::warn!(
+                                "Reloaded image changed size or format, bind groups using the old texture keep it"
+                            );
+                        }
+                        queue_image_upload(&device, &mut uploads, &mut pending, handle.id, image);
+                    }
+                }
+            }
+            AssetEvent::Removed { handle } => {
+                gpu_images.remove(&handle.id);
+                pending.0.remove(&handle.id);
+            }
+        }
+    }
+
+    // unloaded by the budget, the entity is drawn with the placeholder until it is back
+    let waiting: HashSet<HandleId> = visible(
+        textured
+            .iter()
+            .map(|(handle, bound, visibility)| ((handle, bound), visibility)),
+    )
+    .filter(|(_, bound)| **bound == BoundTexture::Placeholder)
+    .map(|(handle, _)| handle.0)
+    .filter(|id| !gpu_images.contains_key(id) && !pending.contains(id))
+    .collect();
+    for id in waiting {
+        if let Some(image) = images.get(&Handle::weak(id)) {
+            queue_image_upload(&device, &mut uploads, &mut pending, id, image);
+        }
+    }
+
+    let done: Vec<HandleId> = pending
+        .0
+        .iter()
+        .filter(|(_, (_, upload))| uploads.is_done(*upload))
+        .map(|(id, _)| *id)
+        .collect();
+    for id in done {
+        let (gpu_image, _) = pending.0.remove(&id).unwrap();
+        gpu_images.insert(id, gpu_image);
+    }
+}
+
+/// Creates the texture of `image` and writes it through the `UploadQueue`, it moves into
+/// the `TextureStore` once the upload is done
+fn queue_image_upload(
+    device: &wgpu::Device,
+    uploads: &mut UploadQueue,
+    pending: &mut PendingImages,
+    id: HandleId,
+    image: &Image,
+) {
+    let raw_img = image.as_raw_image();
+    let texture = Arc::new(Texture::create_empty(
+        device,
+        image.dim,
+        raw_img.pixel_format,
+        None,
+    ));
+    let upload = uploads.write_texture(texture.clone(), &raw_img);
+    // NOTE: replaces an upload still in progress, it finishes unused
+    pending.0.insert(
+        id,
+        (
+            GpuImage {
+                texture,
+                dim: image.dim,
+                pixel_format: raw_img.pixel_format,
+            },
+            upload,
+        ),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bevy_asset::HandleId;
+    use bevy_ecs::{
+        event::Events,
+        schedule::{ParallelSystemDescriptorCoercion, Stage, SystemStage},
+        world::World,
+    };
+
+    use crate::{
+        create_headless_wgpu_resources,
+        render::{
+            material::{bind_resolved_textures_system, TextureMaterial},
+            resource::pipeline::RenderPipeline,
+        },
+        util::{Refer, Store},
+    };
+
+    use super::{
+        checkerboard, resolve_textures_system, BoundTexture, DefaultTextures, MissingTextures,
+        PixelFormat, TextureArrayView, TextureDescriptorExt, TextureHandle, TextureLoadFailed,
+        TextureStore, TextureUsage, TextureViewArray,
+    };
+
+    #[test]
+    fn array_layout_entries() {
+        // texture_2d_array<f32>: one binding viewing every layer
+        let entry = TextureArrayView::layout_entry().with_binding(0);
+        assert_eq!(
+            entry,
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2Array,
+                    multisampled: false,
+                },
+                count: None,
+            }
+        );
+
+        // binding_array<texture_2d<f32>, 6>: six 2D views in one binding
+        let entry = TextureViewArray::layout_entry(6).with_binding(1);
+        assert_eq!(
+            entry,
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: std::num::NonZeroU32::new(6),
+            }
+        );
+    }
+
+    #[test]
+    fn descriptor_defaults_to_a_sampled_upload_target() {
+        let desc = TextureDescriptorExt::new((4, 4), PixelFormat::RGBA8Linear);
+        assert_eq!(
+            desc.usage,
+            wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST
+        );
+        assert_eq!((desc.mip_levels, desc.sample_count), (1, 1));
+        assert_eq!(desc.format(), wgpu::TextureFormat::Rgba8Unorm);
+
+        let desc = TextureDescriptorExt {
+            format_override: Some(wgpu::TextureFormat::Rgba16Float),
+            ..desc
+        };
+        assert_eq!(desc.format(), wgpu::TextureFormat::Rgba16Float);
+    }
+
+    #[test]
+    fn usage_counts_bytes_of_each_image() {
+        assert_eq!(PixelFormat::RGBA8.image_bytes((256, 128)), 256 * 128 * 4);
+        assert_eq!(PixelFormat::G8.image_bytes((100, 3)), 300);
+        // no u32 overflow for large textures
+        assert_eq!(
+            PixelFormat::RGBA8Linear.image_bytes((65536, 65536)),
+            1 << 34
+        );
+
+        let (a, b) = (HandleId::from("a.png"), HandleId::from("b.png"));
+        let mut usage = TextureUsage::default();
+        usage.insert(a, 100);
+        usage.insert(b, 50);
+        assert_eq!(usage.total_bytes, 150);
+        // reuploaded at another size
+        usage.insert(a, 40);
+        assert_eq!(usage.total_bytes, 90);
+        usage.remove(&b);
+        usage.remove(&b);
+        assert_eq!(usage.total_bytes, 40);
+    }
+
+    #[test]
+    fn least_recently_used_go_first() {
+        let ids: Vec<HandleId> = ["a", "b", "c", "d"]
+            .into_iter()
+            .map(HandleId::from)
+            .collect();
+        let mut usage = TextureUsage::default();
+        // a is the oldest, c and d are equally old and d is larger
+        usage.insert(ids[0], 10);
+        usage.next_frame();
+        usage.insert(ids[1], 10);
+        usage.insert(ids[2], 10);
+        usage.insert(ids[3], 20);
+        usage.next_frame();
+        usage.mark_used(&ids[1]);
+        assert_eq!(usage.total_bytes, 50);
+
+        assert!(usage.over_budget(50).is_empty());
+        assert_eq!(usage.over_budget(45), [ids[0]]);
+        assert_eq!(usage.over_budget(25), [ids[0], ids[3]]);
+        // b was used this frame, it stays even though the budget is exceeded
+        assert_eq!(usage.over_budget(0), [ids[0], ids[3], ids[2]]);
+    }
+
+    #[test]
+    fn error_texture_is_a_checkerboard() {
+        let (a, b) = ([255, 0, 255, 255], [0, 0, 0, 255]);
+        let pixels = checkerboard(4, 2, a, b);
+        assert_eq!(pixels.len(), 4 * 4 * 4);
+        let pixel = |x: usize, y: usize| &pixels[(y * 4 + x) * 4..][..4];
+        assert_eq!(pixel(0, 0), a);
+        assert_eq!(pixel(1, 1), a);
+        assert_eq!(pixel(2, 0), b);
+        assert_eq!(pixel(0, 2), b);
+        assert_eq!(pixel(3, 3), a);
+    }
+
+    #[test]
+    fn failed_textures_bind_the_error_texture_once() {
+        let mut world = World::new();
+        world.init_resource::<TextureStore>();
+        world.init_resource::<MissingTextures>();
+        world.init_resource::<Events<TextureLoadFailed>>();
+        world.init_resource::<Store<wgpu::BindGroup>>();
+        let mut stage = SystemStage::single_threaded()
+            .with_system(resolve_textures_system)
+            .with_system(
+                bind_resolved_textures_system::<TextureMaterial>.after(resolve_textures_system),
+            );
+        // NOTE: binding needs the default textures, without an adapter only the resolve runs
+        let adapter = create_headless_wgpu_resources(&mut world, 1, 1);
+        if adapter {
+            let defaults = DefaultTextures::new(
+                world.resource::<Arc<wgpu::Device>>(),
+                world.resource::<wgpu::Queue>(),
+            );
+            world.insert_resource(defaults);
+        } else {
+            eprintln!("No adapter available, skipping the material binding");
+        }
+
+        let (missing, loading) = (HandleId::from("missing.png"), HandleId::from("rock.png"));
+        let first = world
+            .spawn()
+            .insert_bundle((TextureHandle(missing), BoundTexture::Placeholder))
+            .id();
+        if adapter {
+            let white = world.resource::<DefaultTextures>().white.texture.clone();
+            world.entity_mut(first).insert_bundle((
+                TextureMaterial { texture: white },
+                Refer::<RenderPipeline>::new(3),
+            ));
+        }
+        let second = world
+            .spawn()
+            .insert_bundle((TextureHandle(missing), BoundTexture::Placeholder))
+            .id();
+        let waiting = world
+            .spawn()
+            .insert_bundle((TextureHandle(loading), BoundTexture::Placeholder))
+            .id();
+
+        let failed = TextureLoadFailed {
+            id: missing,
+            path: "missing.png".to_string(),
+        };
+        let mut events = world.resource_mut::<Events<TextureLoadFailed>>();
+        events.send(failed.clone());
+        events.send(failed.clone());
+        stage.run(&mut world);
+
+        for entity in [first, second] {
+            assert_eq!(
+                world.get::<BoundTexture>(entity),
+                Some(&BoundTexture::Error)
+            );
+        }
+        assert_eq!(
+            world.get::<BoundTexture>(waiting),
+            Some(&BoundTexture::Placeholder)
+        );
+        let paths: Vec<&str> = world.resource::<MissingTextures>().paths().collect();
+        assert_eq!(paths, ["missing.png"]);
+        if adapter {
+            // the texture is swapped, the pipeline is kept
+            let material = world.get::<TextureMaterial>(first).unwrap();
+            let error = &world.resource::<DefaultTextures>().error.texture;
+            assert!(Arc::ptr_eq(&material.texture, error));
+            assert_eq!(
+                world.get::<Refer<RenderPipeline>>(first),
+                Some(&Refer::new(3))
+            );
+        }
+
+        // failing again does not warn again
+        world
+            .resource_mut::<Events<TextureLoadFailed>>()
+            .send(failed);
+        stage.run(&mut world);
+        assert_eq!(world.resource::<MissingTextures>().paths().count(), 1);
+        assert!(!world
+            .resource_mut::<MissingTextures>()
+            .fail(missing, "missing.png"));
+    }
+}
@@ -0,0 +1,774 @@
+use std::{ops::Range, sync::Arc};
+
+use bevy_app::{CoreStage, Plugin};
+use bevy_ecs::{
+    entity::Entity,
+    prelude::Component,
+    query::With,
+    schedule::ParallelSystemDescriptorCoercion,
+    system::{Commands, Query, Res, ResMut},
+};
+use bytemuck::{Pod, Zeroable};
+use cgmath::{EuclideanSpace, Point3, SquareMatrix, Vector2, Vector3, Zero};
+use repr_trait::C;
+
+use crate::{
+    atlas::Rect,
+    camera::{Camera, CameraUniform},
+    render::{
+        mesh::{GpuMesh, Mesh},
+        prepare_frame_system,
+        resource::{
+            bind::{BindSlots, BindingSet, UniformBuffer},
+            buffer::{Indices, InstanceUnit, MeshVertex, Vertex},
+            pipeline::RenderPipeline,
+            shader::Shader,
+        },
+        targets::RenderTargets,
+        visibility::Visibility,
+        DepthConvention, DepthFormat, InstanceData,
+    },
+    transform::Transform,
+    util::{Refer, Store},
+    FlatSystemLabels, RenderStage,
+};
+
+/// Tiles per side of a chunk, each chunk is one instance buffer and one draw
+pub const CHUNK_SIZE: u32 = 32;
+
+/// Tile grids in the xy plane, drawn in the main pass.
+///
+/// Spawn a [`Tilemap`], its chunks are spawned as entities of their own on the next
+/// prepare. Chunks outside the [`visible_rect`] of the camera are hidden, and only
+/// chunks whose tiles changed are written again.
+pub struct FlatTilemapPlugin;
+impl Plugin for FlatTilemapPlugin {
+    fn build(&self, app: &mut bevy_app::App) {
+        app.init_resource::<Option<TilemapRenderer>>()
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                cull_tilemap_chunks_system
+                    .after(FlatSystemLabels::TransformPropagate)
+                    .before(FlatSystemLabels::VisibilityCompute),
+            )
+            .add_system_to_stage(
+                RenderStage::Prepare,
+                prepare_tilemaps_system
+                    .label(FlatSystemLabels::RenderPrepare)
+                    .after(prepare_frame_system),
+            );
+    }
+}
+
+/// Index into [`TileAtlas::tiles`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TileId(pub u32);
+
+impl TileId {
+    /// Not drawn
+    pub const EMPTY: TileId = TileId(u32::MAX);
+
+    pub fn is_empty(&self) -> bool {
+        *self == TileId::EMPTY
+    }
+}
+
+/// Tile sheet of a [`Tilemap`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct TileAtlas {
+    /// Bind group of the atlas view and sampler in `Store<wgpu::BindGroup>`,
+    /// e.g. `(&texture.view, &texture.sampler).into_bind_group(device)`
+    pub bind_group: usize,
+    /// Texture coordinates of each [`TileId`]
+    pub tiles: Vec<Rect>,
+}
+
+impl TileAtlas {
+    /// Sheet of `columns` x `rows` tiles of the same size, numbered row by row
+    /// from the top left
+    pub fn grid(bind_group: usize, columns: u32, rows: u32) -> Self {
+        let (width, height) = (1.0 / columns as f32, 1.0 / rows as f32);
+        let tiles = (0..rows)
+            .flat_map(|row| (0..columns).map(move |column| (column, row)))
+            .map(|(column, row)| Rect {
+                min: (column as f32 * width, row as f32 * height),
+                max: ((column + 1) as f32 * width, (row + 1) as f32 * height),
+            })
+            .collect();
+        Self { bind_group, tiles }
+    }
+}
+
+/// Axis aligned rectangle in the xy plane, in world units
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WorldRect {
+    pub min: Vector2<f32>,
+    pub max: Vector2<f32>,
+}
+
+/// What a camera sees of the plane at `z`, the bounds of where the rays through the
+/// corners of the screen hit it. Orthographic and perspective projections, of either
+/// depth convention. `None` if the view projection can not be inverted, or a corner
+/// ray misses the plane in front of the camera, e.g. it looks past the horizon
+pub fn visible_rect(camera: &Camera, z: f32) -> Option<WorldRect> {
+    let view_proj = camera.projection_matrix * camera.view_matrix;
+    let inverse_view_proj = view_proj.invert()?;
+    let unproject = |x: f32, y: f32, depth: f32| {
+        Point3::from_homogeneous(inverse_view_proj * Vector3::new(x, y, depth).extend(1.0))
+    };
+    let screen_corners = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)];
+    let mut corners = [Point3::origin(); 4];
+    for (corner, (x, y)) in corners.iter_mut().zip(screen_corners) {
+        // NOTE: two points on the ray, away from an infinite far plane at either end
+        let (a, b) = (unproject(x, y, 0.25), unproject(x, y, 0.75));
+        if (b.z - a.z).abs() <= f32::EPSILON {
+            return None;
+        }
+        let hit = a + (b - a) * ((z - a.z) / (b.z - a.z));
+        // behind the eye of a perspective camera
+        if (view_proj * hit.to_homogeneous()).w <= 0.0 {
+            return None;
+        }
+        *corner = hit;
+    }
+    let mut rect = WorldRect {
+        min: Vector2::new(f32::INFINITY, f32::INFINITY),
+        max: Vector2::new(f32::NEG_INFINITY, f32::NEG_INFINITY),
+    };
+    for corner in corners {
+        rect.min.x = rect.min.x.min(corner.x);
+        rect.min.y = rect.min.y.min(corner.y);
+        rect.max.x = rect.max.x.max(corner.x);
+        rect.max.y = rect.max.y.max(corner.y);
+    }
+    Some(rect)
+}
+
+/// Grid of tiles, `(0, 0)` is the bottom left tile and `y` goes up. Placed at the
+/// translation of its [`Transform`], if it has one, rotation and scale are not applied.
+///
+/// NOTE: resizing is not supported, spawn another map
+#[derive(Component, Debug, Clone)]
+pub struct Tilemap {
+    /// In world units
+    pub tile_size: Vector2<f32>,
+    pub atlas: TileAtlas,
+    dimensions: (u32, u32),
+    tiles: Vec<TileId>,
+    // one per chunk, row by row
+    dirty: Vec<bool>,
+}
+
+impl Tilemap {
+    /// Every tile is empty
+    pub fn new(dimensions: (u32, u32), tile_size: Vector2<f32>, atlas: TileAtlas) -> Self {
+        let (columns, rows) = Self::chunk_grid(dimensions);
+        Self {
+            tile_size,
+            atlas,
+            dimensions,
+            tiles: vec![TileId::EMPTY; (dimensions.0 * dimensions.1) as usize],
+            dirty: vec![true; (columns * rows) as usize],
+        }
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        self.dimensions
+    }
+
+    /// `None` outside the map
+    pub fn tile(&self, x: u32, y: u32) -> Option<TileId> {
+        self.tile_index(x, y).map(|i| self.tiles[i])
+    }
+
+    /// Marks the chunk of the tile dirty if the tile changed.
+    /// Returns `false` outside the map
+    pub fn set_tile(&mut self, x: u32, y: u32, id: TileId) -> bool {
+        let i = match self.tile_index(x, y) {
+            Some(i) => i,
+            None => return false,
+        };
+        if self.tiles[i] != id {
+            self.tiles[i] = id;
+            let chunk = self.chunk_index(Self::chunk_of(x, y));
+            self.dirty[chunk] = true;
+        }
+        true
+    }
+
+    fn tile_index(&self, x: u32, y: u32) -> Option<usize> {
+        (x < self.dimensions.0 && y < self.dimensions.1)
+            .then(|| (y * self.dimensions.0 + x) as usize)
+    }
+
+    /// Chunks per row and per column, the last ones may be partial
+    fn chunk_grid((width, height): (u32, u32)) -> (u32, u32) {
+        (
+            (width + CHUNK_SIZE - 1) / CHUNK_SIZE,
+            (height + CHUNK_SIZE - 1) / CHUNK_SIZE,
+        )
+    }
+
+    pub fn chunk_count(&self) -> (u32, u32) {
+        Self::chunk_grid(self.dimensions)
+    }
+
+    /// The chunk a tile is in
+    pub fn chunk_of(x: u32, y: u32) -> (u32, u32) {
+        (x / CHUNK_SIZE, y / CHUNK_SIZE)
+    }
+
+    /// Row by row, like [`Tilemap::chunks`]
+    pub fn chunk_index(&self, (x, y): (u32, u32)) -> usize {
+        (y * self.chunk_count().0 + x) as usize
+    }
+
+    /// Every chunk, row by row
+    pub fn chunks(&self) -> impl Iterator<Item = (u32, u32)> {
+        let (columns, rows) = self.chunk_count();
+        (0..rows).flat_map(move |y| (0..columns).map(move |x| (x, y)))
+    }
+
+    /// The tiles of a chunk, clipped to the map
+    pub fn chunk_tiles(&self, (x, y): (u32, u32)) -> (Range<u32>, Range<u32>) {
+        let (width, height) = self.dimensions;
+        (
+            (x * CHUNK_SIZE).min(width)..((x + 1) * CHUNK_SIZE).min(width),
+            (y * CHUNK_SIZE).min(height)..((y + 1) * CHUNK_SIZE).min(height),
+        )
+    }
+
+    pub fn is_dirty(&self, chunk: (u32, u32)) -> bool {
+        self.dirty[self.chunk_index(chunk)]
+    }
+
+    pub fn has_dirty(&self) -> bool {
+        self.dirty.contains(&true)
+    }
+
+    /// The dirty chunks, which are clean afterwards
+    pub fn take_dirty(&mut self) -> Vec<(u32, u32)> {
+        let dirty: Vec<_> = self
+            .chunks()
+            .filter(|&chunk| self.is_dirty(chunk))
+            .collect();
+        self.dirty.iter_mut().for_each(|dirty| *dirty = false);
+        dirty
+    }
+
+    /// The chunks overlapping `rect`, for a map with its bottom left corner at `origin`
+    pub fn chunks_in_rect(
+        &self,
+        origin: Vector2<f32>,
+        rect: &WorldRect,
+    ) -> (Range<u32>, Range<u32>) {
+        let (columns, rows) = self.chunk_count();
+        let chunk_size = self.tile_size * CHUNK_SIZE as f32;
+        let range = |min: f32, max: f32, origin: f32, size: f32, count: u32| {
+            let start = ((min - origin) / size).floor().max(0.0);
+            let end = ((max - origin) / size).ceil().max(0.0);
+            (start as u32).min(count)..(end as u32).min(count)
+        };
+        (
+            range(rect.min.x, rect.max.x, origin.x, chunk_size.x, columns),
+            range(rect.min.y, rect.max.y, origin.y, chunk_size.y, rows),
+        )
+    }
+
+    /// One per non-empty tile of the chunk, for a map with its bottom left corner at
+    /// `origin`. Tiles missing from the atlas are skipped
+    pub fn chunk_instances(&self, chunk: (u32, u32), origin: Vector3<f32>) -> Vec<SpriteInstance> {
+        let (xs, ys) = self.chunk_tiles(chunk);
+        let mut instances = Vec::with_capacity(xs.len() * ys.len());
+        for y in ys {
+            for x in xs.clone() {
+                let id = self.tiles[(y * self.dimensions.0 + x) as usize];
+                let rect = match self.atlas.tiles.get(id.0 as usize) {
+                    Some(rect) => rect,
+                    None => continue,
+                };
+                instances.push(SpriteInstance {
+                    position: [
+                        origin.x + x as f32 * self.tile_size.x,
+                        origin.y + y as f32 * self.tile_size.y,
+                        origin.z,
+                    ],
+                    size: self.tile_size.into(),
+                    uv_min: [rect.min.0, rect.min.1],
+                    uv_max: [rect.max.0, rect.max.1],
+                });
+            }
+        }
+        instances
+    }
+}
+
+/// Textured quad from `position` to `position + size` in the xy plane
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, C, Pod, Zeroable)]
+pub struct SpriteInstance {
+    /// Bottom left corner
+    pub position: [f32; 3],
+    pub size: [f32; 2],
+    /// Texture coordinates, `uv_min` is the top left
+    pub uv_min: [f32; 2],
+    pub uv_max: [f32; 2],
+}
+
+impl InstanceUnit for SpriteInstance {
+    const ATTRIBUTES: &'static [wgpu::VertexAttribute] = &wgpu::vertex_attr_array![
+        5 => Float32x3,
+        6 => Float32x2,
+        7 => Float32x2,
+        8 => Float32x2,
+    ];
+}
+
+/// Chunk of a [`Tilemap`], spawned by `prepare_tilemaps_system`
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TilemapChunk {
+    pub map: Entity,
+    pub chunk: (u32, u32),
+}
+
+/// The chunk entities of a [`Tilemap`], inserted with them
+#[derive(Component, Debug, Clone)]
+pub struct TilemapChunks {
+    /// By [`Tilemap::chunk_index`]
+    pub entities: Vec<Entity>,
+    // the instances were written for
+    origin: Vector3<f32>,
+}
+
+fn map_origin(transform: Option<&Transform>) -> Vector3<f32> {
+    transform.map_or(Vector3::zero(), |transform| transform.translation)
+}
+
+/// Hides the chunks outside the view of the camera, without a camera, or one that sees
+/// the plane of the map up to the horizon, every chunk is drawn
+pub fn cull_tilemap_chunks_system(
+    camera: Option<Res<Camera>>,
+    maps: Query<(&Tilemap, &TilemapChunks, Option<&Transform>)>,
+    mut chunks: Query<&mut Visibility, With<TilemapChunk>>,
+) {
+    for (tilemap, map_chunks, transform) in maps.iter() {
+        let origin = map_origin(transform);
+        let rect = camera
+            .as_ref()
+            .and_then(|camera| visible_rect(camera, origin.z));
+        let visible = rect.map(|rect| tilemap.chunks_in_rect(origin.truncate(), &rect));
+        for (chunk, entity) in tilemap.chunks().zip(map_chunks.entities.iter()) {
+            let is_visible = visible.as_ref().map_or(true, |(xs, ys)| {
+                xs.contains(&chunk.0) && ys.contains(&chunk.1)
+            });
+            if let Ok(mut visibility) = chunks.get_mut(*entity) {
+                // NOTE: only written on change, ComputedVisibility follows changes
+                if visibility.visible != is_visible {
+                    visibility.visible = is_visible;
+                }
+            }
+        }
+    }
+}
+
+/// Shared by every map, created with the first device and main pass format
+pub struct TilemapRenderer {
+    camera: UniformBuffer<CameraUniform>,
+    camera_bind_group: usize,
+    pipeline: usize,
+    quad: usize,
+    format: (wgpu::TextureFormat, DepthFormat, DepthConvention),
+}
+
+impl TilemapRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        depth_format: DepthFormat,
+        depth_convention: DepthConvention,
+        pipelines: &mut Store<RenderPipeline>,
+        bind_groups: &mut Store<wgpu::BindGroup>,
+        meshes: &mut Store<GpuMesh>,
+    ) -> Self {
+        let camera =
+            UniformBuffer::new_init(device, CameraUniform::default(), Some("tilemap camera"));
+        let camera_bind_group = bind_groups.insert((&camera).into_bind_group(device));
+        let pipeline = pipelines.insert(Self::create_pipeline(
+            device,
+            &camera,
+            format,
+            depth_format,
+            depth_convention,
+        ));
+        let quad = meshes.insert(GpuMesh::from_mesh(&unit_quad(), device, Some("tile quad")));
+
+        Self {
+            camera,
+            camera_bind_group,
+            pipeline,
+            quad,
+            format: (format, depth_format, depth_convention),
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        camera: &UniformBuffer<CameraUniform>,
+        format: wgpu::TextureFormat,
+        depth_format: DepthFormat,
+        depth_convention: DepthConvention,
+    ) -> RenderPipeline {
+        let camera_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Tilemap Camera Bind Group Layout"),
+            entries: &camera.layout_desc().entries,
+        });
+        let atlas_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Tilemap Atlas Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let module = device.create_shader_module(wgpu::include_wgsl!("../res/sprite.wgsl"));
+        let shader = Shader::with_final(
+            module,
+            vec![Vertex::layout(), SpriteInstance::layout()],
+            vec![Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        )
+        .with_depth_stencil(depth_format.0, Default::default())
+        .with_depth_convention(depth_convention);
+        RenderPipeline::create_usual(
+            device,
+            &[&camera_layout, &atlas_layout],
+            &shader,
+            wgpu::PrimitiveTopology::TriangleList,
+            Some("tilemap"),
+        )
+    }
+
+    /// Rebuilds the pipeline when the main pass format changed
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        camera: &Camera,
+        format: wgpu::TextureFormat,
+        depth_format: DepthFormat,
+        depth_convention: DepthConvention,
+        pipelines: &mut Store<RenderPipeline>,
+    ) {
+        self.camera.update(
+            queue,
+            CameraUniform {
+                view_proj: (camera.projection_matrix * camera.view_matrix).into(),
+            },
+        );
+        if self.format == (format, depth_format, depth_convention) {
+            return;
+        }
+        self.format = (format, depth_format, depth_convention);
+        if let Some(pipeline) = pipelines.get_mut(self.pipeline) {
+            *pipeline =
+                Self::create_pipeline(device, &self.camera, format, depth_format, depth_convention);
+        }
+    }
+}
+
+/// From `(0, 0)` to `(1, 1)` in the xy plane facing `+z`, the instances scale it to a tile
+fn unit_quad() -> Mesh<Vertex> {
+    let vertex = |x: f32, y: f32| Vertex {
+        position: [x, y, 0.0],
+        tex_coords: [x, 1.0 - y],
+    };
+    Mesh::with_all(
+        wgpu::PrimitiveTopology::TriangleList,
+        vec![
+            vertex(0.0, 0.0),
+            vertex(1.0, 0.0),
+            vertex(1.0, 1.0),
+            vertex(0.0, 1.0),
+        ],
+        Some(Indices::U16(vec![0, 1, 2, 2, 3, 0])),
+    )
+}
+
+/// Spawns the chunks of new maps and writes the dirty chunks into their instance buffers
+pub fn prepare_tilemaps_system(
+    mut commands: Commands,
+    (device, queue): (Option<Res<Arc<wgpu::Device>>>, Option<Res<wgpu::Queue>>),
+    camera: Option<Res<Camera>>,
+    targets: Res<Option<RenderTargets>>,
+    mut renderer: ResMut<Option<TilemapRenderer>>,
+    mut pipelines: ResMut<Store<RenderPipeline>>,
+    mut bind_groups: ResMut<Store<wgpu::BindGroup>>,
+    mut meshes: ResMut<Store<GpuMesh>>,
+    mut maps: Query<(
+        Entity,
+        &mut Tilemap,
+        Option<&mut TilemapChunks>,
+        Option<&Transform>,
+    )>,
+    mut chunks: Query<(&mut InstanceData, &mut BindSlots), With<TilemapChunk>>,
+) {
+    let (device, queue) = match (device, queue) {
+        (Some(device), Some(queue)) => (device, queue),
+        _ => return,
+    };
+    let (format, depth_format, depth_convention) = match *targets {
+        Some(targets) => (
+            targets.main_pass_format(),
+            targets.depth_format,
+            targets.depth_convention,
+        ),
+        None => return,
+    };
+
+    let renderer = renderer.get_or_insert_with(|| {
+        TilemapRenderer::new(
+            &device,
+            format,
+            depth_format,
+            depth_convention,
+            &mut pipelines,
+            &mut bind_groups,
+            &mut meshes,
+        )
+    });
+    let default_camera = Camera::default();
+    renderer.prepare(
+        &device,
+        &queue,
+        camera.as_deref().unwrap_or(&default_camera),
+        format,
+        depth_format,
+        depth_convention,
+        &mut pipelines,
+    );
+
+    let capacity = CHUNK_SIZE * CHUNK_SIZE;
+    for (entity, mut tilemap, map_chunks, transform) in maps.iter_mut() {
+        let origin = map_origin(transform);
+        let binds = BindSlots::new()
+            .with(0, renderer.camera_bind_group)
+            .with(1, tilemap.atlas.bind_group);
+
+        let mut map_chunks = match map_chunks {
+            Some(map_chunks) => map_chunks,
+            None => {
+                // NOTE: every chunk starts dirty, all of them are written here
+                let entities = tilemap
+                    .take_dirty()
+                    .into_iter()
+                    .map(|chunk| {
+                        let mut instance_data =
+                            InstanceData::with_capacity_of::<SpriteInstance>(&device, capacity);
+                        instance_data.write_units(&queue, &tilemap.chunk_instances(chunk, origin));
+                        commands
+                            .spawn()
+                            .insert_bundle((
+                                TilemapChunk { map: entity, chunk },
+                                instance_data,
+                                Refer::<GpuMesh>::new(renderer.quad),
+                                Refer::<RenderPipeline>::new(renderer.pipeline),
+                                binds.clone(),
+                                Visibility::default(),
+                            ))
+                            .id()
+                    })
+                    .collect();
+                commands
+                    .entity(entity)
+                    .insert(TilemapChunks { entities, origin });
+                continue;
+            }
+        };
+
+        // the instances are in world space, a moved map is written again
+        let dirty = if map_chunks.origin != origin {
+            map_chunks.origin = origin;
+            tilemap.take_dirty();
+            tilemap.chunks().collect()
+        } else if tilemap.has_dirty() {
+            tilemap.take_dirty()
+        } else {
+            Vec::new()
+        };
+        for chunk in dirty {
+            let entity = map_chunks.entities[tilemap.chunk_index(chunk)];
+            if let Ok((mut instance_data, _)) = chunks.get_mut(entity) {
+                instance_data.write_units(&queue, &tilemap.chunk_instances(chunk, origin));
+            }
+        }
+        for &entity in map_chunks.entities.iter() {
+            if let Ok((_, mut chunk_binds)) = chunks.get_mut(entity) {
+                if *chunk_binds != binds {
+                    *chunk_binds = binds.clone();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{InnerSpace, Point3, Vector2, Vector3, Zero};
+
+    use crate::{
+        camera::{
+            Camera, CameraView, OrthographicProjection, PerspectiveProjection,
+            OPENGL_TO_WGPU_MATRIX,
+        },
+        render::DepthConvention,
+    };
+
+    use super::{visible_rect, TileAtlas, TileId, Tilemap, CHUNK_SIZE};
+
+    fn tilemap(dimensions: (u32, u32)) -> Tilemap {
+        Tilemap::new(dimensions, Vector2::new(1.0, 1.0), TileAtlas::grid(0, 4, 4))
+    }
+
+    #[test]
+    fn tiles_map_to_their_chunks() {
+        let map = tilemap((70, 40));
+        assert_eq!(map.chunk_count(), (3, 2));
+        assert_eq!(Tilemap::chunk_of(31, 31), (0, 0));
+        assert_eq!(Tilemap::chunk_of(33, 32), (1, 1));
+        assert_eq!(map.chunk_index((2, 1)), 5);
+        assert_eq!(
+            map.chunks()
+                .map(|chunk| map.chunk_index(chunk))
+                .collect::<Vec<_>>(),
+            (0..6).collect::<Vec<_>>()
+        );
+        // the last chunks are partial
+        assert_eq!(map.chunk_tiles((2, 1)), (64..70, 32..40));
+        assert_eq!(map.chunk_tiles((0, 0)), (0..CHUNK_SIZE, 0..CHUNK_SIZE));
+    }
+
+    #[test]
+    fn setting_a_tile_dirties_its_chunk_only() {
+        let mut map = tilemap((70, 40));
+        assert_eq!(map.take_dirty().len(), 6);
+        assert!(!map.has_dirty());
+
+        assert!(map.set_tile(33, 35, TileId(1)));
+        assert!(map.set_tile(34, 35, TileId(2)));
+        assert!(!map.set_tile(70, 0, TileId(1)));
+        assert_eq!(map.tile(33, 35), Some(TileId(1)));
+        assert_eq!(map.tile(0, 0), Some(TileId::EMPTY));
+        assert_eq!(map.tile(0, 40), None);
+        assert_eq!(map.take_dirty(), [(1, 1)]);
+
+        // the same tile again is no change
+        map.set_tile(33, 35, TileId(1));
+        assert!(!map.has_dirty());
+
+        let instances = map.chunk_instances((1, 1), Vector3::new(0.0, 0.0, -1.0));
+        assert_eq!(instances.len(), 2);
+        assert_eq!(instances[0].position, [33.0, 35.0, -1.0]);
+        assert_eq!(instances[1].uv_min, [0.5, 0.0]);
+        // empty tiles and ids missing from the atlas are not drawn
+        map.set_tile(34, 35, TileId(16));
+        assert_eq!(map.chunk_instances((1, 1), Vector3::zero()).len(), 1);
+    }
+
+    #[test]
+    fn chunks_in_the_view_of_an_orthographic_camera_are_selected() {
+        // 40x20 world units around (40, 20)
+        let view = CameraView {
+            eye: Point3::new(40.0, 20.0, 10.0),
+            target: Point3::new(40.0, 20.0, 0.0),
+            up: Vector3::unit_y(),
+        };
+        let projection = OrthographicProjection::from_height(20.0, 2.0, 0.1, 100.0);
+        let camera = Camera {
+            view_matrix: view.build_view_matrix(),
+            projection_matrix: OPENGL_TO_WGPU_MATRIX * projection.build_projection_matrix(),
+        };
+        let rect = visible_rect(&camera, 0.0).unwrap();
+        let close = |a: Vector2<f32>, b: Vector2<f32>| (a - b).magnitude() < 1e-3;
+        assert!(close(rect.min, Vector2::new(20.0, 10.0)), "{:?}", rect);
+        assert!(close(rect.max, Vector2::new(60.0, 30.0)), "{:?}", rect);
+
+        let map = tilemap((128, 128));
+        assert_eq!(map.chunks_in_rect(Vector2::zero(), &rect), (0..2, 0..1));
+        assert_eq!(
+            map.chunks_in_rect(Vector2::new(-16.0, -16.0), &rect),
+            (1..3, 0..2)
+        );
+        // nothing of the map is in view
+        assert_eq!(
+            map.chunks_in_rect(Vector2::new(100.0, 0.0), &rect),
+            (0..0, 0..1)
+        );
+    }
+
+    #[test]
+    fn chunks_in_the_view_of_a_perspective_camera_are_selected() {
+        // 90 degrees of fovy from 10 above sees 20 world units tall, as the orthographic one
+        let view = CameraView {
+            eye: Point3::new(40.0, 20.0, 10.0),
+            target: Point3::new(40.0, 20.0, 0.0),
+            up: Vector3::unit_y(),
+        };
+        let close = |a: Vector2<f32>, b: Vector2<f32>| (a - b).magnitude() < 1e-2;
+        for (depth_convention, zfar) in [
+            (DepthConvention::Standard, 100.0),
+            (DepthConvention::ReverseZ, 100.0),
+            (DepthConvention::ReverseZ, f32::INFINITY),
+        ] {
+            let projection = PerspectiveProjection {
+                aspect: 2.0,
+                fovy: std::f32::consts::FRAC_PI_2,
+                znear: 0.1,
+                zfar,
+                depth_convention,
+            };
+            let camera = Camera {
+                view_matrix: view.build_view_matrix(),
+                projection_matrix: OPENGL_TO_WGPU_MATRIX * projection.build_projection_matrix(),
+            };
+            let rect = visible_rect(&camera, 0.0).unwrap();
+            assert!(close(rect.min, Vector2::new(20.0, 10.0)), "{:?}", rect);
+            assert!(close(rect.max, Vector2::new(60.0, 30.0)), "{:?}", rect);
+            let map = tilemap((128, 128));
+            assert_eq!(map.chunks_in_rect(Vector2::zero(), &rect), (0..2, 0..1));
+
+            // a map 5 below is seen 30 world units tall
+            let rect = visible_rect(&camera, -5.0).unwrap();
+            assert!(close(rect.min, Vector2::new(10.0, 5.0)), "{:?}", rect);
+            assert!(close(rect.max, Vector2::new(70.0, 35.0)), "{:?}", rect);
+        }
+
+        // looking along the plane the top rays never reach it
+        let camera = Camera {
+            view_matrix: CameraView {
+                eye: Point3::new(0.0, 0.0, 10.0),
+                target: Point3::new(0.0, 100.0, 10.0),
+                up: Vector3::unit_z(),
+            }
+            .build_view_matrix(),
+            projection_matrix: OPENGL_TO_WGPU_MATRIX
+                * PerspectiveProjection::default().build_projection_matrix(),
+        };
+        assert_eq!(visible_rect(&camera, 0.0), None);
+    }
+}
@@ -0,0 +1,75 @@
+use std::time::{Duration, Instant};
+
+use bevy_ecs::system::ResMut;
+
+pub struct Time {
+    startup: Instant,
+    last_update: Option<Instant>,
+    delta: Duration,
+}
+
+impl Default for Time {
+    fn default() -> Self {
+        Self {
+            startup: Instant::now(),
+            last_update: None,
+            delta: Duration::ZERO,
+        }
+    }
+}
+
+impl Time {
+    pub fn update(&mut self) {
+        self.update_with_instant(Instant::now());
+    }
+
+    /// The first update only starts the clock, `delta` stays zero
+    pub fn update_with_instant(&mut self, now: Instant) {
+        if let Some(last_update) = self.last_update {
+            self.delta = now - last_update;
+        }
+        self.last_update = Some(now);
+    }
+
+    pub fn delta(&self) -> Duration {
+        self.delta
+    }
+
+    pub fn delta_seconds(&self) -> f32 {
+        self.delta.as_secs_f32()
+    }
+
+    pub fn startup(&self) -> Instant {
+        self.startup
+    }
+
+    /// Time from startup to the last update
+    pub fn elapsed(&self) -> Duration {
+        self.last_update
+            .map_or(Duration::ZERO, |last_update| last_update - self.startup)
+    }
+}
+
+pub fn time_system(mut time: ResMut<Time>) {
+    time.update();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Time;
+
+    #[test]
+    fn delta_between_updates() {
+        let mut time = Time::default();
+        let start = time.startup();
+
+        time.update_with_instant(start + Duration::from_millis(5));
+        assert_eq!(time.delta(), Duration::ZERO);
+
+        time.update_with_instant(start + Duration::from_millis(21));
+        assert_eq!(time.delta(), Duration::from_millis(16));
+        assert_eq!(time.elapsed(), Duration::from_millis(21));
+    }
+}
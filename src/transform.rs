@@ -0,0 +1,77 @@
+use bevy_ecs::prelude::Component;
+use bytemuck::{Pod, Zeroable};
+use cgmath::*;
+use repr_trait::C;
+
+use crate::render::resource::{
+    bind::{StageLockedUniform, UpdateGpuUniform},
+    uniform_layout::gpu_uniform,
+};
+
+#[derive(Component, Debug, Clone, Copy)]
+pub struct Transform {
+    pub translation: Vector3<f32>,
+    pub scale: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+}
+
+impl Transform {
+    pub fn from_translation(translation: Vector3<f32>) -> Self {
+        Self {
+            translation,
+            ..Default::default()
+        }
+    }
+
+    /// `child` relative to `self`
+    pub fn mul_transform(&self, child: &Transform) -> Transform {
+        Transform {
+            translation: self.translation
+                + self.rotation * self.scale.mul_element_wise(child.translation),
+            scale: self.scale.mul_element_wise(child.scale),
+            rotation: self.rotation * child.rotation,
+        }
+    }
+
+    pub fn compute_matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.translation)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+}
+
+impl UpdateGpuUniform for Transform {
+    type GU = ModelUniform;
+
+    fn update_uniform(&self, gpu_uniform: &mut Self::GU) {
+        gpu_uniform.model = self.compute_matrix().into();
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            translation: Vector3::zero(),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+            rotation: Quaternion::one(),
+        }
+    }
+}
+
+gpu_uniform! {
+    #[repr(C)]
+    #[derive(Debug, Clone, Copy, C, Pod, Zeroable)]
+    pub struct ModelUniform {
+        pub model: [[f32; 4]; 4] as mat4x4,
+    }
+}
+impl StageLockedUniform for ModelUniform {
+    const FORCE_STAGE: wgpu::ShaderStages = wgpu::ShaderStages::VERTEX;
+}
+impl Default for ModelUniform {
+    fn default() -> Self {
+        Self {
+            model: Matrix4::identity().into(),
+        }
+    }
+}
@@ -0,0 +1,491 @@
+use std::sync::Arc;
+
+use bevy_app::{CoreStage, Plugin};
+use bevy_ecs::{
+    entity::Entity,
+    prelude::Component,
+    schedule::ParallelSystemDescriptorCoercion,
+    system::{Commands, Query, Res, ResMut},
+};
+use bytemuck::{Pod, Zeroable};
+use cgmath::{Matrix4, SquareMatrix, Vector2, Vector3};
+use repr_trait::C;
+
+use crate::{
+    atlas::Rect,
+    color::Color,
+    render::{
+        mesh::{GpuMesh, Mesh},
+        resource::{
+            bind::{BindSlots, BindingSet, GpuUniform, Uniform, UpdateGpuUniform},
+            buffer::{Indices, MeshVertex, Vertex},
+            pipeline::RenderPipeline,
+            shader::Shader,
+        },
+        targets::RenderTargets,
+        DepthConvention, DepthFormat,
+    },
+    util::{Refer, Store},
+    window::screen::ScreenSpace,
+    FlatSystemLabels,
+};
+
+/// Screen space panels drawn in the main pass, over the scene.
+///
+/// Spawn a [`NineSlice`], its mesh is rebuilt only when the size or borders change.
+/// Panels are depth tested against each other, overlapping ones need different layers.
+pub struct FlatUiPlugin;
+impl Plugin for FlatUiPlugin {
+    fn build(&self, app: &mut bevy_app::App) {
+        app.init_resource::<Option<UiRenderer>>()
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                nine_slice_system
+                    .label(FlatSystemLabels::UniformSync)
+                    .after(FlatSystemLabels::VisibilityCompute),
+            );
+    }
+}
+
+/// Entry of a texture atlas, see `TextureAtlasBuilder::build`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AtlasRegion {
+    /// Bind group of the atlas view and sampler in `Store<wgpu::BindGroup>`,
+    /// e.g. `(&texture.view, &texture.sampler).into_bind_group(device)`
+    pub bind_group: usize,
+    /// Of the whole atlas, in pixels
+    pub atlas_size: (u32, u32),
+    pub rect: Rect,
+}
+
+impl AtlasRegion {
+    /// `border_px` as texture coordinates
+    pub fn uv_insets(&self, border_px: [f32; 4]) -> [f32; 4] {
+        let (width, height) = (self.atlas_size.0 as f32, self.atlas_size.1 as f32);
+        let [left, right, top, bottom] = border_px;
+        [left / width, right / width, top / height, bottom / height]
+    }
+}
+
+/// Panel whose borders keep their pixel size while the center stretches
+#[derive(Component, Debug, Clone, PartialEq)]
+pub struct NineSlice {
+    pub texture: AtlasRegion,
+    /// `[left, right, top, bottom]` in atlas pixels, drawn as as many logical pixels
+    pub border_px: [f32; 4],
+    /// In logical pixels, see [`ScreenSpace`]
+    pub size: Vector2<f32>,
+    /// Of the bottom left corner, in logical pixels from the bottom left of the screen.
+    /// [`ScreenSpace::anchor`] places it relative to the other corners
+    pub position: Vector2<f32>,
+    /// Multiplies the texture
+    pub color: Color,
+    /// Higher layers are drawn over lower ones
+    pub layer: u32,
+}
+
+/// 3x3 grid of quads from `(0, 0)` to `size`, 16 vertices and 54 indices.
+///
+/// `uv_insets` are the borders in texture coordinates, `[left, right, top, bottom]`
+/// like `border_px`. When `size` is smaller than the borders they shrink by the same
+/// ratio and the center collapses to zero, the texture coordinates stay in order.
+pub fn create_nine_slice_mesh(
+    size: Vector2<f32>,
+    border_px: [f32; 4],
+    rect: Rect,
+    uv_insets: [f32; 4],
+) -> Mesh<Vertex> {
+    let [left, right, top, bottom] = border_px;
+    // borders that fit in `length`, ends of the center
+    let fit = |length: f32, start: f32, end: f32| {
+        let length = length.max(0.0);
+        let scale = if start + end > length {
+            length / (start + end)
+        } else {
+            1.0
+        };
+        [0.0, start * scale, length - end * scale, length]
+    };
+    let xs = fit(size.x, left, right);
+    let ys = fit(size.y, bottom, top);
+
+    let [u_left, u_right, v_top, v_bottom] = uv_insets;
+    let us = [
+        rect.min.0,
+        rect.min.0 + u_left,
+        rect.max.0 - u_right,
+        rect.max.0,
+    ];
+    // y is up, the texture rows go down
+    let vs = [
+        rect.max.1,
+        rect.max.1 - v_bottom,
+        rect.min.1 + v_top,
+        rect.min.1,
+    ];
+
+    let mut vertices = Vec::with_capacity(16);
+    for row in 0..4 {
+        for column in 0..4 {
+            vertices.push(Vertex {
+                position: [xs[column], ys[row], 0.0],
+                tex_coords: [us[column], vs[row]],
+            });
+        }
+    }
+    let mut indices = Vec::with_capacity(54);
+    for row in 0..3 {
+        for column in 0..3 {
+            let i = row * 4 + column;
+            indices.extend_from_slice(&[i, i + 1, i + 5, i + 5, i + 4, i]);
+        }
+    }
+
+    Mesh::with_all(
+        wgpu::PrimitiveTopology::TriangleList,
+        vertices,
+        Some(Indices::U16(indices)),
+    )
+}
+
+/// Placement of a panel on the screen
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UiQuad {
+    pub screen: ScreenSpace,
+    /// In logical pixels, see [`ScreenSpace`]
+    pub position: Vector2<f32>,
+    pub color: Color,
+    pub layer: u32,
+    /// Of the depth buffer the panels are tested against
+    pub depth_convention: DepthConvention,
+}
+
+impl UiQuad {
+    /// In front of the scene, closer for higher layers
+    pub fn depth(layer: u32) -> f32 {
+        0.01 / (1 + layer) as f32
+    }
+}
+
+impl UpdateGpuUniform for UiQuad {
+    type GU = UiQuadUniform;
+
+    fn update_uniform(&self, gpu_uniform: &mut Self::GU) {
+        // NOTE: the depth is set here, the projection maps z to -z
+        let projection = self.screen.ui_projection();
+        let translation = Matrix4::from_translation(Vector3::new(
+            self.position.x,
+            self.position.y,
+            -self.depth_convention.from_near(Self::depth(self.layer)),
+        ));
+        gpu_uniform.transform = (projection * translation).into();
+        gpu_uniform.color = self.color.into();
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, C, Pod, Zeroable)]
+pub struct UiQuadUniform {
+    /// Pixels to clip space
+    pub transform: [[f32; 4]; 4],
+    /// Linear, see [`Color`]
+    pub color: [f32; 4],
+}
+impl GpuUniform for UiQuadUniform {}
+impl Default for UiQuadUniform {
+    fn default() -> Self {
+        Self {
+            transform: Matrix4::identity().into(),
+            color: Color::WHITE.into(),
+        }
+    }
+}
+
+/// GPU side of a [`NineSlice`], along with its `GpuMesh`
+#[derive(Component)]
+pub struct PreparedNineSlice {
+    quad: Uniform<UiQuad>,
+    bind_group: usize,
+    // what the mesh was built from
+    size: Vector2<f32>,
+    border_px: [f32; 4],
+    texture: AtlasRegion,
+}
+
+/// Pipeline shared by the panels, created with the first device and main pass format
+pub struct UiRenderer {
+    pipeline: usize,
+    quad_layout: wgpu::BindGroupLayout,
+    format: (wgpu::TextureFormat, DepthFormat, DepthConvention),
+}
+
+impl UiRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        depth_format: DepthFormat,
+        depth_convention: DepthConvention,
+        pipelines: &mut Store<RenderPipeline>,
+    ) -> Self {
+        let quad_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("UI Quad Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let pipeline = pipelines.insert(Self::create_pipeline(
+            device,
+            &quad_layout,
+            format,
+            depth_format,
+            depth_convention,
+        ));
+
+        Self {
+            pipeline,
+            quad_layout,
+            format: (format, depth_format, depth_convention),
+        }
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        quad_layout: &wgpu::BindGroupLayout,
+        format: wgpu::TextureFormat,
+        depth_format: DepthFormat,
+        depth_convention: DepthConvention,
+    ) -> RenderPipeline {
+        let atlas_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("UI Atlas Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let module = device.create_shader_module(wgpu::include_wgsl!("../res/ui.wgsl"));
+        let shader = Shader::with_final(
+            module,
+            vec![Vertex::layout()],
+            vec![Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        )
+        .with_depth_stencil(depth_format.0, Default::default())
+        .with_depth_convention(depth_convention);
+        RenderPipeline::create_usual(
+            device,
+            &[&atlas_layout, quad_layout],
+            &shader,
+            wgpu::PrimitiveTopology::TriangleList,
+            Some("nine slice"),
+        )
+    }
+
+    /// Rebuilds the pipeline when the main pass format changed
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        depth_format: DepthFormat,
+        depth_convention: DepthConvention,
+        pipelines: &mut Store<RenderPipeline>,
+    ) {
+        if self.format == (format, depth_format, depth_convention) {
+            return;
+        }
+        self.format = (format, depth_format, depth_convention);
+        if let Some(pipeline) = pipelines.get_mut(self.pipeline) {
+            *pipeline = Self::create_pipeline(
+                device,
+                &self.quad_layout,
+                format,
+                depth_format,
+                depth_convention,
+            );
+        }
+    }
+}
+
+/// Prepares added panels, rebuilds the meshes of resized ones and writes the placements
+pub fn nine_slice_system(
+    mut commands: Commands,
+    (device, queue): (Option<Res<Arc<wgpu::Device>>>, Option<Res<wgpu::Queue>>),
+    targets: Res<Option<RenderTargets>>,
+    screen: Res<ScreenSpace>,
+    mut renderer: ResMut<Option<UiRenderer>>,
+    mut pipelines: ResMut<Store<RenderPipeline>>,
+    mut bind_groups: ResMut<Store<wgpu::BindGroup>>,
+    mut panels: Query<(Entity, &NineSlice, Option<&mut PreparedNineSlice>)>,
+) {
+    let (device, queue) = match (device, queue) {
+        (Some(device), Some(queue)) => (device, queue),
+        _ => return,
+    };
+    let (format, depth_format, depth_convention) = match *targets {
+        Some(targets) => (
+            targets.main_pass_format(),
+            targets.depth_format,
+            targets.depth_convention,
+        ),
+        None => return,
+    };
+
+    let renderer = renderer.get_or_insert_with(|| {
+        UiRenderer::new(
+            &device,
+            format,
+            depth_format,
+            depth_convention,
+            &mut pipelines,
+        )
+    });
+    renderer.prepare(
+        &device,
+        format,
+        depth_format,
+        depth_convention,
+        &mut pipelines,
+    );
+
+    for (entity, panel, prepared) in panels.iter_mut() {
+        let quad = UiQuad {
+            screen: *screen,
+            position: panel.position,
+            color: panel.color,
+            layer: panel.layer,
+            depth_convention,
+        };
+        let mesh = || {
+            GpuMesh::from_mesh(
+                &create_nine_slice_mesh(
+                    panel.size,
+                    panel.border_px,
+                    panel.texture.rect,
+                    panel.texture.uv_insets(panel.border_px),
+                ),
+                &device,
+                Some("nine slice panel"),
+            )
+        };
+
+        let mut prepared = match prepared {
+            Some(prepared) => prepared,
+            None => {
+                let mut uniform: Uniform<UiQuad> =
+                    Uniform::new_default(&device, wgpu::ShaderStages::VERTEX_FRAGMENT);
+                uniform.update(&quad);
+                uniform.sync_buffer(&queue);
+                let bind_group = bind_groups.insert((&uniform).into_bind_group(&device));
+                commands.entity(entity).insert_bundle((
+                    mesh(),
+                    Refer::<RenderPipeline>::new(renderer.pipeline),
+                    BindSlots::new()
+                        .with(0, panel.texture.bind_group)
+                        .with(1, bind_group),
+                    PreparedNineSlice {
+                        quad: uniform,
+                        bind_group,
+                        size: panel.size,
+                        border_px: panel.border_px,
+                        texture: panel.texture,
+                    },
+                ));
+                continue;
+            }
+        };
+
+        prepared.quad.update(&quad);
+        prepared.quad.sync_buffer(&queue);
+        if prepared.size != panel.size
+            || prepared.border_px != panel.border_px
+            || prepared.texture != panel.texture
+        {
+            prepared.size = panel.size;
+            prepared.border_px = panel.border_px;
+            prepared.texture = panel.texture;
+            commands.entity(entity).insert_bundle((
+                mesh(),
+                BindSlots::new()
+                    .with(0, panel.texture.bind_group)
+                    .with(1, prepared.bind_group),
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Vector2;
+
+    use crate::{atlas::Rect, render::mesh::Mesh, render::resource::buffer::Indices};
+
+    use super::create_nine_slice_mesh;
+
+    const RECT: Rect = Rect {
+        min: (0.25, 0.5),
+        max: (0.75, 1.0),
+    };
+    const INSETS: [f32; 4] = [0.0625, 0.0625, 0.125, 0.125];
+
+    fn columns(mesh: &Mesh<super::Vertex>) -> Vec<(f32, f32)> {
+        mesh.get_vertices()[..4]
+            .iter()
+            .map(|vertex| (vertex.position[0], vertex.tex_coords[0]))
+            .collect()
+    }
+
+    #[test]
+    fn grid_has_16_vertices_and_54_indices() {
+        let mesh = create_nine_slice_mesh(Vector2::new(100.0, 50.0), [4.0; 4], RECT, INSETS);
+        assert_eq!(mesh.get_vertices().len(), 16);
+        match mesh.get_indices() {
+            Some(Indices::U16(indices)) => {
+                assert_eq!(indices.len(), 54);
+                assert!(indices.iter().all(|&i| i < 16));
+            }
+            _ => panic!("expected u16 indices"),
+        }
+        assert_eq!(
+            columns(&mesh),
+            vec![(0.0, 0.25), (4.0, 0.3125), (96.0, 0.6875), (100.0, 0.75)]
+        );
+        // the bottom row samples the bottom of the rect
+        assert_eq!(mesh.get_vertices()[0].tex_coords[1], 1.0);
+        assert_eq!(mesh.get_vertices()[15].tex_coords[1], 0.5);
+    }
+
+    #[test]
+    fn small_sizes_collapse_the_center() {
+        let mesh =
+            create_nine_slice_mesh(Vector2::new(6.0, 50.0), [4.0, 8.0, 4.0, 4.0], RECT, INSETS);
+        let columns = columns(&mesh);
+        assert_eq!(columns[1].0, 2.0);
+        assert_eq!(columns[2].0, 2.0);
+        assert_eq!(columns[3].0, 6.0);
+        // still in order
+        assert!(columns.windows(2).all(|pair| pair[0].1 <= pair[1].1));
+    }
+}
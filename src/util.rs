@@ -1,17 +1,27 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
+    fmt,
     marker::PhantomData,
     ops::{Deref, DerefMut},
 };
 
+use bevy_app::{App, CoreStage};
 use bevy_asset::HandleId;
-use bevy_ecs::prelude::Component;
-use bluenoise::BlueNoise;
-use rand_pcg::Pcg64Mcg;
+use bevy_ecs::{
+    entity::Entity,
+    event::{EventReader, EventWriter},
+    prelude::Component,
+    schedule::ParallelSystemDescriptorCoercion,
+    system::{Commands, Query, ResMut},
+};
+
+use crate::texture::{PixelFormat, RawImage, Texture};
 
 pub struct Store<T> {
     ind: usize,
     pub inner: HashMap<usize, T>,
+    // published as StoreRemoved events
+    removed: Vec<usize>,
 }
 
 impl<T> Default for Store<T> {
@@ -19,6 +29,7 @@ impl<T> Default for Store<T> {
         Self {
             ind: 0,
             inner: Default::default(),
+            removed: Vec::new(),
         }
     }
 }
@@ -31,6 +42,18 @@ impl<T> Store<T> {
         self.ind - 1
     }
 
+    /// A key for a value inserted later with [`Store::insert_reserved`], e.g. once it is
+    /// created off the main thread. Lookups miss until then.
+    pub fn reserve(&mut self) -> usize {
+        self.ind += 1;
+        self.ind - 1
+    }
+
+    pub fn insert_reserved(&mut self, key: usize, val: T) {
+        debug_assert!(key < self.ind, "key {} was not reserved", key);
+        self.inner.insert(key, val);
+    }
+
     pub fn get(&self, key: usize) -> Option<&T> {
         self.inner.get(&key)
     }
@@ -42,6 +65,73 @@ impl<T> Store<T> {
     pub fn remove(&mut self, key: usize) -> Option<T> {
         self.inner.remove(&key)
     }
+
+    /// Also sends [`StoreRemoved`] so the components referring to `key` are swept,
+    /// see [`AddStoreCleanup`]. Keys are never reused, a late event cannot hit a new value.
+    pub fn remove_with_events(&mut self, key: usize) -> Option<T> {
+        self.removed.push(key);
+        self.remove(key)
+    }
+}
+
+/// A key removed with [`Store::remove_with_events`]
+pub struct StoreRemoved<T>(pub usize, PhantomData<fn() -> T>);
+
+impl<T> StoreRemoved<T> {
+    pub fn new(key: usize) -> Self {
+        Self(key, PhantomData)
+    }
+}
+
+pub trait AddStoreCleanup {
+    /// Sweeps `Refer<T>` and `ReferMany<T>` of keys removed from `Store<T>` in `CoreStage::Last`,
+    /// before the render stages
+    fn add_store_cleanup<T: Send + Sync + 'static>(&mut self) -> &mut Self;
+}
+
+impl AddStoreCleanup for App {
+    fn add_store_cleanup<T: Send + Sync + 'static>(&mut self) -> &mut Self {
+        self.add_event::<StoreRemoved<T>>()
+            .add_system_to_stage(CoreStage::Last, publish_store_removals_system::<T>)
+            .add_system_to_stage(
+                CoreStage::Last,
+                sweep_removed_refers_system::<T>.after(publish_store_removals_system::<T>),
+            )
+    }
+}
+
+pub fn publish_store_removals_system<T: Send + Sync + 'static>(
+    mut store: ResMut<Store<T>>,
+    mut events: EventWriter<StoreRemoved<T>>,
+) {
+    if !store.removed.is_empty() {
+        events.send_batch(store.removed.drain(..).map(StoreRemoved::new));
+    }
+}
+
+/// Entities lose their `Refer<T>` to a removed key, so the systems querying it skip them,
+/// and the key is dropped from their `ReferMany<T>`
+pub fn sweep_removed_refers_system<T: Send + Sync + 'static>(
+    mut commands: Commands,
+    mut events: EventReader<StoreRemoved<T>>,
+    refers: Query<(Entity, &Refer<T>)>,
+    mut refer_many: Query<&mut ReferMany<T>>,
+) {
+    let removed: HashSet<usize> = events.iter().map(|event| event.0).collect();
+    if removed.is_empty() {
+        return;
+    }
+    for (entity, refer) in refers.iter() {
+        if removed.contains(&refer.0) {
+            commands.entity(entity).remove::<Refer<T>>();
+        }
+    }
+    for mut keys in refer_many.iter_mut() {
+        // NOTE: only mutated when needed, not to trigger change detection
+        if keys.iter().any(|key| removed.contains(key)) {
+            keys.retain(|key| !removed.contains(key));
+        }
+    }
 }
 
 #[derive(Default)]
@@ -59,8 +149,41 @@ impl<T> DerefMut for AssetStore<T> {
     }
 }
 
+/// A key into `Store<T>`, see [`AddStoreCleanup`] for keys that are removed
 #[derive(Component)]
 pub struct Refer<T>(usize, PhantomData<fn() -> T>);
+impl<T> Refer<T> {
+    pub fn new(key: usize) -> Self {
+        Self(key, PhantomData)
+    }
+
+    /// `None` if the key was removed or is only reserved
+    pub fn get<'a>(&self, store: &'a Store<T>) -> Option<&'a T> {
+        store.get(self.0)
+    }
+
+    pub fn get_mut<'a>(&self, store: &'a mut Store<T>) -> Option<&'a mut T> {
+        store.get_mut(self.0)
+    }
+}
+// NOTE: implemented by hand, derives would require `T` to implement them too
+impl<T> Clone for Refer<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<T> Copy for Refer<T> {}
+impl<T> PartialEq for Refer<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+impl<T> Eq for Refer<T> {}
+impl<T> fmt::Debug for Refer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Refer").field(&self.0).finish()
+    }
+}
 impl<T> Deref for Refer<T> {
     type Target = usize;
 
@@ -74,8 +197,36 @@ impl<T> DerefMut for Refer<T> {
     }
 }
 
+/// Keys in insertion order. Bind groups go in
+/// [`BindSlots`](crate::render::resource::bind::BindSlots) so they keep their group index.
 #[derive(Component)]
 pub struct ReferMany<T>(Vec<usize>, PhantomData<fn() -> T>);
+impl<T> ReferMany<T> {
+    pub fn new(keys: Vec<usize>) -> Self {
+        Self(keys, PhantomData)
+    }
+
+    /// Values of the keys in insertion order, removed and reserved keys are skipped
+    pub fn get_all<'a>(&'a self, store: &'a Store<T>) -> impl Iterator<Item = &'a T> + 'a {
+        self.0.iter().filter_map(|key| store.get(*key))
+    }
+}
+impl<T> Clone for ReferMany<T> {
+    fn clone(&self) -> Self {
+        Self::new(self.0.clone())
+    }
+}
+impl<T> PartialEq for ReferMany<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+impl<T> Eq for ReferMany<T> {}
+impl<T> fmt::Debug for ReferMany<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ReferMany").field(&self.0).finish()
+    }
+}
 impl<T> Deref for ReferMany<T> {
     type Target = Vec<usize>;
 
@@ -89,27 +240,308 @@ impl<T> DerefMut for ReferMany<T> {
     }
 }
 
-pub fn blue_noise_image(w: u32, h: u32) -> Vec<u8> {
-    let mut noise = BlueNoise::<Pcg64Mcg>::new(w as f32, h as f32, 5.0);
-    let noise_black = noise.with_samples(w * (h / 3)).with_seed(10);
+#[derive(Debug, Clone)]
+pub struct BlueNoiseParams {
+    /// Minimum distance between samples of a layer, in pixels
+    pub min_distance: f32,
+    /// Upper bound on the fraction of pixels a layer may cover
+    pub density: f32,
+    /// One layer per (seed, value), later layers overwrite earlier ones
+    pub layers: Vec<(u64, u8)>,
+    /// Wraps distances around the borders so the image tiles seamlessly
+    pub tileable: bool,
+    /// The noise is data, use `RGBA8Linear` rather than `RGBA8` when four channels are needed
+    pub pixel_format: PixelFormat,
+}
 
-    let mut noise2 = BlueNoise::<Pcg64Mcg>::new(w as f32, h as f32, 5.0);
-    let noise_gray = noise2.with_samples(w * (h / 3)).with_seed(20);
+impl Default for BlueNoiseParams {
+    fn default() -> Self {
+        Self {
+            min_distance: 5.0,
+            density: 1.0 / 3.0,
+            layers: vec![(10, 255), (20, 127)],
+            tileable: false,
+            pixel_format: PixelFormat::G8,
+        }
+    }
+}
+
+pub struct NoiseImage {
+    pub bytes: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+    pub pixel_format: PixelFormat,
+}
+
+impl NoiseImage {
+    pub fn as_raw_image(&self) -> RawImage<'_> {
+        RawImage::new(&self.bytes, (self.width, self.height), self.pixel_format)
+    }
+}
+
+// splitmix64, enough for sample placement and particles, stable across platforms
+#[derive(Debug, Clone)]
+pub(crate) struct SampleRng(pub(crate) u64);
+
+impl SampleRng {
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    /// In `[0, 1)`
+    pub(crate) fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+/// Poisson disk sampling (Bridson), points are in `[0, w) x [0, h)`
+pub fn poisson_disk_samples(
+    w: u32,
+    h: u32,
+    min_distance: f32,
+    max_samples: usize,
+    seed: u64,
+    tileable: bool,
+) -> Vec<(f32, f32)> {
+    const CANDIDATES: usize = 30;
+
+    let (wf, hf) = (w as f32, h as f32);
+    let cell = min_distance / std::f32::consts::SQRT_2;
+    let (gw, gh) = ((wf / cell).ceil() as i64, (hf / cell).ceil() as i64);
+    let mut grid: Vec<Option<usize>> = vec![None; (gw * gh) as usize];
+    let mut samples: Vec<(f32, f32)> = Vec::new();
+    let mut active: Vec<usize> = Vec::new();
+    let mut rng = SampleRng(seed);
+
+    let axis_distance = |a: f32, b: f32, size: f32| {
+        let d = (a - b).abs();
+        if tileable {
+            d.min(size - d)
+        } else {
+            d
+        }
+    };
+    let cell_of = |(x, y): (f32, f32)| ((x / cell) as i64, (y / cell) as i64);
+
+    let fits = |p: (f32, f32), grid: &Vec<Option<usize>>, samples: &Vec<(f32, f32)>| {
+        let (cx, cy) = cell_of(p);
+        for gy in cy - 2..=cy + 2 {
+            for gx in cx - 2..=cx + 2 {
+                let (gx, gy) = if tileable {
+                    (gx.rem_euclid(gw), gy.rem_euclid(gh))
+                } else if gx < 0 || gy < 0 || gx >= gw || gy >= gh {
+                    continue;
+                } else {
+                    (gx, gy)
+                };
+                if let Some(other) = grid[(gy * gw + gx) as usize] {
+                    let q = samples[other];
+                    let dx = axis_distance(p.0, q.0, wf);
+                    let dy = axis_distance(p.1, q.1, hf);
+                    if dx * dx + dy * dy < min_distance * min_distance {
+                        return false;
+                    }
+                }
+            }
+        }
+        true
+    };
+
+    if max_samples == 0 || w == 0 || h == 0 {
+        return samples;
+    }
+    let first = (rng.next_f32() * wf, rng.next_f32() * hf);
+    let (cx, cy) = cell_of(first);
+    grid[(cy * gw + cx) as usize] = Some(0);
+    samples.push(first);
+    active.push(0);
+
+    while !active.is_empty() && samples.len() < max_samples {
+        let slot = (rng.next_u64() % active.len() as u64) as usize;
+        let origin = samples[active[slot]];
+
+        let mut placed = false;
+        for _ in 0..CANDIDATES {
+            let angle = rng.next_f32() * std::f32::consts::TAU;
+            let radius = min_distance * (1.0 + rng.next_f32());
+            let mut p = (
+                origin.0 + radius * angle.cos(),
+                origin.1 + radius * angle.sin(),
+            );
+            if tileable {
+                p = (p.0.rem_euclid(wf), p.1.rem_euclid(hf));
+            }
+            // NOTE: rem_euclid can round up to the size itself
+            if !(0.0..wf).contains(&p.0) || !(0.0..hf).contains(&p.1) {
+                continue;
+            }
+            if fits(p, &grid, &samples) {
+                let (cx, cy) = cell_of(p);
+                grid[(cy * gw + cx) as usize] = Some(samples.len());
+                active.push(samples.len());
+                samples.push(p);
+                placed = true;
+                break;
+            }
+        }
+        if !placed {
+            active.swap_remove(slot);
+        }
+    }
 
+    samples
+}
+
+pub fn blue_noise_image(w: u32, h: u32, params: &BlueNoiseParams) -> NoiseImage {
+    let max_samples = ((w * h) as f32 * params.density) as usize;
     let mut img: Vec<u8> = vec![0; (w * h) as usize];
 
-    for p in noise_black {
-        img[(p.y as u32 * w + p.x as u32) as usize] = 255;
+    for &(seed, value) in &params.layers {
+        let samples =
+            poisson_disk_samples(w, h, params.min_distance, max_samples, seed, params.tileable);
+        for (x, y) in samples {
+            let (x, y) = (x as u32, y as u32);
+            if x < w && y < h {
+                img[(y * w + x) as usize] = value;
+            }
+        }
+    }
+
+    let bytes = match params.pixel_format {
+        PixelFormat::G8 => img,
+        PixelFormat::RGBA8 | PixelFormat::RGBA8Linear => {
+            img.into_iter().flat_map(|v| [v; 4]).collect()
+        }
+    };
+
+    NoiseImage {
+        bytes,
+        width: w,
+        height: h,
+        pixel_format: params.pixel_format,
+    }
+}
+
+pub fn blue_noise_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    w: u32,
+    h: u32,
+    params: &BlueNoiseParams,
+) -> anyhow::Result<Texture> {
+    let image = blue_noise_image(w, h, params);
+    Texture::from_raw_image(device, queue, &image.as_raw_image(), Some("Blue Noise"))
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_ecs::{
+        event::Events,
+        schedule::{ParallelSystemDescriptorCoercion, Stage, SystemStage},
+        world::World,
+    };
+
+    use crate::texture::PixelFormat;
+
+    use super::{
+        blue_noise_image, poisson_disk_samples, publish_store_removals_system,
+        sweep_removed_refers_system, BlueNoiseParams, Refer, ReferMany, Store, StoreRemoved,
+    };
+
+    #[test]
+    fn removed_keys_are_swept() {
+        let mut world = World::new();
+        world.init_resource::<Events<StoreRemoved<u32>>>();
+        let mut store = Store::<u32>::default();
+        let (removed, kept) = (store.insert(1), store.insert(2));
+        // silent removal, nothing is swept
+        let silent = store.insert(3);
+        store.remove(silent);
+        assert_eq!(store.remove_with_events(removed), Some(1));
+        world.insert_resource(store);
+
+        let single = world.spawn().insert(Refer::<u32>::new(removed)).id();
+        let other = world.spawn().insert(Refer::<u32>::new(kept)).id();
+        let dangling = world.spawn().insert(Refer::<u32>::new(silent)).id();
+        let many = world
+            .spawn()
+            .insert(ReferMany::<u32>::new(vec![removed, kept]))
+            .id();
+
+        SystemStage::single_threaded()
+            .with_system(publish_store_removals_system::<u32>)
+            .with_system(
+                sweep_removed_refers_system::<u32>.after(publish_store_removals_system::<u32>),
+            )
+            .run(&mut world);
+
+        assert!(world.get::<Refer<u32>>(single).is_none());
+        assert_eq!(**world.get::<Refer<u32>>(other).unwrap(), kept);
+        assert_eq!(**world.get::<Refer<u32>>(dangling).unwrap(), silent);
+        assert_eq!(**world.get::<ReferMany<u32>>(many).unwrap(), vec![kept]);
     }
-    let mut c = 0;
-    for p in noise_gray {
-        if p.y as u32 * w + p.x as u32 == 255 {
-            break;
+
+    #[test]
+    fn refers_read_the_store() {
+        let mut store = Store::<&str>::default();
+        let (a, b, c) = (store.insert("a"), store.insert("b"), store.insert("c"));
+        let reserved = store.reserve();
+        store.remove(b);
+
+        let refer = Refer::<&str>::new(a);
+        assert_eq!(refer.get(&store), Some(&"a"));
+        *refer.get_mut(&mut store).unwrap() = "A";
+        assert_eq!(refer.get(&store), Some(&"A"));
+        assert_eq!(Refer::<&str>::new(b).get(&store), None);
+        assert_eq!(Refer::<&str>::new(reserved).get(&store), None);
+        assert_eq!(refer, Refer::new(a));
+        assert_eq!(format!("{:?}", refer), format!("Refer({})", a));
+
+        // insertion order, not key order, missing keys skipped
+        let mut many = ReferMany::<&str>::new(vec![c, b, a]);
+        many.push(reserved);
+        assert_eq!(many.get_all(&store).collect::<Vec<_>>(), [&"c", &"A"]);
+        store.insert_reserved(reserved, "d");
+        assert_eq!(many.get_all(&store).collect::<Vec<_>>(), [&"c", &"A", &"d"]);
+        assert_eq!(many.clone(), many);
+        assert_ne!(many, ReferMany::new(vec![a, b, c, reserved]));
+    }
+
+    #[test]
+    fn tileable_samples_keep_distance_across_borders() {
+        let (w, h, r) = (37, 23, 4.0);
+        let samples = poisson_disk_samples(w, h, r, usize::MAX, 3, true);
+        assert!(samples.len() > 10);
+
+        let wrapped = |a: f32, b: f32, size: f32| {
+            let d = (a - b).abs();
+            d.min(size - d)
+        };
+        for (i, p) in samples.iter().enumerate() {
+            for q in &samples[i + 1..] {
+                let dx = wrapped(p.0, q.0, w as f32);
+                let dy = wrapped(p.1, q.1, h as f32);
+                assert!(dx * dx + dy * dy >= r * r - 1e-3);
+            }
         }
-        c += 1;
-        img[(p.y as u32 * w + p.x as u32) as usize] = 127;
     }
-    dbg!(c);
 
-    img
+    #[test]
+    fn image_is_deterministic_and_sized() {
+        let params = BlueNoiseParams {
+            pixel_format: PixelFormat::RGBA8Linear,
+            ..Default::default()
+        };
+        let a = blue_noise_image(33, 17, &params);
+        let b = blue_noise_image(33, 17, &params);
+
+        assert_eq!(a.bytes, b.bytes);
+        assert_eq!(a.bytes.len(), 33 * 17 * 4);
+        assert!(a.bytes.iter().any(|v| *v == 255));
+        assert!(a.bytes.iter().any(|v| *v == 127));
+    }
 }
@@ -1,8 +1,13 @@
 
 // NOTE: Copied from bevy_window-0.7.0
 
+use bevy_asset::Handle;
 use cgmath::Vector2;
 
+use crate::texture::Image;
+
+use super::util::LogicalVec2;
+
 pub enum WindowCommands {
     SetWindowMode {
         mode: WindowMode,
@@ -27,17 +32,35 @@ pub enum WindowCommands {
     SetDecorations {
         decorations: bool,
     },
+    /// Grabs the cursor as the platform does, see [`CursorMode`] for a portable one
     SetCursorLockMode {
         locked: bool,
     },
+    /// Applied with the grab, visibility and recentering the platform needs
+    SetCursorMode {
+        mode: CursorMode,
+    },
     SetCursorIcon {
         icon: CursorIcon,
     },
+    /// Validated once the image is loaded, see [`IconImage::cursor`]. Winit 0.26 has no
+    /// custom cursors, the command is then ignored
+    ///
+    /// [`IconImage::cursor`]: super::icon::IconImage::cursor
+    SetCursorIconCustom {
+        handle: Handle<Image>,
+        hotspot: (u32, u32),
+    },
+    /// Set once the image is loaded, commands after it wait with it.
+    /// At most [`MAX_WINDOW_ICON_SIZE`](super::icon::MAX_WINDOW_ICON_SIZE) pixels a side
+    SetWindowIcon {
+        handle: Handle<Image>,
+    },
     SetCursorVisibility {
         visible: bool,
     },
     SetCursorPosition {
-        position: Vector2<f32>,
+        position: LogicalVec2,
     },
     SetMaximized {
         maximized: bool,
@@ -51,6 +74,13 @@ pub enum WindowCommands {
     SetResizeConstraints {
         resize_constraints: WindowResizeConstraints,
     },
+    SetImeAllowed {
+        allowed: bool,
+    },
+    /// Where the IME candidate box is placed
+    SetImePosition {
+        position: LogicalVec2,
+    },
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -66,6 +96,88 @@ pub enum WindowMode {
     Fullscreen,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorMode {
+    /// Visible and free to leave the window
+    Free,
+    /// Visible and kept inside the window
+    Confined,
+    /// Hidden and held in place, for mouse look with `AccumulatedMouseMotion`
+    Locked,
+}
+
+impl Default for CursorMode {
+    fn default() -> Self {
+        CursorMode::Free
+    }
+}
+
+/// What grabbing the cursor does on a platform
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorGrab {
+    /// The cursor stays where it is
+    Locks,
+    /// The cursor stays inside the window
+    Confines,
+    /// The platform reported the grab as unsupported or it failed
+    Unsupported,
+}
+
+impl CursorGrab {
+    /// What `set_cursor_grab` does with winit 0.26: macOS locks, Windows and X11 confine
+    pub fn current() -> Self {
+        if cfg!(target_os = "macos") {
+            CursorGrab::Locks
+        } else if cfg!(any(
+            target_os = "ios",
+            target_os = "android",
+            target_arch = "wasm32"
+        )) {
+            CursorGrab::Unsupported
+        } else {
+            CursorGrab::Confines
+        }
+    }
+}
+
+/// Grab, visibility and recentering that come closest to a [`CursorMode`] on a platform
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CursorModePlan {
+    pub grab: bool,
+    pub visible: bool,
+    /// Move the cursor back to the center every frame, the platform cannot lock it
+    pub recenter: bool,
+    /// The mode the window ends up in
+    pub mode: CursorMode,
+    /// `mode` is not the requested one
+    pub fallback: bool,
+}
+
+impl CursorModePlan {
+    pub fn new(requested: CursorMode, grab: CursorGrab) -> Self {
+        let (grab, visible, recenter, mode) = match (requested, grab) {
+            (CursorMode::Free, _) => (false, true, false, CursorMode::Free),
+            (CursorMode::Confined, CursorGrab::Confines) => {
+                (true, true, false, CursorMode::Confined)
+            }
+            // a grab would hold the cursor in place, a visible free one is closer
+            (CursorMode::Confined, CursorGrab::Locks | CursorGrab::Unsupported) => {
+                (false, true, false, CursorMode::Free)
+            }
+            (CursorMode::Locked, CursorGrab::Locks) => (true, false, false, CursorMode::Locked),
+            (CursorMode::Locked, CursorGrab::Confines) => (true, false, true, CursorMode::Confined),
+            (CursorMode::Locked, CursorGrab::Unsupported) => (false, false, true, CursorMode::Free),
+        };
+        Self {
+            grab,
+            visible,
+            recenter,
+            mode,
+            fallback: mode != requested,
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 #[doc(alias = "vsync")]
@@ -86,6 +198,22 @@ pub enum PresentMode {
     Fifo = 2, // NOTE: The explicit ordinal values mirror wgpu and the vulkan spec.
 }
 
+impl From<PresentMode> for wgpu::PresentMode {
+    fn from(val: PresentMode) -> Self {
+        match val {
+            PresentMode::Immediate => wgpu::PresentMode::Immediate,
+            PresentMode::Mailbox => wgpu::PresentMode::Mailbox,
+            PresentMode::Fifo => wgpu::PresentMode::Fifo,
+        }
+    }
+}
+
+impl Default for PresentMode {
+    fn default() -> Self {
+        PresentMode::Fifo
+    }
+}
+
 /// The size limits on a window.
 /// These values are measured in logical pixels, so the user's
 /// scale factor does affect the size limits on the window.
@@ -224,4 +352,65 @@ impl Into<winit::window::CursorIcon> for CursorIcon {
             CursorIcon::RowResize => winit::window::CursorIcon::RowResize,
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::{CursorGrab, CursorMode, CursorModePlan};
+
+    fn plan(requested: CursorMode, grab: CursorGrab) -> (bool, bool, bool, CursorMode, bool) {
+        let plan = CursorModePlan::new(requested, grab);
+        (
+            plan.grab,
+            plan.visible,
+            plan.recenter,
+            plan.mode,
+            plan.fallback,
+        )
+    }
+
+    #[test]
+    fn free_releases_the_cursor_everywhere() {
+        for grab in [
+            CursorGrab::Locks,
+            CursorGrab::Confines,
+            CursorGrab::Unsupported,
+        ] {
+            assert_eq!(
+                plan(CursorMode::Free, grab),
+                (false, true, false, CursorMode::Free, false)
+            );
+        }
+    }
+
+    #[test]
+    fn confined_needs_a_confining_grab() {
+        assert_eq!(
+            plan(CursorMode::Confined, CursorGrab::Confines),
+            (true, true, false, CursorMode::Confined, false)
+        );
+        assert_eq!(
+            plan(CursorMode::Confined, CursorGrab::Locks),
+            (false, true, false, CursorMode::Free, true)
+        );
+        assert_eq!(
+            plan(CursorMode::Confined, CursorGrab::Unsupported),
+            (false, true, false, CursorMode::Free, true)
+        );
+    }
+
+    #[test]
+    fn locked_falls_back_to_recentering() {
+        assert_eq!(
+            plan(CursorMode::Locked, CursorGrab::Locks),
+            (true, false, false, CursorMode::Locked, false)
+        );
+        assert_eq!(
+            plan(CursorMode::Locked, CursorGrab::Confines),
+            (true, false, true, CursorMode::Confined, true)
+        );
+        assert_eq!(
+            plan(CursorMode::Locked, CursorGrab::Unsupported),
+            (false, false, true, CursorMode::Free, true)
+        );
+    }
+}
@@ -1,4 +1,10 @@
-use super::{WindowId, WindowDescriptor};
+use std::path::PathBuf;
+
+use super::{
+    commands::{CursorMode, PresentMode, WindowMode},
+    util::LogicalVec2,
+    WindowDescriptor, WindowId,
+};
 
 
 pub struct CreateWindow {
@@ -23,4 +29,68 @@ pub struct CursorEntered {
 
 pub struct CursorLeft {
     pub window_id: WindowId,
-}
\ No newline at end of file
+}
+
+pub struct CursorMoved {
+    pub window_id: WindowId,
+    pub position: LogicalVec2,
+}
+
+/// Sent once a [`CursorMode`] is applied, `mode` differs from `requested` after a fallback
+pub struct CursorModeChanged {
+    pub window_id: WindowId,
+    pub requested: CursorMode,
+    pub mode: CursorMode,
+    pub fallback: bool,
+}
+
+pub struct PresentModeChanged {
+    pub window_id: WindowId,
+    pub present_mode: PresentMode,
+}
+
+/// Sent once the mode is applied, the new size follows with [`WindowResized`]
+pub struct WindowModeChanged {
+    pub window_id: WindowId,
+    pub mode: WindowMode,
+}
+
+/// New inner size in physical pixels, zero while minimized on some platforms
+pub struct WindowResized {
+    pub window_id: WindowId,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// The window moved to a monitor of another DPI or the setting changed,
+/// the new size is sent with [`WindowResized`]
+pub struct WindowScaleFactorChanged {
+    pub window_id: WindowId,
+    /// Physical pixels per logical pixel
+    pub scale_factor: f64,
+}
+
+pub enum FileDragAndDrop {
+    Dropped { window_id: WindowId, path: PathBuf },
+    Hovered { window_id: WindowId, path: PathBuf },
+    HoveredCancelled { window_id: WindowId },
+}
+
+/// A character typed with a key, text from the IME is sent as [`Ime::Commit`] instead
+pub struct ReceivedCharacter {
+    pub window_id: WindowId,
+    pub char: char,
+}
+
+/// Only sent for windows with IME allowed, see `WindowCommands::SetImeAllowed`
+pub enum Ime {
+    /// Text being composed, `cursor` is a byte range in `value`,
+    /// empty once the composition ends and its text is committed
+    // TODO: winit 0.26 does not report the text being composed, only its end is sent
+    Preedit {
+        window_id: WindowId,
+        value: String,
+        cursor: Option<(usize, usize)>,
+    },
+    Commit { window_id: WindowId, value: String },
+}
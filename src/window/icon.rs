@@ -0,0 +1,272 @@
+use std::fmt;
+
+use bevy_asset::Handle;
+
+use crate::texture::{Image, PixelFormat, RawImage};
+
+use super::commands::WindowCommands;
+
+/// Window icons are scaled down by the platform, larger images are a mistake
+pub const MAX_WINDOW_ICON_SIZE: u32 = 256;
+pub const MAX_CURSOR_ICON_SIZE: u32 = 128;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IconError {
+    TooLarge {
+        dim: (u32, u32),
+        max: u32,
+    },
+    /// The bytes do not match the size and pixel format
+    SizeMismatch {
+        expected: usize,
+        actual: usize,
+    },
+    HotspotOutside {
+        hotspot: (u32, u32),
+        dim: (u32, u32),
+    },
+    /// Rejected by winit
+    Platform(String),
+}
+
+impl fmt::Display for IconError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IconError::TooLarge { dim, max } => write!(
+                f,
+                "{}x{} is larger than the {}x{} an icon can be",
+                dim.0, dim.1, max, max
+            ),
+            IconError::SizeMismatch { expected, actual } => {
+                write!(f, "expected {} bytes of pixels, got {}", expected, actual)
+            }
+            IconError::HotspotOutside { hotspot, dim } => write!(
+                f,
+                "hotspot ({}, {}) is outside the {}x{} cursor",
+                hotspot.0, hotspot.1, dim.0, dim.1
+            ),
+            IconError::Platform(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for IconError {}
+
+/// Unpremultiplied RGBA8 rows, top to bottom, as winit takes icons
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IconImage {
+    pub rgba: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl IconImage {
+    /// Gray images are expanded to opaque RGBA, linear RGBA is taken as is
+    pub fn from_raw(raw: &RawImage<'_>, max_size: u32) -> Result<Self, IconError> {
+        let (width, height, _) = raw.dim;
+        if width > max_size || height > max_size {
+            return Err(IconError::TooLarge {
+                dim: (width, height),
+                max: max_size,
+            });
+        }
+        let expected = raw.pixel_format.image_bytes((width, height)) as usize;
+        if raw.bytes.len() != expected {
+            return Err(IconError::SizeMismatch {
+                expected,
+                actual: raw.bytes.len(),
+            });
+        }
+        let rgba = match raw.pixel_format {
+            PixelFormat::G8 => raw.bytes.iter().flat_map(|&g| [g, g, g, 255]).collect(),
+            PixelFormat::RGBA8 | PixelFormat::RGBA8Linear => raw.bytes.to_vec(),
+        };
+        Ok(Self {
+            rgba,
+            width,
+            height,
+        })
+    }
+
+    pub fn window_icon(image: &Image) -> Result<Self, IconError> {
+        Self::from_raw(&image.as_raw_image(), MAX_WINDOW_ICON_SIZE)
+    }
+
+    /// `hotspot` is the pixel that points, from the top left
+    pub fn cursor(image: &Image, hotspot: (u32, u32)) -> Result<Self, IconError> {
+        let icon = Self::from_raw(&image.as_raw_image(), MAX_CURSOR_ICON_SIZE)?;
+        if hotspot.0 >= icon.width || hotspot.1 >= icon.height {
+            return Err(IconError::HotspotOutside {
+                hotspot,
+                dim: (icon.width, icon.height),
+            });
+        }
+        Ok(icon)
+    }
+
+    pub fn into_winit(self) -> Result<winit::window::Icon, IconError> {
+        winit::window::Icon::from_rgba(self.rgba, self.width, self.height)
+            .map_err(|error| IconError::Platform(error.to_string()))
+    }
+}
+
+/// Of the image a window command waits for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageState {
+    Loaded,
+    Loading,
+    Failed,
+}
+
+/// The image asset of the command, if it has one
+fn command_image(command: &WindowCommands) -> Option<&Handle<Image>> {
+    match command {
+        WindowCommands::SetCursorIconCustom { handle, .. }
+        | WindowCommands::SetWindowIcon { handle } => Some(handle),
+        _ => None,
+    }
+}
+
+/// Takes the commands that can run this frame out of `queue`, in order. A command waiting
+/// for its image stays queued, and so do the commands after it, so a later command still
+/// overrides it. Commands whose image failed to load are dropped
+pub fn take_ready_commands(
+    queue: &mut Vec<WindowCommands>,
+    image_state: impl Fn(&Handle<Image>) -> ImageState,
+) -> Vec<WindowCommands> {
+    let mut ready = Vec::with_capacity(queue.len());
+    let mut commands = std::mem::take(queue).into_iter();
+    while let Some(command) = commands.next() {
+        let state = command_image(&command).map_or(ImageState::Loaded, &image_state);
+        match state {
+            ImageState::Loaded => ready.push(command),
+            ImageState::Failed => log::error!("Icon image failed to load, command dropped"),
+            ImageState::Loading => {
+                queue.push(command);
+                queue.extend(commands);
+                break;
+            }
+        }
+    }
+    ready
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_asset::{Handle, HandleId};
+
+    use crate::{
+        texture::{Image, PixelFormat, RawImage},
+        window::commands::WindowCommands,
+    };
+
+    use super::{take_ready_commands, IconError, IconImage, ImageState};
+
+    #[test]
+    fn pixels_are_converted_to_rgba8() {
+        let gray = RawImage::new(&[0, 128, 255, 64], (2, 2), PixelFormat::G8);
+        let icon = IconImage::from_raw(&gray, 16).unwrap();
+        assert_eq!((icon.width, icon.height), (2, 2));
+        assert_eq!(&icon.rgba[..8], &[0, 0, 0, 255, 128, 128, 128, 255]);
+        assert_eq!(icon.rgba.len(), 16);
+
+        let rgba = [1, 2, 3, 4];
+        for format in [PixelFormat::RGBA8, PixelFormat::RGBA8Linear] {
+            let raw = RawImage::new(&rgba, (1, 1), format);
+            assert_eq!(IconImage::from_raw(&raw, 16).unwrap().rgba, rgba);
+        }
+
+        let short = RawImage::new(&[0; 7], (1, 2), PixelFormat::RGBA8);
+        assert_eq!(
+            IconImage::from_raw(&short, 16),
+            Err(IconError::SizeMismatch {
+                expected: 8,
+                actual: 7
+            })
+        );
+    }
+
+    #[test]
+    fn icons_are_validated() {
+        let image = |width: u32, height: u32| Image {
+            bytes: vec![255; (width * height * 4) as usize],
+            dim: (width, height),
+            is_srgb: true,
+        };
+        assert!(IconImage::window_icon(&image(256, 32)).is_ok());
+        assert_eq!(
+            IconImage::window_icon(&image(257, 32)),
+            Err(IconError::TooLarge {
+                dim: (257, 32),
+                max: 256
+            })
+        );
+        assert!(IconImage::cursor(&image(32, 32), (31, 0)).is_ok());
+        assert_eq!(
+            IconImage::cursor(&image(32, 16), (0, 16)),
+            Err(IconError::HotspotOutside {
+                hotspot: (0, 16),
+                dim: (32, 16)
+            })
+        );
+        assert!(matches!(
+            IconImage::cursor(&image(256, 256), (0, 0)),
+            Err(IconError::TooLarge { max: 128, .. })
+        ));
+    }
+
+    #[test]
+    fn commands_wait_for_their_image() {
+        let loading: Handle<Image> = Handle::weak(HandleId::random::<Image>());
+        let failed: Handle<Image> = Handle::weak(HandleId::random::<Image>());
+        let title = |title: &str| WindowCommands::SetTitle {
+            title: title.to_string(),
+        };
+        let titles = |commands: &[WindowCommands]| -> Vec<String> {
+            commands
+                .iter()
+                .map(|command| match command {
+                    WindowCommands::SetTitle { title } => title.clone(),
+                    WindowCommands::SetWindowIcon { .. } => "icon".to_string(),
+                    _ => unreachable!(),
+                })
+                .collect()
+        };
+
+        let mut queue = vec![
+            title("a"),
+            WindowCommands::SetWindowIcon {
+                handle: failed.clone(),
+            },
+            title("b"),
+            WindowCommands::SetWindowIcon {
+                handle: loading.clone(),
+            },
+            title("c"),
+        ];
+        let state = |loaded: bool| {
+            let (loading, failed) = (loading.clone(), failed.clone());
+            move |handle: &Handle<Image>| {
+                if *handle == failed {
+                    ImageState::Failed
+                } else if *handle == loading && !loaded {
+                    ImageState::Loading
+                } else {
+                    ImageState::Loaded
+                }
+            }
+        };
+
+        // the failed icon is dropped, everything from the loading one on waits
+        let ready = take_ready_commands(&mut queue, state(false));
+        assert_eq!(titles(&ready), ["a", "b"]);
+        assert_eq!(titles(&queue), ["icon", "c"]);
+        let ready = take_ready_commands(&mut queue, state(false));
+        assert!(ready.is_empty());
+        assert_eq!(queue.len(), 2);
+
+        let ready = take_ready_commands(&mut queue, state(true));
+        assert_eq!(titles(&ready), ["icon", "c"]);
+        assert!(queue.is_empty());
+    }
+}
@@ -1,23 +1,51 @@
 use std::collections::HashMap;
 
 use bevy_app::{CoreStage, Plugin};
-use bevy_ecs::system::IntoExclusiveSystem;
+use bevy_asset::Handle;
+use bevy_ecs::{
+    schedule::{ExclusiveSystemDescriptorCoercion, SystemLabel},
+    system::{IntoExclusiveSystem, Res, ResMut},
+};
 use winit::{
     event_loop::{EventLoop, EventLoopWindowTarget},
     window::WindowBuilder,
 };
 
+use crate::{
+    input::{
+        action::modifiers_from_keys, capture::InputCapture, keyboard::KeyCode, mouse::MouseButton,
+        Input, ModifiersState,
+    },
+    texture::Image,
+};
+
 use self::{
-    commands::WindowCommands,
-    events::{CreateWindow, CursorEntered, CursorLeft, FocusChanged, RequestRedraw, WindowCreated},
-    runner::{execute_window_commands, handle_create_window, winit_event_loop_runner},
+    commands::{CursorMode, PresentMode, WindowCommands, WindowMode},
+    events::{
+        CreateWindow, CursorEntered, CursorLeft, CursorModeChanged, CursorMoved, FileDragAndDrop,
+        FocusChanged, Ime, PresentModeChanged, ReceivedCharacter, RequestRedraw, WindowCreated,
+        WindowModeChanged, WindowResized, WindowScaleFactorChanged,
+    },
+    runner::{
+        create_window_system, execute_window_commands, handle_create_window,
+        winit_event_loop_runner, RawEventSubscribers, UpdateMode,
+    },
+    screen::{update_screen_space_system, ScreenSpace},
+    util::LogicalVec2,
 };
 
 pub mod commands;
 pub mod events;
+pub mod icon;
 pub mod runner;
+pub mod screen;
 pub mod util;
 
+/// Creates the windows requested with [`CreateWindow`] during the update,
+/// window commands run after it
+#[derive(SystemLabel)]
+pub struct CreateWindowSystem;
+
 pub struct FlatWinitPlugin {
     pub create_primary_window: bool,
 }
@@ -35,11 +63,21 @@ impl Plugin for FlatWinitPlugin {
         let event_loop = EventLoop::new();
 
         app.init_resource::<WinitWindows>()
+            .init_non_send_resource::<RawEventSubscribers>()
+            .init_resource::<UpdateMode>()
             .set_runner(winit_event_loop_runner)
             // NOTE: What is ExclusiveSystem
             .add_system_to_stage(
                 CoreStage::PostUpdate,
-                execute_window_commands.exclusive_system(),
+                create_window_system
+                    .exclusive_system()
+                    .label(CreateWindowSystem),
+            )
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                execute_window_commands
+                    .exclusive_system()
+                    .after(CreateWindowSystem),
             );
 
         if self.create_primary_window {
@@ -69,7 +107,18 @@ impl Plugin for FlatWindowPlugin {
             .add_event::<RequestRedraw>()
             .add_event::<FocusChanged>()
             .add_event::<CursorEntered>()
-            .add_event::<CursorLeft>();
+            .add_event::<CursorLeft>()
+            .add_event::<CursorMoved>()
+            .add_event::<CursorModeChanged>()
+            .add_event::<PresentModeChanged>()
+            .add_event::<WindowModeChanged>()
+            .add_event::<WindowResized>()
+            .add_event::<WindowScaleFactorChanged>()
+            .add_event::<FileDragAndDrop>()
+            .add_event::<ReceivedCharacter>()
+            .add_event::<Ime>()
+            .init_resource::<ScreenSpace>()
+            .add_system_to_stage(CoreStage::PreUpdate, update_screen_space_system);
     }
 }
 
@@ -94,17 +143,33 @@ impl WinitWindows {
         //
 
         let winit_window = builder.build(event_loop).expect("Window build failed");
+        let size = winit_window.inner_size();
+        let scale_factor = winit_window.scale_factor();
 
         self.winit_to_lib.insert(winit_window.id(), id);
         self.lib_to_winit.insert(id, winit_window.id());
         self.map.insert(id, winit_window);
 
-        Window::new(id, desc)
+        let mut window = Window::new(id, desc);
+        window.update_resolution((size.width, size.height));
+        window.update_scale_factor(scale_factor);
+        window
+    }
+
+    pub fn get_window(&self, id: WindowId) -> Option<&winit::window::Window> {
+        self.map.get(&id)
+    }
+
+    /// `None` for windows that were not created here or are already destroyed
+    pub fn lib_id(&self, winit_id: winit::window::WindowId) -> Option<WindowId> {
+        self.winit_to_lib.get(&winit_id).copied()
     }
 }
 
 pub struct Windows {
     pub map: HashMap<WindowId, Window>,
+    // commands for windows that are not created yet
+    pending: HashMap<WindowId, Vec<WindowCommands>>,
     next_id: usize,
 }
 
@@ -112,16 +177,29 @@ impl Default for Windows {
     fn default() -> Self {
         Self {
             map: Default::default(),
+            pending: Default::default(),
             next_id: 1,
         }
     }
 }
 
 impl Windows {
-    pub fn add(&mut self, window: Window) {
+    pub fn add(&mut self, mut window: Window) {
+        if let Some(pending) = self.pending.remove(&window.id) {
+            window.command_queue.extend(pending);
+        }
         self.map.insert(window.id, window);
     }
 
+    /// Queues `command` for the window, also for ones requested with [`CreateWindow`]
+    /// but not created yet
+    pub fn execute(&mut self, id: WindowId, command: WindowCommands) {
+        match self.map.get_mut(&id) {
+            Some(window) => window.execute(command),
+            None => self.pending.entry(id).or_default().push(command),
+        }
+    }
+
     pub fn reserve_id(&mut self) -> WindowId {
         let id = WindowId(self.next_id);
         self.next_id += 1;
@@ -151,6 +229,21 @@ impl WindowId {
 pub struct Window {
     pub id: WindowId,
     pub desc: WindowDescriptor,
+    present_mode: PresentMode,
+    mode: WindowMode,
+    resolution: (u32, u32),
+    scale_factor: f64,
+    ime_allowed: bool,
+    // the last key pressed has a key code, the next character was typed with it
+    key_typed: bool,
+    // characters are arriving from the IME, the composition was ended
+    ime_committing: bool,
+    focused: bool,
+    cursor_position: Option<LogicalVec2>,
+    requested_cursor_mode: CursorMode,
+    cursor_mode: CursorMode,
+    // the platform could not lock the cursor, it is moved back to the center every frame
+    cursor_recenter: bool,
     command_queue: Vec<WindowCommands>,
 }
 
@@ -158,6 +251,18 @@ impl Window {
     pub fn new(id: WindowId, desc: WindowDescriptor) -> Self {
         Self {
             id,
+            present_mode: desc.present_mode,
+            mode: WindowMode::Windowed,
+            resolution: (0, 0),
+            scale_factor: 1.0,
+            ime_allowed: false,
+            key_typed: false,
+            ime_committing: false,
+            focused: true,
+            cursor_position: None,
+            requested_cursor_mode: CursorMode::Free,
+            cursor_mode: CursorMode::Free,
+            cursor_recenter: false,
             desc,
             command_queue: Vec::new(),
         }
@@ -166,13 +271,170 @@ impl Window {
     pub fn execute(&mut self, command: WindowCommands) {
         self.command_queue.push(command);
     }
+
+    pub fn present_mode(&self) -> PresentMode {
+        self.present_mode
+    }
+
+    pub fn set_present_mode(&mut self, present_mode: PresentMode) {
+        self.execute(WindowCommands::SetPresentMode { present_mode });
+    }
+
+    /// The mode the surface fell back to, the requested one is not supported
+    pub(crate) fn update_present_mode(&mut self, present_mode: PresentMode) {
+        self.present_mode = present_mode;
+    }
+
+    /// The last mode set, applied with the next window commands
+    pub fn mode(&self) -> WindowMode {
+        self.mode
+    }
+
+    /// Inner size in physical pixels, as of the last [`WindowResized`]
+    pub fn resolution(&self) -> (u32, u32) {
+        self.resolution
+    }
+
+    pub(crate) fn update_resolution(&mut self, resolution: (u32, u32)) {
+        self.resolution = resolution;
+    }
+
+    /// Physical pixels per logical pixel, as of the last [`WindowScaleFactorChanged`]
+    pub fn scale_factor(&self) -> f64 {
+        self.scale_factor
+    }
+
+    pub(crate) fn update_scale_factor(&mut self, scale_factor: f64) {
+        self.scale_factor = scale_factor;
+    }
+
+    /// `SizedFullscreen` picks the video mode closest to the current resolution
+    pub fn set_mode(&mut self, mode: WindowMode) {
+        self.mode = mode;
+        self.execute(WindowCommands::SetWindowMode {
+            mode,
+            resolution: self.resolution,
+        });
+    }
+
+    /// Between windowed and borderless fullscreen, exclusive fullscreen goes back to windowed
+    pub fn toggle_fullscreen(&mut self) {
+        let mode = match self.mode {
+            WindowMode::Windowed => WindowMode::BorderlessFullscreen,
+            _ => WindowMode::Windowed,
+        };
+        self.set_mode(mode);
+    }
+
+    pub fn ime_allowed(&self) -> bool {
+        self.ime_allowed
+    }
+
+    /// `None` while outside the window
+    pub fn cursor_position(&self) -> Option<LogicalVec2> {
+        self.cursor_position
+    }
+
+    pub(crate) fn update_cursor_position(&mut self, position: Option<LogicalVec2>) {
+        self.cursor_position = position;
+    }
+
+    /// As of the last [`FocusChanged`]
+    pub fn focused(&self) -> bool {
+        self.focused
+    }
+
+    pub(crate) fn update_focused(&mut self, focused: bool) {
+        self.focused = focused;
+    }
+
+    /// The mode applied by the window commands, a fallback if the platform could not
+    /// apply the requested one, see [`CursorModeChanged`]
+    pub fn cursor_mode(&self) -> CursorMode {
+        self.cursor_mode
+    }
+
+    /// Grabs, hides and recenters the cursor as the platform needs for `mode`.
+    /// `Locked` falls back to `Confined` and recentering where the cursor cannot be locked
+    pub fn set_cursor_mode(&mut self, mode: CursorMode) {
+        self.requested_cursor_mode = mode;
+        self.execute(WindowCommands::SetCursorMode { mode });
+    }
+
+    /// Between free and locked, by the requested mode so a fallback still toggles back
+    pub fn toggle_cursor_lock(&mut self) {
+        let mode = match self.requested_cursor_mode {
+            CursorMode::Free => CursorMode::Locked,
+            CursorMode::Confined | CursorMode::Locked => CursorMode::Free,
+        };
+        self.set_cursor_mode(mode);
+    }
+
+    pub fn set_ime_allowed(&mut self, allowed: bool) {
+        self.execute(WindowCommands::SetImeAllowed { allowed });
+    }
+
+    pub fn set_ime_position(&mut self, position: LogicalVec2) {
+        self.execute(WindowCommands::SetImePosition { position });
+    }
+
+    pub fn set_cursor_position(&mut self, position: LogicalVec2) {
+        self.execute(WindowCommands::SetCursorPosition { position });
+    }
+
+    /// Applied once the image is loaded
+    pub fn set_window_icon(&mut self, handle: Handle<Image>) {
+        self.execute(WindowCommands::SetWindowIcon { handle });
+    }
+
+    pub fn toggle_vsync(&mut self) {
+        let present_mode = match self.present_mode {
+            PresentMode::Fifo => PresentMode::Immediate,
+            PresentMode::Immediate | PresentMode::Mailbox => PresentMode::Fifo,
+        };
+        self.set_present_mode(present_mode);
+    }
+}
+
+/// Toggles the primary window fullscreen on Alt+Enter, not added by the plugins
+pub fn toggle_fullscreen_system(keys: Res<Input<KeyCode>>, mut windows: ResMut<Windows>) {
+    if !keys.just_pressed(KeyCode::Return)
+        || !modifiers_from_keys(&keys).contains(ModifiersState::ALT)
+    {
+        return;
+    }
+    if let Some(window) = windows.map.get_mut(&WindowId::primary()) {
+        window.toggle_fullscreen();
+    }
+}
+
+/// Toggles the cursor lock of the primary window on right click, for mouse look
+/// with `AccumulatedMouseMotion`. Not added by the plugins
+/// Not while the UI captures the pointer, see [`InputCapture`]
+pub fn toggle_cursor_lock_system(
+    buttons: Res<Input<MouseButton>>,
+    capture: Option<Res<InputCapture>>,
+    mut windows: ResMut<Windows>,
+) {
+    if !buttons.just_pressed(MouseButton::Right)
+        || capture.map_or(false, |capture| capture.pointer_captured())
+    {
+        return;
+    }
+    if let Some(window) = windows.map.get_mut(&WindowId::primary()) {
+        window.toggle_cursor_lock();
+    }
 }
 
 #[derive(Clone)]
-pub struct WindowDescriptor {}
+pub struct WindowDescriptor {
+    pub present_mode: PresentMode,
+}
 
 impl Default for WindowDescriptor {
     fn default() -> Self {
-        Self {}
+        Self {
+            present_mode: PresentMode::Fifo,
+        }
     }
 }
@@ -1,4 +1,7 @@
+use std::time::{Duration, Instant};
+
 use bevy_app::AppExit;
+use bevy_asset::{AssetServer, Assets, Handle, LoadState};
 use bevy_ecs::{
     event::ManualEventReader,
     prelude::Events,
@@ -9,55 +12,116 @@ use winit::{
     event_loop::{ControlFlow, EventLoop, EventLoopWindowTarget},
 };
 
-use crate::input::{
-    keyboard::KeyboardInput,
-    mouse::{MouseButtonInput, MouseMotion, MouseWheel},
-    ModifiersChanged, ModifiersState,
+use crate::{
+    input::{
+        keyboard::KeyboardInput,
+        mouse::{MouseButtonInput, MouseMotion, MouseWheel},
+        touch::TouchInput,
+        ModifiersChanged, ModifiersState,
+    },
+    texture::Image,
 };
 
 use super::{
-    commands::{WindowCommands, WindowMode},
-    events::{CreateWindow, CursorEntered, CursorLeft, FocusChanged, WindowCreated, RequestRedraw},
-    util, Windows, WinitWindows,
+    commands::{CursorGrab, CursorModePlan, WindowCommands, WindowMode},
+    events::{
+        CreateWindow, CursorEntered, CursorLeft, CursorModeChanged, CursorMoved, FileDragAndDrop,
+        FocusChanged, Ime, PresentModeChanged, ReceivedCharacter, RequestRedraw, WindowCreated,
+        WindowModeChanged, WindowResized, WindowScaleFactorChanged,
+    },
+    icon::{take_ready_commands, IconImage, ImageState},
+    util::{self, LogicalVec2, PhysicalVec2},
+    Windows, WinitWindows,
 };
 
+/// Set by the runner only while the app updates, so systems can create windows
+pub(crate) struct ActiveWindowTarget(*const EventLoopWindowTarget<()>);
+
+pub fn create_window_system(world: &mut World) {
+    let event_loop = match world.get_non_send_resource::<ActiveWindowTarget>() {
+        Some(target) => target.0,
+        None => return,
+    };
+    // SAFETY: the runner removes the target once the update returns,
+    // it outlives every system run of that update
+    let event_loop = unsafe { &*event_loop };
+    handle_create_window(world, event_loop);
+}
+
+/// Commands of windows without a winit window yet stay queued, and so do commands waiting
+/// for an icon image to load
 pub fn execute_window_commands(world: &mut World) {
     let world = world.cell();
     let winit_windows = world.get_resource::<WinitWindows>().unwrap();
     let mut windows = world.get_resource_mut::<Windows>().unwrap();
+    let mut present_mode_events = world
+        .get_resource_mut::<Events<PresentModeChanged>>()
+        .unwrap();
+    let mut mode_events = world
+        .get_resource_mut::<Events<WindowModeChanged>>()
+        .unwrap();
+    let mut cursor_mode_events = world
+        .get_resource_mut::<Events<CursorModeChanged>>()
+        .unwrap();
+    let images = world.get_resource::<Assets<Image>>();
+    let asset_server = world.get_resource::<AssetServer>();
+    let image_state = |handle: &Handle<Image>| {
+        if images
+            .as_ref()
+            .map_or(false, |images| images.contains(handle))
+        {
+            ImageState::Loaded
+        } else if asset_server.as_ref().map_or(false, |server| {
+            server.get_load_state(handle) == LoadState::Failed
+        }) {
+            ImageState::Failed
+        } else {
+            ImageState::Loading
+        }
+    };
 
     for (id, window) in windows.map.iter_mut() {
-        for command in window.command_queue.drain(..) {
-            let winit_window = winit_windows.map.get(id).unwrap();
+        let winit_window = match winit_windows.map.get(id) {
+            Some(winit_window) => winit_window,
+            None => continue,
+        };
+        for command in take_ready_commands(&mut window.command_queue, &image_state) {
             match command {
                 WindowCommands::SetWindowMode {
                     mode,
                     resolution: (width, height),
-                } => match mode {
-                    WindowMode::Windowed => {
-                        winit_window.set_fullscreen(None);
-                    }
-                    WindowMode::BorderlessFullscreen => {
-                        winit_window
-                            .set_fullscreen(Some(winit::window::Fullscreen::Borderless(None)));
-                    }
-                    WindowMode::SizedFullscreen => {
-                        winit_window.set_fullscreen(Some(winit::window::Fullscreen::Exclusive(
-                            util::get_fitting_videomode(
-                                winit_window.current_monitor().as_ref().unwrap(),
-                                width,
-                                height,
-                            ),
-                        )));
-                    }
-                    WindowMode::Fullscreen => {
-                        winit_window.set_fullscreen(Some(winit::window::Fullscreen::Exclusive(
-                            util::get_best_videomode(
-                                winit_window.current_monitor().as_ref().unwrap(),
-                            ),
-                        )));
+                } => {
+                    match mode {
+                        WindowMode::Windowed => {
+                            winit_window.set_fullscreen(None);
+                        }
+                        WindowMode::BorderlessFullscreen => {
+                            winit_window
+                                .set_fullscreen(Some(winit::window::Fullscreen::Borderless(None)));
+                        }
+                        WindowMode::SizedFullscreen => {
+                            winit_window.set_fullscreen(Some(
+                                winit::window::Fullscreen::Exclusive(util::get_fitting_videomode(
+                                    winit_window.current_monitor().as_ref().unwrap(),
+                                    width,
+                                    height,
+                                )),
+                            ));
+                        }
+                        WindowMode::Fullscreen => {
+                            winit_window.set_fullscreen(Some(
+                                winit::window::Fullscreen::Exclusive(util::get_best_videomode(
+                                    winit_window.current_monitor().as_ref().unwrap(),
+                                )),
+                            ));
+                        }
                     }
-                },
+                    // NOTE: the new inner size arrives with WindowEvent::Resized
+                    mode_events.send(WindowModeChanged {
+                        window_id: *id,
+                        mode,
+                    });
+                }
                 WindowCommands::SetTitle { title } => {
                     winit_window.set_title(&title);
                 }
@@ -73,7 +137,15 @@ pub fn execute_window_commands(world: &mut World) {
                             .to_physical::<f64>(scale_factor),
                     );
                 }
-                WindowCommands::SetPresentMode { .. } => {}
+                WindowCommands::SetPresentMode { present_mode } => {
+                    if window.present_mode != present_mode {
+                        window.present_mode = present_mode;
+                        present_mode_events.send(PresentModeChanged {
+                            window_id: *id,
+                            present_mode,
+                        });
+                    }
+                }
                 WindowCommands::SetResizable { resizable } => {
                     winit_window.set_resizable(resizable);
                 }
@@ -81,25 +153,60 @@ pub fn execute_window_commands(world: &mut World) {
                     winit_window.set_decorations(decorations);
                 }
                 WindowCommands::SetCursorLockMode { locked } => {
-                    winit_window.set_cursor_grab(locked).unwrap_or_else(|_e| {});
+                    if let Err(err) = winit_window.set_cursor_grab(locked) {
+                        log::warn!("Could not set the cursor grab to {}: {}", locked, err);
+                    }
+                }
+                WindowCommands::SetCursorMode { mode } => {
+                    let mut plan = CursorModePlan::new(mode, CursorGrab::current());
+                    // NOTE: also releases the grab of an earlier mode
+                    if let Err(err) = winit_window.set_cursor_grab(plan.grab) {
+                        if plan.grab {
+                            log::warn!("Could not grab the cursor, falling back: {}", err);
+                            plan = CursorModePlan::new(mode, CursorGrab::Unsupported);
+                        }
+                    }
+                    winit_window.set_cursor_visible(plan.visible);
+                    window.cursor_mode = plan.mode;
+                    window.cursor_recenter = plan.recenter;
+                    cursor_mode_events.send(CursorModeChanged {
+                        window_id: *id,
+                        requested: mode,
+                        mode: plan.mode,
+                        fallback: plan.fallback,
+                    });
                 }
                 WindowCommands::SetCursorIcon { icon } => {
                     winit_window.set_cursor_icon(icon.into());
                 }
+                WindowCommands::SetCursorIconCustom { handle, hotspot } => {
+                    // NOTE: loaded, see take_ready_commands
+                    let image = match images.as_ref().and_then(|images| images.get(&handle)) {
+                        Some(image) => image,
+                        None => continue,
+                    };
+                    match IconImage::cursor(image, hotspot) {
+                        Ok(_) => log::warn!(
+                            "Custom cursor icons are not supported on this platform, ignored"
+                        ),
+                        Err(err) => log::error!("Could not set the cursor icon: {}", err),
+                    }
+                }
+                WindowCommands::SetWindowIcon { handle } => {
+                    let image = match images.as_ref().and_then(|images| images.get(&handle)) {
+                        Some(image) => image,
+                        None => continue,
+                    };
+                    match IconImage::window_icon(image).and_then(IconImage::into_winit) {
+                        Ok(icon) => winit_window.set_window_icon(Some(icon)),
+                        Err(err) => log::error!("Could not set the window icon: {}", err),
+                    }
+                }
                 WindowCommands::SetCursorVisibility { visible } => {
                     winit_window.set_cursor_visible(visible);
                 }
                 WindowCommands::SetCursorPosition { position } => {
-                    // NOTE: What is this?
-                    let inner_size = winit_window
-                        .inner_size()
-                        .to_logical::<f32>(winit_window.scale_factor());
-                    winit_window
-                        .set_cursor_position(winit::dpi::LogicalPosition::new(
-                            position.x,
-                            inner_size.height - position.y,
-                        ))
-                        .unwrap_or_else(|_e| {});
+                    set_cursor_position(winit_window, position);
                 }
                 WindowCommands::SetMaximized { maximized } => {
                     winit_window.set_maximized(maximized);
@@ -129,111 +236,165 @@ pub fn execute_window_commands(world: &mut World) {
                         winit_window.set_max_inner_size(Some(max_inner_size));
                     }
                 }
+                WindowCommands::SetImeAllowed { allowed } => {
+                    // NOTE: winit 0.26 has no way to toggle the IME, only gates the Ime events
+                    window.ime_allowed = allowed;
+                }
+                WindowCommands::SetImePosition { position } => {
+                    let position = position.to_physical(
+                        winit_window.inner_size().height,
+                        winit_window.scale_factor(),
+                    );
+                    winit_window.set_ime_position(winit::dpi::PhysicalPosition::new(
+                        position.0.x,
+                        position.0.y,
+                    ));
+                }
             }
         }
+
+        // NOTE: stands in for a lock, the motion still arrives as MouseMotion
+        if window.cursor_recenter && window.focused {
+            let inner_size = winit_window
+                .inner_size()
+                .to_logical::<f32>(winit_window.scale_factor());
+            set_cursor_position(
+                winit_window,
+                LogicalVec2::new(inner_size.width / 2.0, inner_size.height / 2.0),
+            );
+        }
+    }
+}
+
+fn set_cursor_position(winit_window: &winit::window::Window, position: LogicalVec2) {
+    let position = position.to_physical(
+        winit_window.inner_size().height,
+        winit_window.scale_factor(),
+    );
+    winit_window
+        .set_cursor_position(winit::dpi::PhysicalPosition::new(
+            position.0.x,
+            position.0.y,
+        ))
+        .unwrap_or_else(|_e| {});
+}
+
+pub type RawEventSubscriber = Box<dyn FnMut(&mut World, &Event<()>)>;
+
+/// Receive every winit event before the runner translates it,
+/// for integrations that need more than the translated events
+#[derive(Default)]
+pub struct RawEventSubscribers(Vec<RawEventSubscriber>);
+
+impl RawEventSubscribers {
+    pub fn subscribe(&mut self, subscriber: impl FnMut(&mut World, &Event<()>) + 'static) {
+        self.0.push(Box::new(subscriber));
+    }
+}
+
+fn forward_raw_event(world: &mut World, event: &Event<()>) {
+    // NOTE: removed while forwarding so subscribers can borrow the world mutably
+    let mut subscribers = match world.remove_non_send_resource::<RawEventSubscribers>() {
+        Some(subscribers) => subscribers,
+        None => return,
+    };
+    for subscriber in subscribers.0.iter_mut() {
+        subscriber(world, event);
+    }
+    world.insert_non_send_resource(subscribers);
+}
+
+/// How often the runner updates the app, can be changed by systems
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpdateMode {
+    /// Every iteration of the event loop, for games
+    Continuous,
+    /// On window and device events or [`RequestRedraw`], and after `max_wait` at the latest
+    Reactive { max_wait: Duration },
+    /// Only on window events and [`RequestRedraw`], e.g. for editors. Device events, like
+    /// mouse motion outside of the window, are ignored
+    ReactiveLowPower,
+}
+
+impl Default for UpdateMode {
+    fn default() -> Self {
+        Self::Continuous
+    }
+}
+
+/// Events received since the last update
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PendingEvents {
+    pub window: bool,
+    pub device: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UpdateDecision {
+    pub update: bool,
+    /// Poll while an update is due, otherwise wait for the next event or the timeout
+    pub control_flow: ControlFlow,
+}
+
+pub fn decide_update(
+    mode: UpdateMode,
+    pending: PendingEvents,
+    redraw_requested: bool,
+    last_update: Instant,
+    now: Instant,
+) -> UpdateDecision {
+    let (update, wait) = match mode {
+        UpdateMode::Continuous => (true, ControlFlow::Poll),
+        UpdateMode::Reactive { max_wait } => {
+            let timeout = last_update + max_wait;
+            (
+                pending.window || pending.device || redraw_requested || now >= timeout,
+                ControlFlow::WaitUntil(timeout),
+            )
+        }
+        UpdateMode::ReactiveLowPower => (pending.window || redraw_requested, ControlFlow::Wait),
+    };
+    UpdateDecision {
+        update,
+        control_flow: if update { ControlFlow::Poll } else { wait },
     }
 }
 
+fn update_mode(world: &World) -> UpdateMode {
+    world
+        .get_resource::<UpdateMode>()
+        .copied()
+        .unwrap_or_default()
+}
+
 pub fn winit_event_loop_runner(mut app: bevy_app::App) {
     let event_loop = app.world.remove_non_send_resource::<EventLoop<()>>().unwrap();
     app.insert_non_send_resource(event_loop.create_proxy());
 
     let mut redraw_event_reader = ManualEventReader::<RequestRedraw>::default();
     let mut app_exit_event_reader = ManualEventReader::<AppExit>::default();
+    let mut pending = PendingEvents::default();
+    let mut redraw_requested = false;
+    let mut last_update = Instant::now();
 
     event_loop.run(move |event0, event_loop_wt, control_flow| {
+        forward_raw_event(&mut app.world, &event0);
+
+        match &event0 {
+            Event::WindowEvent { .. } | Event::RedrawRequested(_) => pending.window = true,
+            Event::DeviceEvent { .. } => pending.device = true,
+            _ => {}
+        }
         match event0 {
             Event::NewEvents(_) => {}
             Event::WindowEvent {
                 event,
                 window_id: winit_window_id,
-            } => match event {
-                // WindowEvent::Resized(_) => {},
-                // WindowEvent::Moved(_) => {},
-                WindowEvent::CloseRequested => {
+            } => {
+                if handle_window_event(&mut app.world, winit_window_id, event) {
                     *control_flow = ControlFlow::Exit;
-                },
-                // WindowEvent::Destroyed => {},
-                // WindowEvent::DroppedFile(_) => {},
-                // WindowEvent::HoveredFile(_) => {},
-                // WindowEvent::HoveredFileCancelled => {},
-                // WindowEvent::ReceivedCharacter(_) => {},
-                WindowEvent::Focused(focused) => {
-                    let world = app.world.cell();
-                    let winit_windows = world.get_resource::<WinitWindows>().unwrap();
-                    let window_id = winit_windows
-                        .winit_to_lib
-                        .get(&winit_window_id)
-                        .unwrap()
-                        .clone();
-                    let mut events = world.get_resource_mut::<Events<FocusChanged>>().unwrap();
-                    events.send(FocusChanged { window_id, focused });
-                }
-                WindowEvent::KeyboardInput { input, .. } => {
-                    let world = app.world.cell();
-                    let mut events = world.get_resource_mut::<Events<KeyboardInput>>().unwrap();
-                    events.send(KeyboardInput::from(input));
-                }
-                WindowEvent::ModifiersChanged(state) => {
-                    let world = app.world.cell();
-                    let mut events = world.get_resource_mut::<Events<ModifiersChanged>>().unwrap();
-                    events.send(ModifiersChanged(ModifiersState::from(state)));
-                }
-                // WindowEvent::CursorMoved {
-                //     device_id,
-                //     position,
-                //     modifiers,
-                // } => {},
-                WindowEvent::CursorEntered { .. } => {
-                    let world = app.world.cell();
-                    let winit_windows = world.get_resource::<WinitWindows>().unwrap();
-                    let window_id = winit_windows
-                        .winit_to_lib
-                        .get(&winit_window_id)
-                        .unwrap()
-                        .clone();
-                    let mut events = world.get_resource_mut::<Events<CursorEntered>>().unwrap();
-                    events.send(CursorEntered { window_id });
                 }
-                WindowEvent::CursorLeft { .. } => {
-                    let world = app.world.cell();
-                    let winit_windows = world.get_resource::<WinitWindows>().unwrap();
-                    let window_id = winit_windows
-                        .winit_to_lib
-                        .get(&winit_window_id)
-                        .unwrap()
-                        .clone();
-                    let mut events = world.get_resource_mut::<Events<CursorLeft>>().unwrap();
-                    events.send(CursorLeft { window_id });
-                }
-                WindowEvent::MouseWheel { delta, .. } => {
-                    let world = app.world.cell();
-                    let mut events = world.get_resource_mut::<Events<MouseWheel>>().unwrap();
-                    events.send(MouseWheel::from(delta));
-                }
-                WindowEvent::MouseInput { state, button, .. } => {
-                    let world = app.world.cell();
-                    let mut events = world.get_resource_mut::<Events<MouseButtonInput>>().unwrap();
-                    events.send(MouseButtonInput::from_with(button, state));
-                }
-                // WindowEvent::TouchpadPressure {
-                //     device_id,
-                //     pressure,
-                //     stage,
-                // } => {},
-                // WindowEvent::AxisMotion {
-                //     device_id,
-                //     axis,
-                //     value,
-                // } => {},
-                // WindowEvent::Touch(_) => {},
-                // WindowEvent::ScaleFactorChanged {
-                //     scale_factor,
-                //     new_inner_size,
-                // } => {},
-                // WindowEvent::ThemeChanged(_) => {},
-                _ => (),
-            },
+            }
             Event::DeviceEvent { device_id: _, event } => {
                 match event {
                     DeviceEvent::Added => {}
@@ -255,17 +416,39 @@ pub fn winit_event_loop_runner(mut app: bevy_app::App) {
             Event::Suspended => {}
             Event::Resumed => {}
             Event::MainEventsCleared => {
-                handle_create_window(&mut app.world, event_loop_wt);
-                // NOTE: this is why you cannot borrow app at the top
-                app.update();
+                let now = Instant::now();
+                let mode = update_mode(&app.world);
+                if decide_update(mode, pending, redraw_requested, last_update, now).update {
+                    pending = PendingEvents::default();
+                    redraw_requested = false;
+                    last_update = now;
+
+                    handle_create_window(&mut app.world, event_loop_wt);
+                    app.world
+                        .insert_non_send_resource(ActiveWindowTarget(event_loop_wt as *const _));
+                    // NOTE: this is why you cannot borrow app at the top
+                    app.update();
+                    app.world.remove_non_send_resource::<ActiveWindowTarget>();
+                }
             }
             Event::RedrawRequested(_) => {}
             Event::RedrawEventsCleared => {
                 if let Some(app_redraw_events) = app.world.get_resource::<Events<RequestRedraw>>() {
                     if redraw_event_reader.iter(app_redraw_events).last().is_some() {
-                        *control_flow = ControlFlow::Poll;
+                        redraw_requested = true;
                     }
                 }
+                // NOTE: `Time` reads the clock in the update, its delta spans the wait
+                if *control_flow != ControlFlow::Exit {
+                    *control_flow = decide_update(
+                        update_mode(&app.world),
+                        pending,
+                        redraw_requested,
+                        last_update,
+                        Instant::now(),
+                    )
+                    .control_flow;
+                }
                 if let Some(app_exit_events) = app.world.get_resource::<Events<AppExit>>() {
                     if app_exit_event_reader.iter(app_exit_events).last().is_some() {
                         *control_flow = ControlFlow::Exit;
@@ -286,6 +469,224 @@ pub fn winit_event_loop_runner(mut app: bevy_app::App) {
     });
 }
 
+/// Sends the events of `event`, returns whether the window asked to be closed.
+///
+/// NOTE: winit still delivers events for windows that were just destroyed, events of
+/// unknown windows are ignored
+fn handle_window_event(
+    world: &mut World,
+    winit_window_id: winit::window::WindowId,
+    event: WindowEvent<'_>,
+) -> bool {
+    let window_id = match world
+        .get_resource::<WinitWindows>()
+        .and_then(|winit_windows| winit_windows.lib_id(winit_window_id))
+    {
+        Some(window_id) => window_id,
+        None => {
+            log::debug!(
+                "Ignored {:?} of unknown window {:?}",
+                event,
+                winit_window_id
+            );
+            return false;
+        }
+    };
+    match event {
+        WindowEvent::Resized(size) => {
+            let world = world.cell();
+            let mut windows = world.get_resource_mut::<Windows>().unwrap();
+            if let Some(window) = windows.map.get_mut(&window_id) {
+                window.update_resolution((size.width, size.height));
+            }
+            let mut events = world.get_resource_mut::<Events<WindowResized>>().unwrap();
+            events.send(WindowResized {
+                window_id,
+                width: size.width,
+                height: size.height,
+            });
+        }
+        // WindowEvent::Moved(_) => {},
+        WindowEvent::CloseRequested => return true,
+        // WindowEvent::Destroyed => {},
+        WindowEvent::DroppedFile(path) => {
+            let world = world.cell();
+            let mut events = world.get_resource_mut::<Events<FileDragAndDrop>>().unwrap();
+            events.send(FileDragAndDrop::Dropped { window_id, path });
+        }
+        WindowEvent::HoveredFile(path) => {
+            let world = world.cell();
+            let mut events = world.get_resource_mut::<Events<FileDragAndDrop>>().unwrap();
+            events.send(FileDragAndDrop::Hovered { window_id, path });
+        }
+        WindowEvent::HoveredFileCancelled => {
+            let world = world.cell();
+            let mut events = world.get_resource_mut::<Events<FileDragAndDrop>>().unwrap();
+            events.send(FileDragAndDrop::HoveredCancelled { window_id });
+        }
+        // NOTE: winit 0.26 delivers committed IME text as characters, they are told apart from
+        // typing by the key pressed before them, keys going to the IME have no key code
+        WindowEvent::ReceivedCharacter(ch) if !ch.is_control() => {
+            let world = world.cell();
+            let mut windows = world.get_resource_mut::<Windows>().unwrap();
+            let window = match windows.map.get_mut(&window_id) {
+                Some(window) => window,
+                None => return false,
+            };
+            if !window.ime_allowed || std::mem::take(&mut window.key_typed) {
+                window.ime_committing = false;
+                let mut events = world
+                    .get_resource_mut::<Events<ReceivedCharacter>>()
+                    .unwrap();
+                events.send(ReceivedCharacter {
+                    window_id,
+                    char: ch,
+                });
+            } else {
+                let mut events = world.get_resource_mut::<Events<Ime>>().unwrap();
+                if !std::mem::replace(&mut window.ime_committing, true) {
+                    events.send(Ime::Preedit {
+                        window_id,
+                        value: String::new(),
+                        cursor: None,
+                    });
+                }
+                events.send(Ime::Commit {
+                    window_id,
+                    value: ch.to_string(),
+                });
+            }
+        }
+        WindowEvent::Focused(focused) => {
+            let world = world.cell();
+            let mut windows = world.get_resource_mut::<Windows>().unwrap();
+            if let Some(window) = windows.map.get_mut(&window_id) {
+                window.update_focused(focused);
+            }
+            let mut events = world.get_resource_mut::<Events<FocusChanged>>().unwrap();
+            events.send(FocusChanged { window_id, focused });
+        }
+        WindowEvent::KeyboardInput { input, .. } => {
+            let world = world.cell();
+            if input.state == winit::event::ElementState::Pressed {
+                let mut windows = world.get_resource_mut::<Windows>().unwrap();
+                if let Some(window) = windows.map.get_mut(&window_id) {
+                    window.key_typed = input.virtual_keycode.is_some();
+                    window.ime_committing &= !window.key_typed;
+                }
+            }
+            let mut events = world.get_resource_mut::<Events<KeyboardInput>>().unwrap();
+            events.send(KeyboardInput::from(input));
+        }
+        WindowEvent::ModifiersChanged(state) => {
+            let world = world.cell();
+            let mut events = world
+                .get_resource_mut::<Events<ModifiersChanged>>()
+                .unwrap();
+            events.send(ModifiersChanged(ModifiersState::from(state)));
+        }
+        WindowEvent::CursorMoved { position, .. } => {
+            let world = world.cell();
+            let mut windows = world.get_resource_mut::<Windows>().unwrap();
+            if let Some(window) = windows.map.get_mut(&window_id) {
+                let position = PhysicalVec2::new(position.x as f32, position.y as f32)
+                    .to_logical(window.resolution().1, window.scale_factor());
+                window.update_cursor_position(Some(position));
+                let mut events = world.get_resource_mut::<Events<CursorMoved>>().unwrap();
+                events.send(CursorMoved {
+                    window_id,
+                    position,
+                });
+            }
+        }
+        WindowEvent::CursorEntered { .. } => {
+            let world = world.cell();
+            let mut events = world.get_resource_mut::<Events<CursorEntered>>().unwrap();
+            events.send(CursorEntered { window_id });
+        }
+        WindowEvent::CursorLeft { .. } => {
+            let world = world.cell();
+            let mut windows = world.get_resource_mut::<Windows>().unwrap();
+            if let Some(window) = windows.map.get_mut(&window_id) {
+                window.update_cursor_position(None);
+            }
+            let mut events = world.get_resource_mut::<Events<CursorLeft>>().unwrap();
+            events.send(CursorLeft { window_id });
+        }
+        WindowEvent::MouseWheel { delta, .. } => {
+            let world = world.cell();
+            let scale_factor = world
+                .get_resource::<Windows>()
+                .unwrap()
+                .map
+                .get(&window_id)
+                .map_or(1.0, |window| window.scale_factor());
+            let mut events = world.get_resource_mut::<Events<MouseWheel>>().unwrap();
+            events.send(MouseWheel::from_winit(delta, scale_factor));
+        }
+        WindowEvent::MouseInput { state, button, .. } => {
+            let world = world.cell();
+            let mut events = world
+                .get_resource_mut::<Events<MouseButtonInput>>()
+                .unwrap();
+            events.send(MouseButtonInput::from_with(button, state));
+        }
+        // WindowEvent::TouchpadPressure {
+        //     device_id,
+        //     pressure,
+        //     stage,
+        // } => {},
+        // WindowEvent::AxisMotion {
+        //     device_id,
+        //     axis,
+        //     value,
+        // } => {},
+        WindowEvent::Touch(touch) => {
+            let world = world.cell();
+            let windows = world.get_resource::<Windows>().unwrap();
+            if let Some(window) = windows.map.get(&window_id) {
+                let mut events = world.get_resource_mut::<Events<TouchInput>>().unwrap();
+                events.send(TouchInput::from_winit(
+                    window_id,
+                    touch,
+                    window.resolution().1,
+                    window.scale_factor(),
+                ));
+            }
+        }
+        WindowEvent::ScaleFactorChanged {
+            scale_factor,
+            new_inner_size,
+        } => {
+            let world = world.cell();
+            let mut windows = world.get_resource_mut::<Windows>().unwrap();
+            if let Some(window) = windows.map.get_mut(&window_id) {
+                window.update_scale_factor(scale_factor);
+                window.update_resolution((new_inner_size.width, new_inner_size.height));
+            }
+            world
+                .get_resource_mut::<Events<WindowScaleFactorChanged>>()
+                .unwrap()
+                .send(WindowScaleFactorChanged {
+                    window_id,
+                    scale_factor,
+                });
+            // NOTE: not every platform follows up with WindowEvent::Resized
+            world
+                .get_resource_mut::<Events<WindowResized>>()
+                .unwrap()
+                .send(WindowResized {
+                    window_id,
+                    width: new_inner_size.width,
+                    height: new_inner_size.height,
+                });
+        }
+        // WindowEvent::ThemeChanged(_) => {},
+        _ => (),
+    }
+    false
+}
+
 pub fn handle_create_window(
     world: &mut World,
     event_loop: &EventLoopWindowTarget<()>,
@@ -305,3 +706,272 @@ pub fn handle_create_window(
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use bevy_ecs::{
+        prelude::Events,
+        schedule::{Stage, SystemStage},
+        world::World,
+    };
+    use winit::{
+        dpi::PhysicalSize,
+        event::{DeviceId, ElementState, VirtualKeyCode, WindowEvent},
+        event_loop::ControlFlow,
+    };
+
+    use crate::{
+        input::{
+            keyboard::{KeyCode, KeyboardInput},
+            Input,
+        },
+        window::{
+            commands::{WindowCommands, WindowMode},
+            events::{
+                Ime, PresentModeChanged, ReceivedCharacter, WindowModeChanged, WindowResized,
+            },
+            toggle_fullscreen_system, Window, WindowDescriptor, WindowId, Windows, WinitWindows,
+        },
+    };
+
+    use super::{
+        decide_update, execute_window_commands, handle_window_event, PendingEvents, UpdateDecision,
+        UpdateMode,
+    };
+
+    fn set_title() -> WindowCommands {
+        WindowCommands::SetTitle {
+            title: "title".to_string(),
+        }
+    }
+
+    #[test]
+    fn commands_wait_for_the_winit_window() {
+        let mut world = World::new();
+        world.init_resource::<WinitWindows>();
+        world.init_resource::<Events<PresentModeChanged>>();
+        world.init_resource::<Events<WindowModeChanged>>();
+        let mut windows = Windows::default();
+        let mut window = Window::new(WindowId::primary(), WindowDescriptor::default());
+        window.execute(set_title());
+        windows.add(window);
+        world.insert_resource(windows);
+
+        // a frame without the winit window neither panics nor drops the command
+        execute_window_commands(&mut world);
+        execute_window_commands(&mut world);
+        let windows = world.resource::<Windows>();
+        assert_eq!(windows.map[&WindowId::primary()].command_queue.len(), 1);
+    }
+
+    #[test]
+    fn events_of_unknown_windows_are_ignored() {
+        let mut world = World::new();
+        world.init_resource::<WinitWindows>();
+        world.init_resource::<Windows>();
+        world.init_resource::<Events<WindowResized>>();
+        // SAFETY: only looked up, never passed to winit
+        let destroyed = unsafe { winit::window::WindowId::dummy() };
+
+        let resized = WindowEvent::Resized(PhysicalSize::new(800, 600));
+        assert!(!handle_window_event(&mut world, destroyed, resized));
+        assert!(!handle_window_event(
+            &mut world,
+            destroyed,
+            WindowEvent::CloseRequested
+        ));
+        let events = world.resource::<Events<WindowResized>>();
+        assert_eq!(events.get_reader().iter(events).count(), 0);
+    }
+
+    #[test]
+    fn commands_before_creation_are_kept() {
+        let mut windows = Windows::default();
+        let id = windows.reserve_id();
+        windows.execute(id, set_title());
+        assert!(windows.map.is_empty());
+
+        windows.add(Window::new(id, WindowDescriptor::default()));
+        assert_eq!(windows.map[&id].command_queue.len(), 1);
+        assert!(windows.pending.is_empty());
+    }
+
+    #[test]
+    fn typing_and_ime_commits_are_kept_apart() {
+        let mut world = World::new();
+        world.init_resource::<Events<KeyboardInput>>();
+        world.init_resource::<Events<ReceivedCharacter>>();
+        world.init_resource::<Events<Ime>>();
+        // SAFETY: only looked up, never passed to winit
+        let winit_id = unsafe { winit::window::WindowId::dummy() };
+        let mut winit_windows = WinitWindows::default();
+        winit_windows
+            .winit_to_lib
+            .insert(winit_id, WindowId::primary());
+        world.insert_resource(winit_windows);
+        let mut windows = Windows::default();
+        let mut window = Window::new(WindowId::primary(), WindowDescriptor::default());
+        window.ime_allowed = true;
+        windows.add(window);
+        world.insert_resource(windows);
+
+        #[allow(deprecated)]
+        let press = |virtual_keycode| WindowEvent::KeyboardInput {
+            // SAFETY: never passed to winit
+            device_id: unsafe { DeviceId::dummy() },
+            input: winit::event::KeyboardInput {
+                scancode: 0,
+                state: ElementState::Pressed,
+                virtual_keycode,
+                modifiers: Default::default(),
+            },
+            is_synthetic: false,
+        };
+        let mut send = |event| handle_window_event(&mut world, winit_id, event);
+        send(press(Some(VirtualKeyCode::A)));
+        send(WindowEvent::ReceivedCharacter('a'));
+        // the IME takes the keys, then commits two characters
+        send(press(None));
+        send(press(None));
+        send(WindowEvent::ReceivedCharacter('日'));
+        send(WindowEvent::ReceivedCharacter('本'));
+        send(press(Some(VirtualKeyCode::B)));
+        send(WindowEvent::ReceivedCharacter('b'));
+
+        let characters = world.resource::<Events<ReceivedCharacter>>();
+        let typed: String = characters
+            .get_reader()
+            .iter(characters)
+            .map(|character| character.char)
+            .collect();
+        assert_eq!(typed, "ab");
+        let ime = world.resource::<Events<Ime>>();
+        let ime: Vec<_> = ime
+            .get_reader()
+            .iter(ime)
+            .map(|event| match event {
+                Ime::Preedit { value, cursor, .. } => {
+                    assert_eq!(*cursor, None);
+                    format!("preedit {:?}", value)
+                }
+                Ime::Commit { value, .. } => format!("commit {:?}", value),
+            })
+            .collect();
+        assert_eq!(ime, ["preedit \"\"", "commit \"日\"", "commit \"本\""]);
+    }
+
+    #[test]
+    fn alt_enter_toggles_the_tracked_mode() {
+        let mut world = World::new();
+        let mut windows = Windows::default();
+        let mut window = Window::new(WindowId::primary(), WindowDescriptor::default());
+        window.update_resolution((800, 600));
+        windows.add(window);
+        world.insert_resource(windows);
+        world.init_resource::<Input<KeyCode>>();
+        let mut stage = SystemStage::single_threaded().with_system(toggle_fullscreen_system);
+
+        let mode = |world: &World| world.resource::<Windows>().map[&WindowId::primary()].mode();
+        let queued = |world: &World| {
+            world.resource::<Windows>().map[&WindowId::primary()]
+                .command_queue
+                .iter()
+                .filter_map(|command| match command {
+                    WindowCommands::SetWindowMode { mode, resolution } => {
+                        Some((*mode, *resolution))
+                    }
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Enter alone does nothing
+        world
+            .resource_mut::<Input<KeyCode>>()
+            .press(KeyCode::Return);
+        stage.run(&mut world);
+        assert_eq!(mode(&world), WindowMode::Windowed);
+
+        let mut keys = world.resource_mut::<Input<KeyCode>>();
+        keys.reset_all();
+        keys.press(KeyCode::RAlt);
+        keys.press(KeyCode::Return);
+        stage.run(&mut world);
+        assert_eq!(mode(&world), WindowMode::BorderlessFullscreen);
+
+        // held keys do not toggle again
+        world.resource_mut::<Input<KeyCode>>().clear();
+        stage.run(&mut world);
+        world
+            .resource_mut::<Windows>()
+            .map
+            .get_mut(&WindowId::primary())
+            .unwrap()
+            .set_mode(WindowMode::SizedFullscreen);
+        assert_eq!(mode(&world), WindowMode::SizedFullscreen);
+        assert_eq!(
+            queued(&world),
+            [
+                (WindowMode::BorderlessFullscreen, (800, 600)),
+                (WindowMode::SizedFullscreen, (800, 600)),
+            ]
+        );
+    }
+
+    #[test]
+    fn update_decisions_follow_the_mode() {
+        let last_update = Instant::now();
+        let soon = last_update + Duration::from_millis(10);
+        let none = PendingEvents::default();
+        let window = PendingEvents {
+            window: true,
+            device: false,
+        };
+        let device = PendingEvents {
+            window: false,
+            device: true,
+        };
+        let decide =
+            |mode, pending, redraw, now| decide_update(mode, pending, redraw, last_update, now);
+        let update = UpdateDecision {
+            update: true,
+            control_flow: ControlFlow::Poll,
+        };
+
+        assert_eq!(decide(UpdateMode::Continuous, none, false, soon), update);
+
+        let max_wait = Duration::from_millis(100);
+        let reactive = UpdateMode::Reactive { max_wait };
+        assert_eq!(
+            decide(reactive, none, false, soon),
+            UpdateDecision {
+                update: false,
+                control_flow: ControlFlow::WaitUntil(last_update + max_wait),
+            }
+        );
+        assert_eq!(decide(reactive, window, false, soon), update);
+        assert_eq!(decide(reactive, device, false, soon), update);
+        assert_eq!(decide(reactive, none, true, soon), update);
+        assert_eq!(
+            decide(reactive, none, false, last_update + max_wait),
+            update
+        );
+
+        let low_power = UpdateMode::ReactiveLowPower;
+        let wait = UpdateDecision {
+            update: false,
+            control_flow: ControlFlow::Wait,
+        };
+        assert_eq!(decide(low_power, none, false, soon), wait);
+        // no timeout and no device events
+        assert_eq!(
+            decide(low_power, none, false, last_update + max_wait * 100),
+            wait
+        );
+        assert_eq!(decide(low_power, device, false, soon), wait);
+        assert_eq!(decide(low_power, window, false, soon), update);
+        assert_eq!(decide(low_power, none, true, soon), update);
+    }
+}
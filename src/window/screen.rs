@@ -0,0 +1,210 @@
+use bevy_ecs::system::{Res, ResMut};
+use cgmath::{Matrix4, Vector2};
+
+use crate::Headless;
+
+use super::{WindowId, Windows};
+
+/// Size of the primary window, or of the offscreen target of a [`Headless`] app, for
+/// placing UI and text. Updated in `CoreStage::PreUpdate`.
+///
+/// Logical pixels are physical pixels divided by the scale factor, sizes given in them
+/// look the same at every DPI. Screen positions start at the bottom left corner with
+/// y up, like the UI; NDC spans `[-1, 1]` on both axes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScreenSpace {
+    physical_size: (u32, u32),
+    scale_factor: f64,
+}
+
+impl Default for ScreenSpace {
+    fn default() -> Self {
+        Self::new((1, 1), 1.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Anchor {
+    TopLeft,
+    TopCenter,
+    TopRight,
+    CenterLeft,
+    Center,
+    CenterRight,
+    BottomLeft,
+    BottomCenter,
+    BottomRight,
+}
+
+impl Anchor {
+    /// Of the screen, 0 at the left or bottom and 1 at the right or top
+    pub fn fraction(&self) -> Vector2<f32> {
+        let (x, y) = match self {
+            Anchor::TopLeft => (0.0, 1.0),
+            Anchor::TopCenter => (0.5, 1.0),
+            Anchor::TopRight => (1.0, 1.0),
+            Anchor::CenterLeft => (0.0, 0.5),
+            Anchor::Center => (0.5, 0.5),
+            Anchor::CenterRight => (1.0, 0.5),
+            Anchor::BottomLeft => (0.0, 0.0),
+            Anchor::BottomCenter => (0.5, 0.0),
+            Anchor::BottomRight => (1.0, 0.0),
+        };
+        Vector2::new(x, y)
+    }
+}
+
+impl ScreenSpace {
+    /// Zero sizes, e.g. while minimized, are kept at one pixel
+    pub fn new(physical_size: (u32, u32), scale_factor: f64) -> Self {
+        Self {
+            physical_size: (physical_size.0.max(1), physical_size.1.max(1)),
+            scale_factor: if scale_factor > 0.0 {
+                scale_factor
+            } else {
+                1.0
+            },
+        }
+    }
+
+    pub fn physical_size(&self) -> (u32, u32) {
+        self.physical_size
+    }
+
+    /// Physical pixels per logical pixel
+    pub fn scale_factor(&self) -> f64 {
+        self.scale_factor
+    }
+
+    pub fn logical_size(&self) -> Vector2<f32> {
+        let (width, height) = self.physical_size;
+        self.physical_to_logical(Vector2::new(width as f32, height as f32))
+    }
+
+    pub fn physical_to_logical(&self, physical: Vector2<f32>) -> Vector2<f32> {
+        physical / self.scale_factor as f32
+    }
+
+    pub fn logical_to_physical(&self, logical: Vector2<f32>) -> Vector2<f32> {
+        logical * self.scale_factor as f32
+    }
+
+    pub fn logical_to_ndc(&self, logical: Vector2<f32>) -> Vector2<f32> {
+        let size = self.logical_size();
+        Vector2::new(
+            logical.x / size.x * 2.0 - 1.0,
+            logical.y / size.y * 2.0 - 1.0,
+        )
+    }
+
+    pub fn ndc_to_logical(&self, ndc: Vector2<f32>) -> Vector2<f32> {
+        let size = self.logical_size();
+        Vector2::new((ndc.x + 1.0) / 2.0 * size.x, (ndc.y + 1.0) / 2.0 * size.y)
+    }
+
+    /// `offset` logical pixels from `anchor`, x to the right and y up
+    pub fn anchor(&self, anchor: Anchor, offset: Vector2<f32>) -> Vector2<f32> {
+        let fraction = anchor.fraction();
+        let size = self.logical_size();
+        Vector2::new(fraction.x * size.x, fraction.y * size.y) + offset
+    }
+
+    /// Logical pixels to clip space, z is kept for the depth to be set by the caller.
+    /// UI built in logical pixels keeps its physical size across scale factors
+    pub fn ui_projection(&self) -> Matrix4<f32> {
+        let size = self.logical_size();
+        // NOTE: maps z to -z, without the OpenGL correction
+        cgmath::ortho(0.0, size.x, 0.0, size.y, -1.0, 1.0)
+    }
+}
+
+pub fn update_screen_space_system(
+    windows: Res<Windows>,
+    headless: Option<Res<Headless>>,
+    mut screen: ResMut<ScreenSpace>,
+) {
+    let updated = match (windows.map.get(&WindowId::primary()), headless) {
+        (Some(window), _) => ScreenSpace::new(window.resolution(), window.scale_factor()),
+        (None, Some(headless)) => ScreenSpace::new((headless.width, headless.height), 1.0),
+        (None, None) => return,
+    };
+    // NOTE: compared first, so change detection only fires on actual changes
+    if *screen != updated {
+        *screen = updated;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector2, Vector4};
+
+    use super::{Anchor, ScreenSpace};
+
+    fn assert_close(a: Vector2<f32>, b: Vector2<f32>) {
+        assert!(
+            (a - b).x.abs() < 1e-4 && (a - b).y.abs() < 1e-4,
+            "{a:?} != {b:?}"
+        );
+    }
+
+    #[test]
+    fn conversions_at_common_scale_factors() {
+        for scale_factor in [1.0, 1.5, 2.0] {
+            // the same logical screen at every scale factor
+            let physical = (1280.0 * scale_factor) as u32;
+            let screen = ScreenSpace::new((physical, (720.0 * scale_factor) as u32), scale_factor);
+            assert_close(screen.logical_size(), Vector2::new(1280.0, 720.0));
+
+            let logical = Vector2::new(320.0, 540.0);
+            let physical = screen.logical_to_physical(logical);
+            assert_close(physical, logical * scale_factor as f32);
+            assert_close(screen.physical_to_logical(physical), logical);
+
+            let ndc = screen.logical_to_ndc(logical);
+            assert_close(ndc, Vector2::new(-0.5, 0.5));
+            assert_close(screen.ndc_to_logical(ndc), logical);
+            assert_close(
+                screen.logical_to_ndc(Vector2::new(0.0, 0.0)),
+                Vector2::new(-1.0, -1.0),
+            );
+
+            assert_close(
+                screen.anchor(Anchor::TopLeft, Vector2::new(16.0, -16.0)),
+                Vector2::new(16.0, 704.0),
+            );
+            assert_close(
+                screen.anchor(Anchor::Center, Vector2::new(0.0, 0.0)),
+                Vector2::new(640.0, 360.0),
+            );
+            assert_close(
+                screen.anchor(Anchor::BottomRight, Vector2::new(-8.0, 8.0)),
+                Vector2::new(1272.0, 8.0),
+            );
+        }
+    }
+
+    #[test]
+    fn logical_sizes_follow_the_scale_factor() {
+        // physical pixels of a glyph 16 logical pixels tall at the top of the screen
+        let glyph_height = |scale_factor: f64| {
+            let screen = ScreenSpace::new(
+                ((800.0 * scale_factor) as u32, (600.0 * scale_factor) as u32),
+                scale_factor,
+            );
+            let projection = screen.ui_projection();
+            let clip = |y: f32| (projection * Vector4::new(0.0, y, 0.0, 1.0)).y;
+            let top = screen.logical_size().y;
+            (clip(top) - clip(top - 16.0)) / 2.0 * screen.physical_size().1 as f32
+        };
+        assert!((glyph_height(1.0) - 16.0).abs() < 1e-3);
+        assert!((glyph_height(1.5) - 24.0).abs() < 1e-3);
+        assert!((glyph_height(2.0) - 32.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn degenerate_sizes_are_clamped() {
+        let screen = ScreenSpace::new((0, 0), 0.0);
+        assert_eq!(screen.physical_size(), (1, 1));
+        assert_eq!(screen.scale_factor(), 1.0);
+    }
+}
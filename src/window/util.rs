@@ -1,3 +1,49 @@
+use cgmath::Vector2;
+
+/// Logical pixels from the bottom left corner of the window, y up. Cursor positions and
+/// pixel scroll deltas from the runner are in it, the window commands take it
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct LogicalVec2(pub Vector2<f32>);
+
+/// Physical pixels from the top left corner of the window, y down, as winit has them
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PhysicalVec2(pub Vector2<f32>);
+
+impl LogicalVec2 {
+    pub fn new(x: f32, y: f32) -> Self {
+        Self(Vector2::new(x, y))
+    }
+
+    /// A position in a window `window_height` physical pixels tall
+    pub fn to_physical(self, window_height: u32, scale_factor: f64) -> PhysicalVec2 {
+        let scale_factor = scale_factor as f32;
+        PhysicalVec2::new(
+            self.0.x * scale_factor,
+            window_height as f32 - self.0.y * scale_factor,
+        )
+    }
+}
+
+impl PhysicalVec2 {
+    pub fn new(x: f32, y: f32) -> Self {
+        Self(Vector2::new(x, y))
+    }
+
+    /// A position in a window `window_height` physical pixels tall
+    pub fn to_logical(self, window_height: u32, scale_factor: f64) -> LogicalVec2 {
+        let scale_factor = scale_factor as f32;
+        LogicalVec2::new(
+            self.0.x / scale_factor,
+            (window_height as f32 - self.0.y) / scale_factor,
+        )
+    }
+
+    /// A scroll delta, only scaled. Both winit and [`LogicalVec2`] deltas are positive
+    /// to the right and for scrolling up
+    pub fn delta_to_logical(self, scale_factor: f64) -> LogicalVec2 {
+        LogicalVec2(self.0 / scale_factor as f32)
+    }
+}
 
 // NOTE: Copied from bevy_window-0.7.0
 
@@ -45,4 +91,41 @@ pub fn get_best_videomode(monitor: &winit::monitor::MonitorHandle) -> winit::mon
     });
 
     modes.first().unwrap().clone()
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LogicalVec2, PhysicalVec2};
+
+    #[test]
+    fn positions_flip_y_and_scale() {
+        // 800x600 logical at scale factor 2
+        let height = 1200;
+        let top_left = PhysicalVec2::new(0.0, 0.0).to_logical(height, 2.0);
+        assert_eq!(top_left, LogicalVec2::new(0.0, 600.0));
+        let physical = PhysicalVec2::new(200.0, 1100.0);
+        assert_eq!(
+            physical.to_logical(height, 2.0),
+            LogicalVec2::new(100.0, 50.0)
+        );
+        assert_eq!(
+            physical.to_logical(600, 1.0),
+            LogicalVec2::new(200.0, -500.0)
+        );
+
+        for scale_factor in [1.0, 1.5, 2.0] {
+            let logical = LogicalVec2::new(123.0, 45.5);
+            let round_trip = logical
+                .to_physical(height, scale_factor)
+                .to_logical(height, scale_factor);
+            assert!((round_trip.0 - logical.0).x.abs() < 1e-4);
+            assert!((round_trip.0 - logical.0).y.abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn deltas_only_scale() {
+        let delta = PhysicalVec2::new(4.0, -10.0).delta_to_logical(2.0);
+        assert_eq!(delta, LogicalVec2::new(2.0, -5.0));
+    }
+}
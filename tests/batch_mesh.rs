@@ -0,0 +1,62 @@
+use std::sync::Arc;
+
+use bevy_ecs::world::World;
+use try_wgpu::{
+    create_headless_wgpu_resources,
+    render::{
+        mesh::{BatchMesh, GpuMesh, GpuMeshAssembly, Mesh},
+        resource::buffer::{Indices, Vertex},
+    },
+};
+
+fn quad() -> Mesh<Vertex> {
+    let vertex = |x: f32, y: f32| Vertex {
+        position: [x, y, 0.0],
+        tex_coords: [x, y],
+    };
+    Mesh::with_all(
+        wgpu::PrimitiveTopology::TriangleList,
+        vec![
+            vertex(0.0, 0.0),
+            vertex(1.0, 0.0),
+            vertex(1.0, 1.0),
+            vertex(0.0, 1.0),
+        ],
+        Some(Indices::U16(vec![0, 1, 2, 2, 3, 0])),
+    )
+}
+
+#[test]
+fn large_batch_uploads_u32_indices() {
+    let mut world = World::new();
+    if !create_headless_wgpu_resources(&mut world, 1, 1) {
+        eprintln!("No adapter available, skipping");
+        return;
+    }
+    let device = world.resource::<Arc<wgpu::Device>>();
+
+    let mut batch = BatchMesh::new(wgpu::PrimitiveTopology::TriangleList, true);
+    batch.add_all((0..20_000).map(|_| quad())).unwrap();
+    let mesh: &Mesh<Vertex> = (&batch).into();
+    let gpu_mesh = GpuMesh::from_mesh(&batch, device, None);
+
+    match gpu_mesh.assembly {
+        GpuMeshAssembly::Indexed {
+            index_count,
+            index_format,
+            ..
+        } => {
+            assert_eq!(index_format, wgpu::IndexFormat::Uint32);
+            assert_eq!(index_count, 20_000 * 6);
+        }
+        GpuMeshAssembly::NonIndexed { .. } => panic!("Batch lost its indices"),
+    }
+    assert_eq!(
+        mesh.get_indices().unwrap().iter().max(),
+        Some(mesh.vertex_count() as u32 - 1)
+    );
+    assert_eq!(
+        gpu_mesh.buffer_size,
+        (mesh.vertex_count() * std::mem::size_of::<Vertex>() + 20_000 * 6 * 4) as u64
+    );
+}
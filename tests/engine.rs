@@ -0,0 +1,154 @@
+use std::{collections::HashSet, sync::Arc};
+
+use bevy_app::CoreStage;
+use bevy_ecs::schedule::{StageLabel, SystemContainer, SystemLabel, SystemStage};
+use try_wgpu::{
+    camera::Camera,
+    color::Color,
+    particles::FlatParticlePlugin,
+    render::{offscreen::OffscreenTarget, resource::pipeline::RenderPipeline, ClearColor},
+    text::world::FlatText3dPlugin,
+    tilemap::FlatTilemapPlugin,
+    ui::FlatUiPlugin,
+    util::Store,
+    window::{WindowDescriptor, WinitWindows},
+    FlatEngine, FlatSystemLabels, Headless, RenderStage,
+};
+
+#[test]
+fn headless_app_has_the_render_resources() {
+    let clear_color = Color::srgb(0.1, 0.2, 0.3);
+    let mut app = FlatEngine::new()
+        .headless(32, 16)
+        .with_clear_color(clear_color)
+        .build();
+
+    let world = &app.world;
+    assert_eq!(world.resource::<ClearColor>().0, clear_color);
+    assert_eq!(
+        *world.resource::<Headless>(),
+        Headless {
+            width: 32,
+            height: 16
+        }
+    );
+    assert!(world.contains_resource::<WindowDescriptor>());
+    assert!(world.contains_resource::<Camera>());
+    assert!(world.contains_resource::<Store<RenderPipeline>>());
+    // no event loop without a window
+    assert!(!world.contains_resource::<WinitWindows>());
+
+    if !world.contains_resource::<Arc<wgpu::Device>>() {
+        eprintln!("No adapter available, skipping");
+        return;
+    }
+    let target = world.resource::<OffscreenTarget>();
+    assert_eq!((target.width, target.height), (32, 16));
+    assert!(!world.contains_resource::<wgpu::Surface>());
+    // a frame renders without a window
+    app.update();
+}
+
+#[test]
+fn clear_color_defaults_to_black() {
+    let app = FlatEngine::new().headless(1, 1).build();
+    assert_eq!(app.world.resource::<ClearColor>().0, Color::BLACK);
+}
+
+/// Indices of the systems of `stage` labelled `label`, and of every system they run after
+fn labelled_and_dependencies(
+    stage: &SystemStage,
+    label: FlatSystemLabels,
+) -> (Vec<usize>, Vec<HashSet<usize>>) {
+    let systems = stage.parallel_systems();
+    let label = label.as_label();
+    let labelled = (0..systems.len())
+        .filter(|&i| systems[i].labels().contains(&label))
+        .collect();
+    // NOTE: the systems are sorted, dependencies come first
+    let mut before: Vec<HashSet<usize>> = Vec::with_capacity(systems.len());
+    for system in systems {
+        let mut all = HashSet::new();
+        for &dependency in system.dependencies() {
+            all.insert(dependency);
+            all.extend(before[dependency].iter().copied());
+        }
+        before.push(all);
+    }
+    (labelled, before)
+}
+
+fn stage(app: &bevy_app::App, label: impl StageLabel) -> &SystemStage {
+    app.schedule.get_stage::<SystemStage>(label).unwrap()
+}
+
+fn assert_ordered(stage: &SystemStage, first: FlatSystemLabels, then: FlatSystemLabels) {
+    let (firsts, before) = labelled_and_dependencies(stage, first);
+    let (thens, _) = labelled_and_dependencies(stage, then);
+    assert!(!firsts.is_empty(), "no {:?} system", first);
+    assert!(!thens.is_empty(), "no {:?} system", then);
+    for &then_system in &thens {
+        for &first_system in &firsts {
+            assert!(
+                before[then_system].contains(&first_system),
+                "{} ({:?}) is not ordered after {} ({:?})",
+                stage.parallel_systems()[then_system].name(),
+                then,
+                stage.parallel_systems()[first_system].name(),
+                first,
+            );
+        }
+    }
+}
+
+#[test]
+fn engine_systems_are_ordered_by_their_labels() {
+    let mut app = FlatEngine::new().headless(1, 1).build();
+    // NOTE: the optional plugins add systems to the same labels
+    app.add_plugin(FlatUiPlugin)
+        .add_plugin(FlatParticlePlugin)
+        .add_plugin(FlatText3dPlugin)
+        .add_plugin(FlatTilemapPlugin);
+    // the stages sort their systems on the first run, the render systems return early
+    // without an adapter
+    app.update();
+
+    let post_update = stage(&app, CoreStage::PostUpdate);
+    assert_ordered(
+        post_update,
+        FlatSystemLabels::TransformPropagate,
+        FlatSystemLabels::VisibilityCompute,
+    );
+    assert_ordered(
+        post_update,
+        FlatSystemLabels::VisibilityCompute,
+        FlatSystemLabels::UniformSync,
+    );
+    assert_ordered(
+        post_update,
+        FlatSystemLabels::TransformPropagate,
+        FlatSystemLabels::UniformSync,
+    );
+
+    for (label, system) in [
+        (
+            CoreStage::PreUpdate.as_label(),
+            FlatSystemLabels::InputCollect,
+        ),
+        (
+            RenderStage::Prepare.as_label(),
+            FlatSystemLabels::RenderPrepare,
+        ),
+        (
+            RenderStage::MainPass.as_label(),
+            FlatSystemLabels::RenderMain,
+        ),
+        (
+            RenderStage::Present.as_label(),
+            FlatSystemLabels::RenderPresent,
+        ),
+    ] {
+        let (labelled, _) = labelled_and_dependencies(stage(&app, label), system);
+        assert!(!labelled.is_empty(), "no {:?} system", system);
+    }
+}
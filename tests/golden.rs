@@ -0,0 +1,665 @@
+use std::sync::Arc;
+
+use bevy_app::App;
+use bevy_ecs::system::{Commands, Res, ResMut};
+use cgmath::{Vector2, Vector3};
+use image::Rgba;
+use try_wgpu::{
+    atlas::Rect,
+    camera::{Camera, PerspectiveProjection, OPENGL_TO_WGPU_MATRIX},
+    color::Color,
+    render::{
+        depth::DepthBindGroup,
+        mesh::{
+            primitive::{create_aa_plane, create_unit_cube, PlaneAlign},
+            GpuMesh, Mesh,
+        },
+        offscreen::OffscreenTarget,
+        postprocess::PostProcessSettings,
+        render_target::{HiddenFromRenderTargets, RenderToTexture},
+        resource::{
+            bind::{BindSlots, BindingSet},
+            buffer::{Indices, MeshVertex, Vertex},
+            pipeline::RenderPipeline,
+            preprocess,
+            shader::Shader,
+            shader_lib::ShaderInclude,
+        },
+        CurrentFrame, DepthConvention, DepthFormat, FrameEncoders,
+    },
+    testing::{GoldenTest, Tolerance},
+    text::{GlyphRect, TextAtlas},
+    texture::{PixelFormat, RawImage, Texture},
+    ui::{AtlasRegion, FlatUiPlugin, NineSlice},
+    util::{Refer, Store},
+    RenderStage,
+};
+use wgpu::util::DeviceExt;
+
+// NOTE: adapters rasterize the pixels along edges differently, a few may flip
+const TOLERANCE: Tolerance = Tolerance::new(2, 16);
+
+fn golden(name: &str) -> String {
+    format!("{}/tests/golden/{}.png", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+/// The main pass draws to the target directly, tonemapping would hide small changes
+fn without_post_process(app: &mut App) {
+    app.world.resource_mut::<PostProcessSettings>().enabled = false;
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    depth_format: &DepthFormat,
+    module: wgpu::ShaderModule,
+    bind_group_layouts: &[&wgpu::BindGroupLayout],
+) -> RenderPipeline {
+    let shader = Shader::with_final(
+        module,
+        vec![Vertex::layout()],
+        vec![Some(wgpu::ColorTargetState {
+            format: OffscreenTarget::FORMAT,
+            blend: Some(wgpu::BlendState::REPLACE),
+            write_mask: wgpu::ColorWrites::ALL,
+        })],
+    )
+    .with_depth_stencil(depth_format.0, Default::default());
+    RenderPipeline::create_usual(
+        device,
+        bind_group_layouts,
+        &shader,
+        wgpu::PrimitiveTopology::TriangleList,
+        None,
+    )
+}
+
+/// Draws `mesh` with `res/uv.wgsl`, positions are in clip space
+fn spawn_uv_mesh(
+    commands: &mut Commands,
+    device: &wgpu::Device,
+    depth_format: &DepthFormat,
+    pipelines: &mut Store<RenderPipeline>,
+    mesh: Mesh<Vertex>,
+) {
+    let module = device.create_shader_module(wgpu::include_wgsl!("../res/uv.wgsl"));
+    let pipeline = pipelines.insert(create_pipeline(device, depth_format, module, &[]));
+    commands.spawn().insert_bundle((
+        Refer::<RenderPipeline>::new(pipeline),
+        BindSlots::new(),
+        GpuMesh::from_mesh(&mesh, device, None),
+    ));
+}
+
+fn spawn_unit_cube(
+    mut commands: Commands,
+    device: Res<Arc<wgpu::Device>>,
+    depth_format: Res<DepthFormat>,
+    mut pipelines: ResMut<Store<RenderPipeline>>,
+) {
+    spawn_uv_mesh(
+        &mut commands,
+        &device,
+        &depth_format,
+        &mut pipelines,
+        create_unit_cube(),
+    );
+}
+
+fn spawn_aa_plane(
+    mut commands: Commands,
+    device: Res<Arc<wgpu::Device>>,
+    depth_format: Res<DepthFormat>,
+    mut pipelines: ResMut<Store<RenderPipeline>>,
+) {
+    let plane = create_aa_plane(PlaneAlign::XY, 1.0, 1.5, 2, 3, Vector3::new(0.0, 0.0, 0.0));
+    spawn_uv_mesh(&mut commands, &device, &depth_format, &mut pipelines, plane);
+}
+
+/// 4x4 cells of 16 pixels, glyph `i` is a box wider with `i % 4` and taller with `i / 4`.
+/// Stands in for a font atlas, fonts differ between machines.
+fn atlas() -> TextAtlas {
+    const CELL: usize = 16;
+    let size = 4 * CELL;
+    let mut bytes = vec![0; size * size];
+    let mut rects = Vec::new();
+    for i in 0..16 {
+        let (x, y) = ((i % 4) * CELL + 2, (i / 4) * CELL + 2);
+        let (w, h) = (3 + (i % 4) * 3, 3 + (i / 4) * 3);
+        for row in y..y + h {
+            bytes[row * size + x..row * size + x + w].fill(255);
+        }
+        rects.push(GlyphRect::new(
+            (x as u32, y as u32),
+            ((x + w - 1) as u32, (y + h - 1) as u32),
+        ));
+    }
+
+    TextAtlas {
+        descriptors: Vec::new(),
+        rects,
+        w: size,
+        h: size,
+        stride: size,
+        bytes,
+    }
+}
+
+/// The atlas on a quad covering the frame, texels land on pixel centers at the default size
+fn spawn_atlas_quad(
+    mut commands: Commands,
+    device: Res<Arc<wgpu::Device>>,
+    queue: Res<wgpu::Queue>,
+    depth_format: Res<DepthFormat>,
+    mut pipelines: ResMut<Store<RenderPipeline>>,
+    mut bind_groups: ResMut<Store<wgpu::BindGroup>>,
+) {
+    let atlas = atlas();
+    let texture = Texture::from_raw_image(
+        &device,
+        &queue,
+        &RawImage::new(
+            &atlas.bytes,
+            (atlas.w as u32, atlas.h as u32),
+            PixelFormat::G8,
+        ),
+        Some("Golden Atlas"),
+    )
+    .unwrap();
+    let set = (&texture.view, &texture.sampler);
+    let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("Golden Atlas Bind Group Layout"),
+        entries: &set.layout_desc().entries,
+    });
+    let bind_group = bind_groups.insert(set.into_bind_group(&device));
+
+    let module = device.create_shader_module(wgpu::include_wgsl!("../res/atlas.wgsl"));
+    let pipeline = pipelines.insert(create_pipeline(&device, &depth_format, module, &[&layout]));
+
+    let vertex = |x: f32, y: f32, u: f32, v: f32| Vertex {
+        position: [x, y, 0.0],
+        tex_coords: [u, v],
+    };
+    let quad = Mesh::with_all(
+        wgpu::PrimitiveTopology::TriangleList,
+        vec![
+            vertex(-1.0, -1.0, 0.0, 1.0),
+            vertex(1.0, -1.0, 1.0, 1.0),
+            vertex(1.0, 1.0, 1.0, 0.0),
+            vertex(-1.0, 1.0, 0.0, 0.0),
+        ],
+        Some(Indices::U16(vec![0, 1, 2, 2, 3, 0])),
+    );
+    commands.spawn().insert_bundle((
+        Refer::<RenderPipeline>::new(pipeline),
+        BindSlots::new().with(0, bind_group),
+        GpuMesh::from_mesh(&quad, &device, None),
+    ));
+}
+
+/// The unit cube drawn into a render target of the frame size, which a quad covering the
+/// frame samples. Texels land on pixel centers, so it matches the cube drawn directly
+fn spawn_render_target_quad(
+    mut commands: Commands,
+    device: Res<Arc<wgpu::Device>>,
+    depth_format: Res<DepthFormat>,
+    mut pipelines: ResMut<Store<RenderPipeline>>,
+    mut bind_groups: ResMut<Store<wgpu::BindGroup>>,
+) {
+    spawn_uv_mesh(
+        &mut commands,
+        &device,
+        &depth_format,
+        &mut pipelines,
+        create_unit_cube(),
+    );
+
+    let default = GoldenTest::default();
+    let target = RenderToTexture::with_size(
+        &device,
+        (default.width, default.height),
+        OffscreenTarget::FORMAT,
+        Color::BLACK,
+    );
+    let set = (&target.target.view, &target.target.sampler);
+    let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("Golden Render Target Bind Group Layout"),
+        entries: &set.layout_desc().entries,
+    });
+    let bind_group = bind_groups.insert(set.into_bind_group(&device));
+    commands.spawn().insert(target);
+
+    let module = device.create_shader_module(wgpu::include_wgsl!("../res/sampled.wgsl"));
+    let pipeline = pipelines.insert(create_pipeline(&device, &depth_format, module, &[&layout]));
+
+    // in front of the cube
+    let vertex = |x: f32, y: f32, u: f32, v: f32| Vertex {
+        position: [x, y, 1.0],
+        tex_coords: [u, v],
+    };
+    let quad = Mesh::with_all(
+        wgpu::PrimitiveTopology::TriangleList,
+        vec![
+            vertex(-1.0, -1.0, 0.0, 1.0),
+            vertex(1.0, -1.0, 1.0, 1.0),
+            vertex(1.0, 1.0, 1.0, 0.0),
+            vertex(-1.0, 1.0, 0.0, 0.0),
+        ],
+        Some(Indices::U16(vec![0, 1, 2, 2, 3, 0])),
+    );
+    commands.spawn().insert_bundle((
+        Refer::<RenderPipeline>::new(pipeline),
+        BindSlots::new().with(0, bind_group),
+        GpuMesh::from_mesh(&quad, &device, None),
+        HiddenFromRenderTargets,
+    ));
+}
+
+/// 16x16 texels, red and green grow with x and y, the center 8x8 is blue.
+/// Sampled with the nearest texel, so the stretched rows and columns stay sharp
+fn spawn_nine_slice(
+    mut commands: Commands,
+    device: Res<Arc<wgpu::Device>>,
+    queue: Res<wgpu::Queue>,
+    mut bind_groups: ResMut<Store<wgpu::BindGroup>>,
+) {
+    const SIZE: u32 = 16;
+    let mut bytes = Vec::new();
+    for y in 0..SIZE {
+        for x in 0..SIZE {
+            let center = (4..12).contains(&x) && (4..12).contains(&y);
+            bytes.extend_from_slice(&[
+                (x * 16 + 8) as u8,
+                (y * 16 + 8) as u8,
+                if center { 255 } else { 0 },
+                255,
+            ]);
+        }
+    }
+    let texture = Texture::from_raw_image(
+        &device,
+        &queue,
+        &RawImage::new(&bytes, (SIZE, SIZE), PixelFormat::RGBA8),
+        Some("Golden Nine Slice"),
+    )
+    .unwrap();
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
+    let bind_group = bind_groups.insert((&texture.view, &sampler).into_bind_group(&device));
+
+    // corners at 1:1, the center 4x as wide and 2x as tall
+    commands.spawn().insert(NineSlice {
+        texture: AtlasRegion {
+            bind_group,
+            atlas_size: (SIZE, SIZE),
+            rect: Rect {
+                min: (0.0, 0.0),
+                max: (1.0, 1.0),
+            },
+        },
+        border_px: [4.0; 4],
+        size: Vector2::new(40.0, 24.0),
+        position: Vector2::new(12.0, 20.0),
+        color: Color::WHITE,
+        layer: 0,
+    });
+}
+
+/// `res/fog.wgsl` blended over the frame
+struct FogPass {
+    pipeline: wgpu::RenderPipeline,
+    bind_group: wgpu::BindGroup,
+}
+
+impl FogPass {
+    /// Gray, from 1 to 5 units away
+    const FOG: [f32; 8] = [0.5, 0.5, 0.5, 1.0, 1.0, 5.0, 0.0, 0.0];
+
+    fn new(device: &wgpu::Device) -> Self {
+        let source = preprocess::resolve_includes(
+            "fog.wgsl",
+            include_str!("../res/fog.wgsl"),
+            &ShaderInclude::files(),
+        )
+        .unwrap();
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Golden Fog Shader"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+
+        let fog = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Golden Fog"),
+            contents: bytemuck::cast_slice(&Self::FOG),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let fog_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Golden Fog Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Golden Fog Bind Group"),
+            layout: &fog_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: fog.as_entire_binding(),
+            }],
+        });
+
+        let depth_layout = DepthBindGroup::layout(device);
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Golden Fog Pipeline Layout"),
+            bind_group_layouts: &[&fog_layout, &depth_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Golden Fog Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &module,
+                entry_point: Shader::VERTEX_ENTRY_POINT,
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &module,
+                entry_point: Shader::FRAGMENT_ENTRY_POINT,
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: OffscreenTarget::FORMAT,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        Self {
+            pipeline,
+            bind_group,
+        }
+    }
+}
+
+/// Red quads over the top 3/4 of the frame, the left one at depth 0.25, the right one
+/// at 0.75. The camera maps depth to 1 to 10 units, fog is opaque where nothing was drawn
+fn spawn_fog_scene(
+    mut commands: Commands,
+    device: Res<Arc<wgpu::Device>>,
+    depth_format: Res<DepthFormat>,
+    mut camera: ResMut<Camera>,
+    mut pipelines: ResMut<Store<RenderPipeline>>,
+) {
+    camera.projection_matrix = OPENGL_TO_WGPU_MATRIX
+        * PerspectiveProjection {
+            aspect: 1.0,
+            fovy: 1.0,
+            znear: 1.0,
+            zfar: 10.0,
+            ..Default::default()
+        }
+        .build_projection_matrix();
+
+    let module = device.create_shader_module(wgpu::include_wgsl!("../res/solid.wgsl"));
+    let pipeline = pipelines.insert(create_pipeline(&device, &depth_format, module, &[]));
+    // +z is towards the viewer, see res/solid.wgsl
+    for (x, z) in [(-1.0, 0.5), (0.0, -0.5)] {
+        let vertex = |x: f32, y: f32| Vertex {
+            position: [x, y, z],
+            tex_coords: [0.0, 0.0],
+        };
+        let quad = Mesh::with_all(
+            wgpu::PrimitiveTopology::TriangleList,
+            vec![
+                vertex(x, -0.5),
+                vertex(x + 1.0, -0.5),
+                vertex(x + 1.0, 1.0),
+                vertex(x, 1.0),
+            ],
+            Some(Indices::U16(vec![0, 1, 2, 2, 3, 0])),
+        );
+        commands.spawn().insert_bundle((
+            Refer::<RenderPipeline>::new(pipeline),
+            BindSlots::new(),
+            GpuMesh::from_mesh(&quad, &device, None),
+        ));
+    }
+    commands.insert_resource(FogPass::new(&device));
+}
+
+/// Runs in `RenderStage::PostProcess`, the depth texture is readable there
+fn fog_pass_system(
+    device: Res<Arc<wgpu::Device>>,
+    frame: Res<Option<CurrentFrame>>,
+    depth: Res<Option<DepthBindGroup>>,
+    fog: Option<Res<FogPass>>,
+    mut encoders: ResMut<FrameEncoders>,
+) {
+    let (frame, depth, fog) = match (
+        frame.as_ref().as_ref(),
+        depth.as_ref().as_ref().and_then(DepthBindGroup::get),
+        fog,
+    ) {
+        (Some(frame), Some(depth), Some(fog)) => (frame, depth, fog),
+        _ => return,
+    };
+
+    let mut fog_pass = encoders
+        .encoder(&device)
+        .begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Golden Fog Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &frame.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+    fog_pass.set_pipeline(&fog.pipeline);
+    fog_pass.set_bind_group(0, &fog.bind_group, &[]);
+    fog_pass.set_bind_group(1, depth, &[]);
+    fog_pass.draw(0..3, 0..1);
+}
+
+/// Two quads covering the frame 900 units away and 5 cm apart, the far red one is drawn
+/// first. With the standard depth they get the same depth, the near green one fails the
+/// `Less` test against it. The reversed projection has an infinite far plane
+fn spawn_distant_quads(
+    mut commands: Commands,
+    device: Res<Arc<wgpu::Device>>,
+    (depth_format, depth_convention): (Res<DepthFormat>, Res<DepthConvention>),
+    mut pipelines: ResMut<Store<RenderPipeline>>,
+    mut bind_groups: ResMut<Store<wgpu::BindGroup>>,
+) {
+    let projection = PerspectiveProjection {
+        znear: 0.1,
+        zfar: match *depth_convention {
+            DepthConvention::Standard => 1000.0,
+            DepthConvention::ReverseZ => f32::INFINITY,
+        },
+        depth_convention: *depth_convention,
+        ..Default::default()
+    };
+    // the view is the identity, the camera sits at the origin looking along -z
+    let view_proj: [[f32; 4]; 4] =
+        (OPENGL_TO_WGPU_MATRIX * projection.build_projection_matrix()).into();
+    let camera = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Golden Camera"),
+        contents: bytemuck::cast_slice(&view_proj),
+        usage: wgpu::BufferUsages::UNIFORM,
+    });
+    let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("Golden Camera Bind Group Layout"),
+        entries: &[wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::VERTEX,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }],
+    });
+    let bind_group = bind_groups.insert(device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Golden Camera Bind Group"),
+        layout: &layout,
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: camera.as_entire_binding(),
+        }],
+    }));
+
+    let module = device.create_shader_module(wgpu::include_wgsl!("../res/projected_uv.wgsl"));
+    let shader = Shader::with_final(
+        module,
+        vec![Vertex::layout()],
+        vec![Some(wgpu::ColorTargetState {
+            format: OffscreenTarget::FORMAT,
+            blend: Some(wgpu::BlendState::REPLACE),
+            write_mask: wgpu::ColorWrites::ALL,
+        })],
+    )
+    .with_depth_stencil(depth_format.0, Default::default())
+    .with_depth_convention(*depth_convention);
+    let pipeline = pipelines.insert(RenderPipeline::create_usual(
+        &device,
+        &[&layout],
+        &shader,
+        wgpu::PrimitiveTopology::TriangleList,
+        None,
+    ));
+
+    // red first, then green
+    for (z, tex_coords) in [(-900.05, [1.0, 0.0]), (-900.0, [0.0, 1.0])] {
+        let vertex = |x: f32, y: f32| Vertex {
+            position: [x, y, z],
+            tex_coords,
+        };
+        let quad = Mesh::with_all(
+            wgpu::PrimitiveTopology::TriangleList,
+            vec![
+                vertex(-500.0, -500.0),
+                vertex(500.0, -500.0),
+                vertex(500.0, 500.0),
+                vertex(-500.0, 500.0),
+            ],
+            Some(Indices::U16(vec![0, 1, 2, 2, 3, 0])),
+        );
+        commands.spawn().insert_bundle((
+            Refer::<RenderPipeline>::new(pipeline),
+            BindSlots::new().with(0, bind_group),
+            GpuMesh::from_mesh(&quad, &device, None),
+        ));
+    }
+}
+
+#[test]
+fn unit_cube_front_face_uvs() {
+    GoldenTest::render_and_compare(
+        |app| {
+            without_post_process(app);
+            app.add_startup_system(spawn_unit_cube);
+        },
+        golden("unit_cube"),
+        TOLERANCE,
+    );
+}
+
+#[test]
+fn aa_plane_uvs() {
+    GoldenTest::render_and_compare(
+        |app| {
+            without_post_process(app);
+            app.add_startup_system(spawn_aa_plane);
+        },
+        golden("aa_plane"),
+        TOLERANCE,
+    );
+}
+
+#[test]
+fn text_atlas_quad() {
+    GoldenTest::render_and_compare(
+        |app| {
+            without_post_process(app);
+            app.add_startup_system(spawn_atlas_quad);
+        },
+        golden("text_atlas"),
+        TOLERANCE,
+    );
+}
+
+#[test]
+fn render_target_sampled_on_a_quad() {
+    GoldenTest::render_and_compare(
+        |app| {
+            without_post_process(app);
+            app.add_startup_system(spawn_render_target_quad);
+        },
+        golden("render_target_quad"),
+        TOLERANCE,
+    );
+}
+
+#[test]
+fn depth_fog() {
+    GoldenTest::render_and_compare(
+        |app| {
+            without_post_process(app);
+            app.add_startup_system(spawn_fog_scene)
+                .add_system_to_stage(RenderStage::PostProcess, fog_pass_system);
+        },
+        golden("depth_fog"),
+        TOLERANCE,
+    );
+}
+
+#[test]
+fn nine_slice_panel() {
+    GoldenTest::render_and_compare(
+        |app| {
+            without_post_process(app);
+            app.add_plugin(FlatUiPlugin)
+                .add_startup_system(spawn_nine_slice);
+        },
+        golden("nine_slice"),
+        TOLERANCE,
+    );
+}
+
+#[test]
+fn reverse_z_separates_distant_quads() {
+    let render = |depth_convention: DepthConvention| {
+        GoldenTest::default().render(|app| {
+            without_post_process(app);
+            app.insert_resource(depth_convention)
+                .add_startup_system(spawn_distant_quads);
+        })
+    };
+    let (standard, reverse) = match (
+        render(DepthConvention::Standard),
+        render(DepthConvention::ReverseZ),
+    ) {
+        (Some(standard), Some(reverse)) => (standard, reverse),
+        _ => {
+            eprintln!("No adapter available, skipping the reverse-Z comparison");
+            return;
+        }
+    };
+
+    let green = Rgba([0, 255, 0, 255]);
+    assert!(reverse.pixels().all(|pixel| *pixel == green));
+    // the far quad shows through where the depths are equal
+    assert!(standard.pixels().any(|pixel| *pixel != green));
+}
@@ -0,0 +1,324 @@
+use std::sync::Arc;
+
+use bevy_app::AppExit;
+use bevy_ecs::{
+    event::Events,
+    schedule::{ParallelSystemDescriptorCoercion, Stage, SystemStage},
+    system::{Res, ResMut},
+    world::World,
+};
+use try_wgpu::{
+    create_headless_wgpu_resources,
+    render::{
+        main_pass_system,
+        mesh::{primitive::create_unit_cube, GpuMesh, Mesh},
+        offscreen::OffscreenTarget,
+        prepare_frame_system, present_frame_system,
+        resource::{
+            bind::BindSlots,
+            buffer::{Indices, MeshVertex, Vertex},
+            pipeline::{RenderPipeline, StencilMask},
+            reflect::{ReflectionError, ShaderReflection},
+            shader::Shader,
+        },
+        timing::PassTimer,
+        ClearColor, CurrentFrame, DepthFormat, DepthPrepass, FrameEncoders, RenderStats,
+        StencilRef,
+    },
+    util::{
+        publish_store_removals_system, sweep_removed_refers_system, Refer, Store, StoreRemoved,
+    },
+};
+
+const SIZE: u32 = 64;
+
+/// The resources of the frame systems, `None` without an adapter
+fn frame_world(depth_format: DepthFormat) -> Option<World> {
+    let mut world = World::new();
+    world.insert_resource(depth_format);
+    if !create_headless_wgpu_resources(&mut world, SIZE, SIZE) {
+        eprintln!("No adapter available, skipping");
+        return None;
+    }
+    world.init_resource::<Option<CurrentFrame>>();
+    world.init_resource::<FrameEncoders>();
+    world.init_resource::<Store<RenderPipeline>>();
+    world.init_resource::<Store<wgpu::BindGroup>>();
+    world.init_resource::<Store<GpuMesh>>();
+    world.init_resource::<RenderStats>();
+    world.init_resource::<DepthPrepass>();
+    world.init_resource::<ClearColor>();
+    world.init_resource::<PassTimer>();
+    world.init_resource::<Events<AppExit>>();
+    Some(world)
+}
+
+/// Draws red with `res/solid.wgsl`
+fn solid_pipeline(
+    world: &World,
+    write_mask: wgpu::ColorWrites,
+    stencil: wgpu::StencilState,
+) -> RenderPipeline {
+    let device = world.resource::<Arc<wgpu::Device>>();
+    let module = device.create_shader_module(wgpu::include_wgsl!("../res/solid.wgsl"));
+    let shader = Shader::with_final(
+        module,
+        vec![Vertex::layout()],
+        vec![Some(wgpu::ColorTargetState {
+            format: OffscreenTarget::FORMAT,
+            blend: Some(wgpu::BlendState::REPLACE),
+            write_mask,
+        })],
+    )
+    .with_depth_stencil(world.resource::<DepthFormat>().0, stencil);
+    RenderPipeline::create_usual(
+        device,
+        &[],
+        &shader,
+        wgpu::PrimitiveTopology::TriangleList,
+        None,
+    )
+}
+
+/// Red unit cube in front of the camera, `None` without an adapter
+fn cube_world() -> Option<World> {
+    let world = frame_world(DepthFormat::default())?;
+    let pipeline = solid_pipeline(&world, wgpu::ColorWrites::ALL, Default::default());
+    Some(cube_world_with(world, pipeline))
+}
+
+fn cube_world_with(mut world: World, pipeline: RenderPipeline) -> World {
+    let cube = GpuMesh::from_mesh(
+        &create_unit_cube(),
+        world.resource::<Arc<wgpu::Device>>(),
+        None,
+    );
+
+    let pipeline_key = world
+        .resource_mut::<Store<RenderPipeline>>()
+        .insert(pipeline);
+    world.spawn().insert_bundle((
+        Refer::<RenderPipeline>::new(pipeline_key),
+        BindSlots::new(),
+        cube,
+    ));
+
+    world
+}
+
+fn frame_stage() -> SystemStage {
+    SystemStage::single_threaded()
+        .with_system(prepare_frame_system)
+        .with_system(main_pass_system.after(prepare_frame_system))
+        .with_system(present_frame_system.after(main_pass_system))
+}
+
+fn pixel(world: &World, x: u32, y: u32) -> [u8; 4] {
+    let pixels = world.resource::<OffscreenTarget>().read_back(
+        world.resource::<Arc<wgpu::Device>>(),
+        world.resource::<wgpu::Queue>(),
+    );
+    let offset = (y * SIZE + x) as usize * 4;
+    pixels[offset..offset + 4].try_into().unwrap()
+}
+
+fn center_pixel(world: &World) -> [u8; 4] {
+    pixel(world, SIZE / 2, SIZE / 2)
+}
+
+#[test]
+fn offscreen_unit_cube_center_pixel() {
+    let mut world = match cube_world() {
+        Some(world) => world,
+        None => return,
+    };
+
+    frame_stage().run(&mut world);
+    assert_eq!(center_pixel(&world), [255, 0, 0, 255]);
+}
+
+/// Clears the frame green with its own encoder, after the main pass
+fn clear_green_system(
+    frame: Res<Option<CurrentFrame>>,
+    device: Res<Arc<wgpu::Device>>,
+    mut encoders: ResMut<FrameEncoders>,
+) {
+    let frame = frame.as_ref().as_ref().unwrap();
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Post Process Encoder"),
+    });
+    encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some("Post Process Pass"),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view: &frame.view,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color::GREEN),
+                store: true,
+            },
+        })],
+        depth_stencil_attachment: None,
+    });
+    encoders.finish(encoder);
+}
+
+#[test]
+fn post_process_is_submitted_after_main_pass() {
+    let mut world = match cube_world() {
+        Some(world) => world,
+        None => return,
+    };
+
+    frame_stage()
+        .with_system(
+            clear_green_system
+                .after(main_pass_system)
+                .before(present_frame_system),
+        )
+        .run(&mut world);
+    assert_eq!(center_pixel(&world), [0, 255, 0, 255]);
+}
+
+#[test]
+fn removed_pipeline_is_swept_and_not_drawn() {
+    let mut world = match cube_world() {
+        Some(world) => world,
+        None => return,
+    };
+    world.init_resource::<Events<StoreRemoved<RenderPipeline>>>();
+
+    let key = **world.query::<&Refer<RenderPipeline>>().single(&world);
+    world
+        .resource_mut::<Store<RenderPipeline>>()
+        .remove_with_events(key);
+    SystemStage::single_threaded()
+        .with_system(publish_store_removals_system::<RenderPipeline>)
+        .with_system(
+            sweep_removed_refers_system::<RenderPipeline>
+                .after(publish_store_removals_system::<RenderPipeline>),
+        )
+        .run(&mut world);
+    assert_eq!(
+        world.query::<&Refer<RenderPipeline>>().iter(&world).count(),
+        0
+    );
+
+    // only the clear color is left
+    frame_stage().run(&mut world);
+    assert_eq!(center_pixel(&world), [0, 0, 0, 255]);
+}
+
+/// Square of `2 * half` in clip space, `z` maps to depth `0.5 - z / 2`
+fn quad(half: f32, z: f32) -> Mesh<Vertex> {
+    let vertex = |x: f32, y: f32| Vertex {
+        position: [x * half, y * half, z],
+        tex_coords: [0.0, 0.0],
+    };
+    Mesh::with_all(
+        wgpu::PrimitiveTopology::TriangleList,
+        vec![
+            vertex(-1.0, -1.0),
+            vertex(1.0, -1.0),
+            vertex(1.0, 1.0),
+            vertex(-1.0, 1.0),
+        ],
+        Some(Indices::U16(vec![0, 1, 2, 2, 3, 0])),
+    )
+}
+
+#[test]
+fn stencil_mask_clips_the_masked_mesh() {
+    let mut world = match frame_world(DepthFormat::STENCIL) {
+        Some(world) => world,
+        None => return,
+    };
+
+    // the mask covers the center behind the masked quad, which covers the whole frame
+    let mask = (
+        solid_pipeline(&world, wgpu::ColorWrites::empty(), StencilMask::write()),
+        quad(0.5, -0.8),
+    );
+    let masked = (
+        solid_pipeline(&world, wgpu::ColorWrites::ALL, StencilMask::inside()),
+        quad(1.0, 0.0),
+    );
+    for (pipeline, mesh) in [mask, masked] {
+        let mesh = GpuMesh::from_mesh(&mesh, world.resource::<Arc<wgpu::Device>>(), None);
+        let key = world
+            .resource_mut::<Store<RenderPipeline>>()
+            .insert(pipeline);
+        world.spawn().insert_bundle((
+            Refer::<RenderPipeline>::new(key),
+            BindSlots::new(),
+            mesh,
+            StencilRef(1),
+        ));
+    }
+
+    frame_stage().run(&mut world);
+    assert_eq!(center_pixel(&world), [255, 0, 0, 255]);
+    assert_eq!(pixel(&world, 1, 1), [0, 0, 0, 255]);
+}
+
+/// `res/solid.wgsl` with its reflection and `vertex_buffers`
+fn reflected_solid_shader(
+    world: &World,
+    vertex_buffers: Vec<wgpu::VertexBufferLayout<'static>>,
+) -> Shader {
+    let source = include_str!("../res/solid.wgsl");
+    let device = world.resource::<Arc<wgpu::Device>>();
+    let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: None,
+        source: wgpu::ShaderSource::Wgsl(source.into()),
+    });
+    Shader::with_final(module, vertex_buffers, Vec::new())
+        .with_reflection(ShaderReflection::from_wgsl(source).unwrap())
+}
+
+#[test]
+fn reflected_pipeline_draws_without_targets() {
+    let world = match frame_world(DepthFormat::default()) {
+        Some(world) => world,
+        None => return,
+    };
+
+    let shader = reflected_solid_shader(&world, vec![Vertex::layout()]);
+    let (pipeline, layouts) = RenderPipeline::create_reflected(
+        world.resource::<Arc<wgpu::Device>>(),
+        &shader,
+        &OffscreenTarget::FORMAT,
+    )
+    .unwrap();
+    assert!(layouts.is_empty());
+
+    let mut world = cube_world_with(world, pipeline);
+    frame_stage().run(&mut world);
+    assert_eq!(center_pixel(&world), [255, 0, 0, 255]);
+}
+
+#[test]
+fn reflected_pipeline_rejects_a_mismatched_mesh() {
+    let world = match frame_world(DepthFormat::default()) {
+        Some(world) => world,
+        None => return,
+    };
+
+    const ATTRIBUTES: [wgpu::VertexAttribute; 1] = wgpu::vertex_attr_array![0 => Float32x3];
+    let shader = reflected_solid_shader(
+        &world,
+        vec![wgpu::VertexBufferLayout {
+            array_stride: 12,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &ATTRIBUTES,
+        }],
+    );
+    let error = RenderPipeline::create_reflected(
+        world.resource::<Arc<wgpu::Device>>(),
+        &shader,
+        &OffscreenTarget::FORMAT,
+    )
+    .err()
+    .unwrap();
+    assert!(matches!(error, ReflectionError::VertexMismatch(_)));
+    assert!(error.to_string().contains("`tex_coords`"));
+}
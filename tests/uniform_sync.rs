@@ -0,0 +1,50 @@
+use std::sync::Arc;
+
+use bevy_ecs::world::World;
+use try_wgpu::{
+    color::Color,
+    create_headless_wgpu_resources,
+    render::resource::bind::{Uniform, UniformSyncBatcher, UniformSyncStats},
+};
+
+#[test]
+fn one_changed_uniform_one_write() {
+    let mut world = World::new();
+    if !create_headless_wgpu_resources(&mut world, 1, 1) {
+        eprintln!("No adapter available, skipping");
+        return;
+    }
+    let device = world.resource::<Arc<wgpu::Device>>();
+    let queue = world.resource::<wgpu::Queue>();
+
+    let mut uniforms: Vec<Uniform<Color>> = (0..100)
+        .map(|_| Uniform::new_default(device, wgpu::ShaderStages::FRAGMENT))
+        .collect();
+
+    // same contents as the initial buffer
+    let mut batcher = UniformSyncBatcher::new(queue);
+    for uniform in uniforms.iter_mut() {
+        uniform.update(&Color::WHITE);
+        batcher.sync(uniform);
+    }
+    assert_eq!(
+        batcher.stats,
+        UniformSyncStats {
+            writes: 0,
+            skipped: 100
+        }
+    );
+
+    uniforms[42].update(&Color::linear_rgba(1.0, 0.0, 0.0, 1.0));
+    let mut batcher = UniformSyncBatcher::new(queue);
+    for uniform in uniforms.iter_mut() {
+        batcher.sync(uniform);
+    }
+    assert_eq!(
+        batcher.stats,
+        UniformSyncStats {
+            writes: 1,
+            skipped: 99
+        }
+    );
+}